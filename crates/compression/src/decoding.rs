@@ -0,0 +1,170 @@
+//! The guest's side of the compression: `decoding!` wraps the bindings `wit_bindgen_rust`
+//! generates for a capability in ones that decode what it returns, so the guest never sees a
+//! compressed payload.
+
+/// Wraps the guest's bindings of a capability (i.e., the module `wit_bindgen_rust::import!`
+/// generates for it, e.g., `kv`) in ones that decode the payloads it returns, so whether the
+/// capability compresses them (i.e., w/ `compress_results`) is transparent to the guest:
+///
+/// ```ignore
+/// wit_bindgen_rust::import!("wit/kv.wit");
+/// slight_compression::decoding!(kv);
+/// use kv_decoded::Kv;
+/// ```
+///
+/// It's `kv_decoded::Kv`, and `mq_decoded::Mq` — they're used in place of `kv::Kv`, and
+/// `mq::Mq`, and the calls that don't return payloads go through to them as is.
+#[macro_export]
+macro_rules! decoding {
+    (kv) => {
+        pub mod kv_decoded {
+            pub use super::kv::Error;
+
+            fn decoded(payload: Vec<u8>) -> Result<Vec<u8>, Error> {
+                $crate::decode_owned(payload)
+                    .map_err(|e| Error::ErrorWithDescription(format!("{:#}", e)))
+            }
+
+            fn decoded_keys(keys: Vec<Vec<u8>>) -> Result<Vec<Vec<u8>>, Error> {
+                keys.into_iter().map(decoded).collect()
+            }
+
+            /// `kv::Kv`, w/ the payloads, and keys it returns decoded.
+            pub struct Kv(pub super::kv::Kv);
+
+            impl Kv {
+                pub fn open(name: &str) -> Result<Self, Error> {
+                    super::kv::Kv::open(name).map(Self)
+                }
+
+                pub fn get(&self, key: &[u8]) -> Result<Vec<u8>, Error> {
+                    decoded(self.0.get(key)?)
+                }
+
+                pub fn get_tagged(&self, key: &[u8], tags: &[&[u8]]) -> Result<Vec<u8>, Error> {
+                    decoded(self.0.get_tagged(key, tags)?)
+                }
+
+                pub fn get_or_default(
+                    &self,
+                    key: &[u8],
+                    default_value: &[u8],
+                ) -> Result<Vec<u8>, Error> {
+                    decoded(self.0.get_or_default(key, default_value)?)
+                }
+
+                pub fn get_range(
+                    &self,
+                    key: &[u8],
+                    offset: u64,
+                    length: u64,
+                ) -> Result<Vec<u8>, Error> {
+                    decoded(self.0.get_range(key, offset, length)?)
+                }
+
+                pub fn list_keys(&self) -> Result<Vec<Vec<u8>>, Error> {
+                    decoded_keys(self.0.list_keys()?)
+                }
+
+                pub fn list_keys_stream(&self) -> Result<KeyStream, Error> {
+                    self.0.list_keys_stream().map(KeyStream)
+                }
+
+                pub fn resume_keys_stream(&self, token: &str) -> Result<KeyStream, Error> {
+                    self.0.resume_keys_stream(token).map(KeyStream)
+                }
+            }
+
+            impl std::ops::Deref for Kv {
+                type Target = super::kv::Kv;
+
+                fn deref(&self) -> &Self::Target {
+                    &self.0
+                }
+            }
+
+            /// `kv::KeyStream`, w/ the keys it lists decoded.
+            pub struct KeyStream(pub super::kv::KeyStream);
+
+            impl KeyStream {
+                pub fn next_page(&self, max: u32) -> Result<Option<Vec<Vec<u8>>>, Error> {
+                    self.0.next_page(max)?.map(decoded_keys).transpose()
+                }
+            }
+
+            impl std::ops::Deref for KeyStream {
+                type Target = super::kv::KeyStream;
+
+                fn deref(&self) -> &Self::Target {
+                    &self.0
+                }
+            }
+        }
+    };
+    (mq) => {
+        pub mod mq_decoded {
+            pub use super::mq::{Error, QueueMessage, ReceivedMessage};
+
+            fn decoded(payload: Vec<u8>) -> Result<Vec<u8>, Error> {
+                $crate::decode_owned(payload)
+                    .map_err(|e| Error::ErrorWithDescription(format!("{:#}", e)))
+            }
+
+            /// `mq::Mq`, w/ the messages it receives decoded.
+            pub struct Mq(pub super::mq::Mq);
+
+            impl Mq {
+                pub fn open(name: &str) -> Result<Self, Error> {
+                    super::mq::Mq::open(name).map(Self)
+                }
+
+                pub fn receive_any(
+                    queues: &[&str],
+                    wait_ms: u64,
+                ) -> Result<Option<QueueMessage>, Error> {
+                    super::mq::Mq::receive_any(queues, wait_ms)?
+                        .map(|msg| {
+                            Ok(QueueMessage {
+                                payload: decoded(msg.payload)?,
+                                ..msg
+                            })
+                        })
+                        .transpose()
+                }
+
+                pub fn receive(&self) -> Result<Vec<u8>, Error> {
+                    decoded(self.0.receive()?)
+                }
+
+                pub fn receive_wait(&self, wait_ms: u64) -> Result<Vec<u8>, Error> {
+                    decoded(self.0.receive_wait(wait_ms)?)
+                }
+
+                pub fn receive_batch(
+                    &self,
+                    max: u32,
+                    wait_ms: u64,
+                ) -> Result<Vec<ReceivedMessage>, Error> {
+                    self.0
+                        .receive_batch(max, wait_ms)?
+                        .into_iter()
+                        .map(|msg| {
+                            Ok(ReceivedMessage {
+                                payload: decoded(msg.payload)?,
+                                ..msg
+                            })
+                        })
+                        .collect()
+                }
+            }
+
+            impl std::ops::Deref for Mq {
+                type Target = super::mq::Mq;
+
+                fn deref(&self) -> &Self::Target {
+                    &self.0
+                }
+            }
+        }
+    };
+}
@@ -0,0 +1,274 @@
+//! `slight-compression` compresses the payloads capabilities return to guests across the WIT
+//! boundary (see `Compression`), and decodes them on the guest side (see `decoding!`, and
+//! `decode`) — it's shared by the host, and guests, so they can't disagree on the format.
+
+mod decoding;
+
+use std::borrow::Cow;
+
+use anyhow::{bail, Result};
+
+/// The prefix of compressed payloads, which is followed by the id of their codec, and the
+/// compressed bytes.
+///
+/// Payloads w/o it are passed as is, which is what keeps small payloads free of overhead, and
+/// guests decoding the payloads of capabilities that don't compress them working.
+pub const MAGIC: &[u8; 4] = b"\xffslz";
+
+/// How big a payload is before it's compressed, unless a capability says otherwise.
+pub const DEFAULT_THRESHOLD: usize = 4096;
+
+/// The id of payloads that are framed, but not compressed (i.e., small ones that happen to start
+/// w/ `MAGIC`, which would otherwise be mistaken for compressed ones).
+const IDENTITY: u8 = 0;
+
+/// The codecs payloads can be compressed w/.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    /// raw deflate (i.e., w/o zlib, or gzip headers), which is pure Rust on either side
+    Deflate,
+    /// zstd, which compresses better, and faster, but guests need this crate's `zstd`
+    /// feature (and a C toolchain for wasm32-wasi) to decode it
+    Zstd,
+}
+
+impl Codec {
+    pub fn parse(codec: &str) -> Result<Self> {
+        match codec {
+            "deflate" => Ok(Self::Deflate),
+            "zstd" => Ok(Self::Zstd),
+            c => bail!("invalid codec: '{}' (expected 'deflate', or 'zstd')", c),
+        }
+    }
+
+    fn id(&self) -> u8 {
+        match self {
+            Self::Deflate => 1,
+            Self::Zstd => 2,
+        }
+    }
+
+    fn compress(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            // a middle level, as payloads are compressed on every call
+            Self::Deflate => Ok(miniz_oxide::deflate::compress_to_vec(payload, 6)),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Ok(zstd::encode_all(payload, 3)?),
+            #[cfg(not(feature = "zstd"))]
+            Self::Zstd => bail!("zstd isn't supported (i.e., w/o the `zstd` feature)"),
+        }
+    }
+}
+
+/// `Compression` compresses the payloads bigger than its `threshold` w/ its `codec`, as long
+/// as that makes them smaller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Compression {
+    pub codec: Codec,
+    pub threshold: usize,
+}
+
+impl Compression {
+    pub fn new(codec: Codec, threshold: Option<usize>) -> Self {
+        Self {
+            codec,
+            threshold: threshold.unwrap_or(DEFAULT_THRESHOLD),
+        }
+    }
+
+    /// Encodes a payload for the guest (which gets it back w/ `decode`) — the ones no bigger
+    /// than the `threshold`, and the ones compressing doesn't shrink are passed as is.
+    pub fn encode(&self, payload: Vec<u8>) -> Vec<u8> {
+        if payload.len() > self.threshold {
+            match self.codec.compress(&payload) {
+                Ok(compressed) if compressed.len() + MAGIC.len() + 1 < payload.len() => {
+                    return frame(self.codec.id(), &compressed);
+                }
+                Ok(_) => {}
+                // it only costs the payload its compression
+                Err(e) => tracing::warn!("failed to compress a payload, passing it as is: {:#}", e),
+            }
+        }
+        if payload.starts_with(MAGIC) {
+            frame(IDENTITY, &payload)
+        } else {
+            payload
+        }
+    }
+}
+
+/// Decodes a payload a capability returned, whether it's compressed, or not.
+pub fn decode(payload: &[u8]) -> Result<Cow<'_, [u8]>> {
+    let framed = match payload.strip_prefix(MAGIC.as_slice()) {
+        Some(framed) => framed,
+        None => return Ok(Cow::Borrowed(payload)),
+    };
+    let (codec, compressed) = match framed.split_first() {
+        Some((codec, compressed)) => (*codec, compressed),
+        None => bail!("invalid compressed payload: it has no codec"),
+    };
+    match codec {
+        IDENTITY => Ok(Cow::Borrowed(compressed)),
+        1 => miniz_oxide::inflate::decompress_to_vec(compressed)
+            .map(Cow::Owned)
+            .map_err(|e| anyhow::anyhow!("invalid deflate payload: {:?}", e)),
+        #[cfg(feature = "zstd")]
+        2 => Ok(Cow::Owned(zstd::decode_all(compressed)?)),
+        #[cfg(not(feature = "zstd"))]
+        2 => bail!("failed to decode a zstd payload: it needs the `zstd` feature"),
+        c => bail!("invalid compressed payload: unknown codec {}", c),
+    }
+}
+
+/// Decodes a payload a capability returned, like `decode`, w/o copying it if it isn't compressed.
+pub fn decode_owned(payload: Vec<u8>) -> Result<Vec<u8>> {
+    if !payload.starts_with(MAGIC) {
+        return Ok(payload);
+    }
+    Ok(decode(&payload)?.into_owned())
+}
+
+fn frame(codec: u8, bytes: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(MAGIC.len() + 1 + bytes.len());
+    framed.extend_from_slice(MAGIC);
+    framed.push(codec);
+    framed.extend_from_slice(bytes);
+    framed
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::borrow::Cow;
+
+    use super::{decode, decode_owned, Codec, Compression, MAGIC};
+
+    #[test]
+    fn compression_test() {
+        let deflate = Compression::new(Codec::Deflate, Some(64));
+        let text = "the quick brown fox jumps over the lazy dog. ".repeat(100);
+        let encoded = deflate.encode(text.as_bytes().to_vec());
+        assert!(encoded.starts_with(MAGIC));
+        assert!(encoded.len() < text.len() / 4);
+        assert_eq!(decode(&encoded).unwrap(), text.as_bytes());
+
+        // small payloads are passed as is (and decoded w/o copying them)
+        let small = deflate.encode(b"small".to_vec());
+        assert_eq!(small, b"small");
+        assert!(matches!(decode(&small).unwrap(), Cow::Borrowed(b"small")));
+
+        // so are the ones compressing doesn't shrink (e.g., already compressed ones)
+        let compressed = encoded[MAGIC.len() + 1..].to_vec();
+        let reencoded = Compression::new(Codec::Deflate, Some(0)).encode(compressed.clone());
+        assert_eq!(decode(&reencoded).unwrap(), compressed.as_slice());
+
+        // small payloads that look compressed are framed, rather than mistaken for compressed ones
+        let lookalike = [MAGIC.as_slice(), &[1, 2, 3]].concat();
+        let encoded = deflate.encode(lookalike.clone());
+        assert_ne!(encoded, lookalike);
+        assert_eq!(decode(&encoded).unwrap(), lookalike.as_slice());
+
+        assert!(decode(MAGIC).is_err());
+        assert!(decode(&[MAGIC.as_slice(), &[9]].concat()).is_err());
+        assert!(Codec::parse("gzip").is_err());
+    }
+
+    /// The bindings `wit_bindgen_rust` generates for kv, as far as `decoding!` uses them.
+    mod kv {
+        use super::{Codec, Compression};
+
+        #[derive(Debug)]
+        pub enum Error {
+            ErrorWithDescription(String),
+        }
+
+        pub struct Kv;
+
+        fn value() -> Vec<u8> {
+            Compression::new(Codec::Deflate, Some(8)).encode(b"value ".repeat(10))
+        }
+
+        impl Kv {
+            pub fn open(_name: &str) -> Result<Self, Error> {
+                Ok(Self)
+            }
+
+            pub fn get(&self, _key: &[u8]) -> Result<Vec<u8>, Error> {
+                Ok(value())
+            }
+
+            pub fn get_tagged(&self, _key: &[u8], _tags: &[&[u8]]) -> Result<Vec<u8>, Error> {
+                Ok(value())
+            }
+
+            pub fn get_or_default(&self, _key: &[u8], default: &[u8]) -> Result<Vec<u8>, Error> {
+                Ok(default.to_vec())
+            }
+
+            pub fn get_range(
+                &self,
+                _key: &[u8],
+                _offset: u64,
+                _len: u64,
+            ) -> Result<Vec<u8>, Error> {
+                Ok([super::MAGIC.as_slice(), &[9]].concat())
+            }
+
+            pub fn list_keys(&self) -> Result<Vec<Vec<u8>>, Error> {
+                Ok(vec![b"key".to_vec(), value()])
+            }
+
+            pub fn list_keys_stream(&self) -> Result<KeyStream, Error> {
+                Ok(KeyStream)
+            }
+
+            pub fn resume_keys_stream(&self, _token: &str) -> Result<KeyStream, Error> {
+                Ok(KeyStream)
+            }
+
+            pub fn delete(&self, _key: &[u8]) -> Result<(), Error> {
+                Ok(())
+            }
+        }
+
+        pub struct KeyStream;
+
+        impl KeyStream {
+            pub fn next_page(&self, _max: u32) -> Result<Option<Vec<Vec<u8>>>, Error> {
+                Ok(Some(vec![value()]))
+            }
+        }
+    }
+
+    crate::decoding!(kv);
+
+    #[test]
+    fn decoding_test() {
+        let value = b"value ".repeat(10);
+        let store = kv_decoded::Kv::open("my-store").unwrap();
+        assert_eq!(store.get(b"key").unwrap(), value);
+        assert_eq!(
+            store.get_tagged(b"key", &[b"tag".as_slice()]).unwrap(),
+            value
+        );
+        assert_eq!(
+            store.get_or_default(b"key", b"default").unwrap(),
+            b"default"
+        );
+        assert_eq!(
+            store.list_keys().unwrap(),
+            vec![b"key".to_vec(), value.clone()]
+        );
+        let stream = store.list_keys_stream().unwrap();
+        assert_eq!(stream.next_page(10).unwrap(), Some(vec![value.clone()]));
+        let resumed = store.resume_keys_stream("token").unwrap();
+        assert_eq!(resumed.next_page(10).unwrap(), Some(vec![value]));
+        // the calls that don't return payloads go through as is
+        assert!(store.delete(b"key").is_ok());
+
+        let e = store.get_range(b"key", 0, 10).unwrap_err();
+        assert!(matches!(e, kv::Error::ErrorWithDescription(e) if e.contains("unknown codec 9")));
+
+        let unframed = b"value".to_vec();
+        assert_eq!(decode_owned(unframed.clone()).unwrap(), unframed);
+    }
+}
@@ -0,0 +1,72 @@
+use anyhow::{Context, Result};
+use aws_sdk_sts::Client;
+use slight_runtime::deadline::block_on;
+
+use crate::TemporaryCredential;
+
+/// The name of the sessions assumed roles are given, so they can be told apart in CloudTrail.
+const ROLE_SESSION_NAME: &str = "slight";
+
+/// This is the underlying struct behind the `AwsSts` variant of the `CredentialsImplementor` enum.
+///
+/// It provides a property that pertains solely to the AWS STS implementation
+/// of this capability:
+///     - `client`
+///
+/// As per its usage in `CredentialsImplementor`, it must `derive` `Debug`, and `Clone`.
+#[derive(Debug, Clone)]
+pub struct AwsStsImplementor {
+    client: Client,
+}
+
+impl Default for AwsStsImplementor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AwsStsImplementor {
+    /// Creates a new `AwsStsImplementor` instance.
+    ///
+    /// It uses the `aws_config::load_from_env()` for AWS Configuration.
+    /// It will access the AWS Configuration environment variables:
+    ///   - `AWS_ACCESS_KEY_ID`, and
+    ///   - `AWS_SECRET_ACCESS_KEY`, and
+    ///   - `AWS_REGION`.
+    ///
+    /// These long-lived credentials stay on the host, guests only get the temporary
+    /// credentials of the roles they assume.
+    pub fn new() -> Self {
+        let shared_config = futures::executor::block_on(aws_config::load_from_env());
+        Self {
+            client: Client::new(&shared_config),
+        }
+    }
+
+    /// Assumes the role whose ARN is `scope`, returning its temporary credentials.
+    pub fn get(&self, scope: &str) -> Result<TemporaryCredential> {
+        let output = block_on(
+            self.client
+                .assume_role()
+                .role_arn(scope)
+                .role_session_name(ROLE_SESSION_NAME)
+                .send(),
+        )?
+        .with_context(|| format!("failed to assume role '{}'", scope))?;
+        let credentials = output
+            .credentials()
+            .with_context(|| format!("no credentials were returned for role '{}'", scope))?;
+        Ok(TemporaryCredential {
+            access_key_id: credentials.access_key_id().map(str::to_string),
+            secret: credentials
+                .secret_access_key()
+                .with_context(|| format!("no secret access key was returned for role '{}'", scope))?
+                .to_string(),
+            session_token: credentials.session_token().map(str::to_string),
+            expires_at: credentials
+                .expiration()
+                .with_context(|| format!("no expiration was returned for role '{}'", scope))?
+                .secs() as u64,
+        })
+    }
+}
@@ -0,0 +1,101 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use slight_runtime::deadline::block_on;
+use slight_runtime::resource::BasicState;
+
+use crate::TemporaryCredential;
+
+/// This is the underlying struct behind the `AzureAd` variant of the `CredentialsImplementor` enum.
+///
+/// It provides properties that pertain solely to the Azure AD implementation
+/// of this capability:
+///     - `client`, and
+///     - the `tenant_id`, `client_id`, and `client_secret` of the app registration
+///     tokens are requested as.
+///
+/// As per its usage in `CredentialsImplementor`, it must `derive` `Debug`, and `Clone`.
+#[derive(Clone)]
+pub struct AzureAdImplementor {
+    client: reqwest::Client,
+    tenant_id: String,
+    client_id: String,
+    client_secret: String,
+}
+
+impl std::fmt::Debug for AzureAdImplementor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AzureAdImplementor")
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+impl AzureAdImplementor {
+    /// Creates a new `AzureAdImplementor` instance.
+    ///
+    /// The `AZURE_TENANT_ID`, `AZURE_CLIENT_ID`, and `AZURE_CLIENT_SECRET` of the app
+    /// registration are read from the secret store — they stay on the host, guests
+    /// only get the (short-lived) tokens.
+    pub fn new(slight_state: &BasicState) -> Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::new(),
+            tenant_id: secret(slight_state, "AZURE_TENANT_ID")?,
+            client_id: secret(slight_state, "AZURE_CLIENT_ID")?,
+            client_secret: secret(slight_state, "AZURE_CLIENT_SECRET")?,
+        })
+    }
+
+    /// Gets an access token for `scope` (e.g., `https://storage.azure.com/.default`)
+    /// w/ the client credentials flow.
+    pub fn get(&self, scope: &str) -> Result<TemporaryCredential> {
+        let url = format!(
+            "https://login.microsoftonline.com/{}/oauth2/v2.0/token",
+            self.tenant_id
+        );
+        let res = block_on(
+            self.client
+                .post(&url)
+                .form(&[
+                    ("grant_type", "client_credentials"),
+                    ("client_id", self.client_id.as_str()),
+                    ("client_secret", self.client_secret.as_str()),
+                    ("scope", scope),
+                ])
+                .send(),
+        )?
+        .with_context(|| format!("failed to get a token for scope '{}'", scope))?
+        .error_for_status()
+        .with_context(|| format!("failed to get a token for scope '{}'", scope))?;
+        let token = serde_json::from_slice::<TokenResponse>(&block_on(res.bytes())??)
+            .with_context(|| "Azure AD responded w/ an invalid token")?;
+
+        let expires_at = SystemTime::now() + Duration::from_secs(token.expires_in);
+        Ok(TemporaryCredential {
+            access_key_id: None,
+            secret: token.access_token,
+            session_token: None,
+            expires_at: expires_at.duration_since(UNIX_EPOCH)?.as_secs(),
+        })
+    }
+}
+
+fn secret(slight_state: &BasicState, key: &str) -> Result<String> {
+    let value = slight_runtime_configs::resolve(
+        &slight_state.secret_stores,
+        key,
+        &slight_state.config_toml_file_path,
+    )
+    .with_context(|| {
+        format!(
+            "failed to get '{}' secret using secret stores: {:?}",
+            key, slight_state.secret_stores
+        )
+    })?;
+    Ok(String::from_utf8(value)?)
+}
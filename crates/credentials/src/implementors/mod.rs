@@ -0,0 +1,2 @@
+pub mod awssts;
+pub mod azuread;
@@ -0,0 +1,296 @@
+mod implementors;
+
+/// The `SCHEME_NAME` defines the name under which a resource is
+/// identifiable by in a `ResourceMap`.
+const SCHEME_NAME: &str = "credentials";
+/// The operations guests can retry if they time out (see `BasicState::with_idempotent_operations`).
+const IDEMPOTENT_OPERATIONS: &[&str] = &["get"];
+/// The operations that only read, which audits of writes only leave out (see
+/// `BasicState::with_read_operations`).
+const READ_OPERATIONS: &[&str] = &["get"];
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Result};
+use uuid::Uuid;
+
+use implementors::{awssts::AwsStsImplementor, azuread::AzureAdImplementor};
+use slight_runtime::{impl_resource, resource::BasicState};
+
+/// It is mandatory to `use <interface>::*` due to `impl_resource!`.
+/// That is because `impl_resource!` accesses the `crate`'s
+/// `add_to_linker`, and not the `<interface>::add_to_linker` directly.
+use credentials::*;
+wit_bindgen_wasmtime::export!("../../wit/credentials.wit");
+wit_error_rs::impl_error!(credentials::Error);
+slight_runtime::impl_from_anyhow!(credentials::Error);
+
+/// Credentials are refreshed once they expire in less than this, so guests
+/// are never handed credentials that are about to expire.
+const REFRESH_BEFORE_EXPIRY: Duration = Duration::from_secs(5 * 60);
+
+/// The `Credentials` structure is what will implement the `credentials::Credentials` trait
+/// coming from the generated code of off `credentials.wit`.
+///
+/// It maintains a `host_state`.
+pub struct Credentials {
+    host_state: CredentialsState,
+}
+
+impl_resource!(
+    Credentials,
+    credentials::CredentialsTables<Credentials>,
+    CredentialsState,
+    SCHEME_NAME.to_string()
+);
+
+/// This is the type of the `host_state` property from our `Credentials` structure.
+///
+/// It holds:
+///     - a `credentials_implementor` `String` — this comes directly from a
+///     user's `slightfile` and it is what allows us to dynamically
+///     dispatch to a specific implementor's implentation,
+///     - the `slight_state` (of type `BasicState`) that contains common
+///     things received from the slight binary (i.e., the `resource_map`,
+///     the `config_type`, and the `config_toml_file_path`),
+///     - the `allowed_scopes` guests can get credentials for (i.e., the
+///     slightfile's `scopes`), and
+///     - the `cache` of credentials, by scope, shared by all credentials objects.
+pub struct CredentialsState {
+    credentials_implementor: String,
+    slight_state: BasicState,
+    allowed_scopes: Vec<String>,
+    cache: Arc<Mutex<HashMap<String, TemporaryCredential>>>,
+}
+
+impl CredentialsState {
+    pub fn new(
+        credentials_implementor: String,
+        slight_state: BasicState,
+        allowed_scopes: Vec<String>,
+    ) -> Self {
+        Self {
+            credentials_implementor,
+            slight_state: slight_state
+                .with_idempotent_operations(IDEMPOTENT_OPERATIONS)
+                .with_read_operations(READ_OPERATIONS),
+            allowed_scopes,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+/// Temporary credentials, as fetched by an implementor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemporaryCredential {
+    pub access_key_id: Option<String>,
+    pub secret: String,
+    pub session_token: Option<String>,
+    /// in seconds since the unix epoch
+    pub expires_at: u64,
+}
+
+impl TemporaryCredential {
+    /// Whether the credential expires in less than `REFRESH_BEFORE_EXPIRY` from `now`
+    /// (in seconds since the unix epoch).
+    fn needs_refresh(&self, now: u64) -> bool {
+        now + REFRESH_BEFORE_EXPIRY.as_secs() >= self.expires_at
+    }
+}
+
+impl From<TemporaryCredential> for Credential {
+    fn from(credential: TemporaryCredential) -> Self {
+        Self {
+            access_key_id: credential.access_key_id,
+            secret: credential.secret,
+            session_token: credential.session_token,
+            expires_at: credential.expires_at,
+        }
+    }
+}
+
+impl credentials::Credentials for Credentials {
+    type Credentials = CredentialsInner;
+
+    fn credentials_open(&mut self, scope: &str) -> Result<Self::Credentials, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
+        // guests can't ask for arbitrary privileges, only for the scopes the slightfile allows
+        if !is_allowed(&self.host_state.allowed_scopes, scope) {
+            return Err(anyhow::anyhow!(
+                "scope '{}' is not allowed; add it to the `credentials.scopes` of your slightfile to allow it",
+                scope
+            )
+            .into());
+        }
+
+        // populate our inner credentials object w/ the state received from `slight`
+        // (i.e., what type of credentials implementor we are using), and the scope.
+        let inner = Self::Credentials::new(
+            &self.host_state.credentials_implementor,
+            &self.host_state.slight_state,
+            scope,
+        )?;
+
+        self.host_state
+            .slight_state
+            .resource_map
+            .lock()
+            .unwrap()
+            .set(inner.resource_descriptor.clone(), Box::new(inner.clone()));
+
+        Ok(inner)
+    }
+
+    fn credentials_get(&mut self, self_: &Self::Credentials) -> Result<Credential, Error> {
+        let host_state = &self.host_state;
+        host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "get", &self_.scope, || {
+                let now = now_secs();
+                let mut cache = host_state.cache.lock().unwrap();
+                if let Some(credential) = cache.get(&self_.scope) {
+                    if !credential.needs_refresh(now) {
+                        return Ok(credential.clone().into());
+                    }
+                }
+
+                let fetched = match &self_.credentials_implementor {
+                    CredentialsImplementor::AwsSts(ai) => ai.get(&self_.scope),
+                    CredentialsImplementor::AzureAd(ai) => ai.get(&self_.scope),
+                };
+                let credential = match fetched {
+                    Ok(credential) => {
+                        tracing::info!(
+                            "fetched credentials for scope '{}', expiring at {}",
+                            self_.scope,
+                            credential.expires_at
+                        );
+                        cache.insert(self_.scope.clone(), credential.clone());
+                        credential
+                    }
+                    Err(e) => match cache.get(&self_.scope) {
+                        // the cached credentials are about to expire, but still valid
+                        Some(cached) if now < cached.expires_at => {
+                            tracing::warn!(
+                                "failed to refresh credentials for scope '{}', using the cached ones: {:#}",
+                                self_.scope,
+                                e
+                            );
+                            cached.clone()
+                        }
+                        _ => return Err(e.into()),
+                    },
+                };
+                Ok(credential.into())
+            })
+    }
+}
+
+/// The current time, in seconds since the unix epoch.
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn is_allowed(allowed_scopes: &[String], scope: &str) -> bool {
+    allowed_scopes.iter().any(|allowed| allowed == scope)
+}
+
+/// This is the type of the associated type coming from the `credentials::Credentials` trait
+/// implementation.
+///
+/// It holds:
+///     - a `credentials_implementor` (i.e., a variant `CredentialsImplementor` `enum`),
+///     - the `scope` credentials are got for, and
+///     - a `resource_descriptor` (i.e., an UUID that uniquely identifies
+///     resource's instance).
+///
+/// It must `derive`:
+///     - `Debug` due to a constraint on the associated type.
+///     - `Clone` because the `ResourceMap` it will be added onto,
+///     must own its data.
+///
+/// It must be public because the implementation of `credentials::Credentials` cannot leak
+/// a private type.
+#[derive(Debug, Clone)]
+pub struct CredentialsInner {
+    credentials_implementor: CredentialsImplementor,
+    scope: String,
+    resource_descriptor: String,
+}
+
+impl CredentialsInner {
+    fn new(credentials_implementor: &str, slight_state: &BasicState, scope: &str) -> Result<Self> {
+        Ok(Self {
+            credentials_implementor: CredentialsImplementor::new(
+                credentials_implementor,
+                slight_state,
+            )?,
+            scope: scope.to_string(),
+            resource_descriptor: Uuid::new_v4().to_string(),
+        })
+    }
+}
+
+impl slight_runtime::resource::Watch for CredentialsInner {}
+
+/// This defines the available implementor implementations for the `Credentials` interface.
+///
+/// As per its usage in `CredentialsInner`, it must `derive` `Debug`, and `Clone`.
+#[derive(Debug, Clone)]
+enum CredentialsImplementor {
+    AwsSts(AwsStsImplementor),
+    AzureAd(AzureAdImplementor),
+}
+
+impl CredentialsImplementor {
+    fn new(credentials_implementor: &str, slight_state: &BasicState) -> Result<Self> {
+        Ok(match credentials_implementor {
+            "credentials.awssts" => Self::AwsSts(AwsStsImplementor::new()),
+            "credentials.azuread" => Self::AzureAd(AzureAdImplementor::new(slight_state)?),
+            p => bail!(
+                "failed to match provided name (i.e., '{}') to any known host implementations",
+                p
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use super::{is_allowed, TemporaryCredential, REFRESH_BEFORE_EXPIRY};
+
+    #[test]
+    fn needs_refresh_test() {
+        let credential = TemporaryCredential {
+            access_key_id: None,
+            secret: "token".to_string(),
+            session_token: None,
+            expires_at: 10_000,
+        };
+        let refresh_at = 10_000 - REFRESH_BEFORE_EXPIRY.as_secs();
+        assert!(!credential.needs_refresh(refresh_at - 1));
+        assert!(credential.needs_refresh(refresh_at));
+        assert!(credential.needs_refresh(10_001));
+    }
+
+    #[test]
+    fn is_allowed_test() {
+        let allowed_scopes = vec!["arn:aws:iam::123456789012:role/reader".to_string()];
+        assert!(is_allowed(
+            &allowed_scopes,
+            "arn:aws:iam::123456789012:role/reader"
+        ));
+        assert!(!is_allowed(
+            &allowed_scopes,
+            "arn:aws:iam::123456789012:role/admin"
+        ));
+        assert!(!is_allowed(&[], "arn:aws:iam::123456789012:role/reader"));
+    }
+}
@@ -0,0 +1,104 @@
+use hmac::{Mac, SimpleHmac};
+use sha2::{Digest, Sha256, Sha512};
+
+/// The algorithms data can be hashed, and HMACs computed w/ — it's an allow-list, so new ones
+/// are only added once there's a vetted implementation of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+    Sha512,
+    Blake3,
+}
+
+impl Algorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha512 => "sha512",
+            Self::Blake3 => "blake3",
+        }
+    }
+
+    pub fn hash(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha256 => Sha256::digest(data).to_vec(),
+            Self::Sha512 => Sha512::digest(data).to_vec(),
+            Self::Blake3 => blake3::hash(data).as_bytes().to_vec(),
+        }
+    }
+
+    pub fn hmac(&self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha256 => mac::<Sha256>(key, data).finalize().into_bytes().to_vec(),
+            Self::Sha512 => mac::<Sha512>(key, data).finalize().into_bytes().to_vec(),
+            Self::Blake3 => mac::<blake3::Hasher>(key, data)
+                .finalize()
+                .into_bytes()
+                .to_vec(),
+        }
+    }
+
+    /// Verifies that `expected` is the HMAC of `data` w/ `key`, in constant time.
+    pub fn verify(&self, key: &[u8], data: &[u8], expected: &[u8]) -> bool {
+        match self {
+            Self::Sha256 => mac::<Sha256>(key, data).verify_slice(expected).is_ok(),
+            Self::Sha512 => mac::<Sha512>(key, data).verify_slice(expected).is_ok(),
+            Self::Blake3 => mac::<blake3::Hasher>(key, data)
+                .verify_slice(expected)
+                .is_ok(),
+        }
+    }
+}
+
+/// The HMAC of `data` w/ `key`, w/ the `SimpleHmac` that takes any hash (i.e., BLAKE3 too,
+/// which isn't built on a block-level core like SHA-2).
+fn mac<D: Digest + hmac::digest::core_api::BlockSizeUser>(
+    key: &[u8],
+    data: &[u8],
+) -> SimpleHmac<D> {
+    let mut mac = SimpleHmac::<D>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac
+}
+
+#[cfg(test)]
+mod unittests {
+    use super::Algorithm;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn hash_test() {
+        assert_eq!(
+            hex(&Algorithm::Sha256.hash(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(Algorithm::Sha512.hash(b"abc").len(), 64);
+        assert_eq!(
+            hex(&Algorithm::Blake3.hash(b"")),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+    }
+
+    #[test]
+    fn hmac_test() {
+        // RFC 4231, test case 2
+        let mac = Algorithm::Sha256.hmac(b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(
+            hex(&mac),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert!(Algorithm::Sha256.verify(b"Jefe", b"what do ya want for nothing?", &mac));
+        assert!(!Algorithm::Sha256.verify(b"jefe", b"what do ya want for nothing?", &mac));
+        assert!(!Algorithm::Sha256.verify(b"Jefe", b"what do ya want?", &mac));
+        // a truncated mac doesn't verify
+        assert!(!Algorithm::Sha256.verify(b"Jefe", b"what do ya want for nothing?", &mac[..16]));
+
+        let mac = Algorithm::Blake3.hmac(b"key", b"data");
+        assert_eq!(mac.len(), 32);
+        assert!(Algorithm::Blake3.verify(b"key", b"data", &mac));
+        assert!(!Algorithm::Sha512.verify(b"key", b"data", &mac));
+    }
+}
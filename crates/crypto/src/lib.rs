@@ -0,0 +1,200 @@
+mod digest;
+
+/// The `SCHEME_NAME` defines the name under which a resource is
+/// identifiable by in a `ResourceMap`.
+const SCHEME_NAME: &str = "crypto";
+/// The operations guests can retry if they time out (see `BasicState::with_idempotent_operations`).
+const IDEMPOTENT_OPERATIONS: &[&str] = &["hash", "hmac", "verify"];
+/// The operations that only read, which audits of writes only leave out (see
+/// `BasicState::with_read_operations`).
+const READ_OPERATIONS: &[&str] = &["hash", "hmac", "verify"];
+
+use anyhow::{bail, Context, Result};
+use uuid::Uuid;
+
+use slight_runtime::{impl_resource, resource::BasicState};
+
+/// It is mandatory to `use <interface>::*` due to `impl_resource!`.
+/// That is because `impl_resource!` accesses the `crate`'s
+/// `add_to_linker`, and not the `<interface>::add_to_linker` directly.
+use crypto::*;
+wit_bindgen_wasmtime::export!("../../wit/crypto.wit");
+wit_error_rs::impl_error!(crypto::Error);
+slight_runtime::impl_from_anyhow!(crypto::Error);
+
+/// The `Crypto` structure is what will implement the `crypto::Crypto` trait
+/// coming from the generated code of off `crypto.wit`.
+///
+/// It maintains a `host_state`.
+pub struct Crypto {
+    host_state: CryptoState,
+}
+
+impl_resource!(
+    Crypto,
+    crypto::CryptoTables<Crypto>,
+    CryptoState,
+    SCHEME_NAME.to_string()
+);
+
+/// This is the type of the `host_state` property from our `Crypto` structure.
+///
+/// It holds the `slight_state` (of type `BasicState`) that contains common
+/// things received from the slight binary (i.e., the `resource_map`,
+/// the `config_type`, and the `config_toml_file_path`), and the secret stores
+/// HMAC keys that are secrets are looked up in.
+///
+/// Like `parsing`, it's the host itself that computes hashes, so there is no
+/// implementor to choose from.
+pub struct CryptoState {
+    slight_state: BasicState,
+}
+
+impl CryptoState {
+    pub fn new(slight_state: BasicState) -> Self {
+        Self {
+            slight_state: slight_state
+                .with_idempotent_operations(IDEMPOTENT_OPERATIONS)
+                .with_read_operations(READ_OPERATIONS),
+        }
+    }
+}
+
+impl From<crypto::Algorithm> for digest::Algorithm {
+    fn from(algorithm: crypto::Algorithm) -> Self {
+        match algorithm {
+            crypto::Algorithm::Sha256 => Self::Sha256,
+            crypto::Algorithm::Sha512 => Self::Sha512,
+            crypto::Algorithm::Blake3 => Self::Blake3,
+        }
+    }
+}
+
+impl Crypto {
+    /// Runs `f` w/ the `algorithm`, charging the bytes of `data` to the capability's quota.
+    fn digest<T>(
+        &self,
+        operation: &str,
+        algorithm: crypto::Algorithm,
+        data: &[u8],
+        f: impl FnOnce(digest::Algorithm) -> Result<T>,
+    ) -> Result<T, Error> {
+        let slight_state = &self.host_state.slight_state;
+        let algorithm = digest::Algorithm::from(algorithm);
+        slight_state.instrument(SCHEME_NAME, operation, algorithm.name(), || {
+            slight_state.take_bytes(data.len())?;
+            Ok(f(algorithm)?)
+        })
+    }
+
+    /// The bytes of an HMAC key — a secret is looked up in the secret stores on each call, so
+    /// rotating it doesn't need a restart.
+    fn key(&self, key: HmacKey<'_>) -> Result<Vec<u8>> {
+        let key = match key {
+            HmacKey::Inline(key) => key.to_vec(),
+            HmacKey::Secret(name) => {
+                let slight_state = &self.host_state.slight_state;
+                slight_runtime_configs::resolve(
+                    &slight_state.secret_stores,
+                    name,
+                    &slight_state.config_toml_file_path,
+                )
+                .with_context(|| {
+                    format!(
+                        "failed to get the HMAC key '{}' using secret stores: {:?}",
+                        name, slight_state.secret_stores
+                    )
+                })?
+            }
+        };
+        if key.is_empty() {
+            bail!("invalid HMAC key: it's empty");
+        }
+        Ok(key)
+    }
+}
+
+impl crypto::Crypto for Crypto {
+    type Crypto = CryptoInner;
+
+    fn crypto_open(&mut self) -> Result<Self::Crypto, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
+        let inner = Self::Crypto::new();
+
+        self.host_state
+            .slight_state
+            .resource_map
+            .lock()
+            .unwrap()
+            .set(inner.resource_descriptor.clone(), Box::new(inner.clone()));
+
+        Ok(inner)
+    }
+
+    fn crypto_hash(
+        &mut self,
+        _self_: &Self::Crypto,
+        algorithm: crypto::Algorithm,
+        data: PayloadParam<'_>,
+    ) -> Result<Vec<u8>, Error> {
+        self.digest(
+            "hash",
+            algorithm,
+            data,
+            |algorithm| Ok(algorithm.hash(data)),
+        )
+    }
+
+    fn crypto_hmac(
+        &mut self,
+        _self_: &Self::Crypto,
+        algorithm: crypto::Algorithm,
+        key: HmacKey<'_>,
+        data: PayloadParam<'_>,
+    ) -> Result<Vec<u8>, Error> {
+        self.digest("hmac", algorithm, data, |algorithm| {
+            Ok(algorithm.hmac(&self.key(key)?, data))
+        })
+    }
+
+    fn crypto_verify(
+        &mut self,
+        _self_: &Self::Crypto,
+        algorithm: crypto::Algorithm,
+        key: HmacKey<'_>,
+        data: PayloadParam<'_>,
+        mac: &[u8],
+    ) -> Result<bool, Error> {
+        self.digest("verify", algorithm, data, |algorithm| {
+            Ok(algorithm.verify(&self.key(key)?, data, mac))
+        })
+    }
+}
+
+/// This is the type of the associated type coming from the `crypto::Crypto` trait
+/// implementation.
+///
+/// It holds a `resource_descriptor` (i.e., an UUID that uniquely identifies
+/// resource's instance).
+///
+/// It must `derive`:
+///     - `Debug` due to a constraint on the associated type.
+///     - `Clone` because the `ResourceMap` it will be added onto,
+///     must own its data.
+///
+/// It must be public because the implementation of `crypto::Crypto` cannot leak
+/// a private type.
+#[derive(Debug, Clone)]
+pub struct CryptoInner {
+    resource_descriptor: String,
+}
+
+impl CryptoInner {
+    fn new() -> Self {
+        Self {
+            resource_descriptor: Uuid::new_v4().to_string(),
+        }
+    }
+}
+
+impl slight_runtime::resource::Watch for CryptoInner {}
@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+
+/// The context of a deployment, as the operator set it in the slightfile (i.e., on the
+/// `deployment` capability).
+///
+/// It holds:
+///     - the `environment` (e.g., `prod`),
+///     - the `region` (e.g., `eastus`),
+///     - the `deployment_id` (e.g., a release, or a commit), and
+///     - the operator's own `metadata`, sorted by key, so guests see it in the same order
+///     every time.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeploymentContext {
+    pub environment: Option<String>,
+    pub region: Option<String>,
+    pub deployment_id: Option<String>,
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl DeploymentContext {
+    pub fn new(
+        environment: Option<String>,
+        region: Option<String>,
+        deployment_id: Option<String>,
+        metadata: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self> {
+        let metadata = metadata.into_iter().collect::<BTreeMap<_, _>>();
+        if metadata.keys().any(|key| key.trim().is_empty()) {
+            bail!("invalid deployment metadata: its keys can't be empty");
+        }
+        Ok(Self {
+            environment,
+            region,
+            deployment_id,
+            metadata,
+        })
+    }
+
+    /// The value of one of the operator's keys, if it's set.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.metadata.get(key).map(String::as_str)
+    }
+
+    /// The operator's keys, sorted.
+    pub fn keys(&self) -> Vec<String> {
+        self.metadata.keys().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+
+    use super::DeploymentContext;
+
+    #[test]
+    fn metadata_test() -> Result<()> {
+        let context = DeploymentContext::new(
+            Some("prod".to_string()),
+            None,
+            None,
+            [
+                ("team".to_string(), "payments".to_string()),
+                ("tier".to_string(), "gold".to_string()),
+            ],
+        )?;
+        assert_eq!(context.get("team"), Some("payments"));
+        assert_eq!(context.get("owner"), None);
+        assert_eq!(context.keys(), vec!["team", "tier"]);
+
+        assert!(
+            DeploymentContext::new(None, None, None, [(" ".to_string(), "x".to_string())]).is_err()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+mod context;
+
+/// The `SCHEME_NAME` defines the name under which a resource is
+/// identifiable by in a `ResourceMap`.
+const SCHEME_NAME: &str = "deployment";
+/// The operations guests can retry if they time out (see `BasicState::with_idempotent_operations`).
+const IDEMPOTENT_OPERATIONS: &[&str] = &["info", "get"];
+/// The operations that only read, which audits of writes only leave out (see
+/// `BasicState::with_read_operations`).
+const READ_OPERATIONS: &[&str] = &["info", "get", "list-keys"];
+
+use anyhow::Result;
+use uuid::Uuid;
+
+use slight_runtime::{impl_resource, resource::BasicState};
+
+pub use context::DeploymentContext;
+
+/// It is mandatory to `use <interface>::*` due to `impl_resource!`.
+/// That is because `impl_resource!` accesses the `crate`'s
+/// `add_to_linker`, and not the `<interface>::add_to_linker` directly.
+use deployment::*;
+wit_bindgen_wasmtime::export!("../../wit/deployment.wit");
+wit_error_rs::impl_error!(deployment::Error);
+slight_runtime::impl_from_anyhow!(deployment::Error);
+
+/// The `Deployment` structure is what will implement the `deployment::Deployment` trait
+/// coming from the generated code of off `deployment.wit`.
+///
+/// It maintains a `host_state`.
+pub struct Deployment {
+    host_state: DeploymentState,
+}
+
+impl_resource!(
+    Deployment,
+    deployment::DeploymentTables<Deployment>,
+    DeploymentState,
+    SCHEME_NAME.to_string()
+);
+
+/// This is the type of the `host_state` property from our `Deployment` structure.
+///
+/// It holds:
+///     - the `slight_state` (of type `BasicState`) that contains common
+///     things received from the slight binary (i.e., the `resource_map`,
+///     the `config_type`, and the `config_toml_file_path`), and
+///     - the `context` of the deployment, as the operator set it in the slightfile.
+///
+/// Like `platform`, there is only one place the context comes from (i.e., the
+/// slightfile), so there is no implementor to choose from.
+pub struct DeploymentState {
+    slight_state: BasicState,
+    context: DeploymentContext,
+}
+
+impl DeploymentState {
+    pub fn new(slight_state: BasicState) -> Self {
+        Self {
+            slight_state: slight_state
+                .with_idempotent_operations(IDEMPOTENT_OPERATIONS)
+                .with_read_operations(READ_OPERATIONS),
+            context: DeploymentContext::default(),
+        }
+    }
+
+    /// Exposes `context` to the guest, rather than an empty one.
+    pub fn with_context(mut self, context: DeploymentContext) -> Self {
+        self.context = context;
+        self
+    }
+}
+
+impl deployment::Deployment for Deployment {
+    type Deployment = DeploymentInner;
+
+    fn deployment_open(&mut self) -> Result<Self::Deployment, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
+        let inner = Self::Deployment::new();
+
+        self.host_state
+            .slight_state
+            .resource_map
+            .lock()
+            .unwrap()
+            .set(inner.resource_descriptor.clone(), Box::new(inner.clone()));
+
+        Ok(inner)
+    }
+
+    fn deployment_info(&mut self, _self_: &Self::Deployment) -> Result<DeploymentInfo, Error> {
+        let context = &self.host_state.context;
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "info", "", || {
+                Ok(DeploymentInfo {
+                    environment: context.environment.clone(),
+                    region: context.region.clone(),
+                    deployment_id: context.deployment_id.clone(),
+                    metadata: context
+                        .metadata
+                        .iter()
+                        .map(|(key, value)| (key.clone(), value.clone()))
+                        .collect(),
+                })
+            })
+    }
+
+    fn deployment_get(
+        &mut self,
+        _self_: &Self::Deployment,
+        key: &str,
+    ) -> Result<Option<String>, Error> {
+        let context = &self.host_state.context;
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "get", key, || {
+                Ok(context.get(key).map(str::to_string))
+            })
+    }
+
+    fn deployment_list_keys(&mut self, _self_: &Self::Deployment) -> Result<Vec<String>, Error> {
+        self.host_state
+            .slight_state
+            .permit(SCHEME_NAME, "list-keys")?;
+        Ok(self.host_state.context.keys())
+    }
+}
+
+/// This is the type of the associated type coming from the `deployment::Deployment` trait
+/// implementation.
+///
+/// It holds a `resource_descriptor` (i.e., an UUID that uniquely identifies
+/// resource's instance).
+///
+/// It must `derive`:
+///     - `Debug` due to a constraint on the associated type.
+///     - `Clone` because the `ResourceMap` it will be added onto,
+///     must own its data.
+///
+/// It must be public because the implementation of `deployment::Deployment` cannot leak
+/// a private type.
+#[derive(Debug, Clone)]
+pub struct DeploymentInner {
+    resource_descriptor: String,
+}
+
+impl DeploymentInner {
+    fn new() -> Self {
+        Self {
+            resource_descriptor: Uuid::new_v4().to_string(),
+        }
+    }
+}
+
+impl slight_runtime::resource::Watch for DeploymentInner {}
@@ -0,0 +1,158 @@
+use std::cmp::Ordering;
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+/// How a field of a document is compared to a value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+}
+
+impl Comparison {
+    /// Whether two values that compare as `ordering` meet the comparison.
+    fn holds(&self, ordering: Ordering) -> bool {
+        match self {
+            Self::Eq => ordering == Ordering::Equal,
+            Self::Lt => ordering == Ordering::Less,
+            Self::Lte => ordering != Ordering::Greater,
+            Self::Gt => ordering == Ordering::Greater,
+            Self::Gte => ordering != Ordering::Less,
+        }
+    }
+}
+
+/// A condition on a field of the documents to find.
+///
+/// Filters are deliberately minimal, so every implementor can evaluate them the same
+/// way (and on the database's side, when it can): a filter is a list of conditions that
+/// must all hold, and each compares a field to a scalar value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    /// the field, as a path into nested objects (e.g., `["address", "city"]`)
+    pub path: Vec<String>,
+    pub comparison: Comparison,
+    pub value: Value,
+}
+
+impl Condition {
+    /// Parses a condition from a dotted `field` (e.g., `address.city`), and a JSON `value`.
+    pub fn new(field: &str, comparison: Comparison, value: &str) -> Result<Self> {
+        let path = field.split('.').map(str::to_string).collect::<Vec<_>>();
+        if path.iter().any(String::is_empty) {
+            bail!("invalid field: '{}'", field);
+        }
+        let value = serde_json::from_str::<Value>(value)
+            .with_context(|| format!("the value of field '{}' is not valid JSON", field))?;
+        match (&value, comparison) {
+            (Value::Array(_) | Value::Object(_), _) => bail!(
+                "the value of field '{}' must be a string, a number, a boolean, or null",
+                field
+            ),
+            (Value::Bool(_) | Value::Null, c) if c != Comparison::Eq => bail!(
+                "the value of field '{}' must be a string, or a number to compare it w/ {:?}",
+                field,
+                c
+            ),
+            _ => Ok(Self {
+                path,
+                comparison,
+                value,
+            }),
+        }
+    }
+
+    /// Whether a document meets the condition (a document w/o the field never does).
+    pub fn matches(&self, document: &Value) -> bool {
+        let field = self
+            .path
+            .iter()
+            .try_fold(document, |value, segment| value.get(segment));
+        match field.and_then(|field| compare(field, &self.value)) {
+            Some(ordering) => self.comparison.holds(ordering),
+            None => false,
+        }
+    }
+}
+
+/// Whether a document meets all the conditions of a filter.
+pub fn matches(filter: &[Condition], document: &Value) -> bool {
+    filter.iter().all(|condition| condition.matches(document))
+}
+
+/// Compares two scalar JSON values, if they are comparable (i.e., of the same type, where
+/// booleans, and nulls are only ever equal, or not).
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) if a == b => Some(Ordering::Equal),
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+    use serde_json::json;
+
+    use super::{matches, Comparison, Condition};
+
+    #[test]
+    fn matches_test() -> Result<()> {
+        let document = json!({
+            "name": "slight",
+            "stars": 42,
+            "archived": false,
+            "owner": {"org": "deislabs"},
+        });
+        let conditions = [
+            (Condition::new("name", Comparison::Eq, r#""slight""#)?, true),
+            (Condition::new("name", Comparison::Eq, r#""wagi""#)?, false),
+            (Condition::new("stars", Comparison::Eq, "42.0")?, true),
+            (Condition::new("stars", Comparison::Gt, "41")?, true),
+            (Condition::new("stars", Comparison::Lt, "42")?, false),
+            (Condition::new("stars", Comparison::Lte, "42")?, true),
+            (Condition::new("stars", Comparison::Gte, "43")?, false),
+            (Condition::new("name", Comparison::Gte, r#""s""#)?, true),
+            (Condition::new("archived", Comparison::Eq, "false")?, true),
+            (
+                Condition::new("owner.org", Comparison::Eq, r#""deislabs""#)?,
+                true,
+            ),
+            // missing fields, and values of other types never match
+            (Condition::new("owner.team", Comparison::Eq, "null")?, false),
+            (Condition::new("stars", Comparison::Eq, r#""42""#)?, false),
+        ];
+        for (condition, expected) in &conditions {
+            assert_eq!(
+                condition.matches(&document),
+                *expected,
+                "{:?} didn't match as expected",
+                condition
+            );
+        }
+
+        let filter = [
+            Condition::new("name", Comparison::Eq, r#""slight""#)?,
+            Condition::new("stars", Comparison::Gt, "100")?,
+        ];
+        assert!(!matches(&filter, &document));
+        assert!(matches(&filter[..1], &document));
+        assert!(matches(&[], &document));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_condition_test() {
+        assert!(Condition::new("owner..org", Comparison::Eq, "1").is_err());
+        assert!(Condition::new("name", Comparison::Eq, "slight").is_err());
+        assert!(Condition::new("tags", Comparison::Eq, r#"["a"]"#).is_err());
+        assert!(Condition::new("archived", Comparison::Lt, "true").is_err());
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Context, Result};
+use aws_sdk_dynamodb::model::AttributeValue;
+use aws_sdk_dynamodb::{Client, Config, Credentials, Region};
+use serde_json::{Map, Number, Value};
+use slight_runtime::deadline::block_on;
+use slight_runtime::{describe::Description, resource::BasicState};
+use tracing::log;
+
+use crate::filter::{Comparison, Condition};
+
+/// The attribute documents are kept in (i.e., as a DynamoDB map).
+const DOCUMENT: &str = "document";
+
+/// This is the underlying struct behind the `AwsDynamoDb` variant of the `DocstoreImplementor` enum.
+///
+/// It provides properties that pertain solely to the AWS DynamoDB implementation
+/// of this capability:
+///     - `client`, and
+///     - `table_name`.
+///
+/// As per its usage in `DocstoreImplementor`, it must `derive` `Debug`, and `Clone`.
+#[derive(Debug, Clone)]
+pub struct AwsDynamoDbImplementor {
+    client: Client,
+    table_name: String,
+}
+
+impl AwsDynamoDbImplementor {
+    /// Creates a new `AwsDynamoDbImplementor` instance, for the table named `name`.
+    ///
+    /// The AWS credentials are read from the secret store:
+    ///   - `AWS_ACCESS_KEY_ID`,
+    ///   - `AWS_SECRET_ACCESS_KEY`, and
+    ///   - `AWS_REGION`.
+    ///
+    /// The table must have a partition key named `collection`, and a sort key named `id`
+    /// (both of type string). Documents are kept as native DynamoDB maps, so filters are
+    /// evaluated by DynamoDB:
+    /// ```text
+    /// {
+    ///   "collection": {
+    ///       "S": <collection>
+    ///   },
+    ///   "id": {
+    ///       "S": <id>
+    ///   },
+    ///   "document": {
+    ///       "M": <document>
+    ///   }
+    /// }
+    /// ```
+    pub fn new(slight_state: &BasicState, name: &str) -> Result<Self> {
+        let secret = |key: &str| -> Result<String> {
+            let value = slight_runtime_configs::resolve(
+                &slight_state.secret_stores,
+                key,
+                &slight_state.config_toml_file_path,
+            )
+            .with_context(|| {
+                format!(
+                    "failed to get '{}' secret using secret stores: {:?}",
+                    key, slight_state.secret_stores
+                )
+            })?;
+            Ok(String::from_utf8(value)?)
+        };
+        let config = Config::builder()
+            .region(Region::new(secret("AWS_REGION")?))
+            .credentials_provider(Credentials::new(
+                secret("AWS_ACCESS_KEY_ID")?,
+                secret("AWS_SECRET_ACCESS_KEY")?,
+                None,
+                None,
+                "slight-secret-store",
+            ))
+            .build();
+        log::info!(
+            "Creating a new AWS DynamoDB document store with table name: {}",
+            name
+        );
+        Ok(Self {
+            client: Client::from_conf(config),
+            table_name: name.to_string(),
+        })
+    }
+
+    /// Describes the region tables are kept in (see `slight_runtime::describe`).
+    pub fn describe(slight_state: &BasicState) -> Description {
+        let setting = |key: &str| slight_runtime_configs::setting(slight_state, key);
+        let region = setting("AWS_REGION");
+        let endpoint = region
+            .as_ref()
+            .map(|region| format!("dynamodb.{}.amazonaws.com", region))
+            .map_err(|e| anyhow!("{:#}", e));
+        Description::new("docstore.awsdynamodb", "AWS DynamoDB")
+            .with_endpoint("AWS_REGION", endpoint)
+            .with_setting("AWS_REGION", region)
+            .with_secret("AWS_ACCESS_KEY_ID", setting("AWS_ACCESS_KEY_ID"))
+            .with_secret("AWS_SECRET_ACCESS_KEY", setting("AWS_SECRET_ACCESS_KEY"))
+    }
+
+    pub fn put(&self, collection: &str, id: &str, document: &Value) -> Result<()> {
+        log::info!("Putting document '{}' of collection '{}'", id, collection);
+        block_on(
+            self.client
+                .put_item()
+                .table_name(&self.table_name)
+                .item("collection", AttributeValue::S(collection.to_string()))
+                .item("id", AttributeValue::S(id.to_string()))
+                .item(DOCUMENT, to_attribute(document))
+                .send(),
+        )??;
+        Ok(())
+    }
+
+    pub fn get(&self, collection: &str, id: &str) -> Result<Option<Value>> {
+        log::info!("Getting document '{}' of collection '{}'", id, collection);
+        let res = block_on(
+            self.client
+                .get_item()
+                .table_name(&self.table_name)
+                .key("collection", AttributeValue::S(collection.to_string()))
+                .key("id", AttributeValue::S(id.to_string()))
+                .consistent_read(true)
+                .send(),
+        )??;
+        res.item.map(|item| document(&item)).transpose()
+    }
+
+    pub fn delete(&self, collection: &str, id: &str) -> Result<()> {
+        log::info!("Deleting document '{}' of collection '{}'", id, collection);
+        block_on(
+            self.client
+                .delete_item()
+                .table_name(&self.table_name)
+                .key("collection", AttributeValue::S(collection.to_string()))
+                .key("id", AttributeValue::S(id.to_string()))
+                .send(),
+        )??;
+        Ok(())
+    }
+
+    /// Queries the collection's partition, paginating through it, w/ the filter as
+    /// a DynamoDB filter expression.
+    pub fn find(&self, collection: &str, filter: &[Condition]) -> Result<Vec<(String, Value)>> {
+        log::info!("Finding documents of collection '{}'", collection);
+        let expression = FilterExpression::new(filter);
+        let mut found = Vec::new();
+        let mut start_key = None;
+        loop {
+            let mut query = self
+                .client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("#collection = :collection".to_string())
+                .expression_attribute_names("#collection".to_string(), "collection".to_string())
+                .expression_attribute_values(
+                    ":collection".to_string(),
+                    AttributeValue::S(collection.to_string()),
+                )
+                .consistent_read(true)
+                .set_exclusive_start_key(start_key);
+            if !filter.is_empty() {
+                query = query.filter_expression(expression.expression.clone());
+            }
+            for (placeholder, name) in &expression.names {
+                query = query.expression_attribute_names(placeholder.clone(), name.clone());
+            }
+            for (placeholder, value) in &expression.values {
+                query = query.expression_attribute_values(placeholder.clone(), value.clone());
+            }
+            let res = block_on(query.send())??;
+            for item in res.items.unwrap_or_default() {
+                let id = item
+                    .get("id")
+                    .and_then(|id| id.as_s().ok())
+                    .with_context(|| "found an item w/o a string id")?;
+                found.push((id.clone(), document(&item)?));
+            }
+            start_key = res.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(found);
+            }
+        }
+    }
+}
+
+/// A filter as a DynamoDB filter expression, w/ placeholders for every field name (so
+/// they can't clash w/ DynamoDB's reserved words), and value (so they can't inject
+/// anything into the expression).
+#[derive(Debug, PartialEq)]
+struct FilterExpression {
+    expression: String,
+    names: Vec<(String, String)>,
+    values: Vec<(String, AttributeValue)>,
+}
+
+impl FilterExpression {
+    fn new(filter: &[Condition]) -> Self {
+        let mut names = vec![("#document".to_string(), DOCUMENT.to_string())];
+        let mut values = Vec::new();
+        let mut conditions = Vec::new();
+        for (i, condition) in filter.iter().enumerate() {
+            let mut path = "#document".to_string();
+            for (j, segment) in condition.path.iter().enumerate() {
+                let placeholder = format!("#f{}_{}", i, j);
+                path.push('.');
+                path.push_str(&placeholder);
+                names.push((placeholder, segment.clone()));
+            }
+            let value = format!(":v{}", i);
+            let operator = match condition.comparison {
+                Comparison::Eq => "=",
+                Comparison::Lt => "<",
+                Comparison::Lte => "<=",
+                Comparison::Gt => ">",
+                Comparison::Gte => ">=",
+            };
+            conditions.push(format!("{} {} {}", path, operator, value));
+            values.push((value, to_attribute(&condition.value)));
+        }
+        Self {
+            expression: conditions.join(" AND "),
+            names,
+            values,
+        }
+    }
+}
+
+fn document(item: &HashMap<String, AttributeValue>) -> Result<Value> {
+    match item.get(DOCUMENT) {
+        Some(document) => from_attribute(document),
+        None => bail!("found an item w/o a document"),
+    }
+}
+
+fn to_attribute(value: &Value) -> AttributeValue {
+    match value {
+        Value::Null => AttributeValue::Null(true),
+        Value::Bool(b) => AttributeValue::Bool(*b),
+        Value::Number(n) => AttributeValue::N(n.to_string()),
+        Value::String(s) => AttributeValue::S(s.clone()),
+        Value::Array(items) => AttributeValue::L(items.iter().map(to_attribute).collect()),
+        Value::Object(fields) => AttributeValue::M(
+            fields
+                .iter()
+                .map(|(field, value)| (field.clone(), to_attribute(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn from_attribute(attribute: &AttributeValue) -> Result<Value> {
+    Ok(match attribute {
+        AttributeValue::Null(_) => Value::Null,
+        AttributeValue::Bool(b) => Value::Bool(*b),
+        AttributeValue::N(n) => Value::Number(
+            n.parse::<Number>()
+                .with_context(|| format!("invalid number: '{}'", n))?,
+        ),
+        AttributeValue::S(s) => Value::String(s.clone()),
+        AttributeValue::L(items) => Value::Array(
+            items
+                .iter()
+                .map(from_attribute)
+                .collect::<Result<Vec<_>>>()?,
+        ),
+        AttributeValue::M(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(field, value)| Ok((field.clone(), from_attribute(value)?)))
+                .collect::<Result<Map<_, _>>>()?,
+        ),
+        a => bail!("unsupported attribute type in a document: {:?}", a),
+    })
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+    use aws_sdk_dynamodb::model::AttributeValue;
+    use serde_json::json;
+
+    use super::{from_attribute, to_attribute, FilterExpression};
+    use crate::filter::{Comparison, Condition};
+
+    #[test]
+    fn attribute_roundtrip_test() -> Result<()> {
+        let document = json!({
+            "name": "slight",
+            "stars": 42,
+            "ratio": 0.5,
+            "archived": false,
+            "tags": ["wasm", null],
+            "owner": {"org": "deislabs"},
+        });
+        assert_eq!(from_attribute(&to_attribute(&document))?, document);
+        Ok(())
+    }
+
+    #[test]
+    fn filter_expression_test() -> Result<()> {
+        let expression = FilterExpression::new(&[
+            Condition::new("owner.org", Comparison::Eq, r#""deislabs""#)?,
+            Condition::new("stars", Comparison::Gte, "10")?,
+        ]);
+        assert_eq!(
+            expression.expression,
+            "#document.#f0_0.#f0_1 = :v0 AND #document.#f1_0 >= :v1"
+        );
+        assert_eq!(expression.names.len(), 4);
+        assert_eq!(
+            expression.values,
+            vec![
+                (":v0".to_string(), AttributeValue::S("deislabs".to_string())),
+                (":v1".to_string(), AttributeValue::N("10".to_string())),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    env, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+use slight_runtime::describe::Description;
+use uuid::Uuid;
+
+use crate::filter::{self, Condition};
+
+/// The extension of the files documents are kept in.
+const EXTENSION: &str = "json";
+
+/// This is the underlying struct behind the `Filesystem` variant of the `DocstoreImplementor` enum.
+///
+/// It provides a property that pertains solely to the filesystem implementation
+/// of this capability:
+///     - `base`.
+///
+/// Each collection is a directory of the `base`, and each document is a JSON file in it,
+/// named after its id (e.g., `<base>/users/42.json`).
+///
+/// As per its usage in `DocstoreImplementor`, it must `derive` `Debug`, and `Clone`.
+#[derive(Debug, Clone)]
+pub struct FilesystemImplementor {
+    /// The base path for where the document store can be found in your file-system
+    base: PathBuf,
+}
+
+impl FilesystemImplementor {
+    pub fn new(name: &str) -> Self {
+        Self {
+            base: env::temp_dir().join(format!("slight-docstore-{}", name)),
+        }
+    }
+
+    /// Describes where stores are kept (see `slight_runtime::describe`) — each in a directory
+    /// of this one, named after it (i.e., `slight-docstore-<name>`).
+    pub fn describe() -> Description {
+        Description::new("docstore.filesystem", "the filesystem")
+            .with_setting("directory", Ok(env::temp_dir().display().to_string()))
+    }
+
+    fn path(&self, collection: &str, id: &str) -> PathBuf {
+        self.base
+            .join(collection)
+            .join(format!("{}.{}", id, EXTENSION))
+    }
+
+    /// Writes the document to a temporary file first, so readers never see half of it.
+    pub fn put(&self, collection: &str, id: &str, document: &Value) -> Result<()> {
+        let dir = self.base.join(collection);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create collection '{}'", collection))?;
+        let tmp = dir.join(format!(".{}.tmp", Uuid::new_v4()));
+        fs::write(&tmp, serde_json::to_vec(document)?)
+            .with_context(|| format!("failed to write document '{}'", id))?;
+        fs::rename(&tmp, self.path(collection, id))
+            .with_context(|| format!("failed to write document '{}'", id))?;
+        Ok(())
+    }
+
+    pub fn get(&self, collection: &str, id: &str) -> Result<Option<Value>> {
+        read(&self.path(collection, id))
+    }
+
+    pub fn delete(&self, collection: &str, id: &str) -> Result<()> {
+        match fs::remove_file(self.path(collection, id)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("failed to delete document '{}'", id))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads every document of the collection, as there's no index to narrow them down.
+    pub fn find(&self, collection: &str, filter: &[Condition]) -> Result<Vec<(String, Value)>> {
+        let entries = match fs::read_dir(self.base.join(collection)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to read collection '{}'", collection))
+            }
+        };
+        let mut found = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let id = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(id) if path.extension().map_or(false, |e| e == EXTENSION) => id.to_string(),
+                _ => continue,
+            };
+            // the document may have been deleted since the collection was read
+            if let Some(document) = read(&path)? {
+                if filter::matches(filter, &document) {
+                    found.push((id, document));
+                }
+            }
+        }
+        found.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(found)
+    }
+}
+
+fn read(path: &Path) -> Result<Option<Value>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes).with_context(|| {
+            format!("document '{}' is corrupted", path.display())
+        })?)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("failed to read '{}'", path.display())),
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::FilesystemImplementor;
+    use crate::filter::{Comparison, Condition};
+
+    #[test]
+    fn put_get_delete_test() -> Result<()> {
+        let store = FilesystemImplementor::new(&Uuid::new_v4().to_string());
+        assert_eq!(store.get("users", "42")?, None);
+        store.put("users", "42", &json!({"name": "slight"}))?;
+        assert_eq!(store.get("users", "42")?, Some(json!({"name": "slight"})));
+        store.put("users", "42", &json!({"name": "wagi"}))?;
+        assert_eq!(store.get("users", "42")?, Some(json!({"name": "wagi"})));
+        store.delete("users", "42")?;
+        assert_eq!(store.get("users", "42")?, None);
+        // deleting a document that doesn't exist is a no-op
+        store.delete("users", "42")?;
+        Ok(())
+    }
+
+    #[test]
+    fn find_test() -> Result<()> {
+        let store = FilesystemImplementor::new(&Uuid::new_v4().to_string());
+        assert!(store.find("users", &[])?.is_empty());
+        store.put("users", "b", &json!({"age": 30}))?;
+        store.put("users", "a", &json!({"age": 20}))?;
+        store.put("users", "c", &json!({"age": 40}))?;
+        store.put("teams", "d", &json!({"age": 30}))?;
+
+        let ids = |found: Vec<(String, serde_json::Value)>| {
+            found.into_iter().map(|(id, _)| id).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(store.find("users", &[])?), vec!["a", "b", "c"]);
+        let filter = [Condition::new("age", Comparison::Gte, "30")?];
+        assert_eq!(ids(store.find("users", &filter)?), vec!["b", "c"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,2 @@
+pub mod awsdynamodb;
+pub mod filesystem;
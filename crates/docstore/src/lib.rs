@@ -0,0 +1,317 @@
+mod filter;
+mod implementors;
+
+/// The `SCHEME_NAME` defines the name under which a resource is
+/// identifiable by in a `ResourceMap`.
+const SCHEME_NAME: &str = "docstore";
+/// The operations guests can retry if they time out (see `BasicState::with_idempotent_operations`).
+const IDEMPOTENT_OPERATIONS: &[&str] = &["get", "find"];
+/// The operations that only read, which audits of writes only leave out (see
+/// `BasicState::with_read_operations`).
+const READ_OPERATIONS: &[&str] = &["get", "find"];
+
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use uuid::Uuid;
+
+use implementors::{awsdynamodb::AwsDynamoDbImplementor, filesystem::FilesystemImplementor};
+use slight_runtime::{describe::Description, impl_resource, resource::BasicState};
+
+/// It is mandatory to `use <interface>::*` due to `impl_resource!`.
+/// That is because `impl_resource!` accesses the `crate`'s
+/// `add_to_linker`, and not the `<interface>::add_to_linker` directly.
+use docstore::*;
+wit_bindgen_wasmtime::export!("../../wit/docstore.wit");
+wit_error_rs::impl_error!(docstore::Error);
+slight_runtime::impl_from_anyhow!(docstore::Error);
+
+/// The longest a collection name, or a document id can be.
+const MAX_NAME_LEN: usize = 255;
+
+/// The `Docstore` structure is what will implement the `docstore::Docstore` trait
+/// coming from the generated code of off `docstore.wit`.
+///
+/// It maintains a `host_state`.
+pub struct Docstore {
+    host_state: DocstoreState,
+}
+
+impl_resource!(
+    Docstore,
+    docstore::DocstoreTables<Docstore>,
+    DocstoreState,
+    SCHEME_NAME.to_string()
+);
+
+/// This is the type of the `host_state` property from our `Docstore` structure.
+///
+/// It holds:
+///     - a `docstore_implementor` `String` — this comes directly from a
+///     user's `slightfile` and it is what allows us to dynamically
+///     dispatch to a specific implementor's implentation, and
+///     - the `slight_state` (of type `BasicState`) that contains common
+///     things received from the slight binary (i.e., the `resource_map`,
+///     the `config_type`, and the `config_toml_file_path`).
+pub struct DocstoreState {
+    docstore_implementor: String,
+    slight_state: BasicState,
+}
+
+impl DocstoreState {
+    pub fn new(docstore_implementor: String, slight_state: BasicState) -> Self {
+        Self {
+            docstore_implementor,
+            slight_state: slight_state
+                .with_idempotent_operations(IDEMPOTENT_OPERATIONS)
+                .with_read_operations(READ_OPERATIONS),
+        }
+    }
+
+    /// Describes the effective configuration of the backend (see `slight_runtime::describe`),
+    /// w/o connecting to it.
+    pub fn describe(&self) -> Result<Description> {
+        let description = match self.docstore_implementor.as_str() {
+            "docstore.filesystem" => FilesystemImplementor::describe(),
+            "docstore.awsdynamodb" => AwsDynamoDbImplementor::describe(&self.slight_state),
+            p => bail!(
+                "failed to match provided name (i.e., '{}') to any known host implementations",
+                p
+            ),
+        };
+        Ok(description.with_pool_size(self.slight_state.pool_size()))
+    }
+}
+
+impl From<Comparison> for filter::Comparison {
+    fn from(comparison: Comparison) -> Self {
+        match comparison {
+            Comparison::Eq => Self::Eq,
+            Comparison::Lt => Self::Lt,
+            Comparison::Lte => Self::Lte,
+            Comparison::Gt => Self::Gt,
+            Comparison::Gte => Self::Gte,
+        }
+    }
+}
+
+impl docstore::Docstore for Docstore {
+    type Docstore = DocstoreInner;
+
+    fn docstore_open(&mut self, name: &str) -> Result<Self::Docstore, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
+        // populate our inner docstore object w/ the state received from `slight`
+        // (i.e., what type of docstore implementor we are using), and the assigned
+        // name of the object.
+        let inner = Self::Docstore::new(
+            &self.host_state.docstore_implementor,
+            &self.host_state.slight_state,
+            name,
+        )?;
+
+        self.host_state
+            .slight_state
+            .resource_map
+            .lock()
+            .unwrap()
+            .set(inner.resource_descriptor.clone(), Box::new(inner.clone()));
+
+        Ok(inner)
+    }
+
+    fn docstore_put(
+        &mut self,
+        self_: &Self::Docstore,
+        collection: &str,
+        id: &str,
+        document: &str,
+    ) -> Result<(), Error> {
+        check_name("collection", collection)?;
+        check_name("id", id)?;
+        let document = serde_json::from_str::<Value>(document)
+            .with_context(|| format!("document '{}' is not valid JSON", id))?;
+        if !document.is_object() {
+            return Err(anyhow::anyhow!("document '{}' is not a JSON object", id).into());
+        }
+        Ok(self.host_state.slight_state.instrument(
+            SCHEME_NAME,
+            "put",
+            collection,
+            || match &self_.docstore_implementor {
+                DocstoreImplementor::Filesystem(fi) => fi.put(collection, id, &document),
+                DocstoreImplementor::AwsDynamoDb(adi) => adi.put(collection, id, &document),
+            },
+        )?)
+    }
+
+    fn docstore_get(
+        &mut self,
+        self_: &Self::Docstore,
+        collection: &str,
+        id: &str,
+    ) -> Result<Option<String>, Error> {
+        check_name("collection", collection)?;
+        check_name("id", id)?;
+        let document = self.host_state.slight_state.instrument(
+            SCHEME_NAME,
+            "get",
+            collection,
+            || match &self_.docstore_implementor {
+                DocstoreImplementor::Filesystem(fi) => fi.get(collection, id),
+                DocstoreImplementor::AwsDynamoDb(adi) => adi.get(collection, id),
+            },
+        )?;
+        Ok(document.map(|document| document.to_string()))
+    }
+
+    fn docstore_delete(
+        &mut self,
+        self_: &Self::Docstore,
+        collection: &str,
+        id: &str,
+    ) -> Result<(), Error> {
+        check_name("collection", collection)?;
+        check_name("id", id)?;
+        Ok(self
+            .host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "delete", collection, || {
+                match &self_.docstore_implementor {
+                    DocstoreImplementor::Filesystem(fi) => fi.delete(collection, id),
+                    DocstoreImplementor::AwsDynamoDb(adi) => adi.delete(collection, id),
+                }
+            })?)
+    }
+
+    fn docstore_find(
+        &mut self,
+        self_: &Self::Docstore,
+        collection: &str,
+        conditions: Vec<Condition<'_>>,
+    ) -> Result<Vec<FoundDocument>, Error> {
+        check_name("collection", collection)?;
+        let filter = conditions
+            .iter()
+            .map(|c| filter::Condition::new(c.field, c.comparison.into(), c.value))
+            .collect::<Result<Vec<_>>>()?;
+        let found =
+            self.host_state
+                .slight_state
+                .instrument(SCHEME_NAME, "find", collection, || {
+                    match &self_.docstore_implementor {
+                        DocstoreImplementor::Filesystem(fi) => fi.find(collection, &filter),
+                        DocstoreImplementor::AwsDynamoDb(adi) => adi.find(collection, &filter),
+                    }
+                })?;
+        Ok(found
+            .into_iter()
+            .map(|(id, document)| FoundDocument {
+                id,
+                document: document.to_string(),
+            })
+            .collect())
+    }
+}
+
+/// Makes sure a collection name, or a document id can be used as-is by every implementor
+/// (e.g., as a file name): it must be made of letters, digits, `-`, `_`, `.`, or `@`,
+/// and it can't start w/ a `.`.
+fn check_name(what: &str, name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.len() > MAX_NAME_LEN
+        || name.starts_with('.')
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '@'))
+    {
+        bail!(
+            "invalid {}: '{}' (expected up to {} letters, digits, '-', '_', '.', or '@', not starting w/ a '.')",
+            what,
+            name,
+            MAX_NAME_LEN
+        );
+    }
+    Ok(())
+}
+
+/// This is the type of the associated type coming from the `docstore::Docstore` trait
+/// implementation.
+///
+/// It holds:
+///     - a `docstore_implementor` (i.e., a variant `DocstoreImplementor` `enum`), and
+///     - a `resource_descriptor` (i.e., an UUID that uniquely identifies
+///     resource's instance).
+///
+/// It must `derive`:
+///     - `Debug` due to a constraint on the associated type.
+///     - `Clone` because the `ResourceMap` it will be added onto,
+///     must own its data.
+///
+/// It must be public because the implementation of `docstore::Docstore` cannot leak
+/// a private type.
+#[derive(Debug, Clone)]
+pub struct DocstoreInner {
+    docstore_implementor: DocstoreImplementor,
+    resource_descriptor: String,
+}
+
+impl DocstoreInner {
+    fn new(docstore_implementor: &str, slight_state: &BasicState, name: &str) -> Result<Self> {
+        Ok(Self {
+            docstore_implementor: DocstoreImplementor::new(
+                docstore_implementor,
+                slight_state,
+                name,
+            )?,
+            resource_descriptor: Uuid::new_v4().to_string(),
+        })
+    }
+}
+
+impl slight_runtime::resource::Watch for DocstoreInner {}
+
+/// This defines the available implementor implementations for the `Docstore` interface.
+///
+/// As per its usage in `DocstoreInner`, it must `derive` `Debug`, and `Clone`.
+///
+/// The implementors that hold clients share them w/ the other guest instances of the app
+/// (see `BasicState::connection`), rather than connecting once per instance.
+#[derive(Debug, Clone)]
+enum DocstoreImplementor {
+    Filesystem(FilesystemImplementor),
+    AwsDynamoDb(Arc<AwsDynamoDbImplementor>),
+}
+
+impl DocstoreImplementor {
+    fn new(docstore_implementor: &str, slight_state: &BasicState, name: &str) -> Result<Self> {
+        Ok(match docstore_implementor {
+            "docstore.filesystem" => Self::Filesystem(FilesystemImplementor::new(name)),
+            "docstore.awsdynamodb" => Self::AwsDynamoDb(slight_state.try_connection(
+                docstore_implementor,
+                name,
+                || AwsDynamoDbImplementor::new(slight_state, name),
+            )?),
+            p => bail!(
+                "failed to match provided name (i.e., '{}') to any known host implementations",
+                p
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use super::check_name;
+
+    #[test]
+    fn check_name_test() {
+        assert!(check_name("id", "42").is_ok());
+        assert!(check_name("id", "jane.doe@example.com").is_ok());
+        assert!(check_name("id", "").is_err());
+        assert!(check_name("id", "..").is_err());
+        assert!(check_name("id", "../secrets").is_err());
+        assert!(check_name("id", ".hidden").is_err());
+        assert!(check_name("id", &"a".repeat(256)).is_err());
+    }
+}
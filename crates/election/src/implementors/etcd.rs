@@ -0,0 +1,191 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use etcd_client::{Client, LeaderKey, ResignOptions};
+use slight_runtime::deadline::block_on;
+use slight_runtime::{
+    compat::{Compatibility, Requirement, Version},
+    describe::Description,
+    resource::BasicState,
+};
+
+use crate::{lease::Lease, Observed};
+
+/// What the implementor needs of the etcd server: leases (which etcd v3 has had from the
+/// start), and the election API (i.e., `v3election`), which came w/ etcd 3.2.
+pub const COMPATIBILITY: Compatibility = Compatibility {
+    implementor: "election.etcd",
+    server: "etcd",
+    requirements: &[
+        Requirement {
+            feature: "leases",
+            minimum: Version::new(3, 0, 0),
+        },
+        Requirement {
+            feature: "the election API",
+            minimum: Version::new(3, 2, 0),
+        },
+    ],
+    tested_major: 3,
+};
+
+/// This is the underlying struct behind the `Etcd` variant of the `ElectionImplementor` enum.
+///
+/// It provides properties that pertain solely to the etcd implementation
+/// of this capability:
+///     - `client`, and
+///     - the `candidacy` of this candidate (if it campaigned), which is shared by its clones.
+///
+/// As per its usage in `ElectionImplementor`, it must `derive` `Debug`, and `Clone`.
+#[derive(Clone)]
+pub struct EtcdImplementor {
+    client: Client,
+    candidacy: Arc<Mutex<Option<Candidacy>>>,
+}
+
+/// A candidate that was elected: its leader key (i.e., the key it campaigned w/, which etcd
+/// deletes w/ its lease), and the lease that's kept alive for it.
+struct Candidacy {
+    leader: LeaderKey,
+    lease_id: i64,
+    lease: Lease,
+}
+
+impl std::fmt::Debug for EtcdImplementor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EtcdImplementor")
+    }
+}
+
+impl EtcdImplementor {
+    /// Describes the etcd server elections are held in (see `slight_runtime::describe`).
+    pub fn describe(slight_state: &BasicState) -> Description {
+        Description::new("election.etcd", "etcd").with_endpoint(
+            "ETCD_ENDPOINT",
+            slight_runtime_configs::setting(slight_state, "ETCD_ENDPOINT"),
+        )
+    }
+
+    /// Connects to the etcd server, and checks it's compatible (see `COMPATIBILITY`).
+    pub fn connect(slight_state: &BasicState) -> Result<Self> {
+        let endpoint = String::from_utf8(
+            slight_runtime_configs::resolve(
+                &slight_state.secret_stores,
+                "ETCD_ENDPOINT",
+                &slight_state.config_toml_file_path,
+            )
+            .with_context(|| {
+                format!(
+                    "failed to get 'ETCD_ENDPOINT' secret using secret stores: {:?}",
+                    slight_state.secret_stores
+                )
+            })?,
+        )?;
+
+        let mut client = block_on(Client::connect([endpoint], None))?
+            .with_context(|| "failed to connect to etcd server")?;
+        let status = block_on(client.status())?
+            .with_context(|| "failed to get the version of the etcd server")?;
+        COMPATIBILITY.check(status.version())?;
+        Ok(Self {
+            client,
+            candidacy: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Campaigns w/ `value` to lead election `name`, waiting until this candidate is elected.
+    ///
+    /// The candidacy is tied to a lease of `ttl` that is granted, and expired by the etcd
+    /// server, and kept alive from the moment it's granted (i.e., while waiting too) — if the
+    /// host dies, its leadership ends w/ the lease.
+    pub fn campaign(&self, name: &str, value: &[u8], ttl: Duration) -> Result<()> {
+        if self.is_leader() {
+            return Ok(());
+        }
+        let mut client = self.client.clone();
+        let lease_id = block_on(client.lease_grant(ttl.as_secs().max(1) as i64, None))?
+            .with_context(|| "failed to grant lease")?
+            .id();
+        let (mut keeper, mut responses) = block_on(client.lease_keep_alive(lease_id))?
+            .with_context(|| "failed to keep lease alive")?;
+        let lease = Lease::keep_alive(name, ttl, move || {
+            block_on(keeper.keep_alive())??;
+            match block_on(responses.message())?? {
+                Some(response) => Ok(response.ttl() > 0),
+                None => bail!("the lease's keep-alive stream closed"),
+            }
+        })?;
+        // campaigning waits (until it's elected) no longer than the call's deadline
+        let elected = block_on(client.campaign(name, value, lease_id))
+            .map_err(anyhow::Error::from)
+            .and_then(|elected| elected.map_err(anyhow::Error::from));
+        let elected = match elected {
+            Ok(elected) => elected,
+            Err(e) => {
+                lease.stop();
+                // past the deadline, the lease isn't revoked, but left to expire
+                let _ = block_on(client.lease_revoke(lease_id));
+                return Err(e).with_context(|| "failed to campaign");
+            }
+        };
+        let leader = elected
+            .leader()
+            .cloned()
+            .with_context(|| "etcd returned no leader key")?;
+        tracing::info!("elected to lead election '{}'", name);
+        *self.candidacy.lock().unwrap() = Some(Candidacy {
+            leader,
+            lease_id,
+            lease,
+        });
+        Ok(())
+    }
+
+    pub fn is_leader(&self) -> bool {
+        matches!(&*self.candidacy.lock().unwrap(), Some(candidacy) if !candidacy.lease.lost())
+    }
+
+    /// Resigns the leadership, and revokes its lease (unless it was lost already).
+    pub fn resign(&self) -> Result<()> {
+        let candidacy = match self.candidacy.lock().unwrap().take() {
+            Some(candidacy) => candidacy,
+            None => return Ok(()),
+        };
+        candidacy.lease.stop();
+        if candidacy.lease.lost() {
+            return Ok(());
+        }
+        let mut client = self.client.clone();
+        block_on(client.resign(Some(
+            ResignOptions::new().with_leader(candidacy.leader.clone()),
+        )))?
+        .with_context(|| "failed to resign")?;
+        block_on(client.lease_revoke(candidacy.lease_id))?
+            .with_context(|| "failed to revoke lease")?;
+        Ok(())
+    }
+
+    /// Who leads election `name`, as seen by this candidate.
+    pub fn observe(&self, name: &str) -> Result<Observed> {
+        let own_key = match &*self.candidacy.lock().unwrap() {
+            Some(candidacy) if candidacy.lease.lost() => return Ok(Observed::Lost),
+            Some(candidacy) => Some(candidacy.leader.key().to_vec()),
+            None => None,
+        };
+        let mut client = self.client.clone();
+        let leader = match block_on(client.leader(name))? {
+            Ok(leader) => leader,
+            // etcd fails w/ "election: no leader" if no one leads
+            Err(e) if e.to_string().contains("no leader") => return Ok(Observed::Vacant),
+            Err(e) => return Err(e).with_context(|| "failed to get leader"),
+        };
+        Ok(match leader.kv() {
+            Some(kv) if Some(kv.key()) == own_key.as_deref() => Observed::Elected,
+            Some(kv) => Observed::LedBy(kv.value().to_vec()),
+            None => Observed::Vacant,
+        })
+    }
+}
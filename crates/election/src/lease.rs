@@ -0,0 +1,208 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+/// How long a candidate's lease lives, unless the slightfile says otherwise.
+pub const DEFAULT_LEASE_TTL: Duration = Duration::from_secs(10);
+
+/// `Lease` keeps a candidate's lease alive, renewing it every third of its `ttl` from a thread
+/// of its own, until it's stopped (or dropped), or lost.
+///
+/// A lease is lost (see `lost`) once:
+///     - the backend says it expired, or
+///     - it couldn't be renewed for a whole `ttl` (e.g., as the backend is unreachable, or a
+///     renewal hangs), as, by then, it may have expired, and another candidate may have been
+///     elected.
+///
+/// Renewals are made from a thread of their own, and waited for only as long as the lease has
+/// left, so one that hangs (e.g., on a keep-alive the backend never answers) still loses the
+/// lease in time, rather than keeping the leadership past its `ttl`.
+#[derive(Debug)]
+pub struct Lease {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    lost: AtomicBool,
+    stopped: Mutex<bool>,
+    stop: Condvar,
+}
+
+impl Lease {
+    /// Starts keeping the lease of election `name` alive w/ `renew`, which returns whether the
+    /// lease is still alive.
+    pub fn keep_alive(
+        name: &str,
+        ttl: Duration,
+        mut renew: impl FnMut() -> Result<bool> + Send + 'static,
+    ) -> Result<Self> {
+        let inner = Arc::new(Inner::default());
+        let lease = inner.clone();
+        let name = name.to_string();
+        let (renewing, renewals) = mpsc::channel::<()>();
+        let (renewed, results) = mpsc::channel();
+        thread::Builder::new()
+            .name("slight-election-renew".to_string())
+            .spawn(move || {
+                // it stops once the lease's thread is gone (i.e., `renewing` is dropped)
+                while renewals.recv().is_ok() {
+                    if renewed.send(renew()).is_err() {
+                        return;
+                    }
+                }
+            })?;
+        thread::Builder::new()
+            .name("slight-election-lease".to_string())
+            .spawn(move || {
+                let mut renewed_at = Instant::now();
+                while !lease.wait(ttl / 3) {
+                    let attempted_at = Instant::now();
+                    let result = match renewing.send(()) {
+                        Ok(()) => results.recv_timeout(ttl.saturating_sub(renewed_at.elapsed())),
+                        Err(_) => Err(RecvTimeoutError::Disconnected),
+                    };
+                    match result {
+                        Ok(Ok(true)) => renewed_at = attempted_at,
+                        Ok(Ok(false)) => {
+                            tracing::warn!("the lease of election '{}' expired", name);
+                            lease.lost.store(true, Ordering::Release);
+                            return;
+                        }
+                        Ok(Err(e)) => {
+                            tracing::warn!(
+                                "failed to renew the lease of election '{}': {:#}",
+                                name,
+                                e
+                            )
+                        }
+                        // the renewal is still hanging, and the lease ran out while waiting
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => {
+                            tracing::warn!(
+                                "failed to renew the lease of election '{}': its renewals stopped",
+                                name
+                            );
+                            lease.lost.store(true, Ordering::Release);
+                            return;
+                        }
+                    }
+                    if renewed_at.elapsed() >= ttl {
+                        tracing::warn!(
+                            "failed to renew the lease of election '{}' for {:?}, so it's lost",
+                            name,
+                            ttl
+                        );
+                        lease.lost.store(true, Ordering::Release);
+                        return;
+                    }
+                }
+            })?;
+        Ok(Self { inner })
+    }
+
+    /// Whether the lease was lost.
+    pub fn lost(&self) -> bool {
+        self.inner.lost.load(Ordering::Acquire)
+    }
+
+    /// Stops renewing the lease.
+    pub fn stop(&self) {
+        *self.inner.stopped.lock().unwrap() = true;
+        self.inner.stop.notify_all();
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Inner {
+    /// Waits for up to `timeout`, returning whether the lease was stopped in the meantime.
+    fn wait(&self, timeout: Duration) -> bool {
+        let stopped = self.stopped.lock().unwrap();
+        *self
+            .stop
+            .wait_timeout_while(stopped, timeout, |stopped| !*stopped)
+            .unwrap()
+            .0
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use anyhow::{bail, Result};
+
+    use super::Lease;
+
+    const TTL: Duration = Duration::from_millis(300);
+
+    #[test]
+    fn renews_until_stopped_test() -> Result<()> {
+        let renewals = Arc::new(AtomicU32::new(0));
+        let counted = renewals.clone();
+        let lease = Lease::keep_alive("leader", TTL, move || {
+            counted.fetch_add(1, Ordering::Relaxed);
+            Ok(true)
+        })?;
+        thread::sleep(TTL + TTL / 6);
+        assert!(renewals.load(Ordering::Relaxed) >= 2);
+        assert!(!lease.lost());
+
+        lease.stop();
+        thread::sleep(TTL / 6);
+        let stopped_at = renewals.load(Ordering::Relaxed);
+        thread::sleep(TTL);
+        assert_eq!(renewals.load(Ordering::Relaxed), stopped_at);
+        assert!(!lease.lost());
+        Ok(())
+    }
+
+    #[test]
+    fn lost_test() -> Result<()> {
+        // the backend says it expired
+        let expired = Lease::keep_alive("leader", TTL, || Ok(false))?;
+        // the backend can't be reached
+        let unreachable = Lease::keep_alive("leader", TTL, || bail!("connection refused"))?;
+        thread::sleep(TTL / 2);
+        assert!(expired.lost());
+        assert!(!unreachable.lost());
+        thread::sleep(TTL);
+        assert!(unreachable.lost());
+        Ok(())
+    }
+
+    #[test]
+    fn hanging_renewal_test() -> Result<()> {
+        // the first renewal never returns (e.g., a keep-alive the backend doesn't answer)
+        let lease = Lease::keep_alive("leader", TTL, || {
+            thread::sleep(Duration::from_secs(60));
+            Ok(true)
+        })?;
+        thread::sleep(TTL / 2);
+        assert!(!lease.lost());
+        // it's lost once the ttl's up, even though the renewal hasn't returned
+        thread::sleep(TTL * 2 / 3);
+        assert!(lease.lost());
+        Ok(())
+    }
+}
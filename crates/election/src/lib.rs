@@ -0,0 +1,365 @@
+mod implementors;
+mod lease;
+
+/// The `SCHEME_NAME` defines the name under which a resource is
+/// identifiable by in a `ResourceMap`.
+const SCHEME_NAME: &str = "election";
+/// The operations guests can retry if they time out (see `BasicState::with_idempotent_operations`).
+const IDEMPOTENT_OPERATIONS: &[&str] = &["is-leader", "observe"];
+/// The operations that only read, which audits of writes only leave out (see
+/// `BasicState::with_read_operations`).
+const READ_OPERATIONS: &[&str] = &["is-leader", "observe"];
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use uuid::Uuid;
+
+use implementors::etcd::EtcdImplementor;
+use slight_runtime::{describe::Description, impl_resource, resource::BasicState};
+
+pub use lease::DEFAULT_LEASE_TTL;
+
+/// It is mandatory to `use <interface>::*` due to `impl_resource!`.
+/// That is because `impl_resource!` accesses the `crate`'s
+/// `add_to_linker`, and not the `<interface>::add_to_linker` directly.
+use election::*;
+wit_bindgen_wasmtime::export!("../../wit/election.wit");
+wit_error_rs::impl_error!(election::Error);
+slight_runtime::impl_from_anyhow!(election::Error);
+
+/// How often `observe` looks at who leads while waiting for the leadership to change.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often a `HostElection` checks it still leads, and how long it waits to campaign again
+/// after failing to.
+const HOST_CAMPAIGN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The `Election` structure is what will implement the `election::Election` trait
+/// coming from the generated code of off `election.wit`.
+///
+/// It maintains a `host_state`.
+pub struct Election {
+    host_state: ElectionState,
+}
+
+impl_resource!(
+    Election,
+    election::ElectionTables<Election>,
+    ElectionState,
+    SCHEME_NAME.to_string()
+);
+
+/// This is the type of the `host_state` property from our `Election` structure.
+///
+/// It holds:
+///     - an `election_implementor` `String` — this comes directly from a
+///     user's `slightfile` and it is what allows us to dynamically
+///     dispatch to a specific implementor's implentation,
+///     - the `slight_state` (of type `BasicState`) that contains common
+///     things received from the slight binary (i.e., the `resource_map`,
+///     the `config_type`, and the `config_toml_file_path`), and
+///     - the `lease_ttl` of candidates, which the host renews for as long as they lead.
+pub struct ElectionState {
+    election_implementor: String,
+    slight_state: BasicState,
+    lease_ttl: Duration,
+}
+
+impl ElectionState {
+    pub fn new(election_implementor: String, slight_state: BasicState) -> Self {
+        Self {
+            election_implementor,
+            slight_state: slight_state
+                .with_idempotent_operations(IDEMPOTENT_OPERATIONS)
+                .with_read_operations(READ_OPERATIONS),
+            lease_ttl: DEFAULT_LEASE_TTL,
+        }
+    }
+
+    /// Describes the effective configuration of the backend (see `slight_runtime::describe`),
+    /// w/o connecting to it.
+    pub fn describe(&self) -> Result<Description> {
+        let description = match self.election_implementor.as_str() {
+            "election.etcd" => EtcdImplementor::describe(&self.slight_state),
+            p => bail!(
+                "failed to match provided name (i.e., '{}') to any known host implementations",
+                p
+            ),
+        };
+        Ok(description.with_pool_size(self.slight_state.pool_size()))
+    }
+
+    /// Ties candidates to leases of `lease_ttl`, rather than `DEFAULT_LEASE_TTL` — the shorter
+    /// it is, the sooner another candidate is elected once the leader's host dies, but the
+    /// sooner a leader loses its leadership if the backend is unreachable.
+    pub fn with_lease_ttl(mut self, lease_ttl: Option<Duration>) -> Self {
+        self.lease_ttl = lease_ttl.unwrap_or(DEFAULT_LEASE_TTL);
+        self
+    }
+
+    /// Connects to the backend, and checks it's compatible w/ the implementor, so a backend
+    /// that can't serve the guest fails at startup, rather than when the guest first opens it.
+    pub fn check_compatibility(&self) -> Result<()> {
+        ElectionImplementor::connect(&self.election_implementor, &self.slight_state).map(drop)
+    }
+}
+
+/// Who leads an election, as seen by one of its candidates.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Observed {
+    Elected,
+    Lost,
+    LedBy(Vec<u8>),
+    Vacant,
+}
+
+impl From<Observed> for Leadership {
+    fn from(observed: Observed) -> Self {
+        match observed {
+            Observed::Elected => Leadership::Elected,
+            Observed::Lost => Leadership::Lost,
+            Observed::LedBy(value) => Leadership::LedBy(value),
+            Observed::Vacant => Leadership::Vacant,
+        }
+    }
+}
+
+impl election::Election for Election {
+    type Election = ElectionInner;
+
+    fn election_open(&mut self, name: &str) -> Result<Self::Election, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
+        // populate our inner election object w/ the state received from `slight`
+        // (i.e., what type of election implementor we are using), and the name
+        // of the election.
+        let inner = Self::Election::new(
+            &self.host_state.election_implementor,
+            &self.host_state.slight_state,
+            name,
+        )?;
+
+        self.host_state
+            .slight_state
+            .resource_map
+            .lock()
+            .unwrap()
+            .set(inner.resource_descriptor.clone(), Box::new(inner.clone()));
+
+        Ok(inner)
+    }
+
+    fn election_campaign(
+        &mut self,
+        self_: &Self::Election,
+        value: PayloadParam<'_>,
+    ) -> Result<(), Error> {
+        let lease_ttl = self.host_state.lease_ttl;
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "campaign", &self_.name, || {
+                match &self_.election_implementor {
+                    ElectionImplementor::Etcd(ei) => ei.campaign(&self_.name, value, lease_ttl)?,
+                };
+                Ok(())
+            })
+    }
+
+    fn election_is_leader(&mut self, self_: &Self::Election) -> Result<bool, Error> {
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "is-leader", &self_.name, || {
+                Ok(match &self_.election_implementor {
+                    ElectionImplementor::Etcd(ei) => ei.is_leader(),
+                })
+            })
+    }
+
+    fn election_resign(&mut self, self_: &Self::Election) -> Result<(), Error> {
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "resign", &self_.name, || {
+                match &self_.election_implementor {
+                    ElectionImplementor::Etcd(ei) => ei.resign()?,
+                };
+                Ok(())
+            })
+    }
+
+    fn election_observe(
+        &mut self,
+        self_: &Self::Election,
+        timeout_in_secs: u64,
+    ) -> Result<Leadership, Error> {
+        Ok(self
+            .host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "observe", &self_.name, || {
+                self_.wait_for_change(Duration::from_secs(timeout_in_secs))
+            })?
+            .into())
+    }
+}
+
+/// This is the type of the associated type coming from the `election::Election` trait
+/// implementation.
+///
+/// It holds:
+///     - an `election_implementor` (i.e., a variant `ElectionImplementor` `enum`),
+///     - the `name` of the election,
+///     - what this candidate `observed` last (if it did), and
+///     - a `resource_descriptor` (i.e., an UUID that uniquely identifies
+///     resource's instance).
+///
+/// It must `derive`:
+///     - `Debug` due to a constraint on the associated type.
+///     - `Clone` because the `ResourceMap` it will be added onto,
+///     must own its data.
+///
+/// It must be public because the implementation of `election::Election` cannot leak
+/// a private type.
+#[derive(Debug, Clone)]
+pub struct ElectionInner {
+    election_implementor: ElectionImplementor,
+    name: String,
+    observed: Arc<Mutex<Option<Observed>>>,
+    resource_descriptor: String,
+}
+
+impl ElectionInner {
+    fn new(election_implementor: &str, slight_state: &BasicState, name: &str) -> Result<Self> {
+        Ok(Self {
+            election_implementor: ElectionImplementor::connect(election_implementor, slight_state)?,
+            name: name.to_string(),
+            observed: Arc::new(Mutex::new(None)),
+            resource_descriptor: Uuid::new_v4().to_string(),
+        })
+    }
+
+    /// Waits (for up to `timeout`) for the leadership to change from what was last observed,
+    /// returning what it is (i.e., unchanged, if it didn't in time).
+    fn wait_for_change(&self, timeout: Duration) -> Result<Observed> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let observed = match &self.election_implementor {
+                ElectionImplementor::Etcd(ei) => ei.observe(&self.name)?,
+            };
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let mut last = self.observed.lock().unwrap();
+            if last.as_ref() != Some(&observed) || remaining.is_zero() {
+                *last = Some(observed.clone());
+                return Ok(observed);
+            }
+            drop(last);
+            thread::sleep(remaining.min(POLL_INTERVAL));
+        }
+    }
+}
+
+impl slight_runtime::resource::Watch for ElectionInner {}
+
+/// `HostElection` is a candidacy for the host's own use, so that only the replica of an app
+/// that leads does what only one of them should (e.g., fire leader-only timers).
+///
+/// It campaigns from a thread of its own for as long as it's open (i.e., until its last
+/// clone is dropped, when it resigns), and campaigns again whenever it loses its leadership,
+/// so, once the leader's host dies, another replica takes over w/in a lease's TTL.
+#[derive(Debug, Clone)]
+pub struct HostElection {
+    candidate: Arc<HostCandidate>,
+}
+
+#[derive(Debug)]
+struct HostCandidate {
+    election_implementor: ElectionImplementor,
+    stopped: Arc<AtomicBool>,
+}
+
+impl HostElection {
+    /// Starts campaigning w/ `value` (e.g., what identifies the host) to lead election `name`.
+    pub fn open(
+        election_implementor: &str,
+        slight_state: &BasicState,
+        name: &str,
+        value: &[u8],
+    ) -> Result<Self> {
+        let election_implementor =
+            ElectionImplementor::connect(election_implementor, slight_state)?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let (campaigner, stop) = (election_implementor.clone(), stopped.clone());
+        let (name, value) = (name.to_string(), value.to_vec());
+        thread::Builder::new()
+            .name("slight-host-election".to_string())
+            .spawn(move || {
+                while !stop.load(Ordering::Acquire) {
+                    let campaigned = match &campaigner {
+                        ElectionImplementor::Etcd(ei) if !ei.is_leader() => {
+                            // a lost leadership is resigned first, so the host can run again
+                            ei.resign()
+                                .and_then(|_| ei.campaign(&name, &value, DEFAULT_LEASE_TTL))
+                        }
+                        ElectionImplementor::Etcd(_) => Ok(()),
+                    };
+                    if let Err(e) = campaigned {
+                        tracing::warn!("failed to campaign in election '{}': {:#}", name, e);
+                    }
+                    thread::sleep(HOST_CAMPAIGN_INTERVAL);
+                }
+                let resigned = match &campaigner {
+                    ElectionImplementor::Etcd(ei) => ei.resign(),
+                };
+                if let Err(e) = resigned {
+                    tracing::warn!("failed to resign election '{}': {:#}", name, e);
+                }
+            })?;
+        Ok(Self {
+            candidate: Arc::new(HostCandidate {
+                election_implementor,
+                stopped,
+            }),
+        })
+    }
+
+    /// Whether the host leads (i.e., it was elected, and hasn't lost its leadership since).
+    pub fn is_leader(&self) -> bool {
+        match &self.candidate.election_implementor {
+            ElectionImplementor::Etcd(ei) => ei.is_leader(),
+        }
+    }
+}
+
+impl Drop for HostCandidate {
+    fn drop(&mut self) {
+        // it's the campaigning thread that resigns, as it may be campaigning right now
+        self.stopped.store(true, Ordering::Release);
+    }
+}
+
+/// This defines the available implementor implementations for the `Election` interface.
+///
+/// As per its usage in `ElectionInner`, it must `derive` `Debug`, and `Clone`.
+#[derive(Debug, Clone)]
+enum ElectionImplementor {
+    Etcd(EtcdImplementor),
+}
+
+impl ElectionImplementor {
+    /// Connects to the backend of the implementor, failing if it can't, or it's incompatible
+    /// w/ it (see `slight_runtime::compat`).
+    fn connect(election_implementor: &str, slight_state: &BasicState) -> Result<Self> {
+        match election_implementor {
+            "election.etcd" => Ok(Self::Etcd(EtcdImplementor::connect(slight_state)?)),
+            p => bail!(
+                "failed to match provided name (i.e., '{}') to any known host implementations",
+                p
+            ),
+        }
+    }
+}
@@ -1,7 +1,9 @@
 // guest resource
+pub use event_batch_handler::{EventBatchHandler, EventBatchHandlerData};
 pub use event_handler::{EventHandler, EventHandlerData, EventParam};
 
 wit_bindgen_wasmtime::import!("../../wit/event-handler.wit");
+wit_bindgen_wasmtime::import!("../../wit/event-batch-handler.wit");
 pub use cloudevents::AttributesReader;
 pub use cloudevents::AttributesWriter;
 pub use cloudevents::Event;
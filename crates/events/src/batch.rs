@@ -0,0 +1,162 @@
+use std::time::Instant;
+
+use anyhow::Result;
+use slight_events_api::{AttributesReader, Event};
+use slight_runtime::batch::BatchSettings;
+
+use crate::drivers::EventsDriver;
+
+/// How many times an event the guest failed to handle in a batch is delivered again, by
+/// default.
+pub const DEFAULT_MAX_REDELIVERIES: u32 = 3;
+
+/// `EventBatching` decides how events are delivered to a guest that handles them in batches
+/// (i.e., that exports `event-batch-handler.wit`), rather than one by one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EventBatching {
+    /// how long the first event of a batch waits for others to join it, and how many events
+    /// a batch holds at most
+    pub settings: BatchSettings,
+    /// how many times an event the guest failed to handle is delivered again, before it's
+    /// dropped
+    pub max_redeliveries: u32,
+}
+
+impl EventBatching {
+    pub fn new(settings: BatchSettings, max_redeliveries: Option<u32>) -> Self {
+        Self {
+            settings,
+            max_redeliveries: max_redeliveries.unwrap_or(DEFAULT_MAX_REDELIVERIES),
+        }
+    }
+}
+
+/// An event waiting to be delivered in a batch, w/ how many times it was delivered again, as
+/// the guest failed to handle it.
+#[derive(Debug)]
+pub(crate) struct Pending {
+    pub event: Event,
+    pub redeliveries: u32,
+}
+
+/// Collects the next batch of the events of the observable `id`: the `pending` ones first,
+/// and then the ones that arrive w/in the window (or until the batch is full) — or none, if
+/// there are no pending events, and none arrives until the `idle` deadline.
+pub(crate) fn next_batch(
+    driver: &dyn EventsDriver,
+    id: &str,
+    batching: &EventBatching,
+    idle: Instant,
+    mut pending: Vec<Pending>,
+) -> Result<Vec<Pending>> {
+    if pending.is_empty() {
+        match driver.receive(id, idle)? {
+            Some(event) => pending.push(Pending {
+                event,
+                redeliveries: 0,
+            }),
+            None => return Ok(pending),
+        }
+    }
+    let window = Instant::now() + batching.settings.window;
+    while pending.len() < batching.settings.max_size {
+        match driver.receive(id, window)? {
+            Some(event) => pending.push(Pending {
+                event,
+                redeliveries: 0,
+            }),
+            None => break,
+        }
+    }
+    Ok(pending)
+}
+
+/// Settles a delivered `batch` as per the indices of the events the guest `failed` to handle,
+/// returning the ones to deliver again — events that were delivered again `max_redeliveries`
+/// times already are dropped, and all of the others are done w/.
+pub(crate) fn settle(batch: Vec<Pending>, failed: &[u32], max_redeliveries: u32) -> Vec<Pending> {
+    batch
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| failed.contains(&(*i as u32)))
+        .filter_map(|(_, pending)| {
+            if pending.redeliveries >= max_redeliveries {
+                tracing::error!(
+                    "dropping event '{}', as the guest failed to handle it {} times",
+                    pending.event.id(),
+                    pending.redeliveries + 1
+                );
+                return None;
+            }
+            Some(Pending {
+                event: pending.event,
+                redeliveries: pending.redeliveries + 1,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::{Duration, Instant};
+
+    use anyhow::Result;
+    use slight_events_api::{AttributesReader, AttributesWriter, Event};
+    use slight_runtime::batch::BatchSettings;
+
+    use super::{next_batch, settle, EventBatching, Pending};
+    use crate::drivers::{inmemory::InMemoryDriver, EventsDriver};
+
+    fn ids(batch: &[Pending]) -> Vec<(&str, u32)> {
+        batch
+            .iter()
+            .map(|pending| (pending.event.id(), pending.redeliveries))
+            .collect()
+    }
+
+    #[test]
+    fn next_batch_test() -> Result<()> {
+        let driver = InMemoryDriver::default();
+        let sender = driver.register("ob1")?;
+        for id in ["1", "2", "3"] {
+            let mut event = Event::default();
+            event.set_id(id);
+            sender.lock().unwrap().send(event)?;
+        }
+        let batching = EventBatching::new(BatchSettings::new(10, Some(2)), None);
+        let idle = Instant::now() + Duration::from_millis(10);
+
+        // batches are full at `max_size`, and the rest wait for the next one
+        let batch = next_batch(&driver, "ob1", &batching, idle, Vec::new())?;
+        assert_eq!(ids(&batch), vec![("1", 0), ("2", 0)]);
+
+        // the events to deliver again go first
+        let batch = settle(batch, &[1], batching.max_redeliveries);
+        let batch = next_batch(&driver, "ob1", &batching, idle, batch)?;
+        assert_eq!(ids(&batch), vec![("2", 1), ("3", 0)]);
+
+        let batch = next_batch(&driver, "ob1", &batching, idle, Vec::new())?;
+        assert!(batch.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn settle_test() {
+        let batch = ["1", "2", "3"]
+            .into_iter()
+            .zip([0, 2, 1])
+            .map(|(id, redeliveries)| {
+                let mut event = Event::default();
+                event.set_id(id);
+                Pending {
+                    event,
+                    redeliveries,
+                }
+            })
+            .collect();
+        // the events that didn't fail are done w/, and the one that failed a third time is
+        // dropped
+        let redelivered = settle(batch, &[0, 1, 7], 2);
+        assert_eq!(ids(&redelivered), vec![("1", 1)]);
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use slight_events_api::Event;
+
+use super::EventsDriver;
+
+/// This is the default `EventsDriver`.
+///
+/// It delivers events through an in-process channel per observable, so
+/// events never leave the host — which is dropped once its last subscriber leaves.
+#[derive(Debug, Default)]
+pub struct InMemoryDriver {
+    channels: Mutex<HashMap<String, Channel>>,
+}
+
+#[derive(Debug)]
+struct Channel {
+    sender: Arc<Mutex<Sender<Event>>>,
+    receiver: Receiver<Event>,
+    /// how many times the observable is registered, but not unregistered yet
+    subscribers: usize,
+}
+
+impl EventsDriver for InMemoryDriver {
+    fn register(&self, id: &str) -> Result<Arc<Mutex<Sender<Event>>>> {
+        let mut channels = self.channels.lock().unwrap();
+        let channel = channels.entry(id.to_string()).or_insert_with(|| {
+            let (sender, receiver) = unbounded();
+            Channel {
+                sender: Arc::new(Mutex::new(sender)),
+                receiver,
+                subscribers: 0,
+            }
+        });
+        channel.subscribers += 1;
+        Ok(channel.sender.clone())
+    }
+
+    fn unregister(&self, id: &str) -> Result<()> {
+        let mut channels = self.channels.lock().unwrap();
+        let channel = channels
+            .get_mut(id)
+            .with_context(|| format!("observable '{}' was never registered", id))?;
+        channel.subscribers -= 1;
+        // the events still in it have no one left to receive them
+        if channel.subscribers == 0 {
+            channels.remove(id);
+        }
+        Ok(())
+    }
+
+    fn receive(&self, id: &str, deadline: Instant) -> Result<Option<Event>> {
+        let receiver = self
+            .channels
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|c| c.receiver.clone())
+            .with_context(|| format!("observable '{}' was never registered", id))?;
+        match receiver.recv_deadline(deadline) {
+            Ok(event) => Ok(Some(event)),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::{Duration, Instant};
+
+    use anyhow::Result;
+    use slight_events_api::{AttributesReader, AttributesWriter, Event};
+
+    use super::InMemoryDriver;
+    use crate::drivers::EventsDriver;
+
+    #[test]
+    fn delivers_events_in_order() -> Result<()> {
+        let driver = InMemoryDriver::default();
+        let sender = driver.register("ob1")?;
+        for id in ["1", "2"] {
+            let mut event = Event::default();
+            event.set_id(id);
+            sender.lock().unwrap().send(event)?;
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        assert_eq!(driver.receive("ob1", deadline)?.unwrap().id(), "1");
+        assert_eq!(driver.receive("ob1", deadline)?.unwrap().id(), "2");
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_observables_apart() -> Result<()> {
+        let driver = InMemoryDriver::default();
+        let sender = driver.register("ob1")?;
+        driver.register("ob2")?;
+        sender.lock().unwrap().send(Event::default())?;
+
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert!(driver.receive("ob2", deadline)?.is_none());
+        assert!(driver.receive("ob1", deadline)?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn times_out_without_events() -> Result<()> {
+        let driver = InMemoryDriver::default();
+        driver.register("ob1")?;
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert!(driver.receive("ob1", deadline)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_unregistered_observables() {
+        let driver = InMemoryDriver::default();
+        assert!(driver.receive("nope", Instant::now()).is_err());
+        assert!(driver.unregister("nope").is_err());
+    }
+
+    #[test]
+    fn drops_channels_without_subscribers() -> Result<()> {
+        let driver = InMemoryDriver::default();
+        driver.register("ob1")?;
+        driver.register("ob1")?;
+
+        // the channel is kept until the last subscriber leaves
+        driver.unregister("ob1")?;
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert!(driver.receive("ob1", deadline)?.is_none());
+        driver.unregister("ob1")?;
+        assert!(driver.receive("ob1", deadline).is_err());
+        assert!(driver.channels.lock().unwrap().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+pub mod inmemory;
+
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use anyhow::{bail, Result};
+use crossbeam_channel::Sender;
+use slight_events_api::Event;
+
+use inmemory::InMemoryDriver;
+
+/// The names under which the drivers that ship w/ slight are selectable in a `slightfile`.
+pub const EVENTS_DRIVERS: [&str; 2] = ["events", "events.inmemory"];
+
+/// An `EventsDriver` is the transport events are delivered through: from the
+/// resource being watched (i.e., the source), to the guest's event handler (i.e., the sink).
+///
+/// Each observable a guest listens to is registered w/ the driver under an unique
+/// `id`. Then:
+///     - `register` returns the `Sender` the watched resource publishes the events
+///     of that observable to (see `slight_runtime::resource::Watch`), and
+///     - `receive` is called repeatedly (possibly, from a different thread per
+///     observable) to get the next event to pass on to the guest, and
+///     - `unregister` is called once the guest stops listening (i.e., once for each
+///     `register`), so the driver can let go of what it holds for the observable.
+///
+/// Drivers that deliver events over a different transport (e.g., a queue, a topic,
+/// or a webhook) can be implemented out-of-tree and handed to `EventsState::with_driver`.
+/// Such drivers are expected to forward whatever is sent to the `Sender` they hand out
+/// onto their transport, and to read from that transport in `receive`.
+pub trait EventsDriver: Debug + Send + Sync {
+    /// Registers an observable, returning the `Sender` its events are to be published to.
+    fn register(&self, id: &str) -> Result<Arc<Mutex<Sender<Event>>>>;
+
+    /// Waits for the next event of an observable until the `deadline`.
+    ///
+    /// Returns `None` if no event arrived in time.
+    fn receive(&self, id: &str, deadline: Instant) -> Result<Option<Event>>;
+
+    /// Unregisters an observable registered w/ `register` — it does nothing by default.
+    fn unregister(&self, _id: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Constructs one of the drivers that ship w/ slight from its name in a `slightfile`.
+pub fn new(events_driver: &str) -> Result<Arc<dyn EventsDriver>> {
+    match events_driver {
+        "events" | "events.inmemory" => Ok(Arc::new(InMemoryDriver::default())),
+        d => bail!(
+            "failed to match provided name (i.e., '{}') to any known events driver",
+            d
+        ),
+    }
+}
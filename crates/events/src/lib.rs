@@ -1,3 +1,6 @@
+pub mod batch;
+pub mod drivers;
+
 use std::{
     ops::DerefMut,
     sync::{Arc, Mutex},
@@ -9,11 +12,20 @@ use crossbeam_utils::thread;
 
 use crate::events::Error;
 use crate::events::Observable as GeneratedObservable;
-use crossbeam_channel::{unbounded, Receiver, Sender};
-use slight_events_api::{AttributesReader, Event, EventHandler, EventParam};
+use crate::events::TimeoutError;
+use batch::{EventBatching, Pending};
+use drivers::EventsDriver;
+use slight_events_api::{
+    event_batch_handler::EventParam as BatchEventParam, AttributesReader, Event, EventBatchHandler,
+    EventHandler, EventParam,
+};
 
 use slight_runtime::{
+    call::guest_phase,
+    cause,
+    health::{Health, HEALTH},
     impl_resource,
+    invocations::Invocations,
     resource::{Ctx, ResourceMap},
 };
 use uuid::Uuid;
@@ -22,7 +34,7 @@ use wasmtime::Store;
 use crate::events::add_to_linker;
 wit_bindgen_wasmtime::export!("../../wit/events.wit");
 wit_error_rs::impl_error!(Error);
-wit_error_rs::impl_from!(anyhow::Error, Error::ErrorWithDescription);
+slight_runtime::impl_from_anyhow!(Error);
 
 const SCHEME_NAME: &str = "events";
 
@@ -32,20 +44,66 @@ pub struct Events {
     host_state: EventsState,
 }
 
-#[derive(Default)]
 pub struct EventsState {
     resource_map: ResourceMap,
+    driver: Arc<dyn EventsDriver>,
     event_handler: Option<Arc<Mutex<EventHandler<Ctx>>>>,
+    batch_handler: Option<Arc<Mutex<EventBatchHandler<Ctx>>>>,
     store: Option<Arc<Mutex<Store<Ctx>>>>,
+    invocations: Invocations,
+    batching: Option<EventBatching>,
+}
+
+impl Default for EventsState {
+    fn default() -> Self {
+        Self::with_driver(
+            ResourceMap::default(),
+            Arc::new(drivers::inmemory::InMemoryDriver::default()),
+        )
+    }
 }
 
 impl EventsState {
-    pub fn new(resource_map: ResourceMap) -> Self {
+    /// Creates the state of the events capability w/ one of the drivers
+    /// that ship w/ slight (see `drivers::EVENTS_DRIVERS`).
+    pub fn new(events_driver: &str, resource_map: ResourceMap) -> Result<Self> {
+        Ok(Self::with_driver(
+            resource_map,
+            drivers::new(events_driver)?,
+        ))
+    }
+
+    /// Creates the state of the events capability w/ any `EventsDriver`.
+    pub fn with_driver(resource_map: ResourceMap, driver: Arc<dyn EventsDriver>) -> Self {
         Self {
             resource_map,
-            ..Default::default()
+            driver,
+            event_handler: None,
+            batch_handler: None,
+            store: None,
+            invocations: Invocations::default(),
+            batching: None,
         }
     }
+
+    /// Holds the invocations of the event handler to the app's `invocations` — events that
+    /// arrive while as many invocations as it allows are in flight wait for one to finish.
+    pub fn with_invocations(mut self, invocations: Invocations) -> Self {
+        self.invocations = invocations;
+        self
+    }
+
+    /// Delivers events to the guest in batches, as per `batching` (see `batch::EventBatching`),
+    /// rather than one by one — the guest must then export `event-batch-handler.wit`.
+    pub fn with_batching(mut self, batching: Option<EventBatching>) -> Self {
+        self.batching = batching;
+        self
+    }
+
+    /// Whether events are delivered to the guest in batches (see `with_batching`).
+    pub fn is_batching(&self) -> bool {
+        self.batching.is_some()
+    }
 }
 
 impl_resource!(
@@ -61,37 +119,194 @@ pub struct EventsGuest {
 }
 
 /// An owned observable
+///
+/// Its' `id` is what identifies it w/ the `EventsDriver`.
 #[derive(Clone, Debug)]
 struct Observable {
+    id: String,
     rd: String,
     key: String,
-    sender: Arc<Mutex<Sender<Event>>>,
-    receiver: Arc<Mutex<Receiver<Event>>>,
 }
 
 impl From<GeneratedObservable<'_>> for Observable {
     fn from(observable: GeneratedObservable) -> Self {
-        let (sender, receiver) = unbounded();
         Self {
+            id: Uuid::new_v4().to_string(),
             rd: observable.rd.to_string(),
             key: observable.key.to_string(),
-            sender: Arc::new(Mutex::new(sender)),
-            receiver: Arc::new(Mutex::new(receiver)),
         }
     }
 }
 
 impl Events {
-    /// Host will call this function to update store and event_handler
+    /// Host will call this function to update store and event_handler — which guests that
+    /// handle events in batches (see `update_batch_handler`) needn't export.
     pub fn update_state(
         &mut self,
         store: Arc<Mutex<Store<Ctx>>>,
-        event_handler: Arc<Mutex<EventHandler<Ctx>>>,
+        event_handler: Option<Arc<Mutex<EventHandler<Ctx>>>>,
     ) -> Result<()> {
-        self.host_state.event_handler = Some(event_handler);
+        self.host_state.event_handler = event_handler;
         self.host_state.store = Some(store);
         Ok(())
     }
+
+    /// Host will call this function to hand over the guest's batch handler, if events are
+    /// delivered in batches (see `EventsState::with_batching`).
+    pub fn update_batch_handler(&mut self, batch_handler: Arc<Mutex<EventBatchHandler<Ctx>>>) {
+        self.host_state.batch_handler = Some(batch_handler);
+    }
+
+    pub fn is_batching(&self) -> bool {
+        self.host_state.is_batching()
+    }
+}
+
+/// The fields of an event the guest gets that aren't kept as such by the `Event`, which the
+/// params it's handed to the guest as borrow.
+struct Delivery {
+    specversion: String,
+    data: Option<String>,
+    time: Option<String>,
+}
+
+impl Delivery {
+    fn new(event: &Event) -> Self {
+        Self {
+            specversion: event.specversion().as_str().to_string(),
+            data: event.data().cloned().map(|d| {
+                d.try_into().unwrap_or_else(|e| {
+                    tracing::error!("Failed to convert event data to string: {}", e);
+                    "{}".to_string()
+                })
+            }),
+            time: event.time().map(|d| d.to_rfc2822()),
+        }
+    }
+}
+
+/// Borrows an event, and its `Delivery` as the params of either handler (i.e., either
+/// `EventParam` type the bindings generate).
+macro_rules! event_param {
+    ($param:ident, $event:expr, $delivery:expr) => {
+        $param {
+            specversion: &$delivery.specversion,
+            ty: $event.ty(),
+            source: $event.source(),
+            id: $event.id(),
+            data: $delivery.data.as_deref().map(|d| d.as_bytes()),
+            datacontenttype: $event.datacontenttype(),
+            dataschema: None,
+            subject: $event.subject(),
+            time: $delivery.time.as_deref(),
+        }
+    };
+}
+
+impl EventsState {
+    /// Hands the events of an observable to the guest one by one, until none arrives for
+    /// `idle`.
+    fn deliver(&self, ob: &Observable, idle: Duration) -> Result<(), Error> {
+        let handler = self.event_handler.as_ref().with_context(|| {
+            "internal error: events are delivered one by one, but there's no event handler"
+        })?;
+        let store = self.store.as_ref().unwrap();
+        loop {
+            let event = match self.driver.receive(&ob.id, Instant::now() + idle)? {
+                Some(event) => event,
+                None => return Ok(()),
+            };
+            let _invocation = self.invocations.acquire("event");
+            let mut store = store.lock().unwrap();
+            let delivery = Delivery::new(&event);
+            // traced under what caused the event (e.g., a capability call of the guest
+            // handling an http request), if it's known
+            let _cause = cause::enter(&event);
+            let _phase = guest_phase("event");
+            let event_res = handler
+                .lock()
+                .unwrap()
+                .handle_event(store.deref_mut(), event_param!(EventParam, event, delivery));
+            if let Err(e) = event_res {
+                return Err(events::Error::ErrorWithDescription(format!(
+                    "event handler error {}",
+                    e
+                )));
+            }
+        }
+    }
+
+    /// Hands the events of an observable to the guest in batches, until none arrives for
+    /// `idle` (and none is left to deliver again).
+    fn deliver_batches(
+        &self,
+        ob: &Observable,
+        idle: Duration,
+        batching: &EventBatching,
+    ) -> Result<(), Error> {
+        let handler = self.batch_handler.as_ref().with_context(|| {
+            "internal error: events are delivered in batches, but there's no batch handler"
+        })?;
+        let store = self.store.as_ref().unwrap();
+        let mut redelivered = Vec::new();
+        loop {
+            let batch = batch::next_batch(
+                self.driver.as_ref(),
+                &ob.id,
+                batching,
+                Instant::now() + idle,
+                redelivered,
+            )?;
+            if batch.is_empty() {
+                return Ok(());
+            }
+            let _invocation = self.invocations.acquire("event");
+            let mut store = store.lock().unwrap();
+            let deliveries = batch
+                .iter()
+                .map(|pending| Delivery::new(&pending.event))
+                .collect::<Vec<_>>();
+            let params = batch
+                .iter()
+                .zip(&deliveries)
+                .map(|(Pending { event, .. }, delivery)| {
+                    event_param!(BatchEventParam, event, delivery)
+                })
+                .collect::<Vec<_>>();
+            // a batch can only be traced under one cause, so it's the first event's
+            let _cause = cause::enter(&batch[0].event);
+            for pending in &batch[1..] {
+                cause::forget(&pending.event);
+            }
+            let _phase = guest_phase("events");
+            let failed = match handler
+                .lock()
+                .unwrap()
+                .handle_events(store.deref_mut(), &params)
+            {
+                Ok(Ok(failed)) => failed,
+                // the batch failed as a whole, so all of its events did
+                Ok(Err(e)) => {
+                    tracing::warn!("the guest failed to handle a batch of events: {}", e);
+                    (0..batch.len() as u32).collect()
+                }
+                Err(e) => {
+                    return Err(events::Error::ErrorWithDescription(format!(
+                        "event handler error {}",
+                        e
+                    )));
+                }
+            };
+            if !failed.is_empty() {
+                tracing::warn!(
+                    "the guest failed to handle {} of a batch of {} events",
+                    failed.len(),
+                    batch.len()
+                );
+            }
+            redelivered = batch::settle(batch, &failed, batching.max_redeliveries);
+        }
+    }
 }
 
 impl events::Events for Events {
@@ -105,8 +320,14 @@ impl events::Events for Events {
         self_: &Self::Events,
         ob: GeneratedObservable<'_>,
     ) -> Result<Self::Events, Error> {
-        Uuid::parse_str(ob.rd)
-            .with_context(|| "internal error: failed to parse internal handle to this resource")?;
+        if ob.rd == HEALTH {
+            // health events are emitted by the runtime itself, rather than by a resource
+            Health::shared(&mut self.host_state.resource_map.lock().unwrap());
+        } else {
+            Uuid::parse_str(ob.rd).with_context(|| {
+                "internal error: failed to parse internal handle to this resource"
+            })?;
+        }
         let ob = ob.into();
         // FIXME: the reason I had to clone the observable is because the observable is owned by
         // self_ which is not a mutable reference.
@@ -116,6 +337,10 @@ impl events::Events for Events {
     }
 
     fn events_exec(&mut self, self_: &Self::Events, duration: u64) -> Result<(), Error> {
+        let mut registrations = Registrations {
+            driver: self.host_state.driver.as_ref(),
+            ids: Vec::with_capacity(self_.observables.len()),
+        };
         for ob in &self_.observables {
             // check if observable has changed
 
@@ -123,60 +348,18 @@ impl events::Events for Events {
 
             let mut map = map.lock().unwrap();
             let resource = map.get_mut(&ob.rd).unwrap();
-            resource.watch(&ob.key, ob.sender.clone())?;
+            let sender = self.host_state.driver.register(&ob.id)?;
+            registrations.ids.push(&ob.id);
+            resource.watch(&ob.key, sender)?;
         }
         thread::scope(|s| -> Result<()> {
             let mut thread_handles = vec![];
             for ob in &self_.observables {
-                let handler = self.host_state.event_handler.as_ref().unwrap().clone();
-                let store = self.host_state.store.as_mut().unwrap().clone();
-                let receiver = ob.receiver.clone();
-                let receive_thread = s.spawn(move |_| loop {
-                    let recv = receiver
-                        .lock()
-                        .unwrap()
-                        .recv_deadline(Instant::now() + Duration::from_secs(duration));
-                    match recv {
-                        Ok(mut event) => {
-                            let mut store = store.lock().unwrap();
-                            let spec = event.specversion();
-                            let data: Option<String> = event.take_data().2.map(|d| {
-                                d.try_into().unwrap_or_else(|e| {
-                                    tracing::error!(
-                                        "Failed to convert event data to string: {}",
-                                        e
-                                    );
-                                    "{}".to_string()
-                                })
-                            });
-                            let time = event.time().take().map(|d| d.to_rfc2822());
-                            let event_param = EventParam {
-                                specversion: spec.as_str(),
-                                ty: event.ty(),
-                                source: event.source(),
-                                id: event.id(),
-                                data: data.as_deref().map(|d| d.as_bytes()),
-                                datacontenttype: event.datacontenttype(),
-                                dataschema: None,
-                                subject: event.subject(),
-                                time: time.as_deref(),
-                            };
-                            let event_res = handler
-                                .lock()
-                                .unwrap()
-                                .handle_event(store.deref_mut(), event_param);
-                            match event_res {
-                                Ok(_) => (),
-                                Err(e) => {
-                                    return Err(events::Error::ErrorWithDescription(format!(
-                                        "event handler error {}",
-                                        e
-                                    )));
-                                }
-                            }
-                        }
-                        Err(_) => return Ok(()),
-                    }
+                let host_state = &self.host_state;
+                let idle = Duration::from_secs(duration);
+                let receive_thread = s.spawn(move |_| match &host_state.batching {
+                    Some(batching) => host_state.deliver_batches(ob, idle, batching),
+                    None => host_state.deliver(ob, idle),
                 });
                 thread_handles.push(receive_thread);
             }
@@ -196,3 +379,20 @@ impl events::Events for Events {
         Ok(())
     }
 }
+
+/// The observables an `exec` registered w/ the driver, which are unregistered once it returns
+/// (see `EventsDriver::unregister`).
+struct Registrations<'a> {
+    driver: &'a dyn EventsDriver,
+    ids: Vec<&'a str>,
+}
+
+impl Drop for Registrations<'_> {
+    fn drop(&mut self) {
+        for id in &self.ids {
+            if let Err(e) = self.driver.unregister(id) {
+                tracing::warn!("failed to unregister observable '{}': {}", id, e);
+            }
+        }
+    }
+}
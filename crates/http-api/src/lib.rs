@@ -1,5 +1,7 @@
 use anyhow::{bail, Result};
-pub use http_handler::{Error, HttpHandler, HttpHandlerData, Method, Request, Response};
+pub use http_handler::{
+    ClientCert, Enrichment, Error, HttpHandler, HttpHandlerData, Method, Request, Response,
+};
 use hyper::{
     body::HttpBody as HyperHttpBody,
     header::{HeaderName, HeaderValue},
@@ -115,7 +117,7 @@ mod unittests {
 
     use crate::{HttpBody, HttpHeader};
 
-    use super::{Body, HeaderValue, Method, Request, Response, StatusCode};
+    use super::{Body, Enrichment, HeaderValue, Method, Request, Response, StatusCode};
     use anyhow::Result;
 
     #[tokio::test]
@@ -138,6 +140,12 @@ mod unittests {
             headers: &headers.0,
             params: &params,
             body: Some(&bytes.0),
+            enrichment: Enrichment {
+                cookies: &[],
+                client_ip: None,
+                country: None,
+                client_cert: None,
+            },
         };
 
         assert_eq!(req.method, Method::Get);
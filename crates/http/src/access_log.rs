@@ -0,0 +1,332 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Result};
+use hyper::{header, Body, HeaderMap, Request, Response};
+use routerify::{ext::RequestExt, RequestInfo};
+
+/// The request headers redacted from access logs, unless others are configured.
+pub const DEFAULT_REDACTED_HEADERS: [&str; 2] = ["authorization", "cookie"];
+
+/// What redacted header values are replaced w/.
+const REDACTED: &str = "[redacted]";
+
+/// The format of access logs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// the Common Log Format (i.e., `host - - [time] "request" status size`)
+    Common,
+    /// the Common Log Format, followed by the `Referer`, and `User-Agent` headers
+    Combined,
+    /// a JSON object per request, w/ its headers, and latency
+    Json,
+}
+
+impl AccessLogFormat {
+    pub fn parse(format: &str) -> Result<Self> {
+        match format {
+            "common" => Ok(Self::Common),
+            "combined" => Ok(Self::Combined),
+            "json" => Ok(Self::Json),
+            f => bail!(
+                "invalid access log format: '{}' (expected 'common', 'combined', or 'json')",
+                f
+            ),
+        }
+    }
+}
+
+/// The settings of the host-side access log of the http capability.
+#[derive(Clone, Debug)]
+pub struct AccessLogSettings {
+    pub format: AccessLogFormat,
+    /// the names of the headers whose values are redacted
+    pub redacted_headers: Vec<String>,
+}
+
+/// When a request was received, and from where — set as the request's context, so the
+/// access log entry can be written once the response is ready.
+#[derive(Clone, Copy, Debug)]
+pub struct Received {
+    at: SystemTime,
+    started: Instant,
+    remote_addr: SocketAddr,
+}
+
+/// Notes when a request was received (i.e., the pre middleware of the access log).
+pub async fn received(request: Request<Body>) -> Result<Request<Body>> {
+    request.set_context(Received {
+        at: SystemTime::now(),
+        started: Instant::now(),
+        remote_addr: request.remote_addr(),
+    });
+    Ok(request)
+}
+
+/// Writes the access log entry of a request (i.e., the post middleware of the access log).
+pub fn log(settings: &AccessLogSettings, response: &Response<Body>, info: &RequestInfo) {
+    let received = match info.context::<Received>() {
+        Some(received) => received,
+        None => return,
+    };
+    let uri = info.uri().to_string();
+    let version = format!("{:?}", info.version());
+    let entry = Entry {
+        remote_addr: received.remote_addr,
+        at: received.at,
+        latency: received.started.elapsed(),
+        method: info.method().as_str(),
+        uri: &uri,
+        version: &version,
+        status: response.status().as_u16(),
+        size: response_size(response),
+        headers: info.headers(),
+    };
+    tracing::info!(
+        target: "access_log",
+        latency_ms = entry.latency.as_millis() as u64,
+        "{}",
+        entry.format(settings)
+    );
+}
+
+fn response_size(response: &Response<Body>) -> Option<u64> {
+    response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse().ok())
+        .or_else(|| hyper::body::HttpBody::size_hint(response.body()).exact())
+}
+
+/// An access log entry.
+struct Entry<'a> {
+    remote_addr: SocketAddr,
+    at: SystemTime,
+    latency: Duration,
+    method: &'a str,
+    uri: &'a str,
+    version: &'a str,
+    status: u16,
+    size: Option<u64>,
+    headers: &'a HeaderMap,
+}
+
+impl Entry<'_> {
+    fn format(&self, settings: &AccessLogSettings) -> String {
+        let secs = self
+            .at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        match settings.format {
+            AccessLogFormat::Common => self.common(secs),
+            AccessLogFormat::Combined => format!(
+                "{} {} {}",
+                self.common(secs),
+                quoted(self.header(settings, "referer")),
+                quoted(self.header(settings, "user-agent"))
+            ),
+            AccessLogFormat::Json => {
+                let headers = self
+                    .headers
+                    .keys()
+                    .map(|name| {
+                        (
+                            name.to_string(),
+                            self.header(settings, name.as_str()).into(),
+                        )
+                    })
+                    .collect::<serde_json::Map<_, _>>();
+                serde_json::json!({
+                    "time": rfc3339(secs),
+                    "remote_addr": self.remote_addr.ip().to_string(),
+                    "method": self.method,
+                    "uri": self.uri,
+                    "version": self.version,
+                    "status": self.status,
+                    "size": self.size,
+                    "latency_ms": self.latency.as_micros() as f64 / 1000.0,
+                    "headers": headers,
+                })
+                .to_string()
+            }
+        }
+    }
+
+    /// The Common Log Format (i.e., `host ident user [time] "request" status size`).
+    fn common(&self, secs: u64) -> String {
+        format!(
+            "{} - - [{}] \"{} {} {}\" {} {}",
+            self.remote_addr.ip(),
+            clf_time(secs),
+            self.method,
+            self.uri,
+            self.version,
+            self.status,
+            self.size
+                .filter(|size| *size > 0)
+                .map_or_else(|| "-".to_string(), |size| size.to_string())
+        )
+    }
+
+    /// The value of a request header (or `None` if it wasn't sent), redacted if configured so.
+    fn header(&self, settings: &AccessLogSettings, name: &str) -> Option<String> {
+        let value = self.headers.get(name)?;
+        if settings
+            .redacted_headers
+            .iter()
+            .any(|redacted| redacted.eq_ignore_ascii_case(name))
+        {
+            return Some(REDACTED.to_string());
+        }
+        Some(String::from_utf8_lossy(value.as_bytes()).into_owned())
+    }
+}
+
+fn quoted(value: Option<String>) -> String {
+    match value {
+        Some(value) => format!("\"{}\"", value.replace('"', "\\\"")),
+        None => "\"-\"".to_string(),
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Formats seconds since the unix epoch like the Common Log Format does (in UTC, e.g.,
+/// `10/Oct/2000:13:55:36 +0000`).
+fn clf_time(secs: u64) -> String {
+    let (year, month, day) = civil_from_days(secs / 86400);
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Formats seconds since the unix epoch as an RFC 3339 timestamp (in UTC).
+fn rfc3339(secs: u64) -> String {
+    let (year, month, day) = civil_from_days(secs / 86400);
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Converts days since the unix epoch into a (year, month, day) date, as per
+/// http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use anyhow::Result;
+    use hyper::HeaderMap;
+
+    use super::{
+        clf_time, rfc3339, AccessLogFormat, AccessLogSettings, Entry, DEFAULT_REDACTED_HEADERS,
+    };
+
+    /// 10/Oct/2000:13:55:36 +0000
+    const SECS: u64 = 971_186_136;
+
+    fn settings(format: AccessLogFormat) -> AccessLogSettings {
+        AccessLogSettings {
+            format,
+            redacted_headers: DEFAULT_REDACTED_HEADERS.map(str::to_string).to_vec(),
+        }
+    }
+
+    fn headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("user-agent", "curl/7.79.1".parse().unwrap());
+        headers.insert("authorization", "Bearer secret".parse().unwrap());
+        headers
+    }
+
+    fn entry(headers: &HeaderMap) -> Entry<'_> {
+        Entry {
+            remote_addr: "127.0.0.1:51234".parse().unwrap(),
+            at: UNIX_EPOCH + Duration::from_secs(SECS),
+            latency: Duration::from_millis(12),
+            method: "GET",
+            uri: "/users/42?verbose=true",
+            version: "HTTP/1.1",
+            status: 200,
+            size: Some(2326),
+            headers,
+        }
+    }
+
+    #[test]
+    fn time_test() {
+        assert_eq!(clf_time(SECS), "10/Oct/2000:13:55:36 +0000");
+        assert_eq!(rfc3339(SECS), "2000-10-10T13:55:36Z");
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        // a leap day
+        assert_eq!(rfc3339(951_782_400), "2000-02-29T00:00:00Z");
+    }
+
+    #[test]
+    fn common_test() {
+        let headers = headers();
+        assert_eq!(
+            entry(&headers).format(&settings(AccessLogFormat::Common)),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /users/42?verbose=true HTTP/1.1\" 200 2326"
+        );
+    }
+
+    #[test]
+    fn combined_test() {
+        let headers = headers();
+        assert_eq!(
+            entry(&headers).format(&settings(AccessLogFormat::Combined)),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /users/42?verbose=true HTTP/1.1\" 200 2326 \"-\" \"curl/7.79.1\""
+        );
+    }
+
+    #[test]
+    fn json_redacts_headers_test() -> Result<()> {
+        let headers = headers();
+        let line = entry(&headers).format(&settings(AccessLogFormat::Json));
+        let json = serde_json::from_str::<serde_json::Value>(&line)?;
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["latency_ms"], 12.0);
+        assert_eq!(json["headers"]["user-agent"], "curl/7.79.1");
+        assert_eq!(json["headers"]["authorization"], "[redacted]");
+        assert!(!line.contains("secret"));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_format_test() -> Result<()> {
+        assert_eq!(AccessLogFormat::parse("json")?, AccessLogFormat::Json);
+        assert!(AccessLogFormat::parse("apache").is_err());
+        Ok(())
+    }
+}
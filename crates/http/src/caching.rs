@@ -0,0 +1,248 @@
+use anyhow::{anyhow, bail, Result};
+use hyper::{
+    body::HttpBody,
+    header::{self, HeaderValue},
+    http::response::Parts,
+    Body, Method, Request, Response, StatusCode,
+};
+use slight_runtime::split::fnv1a;
+
+/// The headers a 304 keeps from the response it stands for, as the client's cached one is
+/// updated w/ them.
+const NOT_MODIFIED_HEADERS: &[header::HeaderName] = &[
+    header::CACHE_CONTROL,
+    header::CONTENT_LOCATION,
+    header::DATE,
+    header::ETAG,
+    header::EXPIRES,
+    header::VARY,
+];
+
+/// A `CachePolicy` is how the responses of a route are cached by clients (and shared caches),
+/// parsed from `Cache-Control`-like directives, e.g. `max-age=60, etag`:
+///     - `max-age=<secs>`, `s-maxage=<secs>`, `no-store`, `no-cache`, `private`, and `public` make
+///     up the `Cache-Control` of its responses, and
+///     - `etag` tags them w/ a hash of their body (unless the guest tagged them), so the
+///     requests whose `If-None-Match` has it get a 304, w/o the body.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CachePolicy {
+    cache_control: Option<String>,
+    etag: bool,
+}
+
+impl CachePolicy {
+    pub fn parse(policy: &str) -> Result<Self> {
+        let mut directives = Vec::new();
+        let mut etag = false;
+        for directive in policy.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim().to_ascii_lowercase(), Some(value.trim())),
+                None => (directive.to_ascii_lowercase(), None),
+            };
+            match (name.as_str(), value) {
+                ("etag", None) => etag = true,
+                ("max-age" | "s-maxage", Some(secs)) => {
+                    let secs = secs.parse::<u64>().map_err(|_| {
+                        anyhow!(
+                            "invalid cache policy directive '{}': expected secs",
+                            directive
+                        )
+                    })?;
+                    directives.push(format!("{}={}", name, secs));
+                }
+                ("no-store" | "no-cache" | "private" | "public", None) => directives.push(name),
+                _ => bail!(
+                    "unknown cache policy directive '{}' (expected `max-age`, `s-maxage`, `no-store`, `no-cache`, `private`, `public`, or `etag`)",
+                    directive
+                ),
+            }
+        }
+        if etag && directives.iter().any(|d| d == "no-store") {
+            bail!(
+                "invalid cache policy '{}': responses that aren't stored aren't revalidated, so they have no use for an etag",
+                policy
+            );
+        }
+        Ok(Self {
+            cache_control: Some(directives.join(", ")).filter(|cc| !cc.is_empty()),
+            etag,
+        })
+    }
+}
+
+/// What the response to a request is cached, and revalidated by: its route's `policy`, and
+/// the `If-None-Match` of the request — only the responses to `GET`s, and `HEAD`s are cached.
+pub struct Conditional {
+    policy: CachePolicy,
+    if_none_match: Option<String>,
+    cacheable: bool,
+}
+
+impl Conditional {
+    pub fn of(request: &Request<Body>, policy: CachePolicy) -> Self {
+        Self {
+            policy,
+            if_none_match: request
+                .headers()
+                .get(header::IF_NONE_MATCH)
+                .and_then(|tags| tags.to_str().ok())
+                .map(str::to_string),
+            cacheable: matches!(*request.method(), Method::GET | Method::HEAD),
+        }
+    }
+
+    /// Applies the policy to a successful response: it sets its `Cache-Control` (unless the
+    /// guest did), tags it w/ an `ETag` if the policy says so, and turns it into a 304 if the
+    /// `If-None-Match` of the request has its `ETag`.
+    ///
+    /// Only buffered responses (i.e., those whose size is known) are tagged, as streamed ones
+    /// would have to be buffered to be hashed.
+    pub async fn apply(self, res: Response<Body>) -> Result<Response<Body>> {
+        if !self.cacheable || !res.status().is_success() {
+            return Ok(res);
+        }
+        let (mut parts, mut body) = res.into_parts();
+        if let Some(cache_control) = &self.policy.cache_control {
+            parts
+                .headers
+                .entry(header::CACHE_CONTROL)
+                .or_insert(HeaderValue::from_str(cache_control)?);
+        }
+        if self.policy.etag
+            && parts.status == StatusCode::OK
+            && !parts.headers.contains_key(header::ETAG)
+            && body.size_hint().exact().is_some()
+        {
+            let bytes = hyper::body::to_bytes(body).await?;
+            parts
+                .headers
+                .insert(header::ETAG, HeaderValue::from_str(&etag(&bytes))?);
+            body = Body::from(bytes);
+        }
+
+        let etag = parts
+            .headers
+            .get(header::ETAG)
+            .and_then(|tag| tag.to_str().ok());
+        let unchanged = match (&self.if_none_match, etag) {
+            (Some(tags), Some(etag)) => matches(tags, etag),
+            _ => false,
+        };
+        if unchanged {
+            not_modified(&parts)
+        } else {
+            Ok(Response::from_parts(parts, body))
+        }
+    }
+}
+
+/// The `ETag` of a body: its length, and FNV-1a hash (as it only tells the versions of one
+/// resource apart, rather than keeping them secret).
+fn etag(body: &[u8]) -> String {
+    format!("\"{:x}-{:016x}\"", body.len(), fnv1a(body))
+}
+
+/// Whether an `If-None-Match` (i.e., `*`, or a list of tags) has `etag`, by the weak
+/// comparison it calls for (i.e., ignoring `W/`).
+fn matches(if_none_match: &str, etag: &str) -> bool {
+    let weak = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = weak(etag);
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || weak(tag) == etag)
+}
+
+fn not_modified(parts: &Parts) -> Result<Response<Body>> {
+    let mut res = Response::builder().status(StatusCode::NOT_MODIFIED);
+    for name in NOT_MODIFIED_HEADERS {
+        for value in parts.headers.get_all(name) {
+            res = res.header(name, value);
+        }
+    }
+    Ok(res.body(Body::empty())?)
+}
+
+#[cfg(test)]
+mod unittests {
+    use hyper::{header, Body, Method, Request, Response, StatusCode};
+
+    use super::{matches, CachePolicy, Conditional};
+
+    fn request(method: Method, if_none_match: Option<&str>) -> Request<Body> {
+        let mut request = Request::builder().method(method).uri("/users");
+        if let Some(tags) = if_none_match {
+            request = request.header(header::IF_NONE_MATCH, tags);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    async fn respond(policy: &str, request: Request<Body>, res: Response<Body>) -> Response<Body> {
+        let policy = CachePolicy::parse(policy).unwrap();
+        Conditional::of(&request, policy).apply(res).await.unwrap()
+    }
+
+    #[test]
+    fn parse_test() {
+        let policy = CachePolicy::parse("max-age=60, S-MAXAGE=120, public, etag").unwrap();
+        assert_eq!(
+            policy.cache_control.as_deref(),
+            Some("max-age=60, s-maxage=120, public")
+        );
+        assert!(policy.etag);
+        assert_eq!(CachePolicy::parse("etag").unwrap().cache_control, None);
+
+        assert!(CachePolicy::parse("max-age=soon").is_err());
+        assert!(CachePolicy::parse("immutable").is_err());
+        assert!(CachePolicy::parse("no-store, etag").is_err());
+
+        assert!(matches("\"a\", W/\"b\"", "\"b\""));
+        assert!(matches("*", "\"c\""));
+        assert!(!matches("\"a\"", "\"b\""));
+    }
+
+    #[tokio::test]
+    async fn conditional_test() {
+        let ok = || Response::new(Body::from("[\"ada\"]"));
+
+        let res = respond("max-age=60, etag", request(Method::GET, None), ok()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CACHE_CONTROL], "max-age=60");
+        let etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+
+        // an unchanged response is revalidated w/o its body
+        let res = respond("max-age=60, etag", request(Method::GET, Some(&etag)), ok()).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers()[header::ETAG], etag.as_str());
+        assert!(hyper::body::to_bytes(res.into_body())
+            .await
+            .unwrap()
+            .is_empty());
+
+        // while a changed one is sent in full
+        let changed = Response::new(Body::from("[\"ada\", \"grace\"]"));
+        let res = respond("etag", request(Method::GET, Some(&etag)), changed).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_ne!(res.headers()[header::ETAG], etag.as_str());
+
+        // the guest's own headers win, and only the responses to reads are cached
+        let tagged = Response::builder()
+            .header(header::CACHE_CONTROL, "no-cache")
+            .header(header::ETAG, "\"v2\"")
+            .body(Body::empty())
+            .unwrap();
+        let res = respond(
+            "max-age=60, etag",
+            request(Method::GET, Some("W/\"v2\"")),
+            tagged,
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers()[header::CACHE_CONTROL], "no-cache");
+        let res = respond("max-age=60, etag", request(Method::POST, None), ok()).await;
+        assert!(res.headers().get(header::CACHE_CONTROL).is_none());
+        let (mut tx, body) = Body::channel();
+        tokio::spawn(async move { tx.send_data("streamed".into()).await });
+        let res = respond("etag", request(Method::GET, None), Response::new(body)).await;
+        assert!(res.headers().get(header::ETAG).is_none());
+    }
+}
@@ -0,0 +1,116 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use hyper::{Body, HeaderMap, Request, Response, StatusCode};
+use routerify::ext::RequestExt;
+
+/// The header clients set the deadline of a request in, as the Unix time (in millis) they'll
+/// give up on it by.
+pub const DEADLINE_HEADER: &str = "x-request-deadline";
+
+/// The deadline of a request, set when it's received (see `received`).
+#[derive(Clone, Copy, Debug)]
+struct RequestDeadline(Option<Instant>);
+
+/// Sets the deadline of a request (i.e., the pre middleware of deadlines): the earliest of the
+/// client's (see `DEADLINE_HEADER`), and the http capability's `timeout` from when it's
+/// received, if either is set.
+pub async fn received(request: Request<Body>, timeout: Option<Duration>) -> Result<Request<Body>> {
+    let deadline = deadline(
+        request.headers(),
+        timeout,
+        Instant::now(),
+        SystemTime::now(),
+    );
+    request.set_context(RequestDeadline(deadline));
+    Ok(request)
+}
+
+/// The deadline of a request, if it has one.
+pub fn of(request: &impl RequestExt) -> Option<Instant> {
+    request
+        .context::<RequestDeadline>()
+        .and_then(|deadline| deadline.0)
+}
+
+/// Whether the deadline of a request has passed already (e.g., while it waited for the
+/// guest), so there's no point in handling it.
+pub fn passed(request: &Request<Body>) -> bool {
+    of(request).map_or(false, |deadline| Instant::now() >= deadline)
+}
+
+/// The response to a request whose deadline passed before the guest handled it.
+pub fn exceeded() -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(StatusCode::GATEWAY_TIMEOUT)
+        .body(Body::from("Deadline Exceeded"))?)
+}
+
+fn deadline(
+    headers: &HeaderMap,
+    timeout: Option<Duration>,
+    now: Instant,
+    system_now: SystemTime,
+) -> Option<Instant> {
+    let client = headers
+        .get(DEADLINE_HEADER)
+        .and_then(|deadline| deadline.to_str().ok())
+        .and_then(|deadline| deadline.trim().parse::<u64>().ok())
+        .map(|millis| {
+            let at = UNIX_EPOCH + Duration::from_millis(millis);
+            // a deadline in the past has passed as of now
+            now + at.duration_since(system_now).unwrap_or_default()
+        });
+    let server = timeout.map(|timeout| now + timeout);
+    match (client, server) {
+        (Some(client), Some(server)) => Some(client.min(server)),
+        (client, server) => client.or(server),
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+
+    use hyper::HeaderMap;
+
+    use super::{deadline, DEADLINE_HEADER};
+
+    #[test]
+    fn deadline_test() {
+        let now = Instant::now();
+        let system_now = UNIX_EPOCH + Duration::from_secs(1_000);
+        let headers = |deadline: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(DEADLINE_HEADER, deadline.parse().unwrap());
+            headers
+        };
+        let second = Duration::from_secs(1);
+
+        assert_eq!(deadline(&HeaderMap::new(), None, now, system_now), None);
+        assert_eq!(
+            deadline(&HeaderMap::new(), Some(second), now, system_now),
+            Some(now + second)
+        );
+        assert_eq!(
+            deadline(&headers("1000500"), None, now, system_now),
+            Some(now + Duration::from_millis(500))
+        );
+        // the earliest one wins
+        assert_eq!(
+            deadline(
+                &headers("1000500"),
+                Some(Duration::from_millis(200)),
+                now,
+                system_now
+            ),
+            Some(now + Duration::from_millis(200))
+        );
+        // a past deadline has passed as of now, and one that isn't valid is ignored
+        assert_eq!(
+            deadline(&headers("999000"), None, now, system_now),
+            Some(now)
+        );
+        assert_eq!(deadline(&headers("soon"), None, now, system_now), None);
+    }
+}
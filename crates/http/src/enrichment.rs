@@ -0,0 +1,369 @@
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Result};
+use hyper::{header, HeaderMap};
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+
+/// The header proxies append the address they received a request from to.
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// A range of IPs (e.g., `10.0.0.0/8`, or `::1/128`) — a bare IP is a range of its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    fn parse(cidr: &str) -> Result<Self> {
+        let invalid = || {
+            anyhow!(
+                "invalid trusted proxy: '{}' (expected a CIDR, e.g., '10.0.0.0/8')",
+                cidr
+            )
+        };
+        let (network, prefix) = match cidr.split_once('/') {
+            Some((network, prefix)) => (network, Some(prefix)),
+            None => (cidr, None),
+        };
+        let network = canonical(network.trim().parse().map_err(|_| invalid())?);
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().map_err(|_| invalid())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(invalid());
+        }
+        Ok(Self { network, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The GeoIP database (i.e., a MaxMind `.mmdb`, like GeoLite2-Country) countries are looked
+/// up in.
+#[derive(Clone)]
+pub struct GeoIp(Arc<Reader<Vec<u8>>>);
+
+impl fmt::Debug for GeoIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GeoIp")
+    }
+}
+
+impl GeoIp {
+    pub fn open(path: &Path) -> Result<Self> {
+        let reader = Reader::open_readfile(path)
+            .map_err(|e| anyhow!("failed to open GeoIP database {}: {}", path.display(), e))?;
+        Ok(Self(Arc::new(reader)))
+    }
+
+    /// The ISO 3166-1 code of the country of `ip`, if the database knows it.
+    fn country(&self, ip: IpAddr) -> Option<String> {
+        match self.0.lookup::<geoip2::Country>(ip) {
+            Ok(country) => country.country?.iso_code.map(str::to_string),
+            Err(MaxMindDBError::AddressNotFoundError(_)) => None,
+            Err(e) => {
+                tracing::debug!("failed to look up the country of {}: {}", ip, e);
+                None
+            }
+        }
+    }
+}
+
+/// The enrichments of the http capability: fields it derives from each request on the host
+/// side (e.g., the client's IP), and hands to the guest w/ it.
+#[derive(Clone, Debug, Default)]
+pub struct EnrichmentSettings {
+    cookies: bool,
+    client_ip: bool,
+    /// the proxies whose `X-Forwarded-For` is trusted
+    trusted_proxies: Vec<Cidr>,
+    /// the database countries are looked up in, if they are
+    geoip: Option<GeoIp>,
+}
+
+impl EnrichmentSettings {
+    /// Parses the enrichments of the slightfile (i.e., `cookies`, `client_ip`, and/or
+    /// `country`), w/ the CIDRs of the trusted proxies, and the GeoIP database (which
+    /// `country` requires).
+    pub fn new(
+        enrich: &[String],
+        trusted_proxies: &[String],
+        geoip_database: Option<&Path>,
+    ) -> Result<Self> {
+        let mut settings = Self::default();
+        let mut country = false;
+        for enrichment in enrich {
+            match enrichment.as_str() {
+                "cookies" => settings.cookies = true,
+                "client_ip" => settings.client_ip = true,
+                "country" => country = true,
+                e => bail!(
+                    "invalid enrichment: '{}' (expected 'cookies', 'client_ip', or 'country')",
+                    e
+                ),
+            }
+        }
+        settings.geoip = match (country, geoip_database) {
+            (true, Some(path)) => Some(GeoIp::open(path)?),
+            (true, None) => bail!("the country enrichment requires a geoip_database"),
+            (false, Some(_)) => {
+                bail!("invalid geoip_database: it only applies to the country enrichment")
+            }
+            (false, None) => None,
+        };
+        if !trusted_proxies.is_empty() && !settings.client_ip && settings.geoip.is_none() {
+            bail!("invalid trusted_proxies: they only apply to the client_ip, and country enrichments");
+        }
+        settings.trusted_proxies = trusted_proxies
+            .iter()
+            .map(|cidr| Cidr::parse(cidr))
+            .collect::<Result<_>>()?;
+        Ok(settings)
+    }
+
+    /// Derives the enabled enrichments of a request received from `peer` (i.e., the address
+    /// that connected to the server).
+    pub fn enrich<'a>(&self, headers: &'a HeaderMap, peer: SocketAddr) -> Enriched<'a> {
+        let mut enriched = Enriched::default();
+        if self.cookies {
+            enriched.cookies = cookies(headers);
+        }
+        if self.client_ip || self.geoip.is_some() {
+            let client_ip = client_ip(&self.trusted_proxies, headers, peer.ip());
+            enriched.country = self
+                .geoip
+                .as_ref()
+                .and_then(|geoip| geoip.country(client_ip));
+            if self.client_ip {
+                enriched.client_ip = Some(client_ip.to_string());
+            }
+        }
+        enriched
+    }
+}
+
+/// What the enrichments of a request derived from it — see `EnrichmentSettings::enrich`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Enriched<'a> {
+    pub cookies: Vec<(&'a str, &'a str)>,
+    pub client_ip: Option<String>,
+    pub country: Option<String>,
+}
+
+/// Parses the `Cookie` headers of a request into (name, value) pairs, in order — values are
+/// unquoted, and pairs w/o a `=` are skipped.
+fn cookies(headers: &HeaderMap) -> Vec<(&str, &str)> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|cookie| cookie.to_str().ok())
+        .flat_map(|cookie| cookie.split(';'))
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            let name = name.trim();
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            (!name.is_empty()).then_some((name, value))
+        })
+        .collect()
+}
+
+/// Resolves the IP of the client that sent a request through `peer`.
+///
+/// Only trusted proxies are believed about who they forwarded a request for, so, starting at
+/// `peer`, the `X-Forwarded-For` entries are walked from right to left (i.e., from the last
+/// proxy to the first) for as long as the hop they were appended by is a trusted proxy. An
+/// entry that isn't an IP stops the walk at the proxy that appended it.
+fn client_ip(trusted_proxies: &[Cidr], headers: &HeaderMap, peer: IpAddr) -> IpAddr {
+    let trusted = |ip: IpAddr| trusted_proxies.iter().any(|cidr| cidr.contains(ip));
+    // a header that isn't text is an entry that isn't an IP
+    let forwarded = headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .flat_map(|forwarded| match forwarded.to_str() {
+            Ok(forwarded) => forwarded.split(',').collect(),
+            Err(_) => vec![""],
+        })
+        .collect::<Vec<_>>();
+
+    let mut client = canonical(peer);
+    for entry in forwarded.into_iter().rev() {
+        if !trusted(client) {
+            break;
+        }
+        match forwarded_ip(entry) {
+            Some(ip) => client = ip,
+            None => break,
+        }
+    }
+    client
+}
+
+/// Parses an `X-Forwarded-For` entry, which some proxies append w/ a port (e.g.,
+/// `203.0.113.7:52100`, or `[2001:db8::1]:443`).
+fn forwarded_ip(entry: &str) -> Option<IpAddr> {
+    let entry = entry.trim();
+    entry
+        .parse()
+        .ok()
+        .or_else(|| entry.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .map(canonical)
+}
+
+/// Unmaps IPv4 addresses from IPv6 (e.g., `::ffff:10.0.0.1`, as dual-stack servers see them).
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use hyper::{header::HeaderValue, HeaderMap};
+
+    use super::{client_ip, cookies, Cidr, EnrichmentSettings};
+
+    fn headers(headers: &[(&'static str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, HeaderValue::from_str(value).unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn cidr_test() {
+        let private = Cidr::parse("10.0.0.0/8").unwrap();
+        assert!(private.contains("10.1.2.3".parse().unwrap()));
+        assert!(!private.contains("11.0.0.1".parse().unwrap()));
+        assert!(!private.contains("::1".parse().unwrap()));
+        assert!(Cidr::parse("0.0.0.0/0")
+            .unwrap()
+            .contains("203.0.113.7".parse().unwrap()));
+        assert!(Cidr::parse("fd00::/8")
+            .unwrap()
+            .contains("fd12::1".parse().unwrap()));
+        assert!(Cidr::parse("::ffff:192.168.1.1")
+            .unwrap()
+            .contains("192.168.1.1".parse().unwrap()));
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("proxy.internal").is_err());
+    }
+
+    #[test]
+    fn client_ip_test() {
+        let trusted = [
+            Cidr::parse("10.0.0.0/8").unwrap(),
+            Cidr::parse("192.168.1.1").unwrap(),
+        ];
+        let proxy = "10.0.0.2".parse().unwrap();
+        let resolve = |forwarded: &[&str], peer| {
+            let forwarded = forwarded
+                .iter()
+                .map(|entry| ("x-forwarded-for", *entry))
+                .collect::<Vec<_>>();
+            client_ip(&trusted, &headers(&forwarded), peer).to_string()
+        };
+
+        // w/o proxies, the peer is the client
+        assert_eq!(resolve(&[], "203.0.113.7".parse().unwrap()), "203.0.113.7");
+        // untrusted peers can't forward for anyone
+        assert_eq!(
+            resolve(&["198.51.100.1"], "203.0.113.7".parse().unwrap()),
+            "203.0.113.7"
+        );
+        assert_eq!(resolve(&["203.0.113.7"], proxy), "203.0.113.7");
+        // a client can prepend whatever it wants, but not get past the proxies
+        assert_eq!(
+            resolve(&["1.1.1.1, 203.0.113.7, 192.168.1.1"], proxy),
+            "203.0.113.7"
+        );
+        assert_eq!(
+            resolve(&["1.1.1.1, 203.0.113.7:52100", "10.0.0.3"], proxy),
+            "203.0.113.7"
+        );
+        assert_eq!(resolve(&["[2001:db8::1]:443"], proxy), "2001:db8::1");
+        // all the hops are trusted, so the first one is the client
+        assert_eq!(resolve(&["10.0.0.4, 10.0.0.3"], proxy), "10.0.0.4");
+        assert_eq!(resolve(&["203.0.113.7, unknown"], proxy), "10.0.0.2");
+        assert_eq!(
+            resolve(&["203.0.113.7"], "::ffff:10.0.0.2".parse().unwrap()),
+            "203.0.113.7"
+        );
+    }
+
+    #[test]
+    fn cookies_test() {
+        let headers = headers(&[
+            ("cookie", "session=abc123; theme=\"dark\"; flag"),
+            ("cookie", "lang=pt-PT;"),
+        ]);
+        assert_eq!(
+            cookies(&headers),
+            vec![("session", "abc123"), ("theme", "dark"), ("lang", "pt-PT")]
+        );
+    }
+
+    #[test]
+    fn enrich_test() {
+        let settings = EnrichmentSettings::new(
+            &["cookies".to_string(), "client_ip".to_string()],
+            &["10.0.0.0/8".to_string()],
+            None,
+        )
+        .unwrap();
+        let headers = headers(&[("x-forwarded-for", "203.0.113.7"), ("cookie", "a=b")]);
+        let enriched = settings.enrich(&headers, "10.0.0.2:41000".parse().unwrap());
+        assert_eq!(enriched.cookies, vec![("a", "b")]);
+        assert_eq!(enriched.client_ip.as_deref(), Some("203.0.113.7"));
+        assert_eq!(enriched.country, None);
+
+        // nothing is derived unless it's enabled
+        let enriched =
+            EnrichmentSettings::default().enrich(&headers, "10.0.0.2:41000".parse().unwrap());
+        assert!(enriched.cookies.is_empty());
+        assert_eq!(enriched.client_ip, None);
+    }
+
+    #[test]
+    fn invalid_settings_test() {
+        let new = |enrich: &[&str], trusted_proxies: &[&str]| {
+            EnrichmentSettings::new(
+                &enrich.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+                &trusted_proxies
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<_>>(),
+                None,
+            )
+        };
+        assert!(new(&["geo"], &[]).is_err());
+        assert!(new(&["country"], &[]).is_err());
+        assert!(new(&["cookies"], &["10.0.0.0/8"]).is_err());
+        assert!(new(&["client_ip"], &["10.0.0.0/8"]).is_ok());
+    }
+}
@@ -1,32 +1,67 @@
 #![allow(clippy::upper_case_acronyms)]
 
+mod access_log;
+mod caching;
+mod deadline;
+mod enrichment;
+mod negotiation;
+mod openapi;
+mod payload;
+mod streaming;
+mod templates;
+mod tls;
+mod webhooks;
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
 use std::iter::zip;
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::{bail, Result};
 use crossbeam_utils::thread;
 use futures::executor::block_on;
 pub use http::add_to_linker;
 use http::*;
-use hyper::{Body, Server};
+use hyper::{header, Body, Server, StatusCode};
 use routerify::ext::RequestExt;
-use routerify::{Router, RouterBuilder, RouterService};
+use routerify::{Middleware, Router, RouterBuilder, RouterService};
 use slight_runtime::{
+    call::guest_phase,
     impl_resource,
+    invocations::{Invocation, Invocations},
     resource::{Ctx, ResourceMap},
+    trace_context::{TraceContext, TRACEPARENT_HEADER, TRACESTATE_HEADER},
 };
 
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tracing::log;
 
-use slight_http_api::{HttpBody, HttpHandler, HttpHeader, Method, Request};
+use payload::MaxPayload;
+use slight_http_api::{
+    ClientCert, Enrichment, HttpBody, HttpHandler, HttpHeader, Method, Request, Response,
+};
+use slight_webhooks::{Inbox, Webhook};
+use streaming::Outcome;
+use templates::Templates;
 use wasmtime::{Instance, Store};
 
+pub use access_log::{AccessLogFormat, AccessLogSettings, DEFAULT_REDACTED_HEADERS};
+pub use caching::CachePolicy;
+pub use deadline::DEADLINE_HEADER;
+pub use enrichment::EnrichmentSettings;
+pub use negotiation::Format;
+pub use openapi::OpenApi;
+pub use payload::DEFAULT_MAX_PAYLOAD_BYTES;
+pub use templates::TEMPLATE_HEADER;
+pub use tls::{ClientAuth, ClientIdentity, TlsSettings};
+
 wit_bindgen_wasmtime::export!("../../wit/http.wit");
 wit_error_rs::impl_error!(Error);
-wit_error_rs::impl_from!(anyhow::Error, Error::ErrorWithDescription);
+slight_runtime::impl_from_anyhow!(Error);
 
 const SCHEME_NAME: &str = "http";
 
@@ -39,6 +74,17 @@ enum Methods {
     DELETE,
 }
 
+impl Methods {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Methods::GET => "GET",
+            Methods::PUT => "PUT",
+            Methods::POST => "POST",
+            Methods::DELETE => "DELETE",
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 struct Route {
     method: Methods,
@@ -52,6 +98,23 @@ pub struct RouterInner {
     /// The root directory of the filesystem
     _base_uri: String,
     routes: Vec<Route>,
+    /// The guest's catch-all handler, if it registered one
+    fallback: Option<String>,
+    /// The formats of the routes whose responses are negotiated, overriding the
+    /// slightfile's `formats`
+    formats: HashMap<String, Vec<Format>>,
+    /// The caching policies of routes, overriding the slightfile's `cache_policies`
+    cache_policies: HashMap<String, CachePolicy>,
+    /// The routes whose request bodies are streamed to their handlers, rather than buffered
+    streamed_bodies: HashSet<String>,
+}
+
+/// What requests no route matches are handled w/: the routes (to tell an unknown
+/// path from a wrong method), and the guest's catch-all handler (if any).
+#[derive(Clone, Debug)]
+struct Fallback {
+    routes: Vec<Route>,
+    handler: Option<String>,
 }
 
 impl RouterInner {
@@ -92,8 +155,47 @@ impl RouterInner {
         self.routes.push(route);
         Ok(self.clone())
     }
+
+    /// Sets the handler for requests that no route matches.
+    fn fallback(&mut self, handler: String) -> Result<Self, Error> {
+        self.fallback = Some(handler);
+        Ok(self.clone())
+    }
+
+    /// Sets the formats a route's responses are negotiated in (i.e., for all of its methods).
+    fn negotiate(&mut self, route: String, formats: &[&str]) -> Result<Self, Error> {
+        let formats = formats
+            .iter()
+            .map(|format| Format::parse(format))
+            .collect::<Result<Vec<_>>>()?;
+        self.formats.insert(route, formats);
+        Ok(self.clone())
+    }
+
+    /// Sets how a route's responses are cached (i.e., for all of its methods).
+    fn cache(&mut self, route: String, policy: &str) -> Result<Self, Error> {
+        self.cache_policies
+            .insert(route, CachePolicy::parse(policy)?);
+        Ok(self.clone())
+    }
+
+    /// Streams the bodies of a route's requests to its handler (i.e., for all of its methods).
+    fn stream_body(&mut self, route: String) -> Result<Self, Error> {
+        self.streamed_bodies.insert(route);
+        Ok(self.clone())
+    }
 }
 
+/// The body of a request the guest reads as it's streamed (see `streaming`), whose state is
+/// kept by the thread handling the request, rather than by the resource.
+#[derive(Clone, Debug)]
+pub struct RequestStreamInner;
+
+/// A response the guest streams (see `streaming`), whose state is kept by the thread
+/// handling its request, rather than by the resource.
+#[derive(Clone, Debug)]
+pub struct ResponseStreamInner;
+
 #[derive(Clone, Debug)]
 pub struct ServerInner {
     closer: Arc<Mutex<UnboundedSender<()>>>,
@@ -132,21 +234,79 @@ impl Http {
     }
 }
 
+/// The settings of the http capability that come from the slightfile.
+#[derive(Clone, Debug, Default)]
+pub struct HttpSettings {
+    /// The directory templates are rendered from (see `TEMPLATE_HEADER`)
+    pub templates_dir: Option<PathBuf>,
+    /// How requests are logged on the host side, if they are
+    pub access_log: Option<AccessLogSettings>,
+    /// The formats responses are negotiated in, unless a route says otherwise (or none, to not
+    /// negotiate them)
+    pub formats: Vec<Format>,
+    /// The OpenAPI spec requests, and responses are validated against, if any
+    pub openapi: Option<OpenApi>,
+    /// What's derived from requests on the host side, and handed to the guest w/ them
+    pub enrichment: EnrichmentSettings,
+    /// How long the guest has to handle a request, from when it's received — the capability
+    /// calls it makes past it (or past the client's deadline, see `DEADLINE_HEADER`) fail
+    pub request_timeout: Option<Duration>,
+    /// How TLS is terminated, and client certificates are verified, if the server is served
+    /// over TLS
+    pub tls: Option<TlsSettings>,
+    /// How the responses of routes are cached, by route (see `CachePolicy`)
+    pub cache_policies: HashMap<String, CachePolicy>,
+    /// The webhooks of the slightfile (see `slight_webhooks`), which are served at their path
+    /// before the guest's routes
+    pub webhooks: Vec<Webhook>,
+    /// How big the bodies of requests can be before they're answered w/ a 413, rather than
+    /// handed to the guest (defaults to `DEFAULT_MAX_PAYLOAD_BYTES`)
+    pub max_payload_bytes: Option<usize>,
+}
+
 #[derive(Default)]
 pub struct HttpState {
-    _resource_map: ResourceMap,
+    resource_map: ResourceMap,
+    templates: Arc<Templates>,
+    access_log: Option<Arc<AccessLogSettings>>,
+    formats: Vec<Format>,
+    openapi: Option<Arc<OpenApi>>,
+    enrichment: Arc<EnrichmentSettings>,
+    request_timeout: Option<Duration>,
+    tls: Option<TlsSettings>,
+    cache_policies: HashMap<String, CachePolicy>,
+    webhooks: Vec<Webhook>,
+    max_payload_bytes: Option<usize>,
+    invocations: Invocations,
     store: Option<Arc<Mutex<Store<Ctx>>>>,
     instance: Option<Arc<Mutex<Instance>>>,
     closer: Option<Arc<Mutex<UnboundedSender<()>>>>,
 }
 
 impl HttpState {
-    pub fn new(_resource_map: ResourceMap) -> Self {
+    pub fn new(resource_map: ResourceMap, settings: HttpSettings) -> Self {
         Self {
-            _resource_map,
+            resource_map,
+            templates: Arc::new(Templates::new(settings.templates_dir)),
+            access_log: settings.access_log.map(Arc::new),
+            formats: settings.formats,
+            openapi: settings.openapi.map(Arc::new),
+            enrichment: Arc::new(settings.enrichment),
+            request_timeout: settings.request_timeout,
+            tls: settings.tls,
+            cache_policies: settings.cache_policies,
+            webhooks: settings.webhooks,
+            max_payload_bytes: settings.max_payload_bytes,
             ..Default::default()
         }
     }
+
+    /// Holds the invocations of the guest's handlers to the app's `invocations` — requests
+    /// that arrive while as many invocations as it allows are in flight are shed w/ a 503.
+    pub fn with_invocations(mut self, invocations: Invocations) -> Self {
+        self.invocations = invocations;
+        self
+    }
 }
 
 impl_resource!(
@@ -172,6 +332,8 @@ impl Http {
 impl http::Http for Http {
     type Router = RouterInner;
     type Server = ServerInner;
+    type RequestStream = RequestStreamInner;
+    type ResponseStream = ResponseStreamInner;
 
     fn router_new(&mut self) -> Result<Self::Router, Error> {
         Ok(RouterInner::default())
@@ -229,6 +391,52 @@ impl http::Http for Http {
         rclone.delete(route.to_string(), handler.to_string())
     }
 
+    fn router_fallback(
+        &mut self,
+        router: &Self::Router,
+        handler: &str,
+    ) -> Result<Self::Router, Error> {
+        // Router is a reference to the router proxy, so we need to clone it to get a
+        // mutable reference to the router.
+        let mut rclone = router.clone();
+        rclone.fallback(handler.to_string())
+    }
+
+    fn router_negotiate(
+        &mut self,
+        router: &Self::Router,
+        route: &str,
+        formats: Vec<&str>,
+    ) -> Result<Self::Router, Error> {
+        // Router is a reference to the router proxy, so we need to clone it to get a
+        // mutable reference to the router.
+        let mut rclone = router.clone();
+        rclone.negotiate(route.to_string(), &formats)
+    }
+
+    fn router_cache(
+        &mut self,
+        router: &Self::Router,
+        route: &str,
+        policy: &str,
+    ) -> Result<Self::Router, Error> {
+        // Router is a reference to the router proxy, so we need to clone it to get a
+        // mutable reference to the router.
+        let mut rclone = router.clone();
+        rclone.cache(route.to_string(), policy)
+    }
+
+    fn router_stream_body(
+        &mut self,
+        router: &Self::Router,
+        route: &str,
+    ) -> Result<Self::Router, Error> {
+        // Router is a reference to the router proxy, so we need to clone it to get a
+        // mutable reference to the router.
+        let mut rclone = router.clone();
+        rclone.stream_body(route.to_string())
+    }
+
     fn server_serve(
         &mut self,
         address: &str,
@@ -240,15 +448,62 @@ impl http::Http for Http {
 
         // The outer builder is used to define the route paths, while creating a scope
         // for the inner builder which passes states to the route handler.
-        let mut outer_builder: RouterBuilder<Body, anyhow::Error> =
-            Router::builder().data(store).data(instance);
+        let mut outer_builder: RouterBuilder<Body, anyhow::Error> = Router::builder()
+            .data(store)
+            .data(instance)
+            .data(self.host_state.templates.clone())
+            .data(self.host_state.openapi.clone())
+            .data(self.host_state.enrichment.clone())
+            .data(MaxPayload(
+                self.host_state
+                    .max_payload_bytes
+                    .unwrap_or(DEFAULT_MAX_PAYLOAD_BYTES),
+            ))
+            .data(self.host_state.invocations.clone());
+        let request_timeout = self.host_state.request_timeout;
+        outer_builder = outer_builder.middleware(Middleware::pre(move |req| {
+            deadline::received(req, request_timeout)
+        }));
+        if let Some(settings) = self.host_state.access_log.clone() {
+            outer_builder = outer_builder
+                .middleware(Middleware::pre(access_log::received))
+                .middleware(Middleware::post_with_info(move |res, info| {
+                    access_log::log(&settings, &res, &info);
+                    async move { Ok(res) }
+                }));
+        }
+
+        // Deliveries of webhooks are verified, and handed to the guest as events by the host, so
+        // their routes are served before the guest's (which can't shadow them).
+        if !self.host_state.webhooks.is_empty() {
+            let inbox = Inbox::shared(&mut self.host_state.resource_map.lock().unwrap());
+            for webhook in &self.host_state.webhooks {
+                let inner_builder: RouterBuilder<Body, anyhow::Error> = Router::builder()
+                    .data(webhook.clone())
+                    .data(inbox.clone())
+                    .post("/", webhooks::receive);
+                outer_builder = outer_builder.scope(&webhook.path, inner_builder.build().unwrap());
+            }
+        }
 
         // There is a one-to-one mapping between the outer router's scope and inner router builder.
         let mut inner_routes = vec![];
         for route in router.routes.iter() {
             let mut inner_builder: RouterBuilder<Body, anyhow::Error> = Router::builder();
             // per route state
-            inner_builder = inner_builder.data(route.clone());
+            let formats = router
+                .formats
+                .get(&route.route)
+                .unwrap_or(&self.host_state.formats);
+            let cache_policy = router
+                .cache_policies
+                .get(&route.route)
+                .or_else(|| self.host_state.cache_policies.get(&route.route));
+            inner_builder = inner_builder
+                .data(route.clone())
+                .data(Formats(formats.clone()))
+                .data(Caching(cache_policy.cloned()))
+                .data(StreamedBody(router.streamed_bodies.contains(&route.route)));
             match route.method {
                 Methods::GET => {
                     inner_builder = inner_builder.get("/", handler);
@@ -270,20 +525,36 @@ impl http::Http for Http {
         for (route, built) in zip(router.routes.clone(), inner_routes) {
             outer_builder = outer_builder.scope(&route.route, built);
         }
+        // Requests that no route matched end up here, as it's registered last.
+        outer_builder = outer_builder
+            .data(Fallback {
+                routes: router.routes.clone(),
+                handler: router.fallback.clone(),
+            })
+            .any(fallback);
         let built = outer_builder.build().unwrap();
 
         // Log the routes for debugging purposes.
         log::debug!("{:#?}", built);
 
-        // Defines the server
-        let service = RouterService::new(built).unwrap();
         let addr = str_to_socket_address(address)?;
-        let server = Server::bind(&addr).serve(service);
         // Create a channel to send the termination message
         let (tx, rx) = unbounded_channel();
-        let graceful = server.with_graceful_shutdown(shutdown_signal(rx));
-        // Start the server in a separate thread
-        tokio::task::spawn(graceful);
+        if let Some(settings) = self.host_state.tls.clone() {
+            // Start the server in a separate thread, terminating TLS on each connection
+            tokio::task::spawn(async move {
+                if let Err(e) = tls::serve(addr, &settings, built, rx).await {
+                    log::error!("the https server at {} failed: {}", addr, e);
+                }
+            });
+        } else {
+            // Defines the server
+            let service = RouterService::new(built).unwrap();
+            let server = Server::bind(&addr).serve(service);
+            let graceful = server.with_graceful_shutdown(shutdown_signal(rx));
+            // Start the server in a separate thread
+            tokio::task::spawn(graceful);
+        }
 
         let arc_tx = Arc::new(Mutex::new(tx));
         self.host_state.closer = Some(arc_tx.clone());
@@ -296,14 +567,204 @@ impl http::Http for Http {
         let clone = server.clone();
         clone.close()
     }
+
+    fn request_stream_open(&mut self) -> Result<Self::RequestStream, Error> {
+        streaming::open_body()?;
+        Ok(RequestStreamInner)
+    }
+
+    fn request_stream_read(
+        &mut self,
+        _self_: &Self::RequestStream,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        Ok(streaming::read()?)
+    }
+
+    fn response_stream_open(
+        &mut self,
+        status: u16,
+        headers: Vec<(&str, &str)>,
+    ) -> Result<Self::ResponseStream, Error> {
+        streaming::open(status, &headers)?;
+        Ok(ResponseStreamInner)
+    }
+
+    fn response_stream_write(
+        &mut self,
+        _self_: &Self::ResponseStream,
+        chunk: &[u8],
+    ) -> Result<(), Error> {
+        Ok(streaming::write(chunk)?)
+    }
+
+    fn response_stream_close(&mut self, _self_: &Self::ResponseStream) -> Result<(), Error> {
+        Ok(streaming::close()?)
+    }
 }
 
+/// The formats a route's responses are negotiated in (or none, if they aren't).
+#[derive(Clone, Debug)]
+struct Formats(Vec<Format>);
+
+/// How a route's responses are cached (or not at all, if it has no policy).
+#[derive(Clone, Debug)]
+struct Caching(Option<CachePolicy>);
+
+/// Whether the bodies of a route's requests are streamed to its handler.
+#[derive(Clone, Copy, Debug)]
+struct StreamedBody(bool);
+
 async fn handler(request: hyper::Request<Body>) -> Result<hyper::Response<Body>> {
+    let streamed = request
+        .data::<StreamedBody>()
+        .map_or(false, |streamed| streamed.0);
+    let request = match payload::limited(request, streamed).await? {
+        Some(request) => request,
+        None => return payload::too_large(),
+    };
+    let conditional = request
+        .data::<Caching>()
+        .and_then(|caching| caching.0.clone())
+        .map(|policy| caching::Conditional::of(&request, policy));
+    let res = enforcing_openapi(request, handle).await?;
+    match conditional {
+        Some(conditional) => conditional.apply(res).await,
+        None => Ok(res),
+    }
+}
+
+/// Handles a request w/ its route's handler, negotiating the format of its response.
+async fn handle(request: hyper::Request<Body>) -> Result<hyper::Response<Body>> {
+    let route = request.data::<Route>().unwrap().clone();
+    let formats = request.data::<Formats>().unwrap().0.clone();
+    if formats.is_empty() {
+        return respond(request, route.handler).await;
+    }
+
+    // the guest isn't invoked for requests it can't respond to
+    let accept = request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok());
+    match negotiation::negotiate(&formats, accept) {
+        // streamed responses are sent as the guest writes them
+        Some(format) => {
+            if deadline::passed(&request) {
+                return deadline::exceeded();
+            }
+            let invocation = match start_invocation(&request) {
+                Some(invocation) => invocation,
+                None => return overloaded(),
+            };
+            let handler = route.handler;
+            match streaming::run(move || invoke_guest(request, &handler, invocation)).await? {
+                Outcome::Buffered(res) => Ok(negotiation::serialize(res, format).into()),
+                Outcome::Streamed(res) => Ok(res),
+            }
+        }
+        None => {
+            log::debug!("no format of {:?} is acceptable for {:?}", formats, accept);
+            Ok(negotiation::not_acceptable(&formats).into())
+        }
+    }
+}
+
+/// Handles requests that no route matches w/ the guest's catch-all handler, if it
+/// registered one, or else w/ a 405 if a route matches the path (but not the method),
+/// and a 404 if none does.
+async fn fallback(request: hyper::Request<Body>) -> Result<hyper::Response<Body>> {
+    let fallback = request.data::<Fallback>().unwrap().clone();
+    if let Some(handler) = fallback.handler {
+        let request = match payload::limited(request, false).await? {
+            Some(request) => request,
+            None => return payload::too_large(),
+        };
+        return enforcing_openapi(request, |request| respond(request, handler)).await;
+    }
+
+    let allowed = allowed_methods(&fallback.routes, request.uri().path());
+    let res = if allowed.is_empty() {
+        log::debug!("no route matches {}", request.uri().path());
+        hyper::Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("Not Found"))?
+    } else {
+        log::debug!(
+            "no route matches {} {}, allowed methods: {}",
+            request.method(),
+            request.uri().path(),
+            allowed.join(", ")
+        );
+        hyper::Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header(header::ALLOW, allowed.join(", "))
+            .body(Body::from("Method Not Allowed"))?
+    };
+    Ok(res)
+}
+
+/// Responds to a request w/ `respond`, validating it, and its response against the OpenAPI
+/// spec of the http capability, if it has one (see `openapi::enforce`).
+async fn enforcing_openapi<Fut>(
+    request: hyper::Request<Body>,
+    respond: impl FnOnce(hyper::Request<Body>) -> Fut,
+) -> Result<hyper::Response<Body>>
+where
+    Fut: Future<Output = Result<hyper::Response<Body>>>,
+{
+    match request.data::<Option<Arc<OpenApi>>>().cloned().flatten() {
+        Some(spec) => {
+            let streamed = request
+                .data::<StreamedBody>()
+                .map_or(false, |streamed| streamed.0);
+            openapi::enforce(&spec, request, streamed, respond).await
+        }
+        None => respond(request).await,
+    }
+}
+
+/// Responds to a request w/ the guest's `handler`, whether it returns its response, or
+/// streams it.
+async fn respond(request: hyper::Request<Body>, handler: String) -> Result<hyper::Response<Body>> {
+    if deadline::passed(&request) {
+        return deadline::exceeded();
+    }
+    let invocation = match start_invocation(&request) {
+        Some(invocation) => invocation,
+        None => return overloaded(),
+    };
+    match streaming::run(move || invoke_guest(request, &handler, invocation)).await? {
+        Outcome::Buffered(res) => Ok(res.into()),
+        Outcome::Streamed(res) => Ok(res),
+    }
+}
+
+/// Starts the invocation of the guest for a request, or `None` if as many invocations as
+/// the app allows are in flight already.
+fn start_invocation(request: &hyper::Request<Body>) -> Option<Invocation> {
+    request.data::<Invocations>().unwrap().try_acquire("http")
+}
+
+/// The response to a request that was shed, as the app had as many invocations in flight as
+/// it allows.
+fn overloaded() -> Result<hyper::Response<Body>> {
+    Ok(hyper::Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(header::RETRY_AFTER, "1")
+        .body(Body::from("Service Unavailable"))?)
+}
+
+/// Invokes the guest's `handler` for a request, as part of its `invocation` (which finishes
+/// w/ the handler, even if it streams its response).
+fn invoke_guest(
+    request: hyper::Request<Body>,
+    handler: &str,
+    _invocation: Invocation,
+) -> Result<Response> {
     log::debug!("received request: {:?}", &request);
     let (parts, body) = request.into_parts();
 
-    // Fetch states from the request, including Store, Instance and the route name.
-    let route = parts.data::<Route>().unwrap();
+    // Fetch states from the request, including Store, and Instance.
     let mut store = parts
         .data::<Arc<Mutex<Store<Ctx>>>>()
         .unwrap()
@@ -324,41 +785,119 @@ async fn handler(request: hyper::Request<Body>) -> Result<hyper::Response<Body>>
     let method: Method = (&parts.method).into();
     let headers: HttpHeader = (&parts.headers).into();
 
-    // FIXME: `HttpBody::from_body` returns a future here. The reason that `block_on` is used is
-    // because the `store` and `instance` are holding a mutex, which means that the async runtime
-    // cannot switch to another thread.
-    let bytes = block_on(HttpBody::from_body(body))?.inner();
+    // `HttpBody::from_body` returns a future here, but the guest is invoked on a blocking
+    // thread (see `streaming::run`), which is also holding the `store`, and `instance` mutexes.
+    //
+    // Streamed bodies are read by the guest as it handles the request, instead.
+    let streamed = parts
+        .data::<StreamedBody>()
+        .map_or(false, |streamed| streamed.0);
+    let (bytes, _receiving) = if streamed {
+        (None, Some(streaming::receive(body)))
+    } else {
+        (Some(block_on(HttpBody::from_body(body))?.inner()), None)
+    };
     let uri = &(&parts.uri).to_string();
+    let enriched = parts
+        .data::<Arc<EnrichmentSettings>>()
+        .unwrap()
+        .enrich(&parts.headers, parts.remote_addr());
+    let identity = parts.extensions.get::<ClientIdentity>();
+    let sans =
+        identity.map(|identity| identity.sans.iter().map(String::as_str).collect::<Vec<_>>());
     let req = Request {
         method,
         uri,
         headers: &headers.inner(),
-        body: Some(&bytes),
+        body: bytes.as_deref(),
         params: &params,
+        enrichment: Enrichment {
+            cookies: &enriched.cookies,
+            client_ip: enriched.client_ip.as_deref(),
+            country: enriched.country.as_deref(),
+            client_cert: identity
+                .zip(sans.as_deref())
+                .map(|(identity, sans)| ClientCert {
+                    subject: &identity.subject,
+                    sans,
+                }),
+        },
     };
 
     // Construct http handler
-    let mut handler = HttpHandler::new(store.deref_mut(), instance.deref(), |ctx| {
+    let mut http_handler = HttpHandler::new(store.deref_mut(), instance.deref(), |ctx| {
         &mut ctx.http_state
     })
     .unwrap();
 
     // Perform the http request
-    log::debug!("Invoking guest handler {}", &route.handler,);
-    let func = instance
-        .get_typed_func::<(i32, i32, i32, i32, i32, i32, i32, i32, i32, i32), (i32,), _>(
-            store.deref_mut(),
-            &route.handler.replace('_', "-"),
-        );
+    log::debug!("Invoking guest handler {}", handler);
+    // the signature of the guest function is inferred from the `handle_http` it's assigned to,
+    // as it depends on how many fields a request has (i.e., whether they are passed flattened,
+    // or in the guest's memory).
+    let func = instance.get_typed_func::<_, _, _>(store.deref_mut(), &handler.replace('_', "-"));
     if func.is_err() {
-        bail!("Failed to find guest function {}", &route.handler);
+        bail!("Failed to find guest function {}", handler);
     }
-    handler.handle_http = func.unwrap(); // unwrap is safe because we checked above
-    let res = handler.handle_http(store.deref_mut(), req)??;
+    http_handler.handle_http = func.unwrap(); // unwrap is safe because we checked above
+    let res = {
+        let _phase = guest_phase(&format!("http {}", handler));
+        // the capability calls the guest makes while handling the request inherit its deadline
+        let outer = store.data().deadline;
+        store.data_mut().deadline = slight_runtime::deadline::nested(outer, deadline::of(&parts));
+        // and its trace context, which the outbound requests they make carry
+        let _trace_context = TraceContext::enter(Some(trace_context(&parts.headers)));
+        let res = http_handler.handle_http(store.deref_mut(), req);
+        store.data_mut().deadline = outer;
+        res??
+    };
     log::debug!("response: {:?}", res);
 
-    // Perform the conversion from `handle_http::Response` to `hyper::Response`.
-    Ok(res.into())
+    // Render the response if the guest returned a template name, and its data.
+    Ok(parts.data::<Arc<Templates>>().unwrap().render(res))
+}
+
+/// The trace context of a request (i.e., of its `traceparent`, and `tracestate` headers, which
+/// may be split across many), or a new trace, if it doesn't have a valid one.
+fn trace_context(headers: &header::HeaderMap) -> TraceContext {
+    let tracestate = headers
+        .get_all(TRACESTATE_HEADER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    headers
+        .get(TRACEPARENT_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|traceparent| TraceContext::parse(traceparent, Some(&tracestate)))
+        .unwrap_or_else(TraceContext::start)
+}
+
+/// The methods of the routes that match `path`, sorted, and w/o duplicates.
+fn allowed_methods(routes: &[Route], path: &str) -> Vec<&'static str> {
+    let mut allowed = routes
+        .iter()
+        .filter(|route| path_matches(&route.route, path))
+        .map(|route| route.method.as_str())
+        .collect::<Vec<_>>();
+    allowed.sort_unstable();
+    allowed.dedup();
+    allowed
+}
+
+/// Whether a route's path (e.g., `/users/:id`, or `/static/*`) matches `path`.
+fn path_matches(route: &str, path: &str) -> bool {
+    let mut route_segments = route.trim_matches('/').split('/');
+    let mut path_segments = path.trim_matches('/').split('/');
+    loop {
+        match (route_segments.next(), path_segments.next()) {
+            (Some("*"), _) => return true,
+            (Some(r), Some(p)) if r.starts_with(':') && !p.is_empty() => continue,
+            (Some(r), Some(p)) if r == p => continue,
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
 }
 
 async fn shutdown_signal(mut rx: UnboundedReceiver<()>) {
@@ -378,7 +917,9 @@ fn str_to_socket_address(s: &str) -> Result<SocketAddr> {
 
 #[cfg(test)]
 mod unittests {
-    use super::str_to_socket_address;
+    use super::{
+        allowed_methods, path_matches, str_to_socket_address, trace_context, Methods, Route,
+    };
     use anyhow::Result;
     use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
@@ -410,4 +951,57 @@ mod unittests {
         );
         Ok(())
     }
+
+    fn route(method: Methods, route: &str) -> Route {
+        Route {
+            method,
+            route: route.to_string(),
+            handler: "handler".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_path_matches() {
+        assert!(path_matches("/", "/"));
+        assert!(path_matches("/hello", "/hello/"));
+        assert!(path_matches("/users/:id", "/users/42"));
+        assert!(path_matches("/static/*", "/static/css/main.css"));
+        assert!(!path_matches("/hello", "/"));
+        assert!(!path_matches("/hello", "/hello/world"));
+        assert!(!path_matches("/users/:id", "/users"));
+        assert!(!path_matches("/users/:id", "/users/42/posts"));
+    }
+
+    #[test]
+    fn test_allowed_methods() {
+        let routes = vec![
+            route(Methods::PUT, "/users/:id"),
+            route(Methods::GET, "/users/:id"),
+            route(Methods::GET, "/users/:name"),
+            route(Methods::POST, "/users"),
+        ];
+        assert_eq!(allowed_methods(&routes, "/users/42"), vec!["GET", "PUT"]);
+        assert_eq!(allowed_methods(&routes, "/users"), vec!["POST"]);
+        assert!(allowed_methods(&routes, "/posts").is_empty());
+    }
+
+    #[test]
+    fn test_trace_context() {
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert("traceparent", traceparent.parse().unwrap());
+        headers.append("tracestate", "a=1".parse().unwrap());
+        headers.append("tracestate", "b=2".parse().unwrap());
+        let context = trace_context(&headers);
+        assert_eq!(context.traceparent(), traceparent);
+        assert_eq!(context.state.as_deref(), Some("a=1,b=2"));
+
+        // a request w/o a valid context starts a new trace
+        headers.insert("traceparent", "00-invalid".parse().unwrap());
+        assert_ne!(trace_context(&headers).traceparent(), traceparent);
+        assert_ne!(
+            trace_context(&hyper::HeaderMap::new()).trace_id,
+            trace_context(&hyper::HeaderMap::new()).trace_id
+        );
+    }
 }
@@ -0,0 +1,357 @@
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use slight_http_api::Response;
+use tracing::log;
+
+/// The root element of XML responses.
+const XML_ROOT: &str = "response";
+
+/// The element each item of a JSON array becomes in XML responses.
+const XML_ITEM: &str = "item";
+
+/// A format the host can serialize a guest's JSON responses to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Xml,
+    MessagePack,
+}
+
+impl Format {
+    pub fn parse(format: &str) -> Result<Self> {
+        match format {
+            "json" => Ok(Self::Json),
+            "xml" => Ok(Self::Xml),
+            "msgpack" => Ok(Self::MessagePack),
+            f => bail!(
+                "invalid format: '{}' (expected 'json', 'xml', or 'msgpack')",
+                f
+            ),
+        }
+    }
+
+    /// The media types of the format, the first of which is what responses are labeled w/.
+    fn media_types(&self) -> &'static [&'static str] {
+        match self {
+            Self::Json => &["application/json"],
+            Self::Xml => &["application/xml", "text/xml"],
+            Self::MessagePack => &[
+                "application/msgpack",
+                "application/x-msgpack",
+                "application/vnd.msgpack",
+            ],
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        self.media_types()[0]
+    }
+
+    fn serialize(&self, value: &Value) -> Result<Vec<u8>> {
+        Ok(match self {
+            Self::Json => serde_json::to_vec(value)?,
+            Self::Xml => {
+                let mut xml = r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string();
+                write_xml(&mut xml, XML_ROOT, value);
+                xml.into_bytes()
+            }
+            Self::MessagePack => rmp_serde::to_vec_named(value)?,
+        })
+    }
+}
+
+/// Picks the format to respond in, out of the route's `formats`, as per a request's `Accept`
+/// header — or `None` if it accepts none of them.
+///
+/// The client's preferences (i.e., the `q` of each media range) come first, and the order of
+/// `formats` breaks ties. Requests w/o an `Accept` header get the first of `formats`.
+pub fn negotiate(formats: &[Format], accept: Option<&str>) -> Option<Format> {
+    let accept = match accept {
+        Some(accept) if !accept.trim().is_empty() => accept,
+        _ => return formats.first().copied(),
+    };
+    let ranges = accept
+        .split(',')
+        .filter_map(media_range)
+        .collect::<Vec<_>>();
+    let mut best: Option<(Format, f32)> = None;
+    for format in formats {
+        let q = format
+            .media_types()
+            .iter()
+            .filter_map(|media_type| quality(&ranges, media_type))
+            .fold(None, |best: Option<f32>, q| {
+                Some(best.map_or(q, |b| b.max(q)))
+            });
+        match (q, best) {
+            (Some(q), Some((_, best_q))) if q <= best_q => {}
+            (Some(q), _) if q > 0.0 => best = Some((*format, q)),
+            _ => {}
+        }
+    }
+    best.map(|(format, _)| format)
+}
+
+/// Parses a media range of an `Accept` header (e.g., `application/*;q=0.8`) into its
+/// type, subtype, and quality.
+fn media_range(range: &str) -> Option<(String, String, f32)> {
+    let mut parts = range.split(';');
+    let (type_, subtype) = parts.next()?.trim().split_once('/')?;
+    let q = parts
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+        .map_or(Some(1.0), |(_, q)| q.trim().parse::<f32>().ok())?;
+    Some((type_.to_ascii_lowercase(), subtype.to_ascii_lowercase(), q))
+}
+
+/// The quality of a media type, as per the most specific of the `ranges` matching it, if any does.
+fn quality(ranges: &[(String, String, f32)], media_type: &str) -> Option<f32> {
+    let (type_, subtype) = media_type.split_once('/')?;
+    ranges
+        .iter()
+        .filter_map(|(t, s, q)| match (t.as_str(), s.as_str()) {
+            (t, s) if t == type_ && s == subtype => Some((2, *q)),
+            (t, "*") if t == type_ => Some((1, *q)),
+            ("*", "*") => Some((0, *q)),
+            _ => None,
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map(|(_, q)| q)
+}
+
+/// The 406 response to requests accepting none of the route's `formats`.
+pub fn not_acceptable(formats: &[Format]) -> Response {
+    let supported = formats
+        .iter()
+        .map(Format::content_type)
+        .collect::<Vec<_>>()
+        .join(", ");
+    Response {
+        status: 406,
+        headers: Some(vec![
+            ("content-type".to_string(), "text/plain".to_string()),
+            ("vary".to_string(), "accept".to_string()),
+        ]),
+        body: Some(format!("Not Acceptable: supported types are {}", supported).into_bytes()),
+    }
+}
+
+/// Serializes a guest's response to `format`, if it is JSON (i.e., its `content-type` is
+/// `application/json`), or returns it untouched, otherwise.
+///
+/// Failing to serialize a response yields a 500 response, and the reason is logged.
+pub fn serialize(mut res: Response, format: Format) -> Response {
+    let headers = res.headers.get_or_insert_with(Vec::new);
+    headers.push(("vary".to_string(), "accept".to_string()));
+    let content_type = match headers
+        .iter_mut()
+        .find(|(k, _)| k.eq_ignore_ascii_case("content-type"))
+    {
+        Some((_, content_type)) if is_json(content_type) => content_type,
+        _ => return res,
+    };
+    if format == Format::Json {
+        return res;
+    }
+    *content_type = format.content_type().to_string();
+
+    let body = res.body.take().unwrap_or_default();
+    match serde_json::from_slice::<Value>(&body)
+        .with_context(|| "the response body is not valid JSON")
+        .and_then(|value| format.serialize(&value))
+    {
+        Ok(body) => {
+            res.body = Some(body);
+            res
+        }
+        Err(e) => {
+            log::error!(
+                "failed to serialize response to {}: {:#}",
+                format.content_type(),
+                e
+            );
+            Response {
+                status: 500,
+                headers: None,
+                body: Some(b"failed to serialize response".to_vec()),
+            }
+        }
+    }
+}
+
+fn is_json(content_type: &str) -> bool {
+    content_type.split(';').next().map_or(false, |media_type| {
+        media_type.trim().eq_ignore_ascii_case("application/json")
+    })
+}
+
+/// Writes a JSON value as an XML element: objects become nested elements (one per field),
+/// arrays become a sequence of `XML_ITEM` elements, and `null`s become empty elements.
+fn write_xml(xml: &mut String, name: &str, value: &Value) {
+    let name = xml_name(name);
+    if value.is_null() {
+        xml.push_str(&format!("<{}/>", name));
+        return;
+    }
+    xml.push_str(&format!("<{}>", name));
+    match value {
+        Value::Null => {}
+        Value::Bool(b) => xml.push_str(&b.to_string()),
+        Value::Number(n) => xml.push_str(&n.to_string()),
+        Value::String(s) => xml.push_str(&escape_xml(s)),
+        Value::Array(items) => {
+            for item in items {
+                write_xml(xml, XML_ITEM, item);
+            }
+        }
+        Value::Object(fields) => {
+            for (field, value) in fields {
+                write_xml(xml, field, value);
+            }
+        }
+    }
+    xml.push_str(&format!("</{}>", name));
+}
+
+/// Makes a JSON field name a valid XML element name (i.e., replacing invalid characters w/
+/// `_`, and prefixing names that can't start an element w/ `_`).
+fn xml_name(name: &str) -> String {
+    let mut xml_name = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '_' | '-' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    if !xml_name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        || xml_name.to_ascii_lowercase().starts_with("xml")
+    {
+        xml_name.insert(0, '_');
+    }
+    xml_name
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+    use slight_http_api::Response;
+
+    use super::{negotiate, not_acceptable, serialize, Format};
+
+    const ALL: [Format; 3] = [Format::Json, Format::Xml, Format::MessagePack];
+
+    fn response(content_type: &str, body: &str) -> Response {
+        Response {
+            status: 200,
+            headers: Some(vec![("content-type".to_string(), content_type.to_string())]),
+            body: Some(body.as_bytes().to_vec()),
+        }
+    }
+
+    fn header<'a>(res: &'a Response, name: &str) -> Option<&'a str> {
+        res.headers
+            .as_ref()?
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn negotiate_test() {
+        assert_eq!(negotiate(&ALL, None), Some(Format::Json));
+        assert_eq!(negotiate(&ALL, Some("*/*")), Some(Format::Json));
+        assert_eq!(negotiate(&ALL, Some("text/xml")), Some(Format::Xml));
+        assert_eq!(
+            negotiate(&ALL, Some("application/x-msgpack")),
+            Some(Format::MessagePack)
+        );
+        assert_eq!(
+            negotiate(&ALL, Some("application/json;q=0.5, application/xml")),
+            Some(Format::Xml)
+        );
+        // the more specific range wins, even if it comes later
+        assert_eq!(
+            negotiate(&ALL, Some("application/*, application/json;q=0")),
+            Some(Format::Xml)
+        );
+        assert_eq!(
+            negotiate(&[Format::Json], Some("text/html, application/xml")),
+            None
+        );
+        assert_eq!(negotiate(&[], None), None);
+    }
+
+    #[test]
+    fn serialize_xml_test() {
+        let res = serialize(
+            response(
+                "application/json; charset=utf-8",
+                r#"{"name": "<slight>", "tags": ["a", "b"], "age": 1, "nothing": null}"#,
+            ),
+            Format::Xml,
+        );
+        assert_eq!(res.status, 200);
+        assert_eq!(header(&res, "content-type"), Some("application/xml"));
+        assert_eq!(header(&res, "vary"), Some("accept"));
+        assert_eq!(
+            String::from_utf8(res.body.unwrap()).unwrap(),
+            r#"<?xml version="1.0" encoding="UTF-8"?><response><age>1</age><name>&lt;slight&gt;</name><nothing/><tags><item>a</item><item>b</item></tags></response>"#
+        );
+    }
+
+    #[test]
+    fn serialize_msgpack_test() -> Result<()> {
+        let res = serialize(
+            response("application/json", r#"{"name": "slight"}"#),
+            Format::MessagePack,
+        );
+        assert_eq!(header(&res, "content-type"), Some("application/msgpack"));
+        let value: serde_json::Value = rmp_serde::from_slice(&res.body.unwrap())?;
+        assert_eq!(value["name"], "slight");
+        Ok(())
+    }
+
+    #[test]
+    fn leaves_other_responses_untouched() {
+        let res = serialize(response("text/plain", "hello"), Format::Xml);
+        assert_eq!(header(&res, "content-type"), Some("text/plain"));
+        assert_eq!(res.body.unwrap(), b"hello");
+
+        let res = serialize(response("application/json", "not json"), Format::Json);
+        assert_eq!(res.body.unwrap(), b"not json");
+    }
+
+    #[test]
+    fn fails_with_500() {
+        let res = serialize(response("application/json", "not json"), Format::Xml);
+        assert_eq!(res.status, 500);
+    }
+
+    #[test]
+    fn not_acceptable_test() {
+        let res = not_acceptable(&[Format::Json, Format::Xml]);
+        assert_eq!(res.status, 406);
+        assert_eq!(
+            res.body.unwrap(),
+            b"Not Acceptable: supported types are application/json, application/xml"
+        );
+    }
+
+    #[test]
+    fn parse_format_test() -> Result<()> {
+        assert_eq!(Format::parse("msgpack")?, Format::MessagePack);
+        assert!(Format::parse("yaml").is_err());
+        Ok(())
+    }
+}
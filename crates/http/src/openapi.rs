@@ -0,0 +1,834 @@
+use std::{collections::HashMap, fs, future::Future, path::Path};
+
+use anyhow::{bail, Context, Result};
+use hyper::{body::HttpBody, header, http::request::Parts, Body, StatusCode};
+use serde_json::Value;
+use tracing::log;
+use url::form_urlencoded;
+
+/// How deep schemas are followed (i.e., through `$ref`s, and combinators), so circular
+/// references can't loop forever.
+const MAX_DEPTH: usize = 64;
+
+/// The extension operations (or paths) can set to `false` to not be validated.
+const VALIDATE_EXTENSION: &str = "x-slight-validate";
+
+/// What schemas that can't be resolved are treated as (i.e., allowing anything).
+static ANYTHING: Value = Value::Null;
+
+/// An `OpenApi` spec (i.e., an OpenAPI 3 document) the requests to the http capability,
+/// and the responses of its guest are validated against.
+///
+/// Only what's needed to catch contract violations at the edge is validated: parameters,
+/// JSON bodies (w/ the `type`, `enum`, `required`, `properties`, `additionalProperties`,
+/// `items`, `nullable`, `allOf`, `anyOf`, `oneOf`, length, and range keywords), and the
+/// status of responses.
+#[derive(Clone, Debug)]
+pub struct OpenApi {
+    doc: Value,
+}
+
+impl OpenApi {
+    /// Loads a spec from a JSON, or (if it's named `.yaml`, or `.yml`) YAML file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read OpenAPI spec '{}'", path.display()))?;
+        let yaml = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("yaml" | "yml")
+        );
+        Self::parse(&contents, yaml)
+            .with_context(|| format!("invalid OpenAPI spec '{}'", path.display()))
+    }
+
+    pub fn parse(contents: &str, yaml: bool) -> Result<Self> {
+        let doc = if yaml {
+            from_yaml(serde_yaml::from_str(contents)?)
+        } else {
+            serde_json::from_str(contents)?
+        };
+        let version = match &doc["openapi"] {
+            Value::String(version) => version.clone(),
+            // e.g., an unquoted `openapi: 3.0` in YAML
+            Value::Number(version) => version.to_string(),
+            _ => bail!("not an OpenAPI 3 document (i.e., it has no `openapi` version)"),
+        };
+        if !version.starts_with("3.") {
+            bail!("unsupported OpenAPI version: '{}'", version);
+        }
+        if !doc["paths"].is_object() {
+            bail!("the spec has no `paths`");
+        }
+        Ok(Self { doc })
+    }
+
+    /// The operation of the spec a request is for, if the spec describes it, and it's to be
+    /// validated — paths w/ more literal segments win over templated ones (e.g., `/users/me`
+    /// over `/users/{id}`).
+    pub fn operation(&self, method: &str, path: &str) -> Option<Operation<'_>> {
+        let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+        let (_, item, path_params) = self.doc["paths"]
+            .as_object()?
+            .iter()
+            .filter_map(|(template, item)| {
+                let (literals, params) = match_template(template, &segments)?;
+                Some((literals, item, params))
+            })
+            .max_by_key(|(literals, _, _)| *literals)?;
+        let item = self.resolve(item);
+        let op = item.get(method.to_ascii_lowercase())?;
+        if item[VALIDATE_EXTENSION] == false || op[VALIDATE_EXTENSION] == false {
+            return None;
+        }
+        Some(Operation {
+            spec: self,
+            item,
+            op,
+            path_params,
+        })
+    }
+
+    /// Follows a schema's `$ref`s (i.e., to `#/components/...`).
+    fn resolve<'a>(&'a self, mut schema: &'a Value) -> &'a Value {
+        for _ in 0..MAX_DEPTH {
+            match schema["$ref"].as_str() {
+                Some(reference) => {
+                    schema = match reference
+                        .strip_prefix('#')
+                        .and_then(|p| self.doc.pointer(p))
+                    {
+                        Some(schema) => schema,
+                        None => {
+                            log::warn!("can't resolve '{}' in the OpenAPI spec", reference);
+                            return &ANYTHING;
+                        }
+                    }
+                }
+                None => return schema,
+            }
+        }
+        &ANYTHING
+    }
+
+    /// Checks a value against a schema, collecting violations — `at` is what the value is
+    /// (e.g., `body.name`, or `query parameter 'limit'`).
+    fn check(
+        &self,
+        schema: &Value,
+        value: &Value,
+        at: &str,
+        violations: &mut Vec<String>,
+        depth: usize,
+    ) {
+        if depth > MAX_DEPTH {
+            return;
+        }
+        let schema = self.resolve(schema);
+        for sub in schema["allOf"].as_array().into_iter().flatten() {
+            self.check(sub, value, at, violations, depth + 1);
+        }
+        if let Some(any_of) = schema["anyOf"].as_array() {
+            if !any_of.iter().any(|sub| self.conforms(sub, value, depth)) {
+                violations.push(format!("{} doesn't match any schema of `anyOf`", at));
+            }
+        }
+        if let Some(one_of) = schema["oneOf"].as_array() {
+            let matched = one_of
+                .iter()
+                .filter(|sub| self.conforms(sub, value, depth))
+                .count();
+            if matched != 1 {
+                violations.push(format!(
+                    "{} matches {} schemas of `oneOf` (instead of exactly one)",
+                    at, matched
+                ));
+            }
+        }
+
+        if value.is_null() && schema["nullable"] == true {
+            return;
+        }
+        if let Some(ty) = schema["type"].as_str() {
+            if !is_type(value, ty) {
+                violations.push(format!("{} should be of type {}", at, ty));
+                return;
+            }
+        }
+        if let Some(allowed) = schema["enum"].as_array() {
+            if !allowed.contains(value) {
+                violations.push(format!(
+                    "{} should be one of {}",
+                    at,
+                    Value::Array(allowed.clone())
+                ));
+            }
+        }
+
+        match value {
+            Value::String(s) => {
+                let len = s.chars().count() as u64;
+                if matches!(schema["minLength"].as_u64(), Some(min) if len < min) {
+                    violations.push(format!("{} is shorter than {}", at, schema["minLength"]));
+                }
+                if matches!(schema["maxLength"].as_u64(), Some(max) if len > max) {
+                    violations.push(format!("{} is longer than {}", at, schema["maxLength"]));
+                }
+            }
+            Value::Number(n) => {
+                let n = n.as_f64().unwrap_or_default();
+                if matches!(schema["minimum"].as_f64(), Some(min) if n < min) {
+                    violations.push(format!("{} is less than {}", at, schema["minimum"]));
+                }
+                if matches!(schema["maximum"].as_f64(), Some(max) if n > max) {
+                    violations.push(format!("{} is greater than {}", at, schema["maximum"]));
+                }
+            }
+            Value::Array(items) => {
+                let len = items.len() as u64;
+                if matches!(schema["minItems"].as_u64(), Some(min) if len < min) {
+                    violations.push(format!(
+                        "{} has fewer than {} items",
+                        at, schema["minItems"]
+                    ));
+                }
+                if matches!(schema["maxItems"].as_u64(), Some(max) if len > max) {
+                    violations.push(format!("{} has more than {} items", at, schema["maxItems"]));
+                }
+                if let Some(item_schema) = schema.get("items") {
+                    for (i, item) in items.iter().enumerate() {
+                        let at = format!("{}[{}]", at, i);
+                        self.check(item_schema, item, &at, violations, depth + 1);
+                    }
+                }
+            }
+            Value::Object(fields) => {
+                for required in schema["required"].as_array().into_iter().flatten() {
+                    if let Some(name) = required.as_str() {
+                        if !fields.contains_key(name) {
+                            violations.push(format!("{}.{} is required", at, name));
+                        }
+                    }
+                }
+                for (name, field) in fields {
+                    let at = format!("{}.{}", at, name);
+                    match (
+                        schema["properties"].get(name),
+                        &schema["additionalProperties"],
+                    ) {
+                        (Some(property), _) => {
+                            self.check(property, field, &at, violations, depth + 1)
+                        }
+                        (None, Value::Bool(false)) => {
+                            violations.push(format!("{} isn't allowed", at))
+                        }
+                        (None, additional @ Value::Object(_)) => {
+                            self.check(additional, field, &at, violations, depth + 1)
+                        }
+                        (None, _) => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn conforms(&self, schema: &Value, value: &Value, depth: usize) -> bool {
+        let mut violations = Vec::new();
+        self.check(schema, value, "", &mut violations, depth + 1);
+        violations.is_empty()
+    }
+}
+
+/// An `Operation` of an `OpenApi` spec (i.e., a method of one of its paths), w/ the path
+/// parameters of the request it was matched to.
+pub struct Operation<'a> {
+    spec: &'a OpenApi,
+    item: &'a Value,
+    op: &'a Value,
+    path_params: HashMap<String, String>,
+}
+
+impl<'a> Operation<'a> {
+    /// Checks a request's parameters, and (if it was buffered, rather than streamed to the
+    /// handler) body, returning what's wrong w/ them.
+    pub fn check_request(&self, parts: &Parts, body: Option<&[u8]>) -> Vec<String> {
+        let mut violations = Vec::new();
+        let query = parts
+            .uri
+            .query()
+            .map(|query| {
+                form_urlencoded::parse(query.as_bytes())
+                    .into_owned()
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        for param in self.parameters() {
+            let name = param["name"].as_str().unwrap_or_default();
+            let location = param["in"].as_str().unwrap_or_default();
+            let values: Vec<String> = match location {
+                "path" => self.path_params.get(name).cloned().into_iter().collect(),
+                "query" => query
+                    .iter()
+                    .filter(|(key, _)| key == name)
+                    .map(|(_, value)| value.clone())
+                    .collect(),
+                "header" => parts
+                    .headers
+                    .get_all(name)
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .map(str::to_string)
+                    .collect(),
+                // cookies aren't validated
+                _ => continue,
+            };
+            let at = format!("{} parameter '{}'", location, name);
+            if values.is_empty() {
+                if location == "path" || param["required"] == true {
+                    violations.push(format!("{} is required", at));
+                }
+                continue;
+            }
+            if let Some(schema) = param.get("schema") {
+                let schema = self.spec.resolve(schema);
+                let value = if schema["type"] == "array" {
+                    let items = self.spec.resolve(&schema["items"]);
+                    Value::Array(values.iter().map(|value| coerce(items, value)).collect())
+                } else {
+                    coerce(schema, &values[0])
+                };
+                self.spec.check(schema, &value, &at, &mut violations, 0);
+            }
+        }
+
+        let request_body = self.spec.resolve(&self.op["requestBody"]);
+        if let (true, Some(body)) = (request_body.is_object(), body) {
+            let content_type = parts
+                .headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|content_type| content_type.to_str().ok());
+            if body.is_empty() {
+                if request_body["required"] == true {
+                    violations.push("the body is required".to_string());
+                }
+            } else {
+                self.check_content(
+                    &request_body["content"],
+                    content_type,
+                    body,
+                    "body",
+                    &mut violations,
+                );
+            }
+        }
+        violations
+    }
+
+    /// Checks a response's status, and (if it was buffered, rather than streamed) body,
+    /// returning what's wrong w/ them.
+    pub fn check_response(
+        &self,
+        status: StatusCode,
+        content_type: Option<&str>,
+        body: Option<&[u8]>,
+    ) -> Vec<String> {
+        let mut violations = Vec::new();
+        let responses = match self.op["responses"].as_object() {
+            Some(responses) => responses,
+            None => return violations,
+        };
+        let response = [
+            status.as_str().to_string(),
+            format!("{}XX", status.as_u16() / 100),
+            "default".to_string(),
+        ]
+        .iter()
+        .find_map(|key| responses.get(key));
+        let response = match response {
+            Some(response) => self.spec.resolve(response),
+            None => {
+                violations.push(format!("the status {} isn't documented", status.as_u16()));
+                return violations;
+            }
+        };
+        if let Some(body) = body.filter(|body| !body.is_empty()) {
+            self.check_content(
+                &response["content"],
+                content_type,
+                body,
+                "the response body",
+                &mut violations,
+            );
+        }
+        violations
+    }
+
+    /// The parameters of the operation, and of its path (unless the operation overrides them).
+    fn parameters(&self) -> Vec<&'a Value> {
+        let (op, item) = (self.op, self.item);
+        let mut params: Vec<&'a Value> = Vec::new();
+        for param in [&op["parameters"], &item["parameters"]]
+            .into_iter()
+            .filter_map(Value::as_array)
+            .flatten()
+        {
+            let param = self.spec.resolve(param);
+            if !params
+                .iter()
+                .any(|p| p["name"] == param["name"] && p["in"] == param["in"])
+            {
+                params.push(param);
+            }
+        }
+        params
+    }
+
+    /// Checks a body against the media types a request body, or response can have.
+    fn check_content(
+        &self,
+        content: &Value,
+        content_type: Option<&str>,
+        body: &[u8],
+        at: &str,
+        violations: &mut Vec<String>,
+    ) {
+        let content = match content.as_object() {
+            Some(content) if !content.is_empty() => content,
+            _ => return,
+        };
+        let media_type = content_type
+            .map(|content_type| {
+                content_type
+                    .split(';')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_ascii_lowercase()
+            })
+            .unwrap_or_default();
+        let media = content.get(&media_type).or_else(|| {
+            content
+                .iter()
+                .find(|(declared, _)| media_type_matches(declared, &media_type))
+                .map(|(_, media)| media)
+        });
+        let media = match media {
+            Some(media) => media,
+            None => {
+                violations.push(format!(
+                    "the content type of {} should be one of {}",
+                    at,
+                    content.keys().cloned().collect::<Vec<_>>().join(", ")
+                ));
+                return;
+            }
+        };
+        if let (true, Some(schema)) = (is_json(&media_type), media.get("schema")) {
+            match serde_json::from_slice::<Value>(body) {
+                Ok(value) => self.spec.check(schema, &value, at, violations, 0),
+                Err(e) => violations.push(format!("{} isn't valid JSON: {}", at, e)),
+            }
+        }
+    }
+}
+
+/// Responds to a request w/ `respond`, as long as it conforms to its operation in the spec,
+/// or else w/ a 400 listing what's wrong w/ it — responses that don't conform to the spec
+/// are replaced w/ a 500, as the guest broke the API's contract.
+///
+/// The bodies of requests that are `streamed` to the handler aren't buffered to be validated,
+/// so only their parameters are.
+pub async fn enforce<Fut>(
+    spec: &OpenApi,
+    request: hyper::Request<Body>,
+    streamed: bool,
+    respond: impl FnOnce(hyper::Request<Body>) -> Fut,
+) -> Result<hyper::Response<Body>>
+where
+    Fut: Future<Output = Result<hyper::Response<Body>>>,
+{
+    let (method, path) = (request.method().clone(), request.uri().path().to_string());
+    let operation = match spec.operation(method.as_str(), &path) {
+        Some(operation) => operation,
+        None => return respond(request).await,
+    };
+
+    let (parts, body) = request.into_parts();
+    let (body, violations) = if streamed {
+        (body, operation.check_request(&parts, None))
+    } else {
+        let bytes = hyper::body::to_bytes(body).await?;
+        let violations = operation.check_request(&parts, Some(&bytes));
+        (Body::from(bytes), violations)
+    };
+    if !violations.is_empty() {
+        log::debug!(
+            "rejecting {} {}, as it doesn't conform to the OpenAPI spec: {}",
+            method,
+            path,
+            violations.join("; ")
+        );
+        return Ok(hyper::Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header(header::CONTENT_TYPE, "text/plain")
+            .body(Body::from(format!(
+                "the request doesn't conform to the API's spec:\n- {}\n",
+                violations.join("\n- ")
+            )))?);
+    }
+
+    let res = respond(hyper::Request::from_parts(parts, body)).await?;
+    let content_type = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .map(str::to_string);
+    // streamed bodies aren't buffered to be validated, so only their status is
+    let (res, violations) = if res.body().size_hint().exact().is_some() {
+        let (parts, body) = res.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        let violations =
+            operation.check_response(parts.status, content_type.as_deref(), Some(&body));
+        (
+            hyper::Response::from_parts(parts, Body::from(body)),
+            violations,
+        )
+    } else {
+        let violations = operation.check_response(res.status(), content_type.as_deref(), None);
+        (res, violations)
+    };
+    if !violations.is_empty() {
+        log::error!(
+            "the response to {} {} doesn't conform to the OpenAPI spec: {}",
+            method,
+            path,
+            violations.join("; ")
+        );
+        return Ok(hyper::Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from("Internal Server Error"))?);
+    }
+    Ok(res)
+}
+
+/// Converts a YAML document to JSON — YAML keys needn't be strings (e.g., statuses of
+/// responses often aren't quoted), so they're converted too.
+fn from_yaml(value: serde_yaml::Value) -> Value {
+    use serde_yaml::Value as Yaml;
+    match value {
+        Yaml::Null => Value::Null,
+        Yaml::Bool(b) => Value::from(b),
+        Yaml::Number(n) => n
+            .as_i64()
+            .map(Value::from)
+            .or_else(|| n.as_u64().map(Value::from))
+            .unwrap_or_else(|| Value::from(n.as_f64().unwrap_or_default())),
+        Yaml::String(s) => Value::from(s),
+        Yaml::Sequence(items) => items.into_iter().map(from_yaml).collect(),
+        Yaml::Mapping(mapping) => Value::Object(
+            mapping
+                .into_iter()
+                .map(|(key, value)| {
+                    let key = match key {
+                        Yaml::String(key) => key,
+                        Yaml::Number(key) => key.to_string(),
+                        Yaml::Bool(key) => key.to_string(),
+                        key => serde_yaml::to_string(&key)
+                            .unwrap_or_default()
+                            .trim()
+                            .to_string(),
+                    };
+                    (key, from_yaml(value))
+                })
+                .collect(),
+        ),
+        Yaml::Tagged(tagged) => from_yaml(tagged.value),
+    }
+}
+
+/// Matches a path's segments to a path template of the spec (e.g., `/users/{id}`), returning
+/// how many of its segments are literal, and the path parameters.
+fn match_template(template: &str, segments: &[&str]) -> Option<(usize, HashMap<String, String>)> {
+    let template = template.trim_matches('/').split('/').collect::<Vec<_>>();
+    if template.len() != segments.len() {
+        return None;
+    }
+    let mut literals = 0;
+    let mut params = HashMap::new();
+    for (t, s) in template.iter().zip(segments) {
+        match t.strip_prefix('{').and_then(|t| t.strip_suffix('}')) {
+            Some(name) if !s.is_empty() => {
+                params.insert(name.to_string(), s.to_string());
+            }
+            None if t == s => literals += 1,
+            _ => return None,
+        }
+    }
+    Some((literals, params))
+}
+
+/// Converts a parameter (which is always a string) to the type of its schema, so it can be
+/// checked against it — if it can't be, it's left a string, and fails the check.
+fn coerce(schema: &Value, value: &str) -> Value {
+    let coerced = match schema["type"].as_str() {
+        Some("integer") => value.parse::<i64>().ok().map(Value::from),
+        Some("number") => value.parse::<f64>().ok().map(Value::from),
+        Some("boolean") => value.parse::<bool>().ok().map(Value::from),
+        _ => None,
+    };
+    coerced.unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+fn is_type(value: &Value, ty: &str) -> bool {
+    match ty {
+        "integer" => {
+            value.is_i64()
+                || value.is_u64()
+                || matches!(value.as_f64(), Some(n) if n.fract() == 0.0)
+        }
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn is_json(media_type: &str) -> bool {
+    media_type == "application/json" || media_type.ends_with("+json")
+}
+
+/// Whether a media type matches a declared one, which may be a range (e.g., `application/*`).
+fn media_type_matches(declared: &str, media_type: &str) -> bool {
+    let declared = declared.split(';').next().unwrap_or_default().trim();
+    match declared.strip_suffix("/*") {
+        _ if declared == "*/*" => true,
+        Some(ty) => media_type.split('/').next() == Some(ty),
+        None => declared.eq_ignore_ascii_case(media_type),
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+    use hyper::{Body, StatusCode};
+
+    use super::{enforce, OpenApi};
+
+    const SPEC: &str = r##"{
+        "openapi": "3.0.3",
+        "info": { "title": "users", "version": "1" },
+        "paths": {
+            "/users/{id}": {
+                "parameters": [
+                    { "name": "id", "in": "path", "required": true, "schema": { "type": "integer" } }
+                ],
+                "get": {
+                    "parameters": [
+                        { "name": "fields", "in": "query", "schema": { "type": "array", "items": { "enum": ["name", "age"] } } }
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "a user",
+                            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/User" } } }
+                        },
+                        "404": { "description": "no such user" }
+                    }
+                },
+                "put": {
+                    "parameters": [
+                        { "name": "x-request-id", "in": "header", "required": true, "schema": { "type": "string", "minLength": 8 } }
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/User" } } }
+                    },
+                    "responses": { "2XX": { "description": "updated" } }
+                }
+            },
+            "/users/me": {
+                "get": { "x-slight-validate": false, "responses": { "200": { "description": "me" } } }
+            }
+        },
+        "components": {
+            "schemas": {
+                "User": {
+                    "type": "object",
+                    "required": ["name"],
+                    "additionalProperties": false,
+                    "properties": {
+                        "name": { "type": "string", "minLength": 1 },
+                        "age": { "type": "integer", "minimum": 0, "nullable": true }
+                    }
+                }
+            }
+        }
+    }"##;
+
+    fn check(spec: &OpenApi, request: hyper::Request<Body>, body: &str) -> Vec<String> {
+        let (parts, _) = request.into_parts();
+        let operation = spec
+            .operation(parts.method.as_str(), parts.uri.path())
+            .unwrap();
+        operation.check_request(&parts, Some(body.as_bytes()))
+    }
+
+    fn get(uri: &str) -> hyper::Request<Body> {
+        hyper::Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    fn put(uri: &str) -> hyper::Request<Body> {
+        hyper::Request::put(uri)
+            .header("content-type", "application/json")
+            .header("x-request-id", "request-1")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn operation_test() -> Result<()> {
+        let spec = OpenApi::parse(SPEC, false)?;
+        assert!(spec.operation("GET", "/users/42").is_some());
+        assert!(spec.operation("DELETE", "/users/42").is_none());
+        assert!(spec.operation("GET", "/posts").is_none());
+        // the literal path wins, and it opted out of validation
+        assert!(spec.operation("GET", "/users/me").is_none());
+
+        assert!(OpenApi::parse(r#"{ "swagger": "2.0", "paths": {} }"#, false).is_err());
+        let yaml = "openapi: 3.0\npaths:\n  /users/{id}:\n    get:\n      responses:\n        200:\n          description: a user\n";
+        let spec = OpenApi::parse(yaml, true)?;
+        let get = spec.operation("GET", "/users/42").unwrap();
+        assert!(get.check_response(StatusCode::OK, None, None).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn check_parameters_test() -> Result<()> {
+        let spec = OpenApi::parse(SPEC, false)?;
+        assert!(check(&spec, get("/users/42?fields=name&fields=age"), "").is_empty());
+        // the operation's parameters come before its path's
+        assert_eq!(
+            check(&spec, get("/users/abc?fields=email"), ""),
+            vec![
+                "query parameter 'fields'[0] should be one of [\"name\",\"age\"]",
+                "path parameter 'id' should be of type integer",
+            ]
+        );
+
+        let request = hyper::Request::put("/users/42")
+            .header("content-type", "application/json")
+            .header("x-request-id", "short")
+            .body(Body::empty())?;
+        assert_eq!(
+            check(&spec, request, r#"{"name":"ann"}"#),
+            vec!["header parameter 'x-request-id' is shorter than 8"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_body_test() -> Result<()> {
+        let spec = OpenApi::parse(SPEC, false)?;
+        assert!(check(&spec, put("/users/42"), r#"{"name":"ann","age":null}"#).is_empty());
+        assert_eq!(
+            check(&spec, put("/users/42"), ""),
+            vec!["the body is required"]
+        );
+        assert_eq!(
+            check(
+                &spec,
+                put("/users/42"),
+                r#"{"age":-1,"email":"ann@example.com"}"#
+            ),
+            vec![
+                "body.name is required",
+                "body.age is less than 0",
+                "body.email isn't allowed",
+            ]
+        );
+        assert_eq!(check(&spec, put("/users/42"), "{").len(), 1);
+
+        let request = hyper::Request::put("/users/42")
+            .header("content-type", "text/plain")
+            .header("x-request-id", "request-1")
+            .body(Body::empty())?;
+        assert_eq!(
+            check(&spec, request, "ann"),
+            vec!["the content type of body should be one of application/json"]
+        );
+
+        // a body streamed to the handler isn't validated, not even if it's required
+        let (parts, _) = put("/users/42").into_parts();
+        let operation = spec.operation("PUT", "/users/42").unwrap();
+        assert!(operation.check_request(&parts, None).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn check_response_test() -> Result<()> {
+        let spec = OpenApi::parse(SPEC, false)?;
+        let get = spec.operation("GET", "/users/42").unwrap();
+        let json = Some("application/json");
+        assert!(get
+            .check_response(StatusCode::OK, json, Some(br#"{"name":"ann"}"#))
+            .is_empty());
+        assert!(get
+            .check_response(StatusCode::NOT_FOUND, None, Some(b""))
+            .is_empty());
+        assert_eq!(
+            get.check_response(StatusCode::OK, json, Some(br#"{"name":""}"#)),
+            vec!["the response body.name is shorter than 1"]
+        );
+        assert_eq!(
+            get.check_response(StatusCode::CREATED, json, None),
+            vec!["the status 201 isn't documented"]
+        );
+
+        // status ranges match
+        let put = spec.operation("PUT", "/users/42").unwrap();
+        assert!(put
+            .check_response(StatusCode::NO_CONTENT, None, None)
+            .is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn enforce_test() -> Result<()> {
+        let spec = OpenApi::parse(SPEC, false)?;
+        let respond = |body: &'static str| {
+            move |_: hyper::Request<Body>| async move {
+                Ok::<_, anyhow::Error>(
+                    hyper::Response::builder()
+                        .header("content-type", "application/json")
+                        .body(Body::from(body))?,
+                )
+            }
+        };
+
+        let res = enforce(&spec, get("/users/42"), false, respond(r#"{"name":"ann"}"#)).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            hyper::body::to_bytes(res.into_body()).await?,
+            r#"{"name":"ann"}"#
+        );
+
+        let res = enforce(
+            &spec,
+            get("/users/abc"),
+            false,
+            respond(r#"{"name":"ann"}"#),
+        )
+        .await?;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let res = enforce(&spec, get("/users/42"), false, respond(r#"{"age":1}"#)).await?;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        // requests the spec doesn't describe are left to the guest
+        let res = enforce(&spec, get("/posts"), false, respond("not json")).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::Result;
+use hyper::{body::HttpBody as _, header, Body, StatusCode};
+use routerify::ext::RequestExt;
+
+/// How big the bodies of the requests the guest handles can be, unless the capability says
+/// otherwise (see `HttpSettings::max_payload_bytes`).
+pub const DEFAULT_MAX_PAYLOAD_BYTES: usize = 16 * 1024 * 1024;
+
+/// The max size of the bodies of requests, as it's passed to their handlers.
+#[derive(Clone, Copy, Debug)]
+pub struct MaxPayload(pub usize);
+
+/// Buffers the body of a request before the guest is invoked for it (i.e., the way it's handed
+/// to the guest anyway), or `None` if it's bigger than its `MaxPayload`, which is then
+/// answered w/ `too_large` — the guest never sees it.
+///
+/// Streamed bodies are read by the guest as they arrive, so they're only checked by their
+/// declared length.
+pub async fn limited(
+    request: hyper::Request<Body>,
+    streamed: bool,
+) -> Result<Option<hyper::Request<Body>>> {
+    let max = request
+        .data::<MaxPayload>()
+        .map_or(DEFAULT_MAX_PAYLOAD_BYTES, |max| max.0);
+    let declared = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse::<u64>().ok());
+    if declared.is_some_and(|length| length > max as u64) {
+        return Ok(None);
+    }
+    if streamed {
+        return Ok(Some(request));
+    }
+    let (parts, body) = request.into_parts();
+    Ok(read(body, max)
+        .await?
+        .map(|bytes| hyper::Request::from_parts(parts, Body::from(bytes))))
+}
+
+/// Reads a body, or `None` if it's bigger than `max` bytes (which is found out w/o reading it
+/// whole).
+pub async fn read(mut body: Body, max: usize) -> Result<Option<Vec<u8>>> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > max {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Some(bytes))
+}
+
+/// The response to a request whose body is bigger than its `MaxPayload`.
+pub fn too_large() -> Result<hyper::Response<Body>> {
+    Ok(hyper::Response::builder()
+        .status(StatusCode::PAYLOAD_TOO_LARGE)
+        .body(Body::from("Payload Too Large"))?)
+}
+
+#[cfg(test)]
+mod unittests {
+    use hyper::{header, Body};
+
+    use super::{limited, read, DEFAULT_MAX_PAYLOAD_BYTES};
+
+    #[tokio::test]
+    async fn read_test() {
+        let body = read(Body::from(vec![b'x'; 1024]), 1024).await.unwrap();
+        assert_eq!(body.map(|body| body.len()), Some(1024));
+
+        let too_large = Body::from(vec![b'x'; 1025]);
+        assert!(read(too_large, 1024).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn limited_test() {
+        let request = |length: usize, declared: Option<usize>| {
+            let mut request = hyper::Request::builder();
+            if let Some(declared) = declared {
+                request = request.header(header::CONTENT_LENGTH, declared);
+            }
+            request.body(Body::from(vec![b'x'; length])).unwrap()
+        };
+        let small = limited(request(16, Some(16)), false)
+            .await
+            .unwrap()
+            .unwrap();
+        let body = hyper::body::to_bytes(small.into_body()).await.unwrap();
+        assert_eq!(body.len(), 16);
+
+        // past the default max by its declared length, or by what's read (e.g., if it's chunked)
+        let declared = request(0, Some(DEFAULT_MAX_PAYLOAD_BYTES + 1));
+        assert!(limited(declared, true).await.unwrap().is_none());
+        let undeclared = request(DEFAULT_MAX_PAYLOAD_BYTES + 1, None);
+        assert!(limited(undeclared, false).await.unwrap().is_none());
+    }
+}
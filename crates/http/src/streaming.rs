@@ -0,0 +1,292 @@
+use std::cell::RefCell;
+
+use anyhow::{bail, Context, Result};
+use futures::executor::block_on;
+use hyper::{
+    body::{Bytes, HttpBody, Sender},
+    header::{HeaderName, HeaderValue},
+    Body, StatusCode,
+};
+use tokio::sync::oneshot;
+use tracing::log;
+
+thread_local! {
+    /// The response of the request whose handler is running on this thread (see `run`).
+    static RESPONSE: RefCell<Option<Streaming>> = const { RefCell::new(None) };
+    /// The body of the request whose handler is running on this thread, if it's streamed to the
+    /// handler (see `receive`).
+    static REQUEST_BODY: RefCell<Option<Body>> = const { RefCell::new(None) };
+}
+
+/// Where a handler's response stands.
+enum Streaming {
+    /// It hasn't started streaming, so its head can still be sent w/ `head`.
+    NotStarted(oneshot::Sender<hyper::Response<Body>>),
+    /// Its' head was sent, and its body is written through `body`, until it's closed.
+    Started(Option<Sender>),
+}
+
+/// What a guest's handler responded w/.
+pub enum Outcome<T> {
+    /// the response it returned, whose body was buffered
+    Buffered(T),
+    /// the response it streams, whose body is written as the handler goes on
+    Streamed(hyper::Response<Body>),
+}
+
+/// Runs `invoke` (i.e., a guest's handler) on a thread where it can block, so that, if the
+/// handler starts streaming its response (see `open`), the response is sent to the client
+/// right away, while the handler keeps writing its body.
+///
+/// If the handler fails after it started streaming, the body is aborted, so the client can
+/// tell the response is incomplete.
+pub async fn run<T: Send + 'static>(
+    invoke: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<Outcome<T>> {
+    let (head, streamed) = oneshot::channel();
+    let handler = tokio::task::spawn_blocking(move || {
+        RESPONSE.with(|response| *response.borrow_mut() = Some(Streaming::NotStarted(head)));
+        let res = invoke();
+        let response = RESPONSE.with(|response| response.borrow_mut().take());
+        match (&res, response) {
+            (Err(e), Some(Streaming::Started(Some(body)))) => {
+                log::error!("the handler failed while streaming its response: {:#}", e);
+                body.abort();
+            }
+            (Err(e), Some(Streaming::Started(None))) => {
+                log::error!("the handler failed after streaming its response: {:#}", e);
+            }
+            // the body is finished when `body` is dropped
+            _ => {}
+        }
+        res
+    });
+
+    match streamed.await {
+        Ok(streamed) => Ok(Outcome::Streamed(streamed)),
+        // the handler returned w/o streaming
+        Err(_) => Ok(Outcome::Buffered(handler.await??)),
+    }
+}
+
+/// Starts streaming the response of the handler running on this thread.
+pub fn open(status: u16, headers: &[(&str, &str)]) -> Result<()> {
+    let mut response = hyper::Response::builder().status(StatusCode::from_u16(status)?);
+    for (name, value) in headers {
+        response = response.header(HeaderName::try_from(*name)?, HeaderValue::try_from(*value)?);
+    }
+    let (body, receiver) = Body::channel();
+    let response = response.body(receiver)?;
+
+    RESPONSE.with(|streaming| {
+        let mut streaming = streaming.borrow_mut();
+        match streaming.take() {
+            Some(Streaming::NotStarted(head)) => {
+                // the request was dropped (e.g., the client disconnected) if the head can't be sent
+                head.send(response)
+                    .map_err(|_| anyhow::anyhow!("the client disconnected"))?;
+                *streaming = Some(Streaming::Started(Some(body)));
+                Ok(())
+            }
+            Some(started) => {
+                *streaming = Some(started);
+                bail!("the response is already being streamed")
+            }
+            None => bail!("responses can only be streamed while handling a request"),
+        }
+    })
+}
+
+/// Writes a chunk of the streamed response, waiting for the client to take it.
+///
+/// It fails if the client disconnected, so the handler can stop producing the response.
+pub fn write(chunk: &[u8]) -> Result<()> {
+    RESPONSE.with(|streaming| match &mut *streaming.borrow_mut() {
+        Some(Streaming::Started(Some(body))) => {
+            block_on(body.send_data(Bytes::copy_from_slice(chunk)))
+                .with_context(|| "the client disconnected")
+        }
+        Some(Streaming::Started(None)) => bail!("the response stream is closed"),
+        _ => bail!("the response isn't being streamed (see `response-stream::open`)"),
+    })
+}
+
+/// Finishes the streamed response.
+pub fn close() -> Result<()> {
+    RESPONSE.with(|streaming| match &mut *streaming.borrow_mut() {
+        Some(Streaming::Started(body)) => {
+            body.take();
+            Ok(())
+        }
+        _ => bail!("the response isn't being streamed (see `response-stream::open`)"),
+    })
+}
+
+/// Streams the body of a request to the handler running on this thread (i.e., it reads it w/
+/// `read`, rather than having it buffered), until the returned `Receiving` is dropped.
+pub fn receive(body: Body) -> Receiving {
+    REQUEST_BODY.with(|request_body| *request_body.borrow_mut() = Some(body));
+    Receiving
+}
+
+/// Whether the body of a request is being streamed to the handler running on this thread — it
+/// drops the body (if the handler didn't read it all) once it's dropped.
+pub struct Receiving;
+
+impl Drop for Receiving {
+    fn drop(&mut self) {
+        REQUEST_BODY.with(|request_body| request_body.borrow_mut().take());
+    }
+}
+
+/// Checks that the body of the request being handled on this thread is streamed to it.
+pub fn open_body() -> Result<()> {
+    REQUEST_BODY.with(|request_body| match &*request_body.borrow() {
+        Some(_) => Ok(()),
+        None => bail!(
+            "the request's body isn't streamed (see `router::stream-body`), so it's in its `body`"
+        ),
+    })
+}
+
+/// Reads the next chunk of the streamed request body, waiting for the client to send it, or
+/// `None` once it's all read.
+///
+/// As the body is only read from the connection as fast as the handler reads it, a handler
+/// that's slower than the client holds it back (i.e., w/ TCP's flow control). It fails if the
+/// client disconnected mid-upload.
+pub fn read() -> Result<Option<Vec<u8>>> {
+    REQUEST_BODY.with(|request_body| match &mut *request_body.borrow_mut() {
+        Some(body) => match block_on(body.data()) {
+            Some(Ok(chunk)) => Ok(Some(chunk.to_vec())),
+            Some(Err(e)) => Err(e).with_context(|| "the client disconnected mid-upload"),
+            None => Ok(None),
+        },
+        None => bail!("the request's body isn't streamed (see `router::stream-body`)"),
+    })
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::{bail, Result};
+    use hyper::{body::HttpBody, Body};
+
+    use super::{close, open, open_body, read, receive, run, write, Outcome};
+
+    #[tokio::test]
+    async fn buffered_test() -> Result<()> {
+        match run(|| Ok("buffered")).await? {
+            Outcome::Buffered(res) => assert_eq!(res, "buffered"),
+            Outcome::Streamed(_) => panic!("the response wasn't streamed"),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn streamed_test() -> Result<()> {
+        let outcome = run(|| {
+            open(201, &[("content-type", "text/plain")])?;
+            assert!(open(200, &[]).is_err());
+            for chunk in ["hello", ", ", "world"] {
+                write(chunk.as_bytes())?;
+            }
+            close()?;
+            assert!(write(b"!").is_err());
+            Ok(())
+        })
+        .await?;
+        let res = match outcome {
+            Outcome::Streamed(res) => res,
+            Outcome::Buffered(_) => panic!("the response was streamed"),
+        };
+        assert_eq!(res.status(), 201);
+        assert_eq!(res.headers()["content-type"], "text/plain");
+        let body = hyper::body::to_bytes(res.into_body()).await?;
+        assert_eq!(body, "hello, world");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn aborted_test() -> Result<()> {
+        let outcome = run(|| -> Result<()> {
+            open(200, &[])?;
+            write(b"partial")?;
+            bail!("the handler crashed")
+        })
+        .await?;
+        let mut body = match outcome {
+            Outcome::Streamed(res) => res.into_body(),
+            Outcome::Buffered(_) => panic!("the response was streamed"),
+        };
+        assert_eq!(body.data().await.unwrap()?, "partial");
+        assert!(body.data().await.unwrap().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_disconnected_test() -> Result<()> {
+        let (disconnect, disconnected) = std::sync::mpsc::channel::<()>();
+        let (written, write_failed) = std::sync::mpsc::channel();
+        let outcome = run(move || {
+            open(200, &[])?;
+            disconnected.recv()?;
+            written.send(write(b"lost").is_err())?;
+            Ok(())
+        })
+        .await?;
+        match outcome {
+            Outcome::Streamed(res) => drop(res),
+            Outcome::Buffered(_) => panic!("the response was streamed"),
+        }
+        disconnect.send(())?;
+        assert!(tokio::task::spawn_blocking(move || write_failed.recv()).await??);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn request_body_test() -> Result<()> {
+        let (mut upload, body) = Body::channel();
+        let handler = tokio::task::spawn_blocking(move || -> Result<Vec<Vec<u8>>> {
+            let _receiving = receive(body);
+            open_body()?;
+            let mut chunks = Vec::new();
+            while let Some(chunk) = read()? {
+                chunks.push(chunk);
+            }
+            assert!(read()?.is_none());
+            Ok(chunks)
+        });
+        upload.send_data("up".into()).await?;
+        upload.send_data("load".into()).await?;
+        drop(upload);
+        assert_eq!(handler.await??, vec![b"up".to_vec(), b"load".to_vec()]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn upload_interrupted_test() -> Result<()> {
+        let (mut upload, body) = Body::channel();
+        let handler = tokio::task::spawn_blocking(move || {
+            let _receiving = receive(body);
+            loop {
+                match read() {
+                    Ok(Some(_)) => continue,
+                    Ok(None) => return false,
+                    Err(_) => return true,
+                }
+            }
+        });
+        upload.send_data("partial".into()).await?;
+        upload.abort();
+        assert!(handler.await?);
+        Ok(())
+    }
+
+    #[test]
+    fn not_handling_test() {
+        assert!(write(b"chunk").is_err());
+        assert!(close().is_err());
+        assert!(open_body().is_err());
+        assert!(read().is_err());
+    }
+}
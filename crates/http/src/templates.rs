@@ -0,0 +1,177 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use handlebars::Handlebars;
+use slight_http_api::Response;
+use tracing::log;
+
+/// The response header a guest sets to the name of the template it wants
+/// its response to be rendered w/ (e.g., `x-slight-template: index.hbs`).
+///
+/// The body of such response is the JSON data context of the template.
+pub const TEMPLATE_HEADER: &str = "x-slight-template";
+
+/// `Templates` renders guest responses w/ Handlebars templates on the host.
+///
+/// Templates can only be loaded from the `dir` declared for the http capability
+/// in the slightfile — the guest only ever gets to pick a template name within it.
+#[derive(Debug, Default)]
+pub struct Templates {
+    dir: Option<PathBuf>,
+}
+
+impl Templates {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Self { dir }
+    }
+
+    /// Renders a response if the guest asked for it (i.e., by setting `TEMPLATE_HEADER`),
+    /// or returns it untouched, otherwise.
+    ///
+    /// Failing to render a template yields a 500 response, and the reason is logged.
+    pub fn render(&self, mut res: Response) -> Response {
+        let name = match take_header(&mut res, TEMPLATE_HEADER) {
+            Some(name) => name,
+            None => return res,
+        };
+        let data = res.body.take().unwrap_or_default();
+        match self.render_template(&name, &data) {
+            Ok(html) => {
+                res.headers.get_or_insert_with(Vec::new).push((
+                    "content-type".to_string(),
+                    "text/html; charset=utf-8".to_string(),
+                ));
+                res.body = Some(html.into_bytes());
+                res
+            }
+            Err(e) => {
+                log::error!("failed to render template '{}': {:#}", name, e);
+                Response {
+                    status: 500,
+                    headers: None,
+                    body: Some(b"failed to render template".to_vec()),
+                }
+            }
+        }
+    }
+
+    fn render_template(&self, name: &str, data: &[u8]) -> Result<String> {
+        let dir = self
+            .dir
+            .as_ref()
+            .with_context(|| "no templates directory is configured for the http capability")?;
+        let path = template_path(dir, name)?;
+        let template = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read template '{}'", path.display()))?;
+        let data: serde_json::Value = if data.is_empty() {
+            serde_json::Value::Null
+        } else {
+            serde_json::from_slice(data)
+                .with_context(|| "the response body is not a valid JSON data context")?
+        };
+        Ok(Handlebars::new().render_template(&template, &data)?)
+    }
+}
+
+/// Resolves a template name to a path, making sure it can't escape the templates directory.
+fn template_path(dir: &Path, name: &str) -> Result<PathBuf> {
+    let relative = Path::new(name);
+    if name.is_empty()
+        || relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        bail!("invalid template name '{}'", name);
+    }
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("failed to open templates directory '{}'", dir.display()))?;
+    let path = dir
+        .join(relative)
+        .canonicalize()
+        .with_context(|| format!("template '{}' does not exist", name))?;
+    // symlinks could still point outside of the directory
+    if !path.starts_with(&dir) {
+        bail!("template '{}' is outside of the templates directory", name);
+    }
+    Ok(path)
+}
+
+/// Removes a header from a response, returning its value.
+fn take_header(res: &mut Response, name: &str) -> Option<String> {
+    let headers = res.headers.as_mut()?;
+    let i = headers
+        .iter()
+        .position(|(k, _)| k.eq_ignore_ascii_case(name))?;
+    Some(headers.remove(i).1)
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::fs;
+
+    use anyhow::Result;
+    use slight_http_api::Response;
+    use tempdir::TempDir;
+
+    use super::{Templates, TEMPLATE_HEADER};
+
+    fn response(template: Option<&str>, body: &str) -> Response {
+        Response {
+            status: 200,
+            headers: template.map(|t| vec![(TEMPLATE_HEADER.to_string(), t.to_string())]),
+            body: Some(body.as_bytes().to_vec()),
+        }
+    }
+
+    fn templates() -> Result<(TempDir, Templates)> {
+        let dir = TempDir::new("templates")?;
+        fs::write(dir.path().join("hello.hbs"), "<p>Hello, {{name}}!</p>")?;
+        let templates = Templates::new(Some(dir.path().to_path_buf()));
+        Ok((dir, templates))
+    }
+
+    #[test]
+    fn renders_requested_template() -> Result<()> {
+        let (_dir, templates) = templates()?;
+        let res = templates.render(response(Some("hello.hbs"), r#"{"name": "slight"}"#));
+        assert_eq!(res.status, 200);
+        assert_eq!(res.body.unwrap(), b"<p>Hello, slight!</p>");
+        let headers = res.headers.unwrap();
+        assert!(!headers.iter().any(|(k, _)| k == TEMPLATE_HEADER));
+        assert!(headers
+            .iter()
+            .any(|(k, v)| k == "content-type" && v.starts_with("text/html")));
+        Ok(())
+    }
+
+    #[test]
+    fn leaves_other_responses_untouched() -> Result<()> {
+        let (_dir, templates) = templates()?;
+        let res = templates.render(response(None, "{{name}}"));
+        assert_eq!(res.status, 200);
+        assert_eq!(res.body.unwrap(), b"{{name}}");
+        Ok(())
+    }
+
+    #[test]
+    fn fails_with_500() -> Result<()> {
+        let (_dir, templates) = templates()?;
+        for (template, body) in [
+            ("missing.hbs", "{}"),
+            ("../hello.hbs", "{}"),
+            ("/etc/passwd", "{}"),
+            ("hello.hbs", "not json"),
+        ] {
+            let res = templates.render(response(Some(template), body));
+            assert_eq!(res.status, 500, "template '{}' didn't fail", template);
+        }
+
+        let no_dir = Templates::default();
+        assert_eq!(no_dir.render(response(Some("hello.hbs"), "{}")).status, 500);
+        Ok(())
+    }
+}
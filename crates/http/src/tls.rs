@@ -0,0 +1,298 @@
+use std::{
+    fmt,
+    fs::File,
+    io::BufReader,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use anyhow::{anyhow, bail, Result};
+use hyper::{server::conn::Http, service::Service, Body, Request};
+use routerify::{RequestServiceBuilder, Router};
+use tokio::{net::TcpListener, sync::mpsc::UnboundedReceiver};
+use tokio_rustls::{
+    rustls::{
+        server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient},
+        Certificate, PrivateKey, RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+use tracing::log;
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName};
+
+/// Whether clients must present a certificate signed by the client CA, or may present none
+/// (e.g., to fall back to another kind of authentication in the guest).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientAuth {
+    Required,
+    Optional,
+}
+
+impl ClientAuth {
+    pub fn parse(client_auth: &str) -> Result<Self> {
+        match client_auth {
+            "required" => Ok(Self::Required),
+            "optional" => Ok(Self::Optional),
+            _ => bail!(
+                "invalid tls_client_auth: '{}' (expected 'required', or 'optional')",
+                client_auth
+            ),
+        }
+    }
+}
+
+/// The settings of an http server that terminates TLS, and, if it has a client CA, verifies
+/// the certificates of its clients against it (i.e., mutual TLS).
+#[derive(Clone)]
+pub struct TlsSettings {
+    config: Arc<ServerConfig>,
+}
+
+impl fmt::Debug for TlsSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TlsSettings")
+    }
+}
+
+impl TlsSettings {
+    /// Loads the server's certificate chain, and private key (PEM), and the client CA bundle
+    /// (PEM), if any — client certificates are `Required` by default, once there's a CA.
+    pub fn new(
+        cert: &Path,
+        key: &Path,
+        client_ca: Option<&Path>,
+        client_auth: Option<ClientAuth>,
+    ) -> Result<Self> {
+        if client_ca.is_none() && client_auth.is_some() {
+            bail!(
+                "invalid tls_client_auth: client certificates are only verified w/ a tls_client_ca"
+            );
+        }
+        let certs = load_certs(cert)?;
+        let key = load_key(key)?;
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match client_ca {
+            Some(client_ca) => {
+                let mut roots = RootCertStore::empty();
+                for cert in load_certs(client_ca)? {
+                    roots.add(&cert).map_err(|e| {
+                        anyhow!("invalid tls_client_ca {}: {}", client_ca.display(), e)
+                    })?;
+                }
+                let verifier = match client_auth.unwrap_or(ClientAuth::Required) {
+                    ClientAuth::Required => AllowAnyAuthenticatedClient::new(roots),
+                    ClientAuth::Optional => AllowAnyAnonymousOrAuthenticatedClient::new(roots),
+                };
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder
+            .with_single_cert(certs, key)
+            .map_err(|e| anyhow!("invalid tls_cert, or tls_key: {}", e))?;
+        Ok(Self {
+            config: Arc::new(config),
+        })
+    }
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(
+        File::open(path).map_err(|e| anyhow!("failed to open {}: {}", path.display(), e))?,
+    );
+    let certs = rustls_pemfile::certs(&mut reader)
+        .map_err(|e| anyhow!("failed to read certificates from {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        bail!("no certificates found in {}", path.display());
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_key(path: &Path) -> Result<PrivateKey> {
+    let mut reader = BufReader::new(
+        File::open(path).map_err(|e| anyhow!("failed to open {}: {}", path.display(), e))?,
+    );
+    for item in rustls_pemfile::read_all(&mut reader).map_err(|e| {
+        anyhow!(
+            "failed to read the private key from {}: {}",
+            path.display(),
+            e
+        )
+    })? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => continue,
+        }
+    }
+    bail!("no private key found in {}", path.display())
+}
+
+/// The identity of a client that authenticated w/ a certificate the client CA verified, which
+/// is handed to the guest w/ each of its requests.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// the distinguished name of the certificate's subject (e.g., `CN=billing, O=acme`)
+    pub subject: String,
+    /// the certificate's subject alternative names, w/ their kind as a prefix (e.g.,
+    /// `DNS:billing.internal`, `URI:spiffe://acme/billing`, `email:ops@acme.com`, or
+    /// `IP:10.0.0.7`)
+    pub sans: Vec<String>,
+}
+
+impl ClientIdentity {
+    fn parse(der: &[u8]) -> Result<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der)
+            .map_err(|e| anyhow!("failed to parse the client certificate: {}", e))?;
+        Ok(Self {
+            subject: cert.subject().to_string(),
+            sans: sans(&cert),
+        })
+    }
+}
+
+fn sans(cert: &X509Certificate) -> Vec<String> {
+    let names = match cert.subject_alternative_name() {
+        Ok(Some(extension)) => &extension.value.general_names,
+        _ => return Vec::new(),
+    };
+    names
+        .iter()
+        .filter_map(|name| match name {
+            GeneralName::DNSName(dns) => Some(format!("DNS:{}", dns)),
+            GeneralName::URI(uri) => Some(format!("URI:{}", uri)),
+            GeneralName::RFC822Name(email) => Some(format!("email:{}", email)),
+            GeneralName::IPAddress(ip) => ip_name(ip),
+            _ => None,
+        })
+        .collect()
+}
+
+fn ip_name(ip: &[u8]) -> Option<String> {
+    let ip = match ip.len() {
+        4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(ip).ok()?)),
+        16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(ip).ok()?)),
+        _ => return None,
+    };
+    Some(format!("IP:{}", ip))
+}
+
+/// Inserts the identity of the connection's client (if it presented a certificate) into the
+/// extensions of each of its requests, where `invoke_guest` finds it.
+struct WithIdentity<S> {
+    inner: S,
+    identity: Option<ClientIdentity>,
+}
+
+impl<S: Service<Request<Body>>> Service<Request<Body>> for WithIdentity<S> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+        if let Some(identity) = &self.identity {
+            request.extensions_mut().insert(identity.clone());
+        }
+        self.inner.call(request)
+    }
+}
+
+/// Serves `router` at `addr` over TLS until a shutdown signal arrives on `rx` (or Ctrl-C),
+/// which stops accepting connections — the ones already accepted are served to completion.
+///
+/// Handshakes that fail (e.g., as the client's certificate isn't signed by the client CA, or
+/// it presented none, while they're required) reject the connection before any request is
+/// read, and are logged w/ the client's address.
+pub(crate) async fn serve(
+    addr: SocketAddr,
+    settings: &TlsSettings,
+    router: Router<Body, anyhow::Error>,
+    rx: UnboundedReceiver<()>,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    let acceptor = TlsAcceptor::from(settings.config.clone());
+    let mut builder = RequestServiceBuilder::new(router).map_err(|e| anyhow!("{}", e))?;
+    tokio::pin! {
+        let shutdown = crate::shutdown_signal(rx);
+    }
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::warn!("failed to accept a connection: {}", e);
+                    continue;
+                }
+            },
+            _ = &mut shutdown => return Ok(()),
+        };
+        let service = builder.build(peer);
+        let acceptor = acceptor.clone();
+        tokio::task::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("rejected a TLS connection from {}: {}", peer, e);
+                    return;
+                }
+            };
+            let identity = match stream.get_ref().1.peer_certificates() {
+                Some([cert, ..]) => match ClientIdentity::parse(&cert.0) {
+                    Ok(identity) => Some(identity),
+                    Err(e) => {
+                        log::warn!("rejected a TLS connection from {}: {}", peer, e);
+                        return;
+                    }
+                },
+                _ => None,
+            };
+            let service = WithIdentity {
+                inner: service,
+                identity,
+            };
+            if let Err(e) = Http::new().serve_connection(stream, service).await {
+                log::debug!("connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::path::Path;
+
+    use super::{ip_name, ClientAuth, TlsSettings};
+
+    #[test]
+    fn client_auth_test() {
+        assert_eq!(ClientAuth::parse("required").unwrap(), ClientAuth::Required);
+        assert_eq!(ClientAuth::parse("optional").unwrap(), ClientAuth::Optional);
+        assert!(ClientAuth::parse("none").is_err());
+
+        // client certificates can't be required, or optional w/o a CA to verify them against
+        let e = TlsSettings::new(
+            Path::new("cert.pem"),
+            Path::new("key.pem"),
+            None,
+            Some(ClientAuth::Optional),
+        )
+        .unwrap_err();
+        assert!(e.to_string().contains("tls_client_ca"));
+    }
+
+    #[test]
+    fn ip_name_test() {
+        assert_eq!(ip_name(&[10, 0, 0, 7]).as_deref(), Some("IP:10.0.0.7"));
+        let mut v6 = [0; 16];
+        v6[15] = 1;
+        assert_eq!(ip_name(&v6).as_deref(), Some("IP:::1"));
+        assert_eq!(ip_name(&[10, 0]), None);
+    }
+}
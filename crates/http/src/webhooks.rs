@@ -0,0 +1,61 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use hyper::{header, Body, StatusCode};
+use routerify::ext::RequestExt;
+use slight_webhooks::{Inbox, Receipt, Webhook, MAX_PAYLOAD_SIZE};
+
+use crate::payload;
+
+/// How long a provider is asked to wait before delivering again what the guest isn't watching
+/// yet (e.g., as it's starting up).
+const RETRY_AFTER_SECS: &str = "30";
+
+/// Receives a delivery of a webhook of the slightfile — it's verified on the host side, so one
+/// that fails verification is answered w/ a 400, or a 401 before the guest sees anything, and
+/// one that passes is handed to the guest as an event, and answered w/ a 202 (see
+/// `Webhook::receive`).
+///
+/// The guest handles the event w/ `events`, rather than being invoked for the request, so the
+/// provider is answered as soon as it's delivered.
+pub async fn receive(request: hyper::Request<Body>) -> Result<hyper::Response<Body>> {
+    let webhook = request.data::<Webhook>().unwrap().clone();
+    let inbox = request.data::<Inbox>().unwrap().clone();
+    let (parts, body) = request.into_parts();
+    let body = match payload::read(body, MAX_PAYLOAD_SIZE).await? {
+        Some(body) => body,
+        None => {
+            tracing::warn!(
+                "rejecting a delivery of webhook '{}': it's bigger than {} bytes",
+                webhook.name,
+                MAX_PAYLOAD_SIZE
+            );
+            return respond(StatusCode::PAYLOAD_TOO_LARGE, None);
+        }
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let receipt = webhook.receive(
+        &inbox,
+        |name| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        },
+        &body,
+        now,
+    );
+    let retry_after = (receipt == Receipt::Unwatched).then_some(RETRY_AFTER_SECS);
+    respond(StatusCode::from_u16(receipt.status())?, retry_after)
+}
+
+fn respond(status: StatusCode, retry_after: Option<&str>) -> Result<hyper::Response<Body>> {
+    let mut res = hyper::Response::builder().status(status);
+    if let Some(retry_after) = retry_after {
+        res = res.header(header::RETRY_AFTER, retry_after);
+    }
+    Ok(res.body(Body::from(status.canonical_reason().unwrap_or_default()))?)
+}
@@ -0,0 +1,288 @@
+mod queue;
+
+/// The `SCHEME_NAME` defines the name under which a resource is
+/// identifiable by in a `ResourceMap`.
+const SCHEME_NAME: &str = "jobs";
+/// The operations guests can retry if they time out (see `BasicState::with_idempotent_operations`).
+const IDEMPOTENT_OPERATIONS: &[&str] = &["status", "dead-letters"];
+/// The operations that only read, which audits of writes only leave out (see
+/// `BasicState::with_read_operations`).
+const READ_OPERATIONS: &[&str] = &["status", "dead-letters"];
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Result};
+use uuid::Uuid;
+
+use queue::{JobQueue, JobRecord, State, DEFAULT_TIMEOUT};
+use slight_runtime::{impl_resource, resource::BasicState};
+
+/// It is mandatory to `use <interface>::*` due to `impl_resource!`.
+/// That is because `impl_resource!` accesses the `crate`'s
+/// `add_to_linker`, and not the `<interface>::add_to_linker` directly.
+use jobs::*;
+wit_bindgen_wasmtime::export!("../../wit/jobs.wit");
+wit_error_rs::impl_error!(jobs::Error);
+slight_runtime::impl_from_anyhow!(jobs::Error);
+
+/// How often `next` looks for a due job while waiting for one.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The `Jobs` structure is what will implement the `jobs::Jobs` trait
+/// coming from the generated code of off `jobs.wit`.
+///
+/// It maintains a `host_state`.
+pub struct Jobs {
+    host_state: JobsState,
+}
+
+impl_resource!(
+    Jobs,
+    jobs::JobsTables<Jobs>,
+    JobsState,
+    SCHEME_NAME.to_string()
+);
+
+/// This is the type of the `host_state` property from our `Jobs` structure.
+///
+/// It holds:
+///     - a `jobs_store` `String` — this comes directly from a user's `slightfile`
+///     (i.e., its `jobs.store`), and it is the kv implementor jobs are kept in, and
+///     - the `slight_state` (of type `BasicState`) that contains common
+///     things received from the slight binary (i.e., the `resource_map`,
+///     the `config_type`, and the `config_toml_file_path`).
+pub struct JobsState {
+    jobs_store: String,
+    slight_state: BasicState,
+}
+
+impl JobsState {
+    pub fn new(jobs_store: String, slight_state: BasicState) -> Self {
+        Self {
+            jobs_store,
+            slight_state: slight_state
+                .with_idempotent_operations(IDEMPOTENT_OPERATIONS)
+                .with_read_operations(READ_OPERATIONS),
+        }
+    }
+}
+
+impl From<JobRecord> for Job {
+    fn from(job: JobRecord) -> Self {
+        Self {
+            id: job.id,
+            job_type: job.job_type,
+            payload: job.payload,
+            state: match job.state {
+                State::Pending => JobState::Pending,
+                State::Running => JobState::Running,
+                State::Succeeded => JobState::Succeeded,
+                State::Dead => JobState::Dead,
+            },
+            attempts: job.attempts,
+            last_error: job.last_error,
+        }
+    }
+}
+
+impl jobs::Jobs for Jobs {
+    type Jobs = JobsInner;
+
+    fn jobs_open(&mut self, name: &str) -> Result<Self::Jobs, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
+        // populate our inner jobs object w/ the state received from `slight`
+        // (i.e., what kv implementor jobs are kept in), and the name of the queue.
+        let inner = Self::Jobs::new(
+            &self.host_state.jobs_store,
+            &self.host_state.slight_state,
+            name,
+        )?;
+
+        self.host_state
+            .slight_state
+            .resource_map
+            .lock()
+            .unwrap()
+            .set(inner.resource_descriptor.clone(), Box::new(inner.clone()));
+
+        Ok(inner)
+    }
+
+    fn jobs_enqueue(
+        &mut self,
+        self_: &Self::Jobs,
+        job_type: &str,
+        payload: PayloadParam<'_>,
+        options: JobOptions,
+    ) -> Result<String, Error> {
+        let timeout = match options.timeout_in_secs {
+            0 => DEFAULT_TIMEOUT,
+            secs => Duration::from_secs(secs),
+        };
+        Ok(self
+            .host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "enqueue", job_type, || {
+                self_.queue.enqueue(
+                    job_type,
+                    payload,
+                    Duration::from_secs(options.delay_in_secs),
+                    options.max_retries,
+                    timeout,
+                    now_secs(),
+                )
+            })?)
+    }
+
+    fn jobs_status(&mut self, self_: &Self::Jobs, job_id: &str) -> Result<Job, Error> {
+        Ok(self
+            .host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "status", job_id, || self_.queue.status(job_id))?
+            .into())
+    }
+
+    fn jobs_next(
+        &mut self,
+        self_: &Self::Jobs,
+        job_types: Vec<&str>,
+        timeout_in_secs: u64,
+    ) -> Result<Option<Job>, Error> {
+        let target = job_types.join(",");
+        let job = self
+            .host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "next", &target, || {
+                wait_for_next(&self_.queue, &job_types, timeout_in_secs)
+            })?;
+        if let Some(JobRecord {
+            id,
+            lease: Some(lease),
+            ..
+        }) = &job
+        {
+            self_
+                .leases
+                .lock()
+                .unwrap()
+                .insert(id.clone(), lease.clone());
+        }
+        Ok(job.map(Job::from))
+    }
+
+    fn jobs_complete(&mut self, self_: &Self::Jobs, job_id: &str) -> Result<(), Error> {
+        Ok(self
+            .host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "complete", job_id, || {
+                let lease = self_.lease(job_id)?;
+                self_.queue.complete(job_id, &lease, now_secs())?;
+                self_.leases.lock().unwrap().remove(job_id);
+                Ok(())
+            })?)
+    }
+
+    fn jobs_fail(&mut self, self_: &Self::Jobs, job_id: &str, reason: &str) -> Result<(), Error> {
+        Ok(self
+            .host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "fail", job_id, || {
+                let lease = self_.lease(job_id)?;
+                self_.queue.fail(job_id, &lease, reason, now_secs())?;
+                self_.leases.lock().unwrap().remove(job_id);
+                Ok(())
+            })?)
+    }
+
+    fn jobs_dead_letters(&mut self, self_: &Self::Jobs) -> Result<Vec<Job>, Error> {
+        Ok(self
+            .host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "dead-letters", &self_.name, || {
+                self_.queue.dead_letters()
+            })?
+            .into_iter()
+            .map(Job::from)
+            .collect())
+    }
+}
+
+/// Waits (for up to `timeout_in_secs`) for a due job of one of the `job_types`, and starts it.
+fn wait_for_next(
+    queue: &JobQueue,
+    job_types: &[&str],
+    timeout_in_secs: u64,
+) -> Result<Option<JobRecord>> {
+    let deadline = Instant::now() + Duration::from_secs(timeout_in_secs);
+    loop {
+        if let Some(job) = queue.start_next(job_types, now_secs())? {
+            return Ok(Some(job));
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+        std::thread::sleep(remaining.min(POLL_INTERVAL));
+    }
+}
+
+/// The current time, in seconds since the unix epoch.
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// This is the type of the associated type coming from the `jobs::Jobs` trait
+/// implementation.
+///
+/// It holds:
+///     - the `queue` jobs are kept in,
+///     - the `name` of the queue,
+///     - the `leases` of the jobs it started (by id), which only it can complete, or fail, and
+///     - a `resource_descriptor` (i.e., an UUID that uniquely identifies
+///     resource's instance).
+///
+/// It must `derive`:
+///     - `Debug` due to a constraint on the associated type.
+///     - `Clone` because the `ResourceMap` it will be added onto,
+///     must own its data.
+///
+/// It must be public because the implementation of `jobs::Jobs` cannot leak
+/// a private type.
+#[derive(Debug, Clone)]
+pub struct JobsInner {
+    queue: JobQueue,
+    name: String,
+    leases: Arc<Mutex<HashMap<String, String>>>,
+    resource_descriptor: String,
+}
+
+impl JobsInner {
+    fn new(jobs_store: &str, slight_state: &BasicState, name: &str) -> Result<Self> {
+        Ok(Self {
+            queue: JobQueue::open(jobs_store, slight_state, name)?,
+            name: name.to_string(),
+            leases: Arc::default(),
+            resource_descriptor: Uuid::new_v4().to_string(),
+        })
+    }
+
+    /// The lease of a job this resource started, failing if it didn't (e.g., another worker did).
+    fn lease(&self, job_id: &str) -> Result<String> {
+        match self.leases.lock().unwrap().get(job_id) {
+            Some(lease) => Ok(lease.clone()),
+            None => bail!(
+                "job '{}' wasn't started by this jobs resource (i.e., w/ its next), so it can't complete, or fail it",
+                job_id
+            ),
+        }
+    }
+}
+
+impl slight_runtime::resource::Watch for JobsInner {}
@@ -0,0 +1,443 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use slight_kv::HostKv;
+use slight_runtime::resource::BasicState;
+use uuid::Uuid;
+
+/// How long a worker has to complete, or fail a job, unless the job says otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// The backoff before the first retry of a failed job, which doubles w/ each retry.
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The longest backoff between retries of a failed job.
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// The key of the ids of the jobs that are pending, or running.
+const PENDING: &[u8] = b"pending";
+
+/// The key of the ids of the jobs that are dead-lettered.
+const DEAD_LETTERS: &[u8] = b"dead-letters";
+
+/// The state of a job.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Pending,
+    Running,
+    Succeeded,
+    Dead,
+}
+
+/// A job, as kept in the kv store.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub id: String,
+    pub job_type: String,
+    pub payload: Vec<u8>,
+    pub state: State,
+    /// how many times the job was started
+    pub attempts: u32,
+    pub max_retries: u32,
+    pub timeout_in_secs: u64,
+    /// when the job is due (or, if it is running, when it times out), in secs since the unix epoch
+    pub run_at: u64,
+    pub last_error: Option<String>,
+    /// the lease of the worker that started the job, if it's running — only it can complete,
+    /// or fail the job, until it times out
+    #[serde(default)]
+    pub lease: Option<String>,
+}
+
+/// `JobQueue` keeps jobs in a kv store, so they survive restarts, and can be worked on by
+/// many hosts sharing the store.
+///
+/// Each job is kept under its id, and the ids of the pending, and dead-lettered jobs are
+/// kept in an index each. Every change is made w/ a compare-and-swap, so two workers can
+/// never start the same job.
+///
+/// Starting a job leases it to the worker that started it, until it times out: only the holder
+/// of the lease can complete, or fail it, so a worker that took too long can't complete a job
+/// another worker has started since.
+///
+/// A job that fails (or times out) is retried w/ an exponential backoff, until it has been
+/// retried `max_retries` times — then, it is dead-lettered, for operators to inspect.
+#[derive(Debug, Clone)]
+pub struct JobQueue {
+    kv: HostKv,
+}
+
+impl JobQueue {
+    pub fn open(jobs_store: &str, slight_state: &BasicState, name: &str) -> Result<Self> {
+        Ok(Self {
+            kv: HostKv::open(jobs_store, slight_state, &format!("slight-jobs-{}", name))?,
+        })
+    }
+
+    pub fn enqueue(
+        &self,
+        job_type: &str,
+        payload: &[u8],
+        delay: Duration,
+        max_retries: u32,
+        timeout: Duration,
+        now: u64,
+    ) -> Result<String> {
+        let job = JobRecord {
+            id: Uuid::new_v4().to_string(),
+            job_type: job_type.to_string(),
+            payload: payload.to_vec(),
+            state: State::Pending,
+            attempts: 0,
+            max_retries,
+            timeout_in_secs: timeout.as_secs(),
+            run_at: now + delay.as_secs(),
+            last_error: None,
+            lease: None,
+        };
+        if !self
+            .kv
+            .compare_and_swap(&job_key(&job.id), None, &serde_json::to_vec(&job)?)?
+        {
+            bail!("a job w/ id '{}' exists already", job.id);
+        }
+        self.update_index(PENDING, |ids| ids.push(job.id.clone()))?;
+        tracing::info!("enqueued job '{}' of type '{}'", job.id, job_type);
+        Ok(job.id)
+    }
+
+    pub fn status(&self, id: &str) -> Result<JobRecord> {
+        match self.load(id)? {
+            Some((_, job)) => Ok(job),
+            None => bail!("job '{}' not found", id),
+        }
+    }
+
+    /// Starts the first due job of one of the `job_types`, if there's any.
+    ///
+    /// Running jobs that timed out are failed along the way.
+    pub fn start_next(&self, job_types: &[&str], now: u64) -> Result<Option<JobRecord>> {
+        for id in self.index(PENDING)? {
+            let (raw, job) = match self.load(&id)? {
+                Some(loaded) => loaded,
+                None => continue,
+            };
+            if job.run_at > now || !job_types.contains(&job.job_type.as_str()) {
+                continue;
+            }
+            match job.state {
+                State::Pending => {
+                    let started = JobRecord {
+                        state: State::Running,
+                        attempts: job.attempts + 1,
+                        run_at: now + job.timeout_in_secs,
+                        lease: Some(Uuid::new_v4().to_string()),
+                        ..job
+                    };
+                    // someone else may have started it in the meantime
+                    if self.swap(&raw, &started)? {
+                        tracing::info!(
+                            "started job '{}' (attempt {})",
+                            started.id,
+                            started.attempts
+                        );
+                        return Ok(Some(started));
+                    }
+                }
+                State::Running => {
+                    // the worker that started it likely crashed
+                    let reason = format!("timed out after {} secs", job.timeout_in_secs);
+                    self.fail_job(&raw, job, &reason, now)?;
+                }
+                State::Succeeded | State::Dead => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Completes a running job, as the holder of its `lease`.
+    pub fn complete(&self, id: &str, lease: &str, now: u64) -> Result<()> {
+        let (raw, job) = self.leased(id, lease, now)?;
+        let completed = JobRecord {
+            state: State::Succeeded,
+            lease: None,
+            ..job
+        };
+        if !self.swap(&raw, &completed)? {
+            bail!("job '{}' was changed while being completed", id);
+        }
+        self.update_index(PENDING, |ids| ids.retain(|i| i != id))?;
+        tracing::info!("job '{}' succeeded", id);
+        Ok(())
+    }
+
+    /// Fails a running job, as the holder of its `lease`.
+    pub fn fail(&self, id: &str, lease: &str, reason: &str, now: u64) -> Result<()> {
+        let (raw, job) = self.leased(id, lease, now)?;
+        if !self.fail_job(&raw, job, reason, now)? {
+            bail!("job '{}' was changed while being failed", id);
+        }
+        Ok(())
+    }
+
+    pub fn dead_letters(&self) -> Result<Vec<JobRecord>> {
+        let mut jobs = Vec::new();
+        for id in self.index(DEAD_LETTERS)? {
+            if let Some((_, job)) = self.load(&id)? {
+                jobs.push(job);
+            }
+        }
+        Ok(jobs)
+    }
+
+    /// Retries a failed job after a backoff, or dead-letters it if it can't be retried anymore.
+    fn fail_job(&self, raw: &[u8], job: JobRecord, reason: &str, now: u64) -> Result<bool> {
+        let retries = job.attempts.saturating_sub(1);
+        let failed = if retries < job.max_retries {
+            JobRecord {
+                state: State::Pending,
+                run_at: now + backoff(job.attempts).as_secs(),
+                last_error: Some(reason.to_string()),
+                lease: None,
+                ..job
+            }
+        } else {
+            JobRecord {
+                state: State::Dead,
+                last_error: Some(reason.to_string()),
+                lease: None,
+                ..job
+            }
+        };
+        if !self.swap(raw, &failed)? {
+            return Ok(false);
+        }
+        if failed.state == State::Dead {
+            self.update_index(PENDING, |ids| ids.retain(|i| i != &failed.id))?;
+            self.update_index(DEAD_LETTERS, |ids| ids.push(failed.id.clone()))?;
+            tracing::error!(
+                "job '{}' failed {} times, and was dead-lettered: {}",
+                failed.id,
+                failed.attempts,
+                reason
+            );
+        } else {
+            tracing::warn!(
+                "job '{}' failed (attempt {}), retrying in {} secs: {}",
+                failed.id,
+                failed.attempts,
+                failed.run_at - now,
+                reason
+            );
+        }
+        Ok(true)
+    }
+
+    /// Loads a job that must be running, and leased w/ `lease` (i.e., to complete, or fail it).
+    fn leased(&self, id: &str, lease: &str, now: u64) -> Result<(Vec<u8>, JobRecord)> {
+        let (raw, job) = match self.load(id)? {
+            Some((raw, job)) if job.state == State::Running => (raw, job),
+            Some(_) => bail!("job '{}' isn't running", id),
+            None => bail!("job '{}' not found", id),
+        };
+        if job.lease.as_deref() != Some(lease) {
+            bail!(
+                "job '{}' is leased to another worker (i.e., it timed out, and was started again)",
+                id
+            );
+        }
+        if now >= job.run_at {
+            bail!(
+                "the lease of job '{}' expired, as it timed out after {} secs",
+                id,
+                job.timeout_in_secs
+            );
+        }
+        Ok((raw, job))
+    }
+
+    fn load(&self, id: &str) -> Result<Option<(Vec<u8>, JobRecord)>> {
+        let raw = match self.kv.get(&job_key(id))? {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+        let job =
+            serde_json::from_slice(&raw).with_context(|| format!("job '{}' is corrupted", id))?;
+        Ok(Some((raw, job)))
+    }
+
+    /// Replaces a job, if it is still as it was when loaded (i.e., `raw`).
+    fn swap(&self, raw: &[u8], job: &JobRecord) -> Result<bool> {
+        self.kv
+            .compare_and_swap(&job_key(&job.id), Some(raw), &serde_json::to_vec(job)?)
+    }
+
+    fn index(&self, key: &[u8]) -> Result<Vec<String>> {
+        match self.kv.get(key)? {
+            Some(raw) => Ok(serde_json::from_slice(&raw)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn update_index(&self, key: &[u8], f: impl Fn(&mut Vec<String>)) -> Result<()> {
+        loop {
+            let current = self.kv.get(key)?;
+            let mut ids = match &current {
+                Some(raw) => serde_json::from_slice(raw)?,
+                None => Vec::new(),
+            };
+            f(&mut ids);
+            if self
+                .kv
+                .compare_and_swap(key, current.as_deref(), &serde_json::to_vec(&ids)?)?
+            {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn job_key(id: &str) -> Vec<u8> {
+    format!("job/{}", id).into_bytes()
+}
+
+/// The backoff after a job's `attempts`-th attempt failed.
+fn backoff(attempts: u32) -> Duration {
+    let exponent = attempts.saturating_sub(1).min(31);
+    BASE_BACKOFF.saturating_mul(1 << exponent).min(MAX_BACKOFF)
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use slight_runtime::resource::BasicState;
+    use uuid::Uuid;
+
+    use super::{backoff, JobQueue, State, DEFAULT_TIMEOUT, MAX_BACKOFF};
+
+    const NOW: u64 = 1_000_000;
+
+    fn queue() -> JobQueue {
+        JobQueue::open(
+            "kv.filesystem",
+            &BasicState::default(),
+            &Uuid::new_v4().to_string(),
+        )
+        .unwrap()
+    }
+
+    fn enqueue(queue: &JobQueue, delay_in_secs: u64, max_retries: u32) -> Result<String> {
+        queue.enqueue(
+            "email",
+            b"hello",
+            Duration::from_secs(delay_in_secs),
+            max_retries,
+            DEFAULT_TIMEOUT,
+            NOW,
+        )
+    }
+
+    #[test]
+    fn enqueue_start_complete_test() -> Result<()> {
+        let queue = queue();
+        let id = enqueue(&queue, 0, 3)?;
+        assert_eq!(queue.status(&id)?.state, State::Pending);
+
+        assert!(queue.start_next(&["sms"], NOW)?.is_none());
+        let job = queue.start_next(&["email"], NOW)?.unwrap();
+        assert_eq!(job.id, id);
+        assert_eq!(job.payload, b"hello");
+        assert_eq!(job.attempts, 1);
+        // a running job can't be started twice
+        assert!(queue.start_next(&["email"], NOW)?.is_none());
+
+        let lease = job.lease.unwrap();
+        queue.complete(&id, &lease, NOW)?;
+        assert_eq!(queue.status(&id)?.state, State::Succeeded);
+        assert!(queue.complete(&id, &lease, NOW).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn delayed_job_test() -> Result<()> {
+        let queue = queue();
+        enqueue(&queue, 60, 0)?;
+        assert!(queue.start_next(&["email"], NOW + 59)?.is_none());
+        assert!(queue.start_next(&["email"], NOW + 60)?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn failed_job_is_retried_then_dead_lettered_test() -> Result<()> {
+        let queue = queue();
+        let id = enqueue(&queue, 0, 1)?;
+
+        let lease = queue.start_next(&["email"], NOW)?.unwrap().lease.unwrap();
+        queue.fail(&id, &lease, "smtp is down", NOW)?;
+        let job = queue.status(&id)?;
+        assert_eq!(job.state, State::Pending);
+        assert_eq!(job.last_error.as_deref(), Some("smtp is down"));
+        // it is retried after a backoff
+        assert!(queue.start_next(&["email"], NOW)?.is_none());
+        let job = queue.start_next(&["email"], NOW + 1)?.unwrap();
+        assert_eq!(job.attempts, 2);
+        // the lease of the first attempt doesn't hold anymore
+        assert!(queue.fail(&id, &lease, "smtp is down", NOW + 1).is_err());
+
+        queue.fail(&id, &job.lease.unwrap(), "smtp is still down", NOW + 1)?;
+        assert_eq!(queue.status(&id)?.state, State::Dead);
+        assert!(queue.start_next(&["email"], NOW + 3600)?.is_none());
+        let dead_letters = queue.dead_letters()?;
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].id, id);
+        Ok(())
+    }
+
+    #[test]
+    fn timed_out_job_is_retried_test() -> Result<()> {
+        let queue = queue();
+        let id = enqueue(&queue, 0, 1)?;
+        queue.start_next(&["email"], NOW)?.unwrap();
+
+        let timed_out = NOW + DEFAULT_TIMEOUT.as_secs();
+        assert!(queue.start_next(&["email"], timed_out)?.is_none());
+        let job = queue.status(&id)?;
+        assert_eq!(job.state, State::Pending);
+        assert_eq!(job.last_error.as_deref(), Some("timed out after 300 secs"));
+        assert!(queue.start_next(&["email"], timed_out + 1)?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn lease_test() -> Result<()> {
+        let queue = queue();
+        let id = enqueue(&queue, 0, 1)?;
+        let first = queue.start_next(&["email"], NOW)?.unwrap().lease.unwrap();
+        assert!(queue.complete(&id, "not-the-lease", NOW).is_err());
+
+        // a worker that took too long can't complete the job, even before it's retried
+        let timed_out = NOW + DEFAULT_TIMEOUT.as_secs();
+        assert!(queue.complete(&id, &first, timed_out).is_err());
+        assert_eq!(queue.status(&id)?.state, State::Running);
+
+        // nor after another worker started it again
+        queue.start_next(&["email"], timed_out)?;
+        let second = queue.start_next(&["email"], timed_out + 1)?.unwrap();
+        assert!(queue.complete(&id, &first, timed_out + 1).is_err());
+        queue.complete(&id, &second.lease.unwrap(), timed_out + 1)?;
+        assert_eq!(queue.status(&id)?.state, State::Succeeded);
+        Ok(())
+    }
+
+    #[test]
+    fn backoff_test() {
+        assert_eq!(backoff(1), Duration::from_secs(1));
+        assert_eq!(backoff(2), Duration::from_secs(2));
+        assert_eq!(backoff(5), Duration::from_secs(16));
+        assert_eq!(backoff(100), MAX_BACKOFF);
+    }
+}
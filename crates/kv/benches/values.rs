@@ -0,0 +1,78 @@
+//! Measures the host's read path for kv values of different sizes (i.e., what a guest's `get`,
+//! and `get-range` go through before the value is copied into its memory), and reports how
+//! many bytes each read allocates.
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use slight_kv::HostKv;
+use slight_runtime::resource::BasicState;
+
+/// The sizes of the values read: empty, small, and large ones.
+const SIZES: [usize; 4] = [0, 4 << 10, 1 << 20, 16 << 20];
+
+/// Counts the bytes allocated (or reallocated), so a read's allocations can be reported.
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn allocated_by<T>(f: impl FnOnce() -> T) -> usize {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    drop(f());
+    ALLOCATED.load(Ordering::Relaxed) - before
+}
+
+fn values(c: &mut Criterion) {
+    let kv = HostKv::open(
+        "kv.filesystem",
+        &BasicState::default(),
+        &format!("slight-kv-bench-{}", std::process::id()),
+    )
+    .unwrap();
+    let mut group = c.benchmark_group("kv.filesystem");
+    for size in SIZES {
+        kv.set(b"value", &vec![42; size]).unwrap();
+
+        // ideally, a read allocates the value once (i.e., the buffer handed to the guest),
+        // plus a few bytes for the path of the key
+        println!(
+            "get of {} bytes allocated {} bytes, get-range allocated {} bytes",
+            size,
+            allocated_by(|| kv.get(b"value").unwrap()),
+            allocated_by(|| kv.get_range(b"value", 0, u64::MAX).unwrap()),
+        );
+
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("get", size), &size, |b, _| {
+            b.iter(|| kv.get(b"value").unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("get-range", size), &size, |b, _| {
+            b.iter(|| kv.get_range(b"value", 0, u64::MAX).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, values);
+criterion_main!(benches);
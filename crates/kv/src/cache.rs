@@ -0,0 +1,278 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// How many values are cached at most, unless the capability says otherwise.
+pub const DEFAULT_CACHE_MAX_ENTRIES: usize = 10_000;
+
+/// A key, `scope`d by the name of the kv store it belongs to.
+type Entry = (String, Vec<u8>);
+
+/// A cached value, w/ when it was read, the tags it was read w/, and when it was last used.
+type Cached = (Vec<u8>, Instant, Vec<Vec<u8>>, u64);
+
+/// `ReadCache` is a read-through cache of the values read from the kv stores, so that reading
+/// a key again doesn't go to the backend until its value is older than `ttl`, or it is
+/// invalidated — whichever comes first.
+///
+/// A value is invalidated when:
+///     - its key is written to (i.e., set, deleted, patched, incremented, or cleared) through
+///     the same guest,
+///     - the guest invalidates its key explicitly, or
+///     - any of the keys it was tagged w/ when it was read (see `get-tagged`) is invalidated
+///     (i.e., written to, or invalidated explicitly).
+///
+/// Tags are keys of the same kv store, and invalidation doesn't cascade: invalidating `a`
+/// invalidates the values tagged w/ `a`, but not the values tagged w/ those values' keys.
+///
+/// Writes made by someone else (e.g., another guest, or host) don't invalidate anything, so a
+/// value can be up to `ttl` stale, unless the guest invalidates it.
+///
+/// It holds up to `max_entries` values, past which the least recently used one is evicted,
+/// so reading many keys once (e.g., a scan) doesn't grow it w/o bound.
+///
+/// It is disabled (i.e., every read goes to the backend) if `ttl` is `None`.
+#[derive(Clone, Debug, Default)]
+pub struct ReadCache {
+    ttl: Option<Duration>,
+    max_entries: usize,
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// the cached values, w/ when they were read, the tags they were read w/, and when they
+    /// were last used (see `used`)
+    values: HashMap<Entry, Cached>,
+    /// the keys whose values are tagged w/ each tag
+    tagged: HashMap<Entry, HashSet<Vec<u8>>>,
+    /// the cached values, by when they were last used, least recently first
+    used: BTreeMap<u64, Entry>,
+    /// what the next use of a value is numbered
+    uses: u64,
+}
+
+impl ReadCache {
+    pub fn new(ttl: Option<Duration>, max_entries: Option<usize>) -> Self {
+        Self {
+            ttl,
+            max_entries: max_entries.unwrap_or(DEFAULT_CACHE_MAX_ENTRIES),
+            ..Default::default()
+        }
+    }
+
+    /// The cached value of `key`, if it's there, and not older than the `ttl`.
+    pub fn get(&self, scope: &str, key: &[u8]) -> Option<Vec<u8>> {
+        self.get_at(Instant::now(), scope, key)
+    }
+
+    fn get_at(&self, now: Instant, scope: &str, key: &[u8]) -> Option<Vec<u8>> {
+        let ttl = self.ttl?;
+        let entry = (scope.to_string(), key.to_vec());
+        let mut inner = self.inner.lock().unwrap();
+        match inner.values.get(&entry) {
+            Some((value, read_at, _, _)) if now.duration_since(*read_at) < ttl => {
+                let value = value.clone();
+                inner.use_(&entry);
+                Some(value)
+            }
+            Some(_) => {
+                inner.remove(&entry);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Caches `value` as just read for `key`, tagged w/ `tags` (i.e., other keys of the same
+    /// kv store that invalidate it).
+    pub fn put(&self, scope: &str, key: &[u8], value: &[u8], tags: &[&[u8]]) {
+        self.put_at(Instant::now(), scope, key, value, tags)
+    }
+
+    fn put_at(&self, now: Instant, scope: &str, key: &[u8], value: &[u8], tags: &[&[u8]]) {
+        if self.ttl.is_none() || self.max_entries == 0 {
+            return;
+        }
+        let entry = (scope.to_string(), key.to_vec());
+        let mut inner = self.inner.lock().unwrap();
+        // the tags of a value are the ones it was last read w/
+        inner.remove(&entry);
+        while inner.values.len() >= self.max_entries {
+            let evicted = match inner.used.values().next() {
+                Some(evicted) => evicted.clone(),
+                None => break,
+            };
+            inner.remove(&evicted);
+        }
+        for tag in tags {
+            inner
+                .tagged
+                .entry((scope.to_string(), tag.to_vec()))
+                .or_default()
+                .insert(key.to_vec());
+        }
+        let used = inner.uses;
+        inner.uses += 1;
+        inner.used.insert(used, entry.clone());
+        inner.values.insert(
+            entry,
+            (
+                value.to_vec(),
+                now,
+                tags.iter().map(|tag| tag.to_vec()).collect(),
+                used,
+            ),
+        );
+    }
+
+    /// Invalidates the value of `key`, and the values tagged w/ it, returning how many were
+    /// invalidated.
+    pub fn invalidate(&self, scope: &str, key: &[u8]) -> usize {
+        if self.ttl.is_none() {
+            return 0;
+        }
+        let entry = (scope.to_string(), key.to_vec());
+        let mut inner = self.inner.lock().unwrap();
+        let mut invalidated = inner.remove(&entry) as usize;
+        for tagged in inner.tagged.remove(&entry).unwrap_or_default() {
+            invalidated += inner.remove(&(scope.to_string(), tagged)) as usize;
+        }
+        invalidated
+    }
+
+    /// Invalidates the values of all keys in `scope` starting w/ `prefix`, and the values
+    /// tagged w/ any of them (i.e., after clearing them).
+    pub fn invalidate_prefix(&self, scope: &str, prefix: &[u8]) {
+        if self.ttl.is_none() {
+            return;
+        }
+        let keys = {
+            let inner = self.inner.lock().unwrap();
+            inner
+                .values
+                .keys()
+                .chain(inner.tagged.keys())
+                .filter(|(s, k)| s == scope && k.starts_with(prefix))
+                .map(|(_, k)| k.clone())
+                .collect::<HashSet<_>>()
+        };
+        for key in keys {
+            self.invalidate(scope, &key);
+        }
+    }
+}
+
+impl Inner {
+    /// Removes the value of `entry` (and it from the values tagged w/ its tags), returning
+    /// whether there was one.
+    fn remove(&mut self, entry: &Entry) -> bool {
+        let (_, _, tags, used) = match self.values.remove(entry) {
+            Some(value) => value,
+            None => return false,
+        };
+        self.used.remove(&used);
+        for tag in tags {
+            let tag = (entry.0.clone(), tag);
+            if let Some(keys) = self.tagged.get_mut(&tag) {
+                keys.remove(&entry.1);
+                if keys.is_empty() {
+                    self.tagged.remove(&tag);
+                }
+            }
+        }
+        true
+    }
+
+    /// Marks the value of `entry` as the most recently used one.
+    fn use_(&mut self, entry: &Entry) {
+        let uses = self.uses;
+        if let Some((_, _, _, used)) = self.values.get_mut(entry) {
+            self.used.remove(used);
+            *used = uses;
+            self.used.insert(uses, entry.clone());
+            self.uses += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::{Duration, Instant};
+
+    use super::ReadCache;
+
+    const TTL: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn ttl_test() {
+        let cache = ReadCache::new(Some(TTL), None);
+        let t = Instant::now();
+        cache.put_at(t, "store", b"key", b"value", &[]);
+        assert_eq!(
+            cache.get_at(t + TTL / 2, "store", b"key"),
+            Some(b"value".to_vec())
+        );
+        assert_eq!(cache.get_at(t + TTL / 2, "other", b"key"), None);
+        assert_eq!(cache.get_at(t + TTL, "store", b"key"), None);
+
+        let disabled = ReadCache::new(None, None);
+        disabled.put_at(t, "store", b"key", b"value", &[]);
+        assert_eq!(disabled.get_at(t, "store", b"key"), None);
+    }
+
+    #[test]
+    fn lru_test() {
+        let cache = ReadCache::new(Some(TTL), Some(2));
+        let t = Instant::now();
+        cache.put_at(t, "store", b"a", b"1", &[]);
+        cache.put_at(t, "store", b"b", b"2", &[]);
+        // reading `a` makes `b` the least recently used
+        assert_eq!(cache.get_at(t, "store", b"a"), Some(b"1".to_vec()));
+        cache.put_at(t, "store", b"c", b"3", &[b"a"]);
+        assert_eq!(cache.get_at(t, "store", b"b"), None);
+        assert_eq!(cache.get_at(t, "store", b"a"), Some(b"1".to_vec()));
+        assert_eq!(cache.get_at(t, "store", b"c"), Some(b"3".to_vec()));
+
+        // the values tagged w/ an evicted one are still invalidated w/ it
+        cache.put_at(t, "store", b"d", b"4", &[]);
+        assert_eq!(cache.get_at(t, "store", b"a"), None);
+        assert_eq!(cache.invalidate("store", b"a"), 1);
+        assert_eq!(cache.get_at(t, "store", b"c"), None);
+        assert!(cache.inner.lock().unwrap().tagged.is_empty());
+
+        let empty = ReadCache::new(Some(TTL), Some(0));
+        empty.put_at(t, "store", b"a", b"1", &[]);
+        assert_eq!(empty.get_at(t, "store", b"a"), None);
+    }
+
+    #[test]
+    fn invalidate_test() {
+        let cache = ReadCache::new(Some(TTL), None);
+        let t = Instant::now();
+        cache.put_at(t, "store", b"user:1", b"ada", &[]);
+        cache.put_at(t, "store", b"user:1:greeting", b"hi ada", &[b"user:1"]);
+        cache.put_at(t, "store", b"user:1:badge", b"ADA", &[b"user:1", b"badges"]);
+        cache.put_at(t, "store", b"page", b"hi ada", &[b"user:1:greeting"]);
+
+        // a write to a key invalidates it, and the values tagged w/ it, but doesn't cascade
+        assert_eq!(cache.invalidate("store", b"user:1"), 3);
+        assert_eq!(cache.get_at(t, "store", b"user:1:greeting"), None);
+        assert_eq!(cache.get_at(t, "store", b"user:1:badge"), None);
+        assert_eq!(cache.get_at(t, "store", b"page"), Some(b"hi ada".to_vec()));
+        // the other tags of invalidated values are gone too
+        assert_eq!(cache.invalidate("store", b"badges"), 0);
+
+        // a value re-read w/ other tags is no longer invalidated by its old ones
+        cache.put_at(t, "store", b"page", b"hi ada", &[]);
+        assert_eq!(cache.invalidate("store", b"user:1:greeting"), 0);
+
+        cache.put_at(t, "store", b"user:2", b"bob", &[]);
+        cache.put_at(t, "store", b"team", b"bob, and ada", &[b"user:2"]);
+        cache.invalidate_prefix("store", b"user:");
+        assert_eq!(cache.get_at(t, "store", b"team"), None);
+        assert_eq!(cache.get_at(t, "store", b"page"), Some(b"hi ada".to_vec()));
+    }
+}
@@ -1,10 +1,19 @@
-use anyhow::{bail, Result};
-use aws_sdk_dynamodb::model::{AttributeValue, Select};
+use anyhow::{anyhow, Context, Result};
+use aws_sdk_dynamodb::model::{
+    AttributeValue, DeleteRequest, KeysAndAttributes, Select, WriteRequest,
+};
+use aws_sdk_dynamodb::types::{Blob, SdkError};
 use aws_sdk_dynamodb::Client;
-use futures::executor::block_on;
+use slight_runtime::deadline::block_on;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use slight_runtime::{describe::Description, error_kind::NotFound};
 use tracing::log;
 
+use crate::{implementors::slice_range, keys};
+
 /// This is the underlying struct behind the "AWS DynamoDB" variant of the `KvImplementor` enum.
 ///
 /// It provides a properties that pertains solely to the AWS DynamoDB implementation
@@ -27,21 +36,27 @@ impl AwsDynamoDbImplementor {
     ///   - `AWS_REGION`.
     ///
     /// In order to use the AWS DyanmoDB implementor, you must have a DynamoDB table
-    /// with a primary key named `key`.
+    /// with a primary key named `key` of type binary, as keys are stored as-is.
     ///
     /// The layout of the DynamoDB table is as follows:
     /// ```text
     /// {
     ///   "key": {
-    ///       "S": <key>
+    ///       "B": <key>
     ///   },
     ///   "value": {
     ///       "S": <value>
+    ///   },
+    ///   "expires_at": {
+    ///       "N": <unix time in secs> // only for keys set w/ a time to live
     ///   }
     /// }
     /// ```
+    ///
+    /// For keys set w/ a time to live to be deleted, the table must have TTL
+    /// enabled on the `expires_at` attribute.
     pub fn new(name: &str) -> Self {
-        let shared_config = block_on(aws_config::load_from_env());
+        let shared_config = futures::executor::block_on(aws_config::load_from_env());
         let client = Client::new(&shared_config);
         let table_name = name.into();
         log::info!(
@@ -51,35 +66,194 @@ impl AwsDynamoDbImplementor {
         Self { client, table_name }
     }
 
-    pub fn get(&self, key: &str) -> Result<Vec<u8>> {
-        let key_attribute = AttributeValue::S(key.into());
-        log::info!("Getting value from key: {}", key);
+    /// Describes the region tables are kept in (see `slight_runtime::describe`), as
+    /// `aws_config::load_from_env()` finds it (i.e., in the environment, not the secret stores).
+    pub fn describe() -> Description {
+        let var = |key: &str| {
+            env::var(key).with_context(|| format!("'{}' isn't set in the environment", key))
+        };
+        let region = var("AWS_REGION");
+        let endpoint = region
+            .as_ref()
+            .map(|region| format!("dynamodb.{}.amazonaws.com", region))
+            .map_err(|e| anyhow!("{:#}", e));
+        Description::new("kv.awsdynamodb", "AWS DynamoDB")
+            .with_endpoint("AWS_REGION", endpoint)
+            .with_setting("AWS_REGION", region)
+            .with_secret("AWS_ACCESS_KEY_ID", var("AWS_ACCESS_KEY_ID"))
+            .with_secret("AWS_SECRET_ACCESS_KEY", var("AWS_SECRET_ACCESS_KEY"))
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Vec<u8>> {
+        match self.get_opt(key)? {
+            Some(value) => Ok(value),
+            None => Err(NotFound(format!("no value found for key: {}", keys::display(key))).into()),
+        }
+    }
+
+    /// DynamoDB has no ranged reads, so this reads the whole value, and slices it
+    /// (i.e., it transfers the whole value, no matter how few bytes are requested).
+    pub fn get_range(&self, key: &[u8], offset: u64, length: u64) -> Result<Vec<u8>> {
+        Ok(slice_range(self.get(key)?, offset, length))
+    }
+
+    /// Like `get`, but returns `None` instead of failing if the key doesn't exist
+    pub fn get_opt(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let key_attribute = AttributeValue::B(Blob::new(key));
+        log::info!("Getting value from key: {}", keys::display(key));
         let res = block_on(
             self.client
                 .query()
                 .table_name(&self.table_name)
                 .key_condition_expression("#key = :value".to_string())
+                .filter_expression(NOT_EXPIRED_FILTER.to_string())
                 .expression_attribute_names("#key".to_string(), "key".to_string())
+                .expression_attribute_names("#expires_at".to_string(), "expires_at".to_string())
                 .expression_attribute_values(":value".to_string(), key_attribute)
+                .expression_attribute_values(":now".to_string(), unix_now()?)
                 .select(Select::AllAttributes)
                 .send(),
-        )?;
-        match res.items.unwrap_or_default().pop() {
-            Some(item) => {
-                let value = item.get("value").unwrap();
-                let value = value.as_s().unwrap();
-                Ok(value.as_bytes().to_vec())
+        )??;
+        // the value is moved out of the item, rather than copied
+        Ok(res.items.unwrap_or_default().pop().map(|mut item| {
+            match item.remove("value").unwrap() {
+                AttributeValue::S(value) => value.into_bytes(),
+                value => panic!("expected a string value, got {:?}", value),
+            }
+        }))
+    }
+
+    /// Gets the values of `keys`, w/ a result per key (in the same order), failing as a whole
+    /// only if DynamoDB does.
+    ///
+    /// Keys are read w/ `BatchGetItem`, in batches of as many (distinct) keys as DynamoDB
+    /// allows per request, retrying any keys it didn't get to.
+    pub fn get_many(&self, keys: &[Vec<u8>]) -> Result<Vec<Result<Vec<u8>>>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        // BatchGetItem rejects requests w/ duplicate keys
+        let distinct = keys
+            .iter()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let mut values = HashMap::new();
+        for batch in distinct.chunks(MAX_BATCH_GET_ITEMS) {
+            let request = KeysAndAttributes::builder()
+                .set_keys(Some(
+                    batch
+                        .iter()
+                        .map(|key| {
+                            HashMap::from([(
+                                "key".to_string(),
+                                AttributeValue::B(Blob::new(key.as_slice())),
+                            )])
+                        })
+                        .collect(),
+                ))
+                .build();
+            let mut pending = HashMap::from([(self.table_name.clone(), request)]);
+            while !pending.is_empty() {
+                let res = block_on(
+                    self.client
+                        .batch_get_item()
+                        .set_request_items(Some(pending))
+                        .send(),
+                )?
+                .with_context(|| "failed to get batch of keys")?;
+                let items = res
+                    .responses
+                    .unwrap_or_default()
+                    .remove(&self.table_name)
+                    .unwrap_or_default();
+                for mut item in items {
+                    if expired(&item, now) {
+                        continue;
+                    }
+                    if let (Some(AttributeValue::B(key)), Some(AttributeValue::S(value))) =
+                        (item.remove("key"), item.remove("value"))
+                    {
+                        values.insert(key.into_inner(), value.into_bytes());
+                    }
+                }
+                pending = res.unprocessed_keys.unwrap_or_default();
+                pending.retain(|_, request| request.keys().map_or(false, |keys| !keys.is_empty()));
+            }
+        }
+        log::info!(
+            "Got {} of {} keys of table: {}",
+            values.len(),
+            distinct.len(),
+            self.table_name
+        );
+        Ok(keys
+            .iter()
+            .map(|key| {
+                values.get(key).cloned().ok_or_else(|| {
+                    NotFound(format!("no value found for key: {}", keys::display(key))).into()
+                })
+            })
+            .collect())
+    }
+
+    /// Sets the value of a key only if its current value is `expected` (where `None`
+    /// means the key must not exist yet), returning whether the swap happened.
+    ///
+    /// This uses a conditional write, so the comparison happens on DynamoDB's side.
+    pub fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        value: &[u8],
+    ) -> Result<bool> {
+        let value = AttributeValue::S(String::from_utf8(value.to_vec())?);
+        let mut put = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("key", AttributeValue::B(Blob::new(key)))
+            .item("value", value)
+            .expression_attribute_names("#key".to_string(), "key".to_string());
+        put = match expected {
+            Some(expected) => put
+                .condition_expression("attribute_exists(#key) AND #value = :expected".to_string())
+                .expression_attribute_names("#value".to_string(), "value".to_string())
+                .expression_attribute_values(
+                    ":expected".to_string(),
+                    AttributeValue::S(String::from_utf8(expected.to_vec())?),
+                ),
+            // an item that expired, but hasn't been deleted yet, counts as not existing
+            None => put
+                .condition_expression(
+                    "attribute_not_exists(#key) OR #expires_at <= :now".to_string(),
+                )
+                .expression_attribute_names("#expires_at".to_string(), "expires_at".to_string())
+                .expression_attribute_values(":now".to_string(), unix_now()?),
+        };
+        log::info!(
+            "Conditionally setting value for key: {}",
+            keys::display(key)
+        );
+        match block_on(put.send())? {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError { err, .. })
+                if err.is_conditional_check_failed_exception() =>
+            {
+                Ok(false)
             }
-            None => bail!("no value found for key: {}", key),
+            Err(e) => Err(e.into()),
         }
     }
 
-    pub fn set(&self, key: &str, value: &[u8]) -> Result<()> {
-        let key_attribute = AttributeValue::S(key.into());
+    pub fn set(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        let key_attribute = AttributeValue::B(Blob::new(key));
         let value = AttributeValue::S(
             String::from_utf8(value.to_vec()).expect("failed to convert value to String"),
         );
-        log::info!("Setting key value pair: ({}, {:#?})", key, value);
+        log::info!(
+            "Setting key value pair: ({}, {:#?})",
+            keys::display(key),
+            value
+        );
         block_on(
             self.client
                 .put_item()
@@ -87,22 +261,209 @@ impl AwsDynamoDbImplementor {
                 .item("key", key_attribute)
                 .item("value", value)
                 .send(),
-        )?;
+        )??;
+        Ok(())
+    }
+
+    /// Sets the value of a key, which expires after `time_to_live_in_secs`.
+    ///
+    /// The expiry time has to come from the host's clock, as DynamoDB can't compute it
+    /// on its own. From there on, expired items are deleted by DynamoDB's TTL process
+    /// (i.e., as per DynamoDB's clock), but as that can take a while, expired items
+    /// are also filtered out on reads, as per the reading host's clock.
+    pub fn set_with_time_to_live(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        time_to_live_in_secs: u64,
+    ) -> Result<()> {
+        let expires_at = (SystemTime::now() + Duration::from_secs(time_to_live_in_secs))
+            .duration_since(UNIX_EPOCH)?
+            .as_secs();
+        let value = AttributeValue::S(String::from_utf8(value.to_vec())?);
+        log::info!(
+            "Setting key value pair: ({}, {:#?}) expiring at {}",
+            keys::display(key),
+            value,
+            expires_at
+        );
+        block_on(
+            self.client
+                .put_item()
+                .table_name(&self.table_name)
+                .item("key", AttributeValue::B(Blob::new(key)))
+                .item("value", value)
+                .item("expires_at", AttributeValue::N(expires_at.to_string()))
+                .send(),
+        )??;
         Ok(())
     }
 
     /// FIXME: should delete return a success if it is a noop
     /// or should it return an error if the key is not found?
-    pub fn delete(&self, key: &str) -> Result<()> {
-        let key_attribute = AttributeValue::S(key.into());
-        log::info!("Deleting key: {}", key);
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        let key_attribute = AttributeValue::B(Blob::new(key));
+        log::info!("Deleting key: {}", keys::display(key));
         block_on(
             self.client
                 .delete_item()
                 .table_name(&self.table_name)
                 .key("key", key_attribute)
                 .send(),
-        )?;
+        )??;
         Ok(())
     }
+
+    /// Lists all keys, paginating through a scan of the table.
+    pub fn list_keys(&self) -> Result<Vec<Vec<u8>>> {
+        log::info!("Listing keys of table: {}", self.table_name);
+        let mut keys = Vec::new();
+        let mut start_key = None;
+        loop {
+            let res = block_on(
+                self.client
+                    .scan()
+                    .table_name(&self.table_name)
+                    .projection_expression("#key".to_string())
+                    .filter_expression(NOT_EXPIRED_FILTER.to_string())
+                    .expression_attribute_names("#key".to_string(), "key".to_string())
+                    .expression_attribute_names("#expires_at".to_string(), "expires_at".to_string())
+                    .expression_attribute_values(":now".to_string(), unix_now()?)
+                    .set_exclusive_start_key(start_key)
+                    .send(),
+            )??;
+            for item in res.items.unwrap_or_default() {
+                let key = item
+                    .get("key")
+                    .and_then(|k| k.as_b().ok())
+                    .with_context(|| "found an item w/o a binary key")?;
+                keys.push(key.as_ref().to_vec());
+            }
+            start_key = res.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(keys);
+            }
+        }
+    }
+
+    /// Lists a page of the keys a scan of up to `max` items of the table (so, w/ expired items
+    /// filtered out of it, a page may have fewer keys) finds after the key of `cursor` (i.e.,
+    /// the scan's `LastEvaluatedKey` of the previous page), w/ the cursor of the next page —
+    /// none, if it's the last one.
+    pub fn list_keys_page(
+        &self,
+        cursor: Option<&str>,
+        max: usize,
+    ) -> Result<(Vec<Vec<u8>>, Option<String>)> {
+        let start_key = cursor
+            .map(|cursor| -> Result<_> {
+                let key = keys::decode(cursor).with_context(|| "invalid key stream cursor")?;
+                Ok(HashMap::from([(
+                    "key".to_string(),
+                    AttributeValue::B(Blob::new(key)),
+                )]))
+            })
+            .transpose()?;
+        let res = block_on(
+            self.client
+                .scan()
+                .table_name(&self.table_name)
+                .projection_expression("#key".to_string())
+                .filter_expression(NOT_EXPIRED_FILTER.to_string())
+                .expression_attribute_names("#key".to_string(), "key".to_string())
+                .expression_attribute_names("#expires_at".to_string(), "expires_at".to_string())
+                .expression_attribute_values(":now".to_string(), unix_now()?)
+                .limit(max.min(i32::MAX as usize) as i32)
+                .set_exclusive_start_key(start_key)
+                .send(),
+        )??;
+        let mut keys = Vec::new();
+        for item in res.items.unwrap_or_default() {
+            let key = item
+                .get("key")
+                .and_then(|k| k.as_b().ok())
+                .with_context(|| "found an item w/o a binary key")?;
+            keys.push(key.as_ref().to_vec());
+        }
+        let next = match res.last_evaluated_key {
+            Some(last) => Some(keys::encode(
+                last.get("key")
+                    .and_then(|k| k.as_b().ok())
+                    .with_context(|| "the scan's last evaluated key isn't a binary key")?
+                    .as_ref(),
+            )),
+            None => None,
+        };
+        Ok((keys, next))
+    }
+
+    /// Deletes all keys starting with `prefix`, returning how many were deleted.
+    ///
+    /// Keys are deleted w/ `BatchWriteItem`, in batches of as many items as
+    /// DynamoDB allows per request, retrying any items it didn't get to.
+    pub fn clear(&self, prefix: &[u8]) -> Result<u64> {
+        let keys = self
+            .list_keys()?
+            .into_iter()
+            .filter(|key| key.starts_with(prefix))
+            .collect::<Vec<_>>();
+        for batch in keys.chunks(MAX_BATCH_WRITE_ITEMS) {
+            let requests = batch
+                .iter()
+                .map(|key| {
+                    WriteRequest::builder()
+                        .delete_request(
+                            DeleteRequest::builder()
+                                .key("key", AttributeValue::B(Blob::new(key.as_slice())))
+                                .build(),
+                        )
+                        .build()
+                })
+                .collect::<Vec<_>>();
+            let mut pending = HashMap::from([(self.table_name.clone(), requests)]);
+            while !pending.is_empty() {
+                let res = block_on(
+                    self.client
+                        .batch_write_item()
+                        .set_request_items(Some(pending))
+                        .send(),
+                )?
+                .with_context(|| "failed to delete batch of keys")?;
+                pending = res.unprocessed_items.unwrap_or_default();
+                pending.retain(|_, requests| !requests.is_empty());
+            }
+        }
+        log::info!("Cleared {} keys of table: {}", keys.len(), self.table_name);
+        Ok(keys.len() as u64)
+    }
+}
+
+/// The maximum number of items DynamoDB accepts in a single `BatchWriteItem` request.
+const MAX_BATCH_WRITE_ITEMS: usize = 25;
+
+/// The maximum number of keys DynamoDB accepts in a single `BatchGetItem` request.
+const MAX_BATCH_GET_ITEMS: usize = 100;
+
+/// Filters out items that expired, but that DynamoDB's TTL process hasn't deleted yet.
+const NOT_EXPIRED_FILTER: &str = "attribute_not_exists(#expires_at) OR #expires_at > :now";
+
+/// Whether an item expired (as of `now`, in unix secs), like `NOT_EXPIRED_FILTER` has it, as
+/// `BatchGetItem` can't filter items.
+fn expired(item: &HashMap<String, AttributeValue>, now: u64) -> bool {
+    match item.get("expires_at") {
+        Some(AttributeValue::N(expires_at)) => {
+            expires_at.parse::<u64>().map_or(false, |t| t <= now)
+        }
+        _ => false,
+    }
+}
+
+/// Gets the current unix time in secs as a DynamoDB number.
+fn unix_now() -> Result<AttributeValue> {
+    Ok(AttributeValue::N(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)?
+            .as_secs()
+            .to_string(),
+    ))
 }
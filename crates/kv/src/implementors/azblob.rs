@@ -1,92 +1,280 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use azure_core::HttpClient;
 use azure_storage::clients::StorageAccountClient;
 use azure_storage_blobs::prelude::{AsBlobClient, AsContainerClient, ContainerClient};
-use futures::executor::block_on;
-use slight_runtime::resource::BasicState;
+use slight_runtime::deadline::block_on;
+use slight_runtime::{describe::Description, resource::BasicState, support::Unsupported};
 
-use crate::providers::azure;
+use crate::{keys, providers::azure};
 
 /// This is the underlying struct behind the `AzBlob` variant of the `KvImplementor` enum.
 ///
-/// It provides a property that pertains solely to the azblob implementation
+/// It provides properties that pertain solely to the azblob implementation
 /// of this capability:
-///     - `container_client`
+///     - `container_client`, and
+///     - the `storage_account_key` it was created w/.
 ///
-/// As per its' usage in `KvImplementor`, it must `derive` `Debug`, and `Clone`.
-#[derive(Debug, Clone)]
+/// The storage account key is a credential (see `slight_runtime_configs::credential`), so,
+/// when it is rotated, the container client is recreated w/ the new key on its next use.
+///
+/// As per its usage in `KvImplementor`, it must implement `Debug`, and `Clone`.
+#[derive(Clone)]
 pub struct AzBlobImplementor {
-    container_client: Option<Arc<ContainerClient>>,
+    container_client: Arc<Mutex<(String, Arc<ContainerClient>)>>,
+    container_name: String,
+    storage_account_name: String,
+    http_client: Arc<dyn HttpClient>,
+    slight_state: BasicState,
+}
+
+impl std::fmt::Debug for AzBlobImplementor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AzBlobImplementor")
+    }
 }
 
 impl AzBlobImplementor {
-    pub fn new(slight_state: &BasicState, name: &str) -> Self {
+    /// Describes the storage account stores are kept in (see `slight_runtime::describe`).
+    pub fn describe(slight_state: &BasicState) -> Description {
+        let account = slight_runtime_configs::setting(slight_state, "AZURE_STORAGE_ACCOUNT");
+        let endpoint = account
+            .as_ref()
+            .map(|account| format!("{}.blob.core.windows.net", account))
+            .map_err(|e| anyhow!("{:#}", e));
+        Description::new("kv.azblob", "Azure Blob Storage")
+            .with_endpoint("AZURE_STORAGE_ACCOUNT", endpoint)
+            .with_setting("AZURE_STORAGE_ACCOUNT", account)
+            .with_secret(
+                "AZURE_STORAGE_KEY",
+                slight_runtime_configs::setting(slight_state, "AZURE_STORAGE_KEY"),
+            )
+    }
+
+    pub fn new(slight_state: &BasicState, name: &str) -> Result<Self> {
         let storage_account_name = String::from_utf8(
-            slight_runtime_configs::get(
-                &slight_state.secret_store,
+            slight_runtime_configs::resolve(
+                &slight_state.secret_stores,
                 "AZURE_STORAGE_ACCOUNT",
                 &slight_state.config_toml_file_path,
             )
             .with_context(|| {
                 format!(
-                    "failed to get 'AZURE_STORAGE_ACCOUNT' secret using secret store type: {}",
-                    slight_state.secret_store
+                    "failed to get 'AZURE_STORAGE_ACCOUNT' secret using secret stores: {:?}",
+                    slight_state.secret_stores
                 )
-            })
-            .unwrap(),
+            })?,
         )
-        .unwrap();
-        let storage_account_key = String::from_utf8(
-            slight_runtime_configs::get(
-                &slight_state.secret_store,
-                "AZURE_STORAGE_KEY",
-                &slight_state.config_toml_file_path,
-            )
-            .with_context(|| {
-                format!(
-                    "failed to get 'AZURE_STORAGE_KEY' secret using secret store type: {}",
-                    slight_state.secret_store
-                )
-            })
-            .unwrap(),
-        )
-        .unwrap();
+        .context("'AZURE_STORAGE_ACCOUNT' isn't valid UTF-8")?;
+        let storage_account_key = storage_account_key(slight_state)?;
 
         let http_client = azure_core::new_http_client();
-        let container_client = Some(
-            StorageAccountClient::new_access_key(
-                http_client.clone(),
-                storage_account_name,
-                storage_account_key,
-            )
-            .as_container_client(name),
+        let container_client = container_client(
+            http_client.clone(),
+            &storage_account_name,
+            &storage_account_key,
+            name,
         );
-        Self { container_client }
+        Ok(Self {
+            container_client: Arc::new(Mutex::new((storage_account_key, container_client))),
+            container_name: name.to_string(),
+            storage_account_name,
+            http_client,
+            slight_state: slight_state.clone(),
+        })
     }
 
-    pub fn get(&self, key: &str) -> Result<Vec<u8>> {
-        let inner = self.container_client.as_ref().unwrap();
-        let blob_client = inner.as_blob_client(key);
-        let res = block_on(azure::get(blob_client))
-            .with_context(|| format!("failed to get value for key {}", key))?;
+    /// Gets the container client, recreating it first if the storage account key was rotated.
+    ///
+    /// The http client is shared by every container client, so its connections are reused.
+    fn container_client(&self) -> Result<Arc<ContainerClient>> {
+        let storage_account_key = storage_account_key(&self.slight_state)?;
+        let mut inner = self.container_client.lock().unwrap();
+        if inner.0 != storage_account_key {
+            tracing::info!(
+                "recreating the client for container '{}' w/ the rotated storage account key",
+                self.container_name
+            );
+            let container_client = container_client(
+                self.http_client.clone(),
+                &self.storage_account_name,
+                &storage_account_key,
+                &self.container_name,
+            );
+            *inner = (storage_account_key, container_client);
+        }
+        Ok(inner.1.clone())
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Vec<u8>> {
+        let inner = self.container_client()?;
+        let blob_client = inner.as_blob_client(keys::encode(key));
+        let res = block_on(azure::get(blob_client))?
+            .with_context(|| format!("failed to get value for key {}", keys::display(key)))?;
         Ok(res)
     }
 
-    pub fn set(&self, key: &str, value: &[u8]) -> Result<()> {
-        let inner = self.container_client.as_ref().unwrap();
+    /// Gets the values of many keys (e.g., a batch of gets, see `Batcher`) concurrently, as
+    /// blob storage has no batch read, returning the result of each.
+    pub fn get_many(&self, requested: &[Vec<u8>]) -> Result<Vec<Result<Vec<u8>>>> {
+        let inner = self.container_client()?;
+        let gets = requested.iter().map(|key| {
+            let blob_client = inner.as_blob_client(keys::encode(key));
+            async move {
+                azure::get(blob_client)
+                    .await
+                    .with_context(|| format!("failed to get value for key {}", keys::display(key)))
+            }
+        });
+        Ok(block_on(futures::future::join_all(gets))?)
+    }
 
-        let blob_client = inner.as_blob_client(key);
+    /// Reads up to `length` bytes of a key's value, starting at `offset`, w/ a ranged
+    /// read (i.e., only the requested bytes are transferred).
+    pub fn get_range(&self, key: &[u8], offset: u64, length: u64) -> Result<Vec<u8>> {
+        let inner = self.container_client()?;
+        let blob_client = inner.as_blob_client(keys::encode(key));
+        let res = block_on(azure::get_range(blob_client, offset, length))?
+            .with_context(|| format!("failed to get value for key {}", keys::display(key)))?;
+        Ok(res)
+    }
+
+    /// Like `get`, but returns `None` instead of failing if the key doesn't exist
+    pub fn get_opt(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let inner = self.container_client()?;
+        let blob_client = inner.as_blob_client(keys::encode(key));
+        let res = block_on(azure::get_with_etag(blob_client))?
+            .with_context(|| format!("failed to get value for key {}", keys::display(key)))?;
+        Ok(res.map(|(value, _)| value))
+    }
+
+    /// Sets the value of a key only if its current value is `expected` (where `None`
+    /// means the key must not exist yet), returning whether the swap happened.
+    ///
+    /// This relies on the blob's etag, so concurrent writers (even from other hosts)
+    /// can't interleave between our read and our write.
+    pub fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        value: &[u8],
+    ) -> Result<bool> {
+        let inner = self.container_client()?;
+        let blob_name = keys::encode(key);
+        let current = block_on(azure::get_with_etag(inner.as_blob_client(&blob_name)))?
+            .with_context(|| format!("failed to get value for key {}", keys::display(key)))?;
+        if current.as_ref().map(|(v, _)| v.as_slice()) != expected {
+            return Ok(false);
+        }
+        block_on(azure::set_if_match(
+            inner.as_blob_client(&blob_name),
+            Vec::from(value),
+            current.map(|(_, etag)| etag),
+        ))?
+        .with_context(|| format!("failed to set value for key '{}'", keys::display(key)))
+    }
+
+    pub fn set(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        let inner = self.container_client()?;
+
+        let blob_client = inner.as_blob_client(keys::encode(key));
         let value = Vec::from(value);
-        block_on(azure::set(blob_client, value))
-            .with_context(|| format!("failed to set value for key '{}'", key))?;
+        block_on(azure::set(blob_client, value))?
+            .with_context(|| format!("failed to set value for key '{}'", keys::display(key)))?;
         Ok(())
     }
 
-    pub fn delete(&self, key: &str) -> Result<()> {
-        let inner = &self.container_client.as_ref().unwrap();
-        let blob_client = inner.as_blob_client(key);
-        block_on(azure::delete(blob_client)).with_context(|| "failed to delete key's value")?;
+    /// Blob storage has no per-blob expiry (lifecycle management policies only
+    /// work in days), so this isn't supported.
+    pub fn set_with_time_to_live(
+        &self,
+        _key: &[u8],
+        _value: &[u8],
+        _time_to_live_in_secs: u64,
+    ) -> Result<()> {
+        Err(Unsupported::new(
+            "kv.azblob",
+            "set-with-time-to-live",
+            "blobs have no expiry (lifecycle management policies only work in days)",
+        )
+        .into())
+    }
+
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        let inner = self.container_client()?;
+        let blob_client = inner.as_blob_client(keys::encode(key));
+        block_on(azure::delete(blob_client))?.with_context(|| "failed to delete key's value")?;
         Ok(())
     }
+
+    /// Lists all keys, decoded back into their original bytes.
+    ///
+    /// Blob names are encoded keys (see `keys::encode`), so blobs that weren't
+    /// created through this capability are skipped.
+    pub fn list_keys(&self) -> Result<Vec<Vec<u8>>> {
+        let inner = self.container_client()?;
+        let names =
+            block_on(azure::list_blob_names(&inner))?.with_context(|| "failed to list keys")?;
+        Ok(names
+            .iter()
+            .filter_map(|name| keys::decode(name).ok())
+            .collect())
+    }
+
+    /// Lists a page of up to `max` keys (in the order of their blob names), starting at the
+    /// continuation marker `cursor` of the previous page (if any), w/ the marker of the next page
+    /// — none, if it's the last one.
+    pub fn list_keys_page(
+        &self,
+        cursor: Option<&str>,
+        max: usize,
+    ) -> Result<(Vec<Vec<u8>>, Option<String>)> {
+        let inner = self.container_client()?;
+        let (names, next) = block_on(azure::list_blob_names_page(&inner, cursor, max))?
+            .with_context(|| "failed to list keys")?;
+        Ok((
+            names
+                .iter()
+                .filter_map(|name| keys::decode(name).ok())
+                .collect(),
+            next,
+        ))
+    }
+
+    /// Deletes all keys starting with `prefix`, returning how many were deleted.
+    ///
+    /// Blob storage has no bulk delete we can use here, so this deletes
+    /// blobs one by one.
+    pub fn clear(&self, prefix: &[u8]) -> Result<u64> {
+        let mut cleared = 0;
+        for key in self.list_keys()? {
+            if key.starts_with(prefix) {
+                self.delete(&key)?;
+                cleared += 1;
+            }
+        }
+        Ok(cleared)
+    }
+}
+
+fn storage_account_key(slight_state: &BasicState) -> Result<String> {
+    let storage_account_key = slight_runtime_configs::credential(slight_state, "AZURE_STORAGE_KEY")
+        .with_context(|| {
+            format!(
+                "failed to get 'AZURE_STORAGE_KEY' secret using secret stores: {:?}",
+                slight_state.secret_stores
+            )
+        })?;
+    Ok(String::from_utf8(storage_account_key)?)
+}
+
+fn container_client(
+    http_client: Arc<dyn HttpClient>,
+    storage_account_name: &str,
+    storage_account_key: &str,
+    container_name: &str,
+) -> Arc<ContainerClient> {
+    StorageAccountClient::new_access_key(http_client, storage_account_name, storage_account_key)
+        .as_container_client(container_name)
 }
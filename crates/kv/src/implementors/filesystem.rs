@@ -1,9 +1,11 @@
 use std::{
+    collections::BinaryHeap,
     env,
     fs::{self, File},
-    io::{Read, Write},
-    path::PathBuf,
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{Context, Result};
@@ -11,66 +13,345 @@ use chrono::Utc;
 use crossbeam_channel::Sender;
 use notify::{Event as NotifyEvent, RecommendedWatcher, RecursiveMode, Watcher};
 use slight_events_api::{Event, EventBuilder, EventBuilderV10};
+use slight_runtime::{cause, describe::Description, error_kind::NotFound};
 use uuid::Uuid;
 
+use crate::keys;
+
+/// Serializes compare-and-swap operations across every filesystem kv store in this process.
+///
+/// Note: this does not protect against other processes writing to the same base directory.
+static CAS_LOCK: Mutex<()> = Mutex::new(());
+
+/// How far apart the clocks of hosts sharing a base directory are assumed to be, at most.
+///
+/// Keys set w/ a time to live are stored w/ an absolute expiry time taken from the
+/// clock of the host that set them, and each reader compares it against its own
+/// clock. So, a reader whose clock is behind (or ahead) of the writer's sees
+/// the key live for longer (or shorter) than it should. To make sure a reader
+/// whose clock is ahead can't destroy a value that other hosts still consider
+/// live, expired keys are only removed once they've been expired for this long.
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5);
+
+/// A source of the current time (i.e., what lets tests simulate skewed clocks).
+pub trait Clock: std::fmt::Debug + Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The host's clock.
+#[derive(Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
 /// This is the underlying struct behind the `Filesystem` variant of the `KvImplementor` enum.
 ///
-/// It provides two properties that pertain solely to the filesystem implementation of
+/// It provides three properties that pertain solely to the filesystem implementation of
 /// of this capability:
-///     - `base`, and
-///     - `watchers`.
+///     - `base`,
+///     - `watchers`, and
+///     - `clock`.
 ///
-/// As per its' usage in `KvImplementor`, it must `derive` `Debug`, and `Clone`.
+/// As per its usage in `KvImplementor`, it must `derive` `Debug`, and `Clone`.
 #[derive(Debug, Clone)]
 pub struct FilesystemImplementor {
     /// The base path for where the key-value store can be found in your file-system
     pub base: String,
     /// A group of `*Watcher`s that are observing a key
     pub watchers: Vec<Arc<Mutex<RecommendedWatcher>>>,
+    /// The clock used to expire keys set w/ a time to live
+    pub clock: Arc<dyn Clock>,
 }
 
 impl FilesystemImplementor {
     pub fn new(name: &str) -> Self {
+        Self::with_clock(name, Arc::new(SystemClock))
+    }
+
+    /// Describes where stores are kept (see `slight_runtime::describe`) — each in a directory
+    /// of this one, named after it.
+    pub fn describe() -> Description {
+        Description::new("kv.filesystem", "the filesystem")
+            .with_setting("directory", Ok(env::temp_dir().display().to_string()))
+    }
+
+    pub fn with_clock(name: &str, clock: Arc<dyn Clock>) -> Self {
         Self {
             base: env::temp_dir().join(name).to_str().unwrap().to_owned(),
             watchers: Vec::new(),
+            clock,
         }
     }
 
-    pub fn get(&self, key: &str) -> Result<Vec<u8>> {
+    /// Gets the path to the file holding the value of a key.
+    ///
+    /// As file names can't be arbitrary bytes, keys are encoded (see `keys::encode`).
+    fn path(&self, key: &[u8]) -> PathBuf {
+        PathBuf::from(&self.base).join(keys::encode(key))
+    }
+
+    /// Gets the path to the file holding the expiry time of a key (if it has one).
+    ///
+    /// It is a hidden file, so it can never be mistaken for a key.
+    fn expiry_path(&self, key: &[u8]) -> PathBuf {
+        PathBuf::from(&self.base).join(format!(".{}.expiry", keys::encode(key)))
+    }
+
+    /// Gets the time a key expires at, if it was set w/ a time to live.
+    fn expires_at(&self, key: &[u8]) -> Result<Option<SystemTime>> {
+        let expiry = match fs::read_to_string(self.expiry_path(key)) {
+            Ok(expiry) => expiry,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| "failed to read key's expiry time"),
+        };
+        let millis = expiry
+            .trim()
+            .parse::<u64>()
+            .with_context(|| format!("invalid expiry time '{}'", expiry))?;
+        Ok(Some(UNIX_EPOCH + Duration::from_millis(millis)))
+    }
+
+    /// Checks whether a key has expired, as per our `clock`.
+    ///
+    /// A key is only removed once it has been expired for longer than `MAX_CLOCK_SKEW`.
+    fn is_expired(&self, key: &[u8]) -> Result<bool> {
+        let expires_at = match self.expires_at(key)? {
+            Some(expires_at) => expires_at,
+            None => return Ok(false),
+        };
+        let now = self.clock.now();
+        if now < expires_at {
+            return Ok(false);
+        }
+        if now >= expires_at + MAX_CLOCK_SKEW {
+            remove_if_exists(&self.path(key)).with_context(|| "failed to remove expired key")?;
+            remove_if_exists(&self.expiry_path(key))
+                .with_context(|| "failed to remove expired key's expiry time")?;
+        }
+        Ok(true)
+    }
+
+    /// Opens the file holding the value of a key, failing w/ `NotFound` if the key doesn't
+    /// exist (or has expired).
+    fn open_value(&self, key: &[u8]) -> Result<File> {
+        if self.is_expired(key)? {
+            return Err(NotFound(format!(
+                "failed to get key {}: key has expired",
+                keys::display(key)
+            ))
+            .into());
+        }
+        match File::open(self.path(key)) {
+            Ok(file) => Ok(file),
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(NotFound(format!(
+                "failed to get key {}: key doesn't exist",
+                keys::display(key)
+            ))
+            .into()),
+            Err(e) => Err(e).with_context(|| "failed to get key"),
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Vec<u8>> {
         fs::create_dir_all(&self.base)
             .with_context(|| "failed to create base directory for kv store instance")?;
-        let mut file =
-            File::open(PathBuf::from(&self.base).join(key)).with_context(|| "failed to get key")?;
+        let mut file = self.open_value(key)?;
 
-        let mut buf = Vec::new();
+        // the value is read into a buffer of its exact size, which is handed as is to the guest
+        let mut buf = Vec::with_capacity(capacity(file.metadata()?.len()));
         file.read_to_end(&mut buf)
             .with_context(|| "failed to read key's value")?;
         Ok(buf)
     }
 
-    pub fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+    /// Reads up to `length` bytes of a key's value, starting at `offset`, w/o reading
+    /// the rest of the file.
+    pub fn get_range(&self, key: &[u8], offset: u64, length: u64) -> Result<Vec<u8>> {
+        fs::create_dir_all(&self.base)
+            .with_context(|| "failed to create base directory for kv store instance")?;
+        let mut file = self.open_value(key)?;
+
+        let len = file.metadata()?.len();
+        let mut buf = Vec::new();
+        if offset < len {
+            // a `Take` has no size hint, so w/o this the buffer would grow (and be copied)
+            // as the range is read
+            buf.reserve_exact(capacity(length.min(len - offset)));
+            file.seek(SeekFrom::Start(offset))?;
+            file.take(length)
+                .read_to_end(&mut buf)
+                .with_context(|| "failed to read key's value")?;
+        }
+        Ok(buf)
+    }
+
+    /// Like `get`, but returns `None` instead of failing if the key doesn't exist
+    pub fn get_opt(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        fs::create_dir_all(&self.base)
+            .with_context(|| "failed to create base directory for kv store instance")?;
+        if self.is_expired(key)? {
+            return Ok(None);
+        }
+        match fs::read(self.path(key)) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| "failed to read key's value"),
+        }
+    }
+
+    /// Sets the value of a key only if its current value is `expected` (where `None`
+    /// means the key must not exist yet), returning whether the swap happened.
+    pub fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        value: &[u8],
+    ) -> Result<bool> {
+        let _guard = CAS_LOCK.lock().unwrap();
+        if self.get_opt(key)?.as_deref() != expected {
+            return Ok(false);
+        }
+        self.set(key, value)?;
+        Ok(true)
+    }
+
+    pub fn set(&self, key: &[u8], value: &[u8]) -> Result<()> {
         fs::create_dir_all(&self.base)
             .with_context(|| "failed to create base directory for kv store instance")?;
 
-        let mut file = File::create(PathBuf::from(&self.base).join(key))
-            .with_context(|| "failed to create key")?;
+        let path = self.path(key);
+        // before it's written, as its watchers may notice it before this returns
+        cause::defer(&path.display().to_string());
+        let mut file = File::create(path).with_context(|| "failed to create key")?;
 
         file.write_all(value)
             .with_context(|| "failed to set key's value")?;
+        // setting a key w/o a time to live makes it live forever
+        remove_if_exists(&self.expiry_path(key))
+            .with_context(|| "failed to clear key's expiry time")?;
         Ok(())
     }
 
-    pub fn delete(&self, key: &str) -> Result<()> {
+    /// Sets the value of a key, which expires after `time_to_live_in_secs`
+    /// (see `MAX_CLOCK_SKEW` for what to expect when hosts' clocks disagree).
+    pub fn set_with_time_to_live(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        time_to_live_in_secs: u64,
+    ) -> Result<()> {
+        let expires_at = self.clock.now() + Duration::from_secs(time_to_live_in_secs);
+        let millis = expires_at
+            .duration_since(UNIX_EPOCH)
+            .with_context(|| "clock is set before the unix epoch")?
+            .as_millis();
+        self.set(key, value)?;
+        fs::write(self.expiry_path(key), millis.to_string())
+            .with_context(|| "failed to set key's expiry time")?;
+        Ok(())
+    }
+
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
         fs::create_dir_all(&self.base)
             .with_context(|| "failed to create base directory for kv store instance")?;
-        fs::remove_file(PathBuf::from(&self.base).join(key))
-            .with_context(|| "failed to delete key's value")?;
+        let path = self.path(key);
+        cause::defer(&path.display().to_string());
+        fs::remove_file(path).with_context(|| "failed to delete key's value")?;
+        remove_if_exists(&self.expiry_path(key))
+            .with_context(|| "failed to delete key's expiry time")?;
         Ok(())
     }
 
+    /// Lists all keys, decoded back into their original bytes.
+    ///
+    /// Files that aren't valid key encodings (e.g., hidden files) are skipped.
+    pub fn list_keys(&self) -> Result<Vec<Vec<u8>>> {
+        fs::create_dir_all(&self.base)
+            .with_context(|| "failed to create base directory for kv store instance")?;
+        let mut keys = Vec::new();
+        for entry in fs::read_dir(&self.base).with_context(|| "failed to list keys")? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            match entry.file_name().to_str().map(keys::decode) {
+                Some(Ok(key)) => {
+                    if !self.is_expired(&key)? {
+                        keys.push(key);
+                    }
+                }
+                _ => tracing::debug!("skipping non-key file {:?}", entry.file_name()),
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Lists a page of up to `max` keys (in the order of their file names) after the file name
+    /// of `cursor` (i.e., the last one the previous page listed), w/ the cursor of the next page
+    /// — none, if it's the last one.
+    ///
+    /// Directories have no native pagination, so the directory is read in full for every page,
+    /// but only the `max` file names of the page are held at once.
+    pub fn list_keys_page(
+        &self,
+        cursor: Option<&str>,
+        max: usize,
+    ) -> Result<(Vec<Vec<u8>>, Option<String>)> {
+        fs::create_dir_all(&self.base)
+            .with_context(|| "failed to create base directory for kv store instance")?;
+        // the `max` smallest file names after the cursor, w/ the largest on top
+        let mut page = BinaryHeap::with_capacity(max + 1);
+        let mut more = false;
+        for entry in fs::read_dir(&self.base).with_context(|| "failed to list keys")? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let name = match entry.file_name().into_string() {
+                Ok(name) if keys::decode(&name).is_ok() => name,
+                _ => continue,
+            };
+            if matches!(cursor, Some(cursor) if name.as_str() <= cursor) {
+                continue;
+            }
+            page.push(name);
+            if page.len() > max {
+                page.pop();
+                more = true;
+            }
+        }
+        let names = page.into_sorted_vec();
+        let next = if more { names.last().cloned() } else { None };
+        let mut keys = Vec::with_capacity(names.len());
+        for name in &names {
+            let key = keys::decode(name)?;
+            if !self.is_expired(&key)? {
+                keys.push(key);
+            }
+        }
+        Ok((keys, next))
+    }
+
+    /// Deletes all keys starting with `prefix`, returning how many were deleted.
+    pub fn clear(&self, prefix: &[u8]) -> Result<u64> {
+        let mut cleared = 0;
+        for key in self.list_keys()? {
+            if key.starts_with(prefix) {
+                self.delete(&key)?;
+                cleared += 1;
+            }
+        }
+        Ok(cleared)
+    }
+
     pub fn watch(&mut self, key: &str, sender: Arc<Mutex<Sender<Event>>>) -> Result<()> {
-        let path = PathBuf::from(&self.base).join(key);
+        let path = self.path(key.as_bytes());
+        // the causes of writes to it are remembered for as long as it's watched
+        let watched = cause::watch(&path.display().to_string());
         let key = key.to_string();
         let mut watcher =
             notify::recommended_watcher(move |res: Result<NotifyEvent, _>| match res {
@@ -84,7 +365,7 @@ impl FilesystemImplementor {
                         .unwrap_or_default();
                     let content_type = "application/json";
                     let data = serde_json::json!({ "key": key });
-                    let event = EventBuilderV10::new()
+                    let mut event = EventBuilderV10::new()
                         .id(id)
                         .source(path)
                         .ty(format!("{:#?}", event.kind))
@@ -96,6 +377,8 @@ impl FilesystemImplementor {
                             tracing::error!("failed to build event: {}, sending default event", e);
                             Event::default()
                         });
+                    // the guest handling it is traced under the call that wrote the key
+                    cause::mark_deferred(&mut event, watched.resource());
                     sender
                         .lock()
                         .unwrap()
@@ -119,3 +402,205 @@ impl FilesystemImplementor {
         Ok(())
     }
 }
+
+/// The capacity of a buffer for `len` bytes (files can be larger than what fits in memory, in
+/// which case reading them fails anyway).
+fn capacity(len: u64) -> usize {
+    usize::try_from(len).unwrap_or(usize::MAX)
+}
+
+/// Removes a file, succeeding if it didn't exist in the first place.
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, SystemTime},
+    };
+
+    use anyhow::Result;
+    use uuid::Uuid;
+
+    use super::{Clock, FilesystemImplementor, MAX_CLOCK_SKEW};
+
+    /// A clock that only moves when told to.
+    #[derive(Debug)]
+    struct TestClock(Mutex<SystemTime>);
+
+    impl TestClock {
+        fn new(now: SystemTime) -> Arc<Self> {
+            Arc::new(Self(Mutex::new(now)))
+        }
+
+        fn set(&self, now: SystemTime) {
+            *self.0.lock().unwrap() = now;
+        }
+    }
+
+    impl Clock for TestClock {
+        fn now(&self) -> SystemTime {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    /// Creates two stores sharing a base directory (i.e., two hosts), each w/ its own clock.
+    fn hosts(
+        writer_clock: Arc<TestClock>,
+        reader_clock: Arc<TestClock>,
+    ) -> (FilesystemImplementor, FilesystemImplementor) {
+        let name = format!("slight-kv-test-{}", Uuid::new_v4());
+        (
+            FilesystemImplementor::with_clock(&name, writer_clock),
+            FilesystemImplementor::with_clock(&name, reader_clock),
+        )
+    }
+
+    const TTL: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn keys_expire_after_time_to_live() -> Result<()> {
+        let t = SystemTime::now();
+        let clock = TestClock::new(t);
+        let (kv, _) = hosts(clock.clone(), clock.clone());
+        kv.set_with_time_to_live(b"key", b"value", TTL.as_secs())?;
+        assert_eq!(kv.get(b"key")?, b"value");
+
+        clock.set(t + TTL);
+        assert!(kv.get_opt(b"key")?.is_none());
+        assert!(kv.get(b"key").is_err());
+        assert!(kv.list_keys()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn set_clears_time_to_live() -> Result<()> {
+        let t = SystemTime::now();
+        let clock = TestClock::new(t);
+        let (kv, _) = hosts(clock.clone(), clock.clone());
+        kv.set_with_time_to_live(b"key", b"value", TTL.as_secs())?;
+        kv.set(b"key", b"forever")?;
+
+        clock.set(t + TTL * 100);
+        assert_eq!(kv.get(b"key")?, b"forever");
+        kv.delete(b"key")?;
+        Ok(())
+    }
+
+    #[test]
+    fn reader_behind_sees_key_until_its_own_clock_expires_it() -> Result<()> {
+        let t = SystemTime::now();
+        let writer_clock = TestClock::new(t);
+        let (writer, reader) = hosts(writer_clock.clone(), TestClock::new(t - TTL / 2));
+        writer.set_with_time_to_live(b"key", b"value", TTL.as_secs())?;
+
+        // the key expired for the writer, but the reader's clock is behind
+        writer_clock.set(t + TTL);
+        assert!(writer.get_opt(b"key")?.is_none());
+        assert_eq!(reader.get_opt(b"key")?, Some(b"value".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn reader_ahead_does_not_remove_keys_others_consider_live() -> Result<()> {
+        let t = SystemTime::now();
+        let (writer, reader) = hosts(
+            TestClock::new(t),
+            TestClock::new(t + TTL + MAX_CLOCK_SKEW / 2),
+        );
+        writer.set_with_time_to_live(b"key", b"value", TTL.as_secs())?;
+
+        // the reader's clock is ahead, so the key expired for it, but not for the writer
+        assert!(reader.get_opt(b"key")?.is_none());
+        assert_eq!(writer.get_opt(b"key")?, Some(b"value".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn keys_are_removed_once_expired_beyond_skew() -> Result<()> {
+        let t = SystemTime::now();
+        let reader_clock = TestClock::new(t);
+        let (writer, reader) = hosts(TestClock::new(t), reader_clock.clone());
+        writer.set_with_time_to_live(b"key", b"value", TTL.as_secs())?;
+
+        reader_clock.set(t + TTL + MAX_CLOCK_SKEW);
+        assert!(reader.get_opt(b"key")?.is_none());
+        // not even a writer whose clock says the key is live can see it anymore
+        assert!(writer.get_opt(b"key")?.is_none());
+        assert!(!reader.path(b"key").exists());
+        assert!(!reader.expiry_path(b"key").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn clear_only_deletes_keys_under_prefix() -> Result<()> {
+        let kv = FilesystemImplementor::new(&format!("slight-kv-test-{}", Uuid::new_v4()));
+        kv.set(b"app/a", b"1")?;
+        kv.set(b"app/b", b"2")?;
+        kv.set(b"other", b"3")?;
+
+        assert_eq!(kv.clear(b"app/")?, 2);
+        assert_eq!(kv.list_keys()?, vec![b"other".to_vec()]);
+
+        assert_eq!(kv.clear(b"")?, 1);
+        assert!(kv.list_keys()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn values_of_any_size_round_trip() -> Result<()> {
+        let kv = FilesystemImplementor::new(&format!("slight-kv-test-{}", Uuid::new_v4()));
+        for size in [0, 1, 4096, 1 << 20] {
+            let value = (0..size).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+            kv.set(b"key", &value)?;
+            assert_eq!(kv.get(b"key")?, value);
+            assert_eq!(
+                kv.get_range(b"key", 1, u64::MAX)?,
+                value.get(1..).unwrap_or_default()
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn get_range_reads_only_requested_bytes() -> Result<()> {
+        let kv = FilesystemImplementor::new(&format!("slight-kv-test-{}", Uuid::new_v4()));
+        kv.set(b"key", b"spiderlightning")?;
+
+        assert_eq!(kv.get_range(b"key", 6, 5)?, b"light");
+        assert_eq!(kv.get_range(b"key", 6, 100)?, b"lightning");
+        assert!(kv.get_range(b"key", 100, 5)?.is_empty());
+        assert!(kv.get_range(b"missing", 0, 5).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn list_keys_page_test() -> Result<()> {
+        let t = SystemTime::now();
+        let clock = TestClock::new(t);
+        let (kv, _) = hosts(clock.clone(), clock.clone());
+        for key in [b"a", b"b", b"c", b"d"] {
+            kv.set(key, b"value")?;
+        }
+        kv.set_with_time_to_live(b"bb", b"value", TTL.as_secs())?;
+        clock.set(t + TTL);
+
+        let (keys, cursor) = kv.list_keys_page(None, 2)?;
+        assert_eq!(keys, vec![b"a".to_vec(), b"b".to_vec()]);
+        // expired keys are left out of their page
+        let (keys, cursor) = kv.list_keys_page(cursor.as_deref(), 2)?;
+        assert_eq!(keys, vec![b"c".to_vec()]);
+        // keys set behind the cursor aren't listed, while the ones ahead of it are
+        kv.set(b"0", b"value")?;
+        kv.set(b"e", b"value")?;
+        let (keys, cursor) = kv.list_keys_page(cursor.as_deref(), 2)?;
+        assert_eq!(keys, vec![b"d".to_vec(), b"e".to_vec()]);
+        assert!(cursor.is_none());
+        Ok(())
+    }
+}
@@ -1,3 +1,35 @@
 pub mod awsdynamodb;
 pub mod azblob;
 pub mod filesystem;
+
+/// Slices up to `length` bytes of `value` starting at `offset`, for backends that
+/// can't do ranged reads (an `offset` past the end of `value` gets an empty slice).
+///
+/// The slice is cut out of `value` in place, rather than copied to a new buffer.
+pub fn slice_range(mut value: Vec<u8>, offset: u64, length: u64) -> Vec<u8> {
+    let start = usize::try_from(offset).map_or(value.len(), |o| o.min(value.len()));
+    let end = start.saturating_add(usize::try_from(length).unwrap_or(usize::MAX));
+    value.truncate(end);
+    value.drain(..start);
+    value
+}
+
+#[cfg(test)]
+mod unittests {
+    use super::slice_range;
+
+    #[test]
+    fn slice_range_test() {
+        assert_eq!(slice_range(b"spiderlightning".to_vec(), 6, 5), b"light");
+        assert_eq!(
+            slice_range(b"spiderlightning".to_vec(), 6, 100),
+            b"lightning"
+        );
+        assert_eq!(slice_range(b"spiderlightning".to_vec(), 0, 0), b"");
+        assert_eq!(slice_range(b"spiderlightning".to_vec(), 15, 1), b"");
+        assert_eq!(
+            slice_range(b"spiderlightning".to_vec(), u64::MAX, u64::MAX),
+            b""
+        );
+    }
+}
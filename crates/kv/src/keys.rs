@@ -0,0 +1,110 @@
+use anyhow::{bail, Context, Result};
+
+/// The prefix of a hex encoded key — it can never start a key that is stored as-is.
+const HEX_PREFIX: char = '~';
+
+/// Encodes a key into a string that is safe to use as a file or blob name.
+///
+/// Keys are arbitrary bytes, but not every backend can store them as such (e.g., a
+/// filesystem can't have arbitrary bytes as file names). Backends like that store
+/// a key under its encoding, which is:
+///     - the key itself, if it is a non-empty string made only of ASCII letters,
+///     digits, `-`, `_`, and `.` that doesn't start w/ a `.`, or
+///     - a `~`, followed by the lowercase hex of the key's bytes, otherwise.
+///
+/// This keeps the keys most applications already use readable, and compatible
+/// w/ data stored before keys were binary-safe.
+pub fn encode(key: &[u8]) -> String {
+    if is_safe(key) {
+        // safe keys are ASCII, so this can't fail
+        String::from_utf8(key.to_vec()).unwrap()
+    } else {
+        let mut encoded = String::with_capacity(1 + key.len() * 2);
+        encoded.push(HEX_PREFIX);
+        for b in key {
+            encoded.push_str(&format!("{:02x}", b));
+        }
+        encoded
+    }
+}
+
+/// Decodes a key encoded w/ `encode` back into its original bytes.
+pub fn decode(encoded: &str) -> Result<Vec<u8>> {
+    match encoded.strip_prefix(HEX_PREFIX) {
+        Some(hex) => {
+            if hex.len() % 2 != 0 {
+                bail!(
+                    "invalid encoded key '{}': odd number of hex digits",
+                    encoded
+                );
+            }
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| {
+                    hex.get(i..i + 2)
+                        .and_then(|b| u8::from_str_radix(b, 16).ok())
+                        .with_context(|| format!("invalid encoded key '{}'", encoded))
+                })
+                .collect()
+        }
+        None if is_safe(encoded.as_bytes()) => Ok(encoded.as_bytes().to_vec()),
+        None => bail!("invalid encoded key '{}'", encoded),
+    }
+}
+
+/// Gets a printable representation of a key for logs, and error messages.
+pub fn display(key: &[u8]) -> String {
+    String::from_utf8_lossy(key).into_owned()
+}
+
+fn is_safe(key: &[u8]) -> bool {
+    !key.is_empty()
+        && key[0] != b'.'
+        && key
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
+
+#[cfg(test)]
+mod unittests {
+    use super::{decode, encode};
+
+    #[test]
+    fn safe_keys_are_stored_as_is() {
+        assert_eq!(encode(b"my-key"), "my-key");
+        assert_eq!(encode(b"key_1.txt"), "key_1.txt");
+    }
+
+    #[test]
+    fn unsafe_keys_are_hex_encoded() {
+        assert_eq!(encode(b""), "~");
+        assert_eq!(encode(b".queue"), "~2e7175657565");
+        assert_eq!(encode(b"a/b"), "~612f62");
+        assert_eq!(encode(&[0x00, 0xff]), "~00ff");
+        assert_eq!(encode(b"~"), "~7e");
+    }
+
+    #[test]
+    fn encoding_is_reversible() {
+        let keys: [&[u8]; 7] = [
+            b"my-key",
+            b"",
+            b"..",
+            b"a b/c\\d",
+            b"~7e",
+            &[0xde, 0xad, 0xbe, 0xef],
+            "ключ".as_bytes(),
+        ];
+        for key in keys {
+            assert_eq!(decode(&encode(key)).unwrap(), key);
+        }
+    }
+
+    #[test]
+    fn invalid_encodings_are_refused() {
+        assert!(decode("~abc").is_err());
+        assert!(decode("~zz").is_err());
+        assert!(decode("a/b").is_err());
+        assert!(decode(".hidden").is_err());
+    }
+}
@@ -1,22 +1,81 @@
+mod cache;
 mod implementors;
+mod keys;
+pub mod patch;
 pub mod providers;
 
 /// The `SCHEME_NAME` defines the name under which a resource is
 /// identifiable by in a `ResourceMap`.
 const SCHEME_NAME: &str = "kv";
+/// The operations guests can retry if they time out (see `BasicState::with_idempotent_operations`).
+const IDEMPOTENT_OPERATIONS: &[&str] = &[
+    "get",
+    "get-tagged",
+    "get-range",
+    "get-or-default",
+    "invalidate",
+    "list-keys",
+    "token",
+    "resume-keys-stream",
+    "release",
+];
+/// The operations that only read, which audits of writes only leave out (see
+/// `BasicState::with_read_operations`) — unlike `invalidate`, and `release`, which are
+/// idempotent, but drop what the host holds.
+const READ_OPERATIONS: &[&str] = &[
+    "get",
+    "get-tagged",
+    "get-range",
+    "get-or-default",
+    "list-keys",
+    "list-keys-stream",
+    "resume-keys-stream",
+    "next-page",
+    "token",
+    "watch",
+];
+/// The operations implementors don't support (i.e., always fail w/ `Unsupported`), by
+/// implementor, which guests that declare they call them are checked against (see
+/// `slight_runtime::manifest`), and which are left out of what's advertised to them (see
+/// `slight_runtime::support`).
+pub const UNSUPPORTED_OPERATIONS: &[(&str, &[&str])] = &[("kv.azblob", &["set-with-time-to-live"])];
+/// The operations that are opt-in while they're new (see `slight_runtime::flags`), which the
+/// guest can only call if the capability's `enable_operations` has them.
+pub const EXPERIMENTAL_OPERATIONS: &[&str] =
+    &["apply-patch", "list-keys-stream", "resume-keys-stream"];
+/// How big the values the guest sets can be, unless the capability says otherwise (see
+/// `slight_runtime::payload_limit`).
+pub const DEFAULT_MAX_PAYLOAD_BYTES: usize = 16 * 1024 * 1024;
 
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use cache::ReadCache;
 use crossbeam_channel::Sender;
 use implementors::{
     awsdynamodb::AwsDynamoDbImplementor, azblob::AzBlobImplementor,
     filesystem::FilesystemImplementor,
 };
+use patch::Patch;
+use serde::{Deserialize, Serialize};
 use slight_events_api::Event;
 use uuid::Uuid;
 
-use slight_runtime::{impl_resource, resource::BasicState};
+use slight_runtime::{
+    batch::Batcher,
+    describe::Description,
+    drain::DEFAULT_DRAIN_GRACE,
+    encoding::Encoding,
+    impl_resource,
+    page_token::PageTokens,
+    payload_limit::PayloadLimit,
+    release::{Lease, Releasable},
+    resource::{BasicState, Releaser},
+    split::{Operation, TrafficSplit},
+};
 
 /// It is mandatory to `use <interface>::*` due to `impl_resource!`.
 /// That is because `impl_resource!` accesses the `crate`'s
@@ -24,7 +83,7 @@ use slight_runtime::{impl_resource, resource::BasicState};
 use kv::*;
 wit_bindgen_wasmtime::export!("../../wit/kv.wit");
 wit_error_rs::impl_error!(kv::Error);
-wit_error_rs::impl_from!(anyhow::Error, kv::Error::ErrorWithDescription);
+slight_runtime::impl_from_anyhow!(kv::Error);
 
 /// The `Kv` structure is what will implement the `kv::Kv` trait
 /// coming from the generated code of off `kv.wit`.
@@ -39,87 +98,504 @@ pub struct Kv {
 /// It holds:
 ///     - a `kv_implementor` `String` — this comes directly from a
 ///     user's `slightfile` and it is what allows us to dynamically
-///     dispatch to a specific implementor's implentation, and
+///     dispatch to a specific implementor's implentation,
 ///     - the `slight_state` (of type `BasicState`) that contains common
 ///     things received from the slight binary (i.e., the `resource_map`,
-///     the `config_type`, and the `config_toml_file_path`).
+///     the `config_type`, and the `config_toml_file_path`),
+///     - whether `allow_clear` is set, as `clear` is disabled by default,
+///     - the `canary` implementor (if any) a share of operations is routed to,
+///     w/ its `TrafficSplit`,
+///     - the `cache` reads go through (see `ReadCache`),
+///     - the `batcher` (if any) gets are coalesced by, w/ the ones of other guest instances,
+///     - the `encoding` of patches (see `patch::Patch`), and
+///     - whether stores whose backends the guest released are reopened once they're used again
+///     (see `kv_release`), rather than failing,
+///     - the `drain_grace` period releasing them waits for the calls in flight for, and
+///     - the `page_tokens` key streams are resumed w/ (see `key_stream_token`).
 pub struct KvState {
     kv_implementor: String,
     slight_state: BasicState,
+    allow_clear: bool,
+    canary: Option<(String, TrafficSplit)>,
+    cache: ReadCache,
+    batcher: Option<Arc<Batcher<Vec<u8>, Vec<u8>>>>,
+    encoding: Encoding,
+    reopen_released: bool,
+    drain_grace: Duration,
+    page_tokens: PageTokens,
 }
 
 impl KvState {
     pub fn new(kv_implementor: String, slight_state: BasicState) -> Self {
         Self {
             kv_implementor,
-            slight_state,
+            slight_state: slight_state
+                .with_idempotent_operations(IDEMPOTENT_OPERATIONS)
+                .with_read_operations(READ_OPERATIONS),
+            allow_clear: false,
+            canary: None,
+            cache: ReadCache::default(),
+            batcher: None,
+            encoding: Encoding::default(),
+            reopen_released: true,
+            drain_grace: DEFAULT_DRAIN_GRACE,
+            page_tokens: PageTokens::default(),
         }
     }
+
+    /// Describes the effective configuration of the backend (see `slight_runtime::describe`),
+    /// w/o connecting to it.
+    pub fn describe(&self) -> Result<Description> {
+        let description = match self.kv_implementor.as_str() {
+            "kv.filesystem" => FilesystemImplementor::describe(),
+            "kv.azblob" => AzBlobImplementor::describe(&self.slight_state),
+            "kv.awsdynamodb" => AwsDynamoDbImplementor::describe(),
+            p => bail!(
+                "failed to match provided name (i.e., '{}') to any known host implementations",
+                p
+            ),
+        };
+        Ok(description.with_pool_size(self.slight_state.pool_size()))
+    }
+
+    /// Enables the `clear` operation, which deletes keys in bulk.
+    pub fn with_allow_clear(mut self, allow_clear: bool) -> Self {
+        self.allow_clear = allow_clear;
+        self
+    }
+
+    /// Routes a share of operations (as per `split`) to the `canary_implementor`,
+    /// instead of the kv implementor.
+    pub fn with_traffic_split(mut self, canary_implementor: String, split: TrafficSplit) -> Self {
+        self.canary = Some((canary_implementor, split));
+        self
+    }
+
+    /// Caches the values read for up to `ttl`, and up to `max_entries` of them (see
+    /// `ReadCache`), rather than reading them from the backend every time.
+    pub fn with_read_cache(mut self, ttl: Option<Duration>, max_entries: Option<usize>) -> Self {
+        self.cache = ReadCache::new(ttl, max_entries);
+        self
+    }
+
+    /// Coalesces the gets that miss the cache into batches (see `Batcher`), rather than making
+    /// a backend call per key.
+    pub fn with_batching(mut self, batcher: Option<Arc<Batcher<Vec<u8>, Vec<u8>>>>) -> Self {
+        self.batcher = batcher;
+        self
+    }
+
+    /// Takes patches in the `encoding`, rather than the binary bsdiff format.
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Fails the calls to stores whose backends the guest released, rather than reopening them.
+    pub fn with_reopen_released(mut self, reopen_released: bool) -> Self {
+        self.reopen_released = reopen_released;
+        self
+    }
+
+    /// Waits for up to `drain_grace` for the calls in flight on a store's backends, when
+    /// they're released (or the app shuts down), before closing them.
+    pub fn with_drain_grace(mut self, drain_grace: Duration) -> Self {
+        self.drain_grace = drain_grace;
+        self
+    }
+
+    /// Seals the page tokens of key streams w/ `page_tokens` (e.g., ones derived from a
+    /// secret, so they're resumed across restarts, and hosts).
+    pub fn with_page_tokens(mut self, page_tokens: PageTokens) -> Self {
+        self.page_tokens = page_tokens;
+        self
+    }
+
+    /// What the page tokens of a store's key streams are for: the implementors its keys are
+    /// listed from, and the store, so a token is stale once either changes.
+    fn page_token_scope(&self, store: &str) -> String {
+        // implementor names have no `:`, so the store can be anything
+        format!(
+            "kv:{}:{}:{}",
+            self.kv_implementor,
+            self.canary
+                .as_ref()
+                .map_or("", |(canary_implementor, _)| canary_implementor.as_str()),
+            store
+        )
+    }
 }
 
 /// This is the type of the associated type coming from the `kv::Kv` trait
 /// implementation.
 ///
 /// It holds:
-///     - a `kv_implementor` (i.e., a variant `KvImplementor` `enum`), and
+///     - the `backends` of the kv store, which the guest can release (see `kv_release`),
+///     - the `name` of the kv store, and
 ///     - a `resource_descriptor` (i.e., an UUID that uniquely identifies
 ///     resource's instance).
 ///
 /// It must `derive`:
 ///     - `Debug` due to a constraint on the associated type.
 ///     - `Clone` because the `ResourceMap` it will be added onto,
-///     must own its' data.
+///     must own its data.
 ///
 /// It must be public because the implementation of `kv::Kv` cannot leak
 /// a private type.
 #[derive(Debug, Clone)]
 pub struct KvInner {
-    kv_implementor: KvImplementors,
+    backends: Releasable<Backends>,
+    name: String,
     resource_descriptor: String,
 }
 
 impl KvInner {
-    fn new(kv_implementor: &str, slight_state: &BasicState, name: &str) -> Self {
-        Self {
-            kv_implementor: KvImplementors::new(kv_implementor, slight_state, name),
+    fn new(host_state: &KvState, name: &str) -> Result<Self> {
+        let kv_implementor = host_state.kv_implementor.clone();
+        let canary = host_state.canary.clone();
+        let slight_state = host_state.slight_state.clone();
+        let store = name.to_string();
+        let open = move || {
+            Ok(Backends {
+                kv_implementor: KvImplementors::new(&kv_implementor, &slight_state, &store)?,
+                canary: canary
+                    .as_ref()
+                    .map(|(canary_implementor, split)| {
+                        KvImplementors::new(canary_implementor, &slight_state, &store)
+                            .map(|canary| (canary, split.clone()))
+                    })
+                    .transpose()?,
+            })
+        };
+        Ok(Self {
+            backends: Releasable::new(open, host_state.reopen_released)?
+                .with_drain(&format!("kv store '{}'", name), host_state.drain_grace),
+            name: name.to_string(),
             resource_descriptor: Uuid::new_v4().to_string(),
+        })
+    }
+
+    /// The backends of the kv store, reopened if the guest released them.
+    fn open(&self) -> Result<Lease<Backends>> {
+        self.backends.get()
+    }
+}
+
+/// The backends of a kv store, which are opened anew once they're released.
+///
+/// It holds:
+///     - a `kv_implementor` (i.e., a variant `KvImplementor` `enum`), and
+///     - the `canary` implementor (if any), w/ the `TrafficSplit` deciding which
+///     operations go to it.
+#[derive(Debug, Clone)]
+struct Backends {
+    kv_implementor: KvImplementors,
+    canary: Option<(KvImplementors, TrafficSplit)>,
+}
+
+impl Backends {
+    /// The implementor an operation (on `key`, if any) goes to.
+    fn backend(&self, operation: Operation, key: Option<&[u8]>) -> &KvImplementors {
+        match &self.canary {
+            Some((canary, split)) if split.routes_to_canary(operation, key) => canary,
+            _ => &self.kv_implementor,
         }
     }
+
+    /// All the implementors (i.e., the kv implementor, and the canary, if any).
+    fn backends(&self) -> impl Iterator<Item = &KvImplementors> {
+        std::iter::once(&self.kv_implementor).chain(self.canary.as_ref().map(|(canary, _)| canary))
+    }
+}
+
+/// This is the type of the `key-stream` resource (see `kv_list_keys_stream`), which lists the
+/// keys of `kv` from its `position` on.
+///
+/// It's dropped once the guest drops its handle, and holds no resources of the backend (i.e.,
+/// only the cursor of the next page), so abandoned streams don't leak anything.
+#[derive(Debug, Clone)]
+pub struct KeyStreamInner {
+    kv: KvInner,
+    position: Arc<Mutex<StreamPosition>>,
+}
+
+/// Where a `KeyStreamInner` is at: which of the backends the keys are listed from (i.e., the
+/// kv implementor, and then the canary), and the cursor of the next page of it — which is what
+/// its page tokens are sealed w/.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StreamPosition {
+    backend: usize,
+    cursor: Option<String>,
 }
 
 impl slight_runtime::resource::Watch for KvInner {
     fn watch(&mut self, key: &str, sender: Arc<Mutex<Sender<Event>>>) -> Result<()> {
-        match &mut self.kv_implementor {
-            KvImplementors::Filesystem(fi) => fi.watch(key, sender),
-            _ => todo!(),
-        }
+        // the watchers are kept w/ the backend, so releasing it stops them too
+        self.backends
+            .with(|backends| match &mut backends.kv_implementor {
+                KvImplementors::Filesystem(fi) => fi.watch(key, sender),
+                _ => todo!(),
+            })
+    }
+
+    fn releaser(&self) -> Option<Releaser> {
+        let backends = self.backends.clone();
+        Some(Box::new(move || Ok(backends.release())))
     }
 }
 
 /// This defines the available implementor implementations for the `Kv` interface.
 ///
-/// As per its' usage in `KvInner`, it must `derive` `Debug`, and `Clone`.
+/// As per its usage in `KvInner`, it must `derive` `Debug`, and `Clone`.
+///
+/// The implementors that hold clients share them w/ the other guest instances of the app
+/// (see `BasicState::connection`), rather than connecting once per instance.
 #[derive(Debug, Clone)]
 enum KvImplementors {
     Filesystem(FilesystemImplementor),
-    AzBlob(AzBlobImplementor),
-    AwsDynamoDb(AwsDynamoDbImplementor),
+    AzBlob(Arc<AzBlobImplementor>),
+    AwsDynamoDb(Arc<AwsDynamoDbImplementor>),
 }
 
 impl KvImplementors {
-    fn new(kv_implementor: &str, slight_state: &BasicState, name: &str) -> Self {
-        match kv_implementor {
+    /// Opens the implementor's backend `name`, failing if it can't be (e.g., as its secrets
+    /// are missing).
+    fn new(kv_implementor: &str, slight_state: &BasicState, name: &str) -> Result<Self> {
+        Ok(match kv_implementor {
             "kv.filesystem" => Self::Filesystem(FilesystemImplementor::new(name)),
-            "kv.azblob" => Self::AzBlob(AzBlobImplementor::new(slight_state, name)),
-            "kv.awsdynamodb" => Self::AwsDynamoDb(AwsDynamoDbImplementor::new(name)),
-            p => panic!(
+            "kv.azblob" => {
+                Self::AzBlob(slight_state.try_connection(kv_implementor, name, || {
+                    AzBlobImplementor::new(slight_state, name)
+                })?)
+            }
+            "kv.awsdynamodb" => Self::AwsDynamoDb(slight_state.connection(
+                kv_implementor,
+                name,
+                || AwsDynamoDbImplementor::new(name),
+            )),
+            p => bail!(
                 "failed to match provided name (i.e., '{}') to any known host implementations",
                 p
             ),
+        })
+    }
+
+    fn get_opt(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::Filesystem(fi) => fi.get_opt(key),
+            Self::AzBlob(ai) => ai.get_opt(key),
+            Self::AwsDynamoDb(adp) => adp.get_opt(key),
+        }
+    }
+
+    /// Gets the values of `keys`, w/ a result per key, in one batch operation if the backend
+    /// has one (i.e., DynamoDB's `BatchGetItem`), or one by one otherwise.
+    fn get_many(&self, keys: &[Vec<u8>]) -> Result<Vec<Result<Vec<u8>>>> {
+        match self {
+            Self::Filesystem(fi) => Ok(keys.iter().map(|key| fi.get(key)).collect()),
+            Self::AzBlob(ai) => ai.get_many(keys),
+            Self::AwsDynamoDb(adp) => adp.get_many(keys),
+        }
+    }
+
+    fn compare_and_swap(&self, key: &[u8], expected: Option<&[u8]>, value: &[u8]) -> Result<bool> {
+        match self {
+            Self::Filesystem(fi) => fi.compare_and_swap(key, expected, value),
+            Self::AzBlob(ai) => ai.compare_and_swap(key, expected, value),
+            Self::AwsDynamoDb(adp) => adp.compare_and_swap(key, expected, value),
+        }
+    }
+    /// Lists a page of up to `max` keys after `cursor` (none, for the first page) w/ the
+    /// backend's pagination, w/ the cursor of the next page (none, if it's the last one).
+    fn list_keys_page(
+        &self,
+        cursor: Option<&str>,
+        max: usize,
+    ) -> Result<(Vec<Vec<u8>>, Option<String>)> {
+        match self {
+            Self::Filesystem(fi) => fi.list_keys_page(cursor, max),
+            Self::AzBlob(ai) => ai.list_keys_page(cursor, max),
+            Self::AwsDynamoDb(adp) => adp.list_keys_page(cursor, max),
         }
     }
 }
 
+/// `HostKv` is a kv store for the host's own use, so that other capabilities can keep
+/// state in any of the kv implementors (e.g., pubsub's deduplication window).
+#[derive(Debug, Clone)]
+pub struct HostKv {
+    kv_implementor: KvImplementors,
+}
+
+impl HostKv {
+    pub fn open(kv_implementor: &str, slight_state: &BasicState, name: &str) -> Result<Self> {
+        Ok(Self {
+            kv_implementor: KvImplementors::new(kv_implementor, slight_state, name)?,
+        })
+    }
+
+    /// Gets the value of a key, or `None` if it doesn't exist.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.kv_implementor.get_opt(key)
+    }
+
+    /// Reads up to `length` bytes of a key's value, starting at `offset`.
+    pub fn get_range(&self, key: &[u8], offset: u64, length: u64) -> Result<Vec<u8>> {
+        match &self.kv_implementor {
+            KvImplementors::Filesystem(fi) => fi.get_range(key, offset, length),
+            KvImplementors::AzBlob(ai) => ai.get_range(key, offset, length),
+            KvImplementors::AwsDynamoDb(adp) => adp.get_range(key, offset, length),
+        }
+    }
+
+    pub fn set(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        match &self.kv_implementor {
+            KvImplementors::Filesystem(fi) => fi.set(key, value),
+            KvImplementors::AzBlob(ai) => ai.set(key, value),
+            KvImplementors::AwsDynamoDb(adp) => adp.set(key, value),
+        }
+    }
+
+    /// Sets the value of a key only if its current value is `expected` (where `None`
+    /// means the key must not exist yet), returning whether the swap happened.
+    pub fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        value: &[u8],
+    ) -> Result<bool> {
+        self.kv_implementor.compare_and_swap(key, expected, value)
+    }
+
+    pub fn set_with_time_to_live(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        time_to_live_in_secs: u64,
+    ) -> Result<()> {
+        match &self.kv_implementor {
+            KvImplementors::Filesystem(fi) => {
+                fi.set_with_time_to_live(key, value, time_to_live_in_secs)
+            }
+            KvImplementors::AzBlob(ai) => {
+                ai.set_with_time_to_live(key, value, time_to_live_in_secs)
+            }
+            KvImplementors::AwsDynamoDb(adp) => {
+                adp.set_with_time_to_live(key, value, time_to_live_in_secs)
+            }
+        }
+    }
+}
+
+impl Kv {
+    /// Counts the size of `key`, and its `value` (if the call carried one) in the metrics of
+    /// `operation` (see `BasicState::record_sizes`).
+    fn record_sizes(&self, operation: &str, key: &[u8], value: Option<&[u8]>) {
+        self.host_state.slight_state.record_sizes(
+            operation,
+            &keys::display(key),
+            key.len(),
+            value.map(<[u8]>::len),
+        );
+    }
+
+    /// How big the values the guest sets can be (i.e., the capability's payload limit).
+    fn max_payload_bytes(&self) -> usize {
+        self.host_state
+            .slight_state
+            .call_settings
+            .payload_limit
+            .as_ref()
+            .map_or(DEFAULT_MAX_PAYLOAD_BYTES, PayloadLimit::max_bytes)
+    }
+
+    /// Gets the value of `key` from the cache, or reads it from the backend (w/ the last known
+    /// good value as a fallback), and caches it, tagged w/ `tags` — it's returned as the guest
+    /// gets it (see `returned`).
+    fn get_through_cache(
+        &self,
+        self_: &KvInner,
+        operation: &str,
+        key: &[u8],
+        tags: &[&[u8]],
+    ) -> Result<Vec<u8>, Error> {
+        let slight_state = &self.host_state.slight_state;
+        let cache = &self.host_state.cache;
+        slight_state.instrument(SCHEME_NAME, operation, &keys::display(key), || {
+            if let Some(value) = cache.get(&self_.name, key) {
+                self.record_sizes(operation, key, Some(&value));
+                slight_state.charge_bytes(value.len());
+                return Ok(self.returned(operation, value)?);
+            }
+            // whether the value was read from the backend, rather than the last known good one
+            let mut fresh = false;
+            let value = slight_state.last_known_good.read(&self_.name, key, || {
+                let read = slight_state.recorded(SCHEME_NAME, "get", &[&self_.name, &key], || {
+                    let backends = self_.open()?;
+                    let backend = backends.backend(Operation::Read, Some(key));
+                    // reads routed to the canary aren't batched, as a batch goes to one backend
+                    match &self.host_state.batcher {
+                        Some(batcher) if std::ptr::eq(backend, &backends.kv_implementor) => batcher
+                            .call(&self_.name, key.to_vec(), |keys| {
+                                backends.kv_implementor.get_many(&keys)
+                            }),
+                        _ => Ok(match backend {
+                            KvImplementors::Filesystem(fi) => fi.get(key)?,
+                            KvImplementors::AzBlob(ai) => ai.get(key)?,
+                            KvImplementors::AwsDynamoDb(adp) => adp.get(key)?,
+                        }),
+                    }
+                });
+                fresh = read.is_ok();
+                read
+            })?;
+            // a fallback isn't cached, so the next read goes to the backend again
+            if fresh {
+                cache.put(&self_.name, key, &value, tags);
+            }
+            self.record_sizes(operation, key, Some(&value));
+            slight_state.charge_bytes(value.len());
+            Ok(self.returned(operation, value)?)
+        })
+    }
+
+    /// Compresses a `value` `operation` returns to the guest (see `BasicState::compressed`),
+    /// failing if the guest's memory has no room for it (see `BasicState::check_headroom`).
+    fn returned(&self, operation: &str, value: Vec<u8>) -> Result<Vec<u8>> {
+        let value = self.host_state.slight_state.compressed(value);
+        self.host_state
+            .slight_state
+            .check_headroom(operation, value.len())?;
+        Ok(value)
+    }
+}
+
+/// How many bytes a list of keys takes in the guest's memory: the keys, and a pointer, and a
+/// length (i.e., 8 bytes) for each of them.
+fn listed_bytes(keys: &[Vec<u8>]) -> usize {
+    keys.iter().map(|key| key.len() + 8).sum()
+}
+
+/// Compresses the keys listed for the guest, each on its own (see `BasicState::compressed`).
+fn compressed_keys(slight_state: &BasicState, keys: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    keys.into_iter()
+        .map(|key| slight_state.compressed(key))
+        .collect()
+}
+
+/// Parses the value of a counter (i.e., a decimal integer stored as text).
+fn parse_counter(key: &[u8], value: &[u8]) -> Result<i64> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|v| v.trim().parse::<i64>().ok())
+        .with_context(|| {
+            format!(
+                "value for key '{}' is not a valid integer",
+                keys::display(key)
+            )
+        })
+}
+
 // This implements the `ResourceBuilder`, and `Resource` trait
 // for our `Kv` `struct`, and `ResourceTables` for our `kv::KvTables` object.
 //
@@ -134,16 +610,14 @@ impl_resource!(Kv, kv::KvTables<Kv>, KvState, SCHEME_NAME.to_string());
 /// This is the implementation for the generated `kv::Kv` trait from the `kv.wit` file.
 impl kv::Kv for Kv {
     type Kv = KvInner;
+    type KeyStream = KeyStreamInner;
 
     fn kv_open(&mut self, name: &str) -> Result<Self::Kv, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
         // populate our inner kv object w/ the state received from `slight`
         // (i.e., what type of kv implementor we are using), and the assigned
         // name of the object.
-        let inner = Self::Kv::new(
-            &self.host_state.kv_implementor,
-            &self.host_state.slight_state,
-            name,
-        );
+        let inner = Self::Kv::new(&self.host_state, name)?;
 
         self.host_state
             .slight_state
@@ -155,38 +629,480 @@ impl kv::Kv for Kv {
         Ok(inner)
     }
 
-    fn kv_get(&mut self, self_: &Self::Kv, key: &str) -> Result<PayloadResult, Error> {
-        Ok(match &self_.kv_implementor {
-            KvImplementors::Filesystem(fi) => fi.get(key)?,
-            KvImplementors::AzBlob(ai) => ai.get(key)?,
-            KvImplementors::AwsDynamoDb(adp) => adp.get(key)?,
+    fn kv_get(&mut self, self_: &Self::Kv, key: PayloadParam<'_>) -> Result<PayloadResult, Error> {
+        self.get_through_cache(self_, "get", key, &[])
+    }
+
+    fn kv_get_tagged(
+        &mut self,
+        self_: &Self::Kv,
+        key: PayloadParam<'_>,
+        tags: Vec<PayloadParam<'_>>,
+    ) -> Result<PayloadResult, Error> {
+        self.get_through_cache(self_, "get-tagged", key, &tags)
+    }
+
+    fn kv_invalidate(&mut self, self_: &Self::Kv, key: PayloadParam<'_>) -> Result<(), Error> {
+        let host_state = &self.host_state;
+        host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "invalidate", &keys::display(key), || {
+                let invalidated = host_state.cache.invalidate(&self_.name, key);
+                tracing::debug!(
+                    "invalidated {} cached values of '{}'",
+                    invalidated,
+                    keys::display(key)
+                );
+                Ok(())
+            })
+    }
+
+    fn kv_get_range(
+        &mut self,
+        self_: &Self::Kv,
+        key: PayloadParam<'_>,
+        offset: u64,
+        length: u64,
+    ) -> Result<PayloadResult, Error> {
+        let slight_state = &self.host_state.slight_state;
+        slight_state.instrument(SCHEME_NAME, "get-range", &keys::display(key), || {
+            let value = slight_state.recorded(
+                SCHEME_NAME,
+                "get-range",
+                &[&self_.name, &key, &offset, &length],
+                || {
+                    Ok(match self_.open()?.backend(Operation::Read, Some(key)) {
+                        KvImplementors::Filesystem(fi) => fi.get_range(key, offset, length)?,
+                        KvImplementors::AzBlob(ai) => ai.get_range(key, offset, length)?,
+                        KvImplementors::AwsDynamoDb(adp) => adp.get_range(key, offset, length)?,
+                    })
+                },
+            )?;
+            slight_state.charge_bytes(value.len());
+            Ok(self.returned("get-range", value)?)
+        })
+    }
+
+    fn kv_get_or_default(
+        &mut self,
+        self_: &Self::Kv,
+        key: PayloadParam<'_>,
+        default_value: PayloadParam<'_>,
+    ) -> Result<PayloadResult, Error> {
+        let slight_state = &self.host_state.slight_state;
+        slight_state.instrument(SCHEME_NAME, "get-or-default", &keys::display(key), || {
+            let value = slight_state.recorded(
+                SCHEME_NAME,
+                "get-or-default",
+                &[&self_.name, &key],
+                || {
+                    self_
+                        .open()?
+                        .backend(Operation::Read, Some(key))
+                        .get_opt(key)
+                },
+            )?;
+            // the default isn't a value of the store, so it isn't charged either
+            self.record_sizes("get-or-default", key, value.as_deref());
+            slight_state.charge_bytes(value.as_ref().map_or(0, Vec::len));
+            Ok(self.returned(
+                "get-or-default",
+                value.unwrap_or_else(|| default_value.to_vec()),
+            )?)
         })
     }
 
     fn kv_set(
         &mut self,
         self_: &Self::Kv,
-        key: &str,
+        key: PayloadParam<'_>,
         value: PayloadParam<'_>,
     ) -> Result<(), Error> {
-        match &self_.kv_implementor {
-            KvImplementors::Filesystem(fi) => fi.set(key, value)?,
-            KvImplementors::AzBlob(ai) => ai.set(key, value)?,
-            KvImplementors::AwsDynamoDb(adp) => adp.set(key, value)?,
-        };
-        Ok(())
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "set", &keys::display(key), || {
+                self.host_state
+                    .slight_state
+                    .check_payload("set", value.len())?;
+                self.host_state.slight_state.take_bytes(value.len())?;
+                self.record_sizes("set", key, Some(value));
+                self.host_state.slight_state.recorded(
+                    SCHEME_NAME,
+                    "set",
+                    &[&self_.name, &key, &value],
+                    || match self_.open()?.backend(Operation::Write, Some(key)) {
+                        KvImplementors::Filesystem(fi) => fi.set(key, value),
+                        KvImplementors::AzBlob(ai) => ai.set(key, value),
+                        KvImplementors::AwsDynamoDb(adp) => adp.set(key, value),
+                    },
+                )?;
+                self.host_state
+                    .slight_state
+                    .last_known_good
+                    .remember(&self_.name, key, value);
+                self.host_state.cache.invalidate(&self_.name, key);
+                Ok(())
+            })
     }
 
-    fn kv_delete(&mut self, self_: &Self::Kv, key: &str) -> Result<(), Error> {
-        match &self_.kv_implementor {
-            KvImplementors::Filesystem(fi) => fi.delete(key)?,
-            KvImplementors::AzBlob(ai) => ai.delete(key)?,
-            KvImplementors::AwsDynamoDb(adp) => adp.delete(key)?,
-        };
-        Ok(())
+    fn kv_set_with_time_to_live(
+        &mut self,
+        self_: &Self::Kv,
+        key: PayloadParam<'_>,
+        value: PayloadParam<'_>,
+        time_to_live_in_secs: u64,
+    ) -> Result<(), Error> {
+        self.host_state.slight_state.instrument(
+            SCHEME_NAME,
+            "set-with-time-to-live",
+            &keys::display(key),
+            || {
+                self.host_state
+                    .slight_state
+                    .check_payload("set-with-time-to-live", value.len())?;
+                self.host_state.slight_state.take_bytes(value.len())?;
+                self.record_sizes("set-with-time-to-live", key, Some(value));
+                self.host_state.slight_state.recorded(
+                    SCHEME_NAME,
+                    "set-with-time-to-live",
+                    &[&self_.name, &key, &value, &time_to_live_in_secs],
+                    || match self_.open()?.backend(Operation::Write, Some(key)) {
+                        KvImplementors::Filesystem(fi) => {
+                            fi.set_with_time_to_live(key, value, time_to_live_in_secs)
+                        }
+                        KvImplementors::AzBlob(ai) => {
+                            ai.set_with_time_to_live(key, value, time_to_live_in_secs)
+                        }
+                        KvImplementors::AwsDynamoDb(adp) => {
+                            adp.set_with_time_to_live(key, value, time_to_live_in_secs)
+                        }
+                    },
+                )?;
+                // the value will expire, so it can't be served once the backend is unavailable
+                self.host_state
+                    .slight_state
+                    .last_known_good
+                    .forget(&self_.name, key);
+                self.host_state.cache.invalidate(&self_.name, key);
+                Ok(())
+            },
+        )
+    }
+
+    fn kv_delete(&mut self, self_: &Self::Kv, key: PayloadParam<'_>) -> Result<(), Error> {
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "delete", &keys::display(key), || {
+                self.record_sizes("delete", key, None);
+                self.host_state.slight_state.recorded(
+                    SCHEME_NAME,
+                    "delete",
+                    &[&self_.name, &key],
+                    || match self_.open()?.backend(Operation::Write, Some(key)) {
+                        KvImplementors::Filesystem(fi) => fi.delete(key),
+                        KvImplementors::AzBlob(ai) => ai.delete(key),
+                        KvImplementors::AwsDynamoDb(adp) => adp.delete(key),
+                    },
+                )?;
+                self.host_state
+                    .slight_state
+                    .last_known_good
+                    .forget(&self_.name, key);
+                self.host_state.cache.invalidate(&self_.name, key);
+                Ok(())
+            })
+    }
+
+    fn kv_apply_patch(
+        &mut self,
+        self_: &Self::Kv,
+        key: PayloadParam<'_>,
+        patch: PayloadParam<'_>,
+    ) -> Result<(), Error> {
+        self.host_state.slight_state.instrument(
+            SCHEME_NAME,
+            "apply-patch",
+            &keys::display(key),
+            || {
+                // only the patch is transferred, so it's what counts towards the quota
+                self.host_state.slight_state.take_bytes(patch.len())?;
+                let patched = self.host_state.slight_state.recorded(
+                    SCHEME_NAME,
+                    "apply-patch",
+                    &[&self_.name, &key, &patch],
+                    || {
+                        let failed = || format!("failed to patch key '{}'", keys::display(key));
+                        let patch = Patch::decode(
+                            patch,
+                            self.host_state.encoding,
+                            self.max_payload_bytes() as u64,
+                        )
+                        .with_context(failed)?;
+                        // read-modify-write the value — unlike `incr-by`, it isn't retried if
+                        // someone else changed it in between, as the patch would then be applied
+                        // to a value it wasn't computed against (which bsdiff can't tell)
+                        let backends = self_.open()?;
+                        let backend = backends.backend(Operation::Write, Some(key));
+                        let current = backend.get_opt(key)?;
+                        let patched = patch
+                            .apply(current.as_deref().unwrap_or_default())
+                            .with_context(failed)?;
+                        // the patched value is what reaches the backend
+                        self.host_state
+                            .slight_state
+                            .check_payload("apply-patch", patched.len())?;
+                        if !backend.compare_and_swap(key, current.as_deref(), &patched)? {
+                            bail!("{}: it changed while the patch was applied", failed());
+                        }
+                        Ok(patched)
+                    },
+                )?;
+                // the value's size, rather than the patch's, is what operators watch
+                self.record_sizes("apply-patch", key, Some(&patched));
+                self.host_state
+                    .slight_state
+                    .last_known_good
+                    .remember(&self_.name, key, &patched);
+                self.host_state.cache.invalidate(&self_.name, key);
+                Ok(())
+            },
+        )
+    }
+
+    fn kv_incr_by(
+        &mut self,
+        self_: &Self::Kv,
+        key: PayloadParam<'_>,
+        delta: i64,
+    ) -> Result<i64, Error> {
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "incr-by", &keys::display(key), || {
+                let new_value = self.host_state.slight_state.recorded(
+                    SCHEME_NAME,
+                    "incr-by",
+                    &[&self_.name, &key, &delta],
+                    || {
+                        // optimistically read-modify-write the counter, retrying if someone
+                        // else changed it in between.
+                        let backends = self_.open()?;
+                        let backend = backends.backend(Operation::Write, Some(key));
+                        loop {
+                            let current = backend.get_opt(key)?;
+                            let value = match &current {
+                                Some(v) => parse_counter(key, v)?,
+                                None => 0,
+                            };
+                            let new_value = value.checked_add(delta).with_context(|| {
+                                format!("counter for key '{}' overflowed", keys::display(key))
+                            })?;
+                            if backend.compare_and_swap(
+                                key,
+                                current.as_deref(),
+                                new_value.to_string().as_bytes(),
+                            )? {
+                                return Ok(new_value);
+                            }
+                        }
+                    },
+                )?;
+                self.host_state.slight_state.last_known_good.remember(
+                    &self_.name,
+                    key,
+                    new_value.to_string().as_bytes(),
+                );
+                self.host_state.cache.invalidate(&self_.name, key);
+                Ok(new_value)
+            })
+    }
+
+    fn kv_clear(&mut self, self_: &Self::Kv, prefix: PayloadParam<'_>) -> Result<u64, Error> {
+        if !self.host_state.allow_clear {
+            return Err(anyhow::anyhow!(
+                "clear is disabled; set `allow_clear = true` on the kv capability in your slightfile to enable it"
+            )
+            .into());
+        }
+        self.host_state.slight_state.instrument(
+            SCHEME_NAME,
+            "clear",
+            &keys::display(prefix),
+            || {
+                let cleared = self.host_state.slight_state.recorded(
+                    SCHEME_NAME,
+                    "clear",
+                    &[&self_.name, &prefix],
+                    || {
+                        // keys w/ the prefix may have been written to any of the backends
+                        let mut cleared = 0;
+                        for backend in self_.open()?.backends() {
+                            cleared += match backend {
+                                KvImplementors::Filesystem(fi) => fi.clear(prefix)?,
+                                KvImplementors::AzBlob(ai) => ai.clear(prefix)?,
+                                KvImplementors::AwsDynamoDb(adp) => adp.clear(prefix)?,
+                            };
+                        }
+                        Ok(cleared)
+                    },
+                )?;
+                self.host_state
+                    .slight_state
+                    .last_known_good
+                    .forget_prefix(&self_.name, prefix);
+                self.host_state.cache.invalidate_prefix(&self_.name, prefix);
+                tracing::info!(
+                    "cleared {} keys w/ prefix '{}'",
+                    cleared,
+                    keys::display(prefix)
+                );
+                Ok(cleared)
+            },
+        )
+    }
+
+    fn kv_list_keys(&mut self, self_: &Self::Kv) -> Result<Vec<PayloadResult>, Error> {
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "list-keys", "*", || {
+                let listed = self.host_state.slight_state.recorded(
+                    SCHEME_NAME,
+                    "list-keys",
+                    &[&self_.name],
+                    || {
+                        // keys are split across the backends, so they are listed from all of
+                        // them
+                        let backends = self_.open()?;
+                        let mut listed = Vec::new();
+                        for backend in backends.backends() {
+                            listed.extend(match backend {
+                                KvImplementors::Filesystem(fi) => fi.list_keys()?,
+                                KvImplementors::AzBlob(ai) => ai.list_keys()?,
+                                KvImplementors::AwsDynamoDb(adp) => adp.list_keys()?,
+                            });
+                        }
+                        if backends.canary.is_some() {
+                            listed.sort();
+                            listed.dedup();
+                        }
+                        Ok(listed)
+                    },
+                )?;
+                let listed = compressed_keys(&self.host_state.slight_state, listed);
+                self.host_state
+                    .slight_state
+                    .check_headroom("list-keys", listed_bytes(&listed))?;
+                Ok(listed)
+            })
+    }
+
+    fn kv_list_keys_stream(&mut self, self_: &Self::Kv) -> Result<Self::KeyStream, Error> {
+        // the stream lists nothing until its first page (see `key_stream_next_page`)
+        self.host_state
+            .slight_state
+            .permit(SCHEME_NAME, "list-keys-stream")?;
+        Ok(KeyStreamInner {
+            kv: self_.clone(),
+            position: Arc::new(Mutex::new(StreamPosition::default())),
+        })
+    }
+
+    fn kv_resume_keys_stream(
+        &mut self,
+        self_: &Self::Kv,
+        token: &str,
+    ) -> Result<Self::KeyStream, Error> {
+        let host_state = &self.host_state;
+        Ok(host_state.slight_state.instrument(
+            SCHEME_NAME,
+            "resume-keys-stream",
+            &self_.name,
+            || {
+                let position = host_state
+                    .page_tokens
+                    .resume::<StreamPosition>(&host_state.page_token_scope(&self_.name), token)?;
+                Ok(KeyStreamInner {
+                    kv: self_.clone(),
+                    position: Arc::new(Mutex::new(position)),
+                })
+            },
+        )?)
+    }
+
+    fn key_stream_token(&mut self, self_: &Self::KeyStream) -> Result<String, Error> {
+        let host_state = &self.host_state;
+        Ok(host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "token", &self_.kv.name, || {
+                host_state.page_tokens.issue(
+                    &host_state.page_token_scope(&self_.kv.name),
+                    &*self_.position.lock().unwrap(),
+                )
+            })?)
+    }
+
+    fn key_stream_next_page(
+        &mut self,
+        self_: &Self::KeyStream,
+        max: u32,
+    ) -> Result<Option<Vec<PayloadResult>>, Error> {
+        let slight_state = &self.host_state.slight_state;
+        slight_state.instrument(SCHEME_NAME, "next-page", &self_.kv.name, || {
+            let mut position = self_.position.lock().unwrap();
+            // pages w/ no keys (e.g., as the keys of a scan expired) aren't handed to the
+            // guest, as an empty page would look like the end of the stream
+            loop {
+                let backends = self_.kv.open()?;
+                let backend = match backends.backends().nth(position.backend) {
+                    Some(backend) => backend,
+                    None => return Ok(None),
+                };
+                let (keys, cursor) = slight_state.recorded(
+                    SCHEME_NAME,
+                    "next-page",
+                    &[
+                        &self_.kv.name,
+                        &(position.backend as u64),
+                        &position.cursor,
+                        &max,
+                    ],
+                    || backend.list_keys_page(position.cursor.as_deref(), max.max(1) as usize),
+                )?;
+                let keys = compressed_keys(slight_state, keys);
+                // the position only moves past a page the guest has room for, so it can get it
+                // in smaller pages instead
+                if !keys.is_empty() {
+                    slight_state.check_headroom("next-page", listed_bytes(&keys))?;
+                }
+                if cursor.is_none() {
+                    position.backend += 1;
+                }
+                position.cursor = cursor;
+                if !keys.is_empty() {
+                    return Ok(Some(keys));
+                }
+            }
+        })
+    }
+
+    fn kv_release(&mut self, self_: &Self::Kv) -> Result<(), Error> {
+        let slight_state = &self.host_state.slight_state;
+        slight_state.instrument(SCHEME_NAME, "release", &self_.name, || {
+            // the store's entry in the `resource_map` shares its backends w/ the guest's, and
+            // they're drained w/o the map locked
+            let releaser = slight_state
+                .resource_map
+                .lock()
+                .unwrap()
+                .releaser(&self_.resource_descriptor)?;
+            if releaser.map_or(Ok(false), |release| release())? {
+                tracing::info!("released the backends of kv store '{}'", self_.name);
+            }
+            Ok(())
+        })
     }
 
     fn kv_watch(&mut self, self_: &Self::Kv, key: &str) -> Result<Observable, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "watch")?;
         Ok(Observable {
             rd: self_.resource_descriptor.clone(),
             key: key.to_string(),
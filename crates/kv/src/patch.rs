@@ -0,0 +1,537 @@
+use std::io::{Read, Write};
+
+use anyhow::{bail, Context, Result};
+use bzip2::{read::BzDecoder, write::BzEncoder, Compression};
+use serde_json::{json, Value};
+use slight_runtime::encoding::Encoding;
+
+/// The magic bytes a bsdiff patch starts w/ (i.e., the format, and its version).
+const MAGIC: &[u8] = b"BSDIFF40";
+
+/// The length of the header of a bsdiff patch: the `MAGIC` bytes, and three lengths.
+const HEADER_LENGTH: usize = 32;
+
+/// The length of a control of a bsdiff patch: three lengths.
+const CONTROL_LENGTH: usize = 24;
+
+/// A patch of a value, which is computed against it (i.e., its `base`), and decoded from
+/// either of its encodings:
+///     - `Encoding::Binary`, the standard bsdiff format (i.e., `BSDIFF40`, as made by the
+///     `bsdiff` tool, or the libraries of most languages), which is:
+///         - the `MAGIC` bytes (i.e., `BSDIFF40`),
+///         - the lengths of the control, and diff blocks, and of the patched value (as
+///         bsdiff's sign-magnitude little-endian `i64`s), and
+///         - the control, diff, and extra blocks, each compressed w/ bzip2 — the control
+///         block is a sequence of controls, each of which adds the next `x` bytes of the diff
+///         block to as many of the base, inserts the next `y` bytes of the extra block, and
+///         then seeks the base by `z` bytes, or
+///     - `Encoding::Json`, the same controls as a JSON object, for debugging (e.g., reading
+///     patches in a cassette), w/ the bytes they add in hex, and the ones they insert as text
+///     (or in hex, if they aren't UTF-8):
+/// ```json
+/// {
+///   "length": 12,
+///   "controls": [{ "add": "00000000000000", "insert": "there", "seek": 5 }]
+/// }
+/// ```
+///
+/// bsdiff patches don't say what value they were computed against, so one that's applied to
+/// another value is only rejected if it reaches past its end.
+///
+/// Both encodings of a patch decode to the same `Patch`, and encode back to the same bytes (so
+/// a patch can be transcoded from one to the other, e.g., to read a binary one) — as long as
+/// the binary one was compressed like bsdiff does (i.e., at bzip2's best compression).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Patch {
+    length: u64,
+    controls: Vec<Control>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Control {
+    /// the bytes added to as many bytes of the base
+    add: Vec<u8>,
+    /// the bytes inserted after them
+    insert: Vec<u8>,
+    /// how far the base is seeked after them
+    seek: i64,
+}
+
+impl Patch {
+    /// Decodes a patch that makes a value of up to `max_length` bytes (i.e., the capability's
+    /// payload limit) — the blocks of a binary one are only decompressed up to what the value it
+    /// declares makes room for, so a few bytes of patch can't expand to gigabytes (i.e., a
+    /// bzip2 bomb).
+    pub fn decode(patch: &[u8], encoding: Encoding, max_length: u64) -> Result<Self> {
+        let patch = match encoding {
+            Encoding::Binary => Self::decode_binary(patch, max_length)?,
+            Encoding::Json => Self::decode_json(patch)?,
+        };
+        check_length(patch.length, max_length)?;
+        Ok(patch)
+    }
+
+    pub fn encode(&self, encoding: Encoding) -> Vec<u8> {
+        match encoding {
+            Encoding::Binary => self.encode_binary(),
+            Encoding::Json => self.encode_json(),
+        }
+    }
+
+    /// Applies the patch to the `base` value it was computed against, returning the patched
+    /// value — errors leave nothing half-applied.
+    pub fn apply(&self, base: &[u8]) -> Result<Vec<u8>> {
+        // the patched value is only preallocated up to what the patch can account for, so a
+        // bogus length can't make us allocate w/o bound
+        let accounted = self
+            .controls
+            .iter()
+            .map(|control| control.add.len() + control.insert.len())
+            .sum::<usize>();
+        let mut patched = Vec::with_capacity(self.length.min(accounted as u64) as usize);
+        let mut at = 0i64;
+        for control in &self.controls {
+            let range = usize::try_from(at)
+                .ok()
+                .and_then(|start| Some(start..start.checked_add(control.add.len())?))
+                .filter(|range| range.end <= base.len())
+                .with_context(|| {
+                    format!(
+                        "invalid patch: it adds {} bytes at offset {} of a {} bytes long value (i.e., it was computed against another one)",
+                        control.add.len(),
+                        at,
+                        base.len()
+                    )
+                })?;
+            patched.extend(
+                base[range.clone()]
+                    .iter()
+                    .zip(&control.add)
+                    .map(|(byte, add)| byte.wrapping_add(*add)),
+            );
+            patched.extend_from_slice(&control.insert);
+            if patched.len() as u64 > self.length {
+                bail!(
+                    "invalid patch: it makes a value longer than the {} bytes it declares",
+                    self.length
+                );
+            }
+            at = (range.end as i64)
+                .checked_add(control.seek)
+                .with_context(|| "invalid patch: it seeks past the end of the value")?;
+        }
+        if patched.len() as u64 != self.length {
+            bail!(
+                "invalid patch: it makes a {} bytes long value, rather than the {} bytes it declares",
+                patched.len(),
+                self.length
+            );
+        }
+        Ok(patched)
+    }
+
+    fn decode_binary(patch: &[u8], max_length: u64) -> Result<Self> {
+        if patch.len() < HEADER_LENGTH || &patch[..MAGIC.len()] != MAGIC {
+            bail!(
+                "invalid patch: it doesn't start w/ '{}' (i.e., it isn't a bsdiff patch)",
+                String::from_utf8_lossy(MAGIC)
+            );
+        }
+        let header = |at: usize| -> Result<u64> {
+            u64::try_from(offtin(&patch[at..at + 8]))
+                .with_context(|| "invalid patch: its header has a negative length")
+        };
+        let control_length = header(8)? as usize;
+        let add_length = header(16)? as usize;
+        let length = header(24)?;
+        let block = |start: usize, length: usize| {
+            start
+                .checked_add(length)
+                .and_then(|end| patch.get(start..end))
+                .with_context(|| "invalid patch: it's truncated")
+        };
+        let control_block = block(HEADER_LENGTH, control_length)?;
+        let add_block = block(HEADER_LENGTH + control_length, add_length)?;
+        let insert_block = &patch[HEADER_LENGTH + control_length + add_length..];
+        check_length(length, max_length)?;
+        // the value's bytes are all added, or inserted by a control, and bsdiff's controls
+        // each make at least one of them (but for the last one)
+        let max_controls = length.saturating_add(1);
+        let control_block = decompress(
+            control_block,
+            "control",
+            max_controls.saturating_mul(CONTROL_LENGTH as u64),
+        )?;
+        let mut adds = Reader::new(decompress(add_block, "diff", length)?);
+        let mut inserts = Reader::new(decompress(insert_block, "extra", length)?);
+        if control_block.len() % CONTROL_LENGTH != 0 {
+            bail!("invalid patch: its control block is truncated");
+        }
+        let controls = control_block
+            .chunks(CONTROL_LENGTH)
+            .map(|control| {
+                let length = |at: usize| {
+                    usize::try_from(offtin(&control[at..at + 8]))
+                        .with_context(|| "invalid patch: a control has a negative length")
+                };
+                Ok(Control {
+                    add: adds.take(length(0)?)?,
+                    insert: inserts.take(length(8)?)?,
+                    seek: offtin(&control[16..24]),
+                })
+            })
+            .collect::<Result<_>>()?;
+        if !adds.done() || !inserts.done() {
+            bail!("invalid patch: its controls don't account for all of its bytes");
+        }
+        Ok(Self { length, controls })
+    }
+
+    fn encode_binary(&self) -> Vec<u8> {
+        let mut control_block = Vec::with_capacity(self.controls.len() * CONTROL_LENGTH);
+        let mut add_block = Vec::new();
+        let mut insert_block = Vec::new();
+        for control in &self.controls {
+            control_block.extend_from_slice(&offtout(control.add.len() as i64));
+            control_block.extend_from_slice(&offtout(control.insert.len() as i64));
+            control_block.extend_from_slice(&offtout(control.seek));
+            add_block.extend_from_slice(&control.add);
+            insert_block.extend_from_slice(&control.insert);
+        }
+        let control_block = compress(&control_block);
+        let add_block = compress(&add_block);
+        let mut patch = MAGIC.to_vec();
+        patch.extend_from_slice(&offtout(control_block.len() as i64));
+        patch.extend_from_slice(&offtout(add_block.len() as i64));
+        patch.extend_from_slice(&offtout(self.length as i64));
+        patch.extend_from_slice(&control_block);
+        patch.extend_from_slice(&add_block);
+        patch.extend_from_slice(&compress(&insert_block));
+        patch
+    }
+
+    fn decode_json(patch: &[u8]) -> Result<Self> {
+        let patch = serde_json::from_slice::<Value>(patch)
+            .with_context(|| "invalid patch: it isn't JSON")?;
+        let length = patch["length"]
+            .as_u64()
+            .with_context(|| "invalid patch: 'length' isn't an unsigned integer")?;
+        let controls = patch["controls"]
+            .as_array()
+            .with_context(|| "invalid patch: 'controls' isn't an array")?
+            .iter()
+            .map(|control| {
+                let add = control["add"]
+                    .as_str()
+                    .with_context(|| "invalid patch: a control lacks the bytes it adds")
+                    .and_then(from_hex)?;
+                let insert = if let Some(text) = control.get("insert").and_then(Value::as_str) {
+                    text.as_bytes().to_vec()
+                } else if let Some(hex) = control.get("insert_hex").and_then(Value::as_str) {
+                    from_hex(hex)?
+                } else {
+                    bail!("invalid patch: a control lacks the bytes it inserts")
+                };
+                let seek = control["seek"]
+                    .as_i64()
+                    .with_context(|| "invalid patch: a control lacks how far it seeks")?;
+                Ok(Control { add, insert, seek })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { length, controls })
+    }
+
+    fn encode_json(&self) -> Vec<u8> {
+        let controls = self
+            .controls
+            .iter()
+            .map(|control| {
+                let mut encoded = json!({ "add": to_hex(&control.add), "seek": control.seek });
+                match std::str::from_utf8(&control.insert) {
+                    Ok(text) => encoded["insert"] = json!(text),
+                    Err(_) => encoded["insert_hex"] = json!(to_hex(&control.insert)),
+                }
+                encoded
+            })
+            .collect::<Vec<_>>();
+        json!({
+            "length": self.length,
+            "controls": controls,
+        })
+        .to_string()
+        .into_bytes()
+    }
+}
+
+/// Decodes one of bsdiff's sign-magnitude little-endian `i64`s.
+fn offtin(bytes: &[u8]) -> i64 {
+    // the unwrap can't fail, as callers pass exactly 8 bytes
+    let value = u64::from_le_bytes(bytes.try_into().unwrap());
+    let magnitude = (value & !(1 << 63)) as i64;
+    if value & (1 << 63) != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+fn offtout(value: i64) -> [u8; 8] {
+    let mut encoded = value.unsigned_abs();
+    if value < 0 {
+        encoded |= 1 << 63;
+    }
+    encoded.to_le_bytes()
+}
+
+/// Fails if a patch makes a value longer than `max_length` bytes.
+fn check_length(length: u64, max_length: u64) -> Result<()> {
+    if length > max_length {
+        bail!(
+            "invalid patch: it makes a {} bytes long value, which is more than the {} bytes a value can be",
+            length,
+            max_length
+        );
+    }
+    Ok(())
+}
+
+/// Decompresses a block of a patch, failing if it's longer than `max_length` bytes, w/o
+/// decompressing more than a byte past them.
+fn decompress(block: &[u8], name: &str, max_length: u64) -> Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    BzDecoder::new(block)
+        .take(max_length.saturating_add(1))
+        .read_to_end(&mut decompressed)
+        .with_context(|| format!("invalid patch: its {} block isn't bzip2", name))?;
+    if decompressed.len() as u64 > max_length {
+        bail!(
+            "invalid patch: its {} block is longer than the {} bytes the value it makes accounts for",
+            name,
+            max_length
+        );
+    }
+    Ok(decompressed)
+}
+
+fn compress(block: &[u8]) -> Vec<u8> {
+    let mut encoder = BzEncoder::new(Vec::new(), Compression::best());
+    // writing to a `Vec` can't fail
+    encoder.write_all(block).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        bail!("invalid patch: '{}' isn't hex", hex);
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|at| {
+            hex.get(at..at + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .with_context(|| format!("invalid patch: '{}' isn't hex", hex))
+        })
+        .collect()
+}
+
+struct Reader {
+    block: Vec<u8>,
+    at: usize,
+}
+
+impl Reader {
+    fn new(block: Vec<u8>) -> Self {
+        Self { block, at: 0 }
+    }
+
+    fn done(&self) -> bool {
+        self.at == self.block.len()
+    }
+
+    fn take(&mut self, length: usize) -> Result<Vec<u8>> {
+        let bytes = self
+            .at
+            .checked_add(length)
+            .and_then(|end| self.block.get(self.at..end))
+            .with_context(|| "invalid patch: it's truncated")?
+            .to_vec();
+        self.at += length;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use slight_runtime::encoding::Encoding;
+
+    use super::{compress, offtin, offtout, Control, Patch, MAGIC};
+
+    /// The longest value the patches of the tests make (i.e., kv's default payload limit).
+    const MAX_LENGTH: u64 = 16 * 1024 * 1024;
+
+    fn apply(base: &[u8], patch: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Patch::decode(patch, Encoding::Binary, MAX_LENGTH)?.apply(base)
+    }
+
+    /// Builds a patch of `base` that keeps its head, inserts `inserted`, and keeps its tail
+    /// (i.e., like bsdiff would, w/ the head, and tail unchanged).
+    fn patch(base: &[u8], head: usize, inserted: &[u8], tail: usize) -> Vec<u8> {
+        let skipped = base.len() - head - tail;
+        Patch {
+            length: (head + inserted.len() + tail) as u64,
+            controls: vec![
+                Control {
+                    add: vec![0; head],
+                    insert: inserted.to_vec(),
+                    seek: skipped as i64,
+                },
+                Control {
+                    add: vec![0; tail],
+                    insert: Vec::new(),
+                    seek: 0,
+                },
+            ],
+        }
+        .encode(Encoding::Binary)
+    }
+
+    #[test]
+    fn apply_test() {
+        let base = "a".repeat(300) + "old" + &"z".repeat(300);
+        let patched = apply(base.as_bytes(), &patch(base.as_bytes(), 300, b"new", 300)).unwrap();
+        assert_eq!(
+            patched,
+            ("a".repeat(300) + "new" + &"z".repeat(300)).as_bytes()
+        );
+
+        // absent keys are patched as empty values
+        assert_eq!(
+            apply(b"", &patch(b"", 0, b"created", 0)).unwrap(),
+            b"created"
+        );
+
+        // the bytes a control adds change the base's (e.g., 'h' + 1 is 'i')
+        let added = Patch {
+            length: 2,
+            controls: vec![Control {
+                add: vec![1, 0],
+                insert: Vec::new(),
+                seek: 0,
+            }],
+        };
+        assert_eq!(added.apply(b"ho").unwrap(), b"io");
+    }
+
+    #[test]
+    fn invalid_patches_test() {
+        let base = b"hello, world";
+        let valid = patch(base, 7, b"there", 0);
+        assert!(valid.starts_with(MAGIC));
+        assert_eq!(apply(base, &valid).unwrap(), b"hello, there");
+
+        // computed against a shorter value
+        assert!(apply(b"hello", &valid).is_err());
+        // truncated
+        assert!(apply(base, &valid[..valid.len() - 1]).is_err());
+        // not a patch
+        assert!(apply(base, b"hello").is_err());
+        assert!(apply(base, b"SLPATCH1hello, world, and more bytes").is_err());
+        // w/ a declared length that doesn't match
+        let mut wrong_length = valid.clone();
+        wrong_length[24] += 1;
+        assert!(apply(base, &wrong_length).is_err());
+        // w/ a block that isn't bzip2
+        let mut corrupt = valid.clone();
+        corrupt[33] ^= 0xff;
+        assert!(apply(base, &corrupt).is_err());
+    }
+
+    #[test]
+    fn bomb_test() {
+        // a binary patch of a `length` bytes long value, w/ one control that inserts `inserted`
+        // bytes of the extra block
+        let binary = |length: i64, inserted: i64, extra: &[u8]| {
+            let control_block = compress(&[offtout(0), offtout(inserted), offtout(0)].concat());
+            let add_block = compress(b"");
+            let mut patch = MAGIC.to_vec();
+            patch.extend_from_slice(&offtout(control_block.len() as i64));
+            patch.extend_from_slice(&offtout(add_block.len() as i64));
+            patch.extend_from_slice(&offtout(length));
+            patch.extend_from_slice(&control_block);
+            patch.extend_from_slice(&add_block);
+            patch.extend_from_slice(&compress(extra));
+            patch
+        };
+        assert_eq!(
+            Patch::decode(&binary(5, 5, b"hello"), Encoding::Binary, MAX_LENGTH)
+                .unwrap()
+                .apply(b"")
+                .unwrap(),
+            b"hello"
+        );
+
+        // 64 MiB of zeros compress to a few KB, which are only decompressed up to the value
+        let bomb = binary(1024, 1024, &vec![0; 64 * 1024 * 1024]);
+        assert!(bomb.len() < 64 * 1024, "{}", bomb.len());
+        let e = Patch::decode(&bomb, Encoding::Binary, MAX_LENGTH).unwrap_err();
+        assert!(e.to_string().contains("extra block is longer"), "{}", e);
+
+        // and a value longer than the limit isn't made at all
+        let too_long = binary(1024, 1024, &[0; 1024]);
+        assert!(Patch::decode(&too_long, Encoding::Binary, 1024).is_ok());
+        let e = Patch::decode(&too_long, Encoding::Binary, 1023).unwrap_err();
+        assert!(e.to_string().contains("more than the 1023 bytes"), "{}", e);
+        let json = br#"{"length": 1024, "controls": []}"#;
+        assert!(Patch::decode(json, Encoding::Json, 1023).is_err());
+    }
+
+    #[test]
+    fn offt_test() {
+        for value in [0, 1, 12, -5, i64::MAX, -i64::MAX] {
+            assert_eq!(offtin(&offtout(value)), value);
+        }
+        // the sign is the top bit, rather than two's complement
+        assert_eq!(offtout(-1), [1, 0, 0, 0, 0, 0, 0, 0x80]);
+    }
+
+    #[test]
+    fn encodings_round_trip_test() {
+        let base = b"hello, world";
+        let binary = patch(base, 7, b"there", 0);
+        let decoded = Patch::decode(&binary, Encoding::Binary, MAX_LENGTH).unwrap();
+        let json = decoded.encode(Encoding::Json);
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&json).unwrap(),
+            serde_json::json!({
+                "length": 12,
+                "controls": [
+                    { "add": "00000000000000", "insert": "there", "seek": 5 },
+                    { "add": "", "insert": "", "seek": 0 },
+                ],
+            })
+        );
+        // both encodings decode to the same patch, and encode back to the same bytes
+        let from_json = Patch::decode(&json, Encoding::Json, MAX_LENGTH).unwrap();
+        assert_eq!(from_json, decoded);
+        assert_eq!(from_json.encode(Encoding::Binary), binary);
+        assert_eq!(from_json.apply(base).unwrap(), b"hello, there");
+
+        // bytes that aren't UTF-8 are inserted in hex
+        let binary = patch(b"", 0, &[0xff, 0x00], 0);
+        let json = Patch::decode(&binary, Encoding::Binary, MAX_LENGTH)
+            .unwrap()
+            .encode(Encoding::Json);
+        assert!(String::from_utf8(json.clone())
+            .unwrap()
+            .contains(r#""insert_hex":"ff00""#));
+        let from_json = Patch::decode(&json, Encoding::Json, MAX_LENGTH).unwrap();
+        assert_eq!(from_json.encode(Encoding::Binary), binary);
+
+        assert!(Patch::decode(br#"{"length": 12}"#, Encoding::Json, MAX_LENGTH).is_err());
+        assert!(Patch::decode(&binary, Encoding::Json, MAX_LENGTH).is_err());
+    }
+}
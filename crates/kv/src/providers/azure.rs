@@ -1,16 +1,52 @@
 use anyhow::Result;
-use azure_storage_blobs::prelude::BlobClient;
-use bytes::Bytes;
-use std::sync::Arc;
+use azure_core::{
+    error::{Error as AzureError, ErrorKind},
+    prelude::{IfMatchCondition, MaxResults, NextMarker, Range},
+};
+use azure_storage_blobs::prelude::{BlobClient, ContainerClient};
+use futures::StreamExt;
+use slight_runtime::error_kind::NotFound;
+use std::{num::NonZeroU32, sync::Arc};
+
+/// Get the HTTP status code of a failed request, if there was a response at all
+fn http_status(e: &AzureError) -> Option<u16> {
+    match e.kind() {
+        ErrorKind::HttpResponse { status, .. } => Some(*status),
+        _ => None,
+    }
+}
 
 /// Get the value given a `blob_client`
 pub async fn get(blob_client: Arc<BlobClient>) -> Result<Vec<u8>> {
+    match blob_client.get().execute().await {
+        // the body is copied once, into the buffer handed to the guest
+        Ok(res) => Ok(res.data.to_vec()),
+        Err(e) if http_status(&e) == Some(404) => {
+            Err(NotFound("the blob doesn't exist".into()).into())
+        }
+        Err(e) => Err(anyhow::anyhow!("{:?}", e)),
+    }
+}
+
+/// Get up to `length` bytes of the value given a `blob_client`, starting at `offset`
+pub async fn get_range(blob_client: Arc<BlobClient>, offset: u64, length: u64) -> Result<Vec<u8>> {
+    if length == 0 {
+        return Ok(Vec::new());
+    }
     let res = blob_client
         .get()
+        .range(Range::new(offset, offset.saturating_add(length)))
         .execute()
-        .await
-        .map_err(|e| anyhow::anyhow!("{:?}", e))?;
-    Ok(Bytes::from(res.data.to_vec()).to_vec())
+        .await;
+    match res {
+        Ok(res) => Ok(res.data.to_vec()),
+        // the range starts past the end of the blob
+        Err(e) if http_status(&e) == Some(416) => Ok(Vec::new()),
+        Err(e) if http_status(&e) == Some(404) => {
+            Err(NotFound("the blob doesn't exist".into()).into())
+        }
+        Err(e) => Err(anyhow::anyhow!("{:?}", e)),
+    }
 }
 
 /// Set the value given a `blob_client` and `value`
@@ -33,3 +69,78 @@ pub async fn delete(blob_client: Arc<BlobClient>) -> Result<()> {
         .map_err(|e| anyhow::anyhow!("{:?}", e))?;
     Ok(())
 }
+
+/// Get the value and its etag given a `blob_client`, or `None` if the blob doesn't exist
+pub async fn get_with_etag(blob_client: Arc<BlobClient>) -> Result<Option<(Vec<u8>, String)>> {
+    match blob_client.get().execute().await {
+        Ok(res) => Ok(Some((
+            res.data.to_vec(),
+            res.blob.properties.etag.to_string(),
+        ))),
+        Err(e) if http_status(&e) == Some(404) => Ok(None),
+        Err(e) => Err(anyhow::anyhow!("{:?}", e)),
+    }
+}
+
+/// Set the value given a `blob_client` and `value` only if the blob still has the given
+/// `etag` (or doesn't exist yet, if `etag` is `None`).
+///
+/// Returns `false` if that condition didn't hold.
+pub async fn set_if_match(
+    blob_client: Arc<BlobClient>,
+    value: Vec<u8>,
+    etag: Option<String>,
+) -> Result<bool> {
+    let condition = match etag {
+        Some(etag) => IfMatchCondition::Match(etag),
+        None => IfMatchCondition::NotMatch("*".to_string()),
+    };
+    match blob_client
+        .put_block_blob(value)
+        .content_type("text/plain")
+        .if_match(condition)
+        .execute()
+        .await
+    {
+        Ok(_) => Ok(true),
+        Err(e) if matches!(http_status(&e), Some(409) | Some(412)) => Ok(false),
+        Err(e) => Err(anyhow::anyhow!("{:?}", e)),
+    }
+}
+
+/// List the names of all blobs given a `container_client`
+pub async fn list_blob_names(container_client: &ContainerClient) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    let mut pages = container_client.list_blobs().into_stream();
+    while let Some(page) = pages.next().await {
+        let page = page.map_err(|e| anyhow::anyhow!("{:?}", e))?;
+        names.extend(page.blobs.blobs.into_iter().map(|blob| blob.name));
+    }
+    Ok(names)
+}
+
+/// List the names of a page of up to `max` blobs given a `container_client`, starting at the
+/// continuation `marker` of the previous page (if any), w/ the marker of the next page (none, if
+/// it's the last one)
+pub async fn list_blob_names_page(
+    container_client: &ContainerClient,
+    marker: Option<&str>,
+    max: usize,
+) -> Result<(Vec<String>, Option<String>)> {
+    // blob storage lists up to 5000 blobs per page
+    let max = NonZeroU32::new(max.clamp(1, 5000) as u32).unwrap();
+    let mut list = container_client
+        .list_blobs()
+        .max_results(MaxResults::new(max));
+    if let Some(marker) = marker {
+        list = list.marker(NextMarker::new(marker.to_string()));
+    }
+    let page = match list.into_stream().next().await {
+        Some(page) => page.map_err(|e| anyhow::anyhow!("{:?}", e))?,
+        None => return Ok((Vec::new(), None)),
+    };
+    Ok((
+        page.blobs.blobs.into_iter().map(|blob| blob.name).collect(),
+        page.next_marker.map(|marker| marker.as_str().to_string()),
+    ))
+}
@@ -1,19 +1,48 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use etcd_client::Client;
-use futures::executor::block_on;
-use slight_runtime::resource::BasicState;
+use slight_runtime::deadline::block_on;
+use slight_runtime::{
+    compat::{Compatibility, Requirement, Version},
+    describe::Description,
+    resource::BasicState,
+};
 
 use crate::providers::etcd;
 
+/// What the implementor needs of the etcd server: leases (which etcd v3 has had from the
+/// start), and the lock API (i.e., `v3lock`), which came w/ etcd 3.2.
+pub const COMPATIBILITY: Compatibility = Compatibility {
+    implementor: "lockd.etcd",
+    server: "etcd",
+    requirements: &[
+        Requirement {
+            feature: "leases",
+            minimum: Version::new(3, 0, 0),
+        },
+        Requirement {
+            feature: "the lock API",
+            minimum: Version::new(3, 2, 0),
+        },
+    ],
+    tested_major: 3,
+};
+
 /// This is the underlying struct behind the `Etcd` variant of the `EtcdImplementor` enum.
 ///
 /// It provides a property that pertains solely to the etcd implementation
 /// of this capability:
 ///     - `client`
 ///
-/// As per its' usage in `EtcdImplementor`, it must `derive` `Debug`, and `Clone`.
+/// As per its usage in `EtcdImplementor`, it must `derive` `Debug`, and `Clone`.
 #[derive(Clone)]
 pub struct EtcdImplementor {
     client: Option<Arc<Mutex<Client>>>,
@@ -26,39 +55,50 @@ impl std::fmt::Debug for EtcdImplementor {
 }
 
 impl EtcdImplementor {
-    pub fn new(slight_state: &BasicState) -> Self {
+    /// Describes the etcd server locks are kept in (see `slight_runtime::describe`).
+    pub fn describe(slight_state: &BasicState) -> Description {
+        Description::new("lockd.etcd", "etcd").with_endpoint(
+            "ETCD_ENDPOINT",
+            slight_runtime_configs::setting(slight_state, "ETCD_ENDPOINT"),
+        )
+    }
+
+    /// Connects to the etcd server, and checks it's compatible (see `COMPATIBILITY`).
+    pub fn connect(slight_state: &BasicState) -> Result<Self> {
         let endpoint = String::from_utf8(
-            slight_runtime_configs::get(
-                &slight_state.secret_store,
+            slight_runtime_configs::resolve(
+                &slight_state.secret_stores,
                 "ETCD_ENDPOINT",
                 &slight_state.config_toml_file_path,
             )
             .with_context(|| {
                 format!(
-                    "failed to get 'ETCD_ENDPOINT' secret using secret store type: {}",
-                    slight_state.secret_store
+                    "failed to get 'ETCD_ENDPOINT' secret using secret stores: {:?}",
+                    slight_state.secret_stores
                 )
-            })
-            .unwrap(),
-        )
-        .unwrap();
+            })?,
+        )?;
 
-        let client = block_on(Client::connect([endpoint], None))
-            .with_context(|| "failed to connect to etcd server")
-            .unwrap();
-        // ^^^ from my tests with localhost client, this never fails
-        Self {
+        let mut client = block_on(Client::connect([endpoint], None))?
+            .with_context(|| "failed to connect to etcd server")?;
+        let status = block_on(client.status())?
+            .with_context(|| "failed to get the version of the etcd server")?;
+        COMPATIBILITY.check(status.version())?;
+        Ok(Self {
             client: Some(Arc::new(Mutex::new(client))),
-        }
+        })
     }
 
     pub fn lock(&self, lock_name: &[u8]) -> Result<Vec<u8>> {
         let inner = self.client.as_ref().unwrap();
-        let pr = block_on(etcd::lock(&mut inner.lock().unwrap(), lock_name))
+        let pr = block_on(etcd::lock(&mut inner.lock().unwrap(), lock_name))?
             .with_context(|| "failed to acquire lock")?;
         Ok(pr)
     }
 
+    /// The lock is tied to a lease that is granted, and expired by the etcd server,
+    /// so its time to live doesn't depend on the host's clock (i.e., hosts w/ skewed
+    /// clocks can't disagree on whether the lock is still held).
     pub fn lock_with_time_to_live(
         &self,
         lock_name: &[u8],
@@ -69,15 +109,79 @@ impl EtcdImplementor {
             &mut inner.lock().unwrap(),
             lock_name,
             time_to_live_in_secs,
-        ))
+        ))?
         .with_context(|| "failed to acquire lock with time to live")?;
         Ok(pr)
     }
 
+    /// Like `lock_with_time_to_live`, but the lease is kept alive (see `KeptAlive`) from the
+    /// moment it's granted (i.e., while waiting for the lock too), until the returned `KeptAlive`
+    /// is dropped — so the lock is held for as long as the host needs it, and it only expires
+    /// `time_to_live_in_secs` after the host died.
+    pub fn lock_kept_alive(
+        &self,
+        lock_name: &[u8],
+        time_to_live_in_secs: i64,
+    ) -> Result<(Vec<u8>, KeptAlive)> {
+        let mut client = self.client.as_ref().unwrap().lock().unwrap().clone();
+        let lease_id = block_on(etcd::lease_grant(&mut client, time_to_live_in_secs))?
+            .with_context(|| "failed to grant lease")?;
+        let kept_alive = KeptAlive::start(
+            client.clone(),
+            lease_id,
+            Duration::from_secs(time_to_live_in_secs.max(1) as u64),
+        )?;
+        let key = block_on(etcd::lock_with_granted_lease(
+            &mut client,
+            lock_name,
+            lease_id,
+        ))?
+        .with_context(|| "failed to acquire lock")?;
+        Ok((key, kept_alive))
+    }
+
     pub fn unlock(&self, lock_key: &[u8]) -> Result<()> {
         let inner = self.client.as_ref().unwrap();
-        block_on(etcd::unlock(&mut inner.lock().unwrap(), lock_key))
+        block_on(etcd::unlock(&mut inner.lock().unwrap(), lock_key))?
             .with_context(|| "failed to unlock")?;
         Ok(())
     }
 }
+
+/// `KeptAlive` keeps a lease alive, renewing it every third of its `ttl` from a thread of its
+/// own, until it's dropped, or the etcd server says it expired.
+#[derive(Debug)]
+pub struct KeptAlive {
+    /// the thread stops once this is dropped
+    _stop: mpsc::Sender<()>,
+}
+
+impl KeptAlive {
+    fn start(mut client: Client, lease_id: i64, ttl: Duration) -> Result<Self> {
+        let (mut keeper, mut responses) = block_on(client.lease_keep_alive(lease_id))?
+            .with_context(|| "failed to keep lease alive")?;
+        let mut renew = move || -> Result<bool> {
+            block_on(keeper.keep_alive())??;
+            match block_on(responses.message())?? {
+                Some(response) => Ok(response.ttl() > 0),
+                None => bail!("the lease's keep-alive stream closed"),
+            }
+        };
+        let (stop, stopped) = mpsc::channel();
+        thread::Builder::new()
+            .name("slight-lockd-lease".to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(ttl / 3) {
+                    match renew() {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            tracing::warn!("lease {} expired, so its lock isn't held", lease_id);
+                            return;
+                        }
+                        Err(e) => tracing::warn!("failed to renew lease {}: {:#}", lease_id, e),
+                    }
+                }
+            })?;
+        Ok(Self { _stop: stop })
+    }
+}
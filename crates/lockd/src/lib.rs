@@ -4,12 +4,19 @@ pub mod providers;
 /// The `SCHEME_NAME` defines the name under which a resource is
 /// identifiable by in a `ResourceMap`.
 const SCHEME_NAME: &str = "lockd";
+/// The operations guests can retry if they time out (see `BasicState::with_idempotent_operations`)
+/// — none, as they all change the state of the backend.
+const IDEMPOTENT_OPERATIONS: &[&str] = &[];
+/// The operations that only read, which audits of writes only leave out (see
+/// `BasicState::with_read_operations`).
+const READ_OPERATIONS: &[&str] = &[];
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use uuid::Uuid;
 
 use implementors::etcd::EtcdImplementor;
-use slight_runtime::{impl_resource, resource::BasicState};
+pub use implementors::etcd::KeptAlive;
+use slight_runtime::{describe::Description, impl_resource, resource::BasicState};
 
 /// It is mandatory to `use <interface>::*` due to `impl_resource!`.
 /// That is because `impl_resource!` accesses the `crate`'s
@@ -17,7 +24,7 @@ use slight_runtime::{impl_resource, resource::BasicState};
 use lockd::*;
 wit_bindgen_wasmtime::export!("../../wit/lockd.wit");
 wit_error_rs::impl_error!(lockd::Error);
-wit_error_rs::impl_from!(anyhow::Error, lockd::Error::ErrorWithDescription);
+slight_runtime::impl_from_anyhow!(lockd::Error);
 wit_error_rs::impl_from!(
     std::string::FromUtf8Error,
     lockd::Error::ErrorWithDescription
@@ -56,22 +63,44 @@ impl LockdState {
     pub fn new(lockd_implementor: String, slight_state: BasicState) -> Self {
         Self {
             lockd_implementor,
-            slight_state,
+            slight_state: slight_state
+                .with_idempotent_operations(IDEMPOTENT_OPERATIONS)
+                .with_read_operations(READ_OPERATIONS),
         }
     }
+
+    /// Describes the effective configuration of the backend (see `slight_runtime::describe`),
+    /// w/o connecting to it.
+    pub fn describe(&self) -> Result<Description> {
+        let description = match self.lockd_implementor.as_str() {
+            "lockd.etcd" => EtcdImplementor::describe(&self.slight_state),
+            p => bail!(
+                "failed to match provided name (i.e., '{}') to any known host implementations",
+                p
+            ),
+        };
+        Ok(description.with_pool_size(self.slight_state.pool_size()))
+    }
+
+    /// Connects to the backend, and checks it's compatible w/ the implementor, so a backend
+    /// that can't serve the guest fails at startup, rather than when the guest first opens it.
+    pub fn check_compatibility(&self) -> Result<()> {
+        LockdImplementor::connect(&self.lockd_implementor, &self.slight_state).map(drop)
+    }
 }
 
 impl lockd::Lockd for Lockd {
     type Lockd = LockdInner;
 
     fn lockd_open(&mut self) -> Result<Self::Lockd, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
         // populate our inner lockd object w/ the state received from `slight`
         // (i.e., what type of lockd implementor we are using), and the assigned
         // name of the object.
         let inner = Self::Lockd::new(
             &self.host_state.lockd_implementor,
             &self.host_state.slight_state,
-        );
+        )?;
 
         self.host_state
             .slight_state
@@ -88,9 +117,18 @@ impl lockd::Lockd for Lockd {
         self_: &Self::Lockd,
         lock_name: PayloadParam<'_>,
     ) -> Result<PayloadResult, Error> {
-        Ok(match &self_.lockd_implementor {
-            LockdImplementor::Etcd(ei) => ei.lock(lock_name)?,
-        })
+        let target = String::from_utf8_lossy(lock_name);
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "lock", &target, || {
+                self.host_state
+                    .slight_state
+                    .recorded(SCHEME_NAME, "lock", &[&lock_name], || {
+                        Ok(match &self_.lockd_implementor {
+                            LockdImplementor::Etcd(ei) => ei.lock(lock_name)?,
+                        })
+                    })
+            })
     }
 
     fn lockd_lock_with_time_to_live(
@@ -99,11 +137,26 @@ impl lockd::Lockd for Lockd {
         lock_name: PayloadParam<'_>,
         time_to_live_in_secs: i64,
     ) -> Result<PayloadResult, Error> {
-        Ok(match &self_.lockd_implementor {
-            LockdImplementor::Etcd(ei) => {
-                ei.lock_with_time_to_live(lock_name, time_to_live_in_secs)?
-            }
-        })
+        let target = String::from_utf8_lossy(lock_name);
+        self.host_state.slight_state.instrument(
+            SCHEME_NAME,
+            "lock-with-time-to-live",
+            &target,
+            || {
+                self.host_state.slight_state.recorded(
+                    SCHEME_NAME,
+                    "lock-with-time-to-live",
+                    &[&lock_name, &time_to_live_in_secs],
+                    || {
+                        Ok(match &self_.lockd_implementor {
+                            LockdImplementor::Etcd(ei) => {
+                                ei.lock_with_time_to_live(lock_name, time_to_live_in_secs)?
+                            }
+                        })
+                    },
+                )
+            },
+        )
     }
 
     fn lockd_unlock(
@@ -111,10 +164,19 @@ impl lockd::Lockd for Lockd {
         self_: &Self::Lockd,
         lock_key: PayloadParam<'_>,
     ) -> Result<(), Error> {
-        match &self_.lockd_implementor {
-            LockdImplementor::Etcd(ei) => ei.unlock(lock_key)?,
-        };
-        Ok(())
+        let target = String::from_utf8_lossy(lock_key);
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "unlock", &target, || {
+                self.host_state
+                    .slight_state
+                    .recorded(SCHEME_NAME, "unlock", &[&lock_key], || {
+                        match &self_.lockd_implementor {
+                            LockdImplementor::Etcd(ei) => ei.unlock(lock_key)?,
+                        };
+                        Ok(())
+                    })
+            })
     }
 }
 
@@ -129,7 +191,7 @@ impl lockd::Lockd for Lockd {
 /// It must `derive`:
 ///     - `Debug` due to a constraint on the associated type.
 ///     - `Clone` because the `ResourceMap` it will be added onto,
-///     must own its' data.
+///     must own its data.
 ///
 /// It must be public because the implementation of `lockd::Lockd` cannot leak
 /// a private type.
@@ -140,29 +202,65 @@ pub struct LockdInner {
 }
 
 impl LockdInner {
-    fn new(lockd_implementor: &str, slight_state: &BasicState) -> Self {
-        Self {
-            lockd_implementor: LockdImplementor::new(lockd_implementor, slight_state),
+    fn new(lockd_implementor: &str, slight_state: &BasicState) -> Result<Self> {
+        Ok(Self {
+            lockd_implementor: LockdImplementor::connect(lockd_implementor, slight_state)?,
             resource_descriptor: Uuid::new_v4().to_string(),
-        }
+        })
     }
 }
 
 impl slight_runtime::resource::Watch for LockdInner {}
 
+/// `HostLockd` is a lock service for the host's own use, so that the replicas of an app can
+/// take turns doing what only one of them should (e.g., initializing the guest).
+#[derive(Debug, Clone)]
+pub struct HostLockd {
+    lockd_implementor: LockdImplementor,
+}
+
+impl HostLockd {
+    pub fn open(lockd_implementor: &str, slight_state: &BasicState) -> Result<Self> {
+        Ok(Self {
+            lockd_implementor: LockdImplementor::connect(lockd_implementor, slight_state)?,
+        })
+    }
+
+    /// Waits for the lock `lock_name`, returning its key, which it's held under until it's
+    /// unlocked — it's kept alive until the `KeptAlive` is dropped, so it only expires
+    /// `time_to_live_in_secs` after the host holding it died.
+    pub fn lock_kept_alive(
+        &self,
+        lock_name: &[u8],
+        time_to_live_in_secs: i64,
+    ) -> Result<(Vec<u8>, KeptAlive)> {
+        match &self.lockd_implementor {
+            LockdImplementor::Etcd(ei) => ei.lock_kept_alive(lock_name, time_to_live_in_secs),
+        }
+    }
+
+    pub fn unlock(&self, lock_key: &[u8]) -> Result<()> {
+        match &self.lockd_implementor {
+            LockdImplementor::Etcd(ei) => ei.unlock(lock_key),
+        }
+    }
+}
+
 /// This defines the available implementor implementations for the `Lockd` interface.
 ///
-/// As per its' usage in `LockdInner`, it must `derive` `Debug`, and `Clone`.
+/// As per its usage in `LockdInner`, it must `derive` `Debug`, and `Clone`.
 #[derive(Debug, Clone)]
 enum LockdImplementor {
     Etcd(EtcdImplementor),
 }
 
 impl LockdImplementor {
-    fn new(lockd_implementor: &str, slight_state: &BasicState) -> Self {
+    /// Connects to the backend of the implementor, failing if it can't, or it's incompatible
+    /// w/ it (see `slight_runtime::compat`).
+    fn connect(lockd_implementor: &str, slight_state: &BasicState) -> Result<Self> {
         match lockd_implementor {
-            "lockd.etcd" => Self::Etcd(EtcdImplementor::new(slight_state)),
-            p => panic!(
+            "lockd.etcd" => Ok(Self::Etcd(EtcdImplementor::connect(slight_state)?)),
+            p => bail!(
                 "failed to match provided name (i.e., '{}') to any known host implementations",
                 p
             ),
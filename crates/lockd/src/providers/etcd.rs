@@ -27,6 +27,17 @@ pub async fn lock_with_lease(
     Ok(resp?.key().to_vec())
 }
 
+/// Create a lock with a lease that was granted already (e.g., to keep it alive while waiting)
+pub async fn lock_with_granted_lease(
+    client: &mut Client,
+    lock_name: &[u8],
+    lease_id: i64,
+) -> Result<Vec<u8>> {
+    let lock_options = LockOptions::new().with_lease(lease_id);
+    let resp = client.lock(lock_name, Some(lock_options)).await?;
+    Ok(resp.key().to_vec())
+}
+
 pub async fn unlock(client: &mut Client, lock_key: &[u8]) -> Result<()> {
     client.unlock(lock_key).await?;
     Ok(())
@@ -1,15 +1,55 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
 
-use anyhow::{Context, Result};
-use azure_messaging_servicebus::prelude::Client;
-use futures::executor::block_on;
-use slight_runtime::resource::BasicState;
+use anyhow::{anyhow, Context, Result};
+use azure_core::HttpClient;
+use azure_messaging_servicebus::prelude::{Client, PeekLockResponse};
+use slight_runtime::deadline::block_on;
+use slight_runtime::{
+    call, credentials::CredentialsError, describe::Description, resource::BasicState,
+};
+use uuid::Uuid;
 
 use crate::providers::azure;
 
+/// The name of the credential (i.e., secret) the policy key is read from.
+const POLICY_KEY: &str = "AZURE_POLICY_KEY";
+
+/// The longest Service Bus locks a received message for (i.e., the longest lock duration a
+/// queue can be configured w/), after which it's redelivered, so it can't be acknowledged.
+const MAX_LOCK_DURATION: Duration = Duration::from_secs(5 * 60);
+
+/// The Service Bus client, and the policy key it was created w/.
+struct Connection {
+    policy_key: String,
+    client: Client,
+}
+
+/// The policy key is a credential (see `slight_runtime_configs::credential`), so,
+/// when it is rotated, the client is recreated w/ the new key on its next use.
+///
+/// Service Bus handles concurrent sends, and receives (i.e., each received message is locked
+/// to its receiver), so its operations aren't serialized (see `Serial`).
 #[derive(Clone)]
 pub struct AzSbusImplementor {
-    client: Option<Arc<Mutex<Client>>>,
+    connection: Arc<Mutex<Connection>>,
+    service_bus_namespace: String,
+    queue_name: String,
+    policy_name: String,
+    http_client: Arc<dyn HttpClient>,
+    slight_state: BasicState,
+    /// Messages received through `receive_batch` that haven't been acknowledged yet,
+    /// indexed by the handle given to the guest, until their lock expires
+    pending: Arc<Mutex<HashMap<String, Pending>>>,
+}
+
+/// A message received through `receive_batch`, and when it was locked.
+struct Pending {
+    peek_lock: PeekLockResponse,
+    locked_at: Instant,
 }
 
 impl std::fmt::Debug for AzSbusImplementor {
@@ -19,85 +59,243 @@ impl std::fmt::Debug for AzSbusImplementor {
 }
 
 impl AzSbusImplementor {
-    pub fn new(slight_state: &BasicState, name: &str) -> Self {
+    /// Describes the namespace queues are in (see `slight_runtime::describe`).
+    pub fn describe(slight_state: &BasicState) -> Description {
+        let namespace =
+            slight_runtime_configs::setting(slight_state, "AZURE_SERVICE_BUS_NAMESPACE");
+        let endpoint = namespace
+            .as_ref()
+            .map(|namespace| format!("{}.servicebus.windows.net", namespace))
+            .map_err(|e| anyhow!("{:#}", e));
+        Description::new("mq.azsbus", "Azure Service Bus")
+            .with_endpoint("AZURE_SERVICE_BUS_NAMESPACE", endpoint)
+            .with_setting("AZURE_SERVICE_BUS_NAMESPACE", namespace)
+            .with_setting(
+                "AZURE_POLICY_NAME",
+                slight_runtime_configs::setting(slight_state, "AZURE_POLICY_NAME"),
+            )
+            .with_secret(
+                POLICY_KEY,
+                slight_runtime_configs::setting(slight_state, POLICY_KEY),
+            )
+    }
+
+    pub fn new(slight_state: &BasicState, name: &str) -> Result<Self> {
         let service_bus_namespace = String::from_utf8(
-            slight_runtime_configs::get(
-                &slight_state.secret_store,
+            slight_runtime_configs::resolve(
+                &slight_state.secret_stores,
                 "AZURE_SERVICE_BUS_NAMESPACE",
                 &slight_state.config_toml_file_path,
             )
             .with_context(|| {
                 format!(
-                    "failed to get 'AZURE_SERVICE_BUS_NAMESPACE' secret using secret store type: {}",
-                    slight_state.secret_store
+                    "failed to get 'AZURE_SERVICE_BUS_NAMESPACE' secret using secret stores: {:?}",
+                    slight_state.secret_stores
                 )
-            })
-            .unwrap(),
+            })?,
         )
-        .unwrap();
+        .context("'AZURE_SERVICE_BUS_NAMESPACE' isn't valid UTF-8")?;
         let policy_name = String::from_utf8(
-            slight_runtime_configs::get(
-                &slight_state.secret_store,
+            slight_runtime_configs::resolve(
+                &slight_state.secret_stores,
                 "AZURE_POLICY_NAME",
                 &slight_state.config_toml_file_path,
             )
             .with_context(|| {
                 format!(
-                    "failed to get 'AZURE_POLICY_NAME' secret using secret store type: {}",
-                    slight_state.secret_store
-                )
-            })
-            .unwrap(),
-        )
-        .unwrap();
-        let policy_key = String::from_utf8(
-            slight_runtime_configs::get(
-                &slight_state.secret_store,
-                "AZURE_POLICY_KEY",
-                &slight_state.config_toml_file_path,
-            )
-            .with_context(|| {
-                format!(
-                    "failed to get 'AZURE_POLICY_KEY' secret using secret store type: {}",
-                    slight_state.secret_store
+                    "failed to get 'AZURE_POLICY_NAME' secret using secret stores: {:?}",
+                    slight_state.secret_stores
                 )
-            })
-            .unwrap(),
+            })?,
         )
-        .unwrap();
+        .context("'AZURE_POLICY_NAME' isn't valid UTF-8")?;
+        let policy_key = policy_key(slight_state)?;
 
         let http_client = azure_core::new_http_client();
+        let client = Client::new(
+            http_client.clone(),
+            service_bus_namespace.clone(),
+            name.to_owned(),
+            policy_name.clone(),
+            policy_key.clone(),
+        )
+        .with_context(|| "failed to connect to Azure Service Bus")?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(Connection { policy_key, client })),
+            service_bus_namespace,
+            queue_name: name.to_owned(),
+            policy_name,
+            http_client,
+            slight_state: slight_state.clone(),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
 
-        let client = Some(Arc::new(Mutex::new(
-            Client::new(
-                http_client,
-                service_bus_namespace,
-                name.to_owned(),
-                policy_name,
-                policy_key,
+    /// Locks the connection, recreating its client first if the policy key was rotated.
+    fn connection(&self) -> Result<MutexGuard<'_, Connection>> {
+        let policy_key = policy_key(&self.slight_state)?;
+        let mut connection = self.connection.lock().unwrap();
+        if connection.policy_key != policy_key {
+            tracing::info!(
+                "recreating the client for queue '{}' w/ the rotated policy key",
+                self.queue_name
+            );
+            connection.client = Client::new(
+                self.http_client.clone(),
+                self.service_bus_namespace.clone(),
+                self.queue_name.clone(),
+                self.policy_name.clone(),
+                policy_key.clone(),
             )
-            .with_context(|| "failed to connect to Azure Service Bus")
-            .unwrap(),
-        )));
-        Self { client }
+            .with_context(|| "failed to connect to Azure Service Bus")?;
+            connection.policy_key = policy_key;
+            self.slight_state.health.reconnected(crate::SCHEME_NAME);
+        }
+        Ok(connection)
+    }
+
+    /// Makes sure the next request refetches the policy key (and, if it was rotated, recreates
+    /// the client) if Service Bus said the current one expired.
+    fn refresh_if_expired<T>(&self, res: Result<T>) -> Result<T> {
+        if expired(&res) {
+            self.slight_state.credentials.invalidate(POLICY_KEY);
+        }
+        res
+    }
+
+    /// Makes a request w/ the client, replaying it on the recreated one if the policy key
+    /// expired, and its operation is idempotent (see `call::replayed`).
+    fn request<T>(&self, request: impl Fn(&mut Client) -> Result<T>) -> Result<T> {
+        let attempt = || self.refresh_if_expired(request(&mut self.connection()?.client));
+        let res = attempt();
+        let reconnected = expired(&res);
+        call::replayed(crate::SCHEME_NAME, res, reconnected, attempt)
     }
 
     pub fn send(&self, msg: &[u8]) -> Result<()> {
-        let inner = &self.client.as_ref().unwrap();
-        block_on(azure::send(
-            &mut inner.lock().unwrap(),
-            std::str::from_utf8(msg)
-                .with_context(|| "failed to parse message as UTF-8")?
-                .to_string(),
-        ))
-        .with_context(|| "failed to send message to Azure Service Bus")?;
-        Ok(())
+        let msg = std::str::from_utf8(msg)
+            .with_context(|| "failed to parse message as UTF-8")?
+            .to_string();
+        self.request(|client| {
+            block_on(azure::send(client, msg.clone()))?
+                .with_context(|| "failed to send message to Azure Service Bus")
+        })
     }
 
     pub fn receive(&self) -> Result<Vec<u8>> {
-        let inner = &self.client.as_ref().unwrap();
-        let result = block_on(azure::receive(&mut inner.lock().unwrap()))
-            .with_context(|| "failed to receive message from Azure Service Bus")?;
-        Ok(result)
+        self.request(|client| {
+            block_on(azure::receive(client))?
+                .with_context(|| "failed to receive message from Azure Service Bus")
+        })
+    }
+
+    /// Receives a message, waiting up to `wait_ms` for one to arrive on Service Bus' side (i.e.,
+    /// w/o polling), or an empty message if none did.
+    pub fn receive_wait(&self, wait_ms: u64) -> Result<Vec<u8>> {
+        let peek_lock = self.request(|client| {
+            block_on(azure::peek_lock(
+                client,
+                chrono::Duration::milliseconds(wait_ms.min(i64::MAX as u64) as i64),
+            ))?
+            .with_context(|| "failed to receive message from Azure Service Bus")
+        })?;
+        match peek_lock {
+            Some(peek_lock) => {
+                let msg = peek_lock.body().as_bytes().to_vec();
+                let res = block_on(azure::complete(&peek_lock))?
+                    .with_context(|| "failed to complete message on Azure Service Bus");
+                self.refresh_if_expired(res)?;
+                Ok(msg)
+            }
+            None => Ok(Vec::new()),
+        }
     }
+
+    pub fn receive_batch(&self, max: u32, wait_ms: u64) -> Result<Vec<(String, Vec<u8>)>> {
+        self.prune_pending();
+        let deadline = Instant::now() + Duration::from_millis(wait_ms);
+        let mut batch = Vec::new();
+        while batch.len() < max as usize {
+            // only block while we are still waiting for the first message, after that
+            // we just drain whatever is already available
+            let timeout = if batch.is_empty() {
+                deadline.saturating_duration_since(Instant::now())
+            } else {
+                Duration::ZERO
+            };
+            let peek_lock = self.request(|client| {
+                block_on(azure::peek_lock(
+                    client,
+                    chrono::Duration::from_std(timeout)?,
+                ))?
+                .with_context(|| "failed to receive message batch from Azure Service Bus")
+            })?;
+
+            match peek_lock {
+                Some(peek_lock) => {
+                    let handle = Uuid::new_v4().to_string();
+                    batch.push((handle.clone(), peek_lock.body().as_bytes().to_vec()));
+                    let pending = Pending {
+                        peek_lock,
+                        locked_at: Instant::now(),
+                    };
+                    self.pending.lock().unwrap().insert(handle, pending);
+                }
+                None => break,
+            }
+        }
+        Ok(batch)
+    }
+
+    /// Acknowledges the messages of `handles`, which are forgotten even if that fails
+    /// (i.e., their lock was lost), as Service Bus redelivers them.
+    pub fn ack_batch(&self, handles: Vec<&str>) -> Result<()> {
+        self.prune_pending();
+        for handle in handles {
+            let pending = self
+                .pending
+                .lock()
+                .unwrap()
+                .remove(handle)
+                .with_context(|| {
+                    format!(
+                        "unknown message handle: '{}' (i.e., it was acknowledged, or its lock \
+                         expired already)",
+                        handle
+                    )
+                })?;
+            let res = block_on(azure::complete(&pending.peek_lock))?
+                .with_context(|| "failed to acknowledge message on Azure Service Bus");
+            self.refresh_if_expired(res)?;
+        }
+        Ok(())
+    }
+
+    /// Forgets the pending messages whose lock expired, which Service Bus redelivers, so
+    /// the ones a guest never acknowledges don't pile up.
+    fn prune_pending(&self) {
+        self.pending
+            .lock()
+            .unwrap()
+            .retain(|_, pending| pending.locked_at.elapsed() < MAX_LOCK_DURATION);
+    }
+}
+
+/// Whether Service Bus said the policy key expired.
+fn expired<T>(res: &Result<T>) -> bool {
+    matches!(
+        res.as_ref().err().and_then(CredentialsError::of),
+        Some(CredentialsError::Expired(_))
+    )
+}
+
+fn policy_key(slight_state: &BasicState) -> Result<String> {
+    let policy_key =
+        slight_runtime_configs::credential(slight_state, POLICY_KEY).with_context(|| {
+            format!(
+                "failed to get '{}' secret using secret stores: {:?}",
+                POLICY_KEY, slight_state.secret_stores
+            )
+        })?;
+    Ok(String::from_utf8(policy_key)?)
 }
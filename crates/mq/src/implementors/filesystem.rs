@@ -1,12 +1,35 @@
 use std::{
+    collections::HashSet,
     env,
     fs::{self, File, OpenOptions},
-    io::{BufRead, BufReader, Read, Write},
-    path::PathBuf,
-    time::{SystemTime, UNIX_EPOCH},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use slight_runtime::{describe::Description, serial::Serial, split::fnv1a};
+
+/// The magic bytes a message file starts w/ (i.e., the format, and its version).
+const MAGIC: &[u8] = b"SLMQMSG1";
+
+/// The directory (in the base directory) malformed message files are moved to.
+const QUARANTINE: &str = ".quarantine";
+
+/// How long the messages received in a batch are in flight for (i.e., off the queue), unless
+/// they're acknowledged, before they're redelivered (see `receive_batch`).
+const VISIBILITY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the queue is checked again while waiting for messages, if filesystem
+/// notifications aren't available
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often the queue is checked again while waiting for messages, even if filesystem
+/// notifications are available, in case one is missed (e.g., on network filesystems)
+const NOTIFIED_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 /// This is the underlying struct behind the `Filesystem` variant of the `MqImplementor` enum.
 ///
@@ -14,7 +37,9 @@ use anyhow::Result;
 /// of this capability:
 ///     - `base`.
 ///
-/// As per its' usage in `MqImplementor`, it must `derive` `Debug`, and `Clone`.
+/// The queue is a file receives rewrite, so its operations are serialized (see `SERIALIZED`).
+///
+/// As per its usage in `MqImplementor`, it must `derive` `Debug`, and `Clone`.
 #[derive(Debug, Clone)]
 pub struct FilesystemImplementor {
     /// The base path for where the message queue can be found in your file-system
@@ -22,16 +47,44 @@ pub struct FilesystemImplementor {
     /// The name of a hidden file that maintains the queue order and
     /// contains the names of files representating queue elements
     queue: String,
+    /// The name of a hidden file that maintains the elements received in a batch, but not
+    /// acknowledged yet, and when they're redelivered (i.e., `<element> <unix ms>` per line)
+    in_flight: String,
+    visibility_timeout: Duration,
+    /// the turns the operations on the queue file take
+    serial: Serial,
 }
 
 impl FilesystemImplementor {
+    /// The queue file is read, and rewritten w/o the messages received, so a message sent in
+    /// between would be lost, and concurrent receives could get the same message — its
+    /// operations have to be serialized (see `Serial`).
+    pub const SERIALIZED: bool = true;
+
     pub fn new(name: &str) -> Self {
         Self {
             base: env::temp_dir().join(name).to_str().unwrap().to_owned(),
             queue: ".queue".to_string(),
+            in_flight: ".in-flight".to_string(),
+            visibility_timeout: VISIBILITY_TIMEOUT,
+            serial: Serial::default(),
         }
     }
 
+    /// Describes where queues are kept (see `slight_runtime::describe`) — each in a directory
+    /// of this one, named after it.
+    pub fn describe() -> Description {
+        Description::new("mq.filesystem", "the filesystem")
+            .with_setting("directory", Ok(env::temp_dir().display().to_string()))
+    }
+
+    /// Serializes the operations on the queue file w/ `serial` (i.e., the one shared by all of
+    /// the guest instances), rather than only the ones of this instance.
+    pub fn with_serial(mut self, serial: Serial) -> Self {
+        self.serial = serial;
+        self
+    }
+
     pub fn send(&self, msg: &[u8]) -> Result<()> {
         // get a random name for a queue element
         let rand_file_name = format!(
@@ -41,31 +94,55 @@ impl FilesystemImplementor {
 
         fs::create_dir_all(&self.base)?;
 
-        // create a file to store the queue element data
-        let mut file = File::create(PathBuf::from(&self.base).join(&rand_file_name))?;
+        // store the queue element data, so it's either all there, or not at all
+        write_atomically(
+            &PathBuf::from(&self.base).join(&rand_file_name),
+            &frame(msg),
+        )?;
 
-        // write to file msg sent
-        file.write_all(msg)?;
+        self.serial.run(|| -> Result<()> {
+            // open/create queue and store one random name for a queue element per line
+            let mut queue = fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(PathBuf::from(&self.base).join(&self.queue))?;
 
-        // open/create queue and store one random name for a queue element per line
-        let mut queue = fs::OpenOptions::new()
-            .write(true)
-            .append(true)
-            .create(true)
-            .open(PathBuf::from(&self.base).join(&self.queue))?;
+            // add queue element name to the bottom of the queue
+            writeln!(queue, "{}", rand_file_name)?;
 
-        // add queue element name to the bottom of the queue
-        writeln!(queue, "{}", rand_file_name)?;
-
-        Ok(())
+            Ok(())
+        })
     }
 
     pub fn receive(&self) -> Result<Vec<u8>> {
         fs::create_dir_all(&self.base)?;
 
+        let to_receive = match self.serial.run(|| self.pop())? {
+            Some(to_receive) => to_receive,
+            // if queue is empty, respond with empty string
+            None => return Ok(Vec::new()),
+        };
+
+        // get element at top of queue, or the next one, if it's malformed
+        let buf = match self.read_element(&to_receive)? {
+            Some(buf) => buf,
+            None => return self.receive(),
+        };
+
+        // clean-up element from disk
+        fs::remove_file(PathBuf::from(&self.base).join(&to_receive))?;
+
+        Ok(buf)
+    }
+
+    /// Takes the name of the element at the top of the queue off of it, if there's any.
+    fn pop(&self) -> Result<Option<String>> {
+        self.requeue_expired()?;
+
         // get the queue
         let queue = OpenOptions::new()
             .create(true)
+            .truncate(false)
             .read(true)
             .write(true)
             .open(PathBuf::from(&self.base).join(&self.queue))?;
@@ -89,28 +166,615 @@ impl FilesystemImplementor {
             }
 
             // update queue status
-            fs::write(
-                PathBuf::from(&self.base).join(&self.queue),
-                queue_post_receive,
+            write_atomically(
+                &PathBuf::from(&self.base).join(&self.queue),
+                queue_post_receive.as_bytes(),
             )?;
 
             // remove \n char from end of queue element
             to_receive.pop();
 
-            // get element at top of queue
-            let mut file = File::open(PathBuf::from(&self.base).join(&to_receive))?;
-            let mut buf = Vec::new();
+            Ok(Some(to_receive))
+        } else {
+            Ok(None)
+        }
+    }
 
-            // read element's message
-            file.read_to_end(&mut buf)?;
+    /// Receives a message, waiting up to `wait_ms` for one to arrive (see `wait_for`), or
+    /// an empty message if none did.
+    pub fn receive_wait(&self, wait_ms: u64) -> Result<Vec<u8>> {
+        let element = self.wait_for(wait_ms, || Ok(self.take(1, false)?.pop()))?;
+        match element {
+            Some((element, buf)) => {
+                // clean-up element from disk
+                fs::remove_file(PathBuf::from(&self.base).join(&element))?;
+                Ok(buf)
+            }
+            None => Ok(Vec::new()),
+        }
+    }
 
-            // clean-up element from disk
-            fs::remove_file(PathBuf::from(&self.base).join(&to_receive))?;
+    /// Receives up to `max` messages, waiting up to `wait_ms` for at least one to arrive
+    /// (see `wait_for`).
+    ///
+    /// Received messages are taken off the queue, and kept in flight, but their files are only
+    /// cleaned-up from disk once they are acknowledged through `ack_batch` — the ones that
+    /// aren't w/in the visibility timeout (e.g., as the guest crashed while handling them) are
+    /// put back at the top of the queue, so they're redelivered.
+    pub fn receive_batch(&self, max: u32, wait_ms: u64) -> Result<Vec<(String, Vec<u8>)>> {
+        let batch = self.wait_for(wait_ms, || {
+            let batch = self.take(max as usize, true)?;
+            Ok(Some(batch).filter(|batch| !batch.is_empty()))
+        })?;
+        Ok(batch.unwrap_or_default())
+    }
 
-            Ok(buf)
-        } else {
-            // if queue is empty, respond with empty string
-            Ok(Vec::new())
+    /// Acknowledges messages received in a batch, failing w/o acknowledging any if one of them
+    /// isn't in flight (e.g., as it was redelivered, once its visibility timeout expired).
+    pub fn ack_batch(&self, handles: Vec<&str>) -> Result<()> {
+        for handle in &handles {
+            // handles come from the guest, so make sure they can only point
+            // to a queue element inside of our base directory (i.e., not to the queue's files)
+            if Path::new(handle).file_name().and_then(|f| f.to_str()) != Some(handle)
+                || handle.starts_with('.')
+            {
+                bail!("invalid message handle: '{}'", handle);
+            }
+        }
+        fs::create_dir_all(&self.base)?;
+        self.serial.run(|| -> Result<()> {
+            let in_flight_path = PathBuf::from(&self.base).join(&self.in_flight);
+            let mut in_flight = read_lines(&in_flight_path)?;
+            for handle in &handles {
+                if !in_flight.iter().any(|entry| element_of(entry) == *handle) {
+                    bail!(
+                        "unknown message handle: '{}' (i.e., it was acknowledged, or redelivered already)",
+                        handle
+                    );
+                }
+            }
+            in_flight.retain(|entry| !handles.contains(&element_of(entry)));
+            write_lines(&in_flight_path, &in_flight)
+        })?;
+        for handle in handles {
+            fs::remove_file(PathBuf::from(&self.base).join(handle))
+                .with_context(|| format!("failed to acknowledge message '{}'", handle))?;
+        }
+        Ok(())
+    }
+
+    /// Puts the elements in flight whose visibility timeout expired back at the top of the
+    /// queue, so they're redelivered — it's to run in the queue's turn (see `serial`).
+    ///
+    /// The queue is written before the elements are taken out of flight, so a crash in between
+    /// redelivers them twice, rather than never.
+    fn requeue_expired(&self) -> Result<()> {
+        let in_flight_path = PathBuf::from(&self.base).join(&self.in_flight);
+        let in_flight = read_lines(&in_flight_path)?;
+        let now = unix_ms(SystemTime::now());
+        let (expired, in_flight): (Vec<_>, Vec<_>) = in_flight.into_iter().partition(|entry| {
+            // entries w/o a time (e.g., truncated by a crash) are redelivered right away
+            !matches!(
+                entry
+                    .split_once(' ')
+                    .and_then(|(_, redelivered_at)| redelivered_at.parse::<u64>().ok()),
+                Some(redelivered_at) if redelivered_at > now
+            )
+        });
+        if expired.is_empty() {
+            return Ok(());
+        }
+
+        let queue_path = PathBuf::from(&self.base).join(&self.queue);
+        let queue = read_lines(&queue_path)?;
+        let mut requeued = expired
+            .iter()
+            .map(|entry| element_of(entry).to_string())
+            .filter(|element| !queue.contains(element))
+            .collect::<Vec<_>>();
+        tracing::debug!(
+            "redelivering {} message(s) of '{}' that weren't acknowledged in {:?}",
+            requeued.len(),
+            self.base,
+            self.visibility_timeout
+        );
+        requeued.extend(queue);
+        write_lines(&queue_path, &requeued)?;
+        write_lines(&in_flight_path, &in_flight)
+    }
+
+    /// Peeks at the messages on the queue that aren't in `seen`, waiting up to `wait_ms` for
+    /// one to arrive (see `wait_for`), w/o taking any off the queue.
+    ///
+    /// The names of the messages peeked at are added to `seen`, which only keeps the ones
+    /// still on the queue, so it doesn't grow as messages are received.
+    pub fn peek(&self, wait_ms: u64, seen: &mut HashSet<String>) -> Result<Vec<Vec<u8>>> {
+        let peeked = self.wait_for(wait_ms, || {
+            fs::create_dir_all(&self.base)?;
+            let queue_path = PathBuf::from(&self.base).join(&self.queue);
+            let elements = match File::open(&queue_path) {
+                Ok(queue) => BufReader::new(queue)
+                    .lines()
+                    .collect::<std::io::Result<Vec<String>>>()?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => return Err(e.into()),
+            };
+            let on_queue = elements.iter().collect::<HashSet<_>>();
+            seen.retain(|element| on_queue.contains(element));
+
+            let mut peeked = Vec::new();
+            for element in elements {
+                if !seen.insert(element.clone()) {
+                    continue;
+                }
+                match fs::read(PathBuf::from(&self.base).join(&element)) {
+                    // malformed elements are left for receiving to quarantine
+                    Ok(buf) => match unframe(&buf) {
+                        Some(msg) => peeked.push(msg.to_vec()),
+                        None => tracing::warn!(
+                            "skipping malformed message '{}' of '{}'",
+                            element,
+                            self.base
+                        ),
+                    },
+                    // received in the meantime
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            Ok(Some(peeked).filter(|peeked| !peeked.is_empty()))
+        })?;
+        Ok(peeked.unwrap_or_default())
+    }
+
+    /// Calls `take` until it takes something, or `wait_ms` passed.
+    ///
+    /// In between, it waits for the queue to change w/ filesystem notifications (i.e., inotify
+    /// on Linux, FSEvents on macOS, and `ReadDirectoryChangesW` on Windows), so a message is
+    /// received as soon as it's sent. Where they aren't available, the queue is polled every
+    /// `POLL_INTERVAL` instead.
+    fn wait_for<T>(
+        &self,
+        wait_ms: u64,
+        mut take: impl FnMut() -> Result<Option<T>>,
+    ) -> Result<Option<T>> {
+        let deadline = Instant::now() + Duration::from_millis(wait_ms);
+        if let Some(taken) = take()? {
+            return Ok(Some(taken));
+        }
+        if wait_ms == 0 {
+            return Ok(None);
+        }
+
+        // the queue is checked again after the watcher is created, so a message sent in
+        // between isn't missed
+        let (sender, changes) = mpsc::channel();
+        let watcher = self.watch(sender);
+        loop {
+            if let Some(taken) = take()? {
+                return Ok(Some(taken));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            let timeout = deadline - now;
+            match &watcher {
+                Some(_) => {
+                    // a timeout just means the queue is checked again
+                    let _ = changes.recv_timeout(timeout.min(NOTIFIED_POLL_INTERVAL));
+                    // one check of the queue covers all the changes so far
+                    while changes.try_recv().is_ok() {}
+                }
+                None => thread::sleep(timeout.min(POLL_INTERVAL)),
+            }
+        }
+    }
+
+    /// Watches the base directory, notifying `sender` of its changes, or `None` if filesystem
+    /// notifications aren't available.
+    fn watch(&self, sender: mpsc::Sender<()>) -> Option<RecommendedWatcher> {
+        let watch = || -> notify::Result<RecommendedWatcher> {
+            let mut watcher =
+                notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+                    if res.is_ok() {
+                        let _ = sender.send(());
+                    }
+                })?;
+            watcher.watch(Path::new(&self.base), RecursiveMode::NonRecursive)?;
+            Ok(watcher)
+        };
+        match watch() {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                tracing::debug!(
+                    "filesystem notifications aren't available for '{}', polling instead: {}",
+                    self.base,
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Takes up to `max` elements from the top of the queue, returning their
+    /// names alongside their messages — keeping them `in_flight` until they're acknowledged,
+    /// if they're to be (see `receive_batch`).
+    fn take(&self, max: usize, in_flight: bool) -> Result<Vec<(String, Vec<u8>)>> {
+        fs::create_dir_all(&self.base)?;
+
+        let queue_path = PathBuf::from(&self.base).join(&self.queue);
+        let elements = self.serial.run(|| -> Result<Vec<String>> {
+            self.requeue_expired()?;
+            let queue = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .read(true)
+                .write(true)
+                .open(&queue_path)?;
+
+            let mut elements = BufReader::new(&queue)
+                .lines()
+                .collect::<std::io::Result<Vec<String>>>()?;
+            let rest = elements.split_off(max.min(elements.len()));
+            if elements.is_empty() {
+                return Ok(elements);
+            }
+
+            // the elements are in flight before they're off the queue, so a crash in between
+            // redelivers them, rather than losing them
+            if in_flight {
+                let in_flight_path = PathBuf::from(&self.base).join(&self.in_flight);
+                let redelivered_at = unix_ms(SystemTime::now() + self.visibility_timeout);
+                let mut entries = read_lines(&in_flight_path)?;
+                entries.extend(
+                    elements
+                        .iter()
+                        .map(|element| format!("{} {}", element, redelivered_at)),
+                );
+                write_lines(&in_flight_path, &entries)?;
+            }
+
+            // update queue status
+            let mut queue_post_receive = rest.join("\n");
+            if !queue_post_receive.is_empty() {
+                queue_post_receive += "\n";
+            }
+            write_atomically(&queue_path, queue_post_receive.as_bytes())?;
+            Ok(elements)
+        })?;
+
+        let mut batch = Vec::with_capacity(elements.len());
+        for element in elements {
+            if let Some(buf) = self.read_element(&element)? {
+                batch.push((element, buf));
+            }
+        }
+        Ok(batch)
+    }
+
+    /// Reads the message of a queue element, or `None` if it's malformed (e.g., truncated by a
+    /// crash of a slight from before writes were atomic), in which case it's moved to the
+    /// `QUARANTINE` directory, or missing (e.g., as the queue was, as well), w/ a warning.
+    fn read_element(&self, element: &str) -> Result<Option<Vec<u8>>> {
+        let path = PathBuf::from(&self.base).join(element);
+        let buf = match fs::read(&path) {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                tracing::warn!("skipping missing message '{}' of '{}'", element, self.base);
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(msg) = unframe(&buf) {
+            return Ok(Some(msg.to_vec()));
+        }
+        let quarantine = PathBuf::from(&self.base).join(QUARANTINE);
+        fs::create_dir_all(&quarantine)?;
+        fs::rename(&path, quarantine.join(element))
+            .with_context(|| format!("failed to quarantine message '{}'", element))?;
+        tracing::warn!(
+            "quarantined malformed message '{}' of '{}' in '{}'",
+            element,
+            self.base,
+            quarantine.display()
+        );
+        Ok(None)
+    }
+}
+
+/// Frames a message as `MAGIC`, its length, and FNV-1a hash (little-endian `u64`s), followed
+/// by the message, so a message file that doesn't hold all of it can be told apart.
+fn frame(msg: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(MAGIC.len() + 16 + msg.len());
+    framed.extend_from_slice(MAGIC);
+    framed.extend_from_slice(&(msg.len() as u64).to_le_bytes());
+    framed.extend_from_slice(&fnv1a(msg).to_le_bytes());
+    framed.extend_from_slice(msg);
+    framed
+}
+
+/// The message of a framed message file, or `None` if it's malformed — files w/o `MAGIC` were
+/// written by a slight from before messages were framed, and are taken as they are.
+fn unframe(buf: &[u8]) -> Option<&[u8]> {
+    let header = match buf.strip_prefix(MAGIC) {
+        Some(header) => header,
+        None => return Some(buf),
+    };
+    if header.len() < 16 {
+        return None;
+    }
+    let (lengths, msg) = header.split_at(16);
+    // the unwraps can't fail, as the halves are exactly 8 bytes
+    let length = u64::from_le_bytes(lengths[..8].try_into().unwrap());
+    let hash = u64::from_le_bytes(lengths[8..].try_into().unwrap());
+    Some(msg).filter(|msg| msg.len() as u64 == length && fnv1a(msg) == hash)
+}
+
+/// The lines of the file at `path`, or none if it doesn't exist.
+fn read_lines(path: &Path) -> Result<Vec<String>> {
+    match File::open(path) {
+        Ok(file) => Ok(BufReader::new(file)
+            .lines()
+            .collect::<std::io::Result<Vec<String>>>()?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Writes `lines` to the file at `path` (see `write_atomically`), one per line.
+fn write_lines(path: &Path, lines: &[String]) -> Result<()> {
+    let mut contents = lines.join("\n");
+    if !contents.is_empty() {
+        contents += "\n";
+    }
+    write_atomically(path, contents.as_bytes())
+}
+
+/// The element of an entry of the elements in flight (i.e., `<element> <unix ms>`).
+fn element_of(entry: &str) -> &str {
+    entry.split(' ').next().unwrap_or_default()
+}
+
+fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Writes `contents` to a temporary file next to `path`, syncs it to disk, and renames it to
+/// `path`, so readers (and crashes) never see half of it.
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .and_then(|f| f.to_str())
+        .unwrap_or_default();
+    let tmp = path.with_file_name(format!(".{}.tmp", file_name));
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path).with_context(|| format!("failed to write '{}'", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::{
+        collections::HashSet,
+        fs,
+        path::PathBuf,
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use anyhow::Result;
+    use slight_runtime::{resource::StateTable, serial::Serial};
+
+    use super::FilesystemImplementor;
+
+    #[test]
+    fn peek_test() -> Result<()> {
+        let mq = FilesystemImplementor::new(&format!("slight-mq-peek-{}", std::process::id()));
+        let mut seen = HashSet::new();
+        assert!(mq.peek(0, &mut seen)?.is_empty());
+
+        mq.send(b"first")?;
+        mq.send(b"second")?;
+        assert_eq!(
+            mq.peek(0, &mut seen)?,
+            vec![b"first".to_vec(), b"second".to_vec()]
+        );
+        // peeking doesn't take messages off the queue, and only shows the new ones
+        mq.send(b"third")?;
+        assert_eq!(mq.peek(0, &mut seen)?, vec![b"third".to_vec()]);
+        assert_eq!(mq.receive()?, b"first");
+
+        // messages that were received are forgotten
+        assert!(mq.peek(0, &mut seen)?.is_empty());
+        assert_eq!(seen.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn receive_wait_test() -> Result<()> {
+        let mq = FilesystemImplementor::new(&format!("slight-mq-wait-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&mq.base);
+
+        // an empty queue is only reported as such once the wait is over
+        let start = Instant::now();
+        assert!(mq.receive_wait(100)?.is_empty());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(mq.receive_batch(10, 50)?.is_empty());
+
+        // w/o a wait, it's reported right away, and queued messages are received right away
+        let start = Instant::now();
+        assert!(mq.receive_wait(0)?.is_empty());
+        mq.send(b"queued")?;
+        assert_eq!(mq.receive_wait(10_000)?, b"queued");
+        assert!(start.elapsed() < Duration::from_secs(5));
+        Ok(())
+    }
+
+    #[test]
+    fn receive_wait_wakes_up_test() -> Result<()> {
+        let mq = Arc::new(FilesystemImplementor::new(&format!(
+            "slight-mq-wake-{}",
+            std::process::id()
+        )));
+        let _ = fs::remove_dir_all(&mq.base);
+        fs::create_dir_all(&mq.base)?;
+
+        let sender = {
+            let mq = mq.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                mq.send(b"late").unwrap();
+            })
+        };
+        // it's woken up by the send (or, w/o notifications, by polling), long before the wait
+        // is over
+        let start = Instant::now();
+        assert_eq!(mq.receive_wait(30_000)?, b"late");
+        assert!(start.elapsed() < Duration::from_secs(10));
+        sender.join().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_message_test() -> Result<()> {
+        let mq = FilesystemImplementor::new(&format!("slight-mq-truncated-{}", std::process::id()));
+        let base = PathBuf::from(&mq.base);
+        mq.send(b"first")?;
+        mq.send(b"second")?;
+
+        // as if a slight crashed while writing the first message
+        let queue = fs::read_to_string(base.join(".queue"))?;
+        let first = queue.lines().next().unwrap().to_string();
+        let path = base.join(&first);
+        let framed = fs::read(&path)?;
+        fs::write(&path, &framed[..framed.len() - 2])?;
+
+        let mut seen = HashSet::new();
+        assert_eq!(mq.peek(0, &mut seen)?, vec![b"second".to_vec()]);
+        assert_eq!(mq.receive()?, b"second");
+        assert!(base.join(".quarantine").join(&first).exists());
+        assert!(mq.receive()?.is_empty());
+        Ok(())
+    }
+
+    /// How many message files are in the base directory of `mq` (i.e., not the queue's own).
+    fn message_files(mq: &FilesystemImplementor) -> Result<usize> {
+        Ok(fs::read_dir(&mq.base)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+            .count())
+    }
+
+    #[test]
+    fn ack_test() -> Result<()> {
+        let mq = FilesystemImplementor::new(&format!("slight-mq-ack-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&mq.base);
+        mq.send(b"first")?;
+        mq.send(b"second")?;
+
+        let batch = mq.receive_batch(10, 0)?;
+        assert_eq!(batch.len(), 2);
+        mq.ack_batch(vec![&batch[0].0])?;
+        assert_eq!(message_files(&mq)?, 1);
+        // the message that wasn't acknowledged is in flight, rather than back on the queue
+        assert!(mq.receive_batch(10, 0)?.is_empty());
+        assert!(mq.receive()?.is_empty());
+
+        // acknowledged messages, and the queue's own files aren't in flight
+        assert!(mq.ack_batch(vec![&batch[0].0]).is_err());
+        assert!(mq.ack_batch(vec![".queue"]).is_err());
+        assert!(mq.ack_batch(vec!["../.in-flight"]).is_err());
+        // and a batch w/ one of them isn't acknowledged at all
+        assert!(mq.ack_batch(vec![&batch[1].0, &batch[0].0]).is_err());
+        mq.ack_batch(vec![&batch[1].0])?;
+        assert_eq!(message_files(&mq)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn redelivery_test() -> Result<()> {
+        let mut mq =
+            FilesystemImplementor::new(&format!("slight-mq-redelivery-{}", std::process::id()));
+        mq.visibility_timeout = Duration::from_millis(100);
+        let _ = fs::remove_dir_all(&mq.base);
+        mq.send(b"first")?;
+        mq.send(b"second")?;
+        mq.send(b"third")?;
+
+        let batch = mq.receive_batch(2, 0)?;
+        assert_eq!(batch.len(), 2);
+        assert_eq!(mq.receive_batch(1, 0)?[0].1, b"third");
+        thread::sleep(Duration::from_millis(200));
+
+        // the messages that weren't acknowledged in time are redelivered, first
+        let redelivered = mq.receive_batch(10, 0)?;
+        assert_eq!(
+            redelivered
+                .iter()
+                .map(|(_, msg)| msg.as_slice())
+                .collect::<Vec<_>>(),
+            vec![b"first".as_slice(), b"second", b"third"]
+        );
+        // w/ the same handles, which can only be acknowledged once
+        assert_eq!(redelivered[0].0, batch[0].0);
+        mq.ack_batch(
+            redelivered
+                .iter()
+                .map(|(handle, _)| handle.as_str())
+                .collect(),
+        )?;
+        assert!(mq.ack_batch(vec![&batch[0].0]).is_err());
+
+        // once they were, they're gone for good
+        thread::sleep(Duration::from_millis(200));
+        assert!(mq.receive_batch(10, 0)?.is_empty());
+        assert_eq!(message_files(&mq)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_send_and_receive_test() -> Result<()> {
+        let name = format!("slight-mq-concurrent-{}", std::process::id());
+        let serial = Serial::of(
+            &Mutex::new(StateTable::default()),
+            "mq",
+            &name,
+            FilesystemImplementor::SERIALIZED,
+        );
+        let mq = Arc::new(FilesystemImplementor::new(&name).with_serial(serial));
+        let _ = fs::remove_dir_all(&mq.base);
+
+        // w/o taking turns, a send in between a receive reading the queue, and rewriting it
+        // would be lost
+        let senders = (0..4)
+            .map(|sender| {
+                let mq = mq.clone();
+                thread::spawn(move || {
+                    for i in 0..25 {
+                        mq.send(format!("{}-{}", sender, i).as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut received = HashSet::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while received.len() < 100 && Instant::now() < deadline {
+            let batch = mq.receive_batch(10, 10)?;
+            mq.ack_batch(batch.iter().map(|(handle, _)| handle.as_str()).collect())?;
+            for (_, msg) in batch {
+                assert!(received.insert(msg), "received a message twice");
+            }
         }
+        for sender in senders {
+            sender.join().unwrap();
+        }
+        assert_eq!(received.len(), 100);
+        Ok(())
     }
 }
@@ -1,14 +1,44 @@
 mod implementors;
 pub mod providers;
+mod signed;
 
 /// The `SCHEME_NAME` defines the name under which a resource is
 /// identifiable by in a `ResourceMap`.
 const SCHEME_NAME: &str = "mq";
+/// The operations guests can retry if they time out (see `BasicState::with_idempotent_operations`)
+/// — none, as they all change the state of the backend.
+const IDEMPOTENT_OPERATIONS: &[&str] = &[];
+/// The operations that only read, which audits of writes only leave out (see
+/// `BasicState::with_read_operations`).
+const READ_OPERATIONS: &[&str] = &[];
+/// How often the queues are checked again while waiting for a message from any of them.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// The operations that are opt-in while they're new (see `slight_runtime::flags`), which the
+/// guest can only call if the capability's `enable_operations` has them.
+pub const EXPERIMENTAL_OPERATIONS: &[&str] = &["receive-batch"];
+/// How big the messages the guest sends can be, unless the capability says otherwise (see
+/// `slight_runtime::payload_limit`).
+pub const DEFAULT_MAX_PAYLOAD_BYTES: usize = 1024 * 1024;
 
-use anyhow::Result;
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
 
 use implementors::{azsbus::AzSbusImplementor, filesystem::FilesystemImplementor};
-use slight_runtime::{impl_resource, resource::BasicState};
+use slight_runtime::{
+    describe::Description,
+    drain::{InFlight, DEFAULT_DRAIN_GRACE},
+    impl_resource,
+    resource::{BasicState, Releaser},
+    serial::Serial,
+    signing::Signing,
+};
 use uuid::Uuid;
 
 /// It is mandatory to `use <interface>::*` due to `impl_resource!`.
@@ -17,7 +47,8 @@ use uuid::Uuid;
 use mq::*;
 wit_bindgen_wasmtime::export!("../../wit/mq.wit");
 wit_error_rs::impl_error!(mq::Error);
-wit_error_rs::impl_from!(anyhow::Error, mq::Error::ErrorWithDescription);
+
+slight_runtime::impl_from_anyhow!(mq::Error);
 
 /// The `Mq` structure is what will implement the `mq::Mq` trait
 /// coming from the generated code of off `mq.wit`.
@@ -43,28 +74,70 @@ impl_resource!(Mq, mq::MqTables<Mq>, MqState, SCHEME_NAME.to_string());
 /// It holds:
 ///     - a `mq_implementor` `String` — this comes directly from a
 ///     user's `slightfile` and it is what allows us to dynamically
-///     dispatch to a specific implementor's implentation, and
+///     dispatch to a specific implementor's implentation,
 ///     - the `slight_state` (of type `BasicState`) that contains common
 ///     things received from the slight binary (i.e., the `resource_map`,
-///     the `config_type`, and the `config_toml_file_path`).
+///     the `config_type`, and the `config_toml_file_path`),
+///     - the `signing` of messages, if they are to be signed, and verified,
+///     - the `dead_letter_queue` messages that fail verification are sent to, if any,
+///     - the `queues` received from w/ `receive-any`, by name, and
+///     - the `next_queue` to check first, so that `receive-any` takes turns.
 pub struct MqState {
     mq_implementor: String,
     slight_state: BasicState,
+    signing: Option<Signing>,
+    dead_letter_queue: Option<String>,
+    queues: HashMap<String, MqInner>,
+    next_queue: Cell<usize>,
 }
 
 impl MqState {
     pub fn new(mq_implementor: String, slight_state: BasicState) -> Self {
         Self {
             mq_implementor,
-            slight_state,
+            slight_state: slight_state
+                .with_idempotent_operations(IDEMPOTENT_OPERATIONS)
+                .with_read_operations(READ_OPERATIONS),
+            signing: None,
+            dead_letter_queue: None,
+            queues: HashMap::new(),
+            next_queue: Cell::new(0),
         }
     }
+
+    /// Describes the effective configuration of the backend (see `slight_runtime::describe`),
+    /// w/o connecting to it.
+    pub fn describe(&self) -> Result<Description> {
+        let description = match self.mq_implementor.as_str() {
+            "mq.filesystem" => FilesystemImplementor::describe(),
+            "mq.azsbus" => AzSbusImplementor::describe(&self.slight_state),
+            p => bail!(
+                "failed to match provided name (i.e., '{}') to any known host implementations",
+                p
+            ),
+        };
+        Ok(description.with_pool_size(self.slight_state.pool_size()))
+    }
+
+    /// Signs the messages sent, and verifies the ones received before the guest gets them.
+    pub fn with_signing(mut self, signing: Option<Signing>) -> Self {
+        self.signing = signing;
+        self
+    }
+
+    /// Sends the messages that fail verification to `dead_letter_queue` (w/ the same
+    /// implementor), rather than dropping them.
+    pub fn with_dead_letter_queue(mut self, dead_letter_queue: Option<String>) -> Self {
+        self.dead_letter_queue = dead_letter_queue;
+        self
+    }
 }
 
 impl mq::Mq for Mq {
     type Mq = MqInner;
 
     fn mq_open(&mut self, name: &str) -> Result<Self::Mq, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
         // populate our inner mq object w/ the state received from `slight`
         // (i.e., what type of mq implementor we are using), and the assigned
         // name of the object.
@@ -72,7 +145,9 @@ impl mq::Mq for Mq {
             &self.host_state.mq_implementor,
             &self.host_state.slight_state,
             name,
-        );
+            self.host_state.signing.as_ref(),
+            self.host_state.dead_letter_queue.as_deref(),
+        )?;
 
         self.host_state
             .slight_state
@@ -84,72 +159,491 @@ impl mq::Mq for Mq {
         Ok(inner)
     }
 
+    fn mq_receive_any(
+        &mut self,
+        queues: Vec<&str>,
+        wait_ms: u64,
+    ) -> Result<Option<QueueMessage>, Error> {
+        if queues.is_empty() {
+            return Err(
+                anyhow::anyhow!("failed to receive from any queue: no queues given").into(),
+            );
+        }
+        let host_state = &mut self.host_state;
+        // the queues are only opened for calls that can be made (i.e., w/in the grants, flags,
+        // and deadline, which `instrument` checks again), and they're released as the ones
+        // opened by the guest are (see `Watch::releaser`)
+        host_state.slight_state.permit(SCHEME_NAME, "receive-any")?;
+        slight_runtime::deadline::check(SCHEME_NAME, "receive-any")?;
+        for name in &queues {
+            if !host_state.queues.contains_key(*name) {
+                let inner = MqInner::new(
+                    &host_state.mq_implementor,
+                    &host_state.slight_state,
+                    name,
+                    host_state.signing.as_ref(),
+                    host_state.dead_letter_queue.as_deref(),
+                )?;
+                host_state
+                    .slight_state
+                    .resource_map
+                    .lock()
+                    .unwrap()
+                    .set(inner.resource_descriptor.clone(), Box::new(inner.clone()));
+                host_state.queues.insert(name.to_string(), inner);
+            }
+        }
+        let host_state = &self.host_state;
+        host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "receive-any", &queues.join(","), || {
+                let deadline = Instant::now() + Duration::from_millis(bounded(wait_ms));
+                loop {
+                    let start = host_state.next_queue.get();
+                    // a round checks every queue once, w/o waiting on any of them, so a
+                    // message waiting on any queue is received right away
+                    let received = host_state.slight_state.recorded(
+                        SCHEME_NAME,
+                        "receive-any",
+                        &[&queues],
+                        || {
+                            for index in turns(start, queues.len()) {
+                                let msg = host_state.queues[queues[index]]
+                                    .mq_implementor
+                                    .receive_wait(0)?;
+                                if !msg.is_empty() {
+                                    return Ok(Some((index as u32, msg)));
+                                }
+                            }
+                            Ok(None)
+                        },
+                    )?;
+                    if let Some((index, msg)) = received {
+                        let index = index as usize % queues.len();
+                        // the next call starts after this queue, so it can't starve the others
+                        host_state.next_queue.set(index + 1);
+                        if let Some(payload) = host_state.queues[queues[index]].verified(msg)? {
+                            let payload =
+                                returned(&host_state.slight_state, "receive-any", payload)?;
+                            return Ok(Some(QueueMessage {
+                                queue: queues[index].to_string(),
+                                payload,
+                            }));
+                        }
+                        continue;
+                    }
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Ok(None);
+                    }
+                    thread::sleep(remaining.min(POLL_INTERVAL));
+                }
+            })
+    }
+
     fn mq_send(&mut self, self_: &Self::Mq, msg: PayloadParam<'_>) -> Result<(), Error> {
-        match &self_.mq_implementor {
-            MqImplementor::Filesystem(fi) => fi.send(msg)?,
-            MqImplementor::AzSbus(ai) => ai.send(msg)?,
-        };
-        Ok(())
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "send", &self_.name, || {
+                self.host_state
+                    .slight_state
+                    .check_payload("send", msg.len())?;
+                self.host_state.slight_state.take_bytes(msg.len())?;
+                self.host_state.slight_state.recorded(
+                    SCHEME_NAME,
+                    "send",
+                    &[&self_.name, &msg],
+                    || match &self_.signing {
+                        Some(signing) => self_.mq_implementor.send(&signed::seal(signing, msg)),
+                        None => self_.mq_implementor.send(msg),
+                    },
+                )?;
+                Ok(())
+            })
     }
 
     fn mq_receive(&mut self, self_: &Self::Mq) -> Result<PayloadResult, Error> {
-        Ok(match &self_.mq_implementor {
-            MqImplementor::Filesystem(fi) => fi.receive()?,
-            MqImplementor::AzSbus(ai) => ai.receive()?,
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "receive", &self_.name, || {
+                let msg = loop {
+                    let msg = self.host_state.slight_state.recorded(
+                        SCHEME_NAME,
+                        "receive",
+                        &[&self_.name],
+                        || match &self_.mq_implementor {
+                            MqImplementor::Filesystem(fi) => fi.receive(),
+                            MqImplementor::AzSbus(ai) => ai.receive(),
+                        },
+                    )?;
+                    // an empty message means the queue is empty
+                    if msg.is_empty() {
+                        break msg;
+                    }
+                    if let Some(msg) = self_.verified(msg)? {
+                        break msg;
+                    }
+                };
+                returned(&self.host_state.slight_state, "receive", msg)
+            })
+    }
+
+    fn mq_receive_wait(&mut self, self_: &Self::Mq, wait_ms: u64) -> Result<PayloadResult, Error> {
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "receive-wait", &self_.name, || {
+                let deadline = Instant::now() + Duration::from_millis(bounded(wait_ms));
+                let msg = loop {
+                    // rejected messages don't get more time to wait for another one
+                    let wait_ms = deadline
+                        .saturating_duration_since(Instant::now())
+                        .as_millis();
+                    // how long is left to wait depends on timing, so calls are told apart by
+                    // their queue only
+                    let msg = self.host_state.slight_state.recorded(
+                        SCHEME_NAME,
+                        "receive-wait",
+                        &[&self_.name],
+                        || match &self_.mq_implementor {
+                            MqImplementor::Filesystem(fi) => fi.receive_wait(wait_ms as u64),
+                            MqImplementor::AzSbus(ai) => ai.receive_wait(wait_ms as u64),
+                        },
+                    )?;
+                    if msg.is_empty() {
+                        break msg;
+                    }
+                    if let Some(msg) = self_.verified(msg)? {
+                        break msg;
+                    }
+                };
+                returned(&self.host_state.slight_state, "receive-wait", msg)
+            })
+    }
+
+    fn mq_receive_batch(
+        &mut self,
+        self_: &Self::Mq,
+        max: u32,
+        wait_ms: u64,
+    ) -> Result<Vec<ReceivedMessage>, Error> {
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "receive-batch", &self_.name, || {
+                let received = self.host_state.slight_state.recorded(
+                    SCHEME_NAME,
+                    "receive-batch",
+                    &[&self_.name, &max, &wait_ms],
+                    || match &self_.mq_implementor {
+                        MqImplementor::Filesystem(fi) => fi.receive_batch(max, bounded(wait_ms)),
+                        MqImplementor::AzSbus(ai) => ai.receive_batch(max, bounded(wait_ms)),
+                    },
+                )?;
+                // rejected messages are acknowledged right away, so they aren't redelivered
+                let mut batch = Vec::with_capacity(received.len());
+                let mut rejected = Vec::new();
+                for (handle, payload) in received {
+                    match self_.verified(payload)? {
+                        Some(payload) => batch.push((handle, payload)),
+                        None => rejected.push(handle),
+                    }
+                }
+                if !rejected.is_empty() {
+                    let _acking = self_.acking.start();
+                    self.host_state.slight_state.recorded(
+                        SCHEME_NAME,
+                        "ack-batch",
+                        &[&self_.name, &rejected],
+                        || {
+                            self_
+                                .mq_implementor
+                                .ack_batch(rejected.iter().map(String::as_str).collect())
+                        },
+                    )?;
+                }
+                let bytes = batch.iter().map(|(_, payload)| payload.len()).sum();
+                self.host_state.slight_state.charge_bytes(bytes);
+                let batch = batch
+                    .into_iter()
+                    .map(|(handle, payload)| ReceivedMessage {
+                        handle,
+                        payload: self.host_state.slight_state.compressed(payload),
+                    })
+                    .collect::<Vec<_>>();
+                // each message takes a pointer, and a length for its handle, and payload (i.e.,
+                // 16 bytes) too — the messages aren't acknowledged if the guest has no room for
+                // them, so they're redelivered once their visibility timeout, or lock expires
+                // (see `ack-batch`), e.g., to a guest that receives smaller batches
+                let returned = batch
+                    .iter()
+                    .map(|msg| msg.handle.len() + msg.payload.len() + 16)
+                    .sum();
+                self.host_state
+                    .slight_state
+                    .check_headroom("receive-batch", returned)?;
+                Ok(batch)
+            })
+    }
+
+    fn mq_ack_batch(&mut self, self_: &Self::Mq, handles: Vec<&str>) -> Result<(), Error> {
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "ack-batch", &self_.name, || {
+                let _acking = self_.acking.start();
+                self.host_state.slight_state.recorded(
+                    SCHEME_NAME,
+                    "ack-batch",
+                    &[&self_.name, &handles],
+                    || self_.mq_implementor.ack_batch(handles.clone()),
+                )?;
+                Ok(())
+            })
+    }
+}
+
+/// `HostMq` is a queue for the host's own use, so that it can look at, and consume messages
+/// w/o a guest (e.g., `slight mq tail`).
+#[derive(Debug, Clone)]
+pub struct HostMq {
+    mq_implementor: MqImplementor,
+}
+
+impl HostMq {
+    pub fn open(mq_implementor: &str, slight_state: &BasicState, name: &str) -> Result<Self> {
+        Ok(Self {
+            mq_implementor: MqImplementor::new(mq_implementor, slight_state, name)?,
         })
     }
+
+    pub fn send(&self, msg: &[u8]) -> Result<()> {
+        self.mq_implementor.send(msg)
+    }
+
+    /// Peeks at the messages that aren't in `seen` yet, waiting up to `wait_ms` for one to
+    /// arrive, w/o taking them off the queue (see `FilesystemImplementor::peek`).
+    ///
+    /// Only `mq.filesystem` supports it, as Service Bus can't show messages to anyone but
+    /// the receiver that locks them.
+    pub fn peek(&self, wait_ms: u64, seen: &mut HashSet<String>) -> Result<Vec<Vec<u8>>> {
+        match &self.mq_implementor {
+            MqImplementor::Filesystem(fi) => fi.peek(wait_ms, seen),
+            MqImplementor::AzSbus(_) => {
+                bail!("mq.azsbus can't peek at messages w/o receiving them, they can only be consumed")
+            }
+        }
+    }
+
+    /// Receives, and acknowledges up to `max` messages, waiting up to `wait_ms` for at
+    /// least one to arrive.
+    pub fn consume(&self, max: u32, wait_ms: u64) -> Result<Vec<Vec<u8>>> {
+        let batch = match &self.mq_implementor {
+            MqImplementor::Filesystem(fi) => fi.receive_batch(max, wait_ms)?,
+            MqImplementor::AzSbus(ai) => ai.receive_batch(max, wait_ms)?,
+        };
+        let handles = batch
+            .iter()
+            .map(|(handle, _)| handle.as_str())
+            .collect::<Vec<_>>();
+        self.mq_implementor.ack_batch(handles)?;
+        Ok(batch.into_iter().map(|(_, payload)| payload).collect())
+    }
 }
 
 /// This is the type of the associated type coming from the `mq::Mq` trait
 /// implementation.
 ///
 /// It holds:
-///     - a `mq_implementor` (i.e., a variant `MqImplementor` `enum`), and
+///     - a `mq_implementor` (i.e., a variant `MqImplementor` `enum`),
+///     - the `name` of the queue,
+///     - the `signing` of messages (if enabled),
+///     - the `dead_letter` queue messages that fail verification are sent to (if any),
+///     - the `acking` in flight (i.e., the acks of messages, which releasing it waits for), and
 ///     - a `resource_descriptor` (i.e., an UUID that uniquely identifies
 ///     resource's instance).
 ///
 /// It must `derive`:
 ///     - `Debug` due to a constraint on the associated type.
 ///     - `Clone` because the `ResourceMap` it will be added onto,
-///     must own its' data.
+///     must own its data.
 ///
 /// It must be public because the implementation of `mq::Mq` cannot leak
 /// a private type.
 #[derive(Debug, Clone)]
 pub struct MqInner {
     mq_implementor: MqImplementor,
+    name: String,
+    signing: Option<Signing>,
+    dead_letter: Option<MqImplementor>,
+    acking: InFlight,
     resource_descriptor: String,
 }
 
 impl MqInner {
-    fn new(mq_implementor: &str, slight_state: &BasicState, name: &str) -> Self {
-        Self {
-            mq_implementor: MqImplementor::new(mq_implementor, slight_state, name),
+    fn new(
+        mq_implementor: &str,
+        slight_state: &BasicState,
+        name: &str,
+        signing: Option<&Signing>,
+        dead_letter_queue: Option<&str>,
+    ) -> Result<Self> {
+        Ok(Self {
+            mq_implementor: MqImplementor::new(mq_implementor, slight_state, name)?,
+            name: name.to_string(),
+            signing: signing.cloned(),
+            dead_letter: dead_letter_queue
+                .map(|queue| MqImplementor::new(mq_implementor, slight_state, queue))
+                .transpose()?,
+            acking: InFlight::default(),
             resource_descriptor: Uuid::new_v4().to_string(),
+        })
+    }
+
+    /// Gets the payload of a received message, or `None` if it was rejected for failing
+    /// verification (see `Signing::accepts`), in which case it's sent, as it is, to the
+    /// dead-letter queue, if there's one.
+    fn verified(&self, msg: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let signing = match &self.signing {
+            Some(signing) => signing,
+            None => return Ok(Some(msg)),
+        };
+        let (signature, payload) = signed::open(&msg);
+        if signing.accepts(SCHEME_NAME, &[payload], signature) {
+            return Ok(Some(payload.to_vec()));
+        }
+        if let Some(dead_letter) = &self.dead_letter {
+            dead_letter
+                .send(&msg)
+                .with_context(|| "failed to send a rejected message to the dead-letter queue")?;
         }
+        Ok(None)
     }
 }
 
-impl slight_runtime::resource::Watch for MqInner {}
+impl slight_runtime::resource::Watch for MqInner {
+    /// Waits for the acks in flight (e.g., as the app shuts down), so the messages they
+    /// acknowledge aren't redelivered — the queue has no backend of its own to close, though.
+    fn releaser(&self) -> Option<Releaser> {
+        let acking = self.acking.clone();
+        let name = format!("mq '{}'", self.name);
+        Some(Box::new(move || {
+            acking.drain(&name, DEFAULT_DRAIN_GRACE);
+            Ok(false)
+        }))
+    }
+}
 
 /// This defines the available implementor implementations for the `Mq` interface.
 ///
-/// As per its' usage in `MqInner`, it must `derive` `Debug`, and `Clone`.
+/// As per its usage in `MqInner`, it must `derive` `Debug`, and `Clone`.
+///
+/// The implementors that hold clients share them w/ the other guest instances of the app
+/// (see `BasicState::connection`), rather than connecting once per instance.
 #[derive(Debug, Clone)]
 enum MqImplementor {
     Filesystem(FilesystemImplementor),
-    AzSbus(AzSbusImplementor),
+    AzSbus(Arc<AzSbusImplementor>),
 }
 
 impl MqImplementor {
-    fn new(mq_implementor: &str, slight_state: &BasicState, name: &str) -> Self {
-        match mq_implementor {
-            "mq.filesystem" => Self::Filesystem(FilesystemImplementor::new(name)),
-            "mq.azsbus" => Self::AzSbus(AzSbusImplementor::new(slight_state, name)),
-            p => panic!(
+    fn new(mq_implementor: &str, slight_state: &BasicState, name: &str) -> Result<Self> {
+        Ok(match mq_implementor {
+            "mq.filesystem" => {
+                Self::Filesystem(FilesystemImplementor::new(name).with_serial(Serial::of(
+                    &slight_state.resource_map,
+                    SCHEME_NAME,
+                    name,
+                    FilesystemImplementor::SERIALIZED,
+                )))
+            }
+            "mq.azsbus" => {
+                Self::AzSbus(slight_state.try_connection(mq_implementor, name, || {
+                    AzSbusImplementor::new(slight_state, name)
+                })?)
+            }
+            p => bail!(
                 "failed to match provided name (i.e., '{}') to any known host implementations",
                 p
             ),
+        })
+    }
+
+    fn send(&self, msg: &[u8]) -> Result<()> {
+        match self {
+            Self::Filesystem(fi) => fi.send(msg),
+            Self::AzSbus(ai) => ai.send(msg),
         }
     }
+
+    fn receive_wait(&self, wait_ms: u64) -> Result<Vec<u8>> {
+        match self {
+            Self::Filesystem(fi) => fi.receive_wait(wait_ms),
+            Self::AzSbus(ai) => ai.receive_wait(wait_ms),
+        }
+    }
+
+    fn ack_batch(&self, handles: Vec<&str>) -> Result<()> {
+        match self {
+            Self::Filesystem(fi) => fi.ack_batch(handles),
+            Self::AzSbus(ai) => ai.ack_batch(handles),
+        }
+    }
+}
+
+/// How long a receive waits for a message: `wait_ms`, but no longer than the deadline of its
+/// call, if it has one (e.g., that of the http request the guest is handling).
+fn bounded(wait_ms: u64) -> u64 {
+    slight_runtime::deadline::remaining().map_or(wait_ms, |remaining| {
+        wait_ms.min(remaining.as_millis() as u64)
+    })
+}
+
+/// Returns a message the guest received through `operation`, charging its bytes (see
+/// `BasicState::charge_bytes`), and compressed, failing if the guest has no room for it (see
+/// `BasicState::check_headroom`) — it's taken off the queue either way.
+fn returned(slight_state: &BasicState, operation: &str, msg: Vec<u8>) -> Result<Vec<u8>> {
+    slight_state.charge_bytes(msg.len());
+    let msg = slight_state.compressed(msg);
+    slight_state.check_headroom(operation, msg.len())?;
+    Ok(msg)
+}
+
+/// The order `len` queues are checked in, starting w/ the one at `start` (wrapping around).
+fn turns(start: usize, len: usize) -> impl Iterator<Item = usize> {
+    (0..len).map(move |turn| (start + turn) % len)
+}
+
+#[cfg(test)]
+mod unittests {
+    use slight_runtime::{
+        headroom::OutOfMemory,
+        memory::Limiter,
+        resource::{BasicState, ResourceMap},
+    };
+
+    use super::{returned, turns};
+
+    #[test]
+    fn turns_test() {
+        assert_eq!(turns(0, 3).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(turns(2, 3).collect::<Vec<_>>(), vec![2, 0, 1]);
+        // a start past the last queue wraps around (e.g., after a message from the last one)
+        assert_eq!(turns(3, 3).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(turns(0, 0).count(), 0);
+    }
+
+    #[test]
+    fn returned_test() {
+        let slight_state = BasicState::new(ResourceMap::default(), &[], "slightfile.toml");
+        assert_eq!(
+            returned(&slight_state, "receive-any", b"hello".to_vec()).unwrap(),
+            b"hello"
+        );
+
+        // a guest whose memory can't grow has no room for any message
+        let mut limiter = Limiter::default();
+        limiter.limit_memory(0);
+        let slight_state = slight_state.with_headroom(limiter.headroom());
+        let e = returned(&slight_state, "receive-any", b"hello".to_vec()).unwrap_err();
+        assert!(OutOfMemory::is(&e));
+        assert!(e.to_string().contains("'receive-any'"));
+    }
 }
@@ -1,19 +1,21 @@
 use anyhow::{bail, Result};
 use azure_messaging_servicebus::prelude::*;
 use chrono::Duration;
+use slight_runtime::credentials::CredentialsError;
 
 pub async fn send(client: &mut Client, msg: String) -> Result<()> {
-    client.send_message(&msg).await?;
+    client.send_message(&msg).await.map_err(rejected)?;
     Ok(())
 }
 
 pub async fn receive(client: &mut Client) -> Result<Vec<u8>> {
     let peek_lock = client
         .peek_lock_message2(Some(Duration::seconds(60)))
-        .await?;
+        .await
+        .map_err(rejected)?;
 
     if !peek_lock.status().is_success() {
-        bail!("{} when reading queue.", peek_lock.status());
+        return Err(rejected_status(&peek_lock));
     }
 
     if peek_lock.status() == http::StatusCode::NO_CONTENT {
@@ -24,3 +26,132 @@ pub async fn receive(client: &mut Client) -> Result<Vec<u8>> {
     peek_lock.delete_message().await?;
     Ok(body.as_bytes().to_vec())
 }
+
+/// Peek-lock the next message in the queue, waiting up to `timeout` for one to arrive.
+///
+/// The message stays locked until it's acknowledged through `complete`.
+pub async fn peek_lock(client: &mut Client, timeout: Duration) -> Result<Option<PeekLockResponse>> {
+    let peek_lock = client
+        .peek_lock_message2(Some(timeout))
+        .await
+        .map_err(rejected)?;
+
+    if !peek_lock.status().is_success() {
+        return Err(rejected_status(&peek_lock));
+    }
+
+    if peek_lock.status() == http::StatusCode::NO_CONTENT {
+        return Ok(None);
+    }
+
+    Ok(Some(peek_lock))
+}
+
+/// Complete (i.e., delete) a previously peek-locked message
+pub async fn complete(peek_lock: &PeekLockResponse) -> Result<()> {
+    peek_lock.delete_message().await.map_err(rejected)?;
+    Ok(())
+}
+
+/// The error of a request Service Bus failed, w/ what it said about the credentials, if
+/// it rejected them.
+fn rejected<E>(e: E) -> anyhow::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let description = e.to_string();
+    let e = anyhow::Error::new(e);
+    match status(&description).and_then(|status| credentials_error(status, &description)) {
+        Some(credentials_error) => e.context(credentials_error),
+        None => e,
+    }
+}
+
+/// The error of a peek-lock Service Bus didn't succeed.
+fn rejected_status(peek_lock: &PeekLockResponse) -> anyhow::Error {
+    let status = peek_lock.status();
+    let e = anyhow::anyhow!("{} when reading queue.", status);
+    match credentials_error(status.as_u16(), &peek_lock.body()) {
+        Some(credentials_error) => e.context(credentials_error),
+        None => e,
+    }
+}
+
+/// The (authorization related) status code in the description of an error, if any.
+fn status(description: &str) -> Option<u16> {
+    description
+        .split(|c: char| !c.is_ascii_digit())
+        .find_map(|code| match code {
+            "401" => Some(401),
+            "403" => Some(403),
+            _ => None,
+        })
+}
+
+/// Tells apart the ways Service Bus rejects credentials from the status, and the `detail`
+/// of its response (e.g., `<Error><Code>401</Code><Detail>ExpiredToken: ...</Detail></Error>`).
+///
+/// Service Bus answers requests w/ an expired, or a malformed token, as well as w/ one that
+/// lacks a claim w/ a 401, so the detail is what tells them apart.
+pub fn credentials_error(status: u16, detail: &str) -> Option<CredentialsError> {
+    let detail = detail.trim().to_string();
+    let lowercase = detail.to_lowercase();
+    match status {
+        401 | 403 if lowercase.contains("claim") => {
+            Some(CredentialsError::PermissionDenied(detail))
+        }
+        401 if lowercase.contains("expired") => Some(CredentialsError::Expired(detail)),
+        401 => Some(CredentialsError::Invalid(detail)),
+        // other 403s are about quotas, rather than credentials
+        403 if lowercase.contains("unauthorized") => {
+            Some(CredentialsError::PermissionDenied(detail))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use slight_runtime::credentials::CredentialsError;
+
+    use super::{credentials_error, status};
+
+    #[test]
+    fn credentials_error_test() {
+        let expired = "<Error><Code>401</Code><Detail>ExpiredToken: The token is expired. TrackingId:4f1b, SystemTracker:slight.servicebus.windows.net:queue, Timestamp:2022-08-01T00:00:00</Detail></Error>";
+        assert!(matches!(
+            credentials_error(401, expired),
+            Some(CredentialsError::Expired(_))
+        ));
+
+        let invalid = "<Error><Code>401</Code><Detail>InvalidSignature: The token has an invalid signature. TrackingId:4f1b, SystemTracker:slight.servicebus.windows.net:queue, Timestamp:2022-08-01T00:00:00</Detail></Error>";
+        assert!(matches!(
+            credentials_error(401, invalid),
+            Some(CredentialsError::Invalid(_))
+        ));
+
+        let no_claim = "<Error><Code>401</Code><Detail>Unauthorized access. 'Send' claim(s) are required to perform this operation. Resource: 'sb://slight.servicebus.windows.net/queue'. TrackingId:4f1b, SystemTracker:slight.servicebus.windows.net:queue, Timestamp:2022-08-01T00:00:00</Detail></Error>";
+        assert!(matches!(
+            credentials_error(401, no_claim),
+            Some(CredentialsError::PermissionDenied(_))
+        ));
+
+        let quota = "<Error><Code>403</Code><Detail>QuotaExceeded: Number of messages in the queue exceeds the quota. TrackingId:4f1b</Detail></Error>";
+        assert_eq!(credentials_error(403, quota), None);
+        assert_eq!(credentials_error(500, expired), None);
+    }
+
+    #[test]
+    fn status_test() {
+        assert_eq!(
+            status("Unexpected HTTP result (expected: [201], received: 401 Unauthorized)"),
+            Some(401)
+        );
+        assert_eq!(
+            status("received: 403 Forbidden, body: QuotaExceeded"),
+            Some(403)
+        );
+        assert_eq!(status("received: 4011"), None);
+        assert_eq!(status("connection reset"), None);
+    }
+}
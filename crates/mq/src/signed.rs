@@ -0,0 +1,51 @@
+use slight_runtime::signing::{Signing, SIGNATURE_HEADER};
+
+/// Queues carry payloads only (i.e., w/o headers), so a signed message is its signature on a
+/// line prefixed w/ `SIGNATURE_HEADER` (e.g., `slight-signature: hmac-sha256=6a3f…`),
+/// followed by its payload.
+pub fn seal(signing: &Signing, payload: &[u8]) -> Vec<u8> {
+    let signature = signing.sign(&[payload]);
+    let mut sealed =
+        Vec::with_capacity(SIGNATURE_HEADER.len() + signature.len() + payload.len() + 3);
+    sealed.extend_from_slice(SIGNATURE_HEADER.as_bytes());
+    sealed.extend_from_slice(b": ");
+    sealed.extend_from_slice(signature.as_bytes());
+    sealed.push(b'\n');
+    sealed.extend_from_slice(payload);
+    sealed
+}
+
+/// Splits a message into its signature (`None` if it isn't signed), and payload.
+pub fn open(message: &[u8]) -> (Option<&[u8]>, &[u8]) {
+    let prefix = [SIGNATURE_HEADER.as_bytes(), b": "].concat();
+    if !message.starts_with(&prefix) {
+        return (None, message);
+    }
+    let rest = &message[prefix.len()..];
+    match rest.iter().position(|byte| *byte == b'\n') {
+        Some(end) => (Some(&rest[..end]), &rest[end + 1..]),
+        None => (None, message),
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use slight_runtime::signing::{Algorithm, Signing, SigningKey};
+
+    use super::{open, seal};
+
+    #[test]
+    fn seal_open_test() {
+        let signing = Signing::new(
+            SigningKey::new(Algorithm::HmacSha256, b"secret".to_vec()),
+            true,
+        );
+        let sealed = seal(&signing, b"multi\nline");
+        assert!(sealed.starts_with(b"slight-signature: hmac-sha256="));
+        let (signature, payload) = open(&sealed);
+        assert_eq!(payload, b"multi\nline");
+        assert!(signing.accepts("mq", &[payload], signature));
+
+        assert_eq!(open(b"unsigned"), (None, b"unsigned".as_slice()));
+    }
+}
@@ -0,0 +1,263 @@
+use std::{fmt, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use handlebars::Handlebars;
+use slight_runtime::quota::Quota;
+
+use crate::retry::DEFAULT_MAX_RETRIES;
+
+/// Who sends the notifications of a channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provider {
+    /// emails, w/ an SMTP server
+    Smtp,
+    /// text messages, w/ Twilio
+    Twilio,
+    /// text messages, or messages to a topic, w/ AWS SNS
+    Sns,
+    /// nothing is sent, notifications are only logged (i.e., for local development)
+    Log,
+}
+
+impl Provider {
+    pub fn parse(provider: &str) -> Result<Self> {
+        Ok(match provider {
+            "smtp" => Self::Smtp,
+            "twilio" => Self::Twilio,
+            "sns" => Self::Sns,
+            "log" => Self::Log,
+            p => bail!(
+                "invalid provider: '{}' (expected 'smtp', 'twilio', 'sns', or 'log')",
+                p
+            ),
+        })
+    }
+
+    /// The name of the implementor of the provider (e.g., `notifications.smtp`), which its
+    /// connections are shared under (see `BasicState::connection`).
+    pub fn implementor(&self) -> &'static str {
+        match self {
+            Self::Smtp => "notifications.smtp",
+            Self::Twilio => "notifications.twilio",
+            Self::Sns => "notifications.sns",
+            Self::Log => "notifications.log",
+        }
+    }
+}
+
+/// A notification, rendered, and ready to be sent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Notification {
+    pub recipient: String,
+    /// only sent by the providers whose notifications have one (i.e., emails, and topics)
+    pub subject: String,
+    pub body: String,
+}
+
+/// A channel of the slightfile, which sends notifications through its provider.
+///
+/// It holds:
+///     - the `name` guests send through it by,
+///     - the `provider` that sends its notifications,
+///     - who they're sent `from` (e.g., an email address, or a phone number),
+///     - the `templates` they're rendered w/, and the `subject` template,
+///     - the `quota` that limits how many are sent per second, and
+///     - how many times a send that failed transiently is retried (i.e., `max_retries`).
+#[derive(Clone)]
+pub struct Channel {
+    pub name: String,
+    pub provider: Provider,
+    pub from: Option<String>,
+    templates: Arc<Handlebars<'static>>,
+    quota: Option<Arc<Quota>>,
+    pub max_retries: u32,
+}
+
+/// The name the subject template is registered under, which can't clash w/ the templates of
+/// the slightfile, as they're named by keys of a table.
+const SUBJECT_TEMPLATE: &str = "";
+
+impl fmt::Debug for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut templates = self
+            .templates
+            .get_templates()
+            .keys()
+            .filter(|name| name.as_str() != SUBJECT_TEMPLATE)
+            .collect::<Vec<_>>();
+        templates.sort();
+        f.debug_struct("Channel")
+            .field("name", &self.name)
+            .field("provider", &self.provider)
+            .field("from", &self.from)
+            .field("templates", &templates)
+            .field("max_retries", &self.max_retries)
+            .finish()
+    }
+}
+
+impl Channel {
+    /// Makes a channel w/ the `templates` (i.e., their names, and sources) compiled, so one
+    /// that doesn't is caught before the guest sends w/ it.
+    ///
+    /// The `subject` template defaults to the name of the template a notification is sent w/.
+    pub fn new(
+        name: &str,
+        provider: Provider,
+        from: Option<String>,
+        subject: Option<&str>,
+        templates: &[(String, String)],
+        max_retries: Option<u32>,
+    ) -> Result<Self> {
+        if from.is_none() && matches!(provider, Provider::Smtp | Provider::Twilio) {
+            bail!(
+                "invalid channel '{}': its provider needs a `from` to send notifications from",
+                name
+            );
+        }
+        if templates.is_empty() {
+            bail!("invalid channel '{}': it has no templates", name);
+        }
+        let mut registry = Handlebars::new();
+        // data that's missing fails the send, rather than being rendered as blank
+        registry.set_strict_mode(true);
+        // notifications are text (e.g., a text message), not html
+        registry.register_escape_fn(handlebars::no_escape);
+        for (template, source) in templates {
+            if template.is_empty() {
+                bail!("invalid channel '{}': a template has no name", name);
+            }
+            registry
+                .register_template_string(template, source)
+                .with_context(|| {
+                    format!("invalid template '{}' of channel '{}'", template, name)
+                })?;
+        }
+        if let Some(subject) = subject {
+            registry
+                .register_template_string(SUBJECT_TEMPLATE, subject)
+                .with_context(|| format!("invalid subject of channel '{}'", name))?;
+        }
+        Ok(Self {
+            name: name.to_string(),
+            provider,
+            from,
+            templates: Arc::new(registry),
+            quota: None,
+            max_retries: max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+        })
+    }
+
+    /// Limits how many notifications are sent through the channel per second.
+    pub fn with_quota(mut self, quota: Option<Arc<Quota>>) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    /// Takes a send from the quota of the channel (if it has any), failing w/
+    /// `quota::RateLimited` if it exceeds it — before the notification is rendered, or sent.
+    pub fn take_send(&self) -> Result<()> {
+        match &self.quota {
+            Some(quota) => quota.take_op(),
+            None => Ok(()),
+        }
+    }
+
+    /// Renders a notification w/ one of the templates of the channel, and the data.
+    pub fn render(
+        &self,
+        recipient: &str,
+        template: &str,
+        data: &[(&str, &str)],
+    ) -> Result<Notification> {
+        if template == SUBJECT_TEMPLATE || !self.templates.has_template(template) {
+            let mut names = self
+                .templates
+                .get_templates()
+                .keys()
+                .filter(|name| name.as_str() != SUBJECT_TEMPLATE)
+                .collect::<Vec<_>>();
+            names.sort();
+            bail!(
+                "failed to render template '{}': channel '{}' has no such template (i.e., one of {:?})",
+                template,
+                self.name,
+                names
+            );
+        }
+        if recipient.trim().is_empty() {
+            bail!("invalid recipient: it's empty");
+        }
+        let data = data
+            .iter()
+            .map(|(k, v)| (k.to_string(), serde_json::Value::from(*v)))
+            .collect::<serde_json::Map<_, _>>();
+        let render = |template: &str| {
+            self.templates
+                .render(template, &data)
+                .with_context(|| format!("failed to render template '{}'", template))
+        };
+        Ok(Notification {
+            recipient: recipient.to_string(),
+            subject: if self.templates.has_template(SUBJECT_TEMPLATE) {
+                render(SUBJECT_TEMPLATE)?
+            } else {
+                template.to_string()
+            },
+            body: render(template)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+
+    use super::{Channel, Provider};
+
+    fn templates() -> Vec<(String, String)> {
+        vec![(
+            "welcome".to_string(),
+            "Hi {{name}}, welcome to <Acme>!".to_string(),
+        )]
+    }
+
+    #[test]
+    fn render_test() -> Result<()> {
+        let email = Channel::new(
+            "email",
+            Provider::Smtp,
+            Some("noreply@acme.com".to_string()),
+            Some("Welcome, {{name}}"),
+            &templates(),
+            None,
+        )?;
+        let notification = email.render("ada@acme.com", "welcome", &[("name", "Ada")])?;
+        assert_eq!(notification.subject, "Welcome, Ada");
+        // it's text, so nothing is escaped
+        assert_eq!(notification.body, "Hi Ada, welcome to <Acme>!");
+
+        // data that's missing fails, and so do templates the channel doesn't have
+        assert!(email.render("ada@acme.com", "welcome", &[]).is_err());
+        assert!(email.render("ada@acme.com", "goodbye", &[]).is_err());
+        assert!(email.render("ada@acme.com", "", &[]).is_err());
+        assert!(email.render(" ", "welcome", &[("name", "Ada")]).is_err());
+
+        let log = Channel::new("log", Provider::Log, None, None, &templates(), None)?;
+        assert_eq!(
+            log.render("ada", "welcome", &[("name", "Ada")])?.subject,
+            "welcome"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn new_test() {
+        assert!(Channel::new("sms", Provider::Twilio, None, None, &templates(), None).is_err());
+        assert!(Channel::new("sms", Provider::Sns, None, None, &[], None).is_err());
+        let unclosed = vec![("welcome".to_string(), "Hi {{name".to_string())];
+        assert!(Channel::new("sms", Provider::Sns, None, None, &unclosed, None).is_err());
+        assert!(Provider::parse("pigeon").is_err());
+        assert_eq!(Provider::parse("sns").unwrap(), Provider::Sns);
+    }
+}
@@ -0,0 +1,34 @@
+use anyhow::Result;
+
+use crate::channel::Notification;
+
+/// This is the underlying struct behind the `Log` variant of the `NotificationsImplementor`
+/// enum.
+///
+/// It sends nothing: notifications are logged (w/ their body), so the templates of an app
+/// can be tried out in local development, w/o a provider.
+///
+/// As per its usage in `NotificationsImplementor`, it must `derive` `Debug`, and `Clone`.
+#[derive(Debug, Clone)]
+pub struct LogImplementor {
+    channel: String,
+}
+
+impl LogImplementor {
+    pub fn new(channel: &str) -> Self {
+        Self {
+            channel: channel.to_string(),
+        }
+    }
+
+    pub fn send(&self, notification: &Notification) -> Result<()> {
+        tracing::info!(
+            "notification through channel '{}' to '{}' ({}):\n{}",
+            self.channel,
+            notification.recipient,
+            notification.subject,
+            notification.body
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,4 @@
+pub mod log;
+pub mod smtp;
+pub mod sns;
+pub mod twilio;
@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use lettre::{
+    message::Mailbox,
+    transport::smtp::{self, authentication::Credentials},
+    Message, SmtpTransport, Transport,
+};
+use slight_runtime::{call::TimedOut, resource::BasicState};
+
+use crate::{channel::Notification, retry::Transient};
+
+/// How long to wait for the SMTP server, before failing w/ `TimedOut`.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// This is the underlying struct behind the `Smtp` variant of the `NotificationsImplementor`
+/// enum.
+///
+/// It sends notifications as (plain text) emails w/ an SMTP server, which it connects to w/
+/// STARTTLS (on port 587), from the `from` of the channel (e.g., `Acme <noreply@acme.com>`).
+///
+/// The `SMTP_HOST`, `SMTP_USERNAME`, and `SMTP_PASSWORD` are read from the secret store.
+///
+/// As per its usage in `NotificationsImplementor`, it must `derive` `Debug`, and `Clone`.
+#[derive(Debug, Clone)]
+pub struct SmtpImplementor {
+    transport: SmtpTransport,
+    from: Mailbox,
+}
+
+impl SmtpImplementor {
+    pub fn new(slight_state: &BasicState, from: &str) -> Result<Self> {
+        let secret = |key: &str| -> Result<String> {
+            let value = slight_runtime_configs::resolve(
+                &slight_state.secret_stores,
+                key,
+                &slight_state.config_toml_file_path,
+            )
+            .with_context(|| {
+                format!(
+                    "failed to get '{}' secret using secret stores: {:?}",
+                    key, slight_state.secret_stores
+                )
+            })?;
+            Ok(String::from_utf8(value)?)
+        };
+        let host = secret("SMTP_HOST")?;
+        tracing::info!("Creating a new SMTP transport w/ host: {}", host);
+        let transport = SmtpTransport::starttls_relay(&host)
+            .with_context(|| format!("invalid SMTP host: '{}'", host))?
+            .credentials(Credentials::new(
+                secret("SMTP_USERNAME")?,
+                secret("SMTP_PASSWORD")?,
+            ))
+            .timeout(Some(TIMEOUT))
+            .build();
+        Ok(Self {
+            transport,
+            from: from
+                .parse()
+                .with_context(|| format!("invalid email address to send from: '{}'", from))?,
+        })
+    }
+
+    pub fn send(&self, notification: &Notification) -> Result<()> {
+        let to: Mailbox = notification
+            .recipient
+            .parse()
+            .with_context(|| format!("invalid email address: '{}'", notification.recipient))?;
+        let email = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(&notification.subject)
+            .body(notification.body.clone())
+            .context("failed to build the email")?;
+        self.transport
+            .send(&email)
+            .map_err(failed)
+            .with_context(|| format!("failed to send an email to '{}'", notification.recipient))?;
+        Ok(())
+    }
+}
+
+/// Fails w/ `TimedOut` if the SMTP server didn't respond in time (so it may have taken the
+/// email), or transiently if it refused the email for now (i.e., w/ a 4xx).
+fn failed(e: smtp::Error) -> anyhow::Error {
+    if e.is_timeout() {
+        anyhow::Error::new(TimedOut(e.to_string()))
+    } else if e.is_transient() {
+        Transient(e.to_string()).into()
+    } else {
+        e.into()
+    }
+}
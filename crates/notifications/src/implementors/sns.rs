@@ -0,0 +1,83 @@
+use anyhow::{Context, Result};
+use aws_sdk_sns::{error::PublishError, model::MessageAttributeValue, types::SdkError, Client};
+use slight_runtime::call::TimedOut;
+use slight_runtime::deadline::block_on;
+
+use crate::{channel::Notification, retry::Transient};
+
+/// The message attribute the sender id of text messages is set w/.
+const SENDER_ID: &str = "AWS.SNS.SMS.SenderID";
+
+/// This is the underlying struct behind the `Sns` variant of the `NotificationsImplementor`
+/// enum.
+///
+/// It publishes notifications w/ AWS SNS: as text messages to recipients that are phone
+/// numbers (in E.164, e.g., `+14155550100`), or as messages to recipients that are topics
+/// (i.e., their arn), w/ a subject. The `from` of the channel, if any, is the sender id of
+/// text messages.
+///
+/// It uses the `aws_config::load_from_env()` for AWS Configuration (i.e., the
+/// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, and `AWS_REGION` environment variables).
+///
+/// As per its usage in `NotificationsImplementor`, it must `derive` `Debug`, and `Clone`.
+#[derive(Debug, Clone)]
+pub struct SnsImplementor {
+    client: Client,
+    sender_id: Option<String>,
+}
+
+impl SnsImplementor {
+    pub fn new(sender_id: Option<&str>) -> Self {
+        let shared_config = futures::executor::block_on(aws_config::load_from_env());
+        tracing::info!("Creating a new AWS SNS client");
+        Self {
+            client: Client::new(&shared_config),
+            sender_id: sender_id.map(str::to_string),
+        }
+    }
+
+    pub fn send(&self, notification: &Notification) -> Result<()> {
+        let req = self.client.publish().message(&notification.body);
+        let req = if notification.recipient.starts_with("arn:") {
+            req.topic_arn(&notification.recipient)
+                .subject(&notification.subject)
+        } else {
+            let req = req.phone_number(&notification.recipient);
+            match &self.sender_id {
+                Some(sender_id) => req.message_attributes(
+                    SENDER_ID,
+                    MessageAttributeValue::builder()
+                        .data_type("String")
+                        .string_value(sender_id)
+                        .build(),
+                ),
+                None => req,
+            }
+        };
+        block_on(req.send())?.map_err(failed).with_context(|| {
+            format!(
+                "failed to publish a message to '{}'",
+                notification.recipient
+            )
+        })?;
+        Ok(())
+    }
+}
+
+/// Fails w/ `TimedOut` if SNS didn't respond in time (so it may have published the message),
+/// or transiently if it couldn't be reached, throttled the message, or failed on its side.
+fn failed(e: SdkError<PublishError>) -> anyhow::Error {
+    match e {
+        SdkError::TimeoutError(e) => anyhow::Error::new(TimedOut(e.to_string())),
+        SdkError::DispatchFailure(e) if e.is_timeout() => {
+            anyhow::Error::new(TimedOut(e.to_string()))
+        }
+        SdkError::DispatchFailure(e) => Transient(e.to_string()).into(),
+        SdkError::ServiceError { err, raw }
+            if err.is_throttled_exception() || raw.http().status().is_server_error() =>
+        {
+            Transient(err.to_string()).into()
+        }
+        e => e.into(),
+    }
+}
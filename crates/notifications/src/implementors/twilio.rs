@@ -0,0 +1,115 @@
+use anyhow::{bail, Context, Result};
+use reqwest::{Response, StatusCode};
+use slight_runtime::deadline::block_on;
+use slight_runtime::{call::TimedOut, resource::BasicState, trace_context::Propagation};
+
+use crate::{channel::Notification, retry::Transient};
+
+/// The Twilio API messages are sent w/.
+const API: &str = "https://api.twilio.com/2010-04-01";
+
+/// This is the underlying struct behind the `Twilio` variant of the `NotificationsImplementor`
+/// enum.
+///
+/// It sends notifications as text messages w/ Twilio's Messages API, from the `from` of the
+/// channel (i.e., a phone number, or a messaging service sid).
+///
+/// The `TWILIO_ACCOUNT_SID`, and `TWILIO_AUTH_TOKEN` are read from the secret store.
+///
+/// Messages sent while the guest handles a request carry its trace context, unless the
+/// capability's `notifications.propagate_trace_context` is off.
+///
+/// As per its usage in `NotificationsImplementor`, it must `derive` `Debug`, and `Clone`.
+#[derive(Debug, Clone)]
+pub struct TwilioImplementor {
+    client: reqwest::Client,
+    account_sid: String,
+    auth_token: String,
+    from: String,
+    trace_propagation: Propagation,
+}
+
+impl TwilioImplementor {
+    pub fn new(slight_state: &BasicState, from: &str) -> Result<Self> {
+        let secret = |key: &str| -> Result<String> {
+            let value = slight_runtime_configs::resolve(
+                &slight_state.secret_stores,
+                key,
+                &slight_state.config_toml_file_path,
+            )
+            .with_context(|| {
+                format!(
+                    "failed to get '{}' secret using secret stores: {:?}",
+                    key, slight_state.secret_stores
+                )
+            })?;
+            Ok(String::from_utf8(value)?)
+        };
+        Ok(Self {
+            client: reqwest::Client::new(),
+            account_sid: secret("TWILIO_ACCOUNT_SID")?,
+            auth_token: secret("TWILIO_AUTH_TOKEN")?,
+            from: from.to_string(),
+            trace_propagation: slight_state.trace_propagation,
+        })
+    }
+
+    pub fn send(&self, notification: &Notification) -> Result<()> {
+        // messaging services pick the number messages are sent from themselves
+        let from = if self.from.starts_with("MG") {
+            "MessagingServiceSid"
+        } else {
+            "From"
+        };
+        let mut req = self
+            .client
+            .post(format!(
+                "{}/Accounts/{}/Messages.json",
+                API, self.account_sid
+            ))
+            .basic_auth(&self.account_sid, Some(&self.auth_token))
+            .form(&[
+                ("To", notification.recipient.as_str()),
+                (from, self.from.as_str()),
+                ("Body", notification.body.as_str()),
+            ]);
+        for (name, value) in self.trace_propagation.headers() {
+            req = req.header(name, value);
+        }
+        check(block_on(req.send())?.map_err(failed)?).with_context(|| {
+            format!(
+                "failed to send a text message to '{}'",
+                notification.recipient
+            )
+        })
+    }
+}
+
+/// Fails w/ the error Twilio responded w/, if it didn't take the message — transiently if it
+/// was rate limited, or failed on its side.
+fn check(res: Response) -> Result<()> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let body = block_on(res.text())
+        .map(Result::unwrap_or_default)
+        .unwrap_or_default();
+    let message = format!("Twilio responded w/ {}: {}", status, body.trim());
+    if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+        return Err(Transient(message).into());
+    }
+    bail!(message)
+}
+
+/// Fails w/ `TimedOut` if Twilio didn't respond in time (so it may have sent the message), or
+/// transiently if it couldn't be reached at all.
+fn failed(e: reqwest::Error) -> anyhow::Error {
+    if e.is_timeout() {
+        anyhow::Error::new(TimedOut(e.to_string()))
+    } else if e.is_connect() {
+        Transient(e.to_string()).into()
+    } else {
+        e.into()
+    }
+}
@@ -0,0 +1,212 @@
+mod channel;
+mod implementors;
+mod retry;
+
+/// The `SCHEME_NAME` defines the name under which a resource is
+/// identifiable by in a `ResourceMap`.
+const SCHEME_NAME: &str = "notifications";
+
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use uuid::Uuid;
+
+pub use channel::{Channel, Notification, Provider};
+use implementors::{
+    log::LogImplementor, smtp::SmtpImplementor, sns::SnsImplementor, twilio::TwilioImplementor,
+};
+use slight_runtime::{impl_resource, resource::BasicState};
+
+/// It is mandatory to `use <interface>::*` due to `impl_resource!`.
+/// That is because `impl_resource!` accesses the `crate`'s
+/// `add_to_linker`, and not the `<interface>::add_to_linker` directly.
+use notifications::*;
+wit_bindgen_wasmtime::export!("../../wit/notifications.wit");
+wit_error_rs::impl_error!(notifications::Error);
+slight_runtime::impl_from_anyhow!(notifications::Error);
+
+/// The `Notifications` structure is what will implement the `notifications::Notifications`
+/// trait coming from the generated code of off `notifications.wit`.
+///
+/// It maintains a `host_state`.
+pub struct Notifications {
+    host_state: NotificationsState,
+}
+
+impl_resource!(
+    Notifications,
+    notifications::NotificationsTables<Notifications>,
+    NotificationsState,
+    SCHEME_NAME.to_string()
+);
+
+/// This is the type of the `host_state` property from our `Notifications` structure.
+///
+/// It holds:
+///     - the `channels` of the slightfile (w/ their templates compiled, and their quotas),
+///     and
+///     - the `slight_state` (of type `BasicState`) that contains common
+///     things received from the slight binary (i.e., the `resource_map`,
+///     the `config_type`, and the `config_toml_file_path`).
+///
+/// Sends aren't idempotent (i.e., one that timed out may have been sent), so none of its
+/// operations are declared as such.
+pub struct NotificationsState {
+    channels: Vec<Channel>,
+    slight_state: BasicState,
+}
+
+impl NotificationsState {
+    pub fn new(channels: Vec<Channel>, slight_state: BasicState) -> Self {
+        Self {
+            channels,
+            slight_state,
+        }
+    }
+}
+
+impl notifications::Notifications for Notifications {
+    type Notifications = NotificationsInner;
+
+    fn notifications_open(&mut self) -> Result<Self::Notifications, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
+        let inner =
+            Self::Notifications::new(&self.host_state.channels, &self.host_state.slight_state)?;
+
+        self.host_state
+            .slight_state
+            .resource_map
+            .lock()
+            .unwrap()
+            .set(inner.resource_descriptor.clone(), Box::new(inner.clone()));
+
+        Ok(inner)
+    }
+
+    fn notifications_send(
+        &mut self,
+        self_: &Self::Notifications,
+        channel: &str,
+        recipient: &str,
+        template: &str,
+        data: Vec<(&str, &str)>,
+    ) -> Result<(), Error> {
+        let (channel, implementor) = self_.channel(channel)?;
+        // the target is the channel, as recipients are personal data
+        Ok(self
+            .host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "send", &channel.name, || {
+                channel.take_send()?;
+                let notification = channel.render(recipient, template, &data)?;
+                retry::with_retries(&channel.name, channel.max_retries, || {
+                    implementor.send(&notification)
+                })
+            })?)
+    }
+}
+
+/// This is the type of the associated type coming from the `notifications::Notifications`
+/// trait implementation.
+///
+/// It holds:
+///     - the `channels`, each w/ the implementor of its provider, and
+///     - a `resource_descriptor` (i.e., an UUID that uniquely identifies
+///     resource's instance).
+///
+/// It must `derive`:
+///     - `Debug` due to a constraint on the associated type.
+///     - `Clone` because the `ResourceMap` it will be added onto,
+///     must own its data.
+///
+/// It must be public because the implementation of `notifications::Notifications` cannot leak
+/// a private type.
+#[derive(Debug, Clone)]
+pub struct NotificationsInner {
+    channels: Arc<Vec<(Channel, NotificationsImplementor)>>,
+    resource_descriptor: String,
+}
+
+impl NotificationsInner {
+    /// Opens the implementor of each channel's provider, so a channel whose credentials are
+    /// missing fails `open`, rather than the first send through it.
+    fn new(channels: &[Channel], slight_state: &BasicState) -> Result<Self> {
+        Ok(Self {
+            channels: Arc::new(
+                channels
+                    .iter()
+                    .map(|channel| {
+                        Ok((
+                            channel.clone(),
+                            NotificationsImplementor::new(channel, slight_state)?,
+                        ))
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            resource_descriptor: Uuid::new_v4().to_string(),
+        })
+    }
+
+    fn channel(&self, name: &str) -> Result<&(Channel, NotificationsImplementor)> {
+        match self.channels.iter().find(|(channel, _)| channel.name == name) {
+            Some(channel) => Ok(channel),
+            None => bail!(
+                "failed to send a notification: the slightfile has no channel '{}' (i.e., one of {:?})",
+                name,
+                self.channels
+                    .iter()
+                    .map(|(channel, _)| channel.name.as_str())
+                    .collect::<Vec<_>>()
+            ),
+        }
+    }
+}
+
+impl slight_runtime::resource::Watch for NotificationsInner {}
+
+/// This defines the available implementor implementations for the `Notifications` interface.
+///
+/// As per its usage in `NotificationsInner`, it must `derive` `Debug`, and `Clone`.
+///
+/// The implementors that hold clients share them w/ the other guest instances of the app
+/// (see `BasicState::connection`), rather than connecting once per instance.
+#[derive(Debug, Clone)]
+enum NotificationsImplementor {
+    Smtp(Arc<SmtpImplementor>),
+    Twilio(Arc<TwilioImplementor>),
+    Sns(Arc<SnsImplementor>),
+    Log(LogImplementor),
+}
+
+impl NotificationsImplementor {
+    fn new(channel: &Channel, slight_state: &BasicState) -> Result<Self> {
+        let implementor = channel.provider.implementor();
+        // `Channel::new` makes sure the providers that need a `from` have one
+        let from = channel.from.as_deref().unwrap_or_default();
+        Ok(match channel.provider {
+            Provider::Smtp => {
+                Self::Smtp(slight_state.try_connection(implementor, &channel.name, || {
+                    SmtpImplementor::new(slight_state, from)
+                })?)
+            }
+            Provider::Twilio => {
+                Self::Twilio(slight_state.try_connection(implementor, &channel.name, || {
+                    TwilioImplementor::new(slight_state, from)
+                })?)
+            }
+            Provider::Sns => Self::Sns(slight_state.connection(implementor, &channel.name, || {
+                SnsImplementor::new(channel.from.as_deref())
+            })),
+            Provider::Log => Self::Log(LogImplementor::new(&channel.name)),
+        })
+    }
+
+    fn send(&self, notification: &Notification) -> Result<()> {
+        match self {
+            Self::Smtp(si) => si.send(notification),
+            Self::Twilio(ti) => ti.send(notification),
+            Self::Sns(si) => si.send(notification),
+            Self::Log(li) => li.send(notification),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::{fmt, thread, time::Duration};
+
+use anyhow::Result;
+
+/// The backoff before the first retry of a send, which doubles w/ each retry.
+const BASE_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The longest backoff between retries of a send, as the guest waits through them.
+const MAX_BACKOFF: Duration = Duration::from_secs(2);
+
+/// How many times a send is retried, unless its channel says otherwise.
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// `Transient` is the error implementors fail a send w/ when the provider didn't take the
+/// notification, but may if it's sent again (e.g., it was rate limited, or unavailable).
+///
+/// Sends that timed out aren't transient, as the provider may have taken the notification
+/// (i.e., it'd be sent twice), so they fail w/ `slight_runtime::call::TimedOut` instead.
+#[derive(Debug)]
+pub struct Transient(pub String);
+
+impl Transient {
+    pub fn is(error: &anyhow::Error) -> bool {
+        error.downcast_ref::<Self>().is_some()
+    }
+}
+
+impl fmt::Display for Transient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Transient {}
+
+/// Sends w/ `send`, retrying it after a backoff up to `max_retries` times while it fails
+/// transiently — a send that still fails is returned w/ how many times it was tried.
+pub fn with_retries(
+    channel: &str,
+    max_retries: u32,
+    mut send: impl FnMut() -> Result<()>,
+) -> Result<()> {
+    let mut retries = 0;
+    loop {
+        match send() {
+            Err(e) if Transient::is(&e) && retries < max_retries => {
+                retries += 1;
+                let backoff = backoff(retries);
+                tracing::warn!(
+                    "failed to send a notification through channel '{}', retrying in {:?}: {:#}",
+                    channel,
+                    backoff,
+                    e
+                );
+                thread::sleep(backoff);
+            }
+            Err(e) if Transient::is(&e) => {
+                return Err(e.context(format!(
+                    "failed to send a notification through channel '{}' after {} attempts",
+                    channel,
+                    retries + 1
+                )))
+            }
+            res => return res,
+        }
+    }
+}
+
+/// The backoff before the `retries`-th retry of a send.
+fn backoff(retries: u32) -> Duration {
+    let exponent = retries.saturating_sub(1).min(31);
+    BASE_BACKOFF.saturating_mul(1 << exponent).min(MAX_BACKOFF)
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::Duration;
+
+    use anyhow::bail;
+
+    use super::{backoff, with_retries, Transient, MAX_BACKOFF};
+
+    #[test]
+    fn with_retries_test() {
+        // a transient failure is retried until it goes through
+        let mut attempts = 0;
+        let res = with_retries("sms", 2, || {
+            attempts += 1;
+            if attempts < 3 {
+                return Err(Transient("503 Service Unavailable".to_string()).into());
+            }
+            Ok(())
+        });
+        assert!(res.is_ok());
+        assert_eq!(attempts, 3);
+
+        // or until it can't be retried anymore
+        let mut attempts = 0;
+        let res = with_retries("sms", 1, || {
+            attempts += 1;
+            Err(Transient("429 Too Many Requests".to_string()).into())
+        });
+        assert!(Transient::is(&res.unwrap_err()));
+        assert_eq!(attempts, 2);
+
+        // and a permanent one is never retried
+        let mut attempts = 0;
+        let res = with_retries("sms", 3, || {
+            attempts += 1;
+            bail!("400 Bad Request: invalid 'To' phone number")
+        });
+        assert!(!Transient::is(&res.unwrap_err()));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn backoff_test() {
+        assert_eq!(backoff(1), Duration::from_millis(100));
+        assert_eq!(backoff(3), Duration::from_millis(400));
+        assert_eq!(backoff(100), MAX_BACKOFF);
+    }
+}
@@ -0,0 +1,189 @@
+mod value;
+
+/// The `SCHEME_NAME` defines the name under which a resource is
+/// identifiable by in a `ResourceMap`.
+const SCHEME_NAME: &str = "parsing";
+/// The operations guests can retry if they time out (see `BasicState::with_idempotent_operations`).
+const IDEMPOTENT_OPERATIONS: &[&str] = &["parse-json", "parse-csv", "parse-yaml"];
+/// The operations that only read, which audits of writes only leave out (see
+/// `BasicState::with_read_operations`).
+const READ_OPERATIONS: &[&str] = &["parse-json", "parse-csv", "parse-yaml"];
+
+use anyhow::Result;
+use uuid::Uuid;
+
+use slight_runtime::{impl_resource, resource::BasicState};
+
+pub use value::MAX_NODES;
+
+/// It is mandatory to `use <interface>::*` due to `impl_resource!`.
+/// That is because `impl_resource!` accesses the `crate`'s
+/// `add_to_linker`, and not the `<interface>::add_to_linker` directly.
+use parsing::*;
+wit_bindgen_wasmtime::export!("../../wit/parsing.wit");
+wit_error_rs::impl_error!(parsing::Error);
+slight_runtime::impl_from_anyhow!(parsing::Error);
+
+/// The `Parsing` structure is what will implement the `parsing::Parsing` trait
+/// coming from the generated code of off `parsing.wit`.
+///
+/// It maintains a `host_state`.
+pub struct Parsing {
+    host_state: ParsingState,
+}
+
+impl_resource!(
+    Parsing,
+    parsing::ParsingTables<Parsing>,
+    ParsingState,
+    SCHEME_NAME.to_string()
+);
+
+/// This is the type of the `host_state` property from our `Parsing` structure.
+///
+/// It holds the `slight_state` (of type `BasicState`) that contains common
+/// things received from the slight binary (i.e., the `resource_map`,
+/// the `config_type`, and the `config_toml_file_path`).
+///
+/// Like `platform`, inputs are parsed by the host itself, so there is no
+/// implementor to choose from.
+pub struct ParsingState {
+    slight_state: BasicState,
+}
+
+impl ParsingState {
+    pub fn new(slight_state: BasicState) -> Self {
+        Self {
+            slight_state: slight_state
+                .with_idempotent_operations(IDEMPOTENT_OPERATIONS)
+                .with_read_operations(READ_OPERATIONS),
+        }
+    }
+}
+
+impl From<value::Node> for Node {
+    fn from(node: value::Node) -> Self {
+        match node {
+            value::Node::Null => Node::Null,
+            value::Node::Boolean(b) => Node::Boolean(b),
+            value::Node::Number(n) => Node::Number(n),
+            value::Node::Text(s) => Node::Text(s),
+            value::Node::Array(items) => Node::Array(items),
+            value::Node::Object(fields) => Node::Object(fields),
+        }
+    }
+}
+
+impl From<value::Parsed> for Parsed {
+    fn from(parsed: value::Parsed) -> Self {
+        match parsed {
+            Ok(nodes) => Parsed::Valid(nodes.into_iter().map(Node::from).collect()),
+            Err(e) => Parsed::Invalid(SyntaxError {
+                line: e.line,
+                column: e.column,
+                reason: e.reason,
+            }),
+        }
+    }
+}
+
+impl Parsing {
+    /// Parses `input` w/ `parse`, charging its bytes to the capability's quota — inputs that
+    /// aren't valid are parsed successfully, as `Parsed::Invalid`.
+    fn parse(
+        &self,
+        operation: &str,
+        input: &[u8],
+        parse: impl FnOnce(&[u8]) -> Result<value::Parsed>,
+    ) -> Result<Parsed, Error> {
+        let slight_state = &self.host_state.slight_state;
+        slight_state.instrument(SCHEME_NAME, operation, "", || {
+            slight_state.take_bytes(input.len())?;
+            let parsed = parse(input)?;
+            if let Err(e) = &parsed {
+                tracing::debug!(
+                    "{} failed at line {}, column {}: {}",
+                    operation,
+                    e.line,
+                    e.column,
+                    e.reason
+                );
+            }
+            Ok(parsed.into())
+        })
+    }
+}
+
+impl parsing::Parsing for Parsing {
+    type Parsing = ParsingInner;
+
+    fn parsing_open(&mut self) -> Result<Self::Parsing, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
+        let inner = Self::Parsing::new();
+
+        self.host_state
+            .slight_state
+            .resource_map
+            .lock()
+            .unwrap()
+            .set(inner.resource_descriptor.clone(), Box::new(inner.clone()));
+
+        Ok(inner)
+    }
+
+    fn parsing_parse_json(
+        &mut self,
+        _self_: &Self::Parsing,
+        input: PayloadParam<'_>,
+    ) -> Result<Parsed, Error> {
+        self.parse("parse-json", input, |input| Ok(value::json(input)))
+    }
+
+    fn parsing_parse_csv(
+        &mut self,
+        _self_: &Self::Parsing,
+        input: PayloadParam<'_>,
+        delimiter: char,
+        headers: bool,
+    ) -> Result<Parsed, Error> {
+        self.parse("parse-csv", input, |input| {
+            value::csv(input, delimiter, headers)
+        })
+    }
+
+    fn parsing_parse_yaml(
+        &mut self,
+        _self_: &Self::Parsing,
+        input: PayloadParam<'_>,
+    ) -> Result<Parsed, Error> {
+        self.parse("parse-yaml", input, |input| Ok(value::yaml(input)))
+    }
+}
+
+/// This is the type of the associated type coming from the `parsing::Parsing` trait
+/// implementation.
+///
+/// It holds a `resource_descriptor` (i.e., an UUID that uniquely identifies
+/// resource's instance).
+///
+/// It must `derive`:
+///     - `Debug` due to a constraint on the associated type.
+///     - `Clone` because the `ResourceMap` it will be added onto,
+///     must own its data.
+///
+/// It must be public because the implementation of `parsing::Parsing` cannot leak
+/// a private type.
+#[derive(Debug, Clone)]
+pub struct ParsingInner {
+    resource_descriptor: String,
+}
+
+impl ParsingInner {
+    fn new() -> Self {
+        Self {
+            resource_descriptor: Uuid::new_v4().to_string(),
+        }
+    }
+}
+
+impl slight_runtime::resource::Watch for ParsingInner {}
@@ -0,0 +1,360 @@
+use std::fmt;
+
+use anyhow::{bail, Result};
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+
+/// How many nodes a parsed value has at most, so a small input can't make the host build a
+/// huge value (e.g., YAML aliases that refer to each other).
+pub const MAX_NODES: usize = 1_000_000;
+
+/// A node of a parsed value, which refers to its children by their index in the value (see
+/// `parsing.wit`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Node {
+    Null,
+    Boolean(bool),
+    Number(String),
+    Text(String),
+    Array(Vec<u32>),
+    Object(Vec<(String, u32)>),
+}
+
+/// Where, and why the input couldn't be parsed — the `line`, and `column` start at 1, and are
+/// 0 if they aren't known.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub line: u32,
+    pub column: u32,
+    pub reason: String,
+}
+
+/// A parsed value (i.e., its nodes, whose first is the root), or why the input isn't one.
+pub type Parsed = std::result::Result<Vec<Node>, SyntaxError>;
+
+pub fn json(input: &[u8]) -> Parsed {
+    let mut nodes = Vec::new();
+    let mut deserializer = serde_json::Deserializer::from_slice(input);
+    NodeSeed(&mut nodes)
+        .deserialize(&mut deserializer)
+        .and_then(|_| deserializer.end())
+        .map_err(|e| syntax_error(e.line(), e.column(), &e))?;
+    Ok(nodes)
+}
+
+/// Parses a YAML document (i.e., only one), converting the keys that aren't text to text.
+pub fn yaml(input: &[u8]) -> Parsed {
+    let mut nodes = Vec::new();
+    NodeSeed(&mut nodes)
+        .deserialize(serde_yaml::Deserializer::from_slice(input))
+        .map_err(|e| {
+            let (line, column) = e
+                .location()
+                .map_or((0, 0), |location| (location.line(), location.column()));
+            syntax_error(line, column, &e)
+        })?;
+    Ok(nodes)
+}
+
+/// Parses CSV whose fields are separated by `delimiter` (which must be ASCII) into an array of
+/// records: objects named by the first record, if it holds the `headers`, or else arrays.
+pub fn csv(input: &[u8], delimiter: char, headers: bool) -> Result<Parsed> {
+    let delimiter = match u8::try_from(delimiter) {
+        Ok(delimiter) if delimiter.is_ascii() => delimiter,
+        _ => bail!("invalid delimiter '{}': it must be ASCII", delimiter),
+    };
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(headers)
+        .from_reader(input);
+    Ok(records(&mut reader, headers))
+}
+
+fn records(reader: &mut csv::Reader<&[u8]>, headers: bool) -> Parsed {
+    let names = if headers {
+        let names = reader.headers().map_err(csv_error)?;
+        Some(names.iter().map(str::to_string).collect::<Vec<_>>())
+    } else {
+        None
+    };
+    let mut nodes = vec![Node::Null];
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(csv_error)?;
+        if nodes.len() + record.len() >= MAX_NODES {
+            return Err(too_many_nodes());
+        }
+        let row = nodes.len();
+        nodes.push(Node::Null);
+        let fields = record
+            .iter()
+            .map(|field| {
+                nodes.push(Node::Text(field.to_string()));
+                (nodes.len() - 1) as u32
+            })
+            .collect::<Vec<_>>();
+        nodes[row] = match &names {
+            Some(names) => Node::Object(names.iter().cloned().zip(fields).collect()),
+            None => Node::Array(fields),
+        };
+        rows.push(row as u32);
+    }
+    nodes[0] = Node::Array(rows);
+    Ok(nodes)
+}
+
+fn csv_error(e: csv::Error) -> SyntaxError {
+    let line = e.position().map_or(0, |position| position.line());
+    SyntaxError {
+        line: line as u32,
+        column: 0,
+        reason: e.to_string(),
+    }
+}
+
+fn syntax_error(line: usize, column: usize, e: &dyn fmt::Display) -> SyntaxError {
+    let reason = e.to_string();
+    // the position is a field of its own
+    let reason = match reason.find(" at line ") {
+        Some(at) => reason[..at].to_string(),
+        None => reason,
+    };
+    SyntaxError {
+        line: line as u32,
+        column: column as u32,
+        reason,
+    }
+}
+
+fn too_many_nodes() -> SyntaxError {
+    SyntaxError {
+        line: 0,
+        column: 0,
+        reason: format!("the value has more than {} nodes", MAX_NODES),
+    }
+}
+
+/// Deserializes a value of any format into `Node`s, returning the index of its root.
+struct NodeSeed<'a>(&'a mut Vec<Node>);
+
+impl NodeSeed<'_> {
+    /// Reserves the index of a node, whose children are pushed after it.
+    fn reserve<E: de::Error>(&mut self) -> std::result::Result<usize, E> {
+        if self.0.len() >= MAX_NODES {
+            return Err(E::custom(too_many_nodes().reason));
+        }
+        self.0.push(Node::Null);
+        Ok(self.0.len() - 1)
+    }
+
+    fn push<E: de::Error>(mut self, node: Node) -> std::result::Result<u32, E> {
+        let index = self.reserve()?;
+        self.0[index] = node;
+        Ok(index as u32)
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for NodeSeed<'_> {
+    type Value = u32;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<u32, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for NodeSeed<'_> {
+    type Value = u32;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a value")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> std::result::Result<u32, E> {
+        self.push(Node::Null)
+    }
+
+    fn visit_none<E: de::Error>(self) -> std::result::Result<u32, E> {
+        self.push(Node::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<u32, D::Error> {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> std::result::Result<u32, E> {
+        self.push(Node::Boolean(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> std::result::Result<u32, E> {
+        self.push(Node::Number(v.to_string()))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> std::result::Result<u32, E> {
+        self.push(Node::Number(v.to_string()))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> std::result::Result<u32, E> {
+        self.push(Node::Number(v.to_string()))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<u32, E> {
+        self.push(Node::Text(v.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> std::result::Result<u32, E> {
+        self.push(Node::Text(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> std::result::Result<u32, A::Error> {
+        let index = self.reserve()?;
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element_seed(NodeSeed(&mut *self.0))? {
+            items.push(item);
+        }
+        self.0[index] = Node::Array(items);
+        Ok(index as u32)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> std::result::Result<u32, A::Error> {
+        let index = self.reserve()?;
+        let mut fields = Vec::new();
+        while let Some(key) = map.next_key_seed(KeySeed)? {
+            fields.push((key, map.next_value_seed(NodeSeed(&mut *self.0))?));
+        }
+        self.0[index] = Node::Object(fields);
+        Ok(index as u32)
+    }
+}
+
+/// Deserializes a key of an object as text, whatever the scalar it is (e.g., a YAML number).
+struct KeySeed;
+
+impl<'de> DeserializeSeed<'de> for KeySeed {
+    type Value = String;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<String, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for KeySeed {
+    type Value = String;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a key")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> std::result::Result<String, E> {
+        Ok("null".to_string())
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> std::result::Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> std::result::Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> std::result::Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> std::result::Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> std::result::Result<String, E> {
+        Ok(v)
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+
+    use super::{csv, json, yaml, Node};
+
+    fn text(s: &str) -> Node {
+        Node::Text(s.to_string())
+    }
+
+    #[test]
+    fn same_value_across_formats_test() {
+        let expected = vec![
+            Node::Object(vec![("name".to_string(), 1), ("tags".to_string(), 2)]),
+            text("slight"),
+            Node::Array(vec![3, 4]),
+            Node::Number("1".to_string()),
+            Node::Null,
+        ];
+        assert_eq!(
+            json(br#"{"name": "slight", "tags": [1, null]}"#),
+            Ok(expected.clone())
+        );
+        assert_eq!(yaml(b"name: slight\ntags:\n  - 1\n  - ~\n"), Ok(expected));
+
+        // YAML keys that aren't text are converted to it
+        assert_eq!(
+            yaml(b"200: ok\n"),
+            Ok(vec![Node::Object(vec![("200".to_string(), 1)]), text("ok")])
+        );
+        // integers too large for a float aren't rounded
+        assert_eq!(
+            json(b"18446744073709551615"),
+            Ok(vec![Node::Number("18446744073709551615".to_string())])
+        );
+    }
+
+    #[test]
+    fn csv_test() -> Result<()> {
+        let input = b"id,name\n1,kv\n2,mq\n";
+        assert_eq!(
+            csv(input, ',', true)?,
+            Ok(vec![
+                Node::Array(vec![1, 4]),
+                Node::Object(vec![("id".to_string(), 2), ("name".to_string(), 3)]),
+                text("1"),
+                text("kv"),
+                Node::Object(vec![("id".to_string(), 5), ("name".to_string(), 6)]),
+                text("2"),
+                text("mq"),
+            ])
+        );
+        assert_eq!(
+            csv(b"a;b\n", ';', false)?,
+            Ok(vec![
+                Node::Array(vec![1]),
+                Node::Array(vec![2, 3]),
+                text("a"),
+                text("b")
+            ])
+        );
+        assert_eq!(csv(b"a,b\n1\n", ',', true)?.unwrap_err().line, 2);
+        assert!(csv(input, 'é', true).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn syntax_error_test() {
+        let e = json(b"{\n  \"a\": trux\n}").unwrap_err();
+        assert_eq!((e.line, e.reason.as_str()), (2, "expected ident"));
+        assert!(e.column > 0);
+        assert_eq!(json(b"[1] [2]").unwrap_err().reason, "trailing characters");
+        let e = yaml(b"a: [1, 2\n").unwrap_err();
+        assert!(e.line > 0);
+        assert!(!e.reason.contains(" at line "));
+    }
+}
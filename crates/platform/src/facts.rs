@@ -0,0 +1,104 @@
+use anyhow::{bail, Result};
+
+/// The names of all host facts a guest is allowed to obtain.
+///
+/// This is an allow-list: anything not in here is refused, so that adding a new
+/// fact is always a deliberate decision, and we don't end up leaking sensitive
+/// details of the host (e.g., hostnames, env. vars, or users).
+pub const ALLOWED_FACTS: [&str; 5] = ["arch", "os", "family", "cpu-count", "cloud"];
+
+/// Environment variables set by each cloud's runtime (i.e., the same ones their
+/// SDKs look at to resolve credentials from the environment), in order of detection.
+const CLOUD_MARKERS: [(&str, &[&str]); 3] = [
+    (
+        "aws",
+        &[
+            "AWS_EXECUTION_ENV",
+            "AWS_LAMBDA_FUNCTION_NAME",
+            "ECS_CONTAINER_METADATA_URI_V4",
+        ],
+    ),
+    (
+        "azure",
+        &[
+            "IDENTITY_ENDPOINT",
+            "MSI_ENDPOINT",
+            "WEBSITE_INSTANCE_ID",
+            "CONTAINER_APP_NAME",
+        ],
+    ),
+    (
+        "gcp",
+        &["K_SERVICE", "GCE_METADATA_HOST", "FUNCTION_TARGET"],
+    ),
+];
+
+/// Gets the value of an allow-listed host fact.
+pub fn get(fact: &str) -> Result<String> {
+    Ok(match fact {
+        "arch" => std::env::consts::ARCH.to_string(),
+        "os" => std::env::consts::OS.to_string(),
+        "family" => std::env::consts::FAMILY.to_string(),
+        "cpu-count" => std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .to_string(),
+        "cloud" => detect_cloud(|var| std::env::var(var).ok()).to_string(),
+        f => bail!(
+            "unknown platform fact '{}' (available facts are: {})",
+            f,
+            ALLOWED_FACTS.join(", ")
+        ),
+    })
+}
+
+/// Detects the cloud we are running under, returning "none" if we can't tell.
+///
+/// The `lookup` is what we use to read an environment variable.
+fn detect_cloud(lookup: impl Fn(&str) -> Option<String>) -> &'static str {
+    CLOUD_MARKERS
+        .iter()
+        .find(|(_, vars)| vars.iter().any(|v| lookup(v).is_some()))
+        .map(|(cloud, _)| *cloud)
+        .unwrap_or("none")
+}
+
+#[cfg(test)]
+mod unittests {
+    use super::{detect_cloud, get, ALLOWED_FACTS};
+
+    #[test]
+    fn all_allowed_facts_resolve() {
+        for fact in ALLOWED_FACTS {
+            assert!(get(fact).is_ok(), "fact '{}' failed to resolve", fact);
+        }
+    }
+
+    #[test]
+    fn unknown_facts_are_refused() {
+        assert!(get("hostname").is_err());
+        assert!(get("PATH").is_err());
+    }
+
+    #[test]
+    fn cpu_count_is_a_positive_number() {
+        assert!(get("cpu-count").unwrap().parse::<usize>().unwrap() > 0);
+    }
+
+    #[test]
+    fn detects_cloud_from_env() {
+        assert_eq!(detect_cloud(|_| None), "none");
+        assert_eq!(
+            detect_cloud(|v| (v == "AWS_EXECUTION_ENV").then(|| "AWS_Lambda_rust".to_string())),
+            "aws"
+        );
+        assert_eq!(
+            detect_cloud(|v| (v == "IDENTITY_ENDPOINT").then(|| "http://localhost".to_string())),
+            "azure"
+        );
+        assert_eq!(
+            detect_cloud(|v| (v == "K_SERVICE").then(|| "my-service".to_string())),
+            "gcp"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+mod facts;
+
+/// The `SCHEME_NAME` defines the name under which a resource is
+/// identifiable by in a `ResourceMap`.
+const SCHEME_NAME: &str = "platform";
+/// The operations guests can retry if they time out (see `BasicState::with_idempotent_operations`).
+const IDEMPOTENT_OPERATIONS: &[&str] = &["get"];
+/// The operations that only read, which audits of writes only leave out (see
+/// `BasicState::with_read_operations`).
+const READ_OPERATIONS: &[&str] = &["get", "list-facts"];
+
+use anyhow::Result;
+use uuid::Uuid;
+
+use slight_runtime::{impl_resource, resource::BasicState};
+
+/// It is mandatory to `use <interface>::*` due to `impl_resource!`.
+/// That is because `impl_resource!` accesses the `crate`'s
+/// `add_to_linker`, and not the `<interface>::add_to_linker` directly.
+use platform::*;
+wit_bindgen_wasmtime::export!("../../wit/platform.wit");
+wit_error_rs::impl_error!(platform::Error);
+slight_runtime::impl_from_anyhow!(platform::Error);
+
+/// The `Platform` structure is what will implement the `platform::Platform` trait
+/// coming from the generated code of off `platform.wit`.
+///
+/// It maintains a `host_state`.
+pub struct Platform {
+    host_state: PlatformState,
+}
+
+impl_resource!(
+    Platform,
+    platform::PlatformTables<Platform>,
+    PlatformState,
+    SCHEME_NAME.to_string()
+);
+
+/// This is the type of the `host_state` property from our `Platform` structure.
+///
+/// It holds the `slight_state` (of type `BasicState`) that contains common
+/// things received from the slight binary (i.e., the `resource_map`,
+/// the `config_type`, and the `config_toml_file_path`).
+///
+/// Unlike other capabilities, there is only one way to get facts about
+/// the host, so there is no implementor to choose from.
+pub struct PlatformState {
+    slight_state: BasicState,
+}
+
+impl PlatformState {
+    pub fn new(slight_state: BasicState) -> Self {
+        Self {
+            slight_state: slight_state
+                .with_idempotent_operations(IDEMPOTENT_OPERATIONS)
+                .with_read_operations(READ_OPERATIONS),
+        }
+    }
+}
+
+impl platform::Platform for Platform {
+    type Platform = PlatformInner;
+
+    fn platform_open(&mut self) -> Result<Self::Platform, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
+        let inner = Self::Platform::new();
+
+        self.host_state
+            .slight_state
+            .resource_map
+            .lock()
+            .unwrap()
+            .set(inner.resource_descriptor.clone(), Box::new(inner.clone()));
+
+        Ok(inner)
+    }
+
+    fn platform_get(&mut self, _self_: &Self::Platform, fact: &str) -> Result<String, Error> {
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "get", fact, || Ok(facts::get(fact)?))
+    }
+
+    fn platform_list_facts(&mut self, _self_: &Self::Platform) -> Result<Vec<String>, Error> {
+        self.host_state
+            .slight_state
+            .permit(SCHEME_NAME, "list-facts")?;
+        Ok(facts::ALLOWED_FACTS.iter().map(|f| f.to_string()).collect())
+    }
+}
+
+/// This is the type of the associated type coming from the `platform::Platform` trait
+/// implementation.
+///
+/// It holds a `resource_descriptor` (i.e., an UUID that uniquely identifies
+/// resource's instance).
+///
+/// It must `derive`:
+///     - `Debug` due to a constraint on the associated type.
+///     - `Clone` because the `ResourceMap` it will be added onto,
+///     must own its data.
+///
+/// It must be public because the implementation of `platform::Platform` cannot leak
+/// a private type.
+#[derive(Debug, Clone)]
+pub struct PlatformInner {
+    resource_descriptor: String,
+}
+
+impl PlatformInner {
+    fn new() -> Self {
+        Self {
+            resource_descriptor: Uuid::new_v4().to_string(),
+        }
+    }
+}
+
+impl slight_runtime::resource::Watch for PlatformInner {}
@@ -0,0 +1,152 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use slight_kv::HostKv;
+use slight_runtime::resource::BasicState;
+
+use crate::providers::confluent::KafkaMessage;
+
+/// The name of the kv store the ids of seen messages are kept in.
+const DEDUP_STORE_NAME: &str = "slight-pubsub-dedup";
+
+/// `DedupSettings` configures the (optional) deduplication of messages on the consumer side.
+#[derive(Debug, Clone)]
+pub struct DedupSettings {
+    /// the kv implementor (e.g., `kv.filesystem`) the ids of seen messages are kept in
+    pub store: String,
+    /// for how long a message is considered a duplicate of one seen before
+    pub window: Duration,
+    /// the header holding a message's id, or `None` to use a hash of its key, and value
+    pub id_header: Option<String>,
+}
+
+/// `Dedup` keeps the ids of the messages seen in the last `window` in a kv store,
+/// so that a message delivered more than once (e.g., after a rebalance), isn't
+/// handed to the guest more than once.
+#[derive(Debug, Clone)]
+pub struct Dedup {
+    seen: HostKv,
+    window: Duration,
+    id_header: Option<String>,
+}
+
+impl Dedup {
+    pub fn new(settings: &DedupSettings, slight_state: &BasicState) -> Result<Self> {
+        Ok(Self {
+            seen: HostKv::open(&settings.store, slight_state, DEDUP_STORE_NAME)?,
+            window: settings.window,
+            id_header: settings.id_header.clone(),
+        })
+    }
+
+    /// Checks whether a message was seen in the last `window`, and, if it wasn't,
+    /// records it as seen.
+    pub fn is_duplicate(&self, message: &KafkaMessage) -> Result<bool> {
+        let id = message_id(self.id_header.as_deref(), message);
+        if self
+            .seen
+            .get(&id)
+            .with_context(|| "failed to look up message id")?
+            .is_some()
+        {
+            return Ok(true);
+        }
+        self.seen
+            .set_with_time_to_live(&id, &[], self.window.as_secs().max(1))
+            .with_context(|| "failed to record message id")?;
+        Ok(false)
+    }
+}
+
+/// Gets the id of a message from the `id_header` if it has one, or else, from the hash
+/// of its key, and value.
+fn message_id(id_header: Option<&str>, message: &KafkaMessage) -> Vec<u8> {
+    let KafkaMessage(key, value, headers) = message;
+    if let Some(id_header) = id_header {
+        if let Some((_, id)) = headers.iter().find(|(name, _)| name == id_header) {
+            return [b"header:".as_slice(), id.as_slice()].concat();
+        }
+        tracing::debug!(
+            "message has no '{}' header, deduplicating it by its hash",
+            id_header
+        );
+    }
+
+    let mut hasher = Sha256::new();
+    for part in [key, value] {
+        // prefixing w/ the length, so that, say, a key of "ab", and a value of "c"
+        // don't hash the same as a key of "a", and a value of "bc".
+        let part = part.as_deref().unwrap_or_default();
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    [b"hash:".as_slice(), hasher.finalize().as_slice()].concat()
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use slight_runtime::resource::BasicState;
+    use uuid::Uuid;
+
+    use super::{message_id, Dedup, DedupSettings};
+    use crate::providers::confluent::KafkaMessage;
+
+    fn message(key: &str, value: &str, headers: &[(&str, &str)]) -> KafkaMessage {
+        KafkaMessage(
+            Some(key.as_bytes().to_vec()),
+            Some(value.as_bytes().to_vec()),
+            headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.as_bytes().to_vec()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn message_id_from_header_test() {
+        let a = message("k", "v1", &[("message-id", "1")]);
+        let b = message("k", "v2", &[("message-id", "1")]);
+        assert_eq!(
+            message_id(Some("message-id"), &a),
+            message_id(Some("message-id"), &b)
+        );
+        assert_ne!(message_id(None, &a), message_id(None, &b));
+    }
+
+    #[test]
+    fn message_id_from_hash_test() {
+        assert_eq!(
+            message_id(None, &message("k", "v", &[])),
+            message_id(None, &message("k", "v", &[("other", "x")]))
+        );
+        assert_ne!(
+            message_id(None, &message("ab", "c", &[])),
+            message_id(None, &message("a", "bc", &[]))
+        );
+        // messages w/o the header fall back to their hash
+        assert_eq!(
+            message_id(Some("message-id"), &message("k", "v", &[])),
+            message_id(None, &message("k", "v", &[]))
+        );
+    }
+
+    #[test]
+    fn is_duplicate_test() -> Result<()> {
+        let dedup = Dedup::new(
+            &DedupSettings {
+                store: "kv.filesystem".to_string(),
+                window: Duration::from_secs(60),
+                id_header: Some("message-id".to_string()),
+            },
+            &BasicState::default(),
+        )?;
+        let id = Uuid::new_v4().to_string();
+        assert!(!dedup.is_duplicate(&message("k", "v", &[("message-id", &id)]))?);
+        assert!(dedup.is_duplicate(&message("k", "v", &[("message-id", &id)]))?);
+        Ok(())
+    }
+}
@@ -1,21 +1,32 @@
-use std::sync::Arc;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use anyhow::{Context, Result};
 use rdkafka::{consumer::BaseConsumer, producer::BaseProducer, ClientConfig};
-use slight_runtime::resource::BasicState;
+use slight_runtime::{
+    call, credentials::CredentialsError, describe::Description, resource::BasicState,
+};
 
 use crate::providers::confluent::{self, KafkaMessage};
 
+/// The name of the credential (i.e., secret) the SASL password is read from.
+const SASL_PASSWORD: &str = "CK_SASL_PASSWORD";
+
 /// This is one of the underlying structs behind the `ConfluentApacheKafka` variant of the `PubImplementor` enum.
 ///
 /// It provides a property that pertains solely to Confluent's Apache Kafka's implementation
 /// of this capability:
-///     - `producer`
+///     - `producer`, which is recreated w/ a refetched SASL password if Kafka says the
+///     current one expired, and
+///     - the `slight_state` it's created from.
 ///
-/// As per its' usage in `PubImplementor`, it must `derive` `Debug`, and `Clone`.
+/// As per its usage in `PubImplementor`, it must `derive` `Debug`, and `Clone`.
 #[derive(Clone)]
 pub struct PubConfluentApacheKafkaImplementor {
-    producer: Arc<BaseProducer>,
+    producer: Arc<Mutex<BaseProducer>>,
+    slight_state: BasicState,
 }
 
 impl std::fmt::Debug for PubConfluentApacheKafkaImplementor {
@@ -25,21 +36,13 @@ impl std::fmt::Debug for PubConfluentApacheKafkaImplementor {
 }
 
 impl PubConfluentApacheKafkaImplementor {
-    pub fn new(slight_state: &BasicState) -> Self {
-        let akc = ApacheKafkaConfigs::from_state(slight_state).unwrap();
-        let producer: BaseProducer = ClientConfig::new()
-            .set("bootstrap.servers", akc.bootstap_servers)
-            .set("security.protocol", akc.security_protocol)
-            .set("sasl.mechanisms", akc.sasl_mechanisms)
-            .set("sasl.username", akc.sasl_username)
-            .set("sasl.password", akc.sasl_password)
-            .create()
-            .with_context(|| "failed to create producer client")
-            .unwrap(); // panic if we fail to create client
-
-        Self {
-            producer: Arc::new(producer),
-        }
+    pub fn new(slight_state: &BasicState) -> Result<Self> {
+        let producer = create_producer(slight_state)?;
+
+        Ok(Self {
+            producer: Arc::new(Mutex::new(producer)),
+            slight_state: slight_state.clone(),
+        })
     }
 
     pub fn send_message_to_topic(
@@ -47,22 +50,59 @@ impl PubConfluentApacheKafkaImplementor {
         msg_key: &[u8],
         msg_value: &[u8],
         topic: &str,
+        headers: &[(&str, &[u8])],
     ) -> Result<()> {
-        confluent::send(&self.producer, msg_key, msg_value, topic)
-            .with_context(|| "failed to send message to a topic")
+        let mut producer = self.producer.lock().unwrap();
+        let send = |producer: &BaseProducer| {
+            confluent::send(producer, msg_key, msg_value, topic, headers)
+                .with_context(|| "failed to send message to a topic")
+        };
+        let res = send(&producer);
+        let reconnected = expired(&res, &self.slight_state)
+            && match create_producer(&self.slight_state) {
+                Ok(recreated) => {
+                    *producer = recreated;
+                    self.slight_state.health.reconnected(crate::SCHEME_NAME);
+                    true
+                }
+                Err(e) => {
+                    tracing::warn!("failed to recreate producer client: {:#}", e);
+                    false
+                }
+            };
+        call::replayed(crate::SCHEME_NAME, res, reconnected, || send(&producer))
     }
 }
 
+fn create_producer(slight_state: &BasicState) -> Result<BaseProducer> {
+    let akc = ApacheKafkaConfigs::from_state(slight_state)?;
+    ClientConfig::new()
+        .set("bootstrap.servers", akc.bootstap_servers)
+        .set("security.protocol", akc.security_protocol)
+        .set("sasl.mechanisms", akc.sasl_mechanisms)
+        .set("sasl.username", akc.sasl_username)
+        .set("sasl.password", akc.sasl_password)
+        .create()
+        .with_context(|| "failed to create producer client")
+}
+
 /// This is one of the underlying structs behind the `ConfluentApacheKafka` variant of the `SubImplementor` enum.
 ///
 /// It provides a property that pertains solely to Confluent's Apache Kafka's implementation
 /// of this capability:
-///     - `consumer`
+///     - `consumer`, which is recreated w/ a refetched SASL password (and resubscribed to
+///     the `topics`) if Kafka says the current one expired,
+///     - `topics`,
+///     - the `group_id` it consumes in, if not the app's (i.e., `CK_GROUP_ID`), and
+///     - the `slight_state` it's created from.
 ///
-/// As per its' usage in `SubImplementor`, it must `derive` `std::fmt::Debug`, and `Clone`.
+/// As per its usage in `SubImplementor`, it must `derive` `std::fmt::Debug`, and `Clone`.
 #[derive(Clone)]
 pub struct SubConfluentApacheKafkaImplementor {
-    consumer: Arc<BaseConsumer>,
+    consumer: Arc<Mutex<BaseConsumer>>,
+    topics: Arc<Mutex<Vec<String>>>,
+    group_id: Option<String>,
+    slight_state: BasicState,
 }
 
 impl std::fmt::Debug for SubConfluentApacheKafkaImplementor {
@@ -72,50 +112,136 @@ impl std::fmt::Debug for SubConfluentApacheKafkaImplementor {
 }
 
 impl SubConfluentApacheKafkaImplementor {
-    pub fn new(slight_state: &BasicState) -> Self {
-        let akc = ApacheKafkaConfigs::from_state(slight_state).unwrap();
-        let group_id = String::from_utf8(
-            slight_runtime_configs::get(
-                &slight_state.secret_store,
-                "CK_GROUP_ID",
-                &slight_state.config_toml_file_path,
-            )
-            .with_context(|| {
-                format!(
-                    "failed to get 'CK_GROUP_ID' secret using secret store type: {}",
-                    slight_state.secret_store
-                )
-            })
-            .unwrap(),
-        )
-        .unwrap();
-
-        let consumer: BaseConsumer = ClientConfig::new()
-            .set("bootstrap.servers", akc.bootstap_servers)
-            .set("security.protocol", akc.security_protocol)
-            .set("sasl.mechanisms", akc.sasl_mechanisms)
-            .set("sasl.username", akc.sasl_username)
-            .set("sasl.password", akc.sasl_password)
-            .set("group.id", group_id)
-            .create()
-            .with_context(|| "failed to create consumer client")
-            .unwrap(); // panic if we fail to create client
+    pub fn new(slight_state: &BasicState) -> Result<Self> {
+        let consumer = create_consumer(slight_state, None)?;
 
-        Self {
-            consumer: Arc::new(consumer),
-        }
+        Ok(Self {
+            consumer: Arc::new(Mutex::new(consumer)),
+            topics: Arc::new(Mutex::new(Vec::new())),
+            group_id: None,
+            slight_state: slight_state.clone(),
+        })
+    }
+
+    /// Creates a consumer in a consumer group of its own, rather than the app's, so it gets
+    /// every message sent from now on w/o taking any from the app's consumers, or moving
+    /// their offsets.
+    pub fn new_in_group(slight_state: &BasicState, group_id: &str) -> Result<Self> {
+        let consumer = create_consumer(slight_state, Some(group_id))?;
+
+        Ok(Self {
+            consumer: Arc::new(Mutex::new(consumer)),
+            topics: Arc::new(Mutex::new(Vec::new())),
+            group_id: Some(group_id.to_string()),
+            slight_state: slight_state.clone(),
+        })
     }
 
     pub fn subscribe_to_topic(&self, topic: Vec<&str>) -> Result<()> {
-        confluent::subscribe(&self.consumer, topic).with_context(|| "failed to subscribe to topic")
+        let mut consumer = self.consumer.lock().unwrap();
+        let subscribe = |consumer: &BaseConsumer| {
+            let res = confluent::subscribe(consumer, topic.clone())
+                .with_context(|| "failed to subscribe to topic");
+            if res.is_ok() {
+                *self.topics.lock().unwrap() = topic.iter().map(|t| t.to_string()).collect();
+            }
+            res
+        };
+        let res = subscribe(&consumer);
+        let reconnected = self.recreate_if_expired(&mut consumer, &res);
+        call::replayed(crate::SCHEME_NAME, res, reconnected, || {
+            subscribe(&consumer)
+        })
     }
 
-    pub fn poll_for_message(&self, timeout_in_secs: u64) -> Result<KafkaMessage> {
-        confluent::poll(&self.consumer, timeout_in_secs)
-            .with_context(|| "failed to poll for message")
+    pub fn poll_for_message(&self, timeout: Duration) -> Result<KafkaMessage> {
+        let mut consumer = self.consumer.lock().unwrap();
+        let poll = |consumer: &BaseConsumer| {
+            confluent::poll(consumer, timeout).with_context(|| "failed to poll for message")
+        };
+        let res = poll(&consumer);
+        let reconnected = self.recreate_if_expired(&mut consumer, &res);
+        call::replayed(crate::SCHEME_NAME, res, reconnected, || poll(&consumer))
+    }
+
+    /// Recreates the consumer if Kafka said the SASL password expired, returning whether it
+    /// did (i.e., whether the call can be replayed on the new one, see `call::replayed`).
+    fn recreate_if_expired<T>(&self, consumer: &mut BaseConsumer, res: &Result<T>) -> bool {
+        if !expired(res, &self.slight_state) {
+            return false;
+        }
+        let topics = self.topics.lock().unwrap().clone();
+        let recreated =
+            create_consumer(&self.slight_state, self.group_id.as_deref()).and_then(|recreated| {
+                if !topics.is_empty() {
+                    confluent::subscribe(&recreated, topics.iter().map(String::as_str).collect())?;
+                }
+                Ok(recreated)
+            });
+        match recreated {
+            Ok(recreated) => {
+                *consumer = recreated;
+                self.slight_state.health.reconnected(crate::SCHEME_NAME);
+                true
+            }
+            Err(e) => {
+                tracing::warn!("failed to recreate consumer client: {:#}", e);
+                false
+            }
+        }
     }
 }
 
+/// Creates a consumer in `group_id`, or in the app's consumer group (i.e., `CK_GROUP_ID`)
+/// if it's `None` — consumers in a group of their own don't commit their offsets, as no
+/// one picks up where they left.
+fn create_consumer(slight_state: &BasicState, group_id: Option<&str>) -> Result<BaseConsumer> {
+    let akc = ApacheKafkaConfigs::from_state(slight_state)?;
+    let mut config = ClientConfig::new();
+    match group_id {
+        Some(group_id) => config
+            .set("group.id", group_id)
+            .set("enable.auto.commit", "false"),
+        None => config.set("group.id", get_config("CK_GROUP_ID", slight_state)?),
+    };
+    config
+        .set("bootstrap.servers", akc.bootstap_servers)
+        .set("security.protocol", akc.security_protocol)
+        .set("sasl.mechanisms", akc.sasl_mechanisms)
+        .set("sasl.username", akc.sasl_username)
+        .set("sasl.password", akc.sasl_password)
+        .create()
+        .with_context(|| "failed to create consumer client")
+}
+
+/// Whether Kafka said the SASL password expired — if so, it is invalidated, so recreating
+/// the client refetches it.
+fn expired<T>(res: &Result<T>, slight_state: &BasicState) -> bool {
+    match res {
+        Err(e) => match CredentialsError::of(e) {
+            Some(CredentialsError::Expired(_)) => {
+                slight_state.credentials.invalidate(SASL_PASSWORD);
+                true
+            }
+            _ => false,
+        },
+        Ok(_) => false,
+    }
+}
+
+/// Describes the Kafka cluster topics are in (see `slight_runtime::describe`), w/ the
+/// settings its clients are created w/.
+pub fn describe(slight_state: &BasicState) -> Description {
+    let setting = |key: &str| get_config(key, slight_state);
+    Description::new("pubsub.confluent_apache_kafka", "Apache Kafka")
+        .with_endpoint("CK_ENDPOINT", setting("CK_ENDPOINT"))
+        .with_setting("CK_SECURITY_PROTOCOL", setting("CK_SECURITY_PROTOCOL"))
+        .with_setting("CK_SASL_MECHANISMS", setting("CK_SASL_MECHANISMS"))
+        .with_setting("CK_SASL_USERNAME", setting("CK_SASL_USERNAME"))
+        .with_setting("CK_GROUP_ID", setting("CK_GROUP_ID"))
+        .with_secret(SASL_PASSWORD, setting(SASL_PASSWORD))
+}
+
 /// `ApacheKafkaConfigs` is a convenience structure to avoid the innate
 /// repetitiveness of code that comes w/ getting `runtime_configs`.
 struct ApacheKafkaConfigs {
@@ -128,15 +254,15 @@ struct ApacheKafkaConfigs {
 
 fn get_config(config_name: &str, state: &BasicState) -> Result<String> {
     let config = String::from_utf8(
-        slight_runtime_configs::get(
-            &state.secret_store,
+        slight_runtime_configs::resolve(
+            &state.secret_stores,
             config_name,
             &state.config_toml_file_path,
         )
         .with_context(|| {
             format!(
-                "failed to get '{}' secret using secret store type: {}",
-                config_name, state.secret_store
+                "failed to get '{}' secret using secret stores: {:?}",
+                config_name, state.secret_stores
             )
         })?,
     )?;
@@ -150,7 +276,14 @@ impl ApacheKafkaConfigs {
         let sasl_mechanisms = get_config("CK_SASL_MECHANISMS", slight_state)?;
         let sasl_username = get_config("CK_SASL_USERNAME", slight_state)?;
 
-        let sasl_password = get_config("CK_SASL_PASSWORD", slight_state)?;
+        let sasl_password = String::from_utf8(
+            slight_runtime_configs::credential(slight_state, SASL_PASSWORD).with_context(|| {
+                format!(
+                    "failed to get '{}' secret using secret stores: {:?}",
+                    SASL_PASSWORD, slight_state.secret_stores
+                )
+            })?,
+        )?;
 
         Ok(Self {
             bootstap_servers,
@@ -0,0 +1,390 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use slight_runtime::{describe::Description, resource::BasicState};
+use uuid::Uuid;
+
+use crate::providers::confluent::KafkaMessage;
+
+/// The name the broker is shared under in the `StateTable`.
+const BROKER: &str = "pubsub.inmemory";
+
+/// How many messages are queued per subscription (or buffered per topic), before the
+/// oldest ones are dropped.
+const CAPACITY: usize = 10_000;
+
+/// `Delivery` decides what happens to messages published to a topic no one is subscribed to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Delivery {
+    /// they are kept (up to `CAPACITY` per topic), and delivered to the first subscriber of the
+    /// topic, so a message published before its consumer subscribed isn't lost (the default)
+    #[default]
+    Buffered,
+    /// they are dropped, like w/ most brokers
+    Drop,
+}
+
+impl Delivery {
+    pub fn parse(delivery: &str) -> Result<Self> {
+        match delivery {
+            "buffered" => Ok(Self::Buffered),
+            "drop" => Ok(Self::Drop),
+            d => bail!("invalid delivery: '{}' (expected 'buffered', or 'drop')", d),
+        }
+    }
+}
+
+/// This is one of the underlying structs behind the `InMemory` variant of the `PubImplementor` enum.
+///
+/// It provides a property that pertains solely to the in-memory implementation
+/// of this capability:
+///     - `broker`
+///
+/// As per its usage in `PubImplementor`, it must `derive` `Debug`, and `Clone`.
+#[derive(Debug, Clone)]
+pub struct PubInMemoryImplementor {
+    broker: InMemoryBroker,
+}
+
+impl PubInMemoryImplementor {
+    pub fn new(slight_state: &BasicState, delivery: Delivery) -> Self {
+        Self {
+            broker: InMemoryBroker::shared(slight_state, delivery),
+        }
+    }
+
+    pub fn send_message_to_topic(
+        &self,
+        msg_key: &[u8],
+        msg_value: &[u8],
+        topic: &str,
+    ) -> Result<()> {
+        self.broker.publish(msg_key, msg_value, topic);
+        Ok(())
+    }
+}
+
+/// This is one of the underlying structs behind the `InMemory` variant of the `SubImplementor` enum.
+///
+/// It provides properties that pertain solely to the in-memory implementation
+/// of this capability:
+///     - `broker`,
+///     - the `id` of the subscription, and
+///     - the `topics` it's subscribed to (shared by its clones), to subscribe again after
+///     it's released.
+///
+/// As per its usage in `SubImplementor`, it must `derive` `Debug`, and `Clone`.
+#[derive(Debug, Clone)]
+pub struct SubInMemoryImplementor {
+    broker: InMemoryBroker,
+    id: String,
+    topics: Arc<Mutex<Vec<String>>>,
+}
+
+impl SubInMemoryImplementor {
+    pub fn new(slight_state: &BasicState, delivery: Delivery) -> Self {
+        Self {
+            broker: InMemoryBroker::shared(slight_state, delivery),
+            id: Uuid::new_v4().to_string(),
+            topics: Default::default(),
+        }
+    }
+
+    pub fn subscribe_to_topic(&self, topic: Vec<&str>) -> Result<()> {
+        *self.topics.lock().unwrap() = topic.iter().map(|t| t.to_string()).collect();
+        self.broker.subscribe(&self.id, &topic);
+        Ok(())
+    }
+
+    pub fn poll_for_message(&self, timeout: Duration) -> Result<KafkaMessage> {
+        // a released subscription is subscribed again (missing what was published meanwhile)
+        let topics = self.topics.lock().unwrap().clone();
+        if !topics.is_empty() && !self.broker.is_subscribed(&self.id) {
+            self.broker.subscribe(
+                &self.id,
+                &topics.iter().map(String::as_str).collect::<Vec<_>>(),
+            );
+        }
+        Ok(match self.broker.poll(&self.id, timeout)? {
+            Some((key, value)) => KafkaMessage(Some(key), Some(value), Vec::new()),
+            None => KafkaMessage(None, None, Vec::new()),
+        })
+    }
+
+    /// Removes the subscription from the broker (dropping the messages it had queued), so
+    /// it's not delivered copies of messages anymore, returning whether it was subscribed.
+    pub fn release(&self) -> bool {
+        self.broker.unsubscribe(&self.id)
+    }
+}
+
+/// A message's key, and value.
+type Message = (Vec<u8>, Vec<u8>);
+
+/// `InMemoryBroker` delivers messages between the guest instances of an app (i.e., the
+/// modules), w/o an external broker — it is shared through the app's `StateTable`.
+///
+/// Each subscription gets its own copy of the messages published to its topics after
+/// it subscribed. Messages published to topics w/o subscribers are handled as per its
+/// `Delivery`.
+#[derive(Debug, Clone)]
+struct InMemoryBroker {
+    delivery: Delivery,
+    state: Arc<(Mutex<BrokerState>, Condvar)>,
+}
+
+#[derive(Debug, Default)]
+struct BrokerState {
+    subscriptions: HashMap<String, Subscription>,
+    /// the messages published to topics w/o subscribers, if they are `Delivery::Buffered`
+    buffered: HashMap<String, VecDeque<Message>>,
+}
+
+#[derive(Debug, Default)]
+struct Subscription {
+    topics: Vec<String>,
+    queue: VecDeque<Message>,
+}
+
+impl InMemoryBroker {
+    fn new(delivery: Delivery) -> Self {
+        Self {
+            delivery,
+            state: Arc::new((Mutex::new(BrokerState::default()), Condvar::new())),
+        }
+    }
+
+    /// Gets the broker shared by the app, creating it if there's none yet.
+    fn shared(slight_state: &BasicState, delivery: Delivery) -> Self {
+        slight_state
+            .resource_map
+            .lock()
+            .unwrap()
+            .shared(BROKER, || Self::new(delivery))
+            .unwrap() // note: this unwrap will never fail, as only brokers are shared as `BROKER`
+    }
+
+    fn publish(&self, key: &[u8], value: &[u8], topic: &str) {
+        let (state, published) = &*self.state;
+        let mut state = state.lock().unwrap();
+        let mut subscribed = false;
+        for subscription in state
+            .subscriptions
+            .values_mut()
+            .filter(|s| s.topics.iter().any(|t| t == topic))
+        {
+            push(&mut subscription.queue, (key.to_vec(), value.to_vec()));
+            subscribed = true;
+        }
+
+        if subscribed {
+            published.notify_all();
+            return;
+        }
+        match self.delivery {
+            Delivery::Buffered => push(
+                state.buffered.entry(topic.to_string()).or_default(),
+                (key.to_vec(), value.to_vec()),
+            ),
+            Delivery::Drop => {
+                tracing::debug!(
+                    "dropping message published to topic '{}', which has no subscribers",
+                    topic
+                );
+            }
+        }
+    }
+
+    /// Subscribes to `topics`, replacing the topics the subscription had (if any).
+    fn subscribe(&self, id: &str, topics: &[&str]) {
+        let (state, published) = &*self.state;
+        let state = &mut *state.lock().unwrap();
+        let subscription = state.subscriptions.entry(id.to_string()).or_default();
+        subscription.topics = topics.iter().map(|t| t.to_string()).collect();
+        // buffered messages go to the first subscriber of their topic
+        for topic in topics {
+            if let Some(buffered) = state.buffered.remove(*topic) {
+                for message in buffered {
+                    push(&mut subscription.queue, message);
+                }
+            }
+        }
+        published.notify_all();
+    }
+
+    fn is_subscribed(&self, id: &str) -> bool {
+        self.state.0.lock().unwrap().subscriptions.contains_key(id)
+    }
+
+    /// Removes a subscription, returning whether there was one.
+    fn unsubscribe(&self, id: &str) -> bool {
+        let subscription = self.state.0.lock().unwrap().subscriptions.remove(id);
+        match subscription {
+            Some(subscription) => {
+                if !subscription.queue.is_empty() {
+                    tracing::debug!(
+                        "dropping {} messages queued for a released subscription",
+                        subscription.queue.len()
+                    );
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Waits (for up to `timeout`) for a message to be delivered to the subscription.
+    fn poll(&self, id: &str, timeout: Duration) -> Result<Option<Message>> {
+        let deadline = Instant::now() + timeout;
+        let (state, published) = &*self.state;
+        let mut state = state.lock().unwrap();
+        loop {
+            let subscription = match state.subscriptions.get_mut(id) {
+                Some(subscription) => subscription,
+                None => bail!("failed to poll for a message, as no topic was subscribed to"),
+            };
+            if let Some(message) = subscription.queue.pop_front() {
+                return Ok(Some(message));
+            }
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return Ok(None);
+            }
+            state = published.wait_timeout(state, timeout).unwrap().0;
+        }
+    }
+}
+
+/// Describes the broker (see `slight_runtime::describe`), which is the host's own, so it has no
+/// settings.
+pub fn describe() -> Description {
+    Description::new("pubsub.inmemory", "the host's memory")
+}
+
+/// Queues a message, dropping the oldest one if the queue is full.
+fn push(queue: &mut VecDeque<Message>, message: Message) {
+    if queue.len() >= CAPACITY {
+        queue.pop_front();
+        tracing::warn!(
+            "dropping the oldest message, as {} messages are queued already",
+            CAPACITY
+        );
+    }
+    queue.push_back(message);
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::Duration;
+
+    use anyhow::Result;
+
+    use super::{Delivery, InMemoryBroker};
+
+    const NO_WAIT: Duration = Duration::from_millis(0);
+
+    #[test]
+    fn subscribers_get_their_own_copy() -> Result<()> {
+        let broker = InMemoryBroker::new(Delivery::Drop);
+        broker.subscribe("a", &["orders"]);
+        broker.subscribe("b", &["orders", "payments"]);
+        broker.publish(b"k", b"v", "orders");
+        broker.publish(b"k", b"paid", "payments");
+
+        assert_eq!(
+            broker.poll("a", NO_WAIT)?,
+            Some((b"k".to_vec(), b"v".to_vec()))
+        );
+        assert_eq!(broker.poll("a", NO_WAIT)?, None);
+        assert_eq!(
+            broker.poll("b", NO_WAIT)?,
+            Some((b"k".to_vec(), b"v".to_vec()))
+        );
+        assert_eq!(
+            broker.poll("b", NO_WAIT)?,
+            Some((b"k".to_vec(), b"paid".to_vec()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn buffered_until_subscribed() -> Result<()> {
+        let broker = InMemoryBroker::new(Delivery::Buffered);
+        broker.publish(b"k", b"v", "orders");
+        broker.subscribe("a", &["orders"]);
+        broker.subscribe("b", &["orders"]);
+
+        assert_eq!(
+            broker.poll("a", NO_WAIT)?,
+            Some((b"k".to_vec(), b"v".to_vec()))
+        );
+        assert_eq!(broker.poll("b", NO_WAIT)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn dropped_without_subscribers() -> Result<()> {
+        let broker = InMemoryBroker::new(Delivery::Drop);
+        broker.publish(b"k", b"v", "orders");
+        broker.subscribe("a", &["orders"]);
+
+        assert_eq!(broker.poll("a", NO_WAIT)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn poll_waits_for_a_message() -> Result<()> {
+        let broker = InMemoryBroker::new(Delivery::Drop);
+        broker.subscribe("a", &["orders"]);
+        let publisher = broker.clone();
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            publisher.publish(b"k", b"v", "orders");
+        });
+
+        assert_eq!(
+            broker.poll("a", Duration::from_secs(5))?,
+            Some((b"k".to_vec(), b"v".to_vec()))
+        );
+        handle.join().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn unsubscribed_get_nothing() -> Result<()> {
+        let broker = InMemoryBroker::new(Delivery::Buffered);
+        broker.subscribe("a", &["orders"]);
+        broker.subscribe("b", &["orders"]);
+        broker.publish(b"k", b"v", "orders");
+        assert!(broker.unsubscribe("a"));
+        assert!(!broker.unsubscribe("a"));
+
+        broker.publish(b"k", b"paid", "orders");
+        assert!(broker.poll("a", NO_WAIT).is_err());
+        assert!(!broker.is_subscribed("a"));
+        assert_eq!(
+            broker.poll("b", NO_WAIT)?,
+            Some((b"k".to_vec(), b"v".to_vec()))
+        );
+        assert_eq!(broker.state.0.lock().unwrap().subscriptions.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn poll_without_subscribing() {
+        let broker = InMemoryBroker::new(Delivery::Buffered);
+        assert!(broker.poll("a", NO_WAIT).is_err());
+    }
+
+    #[test]
+    fn parse_delivery_test() -> Result<()> {
+        assert_eq!(Delivery::parse("buffered")?, Delivery::Buffered);
+        assert_eq!(Delivery::parse("drop")?, Delivery::Drop);
+        assert!(Delivery::parse("at-least-once").is_err());
+        Ok(())
+    }
+}
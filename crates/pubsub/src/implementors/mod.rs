@@ -1 +1,2 @@
 pub mod apache_kafka;
+pub mod inmemory;
@@ -1,16 +1,42 @@
+mod dedup;
 mod implementors;
 pub mod providers;
+mod schema_registry;
 
 /// The `SCHEME_NAME` defines the name under which a resource is
 /// identifiable by in a `ResourceMap`.
 const SCHEME_NAME: &str = "pubsub";
+/// The operations guests can retry if they time out (see `BasicState::with_idempotent_operations`).
+const IDEMPOTENT_OPERATIONS: &[&str] = &["subscribe-to-topic"];
+/// The operations that only read, which audits of writes only leave out (see
+/// `BasicState::with_read_operations`).
+const READ_OPERATIONS: &[&str] = &[];
+/// How big the messages the guest publishes can be (their key, and value together), unless the
+/// capability says otherwise (see `slight_runtime::payload_limit`) — it's what Kafka brokers
+/// take by default (i.e., `message.max.bytes`).
+pub const DEFAULT_MAX_PAYLOAD_BYTES: usize = 1024 * 1024;
 
-use anyhow::Result;
+use std::time::{Duration, Instant};
 
-use implementors::apache_kafka::{
-    PubConfluentApacheKafkaImplementor, SubConfluentApacheKafkaImplementor,
+use anyhow::{bail, Result};
+
+use dedup::Dedup;
+pub use dedup::DedupSettings;
+
+pub use implementors::inmemory::Delivery;
+use implementors::{
+    apache_kafka::{self, PubConfluentApacheKafkaImplementor, SubConfluentApacheKafkaImplementor},
+    inmemory::{self, PubInMemoryImplementor, SubInMemoryImplementor},
+};
+use providers::confluent::KafkaMessage;
+use schema_registry::SchemaRegistry;
+pub use schema_registry::{SchemaFormat, SchemaRegistrySettings};
+use slight_runtime::{
+    describe::Description,
+    impl_resource,
+    resource::BasicState,
+    signing::{Signing, SIGNATURE_HEADER},
 };
-use slight_runtime::{impl_resource, resource::BasicState};
 use uuid::Uuid;
 
 /// It is mandatory to `use <interface>::*` due to `impl_resource!`.
@@ -19,12 +45,13 @@ use uuid::Uuid;
 use pubsub::*;
 wit_bindgen_wasmtime::export!("../../wit/pubsub.wit");
 wit_error_rs::impl_error!(pubsub::Error);
-wit_error_rs::impl_from!(anyhow::Error, pubsub::Error::ErrorWithDescription);
 wit_error_rs::impl_from!(
     std::string::FromUtf8Error,
     pubsub::Error::ErrorWithDescription
 );
 
+slight_runtime::impl_from_anyhow!(pubsub::Error);
+
 /// The `Pubsub` structure is what will implement the `pubsub::Pubsub` trait
 /// coming from the generated code of off `pubsub.wit`.
 ///
@@ -45,22 +72,89 @@ impl_resource!(
 /// It holds:
 ///     - a `pubsub_implementor` `String` — this comes directly from a
 ///     user's `slightfile` and it is what allows us to dynamically
-///     dispatch to a specific implementor's implentation, and
+///     dispatch to a specific implementor's implentation,
 ///     - the `slight_state` (of type `BasicState`) that contains common
 ///     things received from the slight binary (i.e., the `resource_map`,
-///     the `config_type`, and the `config_toml_file_path`).
+///     the `config_type`, and the `config_toml_file_path`),
+///     - the `dedup_settings`, if messages are to be deduplicated on the consumer side,
+///     - the `delivery` of messages to topics w/o subscribers (`pubsub.inmemory` only), and
+///     - the `signing` of messages, if they are to be signed, and verified, and
+///     - the `schema_registry_settings`, if the values of messages are to be serialized w/
+///     schemas of a schema registry (both `pubsub.confluent_apache_kafka` only).
 pub struct PubsubState {
     pubsub_implementor: String,
     slight_state: BasicState,
+    dedup_settings: Option<DedupSettings>,
+    delivery: Delivery,
+    signing: Option<Signing>,
+    schema_registry_settings: Option<SchemaRegistrySettings>,
 }
 
 impl PubsubState {
     pub fn new(pubsub_implementor: String, slight_state: BasicState) -> Self {
         Self {
             pubsub_implementor,
-            slight_state,
+            slight_state: slight_state
+                .with_idempotent_operations(IDEMPOTENT_OPERATIONS)
+                .with_read_operations(READ_OPERATIONS),
+            dedup_settings: None,
+            delivery: Delivery::default(),
+            signing: None,
+            schema_registry_settings: None,
         }
     }
+
+    /// Describes the effective configuration of the backend (see `slight_runtime::describe`),
+    /// w/o connecting to it.
+    pub fn describe(&self) -> Result<Description> {
+        let description = match self.pubsub_implementor.as_str() {
+            "pubsub.confluent_apache_kafka" => apache_kafka::describe(&self.slight_state),
+            "pubsub.inmemory" => inmemory::describe(),
+            p => bail!(
+                "failed to match provided name (i.e., '{}') to any known host implementations",
+                p
+            ),
+        };
+        Ok(description.with_pool_size(self.slight_state.pool_size()))
+    }
+
+    pub fn with_dedup_settings(mut self, dedup_settings: Option<DedupSettings>) -> Self {
+        self.dedup_settings = dedup_settings;
+        self
+    }
+
+    /// Sets what happens to messages published to a topic no one is subscribed to
+    /// (`pubsub.inmemory` only).
+    pub fn with_delivery(mut self, delivery: Delivery) -> Self {
+        self.delivery = delivery;
+        self
+    }
+
+    /// Signs the messages sent w/ a header (i.e., `SIGNATURE_HEADER`), and verifies the ones
+    /// received before the guest gets them (`pubsub.confluent_apache_kafka` only, as the
+    /// messages of `pubsub.inmemory` never leave the app's process).
+    pub fn with_signing(mut self, signing: Option<Signing>) -> Self {
+        self.signing = signing;
+        self
+    }
+
+    /// Serializes the (JSON) values of the messages sent w/ schemas of a schema registry, and
+    /// deserializes the ones received back into JSON (`pubsub.confluent_apache_kafka` only).
+    pub fn with_schema_registry_settings(
+        mut self,
+        schema_registry_settings: Option<SchemaRegistrySettings>,
+    ) -> Self {
+        self.schema_registry_settings = schema_registry_settings;
+        self
+    }
+
+    /// The schema registry of the messages of a `pub`, or `sub` being opened, if any.
+    fn schema_registry(&self) -> Result<Option<SchemaRegistry>> {
+        self.schema_registry_settings
+            .clone()
+            .map(|settings| SchemaRegistry::new(settings, &self.slight_state))
+            .transpose()
+    }
 }
 
 impl pubsub::Pubsub for Pubsub {
@@ -68,13 +162,16 @@ impl pubsub::Pubsub for Pubsub {
     type Sub = SubInner;
 
     fn pub_open(&mut self) -> Result<Self::Pub, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
         // populate our inner pubsub object w/ the state received from `slight`
         // (i.e., what type of pubsub implementor we are using), and the assigned
         // name of the object.
         let inner = Self::Pub::new(
             &self.host_state.pubsub_implementor,
             &self.host_state.slight_state,
-        );
+            self.host_state.delivery,
+            self.host_state.schema_registry()?,
+        )?;
 
         self.host_state
             .slight_state
@@ -87,13 +184,17 @@ impl pubsub::Pubsub for Pubsub {
     }
 
     fn sub_open(&mut self) -> Result<Self::Sub, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
         // populate our inner pubsub object w/ the state received from `slight`
         // (i.e., what type of pubsub implementor we are using), and the assigned
         // name of the object.
         let inner = Self::Sub::new(
             &self.host_state.pubsub_implementor,
             &self.host_state.slight_state,
-        );
+            self.host_state.dedup_settings.as_ref(),
+            self.host_state.delivery,
+            self.host_state.schema_registry()?,
+        )?;
 
         self.host_state
             .slight_state
@@ -112,21 +213,66 @@ impl pubsub::Pubsub for Pubsub {
         msg_value: PayloadParam<'_>,
         topic: &str,
     ) -> Result<(), Error> {
-        match &self_.pub_implementor {
-            PubImplementor::ConfluentApacheKafka(pi) => {
-                pi.send_message_to_topic(msg_key, msg_value, topic)?
-            }
-        };
-
-        Ok(())
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "send-message-to-topic", topic, || {
+                let bytes = msg_key.len() + msg_value.len();
+                self.host_state
+                    .slight_state
+                    .check_payload("send-message-to-topic", bytes)?;
+                self.host_state.slight_state.take_bytes(bytes)?;
+                let send = || match &self_.pub_implementor {
+                    PubImplementor::ConfluentApacheKafka(pi) => {
+                        let serialized = self_
+                            .schema_registry
+                            .as_ref()
+                            .map(|registry| registry.serialize(topic, msg_value))
+                            .transpose()?;
+                        let msg_value = serialized.as_deref().unwrap_or(msg_value);
+                        let signature = self
+                            .host_state
+                            .signing
+                            .as_ref()
+                            .map(|signing| signing.sign(&[msg_key, msg_value]));
+                        let headers = signature
+                            .iter()
+                            .map(|signature| (SIGNATURE_HEADER, signature.as_bytes()))
+                            .collect::<Vec<_>>();
+                        pi.send_message_to_topic(msg_key, msg_value, topic, &headers)
+                    }
+                    PubImplementor::InMemory(pi) => {
+                        pi.send_message_to_topic(msg_key, msg_value, topic)
+                    }
+                };
+                self.host_state.slight_state.recorded(
+                    SCHEME_NAME,
+                    "send-message-to-topic",
+                    &[&topic, &msg_key, &msg_value],
+                    send,
+                )
+            })
     }
 
     fn sub_subscribe_to_topic(&mut self, self_: &Self::Sub, topic: Vec<&str>) -> Result<(), Error> {
-        match &self_.sub_implementor {
-            SubImplementor::ConfluentApacheKafka(si) => si.subscribe_to_topic(topic)?,
-        }
-
-        Ok(())
+        let target = topic.join(",");
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "subscribe-to-topic", &target, || {
+                self.host_state.slight_state.recorded(
+                    SCHEME_NAME,
+                    "subscribe-to-topic",
+                    &[&target],
+                    || {
+                        match &self_.sub_implementor {
+                            SubImplementor::ConfluentApacheKafka(si) => {
+                                si.subscribe_to_topic(topic)?
+                            }
+                            SubImplementor::InMemory(si) => si.subscribe_to_topic(topic)?,
+                        }
+                        Ok(())
+                    },
+                )
+            })
     }
 
     fn sub_poll_for_message(
@@ -134,120 +280,363 @@ impl pubsub::Pubsub for Pubsub {
         self_: &Self::Sub,
         timeout_in_secs: u64,
     ) -> Result<Message, Error> {
-        Ok(match &self_.sub_implementor {
-            SubImplementor::ConfluentApacheKafka(si) => {
-                si.poll_for_message(timeout_in_secs)
-                    .map(|f| pubsub::Message {
-                        key: f.0,
-                        value: f.1,
-                    })?
-            }
-        })
+        self.host_state.slight_state.instrument(
+            SCHEME_NAME,
+            "poll-for-message",
+            "subscription",
+            || {
+                let deadline = Instant::now() + Duration::from_secs(timeout_in_secs);
+                loop {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    // the message is recorded as it was polled (i.e., w/ its headers), so it's
+                    // verified, and deduplicated again when it's replayed
+                    let (key, value, headers) = self.host_state.slight_state.recorded(
+                        SCHEME_NAME,
+                        "poll-for-message",
+                        &[],
+                        || {
+                            let message = match &self_.sub_implementor {
+                                SubImplementor::ConfluentApacheKafka(si) => {
+                                    si.poll_for_message(timeout)?
+                                }
+                                SubImplementor::InMemory(si) => si.poll_for_message(timeout)?,
+                            };
+                            Ok((message.0, message.1, message.2))
+                        },
+                    )?;
+                    let message = KafkaMessage(key, value, headers);
+                    let bytes = message.0.as_ref().map_or(0, Vec::len)
+                        + message.1.as_ref().map_or(0, Vec::len);
+                    self.host_state.slight_state.charge_bytes(bytes);
+                    // rejected messages, and duplicates are skipped, and polling goes on until
+                    // the timeout is up
+                    let received = message.0.is_some() || message.1.is_some();
+                    if received {
+                        let rejected = match &self.host_state.signing {
+                            Some(signing) => !verified(signing, &message),
+                            None => false,
+                        };
+                        // rejected messages aren't recorded as seen, so a genuine one w/ the
+                        // same id isn't taken for a duplicate
+                        let duplicate = match &self_.dedup {
+                            Some(dedup) if !rejected => dedup.is_duplicate(&message)?,
+                            _ => false,
+                        };
+                        if duplicate {
+                            tracing::debug!("skipping duplicate message");
+                        }
+                        if rejected || duplicate {
+                            if Instant::now() < deadline {
+                                continue;
+                            }
+                            return Ok(pubsub::Message {
+                                key: None,
+                                value: None,
+                            });
+                        }
+                    }
+                    // messages are verified, and deduplicated as they were sent (i.e., still
+                    // serialized)
+                    let value = match (&self_.schema_registry, message.1) {
+                        (Some(registry), Some(value)) => Some(registry.deserialize(&value)?),
+                        (_, value) => value,
+                    };
+                    let message = pubsub::Message {
+                        key: message.0,
+                        value,
+                    };
+                    check_headroom(&self.host_state.slight_state, &message)?;
+                    return Ok(message);
+                }
+            },
+        )
+    }
+}
+
+/// `HostSub` is a subscription for the host's own use, so that it can look at the messages
+/// sent to topics w/o a guest (e.g., `slight pubsub tail`).
+#[derive(Debug, Clone)]
+pub struct HostSub {
+    sub_implementor: SubConfluentApacheKafkaImplementor,
+}
+
+impl HostSub {
+    /// Subscribes to `topics` in a consumer group of its own, so it doesn't take messages
+    /// from the app's subscribers.
+    ///
+    /// Only `pubsub.confluent_apache_kafka` supports it, as the topics of `pubsub.inmemory`
+    /// only exist in the process of the app that uses them.
+    pub fn subscribe(
+        pubsub_implementor: &str,
+        slight_state: &BasicState,
+        topics: Vec<&str>,
+    ) -> Result<Self> {
+        let sub_implementor = match pubsub_implementor {
+            "pubsub.confluent_apache_kafka" => SubConfluentApacheKafkaImplementor::new_in_group(
+                slight_state,
+                &format!("slight-tail-{}", Uuid::new_v4()),
+            )?,
+            "pubsub.inmemory" => bail!(
+                "the topics of pubsub.inmemory only exist in the process of the app that uses them, so they can't be subscribed to from outside of it"
+            ),
+            p => bail!(
+                "failed to match provided name (i.e., '{}') to any known host implementations",
+                p
+            ),
+        };
+        sub_implementor.subscribe_to_topic(topics)?;
+        Ok(Self { sub_implementor })
+    }
+
+    /// Polls for a message for up to `timeout`, returning its key, and value (both `None`
+    /// if none arrived).
+    pub fn poll(&self, timeout: Duration) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>)> {
+        let message = self.sub_implementor.poll_for_message(timeout)?;
+        Ok((message.0, message.1))
+    }
+}
+
+/// `HostPub` is a publisher for the host's own use, so that it can send messages to topics
+/// w/o a guest (e.g., the host's log records, see `slight_runtime::log_sink`).
+#[derive(Debug, Clone)]
+pub struct HostPub {
+    pub_implementor: PubConfluentApacheKafkaImplementor,
+}
+
+impl HostPub {
+    /// Only `pubsub.confluent_apache_kafka` supports it, as the topics of `pubsub.inmemory`
+    /// only exist in the process of the app that uses them.
+    pub fn open(pubsub_implementor: &str, slight_state: &BasicState) -> Result<Self> {
+        let pub_implementor = match pubsub_implementor {
+            "pubsub.confluent_apache_kafka" => PubConfluentApacheKafkaImplementor::new(slight_state)?,
+            "pubsub.inmemory" => bail!(
+                "the topics of pubsub.inmemory only exist in the process of the app that uses them, so the host can't send to them from outside of it"
+            ),
+            p => bail!(
+                "failed to match provided name (i.e., '{}') to any known host implementations",
+                p
+            ),
+        };
+        Ok(Self { pub_implementor })
+    }
+
+    pub fn send_message_to_topic(
+        &self,
+        msg_key: &[u8],
+        msg_value: &[u8],
+        topic: &str,
+    ) -> Result<()> {
+        self.pub_implementor
+            .send_message_to_topic(msg_key, msg_value, topic, &[])
     }
 }
 
 /// This is the type of the associated type coming from the `pubsub::Pubsub` trait implementation.
 ///
 /// It holds:
-///     - a `pub_implementor` (i.e., a variant `PubImplementor` `enum`), and
+///     - a `pub_implementor` (i.e., a variant `PubImplementor` `enum`),
+///     - the `schema_registry` the values of messages are serialized w/ (if enabled), and
 ///     - a `resource_descriptor` (i.e., an UUID that uniquely identifies
 ///     resource's instance).
 ///
 /// It must `derive`:
 ///     - `Debug` due to a constraint on the associated type.
 ///     - `Clone` because the `ResourceMap` it will be added onto,
-///     must own its' data.
+///     must own its data.
 ///
 /// It must be public because the implementation of `pubsub::Pubsub` cannot leak
 /// a private type.
 #[derive(Debug, Clone)]
 pub struct PubInner {
     pub_implementor: PubImplementor,
+    schema_registry: Option<SchemaRegistry>,
     resource_descriptor: String,
 }
 
 impl slight_runtime::resource::Watch for PubInner {}
 
 impl PubInner {
-    fn new(pub_implementor: &str, slight_state: &BasicState) -> Self {
-        Self {
-            pub_implementor: PubImplementor::new(pub_implementor, slight_state),
+    fn new(
+        pub_implementor: &str,
+        slight_state: &BasicState,
+        delivery: Delivery,
+        schema_registry: Option<SchemaRegistry>,
+    ) -> Result<Self> {
+        Ok(Self {
+            pub_implementor: PubImplementor::new(pub_implementor, slight_state, delivery)?,
+            schema_registry,
             resource_descriptor: Uuid::new_v4().to_string(),
-        }
+        })
     }
 }
 
 /// This is the type of the associated type coming from the `pubsub::Pubsub` trait implementation.
 ///
 /// It holds:
-///     - a `sub_implementor` (i.e., a variant `SubImplementor` `enum`), and
+///     - a `sub_implementor` (i.e., a variant `SubImplementor` `enum`),
+///     - the `dedup` window of seen messages (if enabled),
+///     - the `schema_registry` the values of messages are deserialized w/ (if enabled), and
 ///     - a `resource_descriptor` (i.e., an UUID that uniquely identifies
 ///     resource's instance).
 ///
 /// It must `derive`:
 ///     - `Debug` due to a constraint on the associated type.
 ///     - `Clone` because the `ResourceMap` it will be added onto,
-///     must own its' data.
+///     must own its data.
 ///
 /// It must be public because the implementation of `pubsub::Pubsub` cannot leak
 /// a private type.
 #[derive(Debug, Clone)]
 pub struct SubInner {
     sub_implementor: SubImplementor,
+    dedup: Option<Dedup>,
+    schema_registry: Option<SchemaRegistry>,
     resource_descriptor: String,
 }
 
-impl slight_runtime::resource::Watch for SubInner {}
+impl slight_runtime::resource::Watch for SubInner {
+    /// Removes the subscription of a `pubsub.inmemory` subscriber from the broker, so it's not
+    /// queued copies of messages no one polls for — polling again subscribes it again.
+    fn releaser(&self) -> Option<slight_runtime::resource::Releaser> {
+        match &self.sub_implementor {
+            SubImplementor::InMemory(si) => {
+                let si = si.clone();
+                Some(Box::new(move || Ok(si.release())))
+            }
+            SubImplementor::ConfluentApacheKafka(_) => None,
+        }
+    }
+}
 
 impl SubInner {
-    fn new(sub_implementor: &str, slight_state: &BasicState) -> Self {
-        Self {
-            sub_implementor: SubImplementor::new(sub_implementor, slight_state),
+    fn new(
+        sub_implementor: &str,
+        slight_state: &BasicState,
+        dedup_settings: Option<&DedupSettings>,
+        delivery: Delivery,
+        schema_registry: Option<SchemaRegistry>,
+    ) -> Result<Self> {
+        Ok(Self {
+            sub_implementor: SubImplementor::new(sub_implementor, slight_state, delivery)?,
+            dedup: dedup_settings
+                .map(|settings| Dedup::new(settings, slight_state))
+                .transpose()?,
+            schema_registry,
             resource_descriptor: Uuid::new_v4().to_string(),
-        }
+        })
     }
 }
 
 /// This defines the available implementor implementations for the `Pubsub` interface.
 ///
-/// As per its' usage in `PubInner`, it must `derive` `Debug`, and `Clone`.
+/// As per its usage in `PubInner`, it must `derive` `Debug`, and `Clone`.
 #[derive(Debug, Clone)]
 enum PubImplementor {
     ConfluentApacheKafka(PubConfluentApacheKafkaImplementor),
+    InMemory(PubInMemoryImplementor),
 }
 
 impl PubImplementor {
-    fn new(pubsub_implementor: &str, slight_state: &BasicState) -> Self {
-        match pubsub_implementor {
+    fn new(
+        pubsub_implementor: &str,
+        slight_state: &BasicState,
+        delivery: Delivery,
+    ) -> Result<Self> {
+        Ok(match pubsub_implementor {
             "pubsub.confluent_apache_kafka" => {
-                Self::ConfluentApacheKafka(PubConfluentApacheKafkaImplementor::new(slight_state))
+                Self::ConfluentApacheKafka(PubConfluentApacheKafkaImplementor::new(slight_state)?)
+            }
+            "pubsub.inmemory" => {
+                Self::InMemory(PubInMemoryImplementor::new(slight_state, delivery))
             }
-            p => panic!(
+            p => bail!(
                 "failed to match provided name (i.e., '{}') to any known host implementations",
                 p
             ),
-        }
+        })
     }
 }
 
 /// This defines the available implementor implementations for the `Pubsub` interface.
 ///
-/// As per its' usage in `SubInner`, it must `derive` `Debug`, and `Clone`.
+/// As per its usage in `SubInner`, it must `derive` `Debug`, and `Clone`.
 #[derive(Debug, Clone)]
 enum SubImplementor {
     ConfluentApacheKafka(SubConfluentApacheKafkaImplementor),
+    InMemory(SubInMemoryImplementor),
 }
 
 impl SubImplementor {
-    fn new(pubsub_implementor: &str, slight_state: &BasicState) -> Self {
-        match pubsub_implementor {
+    fn new(
+        pubsub_implementor: &str,
+        slight_state: &BasicState,
+        delivery: Delivery,
+    ) -> Result<Self> {
+        Ok(match pubsub_implementor {
             "pubsub.confluent_apache_kafka" => {
-                Self::ConfluentApacheKafka(SubConfluentApacheKafkaImplementor::new(slight_state))
+                Self::ConfluentApacheKafka(SubConfluentApacheKafkaImplementor::new(slight_state)?)
+            }
+            "pubsub.inmemory" => {
+                Self::InMemory(SubInMemoryImplementor::new(slight_state, delivery))
             }
-            p => panic!(
+            p => bail!(
                 "failed to match provided name (i.e., '{}') to any known host implementations",
                 p
             ),
-        }
+        })
+    }
+}
+
+/// Whether a received message can be handed to the guest, as per `Signing::accepts`.
+fn verified(signing: &Signing, message: &KafkaMessage) -> bool {
+    let KafkaMessage(key, value, headers) = message;
+    let signature = headers
+        .iter()
+        .find(|(name, _)| name == SIGNATURE_HEADER)
+        .map(|(_, signature)| signature.as_slice());
+    signing.accepts(
+        SCHEME_NAME,
+        &[
+            key.as_deref().unwrap_or_default(),
+            value.as_deref().unwrap_or_default(),
+        ],
+        signature,
+    )
+}
+
+/// Checks the guest has room for a message it polled (see `BasicState::check_headroom`) — the
+/// message is consumed either way.
+fn check_headroom(slight_state: &BasicState, message: &pubsub::Message) -> Result<()> {
+    let bytes =
+        message.key.as_ref().map_or(0, Vec::len) + message.value.as_ref().map_or(0, Vec::len);
+    slight_state.check_headroom("poll-for-message", bytes)
+}
+
+#[cfg(test)]
+mod unittests {
+    use slight_runtime::{
+        headroom::OutOfMemory,
+        memory::Limiter,
+        resource::{BasicState, ResourceMap},
+    };
+
+    use super::{check_headroom, pubsub::Message};
+
+    #[test]
+    fn check_headroom_test() {
+        let message = Message {
+            key: Some(b"key".to_vec()),
+            value: Some(b"value".to_vec()),
+        };
+        let slight_state = BasicState::new(ResourceMap::default(), &[], "slightfile.toml");
+        assert!(check_headroom(&slight_state, &message).is_ok());
+
+        // a guest whose memory can't grow has no room for any message
+        let mut limiter = Limiter::default();
+        limiter.limit_memory(0);
+        let slight_state = slight_state.with_headroom(limiter.headroom());
+        let e = check_headroom(&slight_state, &message).unwrap_err();
+        assert!(OutOfMemory::is(&e));
+        assert!(e.to_string().contains("'poll-for-message' is 8 bytes"));
     }
 }
@@ -3,33 +3,52 @@ use std::time::Duration;
 use anyhow::Result;
 use rdkafka::{
     consumer::{BaseConsumer, Consumer},
+    error::{KafkaError, RDKafkaErrorCode},
+    message::{Headers, OwnedHeaders},
     producer::{BaseProducer, BaseRecord},
     Message,
 };
+use slight_runtime::credentials::CredentialsError;
 
-/// A wrapper type around a pair of vector of u8s
-pub struct KafkaMessage(pub Option<Vec<u8>>, pub Option<Vec<u8>>);
+/// A wrapper type around a message's key, value, and headers
+pub struct KafkaMessage(
+    pub Option<Vec<u8>>,
+    pub Option<Vec<u8>>,
+    pub Vec<(String, Vec<u8>)>,
+);
 
-/// Send a message
-pub fn send(producer: &BaseProducer, msg_key: &[u8], msg_value: &[u8], topic: &str) -> Result<()> {
-    producer
-        .send(BaseRecord::to(topic).key(msg_key).payload(msg_value))
-        .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+/// Send a message, w/ `headers`
+pub fn send(
+    producer: &BaseProducer,
+    msg_key: &[u8],
+    msg_value: &[u8],
+    topic: &str,
+    headers: &[(&str, &[u8])],
+) -> Result<()> {
+    let mut record = BaseRecord::to(topic).key(msg_key).payload(msg_value);
+    if !headers.is_empty() {
+        record = record.headers(
+            headers
+                .iter()
+                .fold(OwnedHeaders::new(), |owned, (name, value)| {
+                    owned.add(name, *value)
+                }),
+        );
+    }
+    producer.send(record).map_err(|(e, _)| rejected(e))?;
     Ok(())
 }
 
 /// Subscribe to topic
 pub fn subscribe(consumer: &BaseConsumer, topic: Vec<&str>) -> Result<()> {
-    consumer.subscribe(&topic)?;
+    consumer.subscribe(&topic).map_err(rejected)?;
 
     Ok(())
 }
 
 /// Receive/poll for messages
-pub fn poll(consumer: &BaseConsumer, timeout_in_secs: u64) -> Result<KafkaMessage> {
-    let message = consumer
-        .poll(Duration::from_secs(timeout_in_secs))
-        .transpose()?;
+pub fn poll(consumer: &BaseConsumer, timeout: Duration) -> Result<KafkaMessage> {
+    let message = consumer.poll(timeout).transpose().map_err(rejected)?;
 
     match message {
         Some(m) => Ok(KafkaMessage(
@@ -38,7 +57,98 @@ pub fn poll(consumer: &BaseConsumer, timeout_in_secs: u64) -> Result<KafkaMessag
                 .expect("failed to get message key view")
                 .map(Vec::from),
             m.payload().map(Vec::from),
+            m.headers()
+                .map(|headers| {
+                    (0..headers.count())
+                        .filter_map(|i| headers.get(i))
+                        .map(|(name, value)| (name.to_string(), value.to_vec()))
+                        .collect()
+                })
+                .unwrap_or_default(),
         )),
-        None => Ok(KafkaMessage(None, None)),
+        None => Ok(KafkaMessage(None, None, Vec::new())),
+    }
+}
+
+/// The error of a request Kafka failed, w/ what it said about the credentials, if it
+/// rejected them.
+fn rejected(e: KafkaError) -> anyhow::Error {
+    let credentials_error = credentials_error(&e);
+    let e = anyhow::Error::new(e);
+    match credentials_error {
+        Some(credentials_error) => e.context(credentials_error),
+        None => e,
+    }
+}
+
+/// Tells apart the ways Kafka rejects credentials from the error code of a failed request.
+///
+/// Kafka doesn't tell an expired password from a wrong one when a SASL authentication fails,
+/// so only expired delegation tokens are reported as expired.
+pub fn credentials_error(e: &KafkaError) -> Option<CredentialsError> {
+    let detail = e.to_string();
+    match e.rdkafka_error_code()? {
+        RDKafkaErrorCode::DelegationTokenExpired => Some(CredentialsError::Expired(detail)),
+        RDKafkaErrorCode::Authentication
+        | RDKafkaErrorCode::SaslAuthenticationFailed
+        | RDKafkaErrorCode::DelegationTokenNotFound
+        | RDKafkaErrorCode::DelegationTokenOwnerMismatch => Some(CredentialsError::Invalid(detail)),
+        RDKafkaErrorCode::TopicAuthorizationFailed
+        | RDKafkaErrorCode::GroupAuthorizationFailed
+        | RDKafkaErrorCode::ClusterAuthorizationFailed
+        | RDKafkaErrorCode::TransactionalIdAuthorizationFailed
+        | RDKafkaErrorCode::DelegationTokenAuthorizationFailed => {
+            Some(CredentialsError::PermissionDenied(detail))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+    use slight_runtime::credentials::CredentialsError;
+
+    use super::credentials_error;
+
+    #[test]
+    fn credentials_error_test() {
+        assert!(matches!(
+            credentials_error(&KafkaError::MessageConsumption(
+                RDKafkaErrorCode::DelegationTokenExpired
+            )),
+            Some(CredentialsError::Expired(_))
+        ));
+        assert!(matches!(
+            credentials_error(&KafkaError::MessageConsumption(
+                RDKafkaErrorCode::Authentication
+            )),
+            Some(CredentialsError::Invalid(_))
+        ));
+        assert!(matches!(
+            credentials_error(&KafkaError::MessageProduction(
+                RDKafkaErrorCode::SaslAuthenticationFailed
+            )),
+            Some(CredentialsError::Invalid(_))
+        ));
+        assert!(matches!(
+            credentials_error(&KafkaError::MessageProduction(
+                RDKafkaErrorCode::TopicAuthorizationFailed
+            )),
+            Some(CredentialsError::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            credentials_error(&KafkaError::MessageConsumption(
+                RDKafkaErrorCode::GroupAuthorizationFailed
+            )),
+            Some(CredentialsError::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            credentials_error(&KafkaError::MessageProduction(
+                RDKafkaErrorCode::MessageTimedOut
+            )),
+            None
+        ));
+        assert!(matches!(credentials_error(&KafkaError::Canceled), None));
     }
 }
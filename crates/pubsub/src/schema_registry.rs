@@ -0,0 +1,570 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::Cursor,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Context, Result};
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage, FileDescriptor, MessageDescriptor};
+use reqwest::{header, StatusCode};
+use serde::Deserialize;
+use slight_runtime::deadline::block_on;
+use slight_runtime::{credentials::CredentialsError, resource::BasicState};
+
+/// The byte framed messages start w/, followed by the id of their schema (as a big-endian
+/// `u32`).
+const MAGIC_BYTE: u8 = 0;
+
+/// The content type of the registry's API.
+const CONTENT_TYPE: &str = "application/vnd.schemaregistry.v1+json";
+
+/// The name schemas of the `protobuf` format are parsed under.
+const PROTO_FILE: &str = "schema.proto";
+
+/// The formats messages can be serialized in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchemaFormat {
+    Avro,
+    Protobuf,
+}
+
+impl SchemaFormat {
+    pub fn parse(format: &str) -> Result<Self> {
+        match format {
+            "avro" => Ok(Self::Avro),
+            "protobuf" => Ok(Self::Protobuf),
+            f => bail!(
+                "invalid pubsub.schema_format: '{}' (expected 'avro', or 'protobuf')",
+                f
+            ),
+        }
+    }
+
+    /// The `schemaType` of the format, as per the registry (where `AVRO` is the default).
+    fn schema_type(&self) -> &'static str {
+        match self {
+            Self::Avro => "AVRO",
+            Self::Protobuf => "PROTOBUF",
+        }
+    }
+}
+
+/// The settings for serializing the values of messages w/ schemas of a schema registry.
+///
+/// It holds:
+///     - the `format` of the schemas messages are produced w/, and
+///     - the `schemas` (i.e., their sources) messages are produced w/, per topic — the ones
+///     of other topics are produced w/ the latest schema registered for them.
+#[derive(Clone, Debug)]
+pub struct SchemaRegistrySettings {
+    pub format: SchemaFormat,
+    pub schemas: HashMap<String, String>,
+}
+
+/// `SchemaRegistry` serializes the values of messages w/ schemas of a Confluent-compatible
+/// schema registry, whose `CK_SCHEMA_REGISTRY_URL`, and (optional)
+/// `CK_SCHEMA_REGISTRY_USERNAME`, and `CK_SCHEMA_REGISTRY_PASSWORD` are read from the secret
+/// store.
+///
+/// Guests send, and receive values as JSON, which is serialized as per the schema of the
+/// topic's subject (i.e., `<topic>-value`), and framed w/ its id — like the serializers of
+/// Confluent's clients do, so slight apps can produce, and consume messages alongside them.
+/// Keys are left as they are.
+///
+/// A schema of the `schemas` is registered (once) before messages are produced w/ it, unless
+/// it's already registered, or it isn't compatible w/ the latest one (as per the subject's
+/// compatibility level), in which case sending fails. Messages are consumed w/ the schema they
+/// were produced w/, which is looked up by its id.
+///
+/// Only `protobuf` schemas w/o imports (i.e., w/o references) are supported, and messages are
+/// produced w/ the first message type of theirs.
+#[derive(Clone)]
+pub struct SchemaRegistry {
+    client: reqwest::Client,
+    url: String,
+    auth: Option<(String, Option<String>)>,
+    settings: SchemaRegistrySettings,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl fmt::Debug for SchemaRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SchemaRegistry({})", self.url)
+    }
+}
+
+/// The schemas already known, by id, and by the topics they're produced to.
+#[derive(Default)]
+struct Cache {
+    by_id: HashMap<u32, Arc<Schema>>,
+    by_topic: HashMap<String, (u32, Arc<Schema>)>,
+}
+
+#[derive(Deserialize)]
+struct SchemaResponse {
+    schema: String,
+    #[serde(rename = "schemaType")]
+    schema_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SubjectSchemaResponse {
+    id: u32,
+    schema: String,
+    #[serde(rename = "schemaType")]
+    schema_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct IdResponse {
+    id: u32,
+}
+
+#[derive(Deserialize)]
+struct CompatibilityResponse {
+    is_compatible: bool,
+    #[serde(default)]
+    messages: Vec<String>,
+}
+
+impl SchemaRegistry {
+    pub fn new(settings: SchemaRegistrySettings, slight_state: &BasicState) -> Result<Self> {
+        let url = secret(slight_state, "CK_SCHEMA_REGISTRY_URL")?;
+        // not every registry requires auth
+        let auth = secret(slight_state, "CK_SCHEMA_REGISTRY_USERNAME")
+            .ok()
+            .map(|username| {
+                let password =
+                    slight_runtime_configs::credential(slight_state, "CK_SCHEMA_REGISTRY_PASSWORD")
+                        .ok()
+                        .map(String::from_utf8)
+                        .transpose()?;
+                Ok::<_, anyhow::Error>((username, password))
+            })
+            .transpose()?;
+        Ok(Self {
+            client: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            auth,
+            settings,
+            cache: Arc::new(Mutex::new(Cache::default())),
+        })
+    }
+
+    /// Serializes a (JSON) value sent to `topic` w/ its schema, framed w/ its id.
+    pub fn serialize(&self, topic: &str, value: &[u8]) -> Result<Vec<u8>> {
+        let (id, schema) = self.schema_of_topic(topic)?;
+        let serialized = schema
+            .serialize(value)
+            .with_context(|| format!("failed to serialize a message to '{}'", topic))?;
+        Ok(frame(id, &schema.message_indexes(), &serialized))
+    }
+
+    /// Deserializes a received value into JSON w/ the schema it was framed w/.
+    pub fn deserialize(&self, value: &[u8]) -> Result<Vec<u8>> {
+        let (id, body) = unframe(value)?;
+        let schema = self.schema_of_id(id)?;
+        schema
+            .deserialize(body)
+            .with_context(|| format!("failed to deserialize a message w/ schema {}", id))
+    }
+
+    fn schema_of_topic(&self, topic: &str) -> Result<(u32, Arc<Schema>)> {
+        if let Some(known) = self.cache.lock().unwrap().by_topic.get(topic) {
+            return Ok(known.clone());
+        }
+        let subject = format!("{}-value", topic);
+        let (id, schema) = match self.settings.schemas.get(topic) {
+            Some(source) => {
+                let schema = Schema::parse(self.settings.format, source)?;
+                (self.register(&subject, source)?, schema)
+            }
+            None => {
+                let latest = self
+                    .request::<SubjectSchemaResponse>(
+                        reqwest::Method::GET,
+                        &format!("/subjects/{}/versions/latest", subject),
+                        None,
+                    )?
+                    .with_context(|| format!("no schema is registered for '{}'", subject))?;
+                let schema =
+                    Schema::parse(format_of(latest.schema_type.as_deref())?, &latest.schema)?;
+                (latest.id, schema)
+            }
+        };
+        let known = (id, Arc::new(schema));
+        let mut cache = self.cache.lock().unwrap();
+        cache.by_id.insert(id, known.1.clone());
+        cache.by_topic.insert(topic.to_string(), known.clone());
+        Ok(known)
+    }
+
+    fn schema_of_id(&self, id: u32) -> Result<Arc<Schema>> {
+        if let Some(schema) = self.cache.lock().unwrap().by_id.get(&id) {
+            return Ok(schema.clone());
+        }
+        let res = self
+            .request::<SchemaResponse>(reqwest::Method::GET, &format!("/schemas/ids/{}", id), None)?
+            .with_context(|| format!("no schema is registered w/ id {}", id))?;
+        let schema = Arc::new(Schema::parse(
+            format_of(res.schema_type.as_deref())?,
+            &res.schema,
+        )?);
+        self.cache.lock().unwrap().by_id.insert(id, schema.clone());
+        Ok(schema)
+    }
+
+    /// Registers a schema under `subject`, unless it already is, returning its id — a schema
+    /// that isn't compatible w/ the latest one of the subject isn't.
+    fn register(&self, subject: &str, source: &str) -> Result<u32> {
+        let body = serde_json::json!({
+            "schema": source,
+            "schemaType": self.settings.format.schema_type(),
+        });
+        if let Some(registered) = self.request::<IdResponse>(
+            reqwest::Method::POST,
+            &format!("/subjects/{}", subject),
+            Some(&body),
+        )? {
+            return Ok(registered.id);
+        }
+        // a subject w/o versions takes any schema
+        if let Some(compatibility) = self.request::<CompatibilityResponse>(
+            reqwest::Method::POST,
+            &format!("/compatibility/subjects/{}/versions/latest", subject),
+            Some(&body),
+        )? {
+            if !compatibility.is_compatible {
+                bail!(
+                    "the schema of '{}' isn't compatible w/ the latest one registered: {}",
+                    subject,
+                    compatibility.messages.join("; ")
+                );
+            }
+        }
+        let registered = self
+            .request::<IdResponse>(
+                reqwest::Method::POST,
+                &format!("/subjects/{}/versions", subject),
+                Some(&body),
+            )?
+            .with_context(|| format!("failed to register the schema of '{}'", subject))?;
+        tracing::info!(
+            "registered the schema of '{}' w/ id {}",
+            subject,
+            registered.id
+        );
+        Ok(registered.id)
+    }
+
+    /// Makes a request to the registry, returning `None` if what's asked for isn't found.
+    fn request<T: serde::de::DeserializeOwned>(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<Option<T>> {
+        let mut req = self
+            .client
+            .request(method, format!("{}{}", self.url, path))
+            .header(header::ACCEPT, CONTENT_TYPE);
+        if let Some((username, password)) = &self.auth {
+            req = req.basic_auth(username, password.as_ref());
+        }
+        if let Some(body) = body {
+            req = req
+                .header(header::CONTENT_TYPE, CONTENT_TYPE)
+                .body(body.to_string());
+        }
+        let res = block_on(req.send())?
+            .with_context(|| format!("failed to reach the schema registry at {}", self.url))?;
+        let status = res.status();
+        let detail = || format!("the schema registry responded to {} w/ {}", path, status);
+        match status {
+            StatusCode::NOT_FOUND => return Ok(None),
+            StatusCode::UNAUTHORIZED => {
+                return Err(anyhow::anyhow!(detail()).context(CredentialsError::Invalid(detail())))
+            }
+            StatusCode::FORBIDDEN => {
+                return Err(
+                    anyhow::anyhow!(detail()).context(CredentialsError::PermissionDenied(detail()))
+                )
+            }
+            // e.g., an invalid schema, or an incompatible one, when registering it
+            s if !s.is_success() => bail!("{}: {}", detail(), block_on(res.text())??),
+            _ => {}
+        }
+        let body = block_on(res.bytes())??;
+        Ok(Some(serde_json::from_slice(&body).with_context(|| {
+            format!(
+                "the schema registry responded to {} w/ an invalid body",
+                path
+            )
+        })?))
+    }
+}
+
+/// A parsed schema.
+enum Schema {
+    Avro(apache_avro::Schema),
+    /// the file a `protobuf` schema was parsed into, w/ the message type messages are
+    /// produced w/ (i.e., its first one)
+    Protobuf(FileDescriptor, MessageDescriptor),
+}
+
+impl Schema {
+    fn parse(format: SchemaFormat, source: &str) -> Result<Self> {
+        match format {
+            SchemaFormat::Avro => Ok(Self::Avro(
+                apache_avro::Schema::parse_str(source)
+                    .with_context(|| "failed to parse avro schema")?,
+            )),
+            SchemaFormat::Protobuf => {
+                let file = protox_parse::parse(PROTO_FILE, source)
+                    .with_context(|| "failed to parse protobuf schema")?;
+                if !file.dependency.is_empty() {
+                    bail!(
+                        "protobuf schemas w/ imports aren't supported (imports: {:?})",
+                        file.dependency
+                    );
+                }
+                let mut pool = DescriptorPool::new();
+                pool.add_file_descriptor_proto(file)?;
+                let file = pool
+                    .get_file_by_name(PROTO_FILE)
+                    .expect("the schema was just added to the pool");
+                let message = file
+                    .messages()
+                    .next()
+                    .with_context(|| "protobuf schema has no message type")?;
+                Ok(Self::Protobuf(file, message))
+            }
+        }
+    }
+
+    /// The path to the message type messages are produced w/, from the top of the file's
+    /// (i.e., `[0]` for its first one), as the framing of `protobuf` messages says.
+    fn message_indexes(&self) -> Vec<i64> {
+        match self {
+            Self::Avro(_) => Vec::new(),
+            Self::Protobuf(..) => vec![0],
+        }
+    }
+
+    fn serialize(&self, json: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::Avro(schema) => {
+                let json = serde_json::from_slice::<serde_json::Value>(json)?;
+                let value = apache_avro::types::Value::from(json).resolve(schema)?;
+                Ok(apache_avro::to_avro_datum(schema, value)?)
+            }
+            Self::Protobuf(_, message) => {
+                let mut deserializer = serde_json::Deserializer::from_slice(json);
+                let message = DynamicMessage::deserialize(message.clone(), &mut deserializer)?;
+                deserializer.end()?;
+                Ok(message.encode_to_vec())
+            }
+        }
+    }
+
+    fn deserialize(&self, body: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::Avro(schema) => {
+                let value = apache_avro::from_avro_datum(schema, &mut Cursor::new(body), None)?;
+                Ok(serde_json::to_vec(&serde_json::Value::try_from(value)?)?)
+            }
+            Self::Protobuf(file, _) => {
+                let (indexes, body) = message_indexes(body)?;
+                let message = message_of(file, &indexes)?;
+                Ok(serde_json::to_vec(&DynamicMessage::decode(message, body)?)?)
+            }
+        }
+    }
+}
+
+/// The format of a schema, from its `schemaType` (`AVRO` if it has none).
+fn format_of(schema_type: Option<&str>) -> Result<SchemaFormat> {
+    match schema_type {
+        None | Some("AVRO") => Ok(SchemaFormat::Avro),
+        Some("PROTOBUF") => Ok(SchemaFormat::Protobuf),
+        Some(t) => bail!("unsupported schema type: '{}'", t),
+    }
+}
+
+/// Frames a serialized value w/ the id of its schema, and (`protobuf` only) the path to its
+/// message type — where `[0]` (i.e., the first one) is written as a single `0`.
+fn frame(id: u32, indexes: &[i64], serialized: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(serialized.len() + 6);
+    framed.push(MAGIC_BYTE);
+    framed.extend_from_slice(&id.to_be_bytes());
+    match indexes {
+        [] => {}
+        [0] => framed.push(0),
+        indexes => {
+            write_varint(&mut framed, indexes.len() as i64);
+            for index in indexes {
+                write_varint(&mut framed, *index);
+            }
+        }
+    }
+    framed.extend_from_slice(serialized);
+    framed
+}
+
+/// Splits a framed value into the id of its schema, and the rest of it.
+fn unframe(value: &[u8]) -> Result<(u32, &[u8])> {
+    match value {
+        [MAGIC_BYTE, a, b, c, d, rest @ ..] => Ok((u32::from_be_bytes([*a, *b, *c, *d]), rest)),
+        _ => bail!("the message isn't framed w/ the id of a schema"),
+    }
+}
+
+/// Splits the rest of a framed `protobuf` value into the path to its message type, and it.
+fn message_indexes(mut body: &[u8]) -> Result<(Vec<i64>, &[u8])> {
+    let count = read_varint(&mut body)?;
+    if count == 0 {
+        return Ok((vec![0], body));
+    }
+    let indexes = (0..count)
+        .map(|_| read_varint(&mut body))
+        .collect::<Result<Vec<_>>>()?;
+    Ok((indexes, body))
+}
+
+/// The message type at the path `indexes` from the top of `file` (i.e., a message type of its,
+/// then a nested one of that, and so on).
+fn message_of(file: &FileDescriptor, indexes: &[i64]) -> Result<MessageDescriptor> {
+    let not_found = || format!("the schema has no message type at {:?}", indexes);
+    let (first, nested) = indexes.split_first().with_context(not_found)?;
+    let mut message = file
+        .messages()
+        .nth(usize::try_from(*first)?)
+        .with_context(not_found)?;
+    for index in nested {
+        let child = message
+            .child_messages()
+            .nth(usize::try_from(*index)?)
+            .with_context(not_found)?;
+        message = child;
+    }
+    Ok(message)
+}
+
+/// Writes a zig-zag encoded varint (as the framing of `protobuf` messages does).
+fn write_varint(buf: &mut Vec<u8>, value: i64) {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    while zigzag >= 0x80 {
+        buf.push((zigzag as u8) | 0x80);
+        zigzag >>= 7;
+    }
+    buf.push(zigzag as u8);
+}
+
+fn read_varint(buf: &mut &[u8]) -> Result<i64> {
+    let mut zigzag = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = buf
+            .split_first()
+            .with_context(|| "the message's framing is truncated")?;
+        *buf = rest;
+        zigzag |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(((zigzag >> 1) as i64) ^ -((zigzag & 1) as i64));
+        }
+    }
+    bail!("the message's framing has an invalid varint")
+}
+
+fn secret(slight_state: &BasicState, key: &str) -> Result<String> {
+    let value = slight_runtime_configs::resolve(
+        &slight_state.secret_stores,
+        key,
+        &slight_state.config_toml_file_path,
+    )
+    .with_context(|| {
+        format!(
+            "failed to get '{}' secret using secret stores: {:?}",
+            key, slight_state.secret_stores
+        )
+    })?;
+    Ok(String::from_utf8(value)?)
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+
+    use super::{frame, message_indexes, unframe, Schema, SchemaFormat};
+
+    #[test]
+    fn framing_test() -> Result<()> {
+        let framed = frame(42, &[], b"avro");
+        assert_eq!(framed, [0, 0, 0, 0, 42, b'a', b'v', b'r', b'o']);
+        assert_eq!(unframe(&framed)?, (42, b"avro".as_slice()));
+        assert!(unframe(b"raw").is_err());
+
+        // the first message type is written as a single 0
+        let framed = frame(7, &[0], b"proto");
+        let (id, rest) = unframe(&framed)?;
+        assert_eq!(id, 7);
+        assert_eq!(message_indexes(rest)?, (vec![0], b"proto".as_slice()));
+
+        let framed = frame(7, &[1, 0, 70], b"proto");
+        let (_, rest) = unframe(&framed)?;
+        assert_eq!(
+            message_indexes(rest)?,
+            (vec![1, 0, 70], b"proto".as_slice())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn avro_test() -> Result<()> {
+        let schema = Schema::parse(
+            SchemaFormat::Avro,
+            r#"{
+                "type": "record",
+                "name": "Order",
+                "fields": [
+                    { "name": "id", "type": "long" },
+                    { "name": "note", "type": ["null", "string"], "default": null }
+                ]
+            }"#,
+        )?;
+        let serialized = schema.serialize(br#"{ "id": 42, "note": "rush" }"#)?;
+        let json = serde_json::from_slice::<serde_json::Value>(&schema.deserialize(&serialized)?)?;
+        assert_eq!(json, serde_json::json!({ "id": 42, "note": "rush" }));
+
+        assert!(schema.serialize(br#"{ "note": "no id" }"#).is_err());
+        assert!(Schema::parse(SchemaFormat::Avro, "{").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn protobuf_test() -> Result<()> {
+        let schema = Schema::parse(
+            SchemaFormat::Protobuf,
+            r#"
+            syntax = "proto3";
+            message Order {
+                int64 id = 1;
+                string note = 2;
+            }
+            "#,
+        )?;
+        let serialized = schema.serialize(br#"{ "id": "42", "note": "rush" }"#)?;
+        let framed = frame(1, &schema.message_indexes(), &serialized);
+        let (_, rest) = unframe(&framed)?;
+        let json = serde_json::from_slice::<serde_json::Value>(&schema.deserialize(rest)?)?;
+        assert_eq!(json, serde_json::json!({ "id": "42", "note": "rush" }));
+
+        assert!(Schema::parse(
+            SchemaFormat::Protobuf,
+            r#"syntax = "proto3"; import "google/protobuf/timestamp.proto"; message A {}"#
+        )
+        .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    env, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use crossbeam_channel::Sender;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use slight_events_api::{Event, EventBuilder, EventBuilderV10};
+use slight_runtime::support::Unsupported;
+use uuid::Uuid;
+
+/// The directory a ConfigMap is mounted at, unless `CONFIGMAP_DIR_ENV` says otherwise.
+pub const DEFAULT_CONFIGMAP_DIR: &str = "/etc/slight/configmap";
+
+/// The environment variable overriding the directory a ConfigMap is mounted at.
+pub const CONFIGMAP_DIR_ENV: &str = "SLIGHT_CONFIGMAP_DIR";
+
+/// The type of the events sent when a watched config changes.
+pub const CONFIGS_CHANGED_EVENT_TYPE: &str = "slight.configs.changed.v1";
+
+/// `ConfigMap` reads configs from a Kubernetes ConfigMap mounted as a volume (i.e., w/o
+/// `subPath`), where each of its keys is a file named after it, in `DEFAULT_CONFIGMAP_DIR`
+/// (or `CONFIGMAP_DIR_ENV`):
+/// ```yaml
+/// volumes:
+///   - name: config
+///     configMap:
+///       name: my-app
+/// containers:
+///   - volumeMounts:
+///       - name: config
+///         mountPath: /etc/slight/configmap
+///         readOnly: true
+/// ```
+///
+/// Configs are read from the mount every time, so they are as current as it is — but the
+/// kubelet only syncs mounted ConfigMaps periodically, so an update of one takes up to its
+/// sync period, plus the TTL of its cache (about a minute, or two, by default) to show up.
+/// ConfigMaps mounted w/ `subPath` are never updated.
+///
+/// They are read-only, as the mount is (i.e., they are changed through the ConfigMap).
+pub struct ConfigMap;
+
+impl ConfigMap {
+    /// The directory the ConfigMap is mounted at.
+    pub fn dir() -> PathBuf {
+        env::var_os(CONFIGMAP_DIR_ENV)
+            .map_or_else(|| PathBuf::from(DEFAULT_CONFIGMAP_DIR), PathBuf::from)
+    }
+
+    pub fn get(key: &str) -> Result<Vec<u8>> {
+        Self::get_from(&Self::dir(), key)
+    }
+
+    /// Gets the value of a config, or `None` if the ConfigMap doesn't have it.
+    pub fn get_opt(key: &str) -> Result<Option<Vec<u8>>> {
+        Self::get_opt_from(&Self::dir(), key)
+    }
+
+    pub fn set(key: &str, _value: &[u8]) -> Result<()> {
+        Err(anyhow::Error::new(Unsupported::new(
+            "configs.configmap",
+            "set",
+            "it's read-only (i.e., change the ConfigMap instead)",
+        ))
+        .context(format!("failed to set config '{}'", key)))
+    }
+
+    fn get_from(dir: &Path, key: &str) -> Result<Vec<u8>> {
+        Self::get_opt_from(dir, key)?.with_context(|| {
+            format!(
+                "config '{}' not found in the ConfigMap mounted at {}",
+                key,
+                dir.display()
+            )
+        })
+    }
+
+    fn get_opt_from(dir: &Path, key: &str) -> Result<Option<Vec<u8>>> {
+        let path = dir.join(valid_key(key)?);
+        read_if_exists(&path).with_context(|| {
+            format!(
+                "failed to read config '{}' from the ConfigMap mounted at {}",
+                key,
+                dir.display()
+            )
+        })
+    }
+
+    /// Watches `key`, sending an event (of type `CONFIGS_CHANGED_EVENT_TYPE`) whenever its
+    /// value changes, until the returned watcher is dropped.
+    ///
+    /// The kubelet updates a mounted ConfigMap all at once, by pointing its `..data` symlink
+    /// at a new directory, rather than by writing to the files of its keys, so it's the mount
+    /// that is watched, and the value of `key` that is compared to the last one seen.
+    pub fn watch(key: &str, sender: Arc<Mutex<Sender<Event>>>) -> Result<RecommendedWatcher> {
+        Self::watch_in(Self::dir(), key, sender)
+    }
+
+    fn watch_in(
+        dir: PathBuf,
+        key: &str,
+        sender: Arc<Mutex<Sender<Event>>>,
+    ) -> Result<RecommendedWatcher> {
+        let path = dir.join(valid_key(key)?);
+        let key = key.to_string();
+        let mut last = read_if_exists(&path)?;
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+                if let Err(e) = res {
+                    tracing::warn!("failed to watch config '{}': {}", key, e);
+                    return;
+                }
+                let value = match read_if_exists(&path) {
+                    Ok(value) => value,
+                    Err(e) => {
+                        tracing::warn!("failed to read config '{}': {}", key, e);
+                        return;
+                    }
+                };
+                if value == last {
+                    return;
+                }
+                last = value;
+                tracing::debug!("config '{}' changed", key);
+                match changed(&key, &path) {
+                    Ok(event) => {
+                        // the guest stopped listening if the receiver is gone
+                        let _ = sender.lock().unwrap().send(event);
+                    }
+                    Err(e) => tracing::error!("failed to build event: {:#}", e),
+                }
+            })?;
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+        Ok(watcher)
+    }
+}
+
+/// ConfigMap keys are made of alphanumerics, `-`, `_`, and `.` (and can't be `.`, or `..`), so
+/// another key can't point outside of the mount.
+fn valid_key(key: &str) -> Result<&str> {
+    let valid = !key.is_empty()
+        && key != "."
+        && key != ".."
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        bail!(
+            "invalid config key: '{}' (ConfigMap keys are made of alphanumerics, '-', '_', and '.')",
+            key
+        );
+    }
+    Ok(key)
+}
+
+fn read_if_exists(path: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn changed(key: &str, path: &Path) -> Result<Event> {
+    EventBuilderV10::new()
+        .id(Uuid::new_v4().to_string())
+        .source(path.display().to_string())
+        .ty(CONFIGS_CHANGED_EVENT_TYPE)
+        .time(Utc::now())
+        .data("application/json", serde_json::json!({ "key": key }))
+        .build()
+        .with_context(|| "failed to build event")
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::{
+        fs,
+        os::unix::fs::symlink,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use anyhow::Result;
+    use slight_events_api::AttributesReader;
+    use tempdir::TempDir;
+
+    use super::{ConfigMap, CONFIGS_CHANGED_EVENT_TYPE};
+
+    /// Mounts a ConfigMap like the kubelet does (i.e., w/ keys linked through `..data`).
+    fn mount(dir: &TempDir, version: &str, value: &str) -> Result<()> {
+        let data = dir.path().join(version);
+        fs::create_dir(&data)?;
+        fs::write(data.join("LOG_LEVEL"), value)?;
+        let staged = dir.path().join("..data_tmp");
+        symlink(version, &staged)?;
+        fs::rename(&staged, dir.path().join("..data"))?;
+        if !dir.path().join("LOG_LEVEL").exists() {
+            symlink("..data/LOG_LEVEL", dir.path().join("LOG_LEVEL"))?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn get_test() -> Result<()> {
+        let dir = TempDir::new("configmap")?;
+        mount(&dir, "..2024_01_01_00_00_00.1", "info")?;
+        assert_eq!(ConfigMap::get_from(dir.path(), "LOG_LEVEL")?, b"info");
+        assert!(ConfigMap::get_from(dir.path(), "MISSING").is_err());
+        assert_eq!(ConfigMap::get_opt_from(dir.path(), "MISSING")?, None);
+        assert!(ConfigMap::get_opt_from(dir.path(), "../etc/passwd").is_err());
+        assert!(ConfigMap::get_from(dir.path(), "../etc/passwd").is_err());
+        assert!(ConfigMap::set("LOG_LEVEL", b"debug").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn watch_test() -> Result<()> {
+        let dir = TempDir::new("configmap")?;
+        mount(&dir, "..2024_01_01_00_00_00.1", "info")?;
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let _watcher = ConfigMap::watch_in(
+            dir.path().to_path_buf(),
+            "LOG_LEVEL",
+            Arc::new(Mutex::new(sender)),
+        )?;
+
+        mount(&dir, "..2024_01_01_00_01_00.2", "debug")?;
+        let event = receiver.recv_timeout(Duration::from_secs(5))?;
+        assert_eq!(event.ty(), CONFIGS_CHANGED_EVENT_TYPE);
+        assert_eq!(ConfigMap::get_from(dir.path(), "LOG_LEVEL")?, b"debug");
+
+        // an update that doesn't change the value isn't one
+        mount(&dir, "..2024_01_01_00_02_00.3", "debug")?;
+        assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use std::env;
+use std::env::{self, VarError};
 
 use anyhow::Result;
 
@@ -9,6 +9,15 @@ impl EnvVars {
         Ok(env::var(key).map(|thing| thing.as_bytes().to_vec())?)
     }
 
+    /// Gets the value of a config, or `None` if it isn't set (an env var set to "" is).
+    pub fn get_opt(key: &str) -> Result<Option<Vec<u8>>> {
+        match env::var(key) {
+            Ok(thing) => Ok(Some(thing.as_bytes().to_vec())),
+            Err(VarError::NotPresent) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     pub fn set(key: &str, value: &[u8]) -> Result<()> {
         env::set_var(key, std::str::from_utf8(value)?);
         Ok(())
@@ -28,6 +37,17 @@ mod unittests {
         Ok(())
     }
 
+    #[test]
+    fn get_opt_test() -> Result<()> {
+        EnvVars::set("GET_OPT_TEST_EMPTY_KEY", b"")?;
+        assert_eq!(
+            EnvVars::get_opt("GET_OPT_TEST_EMPTY_KEY")?,
+            Some(Vec::new())
+        );
+        assert_eq!(EnvVars::get_opt("GET_OPT_TEST_MISSING_KEY")?, None);
+        Ok(())
+    }
+
     #[test]
     fn check_path_env_var_test() -> Result<()> {
         assert!(!EnvVars::get("PATH")?.is_empty());
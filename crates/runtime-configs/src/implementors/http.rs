@@ -0,0 +1,230 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use futures::executor::block_on;
+use reqwest::{header, StatusCode};
+use serde::Deserialize;
+use slight_runtime::{call::TimedOut, resource::BasicState, support::Unsupported};
+
+/// How often configs are refetched from the config server (at most).
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// `HttpConfigs` reads configs from a remote config server, which responds to a
+/// `GET` of `CONFIGS_HTTP_URL` w/ a JSON document like:
+/// ```json
+/// {
+///     "version": "42",
+///     "configs": {
+///         "<key>": "<value>"
+///     }
+/// }
+/// ```
+///
+/// The `CONFIGS_HTTP_URL`, and (optional) bearer `CONFIGS_HTTP_TOKEN` are
+/// read from the secret store.
+///
+/// Configs are cached, and, when read, refetched if they are older than `POLL_INTERVAL`.
+/// Changes are detected w/ the response's `ETag` (if any), or its `version`. If the
+/// config server is unreachable, the cached configs keep being served.
+#[derive(Debug, Clone)]
+pub struct HttpConfigs {
+    client: reqwest::Client,
+    url: String,
+    token: Option<String>,
+    cache: Arc<Mutex<Option<Cache>>>,
+}
+
+#[derive(Debug)]
+struct Cache {
+    configs: HashMap<String, Vec<u8>>,
+    etag: Option<String>,
+    version: Option<String>,
+    fetched_at: Instant,
+}
+
+#[derive(Deserialize)]
+struct ConfigsDocument {
+    version: Option<serde_json::Value>,
+    configs: HashMap<String, serde_json::Value>,
+}
+
+impl HttpConfigs {
+    pub fn new(slight_state: &BasicState) -> Result<Self> {
+        let url = secret(slight_state, "CONFIGS_HTTP_URL")?;
+        // not every config server requires auth
+        let token = secret(slight_state, "CONFIGS_HTTP_TOKEN").ok();
+        Ok(Self {
+            client: reqwest::Client::new(),
+            url,
+            token,
+            cache: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Result<Vec<u8>> {
+        self.get_opt(key)?
+            .with_context(|| format!("config '{}' not found at {}", key, self.url))
+    }
+
+    /// Gets the value of a config, or `None` if the config server doesn't have it.
+    pub fn get_opt(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let mut cache = self.cache.lock().unwrap();
+        let expired = cache
+            .as_ref()
+            .map_or(true, |c| c.fetched_at.elapsed() >= POLL_INTERVAL);
+        if expired {
+            if let Err(e) = self.refresh(&mut cache) {
+                if cache.is_none() {
+                    return Err(e.context(format!("failed to fetch configs from {}", self.url)));
+                }
+                tracing::warn!(
+                    "failed to refresh configs from {}, serving cached configs: {:#}",
+                    self.url,
+                    e
+                );
+            }
+        }
+
+        Ok(cache
+            .as_ref()
+            .unwrap() // note: this unwrap will never fail, as we either fetched, or bailed
+            .configs
+            .get(key)
+            .cloned())
+    }
+
+    pub fn set(&self, _key: &str, _value: &[u8]) -> Result<()> {
+        Err(Unsupported::new(
+            "configs.http",
+            "set",
+            "it's read-only (i.e., configs must be changed on the config server)",
+        )
+        .into())
+    }
+
+    fn refresh(&self, cache: &mut Option<Cache>) -> Result<()> {
+        let mut req = self.client.get(&self.url);
+        if let Some(token) = &self.token {
+            req = req.bearer_auth(token);
+        }
+        if let Some(etag) = cache.as_ref().and_then(|c| c.etag.as_ref()) {
+            req = req.header(header::IF_NONE_MATCH, etag);
+        }
+        let res = block_on(req.send()).map_err(timed_out)?;
+
+        if res.status() == StatusCode::NOT_MODIFIED {
+            if let Some(c) = cache.as_mut() {
+                c.fetched_at = Instant::now();
+                return Ok(());
+            }
+        }
+        let res = res.error_for_status()?;
+        let etag = res
+            .headers()
+            .get(header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        let (version, configs) = parse_document(&block_on(res.bytes()).map_err(timed_out)?)?;
+
+        if let Some(c) = cache.as_mut() {
+            if version.is_some() && c.version == version {
+                c.etag = etag;
+                c.fetched_at = Instant::now();
+                return Ok(());
+            }
+        }
+        tracing::info!(
+            "fetched configs (version: {}) from {}",
+            version.as_deref().unwrap_or("none"),
+            self.url
+        );
+        *cache = Some(Cache {
+            configs,
+            etag,
+            version,
+            fetched_at: Instant::now(),
+        });
+        Ok(())
+    }
+}
+
+/// Fails w/ `TimedOut` if the config server didn't respond in time, so the guest can tell.
+fn timed_out(e: reqwest::Error) -> anyhow::Error {
+    if e.is_timeout() {
+        anyhow::Error::new(TimedOut(e.to_string()))
+    } else {
+        e.into()
+    }
+}
+
+fn secret(slight_state: &BasicState, key: &str) -> Result<String> {
+    let value = crate::resolve(
+        &slight_state.secret_stores,
+        key,
+        &slight_state.config_toml_file_path,
+    )
+    .with_context(|| {
+        format!(
+            "failed to get '{}' secret using secret stores: {:?}",
+            key, slight_state.secret_stores
+        )
+    })?;
+    Ok(String::from_utf8(value)?)
+}
+
+/// Parses a configs document into its version, and configs.
+///
+/// Values that aren't strings are kept as their JSON representation.
+fn parse_document(body: &[u8]) -> Result<(Option<String>, HashMap<String, Vec<u8>>)> {
+    let document = serde_json::from_slice::<ConfigsDocument>(body)
+        .with_context(|| "config server responded w/ an invalid configs document")?;
+    let configs = document
+        .configs
+        .into_iter()
+        .map(|(key, value)| (key, json_to_string(value).into_bytes()))
+        .collect();
+    Ok((document.version.map(json_to_string), configs))
+}
+
+fn json_to_string(value: serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s,
+        v => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+
+    use super::parse_document;
+
+    #[test]
+    fn parse_document_test() -> Result<()> {
+        let (version, configs) = parse_document(
+            br#"{ "version": 42, "configs": { "greeting": "hello", "retries": 3 } }"#,
+        )?;
+        assert_eq!(version.as_deref(), Some("42"));
+        assert_eq!(configs["greeting"], b"hello");
+        assert_eq!(configs["retries"], b"3");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_document_without_version_test() -> Result<()> {
+        let (version, configs) = parse_document(br#"{ "configs": {} }"#)?;
+        assert!(version.is_none());
+        assert!(configs.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_invalid_document_test() {
+        assert!(parse_document(br#"{ "greeting": "hello" }"#).is_err());
+        assert!(parse_document(b"not json").is_err());
+    }
+}
@@ -1,2 +1,4 @@
+pub mod configmap;
 pub mod envvars;
+pub mod http;
 pub mod usersecrets;
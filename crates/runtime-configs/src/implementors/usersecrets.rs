@@ -11,6 +11,14 @@ pub struct UserSecrets;
 
 impl UserSecrets {
     pub fn get(key: &str, toml_file_path: &str) -> Result<Vec<u8>> {
+        match Self::get_opt(key, toml_file_path)? {
+            Some(value) => Ok(value),
+            None => bail!("failed because this secret isn't encrypted in the toml file"),
+        }
+    }
+
+    /// Gets the value of a config, or `None` if it isn't in the toml file.
+    pub fn get_opt(key: &str, toml_file_path: &str) -> Result<Option<Vec<u8>>> {
         // check if encryption key env var is present
         let encryption_key = if let Ok(s) = get_key() {
             s
@@ -22,29 +30,20 @@ impl UserSecrets {
         let toml_file_path = toml_file_path;
         let toml_file_contents = std::fs::read_to_string(toml_file_path)?;
         let toml = toml::from_str::<TomlFile>(&toml_file_contents)?;
-        if toml.secret_settings.is_none() {
-            bail!("failed because toml file has no secrets");
-        }
-
-        // get env var encryption key
-        let pos = toml
+        let value = match toml
             .secret_settings
-            .as_ref()
-            .unwrap() // note: this unwrap will never fail, so it's ok
             .iter()
-            .position(|s| s.name == key);
-
-        let value = if let Some(value) = pos {
-            &toml.secret_settings.as_ref().unwrap()[value].value
-            // ^^^ note: the unwrap cannot fail
-        } else {
-            // if it isn't, we will just create new
-            bail!("failed because this secret isn't encrypted in the toml file")
+            .flatten()
+            .find(|s| s.name == key)
+        {
+            Some(secret) => &secret.value,
+            None => return Ok(None),
         };
 
         // decrypt key and return value
         let sc = ShortCrypt::new(encryption_key);
-        sc.decrypt_url_component(&value)
+        sc.decrypt_url_component(value)
+            .map(Some)
             .map_err(|err| anyhow::anyhow!(err))
     }
 
@@ -76,6 +75,8 @@ mod unittests {
         let toml_file_path = file_path.to_str().unwrap();
         UserSecrets::set("key", "value".as_bytes(), toml_file_path)?;
         assert!(UserSecrets::get("key", toml_file_path).is_ok());
+        assert!(UserSecrets::get_opt("missing", toml_file_path)?.is_none());
+        assert!(UserSecrets::get("missing", toml_file_path).is_err());
         Ok(())
     }
 }
@@ -3,12 +3,34 @@ pub mod implementors;
 /// The `SCHEME_NAME` defines the name under which a resource is
 /// identifiable by in a `ResourceMap`.
 const SCHEME_NAME: &str = "configs";
+/// The operations guests can retry if they time out (see `BasicState::with_idempotent_operations`).
+const IDEMPOTENT_OPERATIONS: &[&str] = &["get", "get-or-default"];
+/// The operations that only read, which audits of writes only leave out (see
+/// `BasicState::with_read_operations`).
+const READ_OPERATIONS: &[&str] = &["get", "get-or-default", "watch"];
+/// The operations implementors don't support (i.e., always fail w/ `Unsupported`), by
+/// implementor, which guests that declare they call them are checked against (see
+/// `slight_runtime::manifest`), and which are left out of what's advertised to them (see
+/// `slight_runtime::support`) — configs of read-only implementors can't be set.
+pub const UNSUPPORTED_OPERATIONS: &[(&str, &[&str])] =
+    &[("configs.http", &["set"]), ("configs.configmap", &["set"])];
 
-use anyhow::Result;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use crossbeam_channel::Sender;
+use notify::RecommendedWatcher;
+use slight_events_api::Event;
 use uuid::Uuid;
 
-use implementors::{envvars::EnvVars, usersecrets::UserSecrets};
-use slight_runtime::{impl_resource, resource::BasicState};
+use implementors::{
+    configmap::ConfigMap, envvars::EnvVars, http::HttpConfigs, usersecrets::UserSecrets,
+};
+use slight_runtime::{credentials::Credential, impl_resource, resource::BasicState};
 
 /// It is mandatory to `use <interface>::*` due to `impl_resource!`.
 /// That is because `impl_resource!` accesses the `crate`'s
@@ -16,7 +38,7 @@ use slight_runtime::{impl_resource, resource::BasicState};
 use configs::*;
 wit_bindgen_wasmtime::export!("../../wit/configs.wit");
 wit_error_rs::impl_error!(configs::Error);
-wit_error_rs::impl_from!(anyhow::Error, configs::Error::ErrorWithDescription);
+slight_runtime::impl_from_anyhow!(configs::Error);
 
 /// The `Configs` structure is what will implement the `configs::Configs` trait
 /// coming from the generated code of off `configs.wit`.
@@ -41,29 +63,64 @@ impl_resource!(
 ///     dispatch to a specific implementor's implentation, and
 ///     - the `slight_state` (of type `BasicState`) that contains common
 ///     things received from the slight binary (i.e., the `resource_map`,
-///     the `config_type`, and the `config_toml_file_path`).
+///     the `config_type`, and the `config_toml_file_path`), and
+///     - the `http_configs` client (and cache), shared by all configs objects
+///     opened w/ the `configs.http` implementor, and
+///     - the `defaults` of configs, declared in the slightfile, which are returned
+///     when they are absent.
 pub struct ConfigsState {
     pub configs_implementor: String,
     pub slight_state: BasicState,
+    http_configs: Option<HttpConfigs>,
+    defaults: HashMap<String, String>,
 }
 
 impl ConfigsState {
     pub fn new(configs_implementor: String, slight_state: BasicState) -> Self {
         Self {
             configs_implementor,
-            slight_state,
+            slight_state: slight_state
+                .with_idempotent_operations(IDEMPOTENT_OPERATIONS)
+                .with_read_operations(READ_OPERATIONS),
+            http_configs: None,
+            defaults: HashMap::new(),
         }
     }
+
+    pub fn with_defaults(mut self, defaults: HashMap<String, String>) -> Self {
+        self.defaults = defaults;
+        self
+    }
+
+    /// The default of a config (i.e., the value it gets when it's absent) declared in the
+    /// slightfile, if any — which wins over the guest's own (see `configs_get_or_default`).
+    fn default_of(&self, key: &str) -> Option<Vec<u8>> {
+        self.defaults
+            .get(key)
+            .map(|value| value.as_bytes().to_vec())
+    }
 }
 
 impl configs::Configs for Configs {
     type Configs = ConfigsInner;
 
     fn configs_open(&mut self) -> Result<Self::Configs, configs::Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
         // populate our inner configs object w/ the state received from `slight`
         // (i.e., what type of configs implementor we are using), and the assigned
         // name of the object.
-        let inner = Self::Configs::new(&self.host_state.configs_implementor);
+        let state = &mut self.host_state;
+        let http_configs = match ConfigsImplementor::from(state.configs_implementor.as_str()) {
+            // the client (and so, its cache) is shared by every configs object
+            ConfigsImplementor::Http => {
+                if state.http_configs.is_none() {
+                    state.http_configs = Some(HttpConfigs::new(&state.slight_state)?);
+                }
+                state.http_configs.clone()
+            }
+            _ => None,
+        };
+        let inner = Self::Configs::new(&self.host_state.configs_implementor, http_configs);
 
         self.host_state
             .slight_state
@@ -76,11 +133,40 @@ impl configs::Configs for Configs {
     }
 
     fn configs_get(&mut self, self_: &Self::Configs, key: &str) -> Result<Vec<u8>, configs::Error> {
-        Ok(match &self_.configs_implementor {
-            ConfigsImplementor::EnvVars => EnvVars::get(key)?,
-            ConfigsImplementor::UserSecrets => {
-                UserSecrets::get(key, &self.host_state.slight_state.config_toml_file_path)?
-            }
+        let slight_state = &self.host_state.slight_state;
+        slight_state.instrument(SCHEME_NAME, "get", key, || {
+            Ok(slight_state
+                .last_known_good
+                .read(SCHEME_NAME, key.as_bytes(), || {
+                    match self_.get_opt(key, slight_state)? {
+                        Some(value) => Ok(value),
+                        None => self.host_state.default_of(key).with_context(|| {
+                            format!(
+                                "config '{}' not found in {} (and it has no default)",
+                                key,
+                                String::from(self_.configs_implementor.clone())
+                            )
+                        }),
+                    }
+                })?)
+        })
+    }
+
+    fn configs_get_or_default(
+        &mut self,
+        self_: &Self::Configs,
+        key: &str,
+        default_value: PayloadParam<'_>,
+    ) -> Result<Vec<u8>, configs::Error> {
+        let host_state = &self.host_state;
+        let slight_state = &host_state.slight_state;
+        slight_state.instrument(SCHEME_NAME, "get-or-default", key, || {
+            Ok(match self_.get_opt(key, slight_state)? {
+                Some(value) => value,
+                None => host_state
+                    .default_of(key)
+                    .unwrap_or_else(|| default_value.to_vec()),
+            })
         })
     }
 
@@ -90,16 +176,34 @@ impl configs::Configs for Configs {
         key: &str,
         value: PayloadParam<'_>,
     ) -> Result<(), configs::Error> {
-        match &self_.configs_implementor {
-            ConfigsImplementor::EnvVars => EnvVars::set(key, value)?,
-            ConfigsImplementor::UserSecrets => UserSecrets::set(
-                key,
-                value,
-                &self.host_state.slight_state.config_toml_file_path,
-            )?,
-        };
+        let slight_state = &self.host_state.slight_state;
+        slight_state.instrument(SCHEME_NAME, "set", key, || {
+            match &self_.configs_implementor {
+                ConfigsImplementor::EnvVars => EnvVars::set(key, value)?,
+                ConfigsImplementor::UserSecrets => {
+                    UserSecrets::set(key, value, &slight_state.config_toml_file_path)?
+                }
+                ConfigsImplementor::Http => self_.http_configs()?.set(key, value)?,
+                ConfigsImplementor::ConfigMap => ConfigMap::set(key, value)?,
+            };
+            slight_state
+                .last_known_good
+                .remember(SCHEME_NAME, key.as_bytes(), value);
 
-        Ok(())
+            Ok(())
+        })
+    }
+
+    fn configs_watch(
+        &mut self,
+        self_: &Self::Configs,
+        key: &str,
+    ) -> Result<Observable, configs::Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "watch")?;
+        Ok(Observable {
+            rd: self_.resource_descriptor.clone(),
+            key: key.to_string(),
+        })
     }
 }
 
@@ -107,41 +211,83 @@ impl configs::Configs for Configs {
 /// implementation.
 ///
 /// It holds:
-///     - a `configs_implementor` (i.e., a variant `ConfigsImplementor` `enum`), and
+///     - a `configs_implementor` (i.e., a variant `ConfigsImplementor` `enum`),
+///     - the `http_configs` client (only for the `configs.http` implementor),
 ///     - a `resource_descriptor` (i.e., an UUID that uniquely identifies
-///     resource's instance).
+///     resource's instance), and
+///     - the `watchers` of the configs being watched (only for the
+///     `configs.configmap` implementor), which are kept alive w/ it.
 ///
 /// It must `derive`:
 ///     - `Debug` due to a constraint on the associated type.
 ///     - `Clone` because the `ResourceMap` it will be added onto,
-///     must own its' data.
+///     must own its data.
 ///
 /// It must be public because the implementation of `configs::Configs` cannot leak
 /// a private type.
 #[derive(Debug, Clone)]
 pub struct ConfigsInner {
     configs_implementor: ConfigsImplementor,
+    http_configs: Option<HttpConfigs>,
     resource_descriptor: String,
+    watchers: Vec<Arc<Mutex<RecommendedWatcher>>>,
 }
 
 impl ConfigsInner {
-    fn new(configs_implementor: &str) -> Self {
+    fn new(configs_implementor: &str, http_configs: Option<HttpConfigs>) -> Self {
         Self {
             configs_implementor: configs_implementor.into(),
+            http_configs,
             resource_descriptor: Uuid::new_v4().to_string(),
+            watchers: Vec::new(),
         }
     }
+
+    /// Gets the value of a config, or `None` if it's absent (which a config that is present,
+    /// but empty isn't).
+    fn get_opt(&self, key: &str, slight_state: &BasicState) -> Result<Option<Vec<u8>>> {
+        match &self.configs_implementor {
+            ConfigsImplementor::EnvVars => EnvVars::get_opt(key),
+            ConfigsImplementor::UserSecrets => {
+                UserSecrets::get_opt(key, &slight_state.config_toml_file_path)
+            }
+            ConfigsImplementor::Http => self.http_configs()?.get_opt(key),
+            ConfigsImplementor::ConfigMap => ConfigMap::get_opt(key),
+        }
+    }
+
+    fn http_configs(&self) -> Result<&HttpConfigs> {
+        self.http_configs
+            .as_ref()
+            .with_context(|| "internal error: configs.http was opened w/o a client")
+    }
 }
 
-impl slight_runtime::resource::Watch for ConfigsInner {}
+impl slight_runtime::resource::Watch for ConfigsInner {
+    fn watch(&mut self, key: &str, sender: Arc<Mutex<Sender<Event>>>) -> Result<()> {
+        match self.configs_implementor {
+            ConfigsImplementor::ConfigMap => {
+                let watcher = ConfigMap::watch(key, sender)?;
+                self.watchers.push(Arc::new(Mutex::new(watcher)));
+                Ok(())
+            }
+            _ => bail!(
+                "failed to watch config '{}': only configs.configmap supports watching configs",
+                key
+            ),
+        }
+    }
+}
 
 /// This defines the available implementor implementations for the `Configs` interface.
 ///
-/// As per its' usage in `ConfigsInner`, it must `derive` `Debug`, and `Clone`.
+/// As per its usage in `ConfigsInner`, it must `derive` `Debug`, and `Clone`.
 #[derive(Debug, Clone)]
 pub enum ConfigsImplementor {
     EnvVars,
     UserSecrets, // user creates configs at compile time that are encrypted and stored in their slightfile
+    Http,        // configs are fetched from a remote config server
+    ConfigMap,   // configs are read from a mounted Kubernetes ConfigMap
 }
 
 impl From<ConfigsImplementor> for String {
@@ -149,6 +295,8 @@ impl From<ConfigsImplementor> for String {
         match from_ct {
             ConfigsImplementor::UserSecrets => "configs.usersecrets".to_string(),
             ConfigsImplementor::EnvVars => "configs.envvars".to_string(),
+            ConfigsImplementor::Http => "configs.http".to_string(),
+            ConfigsImplementor::ConfigMap => "configs.configmap".to_string(),
         }
     }
 }
@@ -158,6 +306,8 @@ impl From<&str> for ConfigsImplementor {
         match from_str {
             "configs.usersecrets" => ConfigsImplementor::UserSecrets,
             "configs.envvars" => ConfigsImplementor::EnvVars,
+            "configs.http" => ConfigsImplementor::Http,
+            "configs.configmap" => ConfigsImplementor::ConfigMap,
             _ => panic!("Unknown config type: {}", from_str),
         }
     }
@@ -174,12 +324,127 @@ pub fn get(config_type: &str, key: &str, toml_file_path: &str) -> Result<Vec<u8>
     match config_type.into() {
         ConfigsImplementor::EnvVars => Ok(EnvVars::get(key)?),
         ConfigsImplementor::UserSecrets => Ok(UserSecrets::get(key, toml_file_path)?),
+        // the config server's url, and token are themselves read from the secret store
+        ConfigsImplementor::Http => bail!("configs.http can't be used as a secret store"),
+        ConfigsImplementor::ConfigMap => Ok(ConfigMap::get(key)?),
     }
 }
 
+/// Looks up `key` in each of `secret_stores` in order, returning the value from
+/// the first store that has it.
+pub fn resolve(secret_stores: &[String], key: &str, toml_file_path: &str) -> Result<Vec<u8>> {
+    tracing::debug!(
+        "resolving secret '{}' from secret stores in order: {:?}",
+        key,
+        secret_stores
+    );
+    for secret_store in secret_stores {
+        match get(secret_store, key, toml_file_path) {
+            Ok(value) => {
+                tracing::debug!("resolved secret '{}' from '{}'", key, secret_store);
+                return Ok(value);
+            }
+            Err(e) => {
+                tracing::debug!("secret '{}' not found in '{}': {}", key, secret_store, e)
+            }
+        }
+    }
+
+    bail!(
+        "secret '{}' was not found in any of the secret stores: {:?}",
+        key,
+        secret_stores
+    )
+}
+
+/// Looks up the setting `key` of a capability in its secret stores, as a string, w/o caching
+/// it (e.g., to describe its backend, see `slight_runtime::describe`).
+pub fn setting(slight_state: &BasicState, key: &str) -> Result<String> {
+    let value = resolve(
+        &slight_state.secret_stores,
+        key,
+        &slight_state.config_toml_file_path,
+    )?;
+    Ok(String::from_utf8(value)?)
+}
+
+/// Resolves the credential `key` from the secret stores of `slight_state`, through its shared
+/// `credentials`, so that it's refetched (and, if rotated, picked up) before it expires.
+///
+/// A credential's time to live (in seconds) is read from the `<key>_TTL_SECS` secret, if any
+/// of the secret stores has it.
+pub fn credential(slight_state: &BasicState, key: &str) -> Result<Vec<u8>> {
+    slight_state.credentials.get(key, || {
+        let value = resolve(
+            &slight_state.secret_stores,
+            key,
+            &slight_state.config_toml_file_path,
+        )?;
+        let time_to_live = match resolve(
+            &slight_state.secret_stores,
+            &format!("{}_TTL_SECS", key),
+            &slight_state.config_toml_file_path,
+        ) {
+            Ok(ttl) => Some(Duration::from_secs(
+                String::from_utf8(ttl)?
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("'{}_TTL_SECS' is not a number of seconds", key))?,
+            )),
+            Err(_) => None,
+        };
+        Ok(Credential {
+            value,
+            time_to_live,
+        })
+    })
+}
+
 pub fn set(config_type: &str, key: &str, value: &[u8], toml_file_path: &str) -> Result<()> {
     match config_type.into() {
         ConfigsImplementor::EnvVars => Ok(EnvVars::set(key, value)?),
         ConfigsImplementor::UserSecrets => Ok(UserSecrets::set(key, value, toml_file_path)?),
+        ConfigsImplementor::Http => bail!("configs.http can't be used as a secret store"),
+        ConfigsImplementor::ConfigMap => Ok(ConfigMap::set(key, value)?),
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+
+    use super::resolve;
+
+    #[test]
+    fn resolve_uses_first_store_with_key_test() -> Result<()> {
+        std::env::set_var("RESOLVE_TEST_KEY", "from-envvars");
+        let stores = vec![
+            "configs.envvars".to_string(),
+            "configs.usersecrets".to_string(),
+        ];
+        // the envvars store has the key, so usersecrets (and its
+        // non-existent slightfile) is never consulted
+        let value = resolve(&stores, "RESOLVE_TEST_KEY", "non-existent.toml")?;
+        assert_eq!(value, b"from-envvars");
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_falls_through_missing_stores_test() -> Result<()> {
+        std::env::set_var("RESOLVE_FALLBACK_TEST_KEY", "fallback");
+        let stores = vec![
+            "configs.usersecrets".to_string(),
+            "configs.envvars".to_string(),
+        ];
+        let value = resolve(&stores, "RESOLVE_FALLBACK_TEST_KEY", "non-existent.toml")?;
+        assert_eq!(value, b"fallback");
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_fails_when_no_store_has_key_test() {
+        let stores = vec!["configs.envvars".to_string()];
+        assert!(resolve(&stores, "RESOLVE_MISSING_TEST_KEY", "non-existent.toml").is_err());
+        assert!(resolve(&[], "RESOLVE_MISSING_TEST_KEY", "non-existent.toml").is_err());
     }
 }
@@ -0,0 +1,159 @@
+mod shutdown;
+
+/// The `SCHEME_NAME` defines the name under which a resource is
+/// identifiable by in a `ResourceMap`.
+const SCHEME_NAME: &str = "runtime_control";
+
+use anyhow::Result;
+use uuid::Uuid;
+
+use slight_runtime::{impl_resource, resource::BasicState, support::Support};
+
+pub use shutdown::{Shutdown, SHUTDOWN};
+
+/// It is mandatory to `use <interface>::*` due to `impl_resource!`.
+/// That is because `impl_resource!` accesses the `crate`'s
+/// `add_to_linker`, and not the `<interface>::add_to_linker` directly.
+use runtime_control::*;
+wit_bindgen_wasmtime::export!("../../wit/runtime-control.wit");
+wit_error_rs::impl_error!(runtime_control::Error);
+slight_runtime::impl_from_anyhow!(runtime_control::Error);
+
+/// The `RuntimeControl` structure is what will implement the `runtime_control::RuntimeControl`
+/// trait coming from the generated code of off `runtime-control.wit`.
+///
+/// It maintains a `host_state`.
+pub struct RuntimeControl {
+    host_state: RuntimeControlState,
+}
+
+impl_resource!(
+    RuntimeControl,
+    runtime_control::RuntimeControlTables<RuntimeControl>,
+    RuntimeControlState,
+    SCHEME_NAME.to_string()
+);
+
+/// This is the type of the `host_state` property from our `RuntimeControl` structure.
+///
+/// It holds:
+///     - the `slight_state` (of type `BasicState`) that contains common
+///     things received from the slight binary (i.e., the `resource_map`,
+///     the `config_type`, and the `config_toml_file_path`), and
+///     - the `shutdown` of the app, shared through the `resource_map` (see `Shutdown::install`), and
+///     - what the capabilities the slightfile links support (see `Support`).
+///
+/// Like the platform capability, there is only one runtime to control, so there is no
+/// implementor to choose from.
+pub struct RuntimeControlState {
+    slight_state: BasicState,
+    shutdown: Option<Shutdown>,
+    capabilities: Vec<Support>,
+}
+
+impl RuntimeControlState {
+    pub fn new(slight_state: BasicState) -> Self {
+        let shutdown = slight_state
+            .resource_map
+            .lock()
+            .unwrap()
+            .find_shared::<Shutdown>(SHUTDOWN);
+        Self {
+            slight_state,
+            shutdown,
+            capabilities: Vec::new(),
+        }
+    }
+
+    /// The capabilities the slightfile links, as advertised to the guest (see `capabilities`).
+    pub fn with_capabilities(mut self, capabilities: Vec<Support>) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+}
+
+impl runtime_control::RuntimeControl for RuntimeControl {
+    type RuntimeControl = RuntimeControlInner;
+
+    fn runtime_control_open(&mut self) -> Result<Self::RuntimeControl, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
+        let inner = Self::RuntimeControl::new();
+
+        self.host_state
+            .slight_state
+            .resource_map
+            .lock()
+            .unwrap()
+            .set(inner.resource_descriptor.clone(), Box::new(inner.clone()));
+
+        Ok(inner)
+    }
+
+    fn runtime_control_shutdown(
+        &mut self,
+        _self_: &Self::RuntimeControl,
+        exit_code: i32,
+    ) -> Result<(), Error> {
+        let shutdown = &self.host_state.shutdown;
+        self.host_state.slight_state.instrument(
+            SCHEME_NAME,
+            "shutdown",
+            &exit_code.to_string(),
+            || {
+                shutdown
+                    .as_ref()
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("internal error: the app was run w/o a shutdown to request")
+                    })?
+                    .request(exit_code);
+                Ok(())
+            },
+        )
+    }
+
+    fn runtime_control_capabilities(
+        &mut self,
+        _self_: &Self::RuntimeControl,
+    ) -> Result<Vec<Capability>, Error> {
+        let capabilities = &self.host_state.capabilities;
+        self.host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "capabilities", "", || {
+                Ok(capabilities
+                    .iter()
+                    .map(|support| Capability {
+                        name: support.name.clone(),
+                        operations: support.operations.clone(),
+                    })
+                    .collect())
+            })
+    }
+}
+
+/// This is the type of the associated type coming from the `runtime_control::RuntimeControl`
+/// trait implementation.
+///
+/// It holds a `resource_descriptor` (i.e., an UUID that uniquely identifies
+/// resource's instance).
+///
+/// It must `derive`:
+///     - `Debug` due to a constraint on the associated type.
+///     - `Clone` because the `ResourceMap` it will be added onto,
+///     must own its data.
+///
+/// It must be public because the implementation of `runtime_control::RuntimeControl` cannot
+/// leak a private type.
+#[derive(Debug, Clone)]
+pub struct RuntimeControlInner {
+    resource_descriptor: String,
+}
+
+impl RuntimeControlInner {
+    fn new() -> Self {
+        Self {
+            resource_descriptor: Uuid::new_v4().to_string(),
+        }
+    }
+}
+
+impl slight_runtime::resource::Watch for RuntimeControlInner {}
@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use slight_runtime::resource::ResourceMap;
+use tokio::sync::watch;
+
+/// The name the `Shutdown` of an app is shared under in its `StateTable`.
+pub const SHUTDOWN: &str = "slight.shutdown";
+
+/// `Shutdown` is how the guest of an app asks the runtime to shut it down (i.e., w/ the
+/// `runtime_control` capability), which `run_app` waits for alongside CTRL+C.
+///
+/// It keeps the exit code of the first request, so a request made before anyone waits for
+/// one (e.g., during `_start`) isn't missed.
+#[derive(Debug, Clone)]
+pub struct Shutdown(Arc<watch::Sender<Option<i32>>>);
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(None).0))
+    }
+}
+
+impl Shutdown {
+    /// Shares the shutdown w/ the capabilities of an app, through its `resource_map`.
+    ///
+    /// It must be installed before the capabilities are linked.
+    pub fn install(&self, resource_map: &ResourceMap) -> Result<()> {
+        resource_map
+            .lock()
+            .unwrap()
+            .shared(SHUTDOWN, || self.clone())?;
+        Ok(())
+    }
+
+    /// Requests a shutdown, w/ `exit_code`, unless one was requested already.
+    pub fn request(&self, exit_code: i32) {
+        let requested = self.0.send_if_modified(|requested| {
+            if requested.is_some() {
+                return false;
+            }
+            *requested = Some(exit_code);
+            true
+        });
+        if requested {
+            tracing::info!("the guest requested a shutdown, w/ exit code {}", exit_code);
+        }
+    }
+
+    /// The exit code of the shutdown, if one was requested.
+    pub fn exit_code(&self) -> Option<i32> {
+        *self.0.borrow()
+    }
+
+    /// Waits for a shutdown to be requested, returning its exit code.
+    pub async fn requested(&self) -> i32 {
+        let mut receiver = self.0.subscribe();
+        loop {
+            if let Some(exit_code) = *receiver.borrow_and_update() {
+                return exit_code;
+            }
+            // the sender lives as long as we do, so this can't fail
+            let _ = receiver.changed().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::Duration;
+
+    use super::Shutdown;
+
+    #[tokio::test]
+    async fn request_test() {
+        let shutdown = Shutdown::default();
+        assert_eq!(shutdown.exit_code(), None);
+
+        let waiting = shutdown.clone();
+        let waiter = tokio::spawn(async move { waiting.requested().await });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        shutdown.request(3);
+        assert_eq!(waiter.await.unwrap(), 3);
+
+        // the first request wins, and is seen by those waiting after it
+        shutdown.request(0);
+        assert_eq!(shutdown.exit_code(), Some(3));
+        assert_eq!(shutdown.requested().await, 3);
+    }
+}
@@ -0,0 +1,227 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+
+use crate::error_kind::ErrorKind;
+
+/// The target audit records are logged under, so they can be routed apart from the host's
+/// other logs (e.g., w/ `RUST_LOG=slight::audit=info`, or shipped w/ the `log_sink`).
+pub const AUDIT_TARGET: &str = "slight::audit";
+
+/// The operations that are always audited, whatever the filter, and sampling say, as they're
+/// what an audit is for (i.e., wiping data) — so are calls denied by the grants.
+const SECURITY_OPERATIONS: &[&str] = &["clear"];
+
+/// What capability calls are audited (see `Audit`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditSettings {
+    /// only audit writes (i.e., the operations capabilities don't declare as reads, see
+    /// `BasicState::with_read_operations`)
+    pub writes_only: bool,
+    /// only audit the calls that fail
+    pub failures_only: bool,
+    /// the capabilities audited, by name (e.g., `kv.azblob`), or scheme (e.g., `kv`) — all of
+    /// them, if `None`
+    pub capabilities: Option<Vec<String>>,
+    /// the share of reads audited, from 0 (none) to 1 (all of them)
+    pub read_sample_rate: f64,
+}
+
+impl Default for AuditSettings {
+    fn default() -> Self {
+        Self {
+            writes_only: false,
+            failures_only: false,
+            capabilities: None,
+            read_sample_rate: 1.0,
+        }
+    }
+}
+
+impl AuditSettings {
+    /// Checks the `read_sample_rate` is w/in 0, and 1, and that it has reads to sample.
+    pub fn validate(self) -> Result<Self> {
+        if !(0.0..=1.0).contains(&self.read_sample_rate) {
+            bail!(
+                "invalid read_sample_rate: {} (expected 0 to 1)",
+                self.read_sample_rate
+            );
+        }
+        if self.writes_only && self.read_sample_rate < 1.0 {
+            bail!("invalid read_sample_rate: reads aren't audited w/ writes_only");
+        }
+        if self.capabilities.as_ref().is_some_and(Vec::is_empty) {
+            bail!("invalid capabilities: audit at least one, or leave it out to audit all");
+        }
+        Ok(self)
+    }
+
+    fn audits(&self, capability: &str) -> bool {
+        let scheme = capability.split('.').next().unwrap_or(capability);
+        self.capabilities.as_ref().is_none_or(|capabilities| {
+            capabilities
+                .iter()
+                .any(|audited| audited == capability || audited == scheme)
+        })
+    }
+}
+
+/// `Audit` records the capability calls of an app (its capability, operation, target, and
+/// outcome) under `AUDIT_TARGET`, filtered, and sampled as per its settings — calls denied by
+/// the grants, and `SECURITY_OPERATIONS` are exempt from both, and always recorded.
+///
+/// Sampling is deterministic, like the trace's (see `trace::Sampler`): of every
+/// `1 / read_sample_rate` reads, the first is audited.
+///
+/// It's a handle shared by all capabilities of an app, and kept across restarts (so sampling
+/// holds over a whole run), and, until it's configured, nothing is audited.
+#[derive(Clone, Debug, Default)]
+pub struct Audit(Arc<Mutex<AuditState>>);
+
+#[derive(Debug, Default)]
+struct AuditState {
+    settings: Option<AuditSettings>,
+    /// how many reads that passed the filter have been seen
+    reads_seen: u64,
+}
+
+impl Audit {
+    /// Audits calls as per `settings`, or none, if there are none.
+    pub fn configure(&self, settings: Option<AuditSettings>) {
+        *self.0.lock().unwrap() = AuditState {
+            settings,
+            reads_seen: 0,
+        };
+    }
+
+    /// The audit of the calls of `capability` (i.e., its name), or `None` if nothing is
+    /// audited, so calls of apps w/o an audit don't pay for it.
+    pub fn get(&self, capability: &str) -> Option<CapabilityAudit> {
+        self.0
+            .lock()
+            .unwrap()
+            .settings
+            .is_some()
+            .then(|| CapabilityAudit {
+                audit: self.clone(),
+                capability: capability.to_string(),
+            })
+    }
+}
+
+/// The audit of one capability's calls (see `Audit::get`).
+#[derive(Clone, Debug)]
+pub struct CapabilityAudit {
+    audit: Audit,
+    capability: String,
+}
+
+impl CapabilityAudit {
+    /// Records a call of `operation` on `target` that failed w/ `error` (if it did), if it's
+    /// audited, where `read` is whether the operation only reads.
+    pub fn record(&self, operation: &str, target: &str, read: bool, error: Option<ErrorKind>) {
+        if !self.audited(operation, read, error) {
+            return;
+        }
+        let outcome = error.map_or("ok", |kind| kind.as_str());
+        tracing::info!(
+            target: AUDIT_TARGET,
+            capability = self.capability.as_str(),
+            operation,
+            on = target,
+            outcome,
+            "{} {} on '{}': {}",
+            self.capability,
+            operation,
+            target,
+            outcome
+        );
+    }
+
+    pub(crate) fn audited(&self, operation: &str, read: bool, error: Option<ErrorKind>) -> bool {
+        let mut state = self.audit.0.lock().unwrap();
+        let settings = match &state.settings {
+            Some(settings) => settings,
+            None => return false,
+        };
+        if error == Some(ErrorKind::PermissionDenied) || SECURITY_OPERATIONS.contains(&operation) {
+            return true;
+        }
+        if !settings.audits(&self.capability)
+            || (settings.writes_only && read)
+            || (settings.failures_only && error.is_none())
+        {
+            return false;
+        }
+        if !read {
+            return true;
+        }
+        let rate = settings.read_sample_rate;
+        let seen = state.reads_seen;
+        state.reads_seen += 1;
+        (seen as f64 * rate).ceil() < ((seen + 1) as f64 * rate).ceil()
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use super::{Audit, AuditSettings};
+    use crate::error_kind::ErrorKind;
+
+    #[test]
+    fn audit_test() {
+        let audit = Audit::default();
+        assert!(audit.get("kv.azblob").is_none());
+
+        audit.configure(Some(AuditSettings {
+            capabilities: Some(vec!["kv".to_string(), "mq.azsbus".to_string()]),
+            read_sample_rate: 0.25,
+            ..Default::default()
+        }));
+        let kv = audit.get("kv.azblob").unwrap();
+        assert!(kv.audited("set", false, None));
+        // one of every 4 reads
+        let audited = (0..8).filter(|_| kv.audited("get", true, None)).count();
+        assert_eq!(audited, 2);
+
+        // capabilities that aren't audited still have their denials, and clears audited
+        let mq = audit.get("mq.filesystem").unwrap();
+        assert!(!mq.audited("send", false, None));
+        assert!(mq.audited("send", false, Some(ErrorKind::PermissionDenied)));
+        assert!(audit.get("mq.azsbus").unwrap().audited("send", false, None));
+
+        audit.configure(Some(AuditSettings {
+            writes_only: true,
+            failures_only: true,
+            ..Default::default()
+        }));
+        let kv = audit.get("kv.azblob").unwrap();
+        assert!(!kv.audited("get", true, Some(ErrorKind::Timeout)));
+        assert!(!kv.audited("set", false, None));
+        assert!(kv.audited("set", false, Some(ErrorKind::Backend)));
+        assert!(kv.audited("clear", false, None));
+    }
+
+    #[test]
+    fn validate_test() {
+        assert!(AuditSettings::default().validate().is_ok());
+        let invalid = [
+            AuditSettings {
+                read_sample_rate: 1.5,
+                ..Default::default()
+            },
+            AuditSettings {
+                writes_only: true,
+                read_sample_rate: 0.5,
+                ..Default::default()
+            },
+            AuditSettings {
+                capabilities: Some(Vec::new()),
+                ..Default::default()
+            },
+        ];
+        for settings in invalid {
+            assert!(settings.validate().is_err());
+        }
+    }
+}
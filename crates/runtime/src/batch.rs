@@ -0,0 +1,376 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::error_kind::ErrorKind;
+
+/// How many calls are coalesced into a batch at most, by default.
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 25;
+
+/// `BatchSettings` decide how calls of a capability are coalesced into batches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatchSettings {
+    /// how long the first call of a batch waits for others to join it
+    pub window: Duration,
+    /// how many calls a batch holds at most — once it's full, it's run right away
+    pub max_size: usize,
+}
+
+impl BatchSettings {
+    pub fn new(window_ms: u64, max_size: Option<usize>) -> Self {
+        Self {
+            window: Duration::from_millis(window_ms),
+            max_size: max_size.unwrap_or(DEFAULT_MAX_BATCH_SIZE).max(1),
+        }
+    }
+}
+
+/// `Batcher` coalesces independent calls of a capability (e.g., kv gets made by different
+/// guest instances at about the same time) into batches, so that the backend gets one batch
+/// operation, rather than a call per key.
+///
+/// The first call of a `group` (e.g., a kv store, as a batch operation can't span stores)
+/// opens a batch, and waits for up to the `window` for other calls to join it (or until it's
+/// full), before running it w/ its own `run` — so the calls of a group must be runnable by any
+/// of their `run`s. Then:
+///     - the keys are handed to `run` in the order the calls joined the batch, and each call
+///     gets the result at its own position back,
+///     - a key's failure is its call's alone, and
+///     - if the batch fails as a whole (or `run` doesn't return a result per key), every call
+///     of it fails, w/ the batch's error (and its kind, see `ErrorKind`).
+///
+/// A call made while no other call of its group is pending (i.e., being made, or waiting for
+/// its batch) runs right away, w/o waiting for the window, as there's likely nothing to
+/// coalesce it w/ — so calls are only delayed while the group is busy.
+///
+/// As each guest instance makes one call at a time (i.e., guests have no async calls to make
+/// many at once), calls are only coalesced across guest instances (e.g., the http, and events
+/// ones).
+#[derive(Debug)]
+pub struct Batcher<K, V> {
+    settings: BatchSettings,
+    open: Mutex<HashMap<String, Arc<Batch<K, V>>>>,
+    /// how many calls of each group are pending
+    pending: Mutex<HashMap<String, usize>>,
+}
+
+#[derive(Debug)]
+struct Batch<K, V> {
+    state: Mutex<BatchState<K, V>>,
+    /// notified when a call joins the batch, and when its results are in
+    changed: Condvar,
+}
+
+#[derive(Debug)]
+struct BatchState<K, V> {
+    keys: Vec<K>,
+    /// the results of the calls, by position, once the batch ran
+    results: Option<Vec<Option<Result<V>>>>,
+}
+
+impl<K, V> Batcher<K, V> {
+    pub fn new(settings: BatchSettings) -> Self {
+        Self {
+            settings,
+            open: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Makes a call on `key` as part of a batch of `group`, returning its result — `run`
+    /// runs the batch, if this call is the one that opened it.
+    pub fn call(
+        &self,
+        group: &str,
+        key: K,
+        run: impl FnOnce(Vec<K>) -> Result<Vec<Result<V>>>,
+    ) -> Result<V> {
+        let _pending = Pending::new(&self.pending, group);
+        let (batch, position) = self.join(group, key);
+        if position == 0 {
+            self.run(group, &batch, run);
+        }
+        let mut state = batch.state.lock().unwrap();
+        while state.results.is_none() {
+            state = batch.changed.wait(state).unwrap();
+        }
+        state.results.as_mut().unwrap()[position]
+            .take()
+            .expect("a batch's result is taken once")
+    }
+
+    /// Joins the open batch of `group`, or opens one, returning it, and the call's position.
+    fn join(&self, group: &str, key: K) -> (Arc<Batch<K, V>>, usize) {
+        let mut open = self.open.lock().unwrap();
+        if let Some(batch) = open.get(group).cloned() {
+            let mut state = batch.state.lock().unwrap();
+            state.keys.push(key);
+            let position = state.keys.len() - 1;
+            if state.keys.len() >= self.settings.max_size {
+                open.remove(group);
+            }
+            drop(state);
+            batch.changed.notify_all();
+            return (batch, position);
+        }
+        let batch = Arc::new(Batch {
+            state: Mutex::new(BatchState {
+                keys: vec![key],
+                results: None,
+            }),
+            changed: Condvar::new(),
+        });
+        // a batch is only opened for others to join if there are others to (i.e., calls of
+        // the group besides this one are pending), otherwise it's run right away
+        let alone = self
+            .pending
+            .lock()
+            .unwrap()
+            .get(group)
+            .copied()
+            .unwrap_or_default()
+            <= 1;
+        if self.settings.max_size > 1 && !alone {
+            open.insert(group.to_string(), batch.clone());
+        }
+        (batch, 0)
+    }
+
+    /// Waits for the `batch` to fill up, or its window to pass, closes it, and runs it.
+    fn run(
+        &self,
+        group: &str,
+        batch: &Arc<Batch<K, V>>,
+        run: impl FnOnce(Vec<K>) -> Result<Vec<Result<V>>>,
+    ) {
+        let opened =
+            matches!(self.open.lock().unwrap().get(group), Some(b) if Arc::ptr_eq(b, batch));
+        let deadline = Instant::now() + self.settings.window;
+        let mut state = batch.state.lock().unwrap();
+        while opened && state.keys.len() < self.settings.max_size {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            state = batch.changed.wait_timeout(state, deadline - now).unwrap().0;
+        }
+        drop(state);
+
+        // no calls join the batch once it's closed
+        {
+            let mut open = self.open.lock().unwrap();
+            if matches!(open.get(group), Some(b) if Arc::ptr_eq(b, batch)) {
+                open.remove(group);
+            }
+        }
+        let keys = std::mem::take(&mut batch.state.lock().unwrap().keys);
+        let size = keys.len();
+        let results = match panic::catch_unwind(AssertUnwindSafe(|| run(keys))) {
+            Ok(Ok(results)) if results.len() == size => results.into_iter().map(Some).collect(),
+            Ok(Ok(results)) => failed(
+                size,
+                ErrorKind::Backend,
+                &format!("got {} results", results.len()),
+            ),
+            Ok(Err(e)) => failed(size, ErrorKind::of(&e), &format!("{:#}", e)),
+            Err(panic) => {
+                // the other calls of the batch mustn't wait on it forever
+                self.finish(batch, failed(size, ErrorKind::Backend, "it panicked"));
+                panic::resume_unwind(panic);
+            }
+        };
+        self.finish(batch, results);
+    }
+
+    fn finish(&self, batch: &Batch<K, V>, results: Vec<Option<Result<V>>>) {
+        batch.state.lock().unwrap().results = Some(results);
+        batch.changed.notify_all();
+    }
+}
+
+/// The results of a batch of `size` calls that failed as a whole, w/ an error of `kind` —
+/// errors can't be cloned, so each call gets one of the same kind (e.g., so a throttled batch
+/// is `rate-limited` for all of them).
+fn failed<V>(size: usize, kind: ErrorKind, e: &str) -> Vec<Option<Result<V>>> {
+    (0..size)
+        .map(|_| {
+            Some(Err(
+                kind.error(format!("the batch of {} calls failed: {}", size, e))
+            ))
+        })
+        .collect()
+}
+
+/// `Pending` counts a call of a group as pending for as long as it lives.
+struct Pending<'a> {
+    pending: &'a Mutex<HashMap<String, usize>>,
+    group: &'a str,
+}
+
+impl<'a> Pending<'a> {
+    fn new(pending: &'a Mutex<HashMap<String, usize>>, group: &'a str) -> Self {
+        *pending
+            .lock()
+            .unwrap()
+            .entry(group.to_string())
+            .or_default() += 1;
+        Self { pending, group }
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(count) = pending.get_mut(self.group) {
+            *count -= 1;
+            if *count == 0 {
+                pending.remove(self.group);
+            }
+        }
+    }
+}
+
+/// A batcher of a capability's calls, whose keys, and values are bytes.
+type CapabilityBatcher = Batcher<Vec<u8>, Vec<u8>>;
+
+/// `Batches` hold the batchers of an app's capabilities, which are shared by all of its guest
+/// instances (i.e., so that their calls can be coalesced), and kept across restarts.
+#[derive(Clone, Debug, Default)]
+pub struct Batches(Arc<Mutex<BTreeMap<String, Arc<CapabilityBatcher>>>>);
+
+impl Batches {
+    /// Gets the batcher of `capability`, creating it w/ `settings` if there's none yet (or none
+    /// w/ these settings), or `None` if its calls aren't batched.
+    pub fn get(
+        &self,
+        capability: &str,
+        settings: Option<BatchSettings>,
+    ) -> Option<Arc<CapabilityBatcher>> {
+        let settings = settings?;
+        let mut batches = self.0.lock().unwrap();
+        match batches.get(capability) {
+            Some(batcher) if batcher.settings == settings => Some(batcher.clone()),
+            _ => {
+                let batcher = Arc::new(Batcher::new(settings));
+                batches.insert(capability.to_string(), batcher.clone());
+                Some(batcher)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::{
+        sync::{Arc, Barrier, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use anyhow::{bail, Result};
+
+    use super::{BatchSettings, Batcher, Pending};
+    use crate::{error_kind::ErrorKind, quota::RateLimited};
+
+    #[test]
+    fn coalesces_calls_test() {
+        let batcher = Arc::new(Batcher::new(BatchSettings {
+            window: Duration::from_secs(5),
+            max_size: 4,
+        }));
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let barrier = Arc::new(Barrier::new(4));
+        // another call of the group is pending, so the first of these waits for the others
+        let _pending = Pending::new(&batcher.pending, "store");
+        let calls = (0..4)
+            .map(|i| {
+                let batcher = batcher.clone();
+                let batches = batches.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    batcher.call("store", i, |keys: Vec<i32>| {
+                        batches.lock().unwrap().push(keys.clone());
+                        Ok(keys
+                            .into_iter()
+                            .map(|key| match key {
+                                2 => bail!("no value found for key: 2"),
+                                key => Ok(key * 10),
+                            })
+                            .collect())
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        let results = calls
+            .into_iter()
+            .map(|call| call.join().unwrap().map_err(|e| e.to_string()))
+            .collect::<Vec<_>>();
+
+        // a full batch runs right away, rather than after the window, once
+        let batches = batches.lock().unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].len(), 4);
+        // each call gets the result of its own key
+        assert_eq!(
+            results,
+            vec![
+                Ok(0),
+                Ok(10),
+                Err("no value found for key: 2".to_string()),
+                Ok(30)
+            ]
+        );
+    }
+
+    #[test]
+    fn alone_test() -> Result<()> {
+        let batcher = Batcher::<i32, i32>::new(BatchSettings {
+            window: Duration::from_secs(5),
+            max_size: 4,
+        });
+        // w/ nothing else pending, a call doesn't wait for the window
+        let start = Instant::now();
+        assert_eq!(
+            batcher.call("store", 1, |keys| Ok(keys.into_iter().map(Ok).collect()))?,
+            1
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(batcher.pending.lock().unwrap().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn batch_failure_test() -> Result<()> {
+        let batcher = Batcher::<i32, i32>::new(BatchSettings::new(10, None));
+        let e = batcher
+            .call("store", 1, |_| bail!("throttled"))
+            .unwrap_err();
+        assert_eq!(e.to_string(), "the batch of 1 calls failed: throttled");
+        let e = batcher.call("store", 1, |_| Ok(vec![])).unwrap_err();
+        assert_eq!(e.to_string(), "the batch of 1 calls failed: got 0 results");
+        // the calls of a batch that failed as a whole fail w/ its kind of error
+        let e = batcher
+            .call("store", 1, |_| {
+                Err(RateLimited {
+                    capability: "kv".to_string(),
+                    limit: "1 ops/sec".to_string(),
+                    retry_after: Duration::from_secs(1),
+                }
+                .into())
+            })
+            .unwrap_err();
+        assert_eq!(ErrorKind::of(&e), ErrorKind::RateLimited);
+        // the batcher is usable after a batch failed
+        assert_eq!(
+            batcher.call("store", 2, |keys| Ok(keys.into_iter().map(Ok).collect()))?,
+            2
+        );
+        Ok(())
+    }
+}
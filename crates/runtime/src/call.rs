@@ -0,0 +1,788 @@
+use std::{
+    any::Any,
+    cell::Cell,
+    fmt, io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use tracing::span::EnteredSpan;
+
+use crate::{
+    audit::CapabilityAudit,
+    deadline,
+    error_kind::{ErrorKind, Kind},
+    flags::OperationFlags,
+    grants::Grants,
+    metrics::CallMetrics,
+    payload_limit::PayloadLimit,
+    pool::{Pool, PoolExhausted},
+    quota::Quota,
+};
+
+/// The target of the spans of capability calls, and guest phases (see `trace::ChromeTraceLayer`).
+pub const TRACE_TARGET: &str = "slight::trace";
+
+/// `CallSettings` holds the host-side settings that apply to every call
+/// a guest makes into a capability.
+#[derive(Clone, Debug, Default)]
+pub struct CallSettings {
+    /// Calls that take longer than this are logged as warnings (disabled if `None`).
+    pub slow_call_threshold: Option<Duration>,
+    /// The interceptors every call goes through, in order (see `Interceptor`).
+    pub interceptors: Interceptors,
+    /// The quota calls are held to (see `quota::Quota`), if there's any.
+    pub quota: Option<Arc<Quota>>,
+    /// The pool bounding how many calls are in flight at once (see `pool::Pool`), if
+    /// there's any.
+    pub pool: Option<Arc<Pool>>,
+    /// The metrics calls are counted in (see `metrics::CallMetrics`), if there are any.
+    pub metrics: Option<Arc<CallMetrics>>,
+    /// The operations of the capability that are safe to retry if they time out (e.g., its
+    /// reads), as declared by the capability (see `BasicState::with_idempotent_operations`).
+    pub idempotent_operations: &'static [&'static str],
+    /// The operations of the capability that only read (i.e., that don't change what it holds),
+    /// as declared by the capability (see `BasicState::with_read_operations`) — it's apart from
+    /// `idempotent_operations`, as some writes are idempotent too (e.g., re-arming a timer).
+    pub read_operations: &'static [&'static str],
+    /// The operations the guest is allowed to call (see `grants::Grants`).
+    pub grants: Arc<Grants>,
+    /// The operations that are enabled (see `flags::OperationFlags`).
+    pub flags: Arc<OperationFlags>,
+    /// The max size of the payloads the guest sends (see `payload_limit::PayloadLimit`), if
+    /// there's any.
+    pub payload_limit: Option<PayloadLimit>,
+    /// The audit calls are recorded in (see `audit::Audit`), if they're audited.
+    pub audit: Option<CapabilityAudit>,
+}
+
+impl CallSettings {
+    pub fn new(slow_call_threshold_ms: Option<u64>) -> Self {
+        Self {
+            slow_call_threshold: slow_call_threshold_ms.map(Duration::from_millis),
+            interceptors: Interceptors::default(),
+            quota: None,
+            pool: None,
+            metrics: None,
+            idempotent_operations: &[],
+            read_operations: &[],
+            grants: Arc::default(),
+            flags: Arc::default(),
+            payload_limit: None,
+            audit: None,
+        }
+    }
+
+    /// Only lets the guest call the operations of `grants` (e.g., only the reads of a kv store).
+    pub fn with_grants(mut self, grants: Grants) -> Self {
+        self.grants = Arc::new(grants);
+        self
+    }
+
+    /// Only enables the operations `flags` do (e.g., not the experimental ones the app didn't
+    /// opt into).
+    pub fn with_flags(mut self, flags: OperationFlags) -> Self {
+        self.flags = Arc::new(flags);
+        self
+    }
+
+    /// Fails the calls sending payloads bigger than `payload_limit`.
+    pub fn with_payload_limit(mut self, payload_limit: Option<PayloadLimit>) -> Self {
+        self.payload_limit = payload_limit;
+        self
+    }
+
+    /// Records calls in `audit` (i.e., the one of the app, for this capability).
+    pub fn with_audit(mut self, audit: Option<CapabilityAudit>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Holds calls to `quota` (e.g., one shared by all guest instances of an app).
+    pub fn with_quota(mut self, quota: Option<Arc<Quota>>) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    /// Bounds concurrent calls w/ the connections of `pool` (e.g., one shared by all guest
+    /// instances of an app).
+    pub fn with_pool(mut self, pool: Option<Arc<Pool>>) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    /// Counts calls in `metrics` (e.g., ones shared by all guest instances of an app).
+    pub fn with_metrics(mut self, metrics: Option<Arc<CallMetrics>>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn with_idempotent_operations(mut self, operations: &'static [&'static str]) -> Self {
+        self.idempotent_operations = operations;
+        self
+    }
+
+    pub fn with_read_operations(mut self, operations: &'static [&'static str]) -> Self {
+        self.read_operations = operations;
+        self
+    }
+
+    /// Whether `operation` only reads (e.g., so audits of writes only leave it out).
+    pub fn reads(&self, operation: &str) -> bool {
+        self.read_operations.contains(&operation)
+    }
+
+    /// Adds an interceptor, which runs after the ones added before it.
+    pub fn with_interceptor(mut self, interceptor: Arc<dyn Interceptor>) -> Self {
+        self.interceptors.0.push(interceptor);
+        self
+    }
+}
+
+/// `TimedOut` is the error backends fail calls w/ when they time out (e.g., waiting for a
+/// response), so guests get them as `timeout` errors (see `timed_out`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TimedOut(pub String);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "timed out: {}", self.0)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Whether an error was caused by a timeout: a `TimedOut`, an I/O error that timed out, or
+/// waiting for a connection (see `pool::PoolExhausted`).
+pub fn timed_out(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause.is::<TimedOut>()
+            || cause.is::<PoolExhausted>()
+            || cause
+                .downcast_ref::<io::Error>()
+                .is_some_and(|e| e.kind() == io::ErrorKind::TimedOut)
+    })
+}
+
+/// Whether the call that failed w/ a timeout `error` is safe to retry: either it never reached
+/// the backend (i.e., it didn't get a connection), or the operation it was (i.e., the one in
+/// flight on this thread, see `instrument`) only reads — otherwise, it may have been applied
+/// (e.g., a write, or a send).
+pub fn retryable(error: &anyhow::Error) -> bool {
+    PoolExhausted::is(error) || IDEMPOTENT.with(Cell::get)
+}
+
+/// Whether the operation of the call in flight on this thread (if any) is idempotent, and so
+/// safe to make again (see `replayed`).
+pub fn idempotent() -> bool {
+    IDEMPOTENT.with(Cell::get)
+}
+
+/// Replays a call w/ `replay`, if its first attempt failed (w/ `res`) in a way that made the
+/// backend reconnect (i.e., `reconnected`), and its operation is idempotent — so a brief
+/// disconnect (e.g., a rotated credential) is invisible to the guest.
+///
+/// A call is only replayed once, and the others fail w/ the error they did before, as their
+/// first attempt may have been applied (e.g., a send).
+pub fn replayed<T>(
+    capability: &str,
+    res: anyhow::Result<T>,
+    reconnected: bool,
+    replay: impl FnOnce() -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    match res {
+        Err(e) if reconnected && idempotent() => {
+            tracing::info!(
+                "replaying an idempotent {} call on the new connection, as it failed w/: {:#}",
+                capability,
+                e
+            );
+            replay()
+        }
+        res => res,
+    }
+}
+
+thread_local! {
+    /// Whether the operation of the call in flight on this thread (if any) is idempotent —
+    /// errors are converted for the guest inside the call, which is where `retryable` is asked.
+    static IDEMPOTENT: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Marks the operation of a call as in flight on this thread until it's dropped, restoring the
+/// one it's nested in (e.g., a kv call the host makes on behalf of a pubsub one), if any.
+struct InFlight(bool);
+
+impl InFlight {
+    fn enter(idempotent: bool) -> Self {
+        Self(IDEMPOTENT.with(|in_flight| in_flight.replace(idempotent)))
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IDEMPOTENT.with(|in_flight| in_flight.set(self.0));
+    }
+}
+
+/// A capability call, as seen by an `Interceptor`, the chaos (see `chaos::Chaos`), and the mocks
+/// (see `mock::Mocks`) it goes through.
+#[derive(Clone, Copy, Debug)]
+pub struct Call<'a> {
+    pub capability: &'a str,
+    pub operation: &'a str,
+    /// whatever the operation is acting on (e.g., a key, or a queue name)
+    pub target: &'a str,
+}
+
+/// `Interceptor` lets whoever embeds slight wrap every capability call w/ their own logic
+/// (e.g., custom metrics, authorization checks, or rewriting payloads) — it's installed w/
+/// `CallSettings::with_interceptor`, or `BasicState::with_interceptor`.
+pub trait Interceptor: Send + Sync {
+    /// Runs before the call — if it fails, the call is short-circuited: the capability's
+    /// backend isn't called at all, and the guest gets its error instead.
+    fn before(&self, _call: &Call<'_>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Runs after the call w/ what it returned, which it can transform (i.e., the value, in
+    /// place, as the type the operation returns, e.g., `Vec<u8>` for a kv `get`) — if it fails,
+    /// the guest gets its error instead of the call's outcome (e.g., to redact an error
+    /// message, or to reject what a backend returned).
+    ///
+    /// It isn't run for calls that were short-circuited.
+    fn after(&self, _call: &Call<'_>, _returned: Returned<'_>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// What a call returned, as seen by `Interceptor::after`.
+pub enum Returned<'a> {
+    Value(&'a mut dyn Any),
+    Error(&'a dyn fmt::Display),
+}
+
+/// The interceptors of `CallSettings`.
+#[derive(Clone, Default)]
+pub struct Interceptors(Vec<Arc<dyn Interceptor>>);
+
+impl fmt::Debug for Interceptors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Interceptors({})", self.0.len())
+    }
+}
+
+impl Interceptors {
+    fn before(&self, call: &Call<'_>) -> Result<()> {
+        self.0
+            .iter()
+            .try_for_each(|interceptor| interceptor.before(call))
+    }
+
+    fn after<T: Outcome>(&self, call: &Call<'_>, res: &mut T) -> Result<()> {
+        for interceptor in &self.0 {
+            let returned = match res.value_mut() {
+                Some(value) => Returned::Value(value),
+                // the unwrap can't fail, as an outcome w/o a value failed
+                None => Returned::Error(res.error().unwrap()),
+            };
+            interceptor.after(call, returned)?;
+        }
+        Ok(())
+    }
+}
+
+/// `Outcome` is what a capability operation returns, so whether it failed can be seen (e.g., by
+/// the health, or an `Interceptor`), and it can be failed (e.g., w/ an injected fault).
+pub trait Outcome: Sized {
+    fn error(&self) -> Option<&dyn fmt::Display>;
+
+    /// The value it returned, if it didn't fail (e.g., for an `Interceptor` to transform).
+    fn value_mut(&mut self) -> Option<&mut dyn Any>;
+
+    /// The kind of error it failed w/, if it did (i.e., what its call is counted as).
+    fn error_kind(&self) -> Option<ErrorKind>;
+
+    fn from_error(error: anyhow::Error) -> Self;
+
+    /// The outcome of a call that returned `value` (e.g., a mocked one, see `mock::Mock`), or
+    /// `None` if it isn't of the type the operation returns.
+    fn from_value(value: Box<dyn Any>) -> Option<Self>;
+}
+
+impl<T: 'static, E> Outcome for Result<T, E>
+where
+    E: From<anyhow::Error> + fmt::Display + Kind,
+{
+    fn error(&self) -> Option<&dyn fmt::Display> {
+        self.as_ref().err().map(|e| e as &dyn fmt::Display)
+    }
+
+    fn value_mut(&mut self) -> Option<&mut dyn Any> {
+        self.as_mut().ok().map(|value| value as &mut dyn Any)
+    }
+
+    fn error_kind(&self) -> Option<ErrorKind> {
+        self.as_ref().err().map(Kind::kind)
+    }
+
+    fn from_error(error: anyhow::Error) -> Self {
+        Err(error.into())
+    }
+
+    fn from_value(value: Box<dyn Any>) -> Option<Self> {
+        value.downcast::<T>().ok().map(|value| Ok(*value))
+    }
+}
+
+/// Runs a capability operation, measuring how long it took.
+///
+/// The `target` is whatever the operation is acting on (e.g., a key, or a queue name),
+/// and it is only used for logging, tracing, metrics, the audit, and by the `interceptors` of the
+/// `settings`.
+///
+/// Calls exceeding the `quota` of the `settings` fail w/ `quota::RateLimited` w/o running,
+/// and ones that can't get a connection of its `pool` in time fail w/ `pool::PoolExhausted`.
+/// Calls made past the deadline of what the guest is handling (see `Ctx::deadline`) fail
+/// w/ `deadline::DeadlineExceeded`, whether before they run, or while waiting for a connection
+/// (or for their backend, see `deadline::block_on`).
+///
+/// While it runs, whether its `operation` is one of the `idempotent_operations` of the
+/// `settings` is known to `retryable`, and, once it returns, it's counted in their `metrics`
+/// (w/ the kind of error it failed w/, if it did, rejected calls included), and recorded in
+/// their `audit`, if it's audited.
+pub fn instrument<T: Outcome>(
+    settings: &CallSettings,
+    capability: &str,
+    operation: &str,
+    target: &str,
+    f: impl FnOnce() -> T,
+) -> T {
+    let span = tracing::trace_span!(
+        target: TRACE_TARGET,
+        "call",
+        capability,
+        operation,
+        on = target,
+        failed = tracing::field::Empty
+    )
+    .entered();
+    let res = limited(settings, capability, operation, target, f);
+    // whether it failed is what its trace is sampled by (see `trace::Sampler`)
+    span.record("failed", res.error().is_some());
+    if let Some(metrics) = &settings.metrics {
+        metrics.record(operation, target, res.error_kind());
+    }
+    if let Some(audit) = &settings.audit {
+        audit.record(
+            operation,
+            target,
+            settings.reads(operation),
+            res.error_kind(),
+        );
+    }
+    res
+}
+
+/// Fails if the guest isn't permitted to call `operation` of `capability` (i.e., w/ `Denied`, if
+/// it isn't granted it, or w/ `Disabled`, if it isn't enabled), before anything else about the
+/// call is checked.
+pub fn permitted(settings: &CallSettings, capability: &str, operation: &str) -> Result<()> {
+    settings.grants.check(capability, operation)?;
+    settings.flags.check(capability, operation)
+}
+
+/// Runs a capability operation w/in the grants, flags, `quota`, and `pool` of the `settings`, and
+/// through their `interceptors`.
+fn limited<T: Outcome>(
+    settings: &CallSettings,
+    capability: &str,
+    operation: &str,
+    target: &str,
+    f: impl FnOnce() -> T,
+) -> T {
+    if let Err(e) = permitted(settings, capability, operation) {
+        return T::from_error(e);
+    }
+    if let Err(e) = deadline::check(capability, operation) {
+        return T::from_error(e);
+    }
+    if let Some(quota) = &settings.quota {
+        if let Err(e) = quota.take_op() {
+            return T::from_error(e);
+        }
+    }
+    // the connection is held until the call returns
+    let _connection = match settings.pool.as_ref().map(|pool| pool.acquire()) {
+        // waiting for it is bounded by the deadline too
+        Some(Err(e)) => {
+            return T::from_error(deadline::check(capability, operation).err().unwrap_or(e))
+        }
+        connection => connection,
+    };
+    let _in_flight = InFlight::enter(settings.idempotent_operations.contains(&operation));
+    if settings.interceptors.0.is_empty() {
+        return timed(settings, capability, operation, target, f);
+    }
+
+    let call = Call {
+        capability,
+        operation,
+        target,
+    };
+    if let Err(e) = settings.interceptors.before(&call) {
+        tracing::debug!(
+            "{}.{} on '{}' was short-circuited: {:#}",
+            capability,
+            operation,
+            target,
+            e
+        );
+        return T::from_error(e);
+    }
+    let mut res = timed(settings, capability, operation, target, f);
+    match settings.interceptors.after(&call, &mut res) {
+        Ok(()) => res,
+        Err(e) => T::from_error(e),
+    }
+}
+
+/// Runs a capability operation, logging how long it took.
+fn timed<T>(
+    settings: &CallSettings,
+    capability: &str,
+    operation: &str,
+    target: &str,
+    f: impl FnOnce() -> T,
+) -> T {
+    let start = Instant::now();
+    let res = f();
+    let elapsed = start.elapsed();
+
+    tracing::trace!(
+        "{}.{} on '{}' took {:?}",
+        capability,
+        operation,
+        target,
+        elapsed
+    );
+    if let Some(threshold) = settings.slow_call_threshold {
+        if elapsed > threshold {
+            tracing::warn!(
+                "slow capability call: {}.{} on '{}' took {:?} (threshold is {:?})",
+                capability,
+                operation,
+                target,
+                elapsed,
+                threshold
+            );
+        }
+    }
+    res
+}
+
+/// Marks a phase of the guest's run (e.g., its instantiation, or a handler it runs), which
+/// lasts until the returned span is dropped.
+pub fn guest_phase(phase: &str) -> EnteredSpan {
+    tracing::trace_span!(target: TRACE_TARGET, "guest", phase).entered()
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use anyhow::{bail, Result};
+
+    use super::{
+        instrument, permitted, replayed, retryable, timed_out, Call, CallSettings, Interceptor,
+        Returned, TimedOut,
+    };
+    use crate::{
+        audit::{Audit, AuditSettings},
+        deadline::{Deadline, DeadlineExceeded},
+        flags::{Disabled, OperationFlags},
+        grants::{Denied, Grants},
+        pool::{PoolExhausted, PoolSettings, Pools},
+        quota::{QuotaSettings, Quotas, RateLimited},
+        resource::{BasicState, ResourceMap},
+    };
+
+    /// Records the calls it sees, rejects deletes, redacts errors, and upper-cases the values
+    /// of gets.
+    #[derive(Default)]
+    struct NoDeletes(Mutex<Vec<String>>);
+
+    impl Interceptor for NoDeletes {
+        fn before(&self, call: &Call<'_>) -> Result<()> {
+            self.0.lock().unwrap().push(format!(
+                "before {}.{} on {}",
+                call.capability, call.operation, call.target
+            ));
+            if call.operation == "delete" {
+                bail!("deletes aren't allowed");
+            }
+            Ok(())
+        }
+
+        fn after(&self, call: &Call<'_>, returned: Returned<'_>) -> Result<()> {
+            match returned {
+                Returned::Value(value) => {
+                    self.0
+                        .lock()
+                        .unwrap()
+                        .push(format!("after {}", call.operation));
+                    if let Some(value) = value.downcast_mut::<Vec<u8>>() {
+                        value.make_ascii_uppercase();
+                    }
+                    Ok(())
+                }
+                Returned::Error(e) => {
+                    self.0
+                        .lock()
+                        .unwrap()
+                        .push(format!("after {} failed: {}", call.operation, e));
+                    bail!("{} failed", call.operation)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn interceptors_test() {
+        let interceptor = Arc::new(NoDeletes::default());
+        let settings = CallSettings::default().with_interceptor(interceptor.clone());
+
+        // the value of the call is transformed
+        let res: Result<Vec<u8>> =
+            instrument(&settings, "kv", "get", "my-key", || Ok(b"value".to_vec()));
+        assert_eq!(res.unwrap(), b"VALUE");
+
+        // it's short-circuited w/o running
+        let mut called = false;
+        let res: Result<()> = instrument(&settings, "kv", "delete", "my-key", || {
+            called = true;
+            Ok(())
+        });
+        assert_eq!(res.unwrap_err().to_string(), "deletes aren't allowed");
+        assert!(!called);
+
+        // the error of the call is replaced w/ the interceptor's
+        let res: Result<()> = instrument(&settings, "kv", "set", "my-key", || {
+            bail!("the backend is down")
+        });
+        assert_eq!(res.unwrap_err().to_string(), "set failed");
+
+        assert_eq!(
+            *interceptor.0.lock().unwrap(),
+            vec![
+                "before kv.get on my-key",
+                "after get",
+                "before kv.delete on my-key",
+                "before kv.set on my-key",
+                "after set failed: the backend is down",
+            ]
+        );
+
+        // embedders install them on the state of a capability
+        let state = BasicState::new(ResourceMap::default(), &[], "slightfile.toml")
+            .with_interceptor(Arc::new(NoDeletes::default()));
+        let res: Result<()> = state.instrument("kv", "delete", "my-key", || Ok(()));
+        assert_eq!(res.unwrap_err().to_string(), "deletes aren't allowed");
+    }
+
+    #[test]
+    fn quota_test() {
+        let quota = Quotas::default().get(
+            "kv",
+            QuotaSettings {
+                ops_per_sec: Some(1),
+                bytes_per_min: None,
+            },
+        );
+        let settings = CallSettings::default().with_quota(quota);
+
+        let res: Result<()> = instrument(&settings, "kv", "get", "my-key", || Ok(()));
+        assert!(res.is_ok());
+
+        let mut called = false;
+        let res: Result<()> = instrument(&settings, "kv", "get", "my-key", || {
+            called = true;
+            Ok(())
+        });
+        assert!(RateLimited::is(&res.unwrap_err()));
+        assert!(!called);
+    }
+
+    #[test]
+    fn grants_test() {
+        let quota = Quotas::default().get(
+            "kv",
+            QuotaSettings {
+                ops_per_sec: Some(1),
+                bytes_per_min: None,
+            },
+        );
+        let read_only = Grants::new(Some(vec!["get".to_string()]), Vec::new());
+        let settings = CallSettings::default()
+            .with_quota(quota)
+            .with_grants(read_only);
+
+        // a denied call doesn't run, nor count against the quota
+        let mut called = false;
+        let res: Result<()> = instrument(&settings, "kv", "set", "my-key", || {
+            called = true;
+            Ok(())
+        });
+        assert!(Denied::is(&res.unwrap_err()));
+        assert!(!called);
+        let res: Result<()> = instrument(&settings, "kv", "get", "my-key", || Ok(()));
+        assert!(res.is_ok());
+        // so are the operations that aren't instrumented (e.g., `watch`)
+        assert!(Denied::is(
+            &permitted(&settings, "kv", "watch").unwrap_err()
+        ));
+    }
+
+    #[test]
+    fn flags_test() {
+        let flags =
+            OperationFlags::new(&["list-keys-stream"], Vec::new(), vec!["watch".to_string()]);
+        let settings = CallSettings::default().with_flags(flags);
+        let res: Result<()> = instrument(&settings, "kv", "get", "my-key", || Ok(()));
+        assert!(res.is_ok());
+        // the operations that aren't instrumented are enabled, or disabled like the others
+        for operation in ["list-keys-stream", "watch"] {
+            let e = permitted(&settings, "kv", operation).unwrap_err();
+            assert!(Disabled::is(&e), "{}", operation);
+        }
+        // and grants are checked first
+        let settings = settings.with_grants(Grants::new(Some(vec!["get".to_string()]), Vec::new()));
+        assert!(Denied::is(
+            &permitted(&settings, "kv", "watch").unwrap_err()
+        ));
+    }
+
+    #[test]
+    fn pool_test() {
+        let pool = Pools::default().get("kv", Some(PoolSettings::new(1, Some(0))));
+        let settings = CallSettings::default().with_pool(pool);
+
+        // a call made while another is in flight doesn't get a connection
+        let res: Result<()> = instrument(&settings, "kv", "get", "my-key", || {
+            let mut called = false;
+            let res: Result<()> = instrument(&settings, "kv", "get", "other-key", || {
+                called = true;
+                Ok(())
+            });
+            assert!(PoolExhausted::is(&res.unwrap_err()));
+            assert!(!called);
+            Ok(())
+        });
+        assert!(res.is_ok());
+
+        // and the connection is released once the call returns
+        let res: Result<()> = instrument(&settings, "kv", "get", "my-key", || Ok(()));
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn deadline_test() {
+        let pool = Pools::default().get("kv", Some(PoolSettings::new(1, Some(60_000))));
+        let settings = CallSettings::default().with_pool(pool);
+
+        let res: Result<()> = instrument(&settings, "kv", "get", "my-key", || {
+            // a call waiting for a connection gives up once the deadline passes
+            let _deadline = Deadline::enter(Some(Instant::now() + Duration::from_millis(20)));
+            let res: Result<()> = instrument(&settings, "kv", "get", "other-key", || Ok(()));
+            assert!(DeadlineExceeded::is(&res.unwrap_err()));
+
+            // and, once it has, calls aren't made at all
+            let mut called = false;
+            let res: Result<()> = instrument(&settings, "kv", "set", "my-key", || {
+                called = true;
+                Ok(())
+            });
+            assert!(DeadlineExceeded::is(&res.unwrap_err()));
+            assert!(!called);
+            Ok(())
+        });
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn reads_test() {
+        // re-arming a timer is idempotent, but it's a write all the same
+        let settings = CallSettings::default()
+            .with_idempotent_operations(&["set", "next"])
+            .with_read_operations(&["next"]);
+        assert!(settings.reads("next"));
+        assert!(!settings.reads("set"));
+
+        let audit = Audit::default();
+        audit.configure(Some(AuditSettings {
+            writes_only: true,
+            ..Default::default()
+        }));
+        let timers = audit.get("timers").unwrap();
+        assert!(timers.audited("set", settings.reads("set"), None));
+        assert!(!timers.audited("next", settings.reads("next"), None));
+    }
+
+    #[test]
+    fn timeout_test() {
+        let settings = CallSettings::default().with_idempotent_operations(&["get"]);
+        let timeout = || anyhow::Error::new(TimedOut("waiting for redis".to_string()));
+
+        let res: Result<()> = instrument(&settings, "kv", "get", "my-key", || {
+            let e = timeout().context("failed to get value");
+            assert!(timed_out(&e));
+            assert!(retryable(&e));
+            Err(e)
+        });
+        assert!(timed_out(&res.unwrap_err()));
+
+        let _: Result<()> = instrument(&settings, "kv", "set", "my-key", || {
+            let e = timeout();
+            assert!(timed_out(&e));
+            // the value may have been set
+            assert!(!retryable(&e));
+            let io = anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::TimedOut));
+            assert!(timed_out(&io));
+            assert!(!timed_out(&anyhow::anyhow!("not found")));
+            Ok(())
+        });
+        assert!(!retryable(&timeout()));
+    }
+
+    #[test]
+    fn replay_test() {
+        let settings = CallSettings::default().with_idempotent_operations(&["get"]);
+        let disconnected = || Err(anyhow::anyhow!("the connection was reset"));
+        let replays = Mutex::new(0);
+        let replay = || {
+            *replays.lock().unwrap() += 1;
+            Ok("value")
+        };
+
+        // an idempotent call is made again on the new connection
+        let res = instrument(&settings, "kv", "get", "my-key", || {
+            replayed("kv", disconnected(), true, replay)
+        });
+        assert_eq!(res.unwrap(), "value");
+
+        // but not if the backend didn't reconnect, or if the call may have been applied
+        let res = instrument(&settings, "kv", "get", "my-key", || {
+            replayed("kv", disconnected(), false, replay)
+        });
+        assert!(res.is_err());
+        let res = instrument(&settings, "kv", "set", "my-key", || {
+            replayed("kv", disconnected(), true, replay)
+        });
+        assert!(res.is_err());
+        assert_eq!(*replays.lock().unwrap(), 1);
+    }
+}
@@ -0,0 +1,608 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+
+use crate::{error_kind::ErrorKind, resource::ResourceMap};
+
+/// The name a `Cassette` is shared under in the `StateTable` (see `Cassette::install`).
+pub const CASSETTE: &str = "slight.cassette";
+
+/// Whether a `Cassette` records the calls made to the backends, or replays them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// calls go to the backends, and their outcomes are recorded (over the cassette's)
+    Record,
+    /// calls get the outcomes recorded for them, w/o going to the backends — ones that
+    /// weren't recorded fail
+    Replay,
+    /// replays the cassette if it exists, and records it otherwise (the default)
+    Once,
+}
+
+impl CassetteMode {
+    pub fn parse(mode: &str) -> Result<Self> {
+        match mode {
+            "record" => Ok(Self::Record),
+            "replay" => Ok(Self::Replay),
+            "once" => Ok(Self::Once),
+            m => bail!(
+                "invalid cassette mode: '{}' (expected 'record', 'replay', or 'once')",
+                m
+            ),
+        }
+    }
+}
+
+/// A recorded call, as kept in a cassette file.
+#[derive(Clone, Debug, PartialEq)]
+struct Interaction {
+    capability: String,
+    operation: String,
+    /// the arguments of the call, as per `Recorded::record` (e.g., the name of a store, and
+    /// a key)
+    arguments: Value,
+    /// `{ "ok": <value> }`, or `{ "error": <message>, "kind": <error kind> }`
+    outcome: Value,
+}
+
+impl Interaction {
+    fn to_json(&self) -> Value {
+        json!({
+            "capability": self.capability,
+            "operation": self.operation,
+            "arguments": self.arguments,
+            "outcome": self.outcome,
+        })
+    }
+
+    fn from_json(value: &Value) -> Result<Self> {
+        let field = |name: &str| {
+            value
+                .get(name)
+                .cloned()
+                .with_context(|| format!("invalid cassette: an interaction has no '{}'", name))
+        };
+        let string = |name: &str| {
+            field(name)?
+                .as_str()
+                .map(str::to_string)
+                .with_context(|| format!("invalid cassette: '{}' isn't a string", name))
+        };
+        Ok(Self {
+            capability: string("capability")?,
+            operation: string("operation")?,
+            arguments: field("arguments")?,
+            outcome: field("outcome")?,
+        })
+    }
+
+    fn key(&self) -> String {
+        format!("{}.{} {}", self.capability, self.operation, self.arguments)
+    }
+}
+
+struct Inner {
+    path: PathBuf,
+    replaying: bool,
+    /// the cassette file interactions are appended to, if recording
+    file: Option<File>,
+    interactions: Vec<Interaction>,
+    /// how many calls w/ each key (i.e., capability, operation, and arguments) were replayed
+    replayed: HashMap<String, usize>,
+}
+
+/// A `Cassette` records the outcomes of the calls an app's capabilities make to their
+/// backends to a file, so later runs can replay them w/o the backends (e.g., offline, and
+/// deterministically), much like VCR does for http requests.
+///
+/// Calls are told apart by their capability, operation, and arguments, and the ones w/ the
+/// same arguments (e.g., `receive` from a queue) get the outcomes recorded for them in order,
+/// and, once there are none left, the last one again.
+///
+/// The cassette file has a line of JSON for each call recorded (i.e., JSON Lines), which is
+/// appended to it as the call is made — so it's complete however the app exits, w/o being
+/// rewritten on every call. Payloads that are text are kept as strings, and other payloads
+/// as `{ "hex": .. }`, and errors keep their kind (see `ErrorKind::as_str`), so they're
+/// replayed as the same variant of `types.wit`'s `error`:
+/// ```json
+/// {"capability":"kv","operation":"get","arguments":["my-container","my-key"],"outcome":{"ok":"my-value"}}
+/// {"capability":"kv","operation":"get","arguments":["my-container","gone"],"outcome":{"error":"..","kind":"not_found"}}
+/// ```
+///
+/// Cassettes recorded as a JSON array (i.e., before they were appended to) are still
+/// replayed.
+///
+/// It is shared through the app's `StateTable` (see `install`), and only the capabilities
+/// whose backends are wrapped w/ it (see `BasicState::recorded`) are recorded — i.e., kv, mq,
+/// lockd, and pubsub, but not configs, as they're often secrets, which have no place in a
+/// cassette.
+#[derive(Clone)]
+pub struct Cassette(Arc<Mutex<Inner>>);
+
+impl fmt::Debug for Cassette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.0.lock().unwrap();
+        write!(
+            f,
+            "Cassette({}, {})",
+            inner.path.display(),
+            if inner.replaying { "replay" } else { "record" }
+        )
+    }
+}
+
+impl Cassette {
+    pub fn open(path: &Path, mode: CassetteMode) -> Result<Self> {
+        let replaying = match mode {
+            CassetteMode::Record => false,
+            CassetteMode::Replay => true,
+            CassetteMode::Once => path.exists(),
+        };
+        let (interactions, file) = if replaying {
+            let contents = fs::read_to_string(path)
+                .with_context(|| format!("failed to read cassette {}", path.display()))?;
+            let interactions =
+                parse(&contents).with_context(|| format!("invalid cassette {}", path.display()))?;
+            (interactions, None)
+        } else {
+            // recording starts the cassette over
+            let file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(path)
+                .with_context(|| format!("failed to create cassette {}", path.display()))?;
+            (Vec::new(), Some(file))
+        };
+        tracing::info!(
+            "{} calls to backends {} cassette {}",
+            if replaying { "replaying" } else { "recording" },
+            if replaying { "from" } else { "to" },
+            path.display()
+        );
+        Ok(Self(Arc::new(Mutex::new(Inner {
+            path: path.to_path_buf(),
+            replaying,
+            file,
+            interactions,
+            replayed: HashMap::new(),
+        }))))
+    }
+
+    /// Shares the cassette w/ the app whose `StateTable` is `resource_map`.
+    ///
+    /// It must be installed before the capabilities are linked (i.e., before their
+    /// `BasicState` is created).
+    pub fn install(&self, resource_map: &ResourceMap) -> Result<()> {
+        resource_map
+            .lock()
+            .unwrap()
+            .shared(CASSETTE, || self.clone())?;
+        Ok(())
+    }
+
+    /// Replays the outcome of a call, or, if recording, makes it w/ `f`, and records its
+    /// outcome.
+    pub fn call<T: Replayed>(
+        &self,
+        capability: &str,
+        operation: &str,
+        arguments: &[&dyn Recorded],
+        f: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        let mut interaction = Interaction {
+            capability: capability.to_string(),
+            operation: operation.to_string(),
+            arguments: Value::Array(arguments.iter().map(|a| a.record()).collect()),
+            outcome: Value::Null,
+        };
+        if self.0.lock().unwrap().replaying {
+            return self.replay(&interaction);
+        }
+        let res = f();
+        interaction.outcome = match &res {
+            Ok(value) => json!({ "ok": value.record() }),
+            Err(e) => json!({ "error": format!("{:#}", e), "kind": ErrorKind::of(e).as_str() }),
+        };
+        let mut inner = self.0.lock().unwrap();
+        // the call is appended as it's made, so the cassette is complete however the app exits
+        if let Err(e) = append(&mut inner, &interaction) {
+            tracing::warn!("failed to save cassette {}: {:#}", inner.path.display(), e);
+        }
+        inner.interactions.push(interaction);
+        res
+    }
+
+    fn replay<T: Replayed>(&self, call: &Interaction) -> Result<T> {
+        let key = call.key();
+        let outcome = {
+            let mut inner = self.0.lock().unwrap();
+            let recorded = inner
+                .interactions
+                .iter()
+                .filter(|interaction| interaction.key() == key)
+                .map(|interaction| interaction.outcome.clone())
+                .collect::<Vec<_>>();
+            if recorded.is_empty() {
+                bail!(
+                    "the cassette {} has no recorded call to {}",
+                    inner.path.display(),
+                    key
+                );
+            }
+            let replayed = inner.replayed.entry(key.clone()).or_default();
+            let outcome = recorded[(*replayed).min(recorded.len() - 1)].clone();
+            *replayed += 1;
+            outcome
+        };
+        if let Some(message) = outcome.get("error") {
+            let message = message.as_str().unwrap_or_default().to_string();
+            // replayed w/ the kind it was recorded w/ (see `ErrorKind::error`), unless the
+            // cassette was recorded before kinds were kept
+            return Err(
+                match outcome
+                    .get("kind")
+                    .and_then(Value::as_str)
+                    .and_then(ErrorKind::parse)
+                {
+                    Some(kind) => kind.error(message),
+                    None => anyhow::anyhow!(message),
+                },
+            );
+        }
+        let value = outcome.get("ok").with_context(|| {
+            format!(
+                "invalid cassette: the outcome of {} is neither ok, nor an error",
+                key
+            )
+        })?;
+        T::replay(value).with_context(|| format!("invalid cassette: the outcome of {}", key))
+    }
+}
+
+/// The interactions of a cassette: a line of JSON for each (or, for cassettes recorded
+/// before they were appended to, a JSON array of them).
+fn parse(contents: &str) -> Result<Vec<Interaction>> {
+    if contents.trim_start().starts_with('[') {
+        return serde_json::from_str::<Vec<Value>>(contents)?
+            .iter()
+            .map(Interaction::from_json)
+            .collect();
+    }
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Interaction::from_json(&serde_json::from_str(line)?))
+        .collect()
+}
+
+fn append(inner: &mut Inner, interaction: &Interaction) -> Result<()> {
+    if let Some(file) = inner.file.as_mut() {
+        let mut line = serde_json::to_string(&interaction.to_json())?;
+        line.push('\n');
+        // the line is written at once, so a crash leaves no half-written interactions behind
+        file.write_all(line.as_bytes())?;
+        file.flush()?;
+    }
+    Ok(())
+}
+
+/// A value (i.e., an argument, or the outcome of a call) that can be kept in a cassette.
+pub trait Recorded {
+    fn record(&self) -> Value;
+}
+
+/// The outcome of a call, which can be replayed from a cassette.
+pub trait Replayed: Recorded + Sized {
+    fn replay(value: &Value) -> Result<Self>;
+}
+
+impl Recorded for () {
+    fn record(&self) -> Value {
+        Value::Null
+    }
+}
+
+impl Replayed for () {
+    fn replay(_value: &Value) -> Result<Self> {
+        Ok(())
+    }
+}
+
+macro_rules! impl_recorded_number {
+    ($($number:ty),*) => {
+        $(impl Recorded for $number {
+            fn record(&self) -> Value {
+                json!(self)
+            }
+        }
+
+        impl Replayed for $number {
+            fn replay(value: &Value) -> Result<Self> {
+                Ok(serde_json::from_value(value.clone())?)
+            }
+        })*
+    };
+}
+
+impl_recorded_number!(bool, i64, u64, u32);
+
+impl Recorded for &str {
+    fn record(&self) -> Value {
+        Value::String(self.to_string())
+    }
+}
+
+impl Recorded for String {
+    fn record(&self) -> Value {
+        self.as_str().record()
+    }
+}
+
+impl Replayed for String {
+    fn replay(value: &Value) -> Result<Self> {
+        value
+            .as_str()
+            .map(str::to_string)
+            .with_context(|| format!("expected a string, got {}", value))
+    }
+}
+
+/// Payloads that are text are kept as strings, and others as `{ "hex": .. }`.
+impl Recorded for &[u8] {
+    fn record(&self) -> Value {
+        match std::str::from_utf8(self) {
+            Ok(text) => Value::String(text.to_string()),
+            Err(_) => json!({ "hex": hex(self) }),
+        }
+    }
+}
+
+impl Recorded for Vec<u8> {
+    fn record(&self) -> Value {
+        self.as_slice().record()
+    }
+}
+
+impl Replayed for Vec<u8> {
+    fn replay(value: &Value) -> Result<Self> {
+        if let Some(text) = value.as_str() {
+            return Ok(text.as_bytes().to_vec());
+        }
+        value
+            .get("hex")
+            .and_then(Value::as_str)
+            .and_then(unhex)
+            .with_context(|| format!("expected a payload, got {}", value))
+    }
+}
+
+impl<T: Recorded> Recorded for Vec<T> {
+    fn record(&self) -> Value {
+        Value::Array(self.iter().map(Recorded::record).collect())
+    }
+}
+
+impl<T: Replayed> Replayed for Vec<T> {
+    fn replay(value: &Value) -> Result<Self> {
+        value
+            .as_array()
+            .with_context(|| format!("expected an array, got {}", value))?
+            .iter()
+            .map(T::replay)
+            .collect()
+    }
+}
+
+impl<T: Recorded> Recorded for Option<T> {
+    fn record(&self) -> Value {
+        self.as_ref().map_or(Value::Null, Recorded::record)
+    }
+}
+
+impl<T: Replayed> Replayed for Option<T> {
+    fn replay(value: &Value) -> Result<Self> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::replay(value).map(Some),
+        }
+    }
+}
+
+impl<A: Recorded, B: Recorded> Recorded for (A, B) {
+    fn record(&self) -> Value {
+        json!([self.0.record(), self.1.record()])
+    }
+}
+
+impl<A: Replayed, B: Replayed> Replayed for (A, B) {
+    fn replay(value: &Value) -> Result<Self> {
+        match value.as_array().map(Vec::as_slice) {
+            Some([a, b]) => Ok((A::replay(a)?, B::replay(b)?)),
+            _ => bail!("expected a pair, got {}", value),
+        }
+    }
+}
+
+impl<A: Recorded, B: Recorded, C: Recorded> Recorded for (A, B, C) {
+    fn record(&self) -> Value {
+        json!([self.0.record(), self.1.record(), self.2.record()])
+    }
+}
+
+impl<A: Replayed, B: Replayed, C: Replayed> Replayed for (A, B, C) {
+    fn replay(value: &Value) -> Result<Self> {
+        match value.as_array().map(Vec::as_slice) {
+            Some([a, b, c]) => Ok((A::replay(a)?, B::replay(b)?, C::replay(c)?)),
+            _ => bail!("expected a triple, got {}", value),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::{bail, Result};
+    use tempdir::TempDir;
+
+    use super::{Cassette, CassetteMode, Recorded, Replayed};
+    use crate::{
+        call::timed_out,
+        error_kind::{ErrorKind, NotFound},
+        quota::RateLimited,
+    };
+
+    fn get(cassette: &Cassette, key: &str, backend: &str) -> Result<Vec<u8>> {
+        cassette.call("kv", "get", &[&"my-container", &key], || {
+            match backend {
+                "down" => bail!("the backend is down"),
+                "missing" => return Err(NotFound(format!("no key '{}'", key)).into()),
+                _ => {}
+            }
+            Ok(backend.as_bytes().to_vec())
+        })
+    }
+
+    #[test]
+    fn record_replay_test() -> Result<()> {
+        let dir = TempDir::new("cassette")?;
+        let path = dir.path().join("cassette.json");
+
+        let recording = Cassette::open(&path, CassetteMode::Once)?;
+        assert_eq!(get(&recording, "a", "first")?, b"first");
+        assert_eq!(get(&recording, "a", "second")?, b"second");
+        assert!(get(&recording, "b", "down").is_err());
+        let received: Result<Vec<(String, Vec<u8>)>> =
+            recording.call("mq", "receive-batch", &[&"my-queue", &10u32], || {
+                Ok(vec![("handle".to_string(), vec![0xff, 0x00])])
+            });
+        assert_eq!(received?.len(), 1);
+        // a line is appended for each call
+        let contents = std::fs::read_to_string(&path)?;
+        assert_eq!(contents.lines().count(), 4);
+        assert!(contents.contains(r#""hex":"ff00""#));
+
+        // the backend isn't called when replaying
+        let replaying = Cassette::open(&path, CassetteMode::Once)?;
+        assert_eq!(get(&replaying, "a", "down")?, b"first");
+        assert_eq!(get(&replaying, "a", "down")?, b"second");
+        // once there are no outcomes left, the last one is replayed again
+        assert_eq!(get(&replaying, "a", "down")?, b"second");
+        assert_eq!(
+            get(&replaying, "b", "up").unwrap_err().to_string(),
+            "the backend is down"
+        );
+        // calls that weren't recorded fail
+        assert!(get(&replaying, "c", "up").is_err());
+        let received: Vec<(String, Vec<u8>)> =
+            replaying.call("mq", "receive-batch", &[&"my-queue", &10u32], || {
+                bail!("the backend isn't called")
+            })?;
+        assert_eq!(received, vec![("handle".to_string(), vec![0xff, 0x00])]);
+        Ok(())
+    }
+
+    #[test]
+    fn error_kinds_test() -> Result<()> {
+        let dir = TempDir::new("cassette")?;
+        let path = dir.path().join("cassette.json");
+
+        let recording = Cassette::open(&path, CassetteMode::Record)?;
+        assert!(get(&recording, "a", "missing").is_err());
+        let limited: Result<()> = recording.call("mq", "send", &[&"my-queue"], || {
+            Err(RateLimited {
+                capability: "mq".to_string(),
+                limit: "1 ops/sec".to_string(),
+                retry_after: std::time::Duration::from_secs(1),
+            }
+            .into())
+        });
+        assert!(limited.is_err());
+        let timed: Result<()> = recording.call("lockd", "lock", &[&"my-lock"], || {
+            Err(crate::call::TimedOut("the lock is held".to_string()).into())
+        });
+        assert!(timed.is_err());
+
+        // the errors are replayed w/ the kinds they were recorded w/
+        let replaying = Cassette::open(&path, CassetteMode::Replay)?;
+        let e = get(&replaying, "a", "up").unwrap_err();
+        assert!(NotFound::is(&e));
+        assert_eq!(e.to_string(), "no key 'a'");
+        let e = replaying
+            .call("mq", "send", &[&"my-queue"], || Ok(()))
+            .unwrap_err();
+        assert_eq!(ErrorKind::of(&e), ErrorKind::RateLimited);
+        let e = replaying
+            .call("lockd", "lock", &[&"my-lock"], || Ok(()))
+            .unwrap_err();
+        assert!(timed_out(&e));
+        assert_eq!(ErrorKind::of(&e), ErrorKind::Timeout);
+        Ok(())
+    }
+
+    #[test]
+    fn json_array_test() -> Result<()> {
+        let dir = TempDir::new("cassette")?;
+        let path = dir.path().join("cassette.json");
+        std::fs::write(
+            &path,
+            r#"[
+  {
+    "capability": "kv",
+    "operation": "get",
+    "arguments": ["my-container", "a"],
+    "outcome": { "ok": "before" }
+  },
+  {
+    "capability": "kv",
+    "operation": "get",
+    "arguments": ["my-container", "b"],
+    "outcome": { "error": "the backend is down" }
+  }
+]"#,
+        )?;
+
+        let replaying = Cassette::open(&path, CassetteMode::Replay)?;
+        assert_eq!(get(&replaying, "a", "down")?, b"before");
+        let e = get(&replaying, "b", "up").unwrap_err();
+        assert_eq!(ErrorKind::of(&e), ErrorKind::Backend);
+        Ok(())
+    }
+
+    #[test]
+    fn replay_missing_test() {
+        let dir = TempDir::new("cassette").unwrap();
+        assert!(Cassette::open(&dir.path().join("missing.json"), CassetteMode::Replay).is_err());
+        assert!(CassetteMode::parse("rewind").is_err());
+    }
+
+    #[test]
+    fn payloads_test() -> Result<()> {
+        assert_eq!(b"text".as_slice().record(), "text");
+        let binary = vec![0xde, 0xad];
+        assert_eq!(Vec::<u8>::replay(&binary.record())?, binary);
+        assert_eq!(Option::<Vec<u8>>::replay(&None::<Vec<u8>>.record())?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,340 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use slight_events_api::Event;
+use tracing::{span::EnteredSpan, Span};
+
+/// The extension attribute of an event that holds the id of the span it was caused in.
+pub const CAUSE_EXTENSION: &str = "slightcause";
+
+/// How many causes are kept at most, of each kind — the oldest are forgotten first (e.g.,
+/// those of events no one handled).
+const MAX_CAUSES: usize = 1024;
+
+/// How long causes are kept, if they aren't taken before (e.g., those of events no one
+/// handled) — an event handled later than this is traced as an orphan, rather than under a
+/// span that ended long ago.
+const CAUSE_TTL: Duration = Duration::from_secs(60);
+
+/// The spans events were caused in, which the guest phases handling them are traced under,
+/// even though they run on other threads (e.g., the guest making a capability call while
+/// handling an http request, and the event handler it triggers).
+///
+/// It holds:
+///     - the `next_id` of an event's cause,
+///     - the spans `events` were caused in (see `mark`), by the id their `CAUSE_EXTENSION`
+///     holds, until they're handled (see `enter`), and
+///     - the spans the last writes to watched resources were made in (see `defer`), by the
+///     resource, for events emitted for them from other threads later (e.g., a watcher's), and
+///     - the resources that are `watched` (see `watch`), w/ how many watchers each has.
+///
+/// Each cause is kept w/ when it was recorded, and expires after `CAUSE_TTL`.
+struct Causes {
+    next_id: u64,
+    events: Vec<(u64, Instant, Span)>,
+    deferred: Vec<(String, Instant, Span)>,
+    watched: Vec<(String, usize)>,
+}
+
+static CAUSES: Mutex<Causes> = Mutex::new(Causes {
+    next_id: 1,
+    events: Vec::new(),
+    deferred: Vec::new(),
+    watched: Vec::new(),
+});
+
+/// `Watched` is a resource being watched (e.g., by a file watcher), whose writes' causes are
+/// remembered (see `defer`) until it's dropped.
+#[derive(Debug)]
+pub struct Watched(String);
+
+impl Watched {
+    pub fn resource(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Drop for Watched {
+    fn drop(&mut self) {
+        let mut causes = CAUSES.lock().unwrap();
+        if let Some(i) = causes.watched.iter().position(|(r, _)| *r == self.0) {
+            causes.watched[i].1 -= 1;
+            if causes.watched[i].1 == 0 {
+                causes.watched.remove(i);
+                causes
+                    .deferred
+                    .retain(|(deferred, _, _)| *deferred != self.0);
+            }
+        }
+    }
+}
+
+/// Watches `resource` (e.g., the path of a file) for as long as the returned `Watched` lives,
+/// so the causes of writes to it are remembered for the events emitted for them — writes to
+/// resources no one watches aren't.
+pub fn watch(resource: &str) -> Watched {
+    let mut causes = CAUSES.lock().unwrap();
+    match causes.watched.iter_mut().find(|(r, _)| r == resource) {
+        Some((_, watchers)) => *watchers += 1,
+        None => causes.watched.push((resource.to_string(), 1)),
+    }
+    Watched(resource.to_string())
+}
+
+/// Marks `event` as caused in the current span (e.g., the capability call the guest is
+/// making), if there's one.
+pub fn mark(event: &mut Event) {
+    mark_with(event, Span::current());
+}
+
+/// Remembers the current span as the cause of the events emitted for a write to `resource`
+/// (e.g., the path of a file) later, and from another thread (see `mark_deferred`), if it's
+/// watched (see `watch`).
+pub fn defer(resource: &str) {
+    let span = Span::current();
+    if span.is_none() {
+        return;
+    }
+    let mut causes = CAUSES.lock().unwrap();
+    if !causes.watched.iter().any(|(r, _)| r == resource) {
+        return;
+    }
+    causes
+        .deferred
+        .retain(|(deferred, _, _)| deferred != resource);
+    push_bounded(
+        &mut causes.deferred,
+        (resource.to_string(), Instant::now(), span),
+    );
+}
+
+/// Marks `event` as caused in the span the last write to `resource` was made in (see
+/// `defer`), if there was one.
+pub fn mark_deferred(event: &mut Event, resource: &str) {
+    let span = CAUSES
+        .lock()
+        .unwrap()
+        .deferred
+        .iter()
+        .find(|(deferred, at, _)| deferred == resource && at.elapsed() < CAUSE_TTL)
+        .map(|(_, _, span)| span.clone());
+    if let Some(span) = span {
+        mark_with(event, span);
+    }
+}
+
+/// Enters the span `event` was caused in, if it was marked (in this process), for as long as
+/// the returned span lives — so the guest phase handling it is traced under it, rather than
+/// as an orphan of the thread it's handled on.
+pub fn enter(event: &Event) -> Option<EnteredSpan> {
+    take(event).map(Span::entered)
+}
+
+/// Forgets the span `event` was caused in (e.g., as it's handled w/ other events, under the
+/// cause of the first of them).
+pub fn forget(event: &Event) {
+    take(event);
+}
+
+fn take(event: &Event) -> Option<Span> {
+    let id = event
+        .extension(CAUSE_EXTENSION)?
+        .to_string()
+        .parse::<u64>()
+        .ok()?;
+    let mut causes = CAUSES.lock().unwrap();
+    let i = causes
+        .events
+        .iter()
+        .position(|(cause, _, _)| *cause == id)?;
+    let (_, at, span) = causes.events.remove(i);
+    (at.elapsed() < CAUSE_TTL).then_some(span)
+}
+
+fn mark_with(event: &mut Event, span: Span) {
+    if span.is_none() {
+        return;
+    }
+    let id = {
+        let mut causes = CAUSES.lock().unwrap();
+        let id = causes.next_id;
+        causes.next_id += 1;
+        push_bounded(&mut causes.events, (id, Instant::now(), span));
+        id
+    };
+    event.set_extension(CAUSE_EXTENSION, id.to_string());
+}
+
+/// Records a cause, forgetting those that expired (see `CAUSE_TTL`), and the oldest past
+/// `MAX_CAUSES`.
+fn push_bounded<K>(causes: &mut Vec<(K, Instant, Span)>, cause: (K, Instant, Span)) {
+    causes.retain(|(_, at, _)| at.elapsed() < CAUSE_TTL);
+    if causes.len() >= MAX_CAUSES {
+        causes.remove(0);
+    }
+    causes.push(cause);
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use anyhow::Result;
+    use slight_events_api::Event;
+    use tracing::{
+        span::{Attributes, Id},
+        Subscriber,
+    };
+    use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+    use super::{defer, enter, mark, mark_deferred, watch, CAUSES};
+    use crate::{
+        call::{guest_phase, instrument, CallSettings},
+        trace::Fields,
+    };
+
+    /// Records the name of each span (i.e., its phase, or its capability call), w/ the names
+    /// of its ancestors, innermost first.
+    #[derive(Clone, Default)]
+    struct Hierarchy(Arc<Mutex<Vec<Vec<String>>>>);
+
+    struct Name(String);
+
+    impl<S> Layer<S> for Hierarchy
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            let name = match (fields.0.get("phase"), fields.0.get("operation")) {
+                (Some(phase), _) => phase.as_str().unwrap().to_string(),
+                (_, Some(operation)) => format!(
+                    "{}.{}",
+                    fields.0["capability"].as_str().unwrap(),
+                    operation.as_str().unwrap()
+                ),
+                _ => attrs.metadata().name().to_string(),
+            };
+            let span = ctx.span(id).unwrap();
+            let ancestors = span
+                .scope()
+                .skip(1)
+                .map(|ancestor| ancestor.extensions().get::<Name>().unwrap().0.clone());
+            let hierarchy = std::iter::once(name.clone()).chain(ancestors).collect();
+            span.extensions_mut().insert(Name(name));
+            self.0.lock().unwrap().push(hierarchy);
+        }
+    }
+
+    fn call(capability: &str, operation: &str, f: impl FnOnce()) {
+        let res: Result<()> = instrument(
+            &CallSettings::default(),
+            capability,
+            operation,
+            "key",
+            || {
+                f();
+                Ok(())
+            },
+        );
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn request_triggers_event_test() {
+        let hierarchy = Hierarchy::default();
+        let dispatch =
+            tracing::Dispatch::new(tracing_subscriber::registry().with(hierarchy.clone()));
+
+        tracing::dispatcher::with_default(&dispatch, || {
+            let mut event = Event::default();
+            let mut deferred = Event::default();
+            let _watched = watch("/tmp/kv/orders");
+            {
+                let _phase = guest_phase("http GET /orders");
+                // e.g., health events are emitted while the call is made
+                call("kv", "set", || mark(&mut event));
+                // e.g., watchers emit theirs once the write is noticed
+                call("kv", "delete", || defer("/tmp/kv/orders"));
+            }
+            mark_deferred(&mut deferred, "/tmp/kv/orders");
+
+            // the events are handled on threads of their own
+            for (event, operation) in [(event, "get"), (deferred, "exists")] {
+                let dispatch = dispatch.clone();
+                thread::spawn(move || {
+                    tracing::dispatcher::with_default(&dispatch, || {
+                        let _cause = enter(&event);
+                        let _phase = guest_phase("event");
+                        call("kv", operation, || {});
+                    })
+                })
+                .join()
+                .unwrap();
+            }
+        });
+
+        let hierarchy = hierarchy.0.lock().unwrap();
+        assert!(hierarchy.contains(&vec![
+            "kv.get".to_string(),
+            "event".to_string(),
+            "kv.set".to_string(),
+            "http GET /orders".to_string(),
+        ]));
+        assert!(hierarchy.contains(&vec![
+            "kv.exists".to_string(),
+            "event".to_string(),
+            "kv.delete".to_string(),
+            "http GET /orders".to_string(),
+        ]));
+    }
+
+    #[test]
+    fn unwatched_test() {
+        let subscriber = tracing_subscriber::registry();
+        tracing::subscriber::with_default(subscriber, || {
+            let _phase = guest_phase("http GET /orders");
+            let deferred = |resource: &str| {
+                CAUSES
+                    .lock()
+                    .unwrap()
+                    .deferred
+                    .iter()
+                    .any(|(deferred, _, _)| deferred == resource)
+            };
+            // writes to resources no one watches aren't remembered
+            call("kv", "set", || defer("/tmp/kv/unwatched"));
+            assert!(!deferred("/tmp/kv/unwatched"));
+
+            // nor are they once the watcher's gone
+            let watched = watch("/tmp/kv/watched");
+            call("kv", "set", || defer("/tmp/kv/watched"));
+            assert!(deferred("/tmp/kv/watched"));
+            drop(watched);
+            assert!(!deferred("/tmp/kv/watched"));
+        });
+    }
+
+    #[test]
+    fn unmarked_event_test() {
+        let hierarchy = Hierarchy::default();
+        let subscriber = tracing_subscriber::registry().with(hierarchy.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            // events emitted outside of any span (e.g., by a watcher), have no cause
+            let mut event = Event::default();
+            mark(&mut event);
+            assert!(enter(&event).is_none());
+            let _phase = guest_phase("event");
+        });
+        assert_eq!(
+            *hierarchy.0.lock().unwrap(),
+            vec![vec!["event".to_string()]]
+        );
+    }
+}
@@ -0,0 +1,335 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    call::{Call, Outcome, TimedOut},
+    quota::RateLimited,
+    resource::ResourceMap,
+};
+
+/// The name `Chaos` is shared under in the `StateTable` (see `Chaos::install`).
+pub const CHAOS: &str = "slight.chaos";
+
+/// The env var that must be set to `CHAOS_ENV_VALUE` for chaos to be injected (see `gate`).
+pub const CHAOS_ENV_VAR: &str = "SLIGHT_CHAOS";
+
+pub const CHAOS_ENV_VALUE: &str = "enabled";
+
+/// How long latency, and timeouts last, unless the settings say otherwise.
+pub const DEFAULT_CHAOS_LATENCY: Duration = Duration::from_millis(500);
+
+/// A fault injected into a capability call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// the call is delayed by the latency, and then made as usual
+    Latency,
+    /// the call fails as if the backend did (i.e., w/ an `error-with-description`)
+    Error,
+    /// the call fails w/ a `timeout`, after the latency (i.e., as if it waited for the backend)
+    Timeout,
+    /// the call fails w/ `rate-limited`, as if the capability's quota was exceeded
+    RateLimited,
+}
+
+impl Fault {
+    pub fn parse(fault: &str) -> Result<Self> {
+        match fault {
+            "latency" => Ok(Self::Latency),
+            "error" => Ok(Self::Error),
+            "timeout" => Ok(Self::Timeout),
+            "rate_limited" => Ok(Self::RateLimited),
+            _ => bail!(
+                "invalid chaos fault: '{}' (expected 'latency', 'error', 'timeout', or 'rate_limited')",
+                fault
+            ),
+        }
+    }
+}
+
+/// The chaos injected into the calls of a capability.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChaosSettings {
+    /// the share of calls a fault is injected into, from 0 to 1
+    pub rate: f64,
+    /// the faults injected, one of which is picked at random for each call
+    pub faults: Vec<Fault>,
+    pub latency: Duration,
+    /// the operations faults are injected into (e.g., `get`), if not all of them
+    pub operations: Option<Vec<String>>,
+}
+
+impl ChaosSettings {
+    /// Checks the `rate` is w/in 0, and 1, and that there are faults to inject.
+    pub fn validate(self) -> Result<Self> {
+        if !(0.0..=1.0).contains(&self.rate) {
+            bail!("invalid chaos rate: {} (expected 0 to 1)", self.rate);
+        }
+        if self.faults.is_empty() {
+            bail!("invalid chaos faults: inject at least one");
+        }
+        if self.operations.as_ref().map_or(false, Vec::is_empty) {
+            bail!("invalid chaos operations: inject into at least one, or leave it out to inject into all");
+        }
+        Ok(self)
+    }
+
+    fn injects(&self, operation: &str) -> bool {
+        self.operations
+            .as_ref()
+            .map_or(true, |operations| operations.iter().any(|o| o == operation))
+    }
+}
+
+/// Whether chaos is injected: only w/ both the explicit `flag` (i.e., `slight run --chaos`),
+/// and the `env` gate (i.e., the value of `CHAOS_ENV_VAR`) — so neither a slightfile w/
+/// chaos, nor a stray env var alone can enable it (e.g., in production).
+///
+/// The flag w/o the env var fails, rather than silently running w/o chaos.
+pub fn gate(flag: bool, env: Option<&str>) -> Result<bool> {
+    match (flag, env) {
+        (false, _) => Ok(false),
+        (true, Some(CHAOS_ENV_VALUE)) => Ok(true),
+        (true, _) => bail!(
+            "--chaos needs {}={} too, so it can't be enabled by accident",
+            CHAOS_ENV_VAR,
+            CHAOS_ENV_VALUE
+        ),
+    }
+}
+
+/// `Chaos` injects faults into the calls of an app's capabilities at the rate of their
+/// settings, so the guest's handling of backend failures (e.g., its retries, or how it
+/// degrades) can be tested before a real outage does.
+///
+/// Faults are injected where calls are instrumented (see `BasicState::instrument`), so they
+/// fail w/ the same errors the guest would get from a real backend (i.e., the variants of
+/// `types.wit`'s `error`), and they're counted in the metrics, and health like those.
+///
+/// It's shared w/ the capabilities through the app's `StateTable` (see `install`), and its
+/// clones share the random numbers it picks faults w/, which are seeded for runs to be
+/// reproducible, if it has a seed.
+#[derive(Clone, Debug)]
+pub struct Chaos(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    /// the settings of the capabilities, by scheme (e.g., `kv`)
+    capabilities: HashMap<String, ChaosSettings>,
+    rng: Mutex<StdRng>,
+    /// how many faults were injected into the calls of each capability
+    injected: Mutex<HashMap<String, u64>>,
+}
+
+impl Chaos {
+    pub fn new(capabilities: HashMap<String, ChaosSettings>, seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self(Arc::new(Inner {
+            capabilities,
+            rng: Mutex::new(rng),
+            injected: Mutex::default(),
+        }))
+    }
+
+    /// Installs the chaos in the `StateTable` of an app, so its capabilities inject it.
+    ///
+    /// It must be installed before the capabilities are linked (i.e., before their
+    /// `BasicState` is created).
+    pub fn install(&self, resource_map: &ResourceMap) -> Result<()> {
+        resource_map
+            .lock()
+            .unwrap()
+            .shared(CHAOS, || self.clone())?;
+        Ok(())
+    }
+
+    /// How many faults were injected into the calls of `capability`.
+    pub fn injected(&self, capability: &str) -> u64 {
+        self.0
+            .injected
+            .lock()
+            .unwrap()
+            .get(capability)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Injects a fault into a call, if it's picked to get one: the outcome of the faults that
+    /// fail it, or `None` if it goes to the capability's backend (i.e., it got none, or it was
+    /// only delayed).
+    pub(crate) fn inject<T: Outcome>(&self, call: &Call<'_>) -> Option<T> {
+        let settings = self
+            .0
+            .capabilities
+            .get(call.capability)
+            .filter(|settings| settings.injects(call.operation))?;
+        let fault = {
+            let mut rng = self.0.rng.lock().unwrap();
+            if !rng.gen_bool(settings.rate) {
+                return None;
+            }
+            settings.faults[rng.gen_range(0..settings.faults.len())]
+        };
+        *self
+            .0
+            .injected
+            .lock()
+            .unwrap()
+            .entry(call.capability.to_string())
+            .or_default() += 1;
+        tracing::info!(
+            "chaos: injecting {:?} into {}.{} on '{}'",
+            fault,
+            call.capability,
+            call.operation,
+            call.target
+        );
+        match fault {
+            Fault::Latency => {
+                std::thread::sleep(settings.latency);
+                None
+            }
+            Fault::Error => Some(T::from_error(anyhow::anyhow!(
+                "chaos: injected a backend error into {}.{}",
+                call.capability,
+                call.operation
+            ))),
+            Fault::Timeout => {
+                std::thread::sleep(settings.latency);
+                Some(T::from_error(
+                    TimedOut(format!(
+                        "chaos: injected a timeout into {}.{}",
+                        call.capability, call.operation
+                    ))
+                    .into(),
+                ))
+            }
+            Fault::RateLimited => Some(T::from_error(
+                RateLimited {
+                    capability: call.capability.to_string(),
+                    limit: "chaos".to_string(),
+                    retry_after: settings.latency,
+                }
+                .into(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::{collections::HashMap, time::Duration};
+
+    use anyhow::Result;
+
+    use super::{gate, Chaos, ChaosSettings, Fault, CHAOS_ENV_VALUE};
+    use crate::{
+        call::timed_out,
+        error_kind::ErrorKind,
+        resource::{BasicState, ResourceMap},
+    };
+
+    fn settings(rate: f64, faults: &[Fault]) -> ChaosSettings {
+        ChaosSettings {
+            rate,
+            faults: faults.to_vec(),
+            latency: Duration::from_millis(1),
+            operations: None,
+        }
+    }
+
+    fn get(state: &BasicState) -> Result<Vec<u8>> {
+        state.instrument("kv", "get", "my-key", || Ok(b"from the backend".to_vec()))
+    }
+
+    #[test]
+    fn gate_test() {
+        assert!(!gate(false, Some(CHAOS_ENV_VALUE)).unwrap());
+        assert!(!gate(false, None).unwrap());
+        assert!(gate(true, Some(CHAOS_ENV_VALUE)).unwrap());
+        // the flag alone isn't enough
+        assert!(gate(true, None).is_err());
+        assert!(gate(true, Some("1")).is_err());
+    }
+
+    #[test]
+    fn inject_test() -> Result<()> {
+        let resource_map = ResourceMap::default();
+        let chaos = Chaos::new(
+            HashMap::from([
+                ("kv".to_string(), settings(1.0, &[Fault::Timeout])),
+                ("mq".to_string(), settings(0.0, &[Fault::Error])),
+            ]),
+            Some(42),
+        );
+        chaos.install(&resource_map)?;
+        let state = BasicState::new(resource_map, &[], "slightfile.toml");
+
+        // the faults are the errors a real backend fails w/
+        let e = get(&state).unwrap_err();
+        assert!(timed_out(&e));
+        assert_eq!(ErrorKind::of(&e), ErrorKind::Timeout);
+        assert_eq!(chaos.injected("kv"), 1);
+
+        // a capability at a rate of 0, or w/o chaos gets none
+        let res: Result<()> = state.instrument("mq", "send", "my-queue", || Ok(()));
+        assert!(res.is_ok());
+        let res: Result<()> = state.instrument("lockd", "lock", "my-lock", || Ok(()));
+        assert!(res.is_ok());
+        assert_eq!(chaos.injected("mq"), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn rate_test() -> Result<()> {
+        let resource_map = ResourceMap::default();
+        let chaos = Chaos::new(
+            HashMap::from([(
+                "kv".to_string(),
+                ChaosSettings {
+                    operations: Some(vec!["get".to_string()]),
+                    ..settings(0.5, &[Fault::Error, Fault::RateLimited, Fault::Latency])
+                },
+            )]),
+            Some(7),
+        );
+        chaos.install(&resource_map)?;
+        let state = BasicState::new(resource_map, &[], "slightfile.toml");
+
+        let mut kinds = HashMap::new();
+        for _ in 0..400 {
+            let kind = get(&state).err().map(|e| ErrorKind::of(&e));
+            *kinds.entry(kind).or_insert(0_u64) += 1;
+        }
+        let injected = chaos.injected("kv");
+        assert!((150..=250).contains(&injected), "{}", injected);
+        assert!(kinds[&Some(ErrorKind::Backend)] > 0);
+        assert!(kinds[&Some(ErrorKind::RateLimited)] > 0);
+        // those only delayed got to the backend
+        assert!(kinds[&None] > 400 - injected);
+
+        // other operations get none
+        for _ in 0..20 {
+            let res: Result<()> = state.instrument("kv", "set", "my-key", || Ok(()));
+            assert!(res.is_ok());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn validate_test() {
+        assert!(settings(0.1, &[Fault::Latency]).validate().is_ok());
+        assert!(settings(1.5, &[Fault::Latency]).validate().is_err());
+        assert!(settings(0.1, &[]).validate().is_err());
+        assert!(Fault::parse("rate_limited").is_ok());
+        assert!(Fault::parse("explode").is_err());
+    }
+}
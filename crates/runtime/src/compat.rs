@@ -0,0 +1,203 @@
+use std::fmt;
+
+use anyhow::Result;
+
+/// `Version` is the version of a backend's server, as it reports it (e.g., `3.5.9`, or
+/// `v3.4.0-rc.1`, where what follows the patch is ignored).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl Version {
+    pub const fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parses a version, where a missing minor, or patch is a 0 (e.g., `3.4` is `3.4.0`), or
+    /// `None` if it isn't one.
+    pub fn parse(version: &str) -> Option<Self> {
+        let version = version.trim();
+        let version = version.strip_prefix('v').unwrap_or(version);
+        let core = version.split(['-', '+']).next()?;
+        let mut parts = core.split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next().map_or(Some(0), |minor| minor.parse().ok())?;
+        let patch = parts.next().map_or(Some(0), |patch| patch.parse().ok())?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self::new(major, minor, patch))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// `Requirement` is the oldest version of a backend's server that has a `feature` an
+/// implementor uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Requirement {
+    /// the feature, as it's named in the error of a server that's too old (e.g., `the lock
+    /// API`)
+    pub feature: &'static str,
+    pub minimum: Version,
+}
+
+/// `Compatibility` is what an implementor needs of the server of its backend, which it checks
+/// when it connects to it (see `Compatibility::check`), so a server that's too old fails at
+/// startup w/ what it is, and what's needed, rather than on first use w/ whatever error the
+/// server answers an unknown request w/.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Compatibility {
+    /// the implementor of the capability (e.g., `lockd.etcd`)
+    pub implementor: &'static str,
+    /// the server, as it's named in errors (e.g., `etcd`)
+    pub server: &'static str,
+    pub requirements: &'static [Requirement],
+    /// the major version of the server the implementor is tested w/, newer ones are warned
+    /// about, as they may have dropped what it uses
+    pub tested_major: u64,
+}
+
+impl Compatibility {
+    /// Checks the version the server says it is, failing w/ `Incompatible` if it's older than
+    /// one of the `requirements`.
+    ///
+    /// A version that can't be parsed is only warned about, as the server may just report it
+    /// in a way this doesn't know of (e.g., a managed service's own).
+    pub fn check(&self, detected: &str) -> Result<()> {
+        let version = match Version::parse(detected) {
+            Some(version) => version,
+            None => {
+                tracing::warn!(
+                    "{} couldn't tell the version of its {} server (i.e., '{}'), assuming it's compatible",
+                    self.implementor,
+                    self.server,
+                    detected
+                );
+                return Ok(());
+            }
+        };
+        if let Some(requirement) = self
+            .requirements
+            .iter()
+            .find(|requirement| version < requirement.minimum)
+        {
+            return Err(Incompatible {
+                implementor: self.implementor.to_string(),
+                server: self.server.to_string(),
+                detected: version,
+                feature: requirement.feature.to_string(),
+                required: requirement.minimum,
+            }
+            .into());
+        }
+        if version.major > self.tested_major {
+            tracing::warn!(
+                "{} is tested w/ {} {}.x, not {}, which may not be compatible",
+                self.implementor,
+                self.server,
+                self.tested_major,
+                version
+            );
+        }
+        tracing::debug!(
+            "{} is connected to {} {}",
+            self.implementor,
+            self.server,
+            version
+        );
+        Ok(())
+    }
+}
+
+/// `Incompatible` is the error of a backend's server being older than what a feature an
+/// implementor uses needs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Incompatible {
+    pub implementor: String,
+    pub server: String,
+    pub detected: Version,
+    pub feature: String,
+    pub required: Version,
+}
+
+impl Incompatible {
+    /// Whether an error was caused by an incompatible server.
+    pub fn is(error: &anyhow::Error) -> bool {
+        error.downcast_ref::<Self>().is_some()
+    }
+}
+
+impl fmt::Display for Incompatible {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} needs {} {}, or newer for {}, but the server is {} {}",
+            self.implementor, self.server, self.required, self.feature, self.server, self.detected
+        )
+    }
+}
+
+impl std::error::Error for Incompatible {}
+
+#[cfg(test)]
+mod unittests {
+    use super::{Compatibility, Incompatible, Requirement, Version};
+
+    const ETCD: Compatibility = Compatibility {
+        implementor: "lockd.etcd",
+        server: "etcd",
+        requirements: &[
+            Requirement {
+                feature: "leases",
+                minimum: Version::new(3, 0, 0),
+            },
+            Requirement {
+                feature: "the lock API",
+                minimum: Version::new(3, 2, 0),
+            },
+        ],
+        tested_major: 3,
+    };
+
+    #[test]
+    fn version_test() {
+        assert_eq!(Version::parse("3.5.9"), Some(Version::new(3, 5, 9)));
+        assert_eq!(Version::parse("v3.4.0-rc.1"), Some(Version::new(3, 4, 0)));
+        assert_eq!(Version::parse("3.4"), Some(Version::new(3, 4, 0)));
+        assert_eq!(Version::parse("2.3.8+git"), Some(Version::new(2, 3, 8)));
+        assert_eq!(Version::parse("not a version"), None);
+        assert_eq!(Version::parse("1.2.3.4"), None);
+        assert!(Version::new(3, 10, 0) > Version::new(3, 9, 9));
+    }
+
+    #[test]
+    fn compatibility_test() {
+        assert!(ETCD.check("3.5.9").is_ok());
+        assert!(ETCD.check("3.2.0").is_ok());
+        // newer majors, and versions that can't be parsed are only warned about
+        assert!(ETCD.check("4.0.0").is_ok());
+        assert!(ETCD.check("managed").is_ok());
+
+        let e = ETCD.check("3.1.20").unwrap_err();
+        assert!(Incompatible::is(&e));
+        assert_eq!(
+            e.to_string(),
+            "lockd.etcd needs etcd 3.2.0, or newer for the lock API, but the server is etcd 3.1.20"
+        );
+        // the first requirement that isn't met is the one reported
+        let e = ETCD.check("2.3.8").unwrap_err();
+        assert!(e.to_string().contains("for leases"));
+    }
+}
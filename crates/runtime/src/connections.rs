@@ -0,0 +1,181 @@
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, Weak},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::resource::StateTable;
+
+/// The name `Connections` are shared under in the `StateTable` (see `Connections::shared`).
+pub const CONNECTIONS: &str = "connections";
+
+/// `Connections` are the backend clients (e.g., an Azure container client, or an etcd client,
+/// w/ their connection pools) of an app, shared by all of its guest instances (i.e., the
+/// main one, and the events, http, and init ones), so N instances opening a store don't open
+/// N× the connections.
+///
+/// A connection is opened by the first instance that needs it, and kept for as long as any of
+/// them holds it — the table only holds it weakly, so once every instance released it (see
+/// `release::Releasable`), it's closed, and the next one to need it opens it anew.
+///
+/// Opening one connection only blocks the instances waiting for that one, and the instances
+/// sharing a connection call it concurrently, so what's shared must be thread-safe (i.e.,
+/// `Send`, and `Sync`), which is what backend clients are for.
+#[derive(Clone, Default)]
+pub struct Connections(Arc<Mutex<HashMap<String, Slot>>>);
+
+/// Where a connection is kept, locked while it's opened, so it's opened once.
+type Slot = Arc<Mutex<Weak<dyn Any + Send + Sync>>>;
+
+impl fmt::Debug for Connections {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Connections")
+    }
+}
+
+impl Connections {
+    /// Gets the connections of the app whose `StateTable` this is, creating them if there are
+    /// none yet.
+    pub fn shared(state_table: &mut StateTable) -> Self {
+        state_table
+            .shared(CONNECTIONS, Self::default)
+            .expect("the name of the connections is reserved")
+    }
+
+    /// Gets the connection `key` names (e.g., `kv.azblob:orders`), opening it w/ `open` if no
+    /// instance holds it.
+    pub fn get_or_open<T>(&self, key: &str, open: impl FnOnce() -> T) -> Arc<T>
+    where
+        T: Send + Sync + 'static,
+    {
+        // note: keys are namespaced by implementor, so a connection is only ever of one type
+        self.try_get_or_open(key, || Ok(open()))
+            .unwrap_or_else(|e| panic!("{:#}", e))
+    }
+
+    /// Like `get_or_open`, but for connections whose opening can fail, in which case none is
+    /// kept, so the next instance to need it tries again.
+    pub fn try_get_or_open<T>(&self, key: &str, open: impl FnOnce() -> Result<T>) -> Result<Arc<T>>
+    where
+        T: Send + Sync + 'static,
+    {
+        let slot = self
+            .0
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_insert_with(|| {
+                let closed: Weak<dyn Any + Send + Sync> = Weak::<()>::new();
+                Arc::new(Mutex::new(closed))
+            })
+            .clone();
+        // the map is unlocked, so only the instances waiting for this connection wait for it
+        let mut slot = slot.lock().unwrap();
+        if let Some(connection) = slot.upgrade() {
+            return connection
+                .downcast::<T>()
+                .map_err(|_| anyhow!("connection '{}' is of another type", key));
+        }
+        tracing::debug!("opening connection '{}'", key);
+        let connection = Arc::new(open()?);
+        let erased: Arc<dyn Any + Send + Sync> = connection.clone();
+        *slot = Arc::downgrade(&erased);
+        Ok(connection)
+    }
+
+    /// How many connections are open (i.e., held by an instance), where those being opened
+    /// aren't counted yet.
+    pub fn open(&self) -> usize {
+        self.0
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|slot| {
+                slot.try_lock()
+                    .map_or(false, |slot| slot.strong_count() > 0)
+            })
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    use anyhow::bail;
+
+    use super::Connections;
+    use crate::resource::StateTable;
+
+    #[test]
+    fn connections_test() {
+        let mut state_table = StateTable::default();
+        let connections = Connections::shared(&mut state_table);
+        let opened = AtomicUsize::new(0);
+        let open = || {
+            opened.fetch_add(1, Ordering::SeqCst);
+            String::from("client")
+        };
+
+        // another instance of the app gets the same connection
+        let first = connections.get_or_open("kv.azblob:orders", open);
+        let second = Connections::shared(&mut state_table).get_or_open("kv.azblob:orders", open);
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(opened.load(Ordering::SeqCst), 1);
+        connections.get_or_open("kv.azblob:invoices", open);
+        assert_eq!(opened.load(Ordering::SeqCst), 2);
+        assert_eq!(connections.open(), 1);
+
+        // once no instance holds it, it's closed, and opened anew
+        drop((first, second));
+        assert_eq!(connections.open(), 0);
+        let orders = connections.get_or_open("kv.azblob:orders", open);
+        assert_eq!(opened.load(Ordering::SeqCst), 3);
+        assert_eq!(*orders, "client");
+        assert!(connections
+            .try_get_or_open("kv.azblob:orders", || Ok(42_u32))
+            .is_err());
+    }
+
+    #[test]
+    fn concurrent_open_test() -> anyhow::Result<()> {
+        let connections = Connections::default();
+        let opened = Arc::new(AtomicUsize::new(0));
+        let instances = (0..8)
+            .map(|_| {
+                let (connections, opened) = (connections.clone(), opened.clone());
+                thread::spawn(move || {
+                    connections.get_or_open("lockd.etcd", || {
+                        opened.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(std::time::Duration::from_millis(10));
+                        "client"
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        let clients = instances
+            .into_iter()
+            .map(|instance| instance.join().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(opened.load(Ordering::SeqCst), 1);
+        assert!(clients.windows(2).all(|w| Arc::ptr_eq(&w[0], &w[1])));
+
+        // a failed open keeps nothing, so the next one tries again
+        let failed = connections.try_get_or_open::<&str>("mq.azsbus:orders", || bail!("refused"));
+        assert!(failed.is_err());
+        assert_eq!(
+            *connections.try_get_or_open("mq.azsbus:orders", || Ok("client"))?,
+            "client"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+/// How often credentials w/o a time to live are refetched, so that rotated ones get picked up.
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Credentials w/ a time to live are refetched once this fraction (in percent) of it has
+/// elapsed, leaving some room for the refetch to be retried before they expire.
+const REFRESH_AT_PERCENT: u32 = 80;
+
+/// A `Credential` is a secret used to authenticate against a backend (e.g., an access key),
+/// and how long it is valid for — if its provider says so.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Credential {
+    pub value: Vec<u8>,
+    pub time_to_live: Option<Duration>,
+}
+
+/// `CredentialsError` tells apart the ways a backend can reject the credentials it was
+/// given, each holding what the backend said about it.
+///
+/// Backends attach it (as context) to the errors they fail w/, so capabilities can report
+/// it to guests as such (see `CredentialsError::of`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CredentialsError {
+    /// The credentials were valid, but have expired (e.g., they were rotated).
+    Expired(String),
+    /// The credentials are wrong (e.g., a mistyped, or revoked key).
+    Invalid(String),
+    /// The credentials are valid, but aren't allowed to do what was asked.
+    PermissionDenied(String),
+}
+
+impl CredentialsError {
+    /// The `CredentialsError` an error was caused by, if any.
+    pub fn of(error: &anyhow::Error) -> Option<&Self> {
+        error.downcast_ref::<Self>()
+    }
+}
+
+impl fmt::Display for CredentialsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Expired(detail) => write!(
+                f,
+                "the credentials have expired, they'll be refetched for the next call ({})",
+                detail
+            ),
+            Self::Invalid(detail) => write!(
+                f,
+                "the credentials are invalid, check the secrets they're read from ({})",
+                detail
+            ),
+            Self::PermissionDenied(detail) => write!(
+                f,
+                "the credentials aren't allowed to do this, check what they're granted ({})",
+                detail
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CredentialsError {}
+
+/// `Credentials` fetch, and cache the credentials backends authenticate w/, refetching each
+/// one before it expires, so backends pick up rotated credentials w/o the app being restarted.
+///
+/// They are shared by all capabilities of an app, so a credential used by many backends is
+/// fetched (and refreshed) once.
+///
+/// If refetching a credential fails, the cached one keeps being used until it expires.
+#[derive(Clone, Debug, Default)]
+pub struct Credentials {
+    cache: Arc<Mutex<HashMap<String, Cached>>>,
+}
+
+#[derive(Debug)]
+struct Cached {
+    credential: Credential,
+    refresh_at: Instant,
+    expires_at: Option<Instant>,
+}
+
+impl Credentials {
+    /// Gets the credential named `name`, fetching it w/ `fetch` if it isn't cached, or is due
+    /// for a refresh.
+    pub fn get(&self, name: &str, fetch: impl FnOnce() -> Result<Credential>) -> Result<Vec<u8>> {
+        self.get_at(Instant::now(), name, fetch)
+    }
+
+    /// Forgets the credential named `name` (e.g., because a backend said it expired), so
+    /// it's refetched the next time it's used.
+    pub fn invalidate(&self, name: &str) {
+        if self.cache.lock().unwrap().remove(name).is_some() {
+            tracing::info!("credential '{}' was invalidated", name);
+        }
+    }
+
+    fn get_at(
+        &self,
+        now: Instant,
+        name: &str,
+        fetch: impl FnOnce() -> Result<Credential>,
+    ) -> Result<Vec<u8>> {
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(cached) = cache.get(name) {
+            if now < cached.refresh_at {
+                return Ok(cached.credential.value.clone());
+            }
+        }
+
+        match fetch() {
+            Ok(credential) => {
+                let rotated = cache
+                    .get(name)
+                    .is_some_and(|cached| cached.credential.value != credential.value);
+                if rotated {
+                    tracing::info!("credential '{}' was rotated", name);
+                }
+                let value = credential.value.clone();
+                cache.insert(name.to_string(), Cached::new(now, credential));
+                Ok(value)
+            }
+            Err(e) => match cache.get(name) {
+                Some(cached) if !cached.expired(now) => {
+                    tracing::warn!(
+                        "failed to refresh credential '{}', using the cached one: {:#}",
+                        name,
+                        e
+                    );
+                    Ok(cached.credential.value.clone())
+                }
+                _ => {
+                    cache.remove(name);
+                    Err(e).with_context(|| format!("failed to fetch credential '{}'", name))
+                }
+            },
+        }
+    }
+}
+
+impl Cached {
+    fn new(fetched_at: Instant, credential: Credential) -> Self {
+        let (refresh_at, expires_at) = match credential.time_to_live {
+            Some(ttl) => (
+                fetched_at + ttl * REFRESH_AT_PERCENT / 100,
+                Some(fetched_at + ttl),
+            ),
+            None => (fetched_at + DEFAULT_REFRESH_INTERVAL, None),
+        };
+        Self {
+            credential,
+            refresh_at,
+            expires_at,
+        }
+    }
+
+    fn expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::{Duration, Instant};
+
+    use anyhow::{bail, Context, Result};
+
+    use super::{Credential, Credentials, CredentialsError, DEFAULT_REFRESH_INTERVAL};
+
+    const TTL: Duration = Duration::from_secs(100);
+
+    fn credential(value: &str, time_to_live: Option<Duration>) -> Result<Credential> {
+        Ok(Credential {
+            value: value.as_bytes().to_vec(),
+            time_to_live,
+        })
+    }
+
+    fn outage() -> Result<Credential> {
+        bail!("secret store is unavailable")
+    }
+
+    #[test]
+    fn cached_until_refresh_is_due() -> Result<()> {
+        let credentials = Credentials::default();
+        let t = Instant::now();
+        credentials.get_at(t, "key", || credential("v1", Some(TTL)))?;
+
+        let before_refresh = t + Duration::from_secs(79);
+        assert_eq!(
+            credentials.get_at(before_refresh, "key", || credential("v2", Some(TTL)))?,
+            b"v1"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn refreshed_before_expiry() -> Result<()> {
+        let credentials = Credentials::default();
+        let t = Instant::now();
+        credentials.get_at(t, "key", || credential("v1", Some(TTL)))?;
+
+        let refresh = t + Duration::from_secs(80);
+        assert_eq!(
+            credentials.get_at(refresh, "key", || credential("v2", Some(TTL)))?,
+            b"v2"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn refreshed_periodically_without_time_to_live() -> Result<()> {
+        let credentials = Credentials::default();
+        let t = Instant::now();
+        credentials.get_at(t, "key", || credential("v1", None))?;
+
+        let refresh = t + DEFAULT_REFRESH_INTERVAL;
+        assert_eq!(
+            credentials.get_at(refresh, "key", || credential("v2", None))?,
+            b"v2"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cached_credential_used_if_refresh_fails_before_expiry() -> Result<()> {
+        let credentials = Credentials::default();
+        let t = Instant::now();
+        credentials.get_at(t, "key", || credential("v1", Some(TTL)))?;
+
+        assert_eq!(
+            credentials.get_at(t + Duration::from_secs(90), "key", outage)?,
+            b"v1"
+        );
+        assert!(credentials.get_at(t + TTL, "key", outage).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn credentials_are_named() -> Result<()> {
+        let credentials = Credentials::default();
+        credentials.get("a", || credential("a", None))?;
+
+        assert!(credentials.get("b", outage).is_err());
+        assert_eq!(credentials.get("a", outage)?, b"a");
+        Ok(())
+    }
+
+    #[test]
+    fn invalidated_credentials_are_refetched() -> Result<()> {
+        let credentials = Credentials::default();
+        credentials.get("key", || credential("v1", Some(TTL)))?;
+
+        credentials.invalidate("key");
+        assert_eq!(
+            credentials.get("key", || credential("v2", Some(TTL)))?,
+            b"v2"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn credentials_error_of_test() {
+        let expired: Result<()> =
+            Err(CredentialsError::Expired("token expired".to_string()).into());
+        let e = expired
+            .with_context(|| "failed to send message")
+            .unwrap_err();
+        assert_eq!(
+            CredentialsError::of(&e),
+            Some(&CredentialsError::Expired("token expired".to_string()))
+        );
+        assert_eq!(CredentialsError::of(&anyhow::anyhow!("not found")), None);
+    }
+}
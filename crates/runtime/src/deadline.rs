@@ -0,0 +1,223 @@
+use std::{
+    cell::Cell,
+    fmt,
+    future::Future,
+    sync::Arc,
+    task::{Context, Poll},
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+use futures::task::{waker, ArcWake};
+
+/// `DeadlineExceeded` is the error calls fail w/ once the deadline of what the guest is
+/// handling (e.g., an http request whose client gave up on it) has passed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeadlineExceeded {
+    pub capability: String,
+    pub operation: String,
+    /// how long ago the deadline passed
+    pub by: Duration,
+}
+
+impl DeadlineExceeded {
+    /// Whether an error was caused by a call made past its deadline, or one whose backend
+    /// didn't answer before it (see `Interrupted`).
+    pub fn is(error: &anyhow::Error) -> bool {
+        error
+            .chain()
+            .any(|cause| cause.is::<Self>() || cause.is::<Interrupted>())
+    }
+}
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{} wasn't made, as its deadline passed {:?} ago",
+            self.capability, self.operation, self.by
+        )
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+/// `Interrupted` is the error of a backend that didn't answer before the deadline of the call
+/// waiting for it (see `block_on`), which the call is given up on for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Interrupted {
+    /// how long the backend was waited for
+    pub waited: Duration,
+}
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the backend didn't answer before the call's deadline (it was waited for {:?})",
+            self.waited
+        )
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+thread_local! {
+    /// The deadline of the capability call made on this thread (if any), i.e., the one of what
+    /// the guest making it is handling — which is kept on its `Ctx` (see `Ctx::deadline`),
+    /// and handed to each call as it's made (see `call`), as calls can't reach the `Ctx`.
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Sets the deadline of the capability call about to be made on this thread to the one of the
+/// `Ctx` it's made from (see `resource::get_table`).
+pub fn call(deadline: Option<Instant>) {
+    DEADLINE.with(|current| current.set(deadline));
+}
+
+/// The deadline of what's nested in something w/ the `outer` one — a deadline never extends
+/// the one it's nested in.
+pub fn nested(outer: Option<Instant>, deadline: Option<Instant>) -> Option<Instant> {
+    match (outer, deadline) {
+        (Some(outer), Some(deadline)) => Some(outer.min(deadline)),
+        (outer, deadline) => outer.or(deadline),
+    }
+}
+
+/// Sets the deadline of the calls made on this thread until it's dropped, restoring the one it
+/// replaced (e.g., for the host's own calls, which aren't made from a `Ctx`) — it never extends
+/// the one it's nested in.
+pub struct Deadline(Option<Instant>);
+
+impl Deadline {
+    pub fn enter(deadline: Option<Instant>) -> Self {
+        Self(DEADLINE.with(|current| current.replace(nested(current.get(), deadline))))
+    }
+}
+
+impl Drop for Deadline {
+    fn drop(&mut self) {
+        DEADLINE.with(|current| current.set(self.0));
+    }
+}
+
+/// The deadline of the calls made on this thread, if they have one.
+pub fn current() -> Option<Instant> {
+    DEADLINE.with(Cell::get)
+}
+
+/// How long the calls made on this thread have left, if they have a deadline — backends bound
+/// their waits (e.g., for a connection, or a message) w/ it, so they don't outlive it.
+pub fn remaining() -> Option<Duration> {
+    current().map(|deadline| deadline.saturating_duration_since(Instant::now()))
+}
+
+/// Waits for a backend's `future` (e.g., a request it's sent) for as long as the call it's for
+/// has left (see `remaining`), failing w/ `Interrupted` if it isn't done by then — so a backend
+/// that hangs doesn't keep the guest waiting past the deadline of what it's handling.
+///
+/// W/o a deadline, it waits for as long as the future takes, like `futures::executor::block_on`.
+pub fn block_on<F: Future>(future: F) -> Result<F::Output, Interrupted> {
+    let deadline = match current() {
+        Some(deadline) => deadline,
+        None => return Ok(futures::executor::block_on(future)),
+    };
+    let started = Instant::now();
+    let waker = waker(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return Ok(output);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(Interrupted {
+                waited: started.elapsed(),
+            });
+        }
+        // woken up once the future can make progress, or when the deadline passes
+        thread::park_timeout(deadline - now);
+    }
+}
+
+/// Wakes the thread a future is waited for on (see `block_on`).
+struct Unpark(Thread);
+
+impl ArcWake for Unpark {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.unpark();
+    }
+}
+
+/// Fails a call w/ `DeadlineExceeded` if its deadline has passed, before it's made.
+pub fn check(capability: &str, operation: &str) -> anyhow::Result<()> {
+    match current() {
+        Some(deadline) if Instant::now() >= deadline => Err(DeadlineExceeded {
+            capability: capability.to_string(),
+            operation: operation.to_string(),
+            by: deadline.elapsed(),
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::{Duration, Instant};
+
+    use futures::future;
+
+    use super::{block_on, call, check, current, remaining, Deadline, DeadlineExceeded};
+
+    #[test]
+    fn deadline_test() {
+        assert!(check("kv", "get").is_ok());
+        assert_eq!(remaining(), None);
+
+        let later = Instant::now() + Duration::from_secs(60);
+        {
+            let _deadline = Deadline::enter(Some(later));
+            assert!(check("kv", "get").is_ok());
+            assert!(remaining().unwrap() > Duration::from_secs(50));
+
+            // nested deadlines only ever shorten the one they're in
+            {
+                let _earlier = Deadline::enter(Some(Instant::now()));
+                let e = check("kv", "get").unwrap_err();
+                assert!(DeadlineExceeded::is(&e));
+                assert_eq!(remaining(), Some(Duration::ZERO));
+            }
+            {
+                let _none = Deadline::enter(None);
+                assert_eq!(current(), Some(later));
+            }
+            assert_eq!(current(), Some(later));
+        }
+        assert_eq!(current(), None);
+    }
+
+    #[test]
+    fn block_on_test() {
+        // w/o a deadline, it waits for as long as it takes
+        assert_eq!(block_on(future::ready(1)), Ok(1));
+
+        // a backend that never answers is given up on at the deadline
+        let _deadline = Deadline::enter(Some(Instant::now() + Duration::from_millis(20)));
+        assert_eq!(block_on(future::ready(2)), Ok(2));
+        let e = block_on(future::pending::<()>()).unwrap_err();
+        assert!(e.waited >= Duration::from_millis(15));
+        assert!(DeadlineExceeded::is(&e.into()));
+    }
+
+    #[test]
+    fn call_test() {
+        // each call is made under the deadline of the `Ctx` it's made from
+        let later = Instant::now() + Duration::from_secs(60);
+        call(Some(later));
+        assert_eq!(current(), Some(later));
+        call(None);
+        assert_eq!(current(), None);
+    }
+}
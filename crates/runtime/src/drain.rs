@@ -0,0 +1,132 @@
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+/// How long a backend being closed waits for its in-flight operations, if its capability
+/// doesn't say.
+pub const DEFAULT_DRAIN_GRACE: Duration = Duration::from_secs(5);
+
+/// `InFlight` counts the operations in flight on a backend, so closing it can wait for them
+/// to finish (see `drain`), rather than tearing it down under them (e.g., mid-write, or before
+/// a message is acked).
+///
+/// It's a handle, so all of its clones count the same operations.
+#[derive(Clone, Debug, Default)]
+pub struct InFlight(Arc<(Mutex<usize>, Condvar)>);
+
+/// An operation in flight, until it's dropped.
+#[derive(Debug)]
+pub struct Operation(InFlight);
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        let (count, finished) = &*(self.0).0;
+        *count.lock().unwrap() -= 1;
+        finished.notify_all();
+    }
+}
+
+/// How draining a backend went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Drained {
+    /// how many operations were in flight when it started
+    pub waited_for: usize,
+    /// how many of them were still in flight at the end of the grace period, which aren't
+    /// waited for anymore — they aren't cut short, though, as each finishes w/ the clone of the
+    /// backend it got (see `Lease`), which is only closed once the last of them is done
+    pub outstanding: usize,
+}
+
+impl InFlight {
+    pub fn start(&self) -> Operation {
+        *(self.0).0.lock().unwrap() += 1;
+        Operation(self.clone())
+    }
+
+    pub fn count(&self) -> usize {
+        *(self.0).0.lock().unwrap()
+    }
+
+    /// Waits up to `grace` for the operations in flight to finish, logging how many it waited
+    /// for, and how many it gave up on, if any — `what` is the backend, for the logs.
+    pub fn drain(&self, what: &str, grace: Duration) -> Drained {
+        let (count, finished) = &*self.0;
+        let deadline = Instant::now() + grace;
+        let mut in_flight = count.lock().unwrap();
+        let waited_for = *in_flight;
+        while *in_flight > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            in_flight = finished.wait_timeout(in_flight, deadline - now).unwrap().0;
+        }
+        let drained = Drained {
+            waited_for,
+            outstanding: *in_flight,
+        };
+        if drained.outstanding > 0 {
+            tracing::warn!(
+                "stopped waiting for {} of the {} in-flight operations on {}, as they didn't finish in {:?}",
+                drained.outstanding,
+                drained.waited_for,
+                what,
+                grace
+            );
+        } else if drained.waited_for > 0 {
+            tracing::info!(
+                "drained {}: waited for {} in-flight operations",
+                what,
+                drained.waited_for
+            );
+        }
+        drained
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::{thread, time::Duration};
+
+    use super::{Drained, InFlight};
+
+    #[test]
+    fn drain_test() {
+        let in_flight = InFlight::default();
+        assert_eq!(
+            in_flight.drain("kv", Duration::ZERO),
+            Drained {
+                waited_for: 0,
+                outstanding: 0
+            }
+        );
+
+        // an operation that finishes w/in the grace period is waited for
+        let operation = in_flight.start();
+        assert_eq!(in_flight.count(), 1);
+        let finishing = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(operation);
+        });
+        assert_eq!(
+            in_flight.drain("kv", Duration::from_secs(5)),
+            Drained {
+                waited_for: 1,
+                outstanding: 0
+            }
+        );
+        finishing.join().unwrap();
+
+        // and one that doesn't is given up on
+        let _stuck = in_flight.start();
+        let _other = in_flight.clone().start();
+        assert_eq!(
+            in_flight.drain("kv", Duration::from_millis(10)),
+            Drained {
+                waited_for: 2,
+                outstanding: 2
+            }
+        );
+    }
+}
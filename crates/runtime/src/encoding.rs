@@ -0,0 +1,22 @@
+use anyhow::{bail, Result};
+
+/// How the structured values a guest passes a capability as payloads (e.g., kv's patches)
+/// are encoded across the WIT boundary: compactly (i.e., in a binary format of the
+/// capability's), by default, or as JSON text, which is bigger, and slower to decode, but can
+/// be read as is (e.g., in a cassette, or a trace).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Binary,
+    Json,
+}
+
+impl Encoding {
+    pub fn parse(encoding: &str) -> Result<Self> {
+        match encoding {
+            "binary" => Ok(Self::Binary),
+            "json" => Ok(Self::Json),
+            e => bail!("invalid encoding: '{}' (expected 'binary', or 'json')", e),
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::fmt;
+
+use crate::{
+    call::{timed_out, TimedOut},
+    credentials::CredentialsError,
+    deadline::DeadlineExceeded,
+    flags::Disabled,
+    grants::Denied,
+    headroom::OutOfMemory,
+    payload_limit::PayloadTooLarge,
+    quota::RateLimited,
+    support::Unsupported,
+};
+
+/// `NotFound` is the error of reading something that doesn't exist (e.g., a kv key that was
+/// never set, or that expired), so guests can tell it apart from the backend failing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NotFound(pub String);
+
+impl NotFound {
+    /// Whether an error was caused by reading something that doesn't exist.
+    pub fn is(error: &anyhow::Error) -> bool {
+        error.downcast_ref::<Self>().is_some()
+    }
+}
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+/// `KindError` is an error that's only known by its kind, and message (e.g., one replayed
+/// from a cassette, or the one the calls of a batch that failed as a whole share), so guests
+/// get the variant of `types.wit`'s `error` the original error was mapped to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KindError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl KindError {
+    /// The kind of an error that's only known by it, if it is.
+    pub fn kind_of(error: &anyhow::Error) -> Option<ErrorKind> {
+        error.downcast_ref::<Self>().map(|e| e.kind)
+    }
+}
+
+impl fmt::Display for KindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for KindError {}
+
+/// `ErrorKind` is the kind of error a capability call failed w/, as guests see it (i.e., the
+/// variant of `types.wit`'s `error` it's mapped to by `impl_from_anyhow!`).
+///
+/// It's a small, fixed set, so the call metrics can be labeled w/ it (see `metrics`), w/o
+/// blowing up their cardinality.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ErrorKind {
+    /// the call exceeded the quota of the capability
+    RateLimited,
+    /// the call sent a payload bigger than the capability takes
+    PayloadTooLarge,
+    /// the result of the call is bigger than the guest's memory has room for
+    OutOfMemory,
+    /// the deadline of what the guest is handling passed, so the call wasn't made (or its
+    /// backend wasn't waited for any longer)
+    DeadlineExceeded,
+    /// the guest isn't granted the operation, or the credentials of the backend aren't
+    /// allowed to do it
+    PermissionDenied,
+    /// the operation isn't enabled (e.g., it's experimental, and the app didn't opt into it)
+    Disabled,
+    /// the backend of the capability doesn't support the operation
+    Unsupported,
+    /// what the call asked for doesn't exist (e.g., a kv key that was never set)
+    NotFound,
+    /// the call timed out
+    Timeout,
+    /// the credentials of the backend have expired
+    CredentialsExpired,
+    /// the credentials of the backend are wrong
+    CredentialsInvalid,
+    /// the backend failed otherwise (i.e., an `error-with-description`)
+    Backend,
+}
+
+impl ErrorKind {
+    /// The kind of an error, by what caused it — the causes are checked in a fixed order, so
+    /// an error w/ many of them is of the first one's kind.
+    pub fn of(error: &anyhow::Error) -> Self {
+        // errors only known by their kind are of it
+        if let Some(kind) = KindError::kind_of(error) {
+            kind
+        } else if RateLimited::is(error) {
+            Self::RateLimited
+        } else if PayloadTooLarge::is(error) {
+            Self::PayloadTooLarge
+        } else if OutOfMemory::is(error) {
+            Self::OutOfMemory
+        } else if DeadlineExceeded::is(error) {
+            Self::DeadlineExceeded
+        } else if Denied::is(error) {
+            Self::PermissionDenied
+        } else if Disabled::is(error) {
+            Self::Disabled
+        } else if Unsupported::is(error) {
+            Self::Unsupported
+        } else if NotFound::is(error) {
+            Self::NotFound
+        } else if timed_out(error) {
+            Self::Timeout
+        } else {
+            match CredentialsError::of(error) {
+                Some(CredentialsError::Expired(_)) => Self::CredentialsExpired,
+                Some(CredentialsError::Invalid(_)) => Self::CredentialsInvalid,
+                Some(CredentialsError::PermissionDenied(_)) => Self::PermissionDenied,
+                None => Self::Backend,
+            }
+        }
+    }
+
+    /// The label value of the kind (e.g., `rate_limited`).
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::RateLimited => "rate_limited",
+            Self::PayloadTooLarge => "payload_too_large",
+            Self::OutOfMemory => "out_of_memory",
+            Self::DeadlineExceeded => "deadline_exceeded",
+            Self::PermissionDenied => "permission_denied",
+            Self::Disabled => "disabled",
+            Self::Unsupported => "unsupported",
+            Self::NotFound => "not_found",
+            Self::Timeout => "timeout",
+            Self::CredentialsExpired => "credentials_expired",
+            Self::CredentialsInvalid => "credentials_invalid",
+            Self::Backend => "backend_error",
+        }
+    }
+
+    /// An error of this kind, w/ `message`: timeouts, and missing things are made as such (e.g.,
+    /// so they're retried as they would have been), and others as a `KindError`.
+    pub fn error(self, message: String) -> anyhow::Error {
+        match self {
+            Self::Timeout => {
+                // which `TimedOut` prefixes again
+                let message = message.strip_prefix("timed out: ").unwrap_or(&message);
+                TimedOut(message.to_string()).into()
+            }
+            Self::NotFound => NotFound(message).into(),
+            kind => KindError { kind, message }.into(),
+        }
+    }
+
+    /// The kind whose label value is `kind` (see `as_str`).
+    pub fn parse(kind: &str) -> Option<Self> {
+        [
+            Self::RateLimited,
+            Self::PayloadTooLarge,
+            Self::OutOfMemory,
+            Self::DeadlineExceeded,
+            Self::PermissionDenied,
+            Self::Disabled,
+            Self::Unsupported,
+            Self::NotFound,
+            Self::Timeout,
+            Self::CredentialsExpired,
+            Self::CredentialsInvalid,
+            Self::Backend,
+        ]
+        .into_iter()
+        .find(|k| k.as_str() == kind)
+    }
+}
+
+/// `Kind` tells the `ErrorKind` of the errors capability calls fail w/: `anyhow::Error`s, and
+/// (through `impl_from_anyhow!`) the `error`s of the capabilities' interfaces.
+pub trait Kind {
+    fn kind(&self) -> ErrorKind;
+}
+
+impl Kind for anyhow::Error {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::of(self)
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::Duration;
+
+    use super::{ErrorKind, NotFound};
+    use crate::{
+        credentials::CredentialsError, flags::OperationFlags, grants::Grants, headroom::Headroom,
+        payload_limit::PayloadLimit, quota::RateLimited,
+    };
+
+    #[test]
+    fn error_kind_test() {
+        let rate_limited = anyhow::Error::new(RateLimited {
+            capability: "kv".to_string(),
+            limit: "1 ops/sec".to_string(),
+            retry_after: Duration::from_secs(1),
+        });
+        assert_eq!(ErrorKind::of(&rate_limited), ErrorKind::RateLimited);
+
+        let too_large = PayloadLimit::new("mq", 1).check("send", 2).unwrap_err();
+        assert_eq!(ErrorKind::of(&too_large).as_str(), "payload_too_large");
+        let out_of_memory = Headroom::default().check("get", usize::MAX).unwrap_err();
+        assert_eq!(ErrorKind::of(&out_of_memory), ErrorKind::OutOfMemory);
+
+        let denied = Grants::new(Some(Vec::new()), Vec::new())
+            .check("kv", "set")
+            .unwrap_err();
+        assert_eq!(ErrorKind::of(&denied), ErrorKind::PermissionDenied);
+        let disabled = OperationFlags::new(&["get-many"], Vec::new(), Vec::new())
+            .check("kv", "get-many")
+            .unwrap_err();
+        assert_eq!(ErrorKind::of(&disabled).as_str(), "disabled");
+        let forbidden = anyhow::Error::new(CredentialsError::PermissionDenied("403".into()));
+        assert_eq!(ErrorKind::of(&forbidden), ErrorKind::PermissionDenied);
+        let expired = anyhow::Error::new(CredentialsError::Expired("401".into()));
+        assert_eq!(ErrorKind::of(&expired).as_str(), "credentials_expired");
+
+        let not_found = anyhow::Error::new(NotFound("no value found for key 'my-key'".into()))
+            .context("failed to get 'my-key'");
+        assert_eq!(ErrorKind::of(&not_found).as_str(), "not_found");
+
+        // whatever else the backend fails w/ is a backend error, even w/ more context
+        let failed = anyhow::anyhow!("connection reset").context("failed to get 'my-key'");
+        assert_eq!(ErrorKind::of(&failed).as_str(), "backend_error");
+    }
+}
@@ -0,0 +1,475 @@
+use std::{
+    fmt::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+
+use crate::metrics::{Histogram, Metrics, Sizes};
+
+/// What a metric `Family` measures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Counter,
+    Histogram,
+}
+
+/// `Family` is a metric of the capability calls of apps (e.g., `slight_capability_calls_total`),
+/// w/ a sample per app, and set of labels — the same whatever it's exported to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Family {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: Kind,
+    pub samples: Vec<Sample>,
+}
+
+/// A sample of a metric `Family`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sample {
+    /// the app whose calls it counts
+    pub app: String,
+    /// the labels, in order, but the app
+    pub labels: Vec<(&'static str, String)>,
+    pub measure: Measure,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Measure {
+    Count(u64),
+    Histogram(Histogram),
+}
+
+/// The metric families of the capability calls of `apps` (i.e., by app name).
+pub fn families(apps: &[(&str, &Metrics)]) -> Vec<Family> {
+    let mut calls = Family {
+        name: "slight_capability_calls_total",
+        help: "How many calls the app's guests made into capabilities, by operation, target (past a capability's metrics_max_targets, targets are counted as `other`), and outcome (`ok`, or the kind of error the calls failed w/: rate_limited, payload_too_large, out_of_memory, deadline_exceeded, permission_denied, disabled, unsupported, not_found, timeout, credentials_expired, credentials_invalid, or backend_error).",
+        kind: Kind::Counter,
+        samples: Vec::new(),
+    };
+    let mut bucketed = Family {
+        name: "slight_capability_calls_bucketed_total",
+        help: "How many calls into capabilities were counted w/ an `other` target, as the capability had seen metrics_max_targets targets already.",
+        kind: Kind::Counter,
+        samples: Vec::new(),
+    };
+    let mut keys = Family {
+        name: "slight_capability_key_size_bytes",
+        help: "The lengths of the keys the app's guests passed to capabilities, by operation.",
+        kind: Kind::Histogram,
+        samples: Vec::new(),
+    };
+    let mut values = Family {
+        name: "slight_capability_value_size_bytes",
+        help: "The sizes of the values the app's guests stored in, or read from capabilities, by operation, and target (past a capability's metrics_max_targets, targets are counted as `other`).",
+        kind: Kind::Histogram,
+        samples: Vec::new(),
+    };
+    let mut stale_reads = Family {
+        name: "slight_capability_stale_reads_total",
+        help: "How many reads of capabilities were served a last known good value, as their backend failed (see last_known_good_max_age_ms).",
+        kind: Kind::Counter,
+        samples: Vec::new(),
+    };
+    let mut restarts = Family {
+        name: "slight_guest_restarts_total",
+        help: "How many times the app's guest was restarted after it crashed (see max_restarts).",
+        kind: Kind::Counter,
+        samples: Vec::new(),
+    };
+    let mut split = Family {
+        name: "slight_traffic_split_operations_total",
+        help: "How many operations of a capability w/ a canary were routed to its primary, and canary backends, by whether they read, or wrote (see kv.canary_read_percent, and kv.canary_write_percent).",
+        kind: Kind::Counter,
+        samples: Vec::new(),
+    };
+    for (app, metrics) in apps {
+        let sample = |labels, measure| Sample {
+            app: app.to_string(),
+            labels,
+            measure,
+        };
+        restarts
+            .samples
+            .push(sample(Vec::new(), Measure::Count(metrics.restarts())));
+        for call in metrics.reports() {
+            let mut labels = vec![
+                ("capability", call.capability),
+                ("operation", call.operation),
+            ];
+            labels.extend(call.target.map(|target| ("target", target)));
+            labels.push((
+                "outcome",
+                call.error.map_or("ok", |kind| kind.as_str()).to_string(),
+            ));
+            calls
+                .samples
+                .push(sample(labels, Measure::Count(call.calls)));
+        }
+        for (capability, calls) in metrics.bucketed() {
+            bucketed.samples.push(sample(
+                vec![("capability", capability)],
+                Measure::Count(calls),
+            ));
+        }
+        for (capability, reads) in metrics.stale_reads() {
+            stale_reads.samples.push(sample(
+                vec![("capability", capability)],
+                Measure::Count(reads),
+            ));
+        }
+        for (capability, traffic_split) in metrics.splits() {
+            let stats = traffic_split.stats();
+            for (backend, operation, operations) in [
+                (traffic_split.primary(), "read", stats.primary_reads),
+                (traffic_split.canary(), "read", stats.canary_reads),
+                (traffic_split.primary(), "write", stats.primary_writes),
+                (traffic_split.canary(), "write", stats.canary_writes),
+            ] {
+                split.samples.push(sample(
+                    vec![
+                        ("capability", capability.clone()),
+                        ("backend", backend.to_string()),
+                        ("operation", operation.to_string()),
+                    ],
+                    Measure::Count(operations),
+                ));
+            }
+        }
+        for report in metrics.size_reports() {
+            let mut labels = vec![
+                ("capability", report.capability),
+                ("operation", report.operation),
+            ];
+            labels.extend(report.target.map(|target| ("target", target)));
+            let family = match report.sizes {
+                Sizes::Keys => &mut keys,
+                Sizes::Values => &mut values,
+            };
+            family
+                .samples
+                .push(sample(labels, Measure::Histogram(report.histogram)));
+        }
+    }
+    vec![calls, bucketed, keys, values, stale_reads, restarts, split]
+}
+
+/// `Exporter` encodes metric families in the format of a metrics backend, so the same metrics
+/// feed whichever backend an operator uses — whether it scrapes them (e.g., `Prometheus`), or
+/// is pushed them (e.g., `Otlp`) is up to the host.
+pub trait Exporter: Send + Sync {
+    /// The content type of what `encode` returns (e.g., to serve it, or push it w/).
+    fn content_type(&self) -> &'static str;
+
+    fn encode(&self, families: &[Family]) -> Vec<u8>;
+}
+
+/// `Prometheus` encodes metrics in the Prometheus text format, to be scraped.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Prometheus;
+
+impl Prometheus {
+    /// Encodes `families` as text, w/ the app as the first label of each sample.
+    pub fn render(&self, families: &[Family]) -> String {
+        let mut out = String::new();
+        // note: writing to a `String` can't fail
+        for family in families {
+            let kind = match family.kind {
+                Kind::Counter => "counter",
+                Kind::Histogram => "histogram",
+            };
+            writeln!(out, "# HELP {} {}", family.name, family.help).unwrap();
+            writeln!(out, "# TYPE {} {}", family.name, kind).unwrap();
+            for sample in &family.samples {
+                let labels = [("app", sample.app.as_str())]
+                    .into_iter()
+                    .chain(
+                        sample
+                            .labels
+                            .iter()
+                            .map(|(name, value)| (*name, value.as_str())),
+                    )
+                    .map(|(name, value)| format!("{}=\"{}\"", name, label_value(value)))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                match &sample.measure {
+                    Measure::Count(count) => {
+                        writeln!(out, "{}{{{}}} {}", family.name, labels, count).unwrap()
+                    }
+                    Measure::Histogram(histogram) => {
+                        for (bound, count) in histogram.buckets() {
+                            let le =
+                                bound.map_or_else(|| "+Inf".to_string(), |bound| bound.to_string());
+                            writeln!(
+                                out,
+                                "{}_bucket{{{},le=\"{}\"}} {}",
+                                family.name, labels, le, count
+                            )
+                            .unwrap();
+                        }
+                        writeln!(out, "{}_sum{{{}}} {}", family.name, labels, histogram.sum)
+                            .unwrap();
+                        writeln!(
+                            out,
+                            "{}_count{{{}}} {}",
+                            family.name,
+                            labels,
+                            histogram.count()
+                        )
+                        .unwrap();
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+impl Exporter for Prometheus {
+    fn content_type(&self) -> &'static str {
+        "text/plain; version=0.0.4"
+    }
+
+    fn encode(&self, families: &[Family]) -> Vec<u8> {
+        self.render(families).into_bytes()
+    }
+}
+
+/// Escapes a label value of the Prometheus text format (e.g., targets, which can be anything).
+pub fn label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// `Otlp` encodes metrics as an OTLP/HTTP (JSON) export request, to be pushed to a collector,
+/// w/ a resource per app (i.e., its `service.name`), and counts that are cumulative `since`
+/// (see `Metrics::since`).
+#[derive(Clone, Copy, Debug)]
+pub struct Otlp {
+    pub since: SystemTime,
+}
+
+impl Otlp {
+    /// The export request of `families`, as of `now`.
+    pub fn request(&self, families: &[Family], now: SystemTime) -> Value {
+        let (since, now) = (nanos(self.since), nanos(now));
+        let mut apps = families
+            .iter()
+            .flat_map(|family| family.samples.iter().map(|sample| sample.app.as_str()))
+            .collect::<Vec<_>>();
+        apps.sort_unstable();
+        apps.dedup();
+
+        let resource_metrics = apps
+            .into_iter()
+            .map(|app| {
+                let metrics = families
+                    .iter()
+                    .filter_map(|family| {
+                        let points = family
+                            .samples
+                            .iter()
+                            .filter(|sample| sample.app == app)
+                            .map(|sample| data_point(sample, &since, &now))
+                            .collect::<Vec<_>>();
+                        (!points.is_empty()).then(|| metric(family, points))
+                    })
+                    .collect::<Vec<_>>();
+                json!({
+                    "resource": {
+                        "attributes": attributes(&[("service.name", app.to_string())]),
+                    },
+                    "scopeMetrics": [{
+                        "scope": { "name": "slight" },
+                        "metrics": metrics,
+                    }],
+                })
+            })
+            .collect::<Vec<_>>();
+        json!({ "resourceMetrics": resource_metrics })
+    }
+}
+
+/// A metric of OTLP, whose counts are cumulative (i.e., `AGGREGATION_TEMPORALITY_CUMULATIVE`).
+fn metric(family: &Family, points: Vec<Value>) -> Value {
+    match family.kind {
+        Kind::Counter => json!({
+            "name": family.name,
+            "description": family.help,
+            "sum": {
+                "dataPoints": points,
+                "aggregationTemporality": 2,
+                "isMonotonic": true,
+            },
+        }),
+        Kind::Histogram => json!({
+            "name": family.name,
+            "description": family.help,
+            "unit": "By",
+            "histogram": {
+                "dataPoints": points,
+                "aggregationTemporality": 2,
+            },
+        }),
+    }
+}
+
+/// A data point of OTLP, where 64-bit integers are strings, and the counts of histogram
+/// buckets aren't cumulative, unlike in the Prometheus text format.
+fn data_point(sample: &Sample, since: &str, now: &str) -> Value {
+    let mut point = json!({
+        "attributes": attributes(&sample.labels),
+        "startTimeUnixNano": since,
+        "timeUnixNano": now,
+    });
+    match &sample.measure {
+        Measure::Count(count) => point["asInt"] = json!(count.to_string()),
+        Measure::Histogram(histogram) => {
+            let buckets = histogram.buckets();
+            let mut previous = 0;
+            let counts = buckets
+                .iter()
+                .map(|(_, cumulative)| {
+                    let count = cumulative - previous;
+                    previous = *cumulative;
+                    count.to_string()
+                })
+                .collect::<Vec<_>>();
+            let bounds = buckets
+                .iter()
+                .filter_map(|(bound, _)| bound.map(|bound| bound as f64))
+                .collect::<Vec<_>>();
+            point["count"] = json!(histogram.count().to_string());
+            point["sum"] = json!(histogram.sum as f64);
+            point["bucketCounts"] = json!(counts);
+            point["explicitBounds"] = json!(bounds);
+        }
+    }
+    point
+}
+
+fn nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+impl Exporter for Otlp {
+    fn content_type(&self) -> &'static str {
+        "application/json"
+    }
+
+    fn encode(&self, families: &[Family]) -> Vec<u8> {
+        self.request(families, SystemTime::now())
+            .to_string()
+            .into_bytes()
+    }
+}
+
+fn attributes(labels: &[(&'static str, String)]) -> Value {
+    labels
+        .iter()
+        .map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }))
+        .collect()
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use serde_json::json;
+
+    use super::{families, Otlp, Prometheus};
+    use crate::{
+        error_kind::ErrorKind,
+        metrics::{LabelSettings, Metrics, SizeBuckets},
+        split::{Operation, TrafficSplit},
+    };
+
+    fn metrics() -> Metrics {
+        let metrics = Metrics::default();
+        let kv = metrics.get(
+            "kv.filesystem",
+            LabelSettings::default(),
+            SizeBuckets::new(vec![16], vec![100]),
+        );
+        kv.record_sizes("set", "user:\"1\"", 6, Some(120));
+        kv.record("set", "user:\"1\"", None);
+        kv.record("set", "user:\"1\"", Some(ErrorKind::Timeout));
+        metrics.record_restart();
+        metrics
+            .split(
+                "kv.filesystem",
+                TrafficSplit::new("kv.filesystem", "kv.azblob", 0, 100).unwrap(),
+            )
+            .routes_to_canary(Operation::Write, Some(b"user:1"));
+        metrics
+    }
+
+    #[test]
+    fn prometheus_test() {
+        let metrics = metrics();
+        let text = Prometheus.render(&families(&[("orders", &metrics)]));
+        assert!(text.contains("# TYPE slight_capability_calls_total counter\n"));
+        assert!(text.contains(
+            "slight_capability_calls_total{app=\"orders\",capability=\"kv.filesystem\",operation=\"set\",target=\"user:\\\"1\\\"\",outcome=\"timeout\"} 1\n"
+        ));
+        assert!(text.contains(
+            "slight_capability_calls_bucketed_total{app=\"orders\",capability=\"kv.filesystem\"} 0\n"
+        ));
+        assert!(text.contains(
+            "slight_capability_value_size_bytes_bucket{app=\"orders\",capability=\"kv.filesystem\",operation=\"set\",target=\"user:\\\"1\\\"\",le=\"+Inf\"} 1\n"
+        ));
+        assert!(text.contains(
+            "slight_traffic_split_operations_total{app=\"orders\",capability=\"kv.filesystem\",backend=\"kv.azblob\",operation=\"write\"} 1\n"
+        ));
+        assert!(text.contains("slight_guest_restarts_total{app=\"orders\"} 1\n"));
+        assert!(text.contains(
+            "slight_capability_stale_reads_total{app=\"orders\",capability=\"kv.filesystem\"} 0\n"
+        ));
+        assert!(text.contains(
+            "slight_capability_key_size_bytes_count{app=\"orders\",capability=\"kv.filesystem\",operation=\"set\"} 1\n"
+        ));
+    }
+
+    #[test]
+    fn otlp_test() {
+        let metrics = metrics();
+        let otlp = Otlp {
+            since: UNIX_EPOCH + Duration::from_secs(1),
+        };
+        let request = otlp.request(
+            &families(&[("orders", &metrics)]),
+            UNIX_EPOCH + Duration::from_secs(2),
+        );
+        let resource = &request["resourceMetrics"][0];
+        assert_eq!(
+            resource["resource"]["attributes"][0],
+            json!({ "key": "service.name", "value": { "stringValue": "orders" } })
+        );
+        let metrics = resource["scopeMetrics"][0]["metrics"].as_array().unwrap();
+        assert_eq!(metrics.len(), 7);
+        let calls = &metrics[0]["sum"]["dataPoints"];
+        assert_eq!(calls.as_array().unwrap().len(), 2);
+        assert_eq!(calls[0]["asInt"], "1");
+        assert_eq!(calls[0]["startTimeUnixNano"], "1000000000");
+        assert_eq!(calls[0]["timeUnixNano"], "2000000000");
+        assert_eq!(
+            calls[0]["attributes"][3],
+            json!({ "key": "outcome", "value": { "stringValue": "ok" } })
+        );
+        // buckets aren't cumulative in OTLP, unlike in the Prometheus text format
+        let values = &metrics[3]["histogram"]["dataPoints"][0];
+        assert_eq!(values["bucketCounts"], json!(["0", "1"]));
+        assert_eq!(values["explicitBounds"], json!([100.0]));
+        assert_eq!(values["sum"], 120.0);
+
+        // apps w/o samples have no resource
+        let empty = otlp.request(&families(&[]), UNIX_EPOCH);
+        assert_eq!(empty["resourceMetrics"], json!([]));
+    }
+}
@@ -0,0 +1,171 @@
+use std::fmt;
+
+use anyhow::{bail, Result};
+
+/// `OperationFlags` decide which operations of a capability are enabled, so new operations
+/// can ship as opt-in, and be rolled out (or back) one app at a time — calls of the disabled
+/// ones fail w/ `Disabled`, before they reach the backend.
+///
+/// The `experimental` operations of a capability (i.e., as it declares them) are disabled,
+/// unless they're `enabled`, and the `disabled` ones are disabled either way. Unlike `Grants`,
+/// which are about what a guest is trusted w/, they're about what an app has opted into.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OperationFlags {
+    experimental: &'static [&'static str],
+    enabled: Vec<String>,
+    disabled: Vec<String>,
+}
+
+impl OperationFlags {
+    pub fn new(
+        experimental: &'static [&'static str],
+        enabled: Vec<String>,
+        disabled: Vec<String>,
+    ) -> Self {
+        Self {
+            experimental,
+            enabled,
+            disabled,
+        }
+    }
+
+    pub fn enables(&self, operation: &str) -> bool {
+        let opted_in =
+            !self.experimental.contains(&operation) || self.enabled.iter().any(|o| o == operation);
+        opted_in && !self.disabled.iter().any(|o| o == operation)
+    }
+
+    /// Fails w/ `Disabled` if `operation` of `capability` isn't enabled.
+    pub fn check(&self, capability: &str, operation: &str) -> Result<()> {
+        if self.enables(operation) {
+            return Ok(());
+        }
+        Err(Disabled {
+            capability: capability.to_string(),
+            operation: operation.to_string(),
+            experimental: self.experimental.contains(&operation)
+                && !self.disabled.iter().any(|o| o == operation),
+        }
+        .into())
+    }
+
+    /// Checks that the operations enabled, or disabled are of the `operations` of the
+    /// capability (see `support::operations`), and that the enabled ones are experimental, as
+    /// the others are enabled already (i.e., it's a typo, or the operation is stable now).
+    pub fn validate(&self, operations: &[String]) -> Result<()> {
+        let unknown = self
+            .enabled
+            .iter()
+            .chain(&self.disabled)
+            .filter(|operation| !operations.contains(operation))
+            .collect::<Vec<_>>();
+        if !unknown.is_empty() {
+            bail!(
+                "unknown operations {:?} (expected some of {:?})",
+                unknown,
+                operations
+            );
+        }
+        let stable = self
+            .enabled
+            .iter()
+            .filter(|operation| !self.experimental.contains(&operation.as_str()))
+            .collect::<Vec<_>>();
+        if !stable.is_empty() {
+            bail!(
+                "operations {:?} aren't experimental, so they're enabled already (the experimental ones are {:?})",
+                stable,
+                self.experimental
+            );
+        }
+        Ok(())
+    }
+}
+
+/// `Disabled` is the error the calls of operations that aren't enabled (see `OperationFlags`)
+/// fail w/.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Disabled {
+    pub capability: String,
+    pub operation: String,
+    /// whether it's disabled as it's experimental, and wasn't opted into, rather than
+    /// disabled outright
+    pub experimental: bool,
+}
+
+impl Disabled {
+    /// Whether an error was caused by calling an operation that isn't enabled.
+    pub fn is(error: &anyhow::Error) -> bool {
+        error.downcast_ref::<Self>().is_some()
+    }
+}
+
+impl fmt::Display for Disabled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.experimental {
+            write!(
+                f,
+                "'{}' of '{}' is experimental, and disabled (see its `enable_operations`)",
+                self.operation, self.capability
+            )
+        } else {
+            write!(
+                f,
+                "'{}' of '{}' is disabled (see its `disable_operations`)",
+                self.operation, self.capability
+            )
+        }
+    }
+}
+
+impl std::error::Error for Disabled {}
+
+#[cfg(test)]
+mod unittests {
+    use super::{Disabled, OperationFlags};
+
+    fn operations(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn flags_test() {
+        const EXPERIMENTAL: &[&str] = &["get-many", "set-many"];
+        let flags = OperationFlags::new(EXPERIMENTAL, Vec::new(), Vec::new());
+        assert!(flags.enables("get"));
+        assert!(!flags.enables("get-many"));
+        let e = flags.check("kv.azblob", "get-many").unwrap_err();
+        assert!(Disabled::is(&e));
+        assert_eq!(
+            e.to_string(),
+            "'get-many' of 'kv.azblob' is experimental, and disabled (see its `enable_operations`)"
+        );
+
+        // opted into one of them, w/ a stable one rolled back
+        let flags = OperationFlags::new(
+            EXPERIMENTAL,
+            operations(&["get-many"]),
+            operations(&["clear"]),
+        );
+        assert!(flags.enables("get-many"));
+        assert!(!flags.enables("set-many"));
+        assert!(!flags.enables("clear"));
+        assert_eq!(
+            flags.check("kv.azblob", "clear").unwrap_err().to_string(),
+            "'clear' of 'kv.azblob' is disabled (see its `disable_operations`)"
+        );
+
+        let kv = operations(&["get", "clear", "get-many", "set-many"]);
+        assert!(flags.validate(&kv).is_ok());
+        assert!(
+            OperationFlags::new(EXPERIMENTAL, operations(&["get"]), Vec::new())
+                .validate(&kv)
+                .is_err()
+        );
+        assert!(
+            OperationFlags::new(EXPERIMENTAL, Vec::new(), operations(&["remove"]))
+                .validate(&kv)
+                .is_err()
+        );
+    }
+}
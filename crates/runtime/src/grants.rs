@@ -0,0 +1,131 @@
+use std::fmt;
+
+use anyhow::{bail, Result};
+
+/// `Grants` are the operations of a capability a guest is allowed to call, for least-privilege
+/// setups (e.g., a less-trusted guest that may only read from a kv store) — its calls of the
+/// others fail w/ `Denied`, before they reach the backend (or count against its quota).
+///
+/// Operations are named as their function is (e.g., `set-with-time-to-live`): if any are
+/// `allow`ed, only those are granted, and the `deny`ed ones are revoked either way.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Grants {
+    allow: Option<Vec<String>>,
+    deny: Vec<String>,
+}
+
+impl Grants {
+    pub fn new(allow: Option<Vec<String>>, deny: Vec<String>) -> Self {
+        Self { allow, deny }
+    }
+
+    /// Whether every operation is granted, as is the case unless the slightfile says otherwise.
+    pub fn is_unrestricted(&self) -> bool {
+        self.allow.is_none() && self.deny.is_empty()
+    }
+
+    pub fn allows(&self, operation: &str) -> bool {
+        let allowed = self
+            .allow
+            .as_ref()
+            .is_none_or(|allow| allow.iter().any(|o| o == operation));
+        allowed && !self.deny.iter().any(|o| o == operation)
+    }
+
+    /// Fails w/ `Denied` if the guest isn't granted `operation` of `capability`.
+    pub fn check(&self, capability: &str, operation: &str) -> Result<()> {
+        if self.allows(operation) {
+            return Ok(());
+        }
+        Err(Denied {
+            capability: capability.to_string(),
+            operation: operation.to_string(),
+        }
+        .into())
+    }
+
+    /// Checks that the operations granted, or denied are of the `operations` of the capability
+    /// (see `support::operations`), as any other is a typo, which would otherwise go unnoticed
+    /// (i.e., granting, or denying nothing).
+    pub fn validate(&self, operations: &[String]) -> Result<()> {
+        let unknown = self
+            .allow
+            .iter()
+            .flatten()
+            .chain(&self.deny)
+            .filter(|operation| !operations.contains(operation))
+            .collect::<Vec<_>>();
+        if !unknown.is_empty() {
+            bail!(
+                "unknown operations {:?} (expected some of {:?})",
+                unknown,
+                operations
+            );
+        }
+        Ok(())
+    }
+}
+
+/// `Denied` is the error the calls of operations the guest isn't granted (see `Grants`) fail w/.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Denied {
+    pub capability: String,
+    pub operation: String,
+}
+
+impl Denied {
+    /// Whether an error was caused by calling an operation that isn't granted.
+    pub fn is(error: &anyhow::Error) -> bool {
+        error.downcast_ref::<Self>().is_some()
+    }
+}
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the guest isn't granted '{}' of '{}' (see its `allow_operations`, and `deny_operations`)",
+            self.operation, self.capability
+        )
+    }
+}
+
+impl std::error::Error for Denied {}
+
+#[cfg(test)]
+mod unittests {
+    use super::{Denied, Grants};
+
+    fn operations(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn grants_test() {
+        let unrestricted = Grants::default();
+        assert!(unrestricted.is_unrestricted());
+        assert!(unrestricted.allows("set"));
+
+        // read-only, w/ one of the reads revoked
+        let read_only = Grants::new(
+            Some(operations(&["open", "get", "list-keys"])),
+            operations(&["list-keys"]),
+        );
+        assert!(read_only.allows("get"));
+        assert!(!read_only.allows("list-keys"));
+        assert!(!read_only.allows("set"));
+        let e = read_only.check("kv", "set").unwrap_err();
+        assert!(Denied::is(&e));
+        assert!(read_only.check("kv", "get").is_ok());
+
+        let no_deletes = Grants::new(None, operations(&["delete"]));
+        assert!(no_deletes.allows("set"));
+        assert!(!no_deletes.allows("delete"));
+
+        let kv = operations(&["open", "get", "set", "delete", "list-keys"]);
+        assert!(read_only.validate(&kv).is_ok());
+        assert!(Grants::new(None, operations(&["remove"]))
+            .validate(&kv)
+            .is_err());
+    }
+}
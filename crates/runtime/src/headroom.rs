@@ -0,0 +1,182 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+
+use crate::memory::MemoryMonitor;
+
+/// How large a linear memory of a guest can be, at most, as it's addressed w/ 32 bits.
+const WASM32_MAX_BYTES: u64 = 1 << 32;
+
+/// `Headroom` is how much room the linear memory of a guest instance has for the results
+/// capabilities return to it (e.g., a big kv value, or a batch of messages), which the guest
+/// allocates a buffer for while the host writes them into its memory.
+///
+/// When the guest can't allocate the buffer, its allocator aborts (i.e., the whole instance
+/// traps), w/ nothing the host can do about it by then, so results that can't fit are
+/// checked before they're returned (see `BasicState::check_headroom`), and fail w/
+/// `OutOfMemory` instead, which the guest can handle (e.g., by reading the value by ranges).
+///
+/// The host can't tell how much of the guest's heap is free, so it only fails the results that
+/// can't fit even if all of it was: the ones bigger than the heap's memory (i.e., what the
+/// memory grew by since the instance started), and what it can still grow by (i.e., up to
+/// `max_memory_bytes`, and the growth budget, if it traps). A result that fits that may still
+/// fail to be allocated (e.g., if the heap is fragmented), and trap as before.
+///
+/// It's updated by the `Limiter` of the instance's store, and its clones share it.
+#[derive(Clone, Debug, Default)]
+pub struct Headroom(Arc<Mutex<Inner>>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// how many bytes the memories of the instance started w/ (i.e., its static data, and
+    /// stack), which the heap can't have
+    initial: u64,
+    /// how many bytes the memories of the instance have
+    bytes: u64,
+    max_bytes: Option<u64>,
+    monitor: Option<MemoryMonitor>,
+}
+
+impl Headroom {
+    /// Holds the room to what each memory can grow to (i.e., `max_memory_bytes`).
+    pub(crate) fn limit(&self, max_bytes: usize) {
+        self.0.lock().unwrap().max_bytes = Some(max_bytes as u64);
+    }
+
+    /// Holds the room to what the growth budget of `monitor` has left, if it traps.
+    pub(crate) fn monitor(&self, monitor: MemoryMonitor) {
+        self.0.lock().unwrap().monitor = Some(monitor);
+    }
+
+    /// Records that a memory grew from `current`, to `desired` bytes — memories that are
+    /// being created (i.e., `current` is 0) are the ones the instance starts w/.
+    pub(crate) fn grown(&self, current: usize, desired: usize) {
+        let mut inner = self.0.lock().unwrap();
+        if current == 0 {
+            inner.initial += desired as u64;
+        }
+        inner.bytes += desired.saturating_sub(current) as u64;
+    }
+
+    /// The most bytes a result can be for the guest to be able to allocate it.
+    pub fn available(&self) -> u64 {
+        let inner = self.0.lock().unwrap();
+        let mut growable = inner
+            .max_bytes
+            .unwrap_or(WASM32_MAX_BYTES)
+            .min(WASM32_MAX_BYTES)
+            .saturating_sub(inner.bytes);
+        if let Some(remaining) = inner
+            .monitor
+            .as_ref()
+            .and_then(MemoryMonitor::remaining_growth)
+        {
+            growable = growable.min(remaining);
+        }
+        inner.bytes.saturating_sub(inner.initial) + growable
+    }
+
+    /// Checks the size of a result `operation` returns to the guest, failing w/ `OutOfMemory`
+    /// if the guest can't allocate it.
+    pub fn check(&self, operation: &str, bytes: usize) -> Result<()> {
+        let available = self.available();
+        if bytes as u64 > available {
+            return Err(OutOfMemory {
+                operation: operation.to_string(),
+                size: bytes,
+                available,
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// `OutOfMemory` is the error calls whose result the guest can't allocate (see `Headroom`)
+/// fail w/.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutOfMemory {
+    /// the operation, as its function is named (e.g., `get`)
+    pub operation: String,
+    /// the size of the result, in bytes
+    pub size: usize,
+    /// the most bytes the guest's memory has room for
+    pub available: u64,
+}
+
+impl OutOfMemory {
+    /// Whether an error was caused by a call whose result the guest can't allocate.
+    pub fn is(error: &anyhow::Error) -> bool {
+        error.downcast_ref::<Self>().is_some()
+    }
+}
+
+impl fmt::Display for OutOfMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the result of '{}' is {} bytes, which is more than the guest's memory has room for (i.e., {} bytes, see `max_memory_bytes`)",
+            self.operation, self.size, self.available
+        )
+    }
+}
+
+impl std::error::Error for OutOfMemory {}
+
+#[cfg(test)]
+mod unittests {
+    use crate::memory::{GrowthAction, GrowthSettings, MemoryMonitor};
+
+    use super::{Headroom, OutOfMemory, WASM32_MAX_BYTES};
+
+    const PAGE: usize = 65536;
+
+    #[test]
+    fn headroom_test() {
+        let headroom = Headroom::default();
+        // w/o limits, it's what a 32-bit memory can be
+        assert_eq!(headroom.available(), WASM32_MAX_BYTES);
+
+        headroom.limit(8 * PAGE);
+        headroom.grown(0, 2 * PAGE);
+        assert_eq!(headroom.available(), 6 * PAGE as u64);
+        // the heap's memory counts, as it may be free
+        headroom.grown(2 * PAGE, 4 * PAGE);
+        assert_eq!(headroom.available(), 6 * PAGE as u64);
+        assert!(headroom.check("get", 6 * PAGE).is_ok());
+
+        let e = headroom.check("get", 6 * PAGE + 1).unwrap_err();
+        assert!(OutOfMemory::is(&e));
+        assert_eq!(
+            e.to_string(),
+            "the result of 'get' is 393217 bytes, which is more than the guest's memory has room for (i.e., 393216 bytes, see `max_memory_bytes`)"
+        );
+    }
+
+    #[test]
+    fn headroom_budget_test() {
+        let headroom = Headroom::default();
+        let monitor = MemoryMonitor::default();
+        headroom.monitor(monitor.clone());
+        headroom.grown(0, PAGE);
+
+        // a budget that only warns doesn't hold the memory back
+        monitor.configure(Some(GrowthSettings::new(
+            PAGE as u64,
+            None,
+            GrowthAction::Warn,
+        )));
+        assert_eq!(headroom.available(), WASM32_MAX_BYTES - PAGE as u64);
+
+        // one that traps does
+        monitor.configure(Some(GrowthSettings::new(
+            PAGE as u64,
+            None,
+            GrowthAction::Trap,
+        )));
+        assert_eq!(headroom.available(), PAGE as u64);
+    }
+}
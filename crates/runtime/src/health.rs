@@ -0,0 +1,284 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use crossbeam_channel::Sender;
+use slight_events_api::{Event, EventBuilder, EventBuilderV10};
+use uuid::Uuid;
+
+use crate::{
+    cause,
+    error_kind::ErrorKind,
+    resource::{StateTable, Watch},
+};
+
+/// The resource descriptor guests listen to health events w/ — i.e., an `observable` whose
+/// `rd` is this, and whose `key` is the name of a capability (e.g., `kv`), or `*` for all
+/// of them.
+pub const HEALTH: &str = "slight.health";
+
+/// The type of health events.
+pub const HEALTH_EVENT_TYPE: &str = "slight.health.v1";
+
+/// How many calls in a row must fail for a capability to be considered unhealthy — any
+/// call that succeeds after that considers it recovered.
+pub const UNHEALTHY_AFTER: u32 = 5;
+
+/// What a health event says about a capability.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HealthState {
+    /// its calls succeed again, after it was unhealthy
+    Healthy,
+    /// its last `UNHEALTHY_AFTER` calls failed
+    Unhealthy,
+    /// its backend reconnected (e.g., w/ a rotated credential)
+    Reconnected,
+}
+
+impl fmt::Display for HealthState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HealthState::Healthy => "healthy",
+            HealthState::Unhealthy => "unhealthy",
+            HealthState::Reconnected => "reconnected",
+        })
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    /// how many calls in a row failed, per capability
+    failures: HashMap<String, u32>,
+    /// the capability (or `*`) each listener is interested in, w/ where its events go
+    listeners: Vec<(String, Arc<Mutex<Sender<Event>>>)>,
+}
+
+/// `Health` tracks the health of an app's capabilities from the outcome of their calls, and
+/// emits an event (of type `HEALTH_EVENT_TYPE`) whenever it changes, so a guest listening to
+/// them can adapt (e.g., shed load while kv is unhealthy).
+///
+/// Capabilities are named by their scheme (e.g., `kv`), so all the kv stores of an app share
+/// their health. Only the failures of the backend count (i.e., timeouts, credentials that
+/// don't work, and errors of its own): calls it rejected for the guest's input (e.g., a key
+/// that doesn't exist, or an operation its credentials aren't allowed to do) show it's
+/// answering, and calls rejected before they reached it (e.g., because of a quota) say nothing
+/// about it either way.
+///
+/// It is shared through the app's `StateTable` under `HEALTH` (see `shared`), where the
+/// events capability finds it.
+#[derive(Clone, Default)]
+pub struct Health(Arc<Mutex<Inner>>);
+
+impl fmt::Debug for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Health")
+    }
+}
+
+impl Health {
+    /// Gets the health of the app whose `StateTable` this is, creating it if there's none yet.
+    pub fn shared(state_table: &mut StateTable) -> Self {
+        if let Some(health) = state_table.find_shared::<Self>(HEALTH) {
+            return health;
+        }
+        let health = Self::default();
+        state_table.set(HEALTH.to_string(), Box::new(health.clone()));
+        state_table
+            .shared(HEALTH, || health.clone())
+            .expect("the name of the health is reserved")
+    }
+
+    /// Records the outcome of a call into `capability`, by the kind of `error` it failed w/, if
+    /// it did.
+    pub fn record(&self, capability: &str, error: Option<ErrorKind>) {
+        let failed = match error {
+            None | Some(ErrorKind::NotFound | ErrorKind::PermissionDenied) => false,
+            Some(
+                ErrorKind::Timeout
+                | ErrorKind::CredentialsExpired
+                | ErrorKind::CredentialsInvalid
+                | ErrorKind::Backend,
+            ) => true,
+            Some(_) => return,
+        };
+        let mut inner = self.0.lock().unwrap();
+        if !inner.failures.contains_key(capability) {
+            // capabilities whose calls never failed are healthy
+            if !failed {
+                return;
+            }
+            inner.failures.insert(capability.to_string(), 0);
+        }
+        let failures = inner.failures.get_mut(capability).unwrap();
+        let was_healthy = *failures < UNHEALTHY_AFTER;
+        *failures = if failed {
+            failures.saturating_add(1)
+        } else {
+            0
+        };
+        let state = match (*failures < UNHEALTHY_AFTER, was_healthy) {
+            (false, true) => HealthState::Unhealthy,
+            (true, false) => HealthState::Healthy,
+            _ => return,
+        };
+        match state {
+            HealthState::Unhealthy => tracing::warn!("'{}' is {}", capability, state),
+            _ => tracing::info!("'{}' is {}", capability, state),
+        }
+        emit(&mut inner, capability, state);
+    }
+
+    /// Records that the backend of `capability` reconnected.
+    pub fn reconnected(&self, capability: &str) {
+        emit(
+            &mut self.0.lock().unwrap(),
+            capability,
+            HealthState::Reconnected,
+        );
+    }
+
+    pub fn is_healthy(&self, capability: &str) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .failures
+            .get(capability)
+            .map_or(true, |failures| *failures < UNHEALTHY_AFTER)
+    }
+}
+
+impl Watch for Health {
+    fn watch(&mut self, key: &str, sender: Arc<Mutex<Sender<Event>>>) -> Result<()> {
+        self.0
+            .lock()
+            .unwrap()
+            .listeners
+            .push((key.to_string(), sender));
+        Ok(())
+    }
+}
+
+/// Sends a health event to the listeners of `capability`, forgetting the ones that are gone.
+fn emit(inner: &mut Inner, capability: &str, state: HealthState) {
+    let event = match event(capability, state) {
+        Ok(event) => event,
+        Err(e) => {
+            tracing::error!("failed to build health event: {:#}", e);
+            return;
+        }
+    };
+    inner.listeners.retain(|(key, sender)| {
+        if key != "*" && key != capability {
+            return true;
+        }
+        // the guest handling it is traced under the call whose outcome changed the health
+        let mut event = event.clone();
+        cause::mark(&mut event);
+        sender.lock().unwrap().send(event).is_ok()
+    });
+}
+
+fn event(capability: &str, state: HealthState) -> Result<Event> {
+    EventBuilderV10::new()
+        .id(Uuid::new_v4().to_string())
+        .source(capability)
+        .ty(HEALTH_EVENT_TYPE)
+        .subject(state.to_string())
+        .time(Utc::now())
+        .data(
+            "application/json",
+            serde_json::json!({ "capability": capability, "state": state.to_string() }),
+        )
+        .build()
+        .with_context(|| "failed to build event")
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::Result;
+    use slight_events_api::{AttributesReader, Event};
+
+    use super::{Health, HEALTH, UNHEALTHY_AFTER};
+    use crate::{
+        error_kind::ErrorKind,
+        resource::{StateTable, Watch},
+    };
+
+    fn states(events: &crossbeam_channel::Receiver<Event>) -> Vec<String> {
+        events
+            .try_iter()
+            .map(|event| event.subject().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn health_events_test() -> Result<()> {
+        let mut state_table = StateTable::default();
+        let health = Health::shared(&mut state_table);
+        let (kv, kv_events) = crossbeam_channel::unbounded();
+        let (all, all_events) = crossbeam_channel::unbounded();
+        state_table
+            .get_mut(HEALTH)?
+            .watch("kv", Arc::new(Mutex::new(kv)))?;
+        state_table
+            .get_mut(HEALTH)?
+            .watch("*", Arc::new(Mutex::new(all)))?;
+
+        for _ in 1..UNHEALTHY_AFTER {
+            health.record("kv", Some(ErrorKind::Backend));
+        }
+        // a success resets the failures
+        health.record("kv", None);
+        assert!(health.is_healthy("kv"));
+        for _ in 0..UNHEALTHY_AFTER + 2 {
+            health.record("kv", Some(ErrorKind::Backend));
+        }
+        assert!(!health.is_healthy("kv"));
+        health.record("kv", None);
+        health.record("kv", None);
+        health.reconnected("mq");
+
+        assert_eq!(states(&kv_events), vec!["unhealthy", "healthy"]);
+        assert_eq!(
+            states(&all_events),
+            vec!["unhealthy", "healthy", "reconnected"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn guest_errors_test() {
+        let health = Health::default();
+        for _ in 0..UNHEALTHY_AFTER {
+            health.record("kv", Some(ErrorKind::NotFound));
+            health.record("kv", Some(ErrorKind::PermissionDenied));
+            health.record("kv", Some(ErrorKind::RateLimited));
+        }
+        assert!(health.is_healthy("kv"));
+
+        // rejected calls don't reset the failures of the backend, but the ones it answered do
+        for _ in 1..UNHEALTHY_AFTER {
+            health.record("kv", Some(ErrorKind::Timeout));
+            health.record("kv", Some(ErrorKind::Disabled));
+        }
+        health.record("kv", Some(ErrorKind::NotFound));
+        health.record("kv", Some(ErrorKind::Backend));
+        assert!(health.is_healthy("kv"));
+    }
+
+    #[test]
+    fn shared_test() {
+        let mut state_table = StateTable::default();
+        let health = Health::shared(&mut state_table);
+        for _ in 0..UNHEALTHY_AFTER {
+            health.record("kv", Some(ErrorKind::Backend));
+        }
+        assert!(!Health::shared(&mut state_table).is_healthy("kv"));
+    }
+}
@@ -0,0 +1,156 @@
+use std::sync::{Arc, Condvar, Mutex};
+
+/// What `Invocations` report about the guest invocations of an app.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InvocationsReport {
+    /// how many invocations are in flight right now
+    pub in_flight: u32,
+    /// the most invocations that were in flight at once (i.e., the high-water mark)
+    pub peak: u32,
+    /// how many invocations can be in flight at once, if there's a limit
+    pub max: Option<u32>,
+    /// how many invocations waited for another one to finish (e.g., events)
+    pub delayed: u64,
+    /// how many invocations were shed, as none could be in flight (e.g., http requests)
+    pub shed: u64,
+}
+
+/// `Invocations` bound how many times the guest is invoked at once (i.e., how many of its
+/// exports run at once) across all of the triggers that invoke it — the http handlers, and
+/// the event handlers — to its `max`, like a semaphore shared by them.
+///
+/// What happens to the invocations beyond it depends on their trigger: some wait for one to
+/// finish (see `acquire`), while others are shed right away (see `try_acquire`).
+///
+/// They are shared by all of an app's guest instances, and kept across its restarts.
+#[derive(Clone, Debug, Default)]
+pub struct Invocations(Arc<Inner>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    state: Mutex<State>,
+    released: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    max: Option<u32>,
+    in_flight: u32,
+    peak: u32,
+    delayed: u64,
+    shed: u64,
+}
+
+impl State {
+    fn full(&self) -> bool {
+        matches!(self.max, Some(max) if self.in_flight >= max)
+    }
+
+    fn start(&mut self) {
+        self.in_flight += 1;
+        self.peak = self.peak.max(self.in_flight);
+    }
+}
+
+/// An `Invocation` in flight, which finishes once it's dropped.
+#[derive(Debug)]
+pub struct Invocation(Arc<Inner>);
+
+impl Drop for Invocation {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().in_flight -= 1;
+        self.0.released.notify_one();
+    }
+}
+
+impl Invocations {
+    /// Sets how many invocations can be in flight at once (unlimited, if `None`), as per the
+    /// slightfile of the app's current run.
+    pub fn configure(&self, max: Option<u32>) {
+        self.0.state.lock().unwrap().max = max;
+        self.0.released.notify_all();
+    }
+
+    /// Starts an invocation, waiting for one to finish first, if there are `max` in flight.
+    pub fn acquire(&self, trigger: &str) -> Invocation {
+        let mut state = self.0.state.lock().unwrap();
+        if state.full() {
+            state.delayed += 1;
+            tracing::debug!(
+                "delaying the {} invocation, as {} invocations are in flight",
+                trigger,
+                state.in_flight
+            );
+        }
+        while state.full() {
+            state = self.0.released.wait(state).unwrap();
+        }
+        state.start();
+        Invocation(self.0.clone())
+    }
+
+    /// Starts an invocation, or `None` if there are `max` in flight.
+    pub fn try_acquire(&self, trigger: &str) -> Option<Invocation> {
+        let mut state = self.0.state.lock().unwrap();
+        if state.full() {
+            state.shed += 1;
+            tracing::debug!(
+                "shedding the {} invocation, as {} invocations are in flight",
+                trigger,
+                state.in_flight
+            );
+            return None;
+        }
+        state.start();
+        Some(Invocation(self.0.clone()))
+    }
+
+    pub fn report(&self) -> InvocationsReport {
+        let state = self.0.state.lock().unwrap();
+        InvocationsReport {
+            in_flight: state.in_flight,
+            peak: state.peak,
+            max: state.max,
+            delayed: state.delayed,
+            shed: state.shed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::{thread, time::Duration};
+
+    use super::Invocations;
+
+    #[test]
+    fn bounds_invocations_test() {
+        let invocations = Invocations::default();
+        invocations.configure(Some(1));
+        let first = invocations.acquire("event");
+        assert!(invocations.try_acquire("http").is_none());
+
+        let delayed = {
+            let invocations = invocations.clone();
+            thread::spawn(move || drop(invocations.acquire("event")))
+        };
+        thread::sleep(Duration::from_millis(50));
+        // the delayed invocation waits for the first one to finish
+        assert!(!delayed.is_finished());
+        drop(first);
+        delayed.join().unwrap();
+
+        let report = invocations.report();
+        assert_eq!(report.in_flight, 0);
+        assert_eq!(report.peak, 1);
+        assert_eq!(report.delayed, 1);
+        assert_eq!(report.shed, 1);
+
+        // w/o a limit, nothing is delayed, or shed
+        invocations.configure(None);
+        let _held = (0..3)
+            .map(|_| invocations.try_acquire("http").unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(invocations.report().peak, 3);
+    }
+}
@@ -0,0 +1,247 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::{error_kind::ErrorKind, metrics::CallMetrics};
+
+/// The last value read for each `scope`d key, w/ when it was read.
+type Values = HashMap<(String, Vec<u8>), (Vec<u8>, Instant)>;
+
+/// `LastKnownGood` remembers the last value successfully read for each key, so that,
+/// if the backend later fails to serve a read, the last known good value can be served
+/// instead — as long as it isn't older than `max_age`.
+///
+/// Keys are `scope`d (e.g., by the name of the kv store they belong to), and values are
+/// only tracked for writes made through this host, so a key deleted by someone else can
+/// be served (for up to `max_age`) during an outage.
+///
+/// Only reads that fail as the backend did (i.e., w/ a backend error, or a timeout) are
+/// served a last known good value — a key that doesn't exist (anymore), or a read the guest
+/// isn't allowed to make fails as usual.
+///
+/// It is disabled (i.e., reads fail as usual) if `max_age` is `None`.
+#[derive(Clone, Debug, Default)]
+pub struct LastKnownGood {
+    max_age: Option<Duration>,
+    values: Arc<Mutex<Values>>,
+    /// the metrics the stale reads are counted in (see `CallMetrics::record_stale_read`)
+    metrics: Option<Arc<CallMetrics>>,
+}
+
+impl LastKnownGood {
+    pub fn new(max_age_ms: Option<u64>) -> Self {
+        Self {
+            max_age: max_age_ms.map(Duration::from_millis),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_metrics(mut self, metrics: Option<Arc<CallMetrics>>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Reads a value w/ `f`, falling back to the last known good value for `key` if it fails.
+    pub fn read(
+        &self,
+        scope: &str,
+        key: &[u8],
+        f: impl FnOnce() -> Result<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        self.read_at(Instant::now(), scope, key, f)
+    }
+
+    fn read_at(
+        &self,
+        now: Instant,
+        scope: &str,
+        key: &[u8],
+        f: impl FnOnce() -> Result<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        let max_age = match self.max_age {
+            Some(max_age) => max_age,
+            None => return f(),
+        };
+
+        let entry = (scope.to_string(), key.to_vec());
+        match f() {
+            Ok(value) => {
+                self.values
+                    .lock()
+                    .unwrap()
+                    .insert(entry, (value.clone(), now));
+                Ok(value)
+            }
+            Err(e) => {
+                let mut values = self.values.lock().unwrap();
+                match ErrorKind::of(&e) {
+                    ErrorKind::Backend | ErrorKind::Timeout => {}
+                    ErrorKind::NotFound => {
+                        values.remove(&entry);
+                        return Err(e);
+                    }
+                    _ => return Err(e),
+                }
+                match values.get(&entry) {
+                    Some((value, read_at)) if now.duration_since(*read_at) <= max_age => {
+                        if let Some(metrics) = &self.metrics {
+                            metrics.record_stale_read();
+                        }
+                        tracing::warn!(
+                            "serving last known good value for '{}' in '{}' (read {:?} ago), because the backend failed: {}",
+                            String::from_utf8_lossy(key),
+                            scope,
+                            now.duration_since(*read_at),
+                            e
+                        );
+                        Ok(value.clone())
+                    }
+                    _ => {
+                        values.remove(&entry);
+                        Err(e)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Records `value` as the last known good value for `key` (i.e., after writing it).
+    pub fn remember(&self, scope: &str, key: &[u8], value: &[u8]) {
+        if self.max_age.is_some() {
+            self.values.lock().unwrap().insert(
+                (scope.to_string(), key.to_vec()),
+                (value.to_vec(), Instant::now()),
+            );
+        }
+    }
+
+    /// Forgets the last known good value for `key` (i.e., after deleting it).
+    pub fn forget(&self, scope: &str, key: &[u8]) {
+        self.values
+            .lock()
+            .unwrap()
+            .remove(&(scope.to_string(), key.to_vec()));
+    }
+
+    /// Forgets the last known good values of all keys in `scope` starting w/ `prefix`.
+    pub fn forget_prefix(&self, scope: &str, prefix: &[u8]) {
+        self.values
+            .lock()
+            .unwrap()
+            .retain(|(s, k), _| s != scope || !k.starts_with(prefix));
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::{Duration, Instant};
+
+    use anyhow::{bail, Result};
+
+    use super::LastKnownGood;
+    use crate::{
+        error_kind::NotFound,
+        grants::Grants,
+        metrics::{LabelSettings, Metrics, SizeBuckets},
+    };
+
+    const MAX_AGE: Duration = Duration::from_secs(10);
+
+    fn outage() -> Result<Vec<u8>> {
+        bail!("backend is unavailable")
+    }
+
+    fn last_known_good(metrics: &Metrics) -> LastKnownGood {
+        LastKnownGood::new(Some(MAX_AGE.as_millis() as u64)).with_metrics(Some(metrics.get(
+            "kv",
+            LabelSettings::default(),
+            SizeBuckets::default(),
+        )))
+    }
+
+    #[test]
+    fn serves_last_known_good_within_max_age() -> Result<()> {
+        let metrics = Metrics::default();
+        let lkg = last_known_good(&metrics);
+        let t = Instant::now();
+        lkg.read_at(t, "store", b"key", || Ok(b"value".to_vec()))?;
+
+        assert_eq!(lkg.read_at(t + MAX_AGE, "store", b"key", outage)?, b"value");
+        assert_eq!(metrics.stale_reads(), vec![("kv".to_string(), 1)]);
+        Ok(())
+    }
+
+    #[test]
+    fn only_serves_last_known_good_when_backend_fails() -> Result<()> {
+        let metrics = Metrics::default();
+        let lkg = last_known_good(&metrics);
+        lkg.remember("store", b"key", b"value");
+
+        // the guest isn't allowed to read it, whatever the value was
+        let denied = || -> Result<Vec<u8>> {
+            Err(Grants::new(Some(Vec::new()), Vec::new())
+                .check("kv", "get")
+                .unwrap_err())
+        };
+        assert!(lkg.read("store", b"key", denied).is_err());
+        // a key someone else deleted isn't served, nor remembered anymore
+        let not_found = || -> Result<Vec<u8>> { Err(NotFound("no such key".into()).into()) };
+        assert!(lkg.read("store", b"key", not_found).is_err());
+        assert!(lkg.read("store", b"key", outage).is_err());
+        assert_eq!(metrics.stale_reads(), vec![("kv".to_string(), 0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn fails_once_last_known_good_is_too_old() -> Result<()> {
+        let metrics = Metrics::default();
+        let lkg = last_known_good(&metrics);
+        let t = Instant::now();
+        lkg.read_at(t, "store", b"key", || Ok(b"value".to_vec()))?;
+
+        let too_late = t + MAX_AGE + Duration::from_millis(1);
+        assert!(lkg.read_at(too_late, "store", b"key", outage).is_err());
+        assert_eq!(metrics.stale_reads(), vec![("kv".to_string(), 0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn values_are_scoped() -> Result<()> {
+        let lkg = LastKnownGood::new(Some(MAX_AGE.as_millis() as u64));
+        lkg.remember("store", b"key", b"value");
+
+        assert!(lkg.read("other-store", b"key", outage).is_err());
+        assert_eq!(lkg.read("store", b"key", outage)?, b"value");
+        Ok(())
+    }
+
+    #[test]
+    fn forgotten_values_are_not_served() {
+        let lkg = LastKnownGood::new(Some(MAX_AGE.as_millis() as u64));
+        lkg.remember("store", b"app/a", b"1");
+        lkg.remember("store", b"app/b", b"2");
+
+        lkg.remember("store", b"other", b"3");
+
+        lkg.forget("store", b"other");
+        assert!(lkg.read("store", b"other", outage).is_err());
+
+        lkg.forget_prefix("store", b"app/");
+        assert!(lkg.read("store", b"app/a", outage).is_err());
+        assert!(lkg.read("store", b"app/b", outage).is_err());
+    }
+
+    #[test]
+    fn disabled_without_max_age() -> Result<()> {
+        let lkg = LastKnownGood::new(None);
+        lkg.read("store", b"key", || Ok(b"value".to_vec()))?;
+        lkg.remember("store", b"key", b"value");
+
+        assert!(lkg.read("store", b"key", outage).is_err());
+        Ok(())
+    }
+}
@@ -1,11 +1,54 @@
+pub mod audit;
+pub mod batch;
+pub mod call;
+pub mod cassette;
+pub mod cause;
+pub mod chaos;
+pub mod compat;
+pub mod connections;
+pub mod credentials;
+pub mod deadline;
+pub mod describe;
+pub mod drain;
+pub mod encoding;
+pub mod error_kind;
+pub mod export;
+pub mod flags;
+pub mod grants;
+pub mod headroom;
+pub mod health;
+pub mod invocations;
+pub mod last_known_good;
+pub mod log_sink;
+pub mod manifest;
+pub mod memory;
+pub mod metrics;
+pub mod mock;
+pub mod page_token;
+pub mod payload_limit;
+pub mod pool;
+pub mod quota;
+pub mod redact;
+pub mod release;
 pub mod resource;
+pub mod sandbox;
+pub mod serial;
+pub mod signing;
+pub mod split;
+pub mod support;
+pub mod trace;
+pub mod trace_context;
 use std::collections::HashMap;
 
 use anyhow::Result;
-use resource::{Ctx, GuestData, HttpData, ResourceBuilder};
+use headroom::Headroom;
+use memory::{Limiter, MemoryMonitor};
+use rand::{rngs::StdRng, SeedableRng};
+use resource::{BatchGuestData, Ctx, GuestData, HttpData, ResourceBuilder};
+use sandbox::FilesystemSandbox;
 use wasi_cap_std_sync::WasiCtxBuilder;
 use wasi_common::WasiCtx;
-use wasmtime::{Config, Engine, Instance, Linker, Module, Store};
+use wasmtime::{Config, Engine, Instance, InstancePre, Linker, Module, Store};
 use wasmtime_wasi::*;
 
 /// A wasmtime runtime context to be passed to a wasm module.
@@ -14,7 +57,13 @@ pub struct RuntimeContext<Host> {
     pub wasi: Option<WasiCtx>,
     pub data: HashMap<String, Host>,
     pub state: GuestData,
+    pub batch_state: BatchGuestData,
     pub http_state: HttpData,
+    pub limits: Limiter,
+    /// The deadline of what the guest is handling (e.g., an http request whose client gives up
+    /// on it then), if it has one, which the capability calls it makes are held to (see
+    /// `deadline`).
+    pub deadline: Option<std::time::Instant>,
 }
 
 /// A wasmtime-based runtime builder.
@@ -28,15 +77,26 @@ pub struct Builder {
 impl Builder {
     /// Create a new runtime builder.
     pub fn new_default() -> Result<Self> {
+        Self::new_with_engine(&Engine::new(&default_config()?)?)
+    }
+
+    /// Create a new runtime builder that shares an existing engine.
+    ///
+    /// Modules compiled w/ the same engine can be instantiated in any of
+    /// the builders created from it (see `pre_build`, and `build_from_pre`).
+    pub fn new_with_engine(engine: &Engine) -> Result<Self> {
         let wasi = default_wasi()?;
-        let engine = Engine::new(&default_config()?)?;
+        let engine = engine.clone();
         let mut linker = Linker::new(&engine);
         linker.allow_shadowing(true);
         let ctx = RuntimeContext {
             wasi: Some(wasi),
             data: HashMap::new(),
             state: GuestData::default(),
+            batch_state: BatchGuestData::default(),
             http_state: HttpData::default(),
+            limits: Limiter::default(),
+            deadline: None,
         };
 
         let store = Store::new(&engine, ctx);
@@ -71,12 +131,78 @@ impl Builder {
         Ok(self)
     }
 
+    /// Limit how large each linear memory of the guest can grow, in bytes.
+    pub fn limit_memory(&mut self, max_memory_bytes: usize) -> &mut Self {
+        self.store.data_mut().limits.limit_memory(max_memory_bytes);
+        self.store.limiter(|ctx| &mut ctx.limits);
+        self
+    }
+
+    /// Track the linear memories of the guest, and how they grow, w/ `monitor` (see
+    /// `MemoryMonitor`).
+    pub fn monitor_memory(&mut self, monitor: MemoryMonitor) -> &mut Self {
+        self.store.data_mut().limits.monitor(monitor);
+        self.store.limiter(|ctx| &mut ctx.limits);
+        self
+    }
+
+    /// The headroom of the guest's memory for the results capabilities return to it (see
+    /// `Headroom`), which its capabilities are to check them against.
+    pub fn headroom(&self) -> Headroom {
+        self.store.data().limits.headroom()
+    }
+
+    /// Sandbox the filesystem of the guest (see `FilesystemSandbox`), rather than giving it
+    /// the default `cache` directory.
+    ///
+    /// It replaces the WASI context, so it's to come before whatever else configures it
+    /// (e.g., `seed_random`).
+    pub fn sandbox_filesystem(&mut self, sandbox: &FilesystemSandbox) -> Result<&mut Self> {
+        self.store.data_mut().wasi = Some(sandbox.wasi()?);
+        Ok(self)
+    }
+
+    /// Seed the randomness of the guest (i.e., WASI `random_get`) w/ `seed`, rather than
+    /// getting it from the OS' CSPRNG, so the guest gets the same random numbers every run.
+    ///
+    /// This is for tests only (e.g., to replay a cassette), as the numbers are predictable.
+    pub fn seed_random(&mut self, seed: u64) -> &mut Self {
+        if let Some(wasi) = self.store.data_mut().wasi.as_mut() {
+            wasi.random = Box::new(StdRng::seed_from_u64(seed));
+        }
+        self
+    }
+
     /// Instantiate the guest module.
     pub fn build(mut self, module: &str) -> Result<(Engine, Store<Ctx>, Instance)> {
         let module = Module::from_file(&self.engine, module)?;
         let instance = self.linker.instantiate(&mut self.store, &module)?;
         Ok((self.engine, self.store, instance))
     }
+
+    /// Get the engine of this builder.
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Resolve the imports of an already compiled module against the linked
+    /// capabilities, so that it can be instantiated many times w/o redoing that work.
+    ///
+    /// All host capabilities are linked as host functions, so the returned
+    /// `InstancePre` can be instantiated in the store of any builder that
+    /// shares this builder's engine, and has linked the same capabilities.
+    pub fn pre_build(&mut self, module: &Module) -> Result<InstancePre<Ctx>> {
+        self.linker.instantiate_pre(&mut self.store, module)
+    }
+
+    /// Instantiate a module that has already been compiled, and pre-linked.
+    pub fn build_from_pre(
+        mut self,
+        instance_pre: &InstancePre<Ctx>,
+    ) -> Result<(Store<Ctx>, Instance)> {
+        let instance = instance_pre.instantiate(&mut self.store)?;
+        Ok((self.store, instance))
+    }
 }
 
 // TODO (Joe): expose the wasmtime config as a capability?
@@ -0,0 +1,305 @@
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+/// The name of the custom section of a module that holds its `Manifest`.
+pub const MANIFEST_SECTION: &str = "slight-manifest";
+
+/// The version of the `Manifest` format this slight reads (and the newest it can).
+pub const MANIFEST_VERSION: u64 = 1;
+
+/// A `Manifest` is what a guest declares it requires of the host: which capabilities, and which
+/// of their operations it calls, so it can be checked against the slightfile at startup, rather
+/// than failing once the guest calls an operation the configured backend doesn't support.
+///
+/// It's embedded in the module as a custom section named `MANIFEST_SECTION`, as JSON, e.g.:
+///     `{ "version": 1, "capabilities": [{ "name": "kv", "operations": ["get", "set"] }] }`
+///
+/// where a capability is named as its interface is (e.g., `kv`, or `configs`), and each of its
+/// operations as its function is (e.g., `set-with-time-to-live`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    pub version: u64,
+    pub capabilities: Vec<Requirement>,
+}
+
+/// A capability a guest requires, and the operations of it it calls (none, if it didn't say).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Requirement {
+    pub name: String,
+    pub operations: Vec<String>,
+}
+
+impl Manifest {
+    /// Reads the manifest of a `module` (i.e., its bytes), or `None` if it has none.
+    pub fn read(module: &[u8]) -> Result<Option<Self>> {
+        custom_section(module, MANIFEST_SECTION)?
+            .map(Self::parse)
+            .transpose()
+    }
+
+    pub fn parse(section: &[u8]) -> Result<Self> {
+        let manifest = serde_json::from_slice::<Value>(section)
+            .with_context(|| format!("invalid {} section", MANIFEST_SECTION))?;
+        let version = manifest["version"]
+            .as_u64()
+            .with_context(|| format!("invalid {}: it has no version", MANIFEST_SECTION))?;
+        if version > MANIFEST_VERSION {
+            bail!(
+                "invalid {}: version {} is newer than this slight reads (i.e., {})",
+                MANIFEST_SECTION,
+                version,
+                MANIFEST_VERSION
+            );
+        }
+        let capabilities = match &manifest["capabilities"] {
+            Value::Null => Vec::new(),
+            Value::Array(capabilities) => capabilities
+                .iter()
+                .map(Requirement::from_json)
+                .collect::<Result<_>>()?,
+            _ => bail!(
+                "invalid {}: its capabilities must be a list",
+                MANIFEST_SECTION
+            ),
+        };
+        Ok(Self {
+            version,
+            capabilities,
+        })
+    }
+
+    /// Checks that the `capabilities` of the slightfile (i.e., their names, like
+    /// `kv.filesystem`) provide what the guest requires, where `operations` are the operations
+    /// of an interface (e.g., `kv`), and `unsupported` says which of them the backend of a
+    /// capability doesn't support — failing w/ all of what they don't.
+    ///
+    /// An operation that isn't one of its interface's (e.g., a typo) fails the check too,
+    /// rather than passing it as if it were supported.
+    pub fn check<'a>(
+        &self,
+        capabilities: &[&str],
+        operations: impl Fn(&str) -> Vec<String>,
+        unsupported: impl Fn(&str) -> &'a [&'a str],
+    ) -> Result<()> {
+        let mut missing = Vec::new();
+        for requirement in &self.capabilities {
+            let known = operations(&requirement.name);
+            for operation in &requirement.operations {
+                if !known.contains(operation) {
+                    missing.push(format!(
+                        "the guest calls '{}' of '{}', which isn't one of its operations (i.e., one of {:?})",
+                        operation, requirement.name, known
+                    ));
+                }
+            }
+            let configured = capabilities
+                .iter()
+                .filter(|capability| interface(capability) == requirement.name)
+                .collect::<Vec<_>>();
+            if configured.is_empty() {
+                missing.push(format!(
+                    "the guest requires the '{}' capability, but the slightfile has none",
+                    requirement.name
+                ));
+            }
+            for capability in configured {
+                let unsupported = unsupported(capability);
+                for operation in &requirement.operations {
+                    if unsupported.contains(&operation.as_str()) {
+                        missing.push(format!(
+                            "the guest calls '{}' of '{}', which '{}' doesn't support",
+                            operation, requirement.name, capability
+                        ));
+                    }
+                }
+            }
+        }
+        if !missing.is_empty() {
+            bail!(
+                "the slightfile doesn't provide what the guest requires (as per its {}):\n  - {}",
+                MANIFEST_SECTION,
+                missing.join("\n  - ")
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Requirement {
+    fn from_json(requirement: &Value) -> Result<Self> {
+        let name = requirement["name"].as_str().with_context(|| {
+            format!(
+                "invalid {}: a capability has no name: {}",
+                MANIFEST_SECTION, requirement
+            )
+        })?;
+        let operations = match &requirement["operations"] {
+            Value::Null => Vec::new(),
+            Value::Array(operations) => operations
+                .iter()
+                .map(|operation| operation.as_str().map(str::to_string))
+                .collect::<Option<_>>()
+                .with_context(|| {
+                    format!(
+                        "invalid {}: the operations of '{}' must be strings",
+                        MANIFEST_SECTION, name
+                    )
+                })?,
+            _ => bail!(
+                "invalid {}: the operations of '{}' must be a list",
+                MANIFEST_SECTION,
+                name
+            ),
+        };
+        Ok(Self {
+            name: name.to_string(),
+            operations,
+        })
+    }
+}
+
+/// The interface a capability of the slightfile implements (e.g., `kv` for `kv.filesystem`,
+/// and `runtime-control` for `runtime_control`).
+fn interface(capability: &str) -> String {
+    capability
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .replace('_', "-")
+}
+
+/// The payload of the first custom section of a wasm `module` named `name`, if it has one.
+fn custom_section<'a>(module: &'a [u8], name: &str) -> Result<Option<&'a [u8]>> {
+    let mut sections = match module.strip_prefix(b"\0asm") {
+        Some(rest) if rest.len() >= 4 => &rest[4..],
+        _ => bail!("not a wasm module"),
+    };
+    while !sections.is_empty() {
+        let id = sections[0];
+        sections = &sections[1..];
+        let size = leb128(&mut sections)?;
+        if size > sections.len() {
+            bail!("invalid wasm module: section {} is truncated", id);
+        }
+        let (mut section, rest) = sections.split_at(size);
+        sections = rest;
+        // custom sections are the ones w/ id 0, and each starts w/ its name
+        if id == 0 {
+            let length = leb128(&mut section)?;
+            if length > section.len() {
+                bail!("invalid wasm module: the name of a custom section is truncated");
+            }
+            let (section_name, payload) = section.split_at(length);
+            if section_name == name.as_bytes() {
+                return Ok(Some(payload));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Reads an unsigned LEB128 number (as sizes are encoded in wasm) off the front of `bytes`.
+fn leb128(bytes: &mut &[u8]) -> Result<usize> {
+    let mut value = 0usize;
+    for (i, byte) in bytes.iter().enumerate().take(5) {
+        value |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[i + 1..];
+            return Ok(value);
+        }
+    }
+    bail!("invalid wasm module: malformed size")
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+
+    use super::{Manifest, Requirement, MANIFEST_SECTION};
+
+    /// A module w/ an (empty) type section, and a custom section of `name`, holding `payload`.
+    fn module(name: &str, payload: &[u8]) -> Vec<u8> {
+        let mut custom = vec![name.len() as u8];
+        custom.extend_from_slice(name.as_bytes());
+        custom.extend_from_slice(payload);
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        module.extend_from_slice(&[1, 1, 0]);
+        module.push(0);
+        module.push(custom.len() as u8);
+        module.extend_from_slice(&custom);
+        module
+    }
+
+    #[test]
+    fn read_test() -> Result<()> {
+        let manifest =
+            br#"{"version":1,"capabilities":[{"name":"kv","operations":["get"]},{"name":"http"}]}"#;
+        assert_eq!(
+            Manifest::read(&module(MANIFEST_SECTION, manifest))?,
+            Some(Manifest {
+                version: 1,
+                capabilities: vec![
+                    Requirement {
+                        name: "kv".to_string(),
+                        operations: vec!["get".to_string()],
+                    },
+                    Requirement {
+                        name: "http".to_string(),
+                        operations: vec![],
+                    },
+                ],
+            })
+        );
+        assert_eq!(Manifest::read(&module("name", b"\0"))?, None);
+        assert!(Manifest::read(&module(MANIFEST_SECTION, br#"{"version":2}"#)).is_err());
+        assert!(Manifest::read(b"not wasm").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn check_test() -> Result<()> {
+        let manifest = Manifest::parse(
+            br#"{"version":1,"capabilities":[{"name":"kv","operations":["get","set-with-time-to-live"]},{"name":"runtime-control"}]}"#,
+        )?;
+        let operations = |interface: &str| match interface {
+            "kv" => vec!["get".to_string(), "set-with-time-to-live".to_string()],
+            _ => vec![],
+        };
+        let unsupported = |capability: &str| -> &[&str] {
+            match capability {
+                "kv.azblob" => &["set-with-time-to-live"],
+                _ => &[],
+            }
+        };
+        manifest.check(
+            &["kv.filesystem", "runtime_control"],
+            operations,
+            unsupported,
+        )?;
+
+        let e = manifest
+            .check(&["kv.filesystem", "kv.azblob"], operations, unsupported)
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("'set-with-time-to-live' of 'kv', which 'kv.azblob' doesn't support"));
+        assert!(e.contains("requires the 'runtime-control' capability"));
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_operation_test() -> Result<()> {
+        let manifest = Manifest::parse(
+            br#"{"version":1,"capabilities":[{"name":"kv","operations":["get","gte"]}]}"#,
+        )?;
+        let e = manifest
+            .check(
+                &["kv.filesystem"],
+                |_| vec!["get".to_string()],
+                |_| -> &[&str] { &[] },
+            )
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("'gte' of 'kv', which isn't one of its operations"));
+        Ok(())
+    }
+}
@@ -0,0 +1,395 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use wasmtime::{ResourceLimiter, StoreLimits, StoreLimitsBuilder};
+
+use crate::headroom::Headroom;
+
+/// The window memory growth is measured over, if `GrowthSettings` don't say.
+pub const DEFAULT_GROWTH_WINDOW: Duration = Duration::from_secs(3600);
+
+/// What happens when the linear memories of an app grow by more than their budget within
+/// the window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GrowthAction {
+    /// log a warning (once per window), and let them grow
+    Warn,
+    /// refuse to grow them (i.e., `memory.grow` fails), which most guests trap on (e.g.,
+    /// Rust guests abort when they can't allocate)
+    Trap,
+}
+
+impl GrowthAction {
+    pub fn parse(action: &str) -> Result<Self> {
+        match action {
+            "warn" => Ok(Self::Warn),
+            "trap" => Ok(Self::Trap),
+            _ => bail!(
+                "invalid memory growth action: '{}' (expected 'warn', or 'trap')",
+                action
+            ),
+        }
+    }
+}
+
+/// The budget of how much the linear memories of an app can grow by within a window, which
+/// catches slow leaks a static cap (i.e., `max_memory_bytes`) only does once it's too late.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GrowthSettings {
+    pub budget_bytes: u64,
+    pub window: Duration,
+    pub action: GrowthAction,
+}
+
+impl GrowthSettings {
+    pub fn new(budget_bytes: u64, window_secs: Option<u64>, action: GrowthAction) -> Self {
+        Self {
+            budget_bytes,
+            window: window_secs.map_or(DEFAULT_GROWTH_WINDOW, Duration::from_secs),
+            action,
+        }
+    }
+}
+
+/// What a `MemoryMonitor` reports about the linear memories of an app.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// how many bytes the linear memories of its guest instances have right now
+    pub bytes: u64,
+    /// the most bytes they had at once (i.e., the high-water mark)
+    pub peak: u64,
+    /// how many bytes they grew by, net (i.e., less the growth of the instances dropped since),
+    /// within the last window (w/o the memories instances start w/), if there's a budget
+    pub growth_bytes: u64,
+    /// how many times the growth went over budget
+    pub over_budget: u64,
+    /// how many times they were refused to grow for it
+    pub refused: u64,
+}
+
+/// `MemoryMonitor` tracks the linear memories of all of an app's guest instances (e.g., the
+/// ones handling http requests), which it's kept across restarts of, and how they grow
+/// over time.
+///
+/// The growth is net: the memories of instances that are dropped (e.g., once they've handled
+/// a request) aren't growth anymore, so only what's kept growing (i.e., a leak) counts
+/// towards the budget, not instances that come, and go.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryMonitor(Arc<Mutex<Inner>>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    settings: Option<GrowthSettings>,
+    bytes: u64,
+    peak: u64,
+    /// how many bytes the memories of the live instances grew by since they started
+    grown: u64,
+    /// when `grown` changed within the last window, and what it changed to
+    samples: VecDeque<(Instant, u64)>,
+    /// what `grown` was when the last window started
+    baseline: u64,
+    /// when the growth was last reported to be over budget
+    warned_at: Option<Instant>,
+    over_budget: u64,
+    refused: u64,
+}
+
+impl Inner {
+    /// How many bytes the memories grew by, net, within the window.
+    fn growth_bytes(&mut self, now: Instant, window: Duration) -> u64 {
+        while let Some((at, grown)) = self.samples.front().copied() {
+            if now.duration_since(at) <= window {
+                break;
+            }
+            self.baseline = grown;
+            self.samples.pop_front();
+        }
+        self.grown.saturating_sub(self.baseline)
+    }
+
+    fn sample(&mut self, now: Instant) {
+        if self.settings.is_some() {
+            self.samples.push_back((now, self.grown));
+        }
+    }
+}
+
+impl MemoryMonitor {
+    /// Sets the budget the growth of the memories is held to (none, if `None`), as per the
+    /// slightfile of the app's current run.
+    pub fn configure(&self, settings: Option<GrowthSettings>) {
+        let mut inner = self.0.lock().unwrap();
+        inner.settings = settings;
+        // the growth before is the new window's baseline
+        inner.baseline = inner.grown;
+        inner.samples.clear();
+    }
+
+    /// Whether a memory can grow by `bytes` — memories that are being created (i.e., the one
+    /// an instance starts w/) always can.
+    fn growing(&self, bytes: u64, created: bool) -> bool {
+        let mut inner = self.0.lock().unwrap();
+        if let (Some(settings), false) = (inner.settings, created) {
+            let now = Instant::now();
+            let growth_bytes = inner.growth_bytes(now, settings.window) + bytes;
+            if growth_bytes > settings.budget_bytes {
+                inner.over_budget += 1;
+                if settings.action == GrowthAction::Trap {
+                    inner.refused += 1;
+                    tracing::error!(
+                        "refused to grow the guest's memory by {} bytes, as it grew by {} bytes in the last {:?} (i.e., more than its budget of {} bytes), which looks like a leak",
+                        bytes,
+                        growth_bytes - bytes,
+                        settings.window,
+                        settings.budget_bytes
+                    );
+                    return false;
+                }
+                if inner
+                    .warned_at
+                    .is_none_or(|at| now.duration_since(at) >= settings.window)
+                {
+                    inner.warned_at = Some(now);
+                    tracing::warn!(
+                        "the guest's memory grew by {} bytes in the last {:?} (i.e., more than its budget of {} bytes), which looks like a leak — it's now {} bytes",
+                        growth_bytes,
+                        settings.window,
+                        settings.budget_bytes,
+                        inner.bytes + bytes
+                    );
+                }
+            }
+        }
+        if !created {
+            inner.grown += bytes;
+            inner.sample(Instant::now());
+        }
+        inner.bytes += bytes;
+        inner.peak = inner.peak.max(inner.bytes);
+        true
+    }
+
+    /// Releases the memories of a dropped instance: `bytes` in all, `grown` of which they
+    /// grew by since it started.
+    fn released(&self, bytes: u64, grown: u64) {
+        let mut inner = self.0.lock().unwrap();
+        inner.bytes = inner.bytes.saturating_sub(bytes);
+        inner.grown = inner.grown.saturating_sub(grown);
+        inner.sample(Instant::now());
+    }
+
+    /// How many more bytes the memories can grow by within the window, if the budget traps
+    /// (i.e., they're refused to grow past it).
+    pub(crate) fn remaining_growth(&self) -> Option<u64> {
+        let mut inner = self.0.lock().unwrap();
+        match inner.settings {
+            Some(settings) if settings.action == GrowthAction::Trap => {
+                let growth_bytes = inner.growth_bytes(Instant::now(), settings.window);
+                Some(settings.budget_bytes.saturating_sub(growth_bytes))
+            }
+            _ => None,
+        }
+    }
+
+    /// Reports on the memories.
+    pub fn report(&self) -> MemoryReport {
+        let mut inner = self.0.lock().unwrap();
+        let growth_bytes = inner.settings.map_or(0, |settings| {
+            inner.growth_bytes(Instant::now(), settings.window)
+        });
+        MemoryReport {
+            bytes: inner.bytes,
+            peak: inner.peak,
+            growth_bytes,
+            over_budget: inner.over_budget,
+            refused: inner.refused,
+        }
+    }
+}
+
+/// `Limiter` is what limits the resources of a guest instance (i.e., its store): the static
+/// `limits` of wasmtime, and, if it has a `monitor`, the growth of its memories — which it
+/// keeps the `headroom` of the instance up to date w/.
+///
+/// The memories of an instance are released from the monitor when its store is dropped.
+#[derive(Default)]
+pub struct Limiter {
+    limits: StoreLimits,
+    monitor: Option<MemoryMonitor>,
+    headroom: Headroom,
+    /// how many bytes the memories of this instance have
+    bytes: u64,
+    /// how many of them it grew by since it started
+    grown: u64,
+}
+
+impl Limiter {
+    /// Limits how large each memory can grow, in bytes.
+    pub fn limit_memory(&mut self, max_memory_bytes: usize) {
+        self.limits = StoreLimitsBuilder::new()
+            .memory_size(max_memory_bytes)
+            .build();
+        self.headroom.limit(max_memory_bytes);
+    }
+
+    pub fn monitor(&mut self, monitor: MemoryMonitor) {
+        self.headroom.monitor(monitor.clone());
+        self.monitor = Some(monitor);
+    }
+
+    /// The headroom of the instance's memory (see `Headroom`).
+    pub fn headroom(&self) -> Headroom {
+        self.headroom.clone()
+    }
+}
+
+impl ResourceLimiter for Limiter {
+    fn memory_growing(&mut self, current: usize, desired: usize, maximum: Option<usize>) -> bool {
+        if !self.limits.memory_growing(current, desired, maximum) {
+            return false;
+        }
+        let bytes = desired.saturating_sub(current) as u64;
+        if let Some(monitor) = &self.monitor {
+            if !monitor.growing(bytes, current == 0) {
+                return false;
+            }
+        }
+        self.bytes += bytes;
+        if current != 0 {
+            self.grown += bytes;
+        }
+        self.headroom.grown(current, desired);
+        true
+    }
+
+    fn table_growing(&mut self, current: u32, desired: u32, maximum: Option<u32>) -> bool {
+        self.limits.table_growing(current, desired, maximum)
+    }
+
+    fn instances(&self) -> usize {
+        self.limits.instances()
+    }
+
+    fn tables(&self) -> usize {
+        self.limits.tables()
+    }
+
+    fn memories(&self) -> usize {
+        self.limits.memories()
+    }
+}
+
+impl Drop for Limiter {
+    fn drop(&mut self) {
+        if let Some(monitor) = &self.monitor {
+            monitor.released(self.bytes, self.grown);
+        }
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::Duration;
+
+    use wasmtime::ResourceLimiter;
+
+    use super::{GrowthAction, GrowthSettings, Limiter, MemoryMonitor};
+
+    const PAGE: usize = 65536;
+
+    #[test]
+    fn growth_test() {
+        let monitor = MemoryMonitor::default();
+        let settings = GrowthSettings::new(2 * PAGE as u64, None, GrowthAction::Trap);
+        monitor.configure(Some(settings));
+        let mut limiter = Limiter::default();
+        limiter.monitor(monitor.clone());
+
+        // the memory an instance starts w/ isn't growth
+        assert!(limiter.memory_growing(0, 16 * PAGE, None));
+        assert!(limiter.memory_growing(16 * PAGE, 17 * PAGE, None));
+        assert!(limiter.memory_growing(17 * PAGE, 18 * PAGE, None));
+        assert!(!limiter.memory_growing(18 * PAGE, 19 * PAGE, None));
+        let report = monitor.report();
+        assert_eq!(report.bytes, 18 * PAGE as u64);
+        assert_eq!(report.growth_bytes, 2 * PAGE as u64);
+        assert_eq!(report.refused, 1);
+
+        // w/ another instance, the memories of both count
+        let mut other = Limiter::default();
+        other.monitor(monitor.clone());
+        assert!(other.memory_growing(0, PAGE, None));
+        assert_eq!(monitor.report().peak, 19 * PAGE as u64);
+        drop(limiter);
+        assert_eq!(monitor.report().bytes, PAGE as u64);
+        assert_eq!(monitor.report().peak, 19 * PAGE as u64);
+    }
+
+    #[test]
+    fn net_growth_test() {
+        let monitor = MemoryMonitor::default();
+        let settings = GrowthSettings::new(3 * PAGE as u64, None, GrowthAction::Trap);
+        monitor.configure(Some(settings));
+
+        // instances that grow, and are dropped (e.g., once they've handled a request) don't
+        // add up to a leak
+        for _ in 0..4 {
+            let mut limiter = Limiter::default();
+            limiter.monitor(monitor.clone());
+            assert!(limiter.memory_growing(0, 16 * PAGE, None));
+            assert!(limiter.memory_growing(16 * PAGE, 18 * PAGE, None));
+            assert_eq!(monitor.report().growth_bytes, 2 * PAGE as u64);
+        }
+        let report = monitor.report();
+        assert_eq!(report.bytes, 0);
+        assert_eq!(report.growth_bytes, 0);
+        assert_eq!(report.refused, 0);
+
+        // while what's kept growing does
+        let mut limiter = Limiter::default();
+        limiter.monitor(monitor.clone());
+        assert!(limiter.memory_growing(0, PAGE, None));
+        assert!(limiter.memory_growing(PAGE, 4 * PAGE, None));
+        assert!(!limiter.memory_growing(4 * PAGE, 5 * PAGE, None));
+        assert_eq!(monitor.report().refused, 1);
+    }
+
+    #[test]
+    fn growth_window_test() {
+        let monitor = MemoryMonitor::default();
+        let settings = GrowthSettings {
+            budget_bytes: PAGE as u64,
+            window: Duration::from_millis(50),
+            action: GrowthAction::Warn,
+        };
+        monitor.configure(Some(settings));
+        let mut limiter = Limiter::default();
+        limiter.monitor(monitor.clone());
+        assert!(limiter.memory_growing(0, PAGE, None));
+        // warning doesn't stop the growth
+        assert!(limiter.memory_growing(PAGE, 2 * PAGE, None));
+        assert!(limiter.memory_growing(2 * PAGE, 3 * PAGE, None));
+        assert_eq!(monitor.report().over_budget, 1);
+
+        // growth older than the window doesn't count towards the budget
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(monitor.report().growth_bytes, 0);
+        assert!(limiter.memory_growing(3 * PAGE, 4 * PAGE, None));
+        assert_eq!(monitor.report().over_budget, 1);
+        assert_eq!(monitor.report().refused, 0);
+    }
+
+    #[test]
+    fn static_limits_test() {
+        let mut limiter = Limiter::default();
+        limiter.limit_memory(PAGE);
+        assert!(limiter.memory_growing(0, PAGE, None));
+        assert!(!limiter.memory_growing(PAGE, 2 * PAGE, None));
+        // the memory it started w/ is all it has, so there's no room for results
+        assert_eq!(limiter.headroom().available(), 0);
+    }
+}
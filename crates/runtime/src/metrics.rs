@@ -0,0 +1,578 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::SystemTime,
+};
+
+use crate::{error_kind::ErrorKind, split::TrafficSplit};
+
+/// The label value targets past a capability's `max_targets` are counted under.
+pub const OTHER: &str = "other";
+
+/// How many distinct targets a capability's calls are labeled w/, by default.
+pub const DEFAULT_MAX_TARGETS: usize = 100;
+
+/// The upper bounds (in bytes) of the buckets key lengths are counted in, by default.
+pub const DEFAULT_KEY_BUCKETS: &[u64] = &[8, 16, 32, 64, 128, 256, 512, 1024];
+
+/// The upper bounds (in bytes) of the buckets value sizes are counted in, by default (i.e.,
+/// from 64B to 4MiB, by a factor of 4).
+pub const DEFAULT_VALUE_BUCKETS: &[u64] =
+    &[64, 256, 1024, 4096, 16384, 65536, 262144, 1048576, 4194304];
+
+/// `LabelSettings` decide which labels the calls of a capability are counted w/, so that
+/// high-cardinality targets (e.g., per-user keys) can't blow up the metrics backend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LabelSettings {
+    /// whether calls are labeled w/ their target (e.g., a key, or a queue name) at all
+    pub target_label: bool,
+    /// how many distinct targets get a label of their own — the first ones seen do, and the
+    /// ones after them are counted under `OTHER`
+    pub max_targets: usize,
+}
+
+impl Default for LabelSettings {
+    fn default() -> Self {
+        Self {
+            target_label: true,
+            max_targets: DEFAULT_MAX_TARGETS,
+        }
+    }
+}
+
+/// `SizeBuckets` are the upper bounds (in bytes) of the buckets the sizes of the keys, and
+/// values a capability's calls carry are counted in (see `CallMetrics::record_sizes`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SizeBuckets {
+    keys: Vec<u64>,
+    values: Vec<u64>,
+}
+
+impl SizeBuckets {
+    /// The bounds can be in any order — sizes past the last one are counted in a bucket of
+    /// their own (i.e., `+Inf`).
+    pub fn new(mut keys: Vec<u64>, mut values: Vec<u64>) -> Self {
+        for bounds in [&mut keys, &mut values] {
+            bounds.sort_unstable();
+            bounds.dedup();
+        }
+        Self { keys, values }
+    }
+}
+
+impl Default for SizeBuckets {
+    fn default() -> Self {
+        Self::new(DEFAULT_KEY_BUCKETS.to_vec(), DEFAULT_VALUE_BUCKETS.to_vec())
+    }
+}
+
+/// A histogram of sizes, w/ a count per bucket (and one past the last bucket's bound).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Histogram {
+    bounds: Vec<u64>,
+    counts: Vec<u64>,
+    pub sum: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &[u64]) -> Self {
+        Self {
+            bounds: bounds.to_vec(),
+            counts: vec![0; bounds.len() + 1],
+            sum: 0,
+        }
+    }
+
+    fn observe(&mut self, size: u64) {
+        let bucket = self.bounds.partition_point(|bound| *bound < size);
+        self.counts[bucket] += 1;
+        self.sum += size;
+    }
+
+    /// How many sizes were observed.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The cumulative count of each bucket, by its upper bound (`None` for the one past the
+    /// last bound), as the Prometheus text format lists them.
+    pub fn buckets(&self) -> Vec<(Option<u64>, u64)> {
+        let bounds = self.bounds.iter().copied().map(Some).chain([None]);
+        let mut cumulative = 0;
+        bounds
+            .zip(&self.counts)
+            .map(|(bound, count)| {
+                cumulative += count;
+                (bound, cumulative)
+            })
+            .collect()
+    }
+}
+
+/// `CallMetrics` count the calls of a capability, by operation, target, and the kind of error
+/// they failed w/, if they did (see `call::instrument`), and the sizes of the keys, and values
+/// they carry.
+#[derive(Debug)]
+pub struct CallMetrics {
+    capability: String,
+    settings: LabelSettings,
+    buckets: SizeBuckets,
+    counts: Mutex<Counts>,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    /// the targets that got a label of their own
+    targets: HashSet<String>,
+    /// the calls, by operation, target label (if any), and the kind of error they failed w/ (if
+    /// they did)
+    calls: BTreeMap<(String, Option<String>, Option<ErrorKind>), u64>,
+    /// how many calls were counted under `OTHER`, as their target didn't fit
+    bucketed: u64,
+    /// how many reads were served a last known good value, as the backend failed (see
+    /// `LastKnownGood`)
+    stale_reads: u64,
+    /// the key lengths, by operation (a key's length is the same whatever its target label)
+    keys: BTreeMap<String, Histogram>,
+    /// the value sizes, by operation, and target label (if any)
+    values: BTreeMap<(String, Option<String>), Histogram>,
+}
+
+impl Counts {
+    /// The label of `target`, as per `settings` (see `LabelSettings`), and whether it didn't
+    /// fit (i.e., it's labeled `OTHER`).
+    fn target_label(&mut self, settings: LabelSettings, target: &str) -> (Option<String>, bool) {
+        if !settings.target_label {
+            (None, false)
+        } else if self.targets.contains(target) {
+            (Some(target.to_string()), false)
+        } else if self.targets.len() < settings.max_targets {
+            self.targets.insert(target.to_string());
+            (Some(target.to_string()), false)
+        } else {
+            (Some(OTHER.to_string()), true)
+        }
+    }
+}
+
+impl CallMetrics {
+    fn new(capability: &str, settings: LabelSettings, buckets: SizeBuckets) -> Self {
+        Self {
+            capability: capability.to_string(),
+            settings,
+            buckets,
+            counts: Mutex::new(Counts::default()),
+        }
+    }
+
+    /// Counts a call of `operation` on `target`, which failed w/ an `error` of that kind, if
+    /// there's one.
+    pub fn record(&self, operation: &str, target: &str, error: Option<ErrorKind>) {
+        let mut counts = self.counts.lock().unwrap();
+        let (target, bucketed) = counts.target_label(self.settings, target);
+        if bucketed {
+            counts.bucketed += 1;
+        }
+        *counts
+            .calls
+            .entry((operation.to_string(), target, error))
+            .or_default() += 1;
+    }
+
+    /// Counts a read that was served a last known good value, rather than failing.
+    pub fn record_stale_read(&self) {
+        self.counts.lock().unwrap().stale_reads += 1;
+    }
+
+    /// Counts the length of the `key` a call of `operation` on `target` carried, and the size
+    /// of its `value` (if it carried one, e.g., a get, but not a delete).
+    ///
+    /// Targets are labeled like the calls' (i.e., past the `max_targets`, as `OTHER`), but
+    /// they're only counted as bucketed by `record`.
+    pub fn record_sizes(&self, operation: &str, target: &str, key: usize, value: Option<usize>) {
+        let mut counts = self.counts.lock().unwrap();
+        let keys = &self.buckets.keys;
+        counts
+            .keys
+            .entry(operation.to_string())
+            .or_insert_with(|| Histogram::new(keys))
+            .observe(key as u64);
+        if let Some(value) = value {
+            let (target, _) = counts.target_label(self.settings, target);
+            let values = &self.buckets.values;
+            counts
+                .values
+                .entry((operation.to_string(), target))
+                .or_insert_with(|| Histogram::new(values))
+                .observe(value as u64);
+        }
+    }
+}
+
+/// What `Metrics` report about the calls of a capability w/ the same labels.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallReport {
+    pub capability: String,
+    pub operation: String,
+    /// the target label, unless the capability's calls aren't labeled w/ it
+    pub target: Option<String>,
+    /// the kind of error the calls failed w/, if they did
+    pub error: Option<ErrorKind>,
+    pub calls: u64,
+}
+
+/// Which sizes a `SizeReport` is of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sizes {
+    Keys,
+    Values,
+}
+
+/// What `Metrics` report about the sizes of the keys, or values of a capability's calls w/
+/// the same labels.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SizeReport {
+    pub capability: String,
+    pub operation: String,
+    /// the target label, unless these are the key lengths, or the capability's calls aren't
+    /// labeled w/ it
+    pub target: Option<String>,
+    pub sizes: Sizes,
+    pub histogram: Histogram,
+}
+
+/// `Metrics` hold the call metrics of an app's capabilities, which are shared by all of its
+/// guest instances, and kept across restarts (which they count too).
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    metrics: Arc<Mutex<BTreeMap<String, Arc<CallMetrics>>>>,
+    /// how many times the app's guest was restarted after it crashed
+    restarts: Arc<AtomicU64>,
+    /// the traffic splits of the app's capabilities, by capability
+    splits: Arc<Mutex<BTreeMap<String, TrafficSplit>>>,
+    /// when counting started (i.e., what the counts are cumulative since)
+    since: SystemTime,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            metrics: Default::default(),
+            restarts: Default::default(),
+            splits: Default::default(),
+            since: SystemTime::now(),
+        }
+    }
+}
+
+impl Metrics {
+    /// When counting started, which the counts are cumulative since (e.g., for backends that
+    /// are pushed them, see `export::Otlp`).
+    pub fn since(&self) -> SystemTime {
+        self.since
+    }
+
+    /// Gets the call metrics of `capability`, creating them w/ `settings`, and `buckets` if
+    /// there are none yet (or none w/ these, in which case counting starts over).
+    pub fn get(
+        &self,
+        capability: &str,
+        settings: LabelSettings,
+        buckets: SizeBuckets,
+    ) -> Arc<CallMetrics> {
+        let mut metrics = self.metrics.lock().unwrap();
+        match metrics.get(capability) {
+            Some(call_metrics)
+                if call_metrics.settings == settings && call_metrics.buckets == buckets =>
+            {
+                call_metrics.clone()
+            }
+            _ => {
+                let call_metrics = Arc::new(CallMetrics::new(capability, settings, buckets));
+                metrics.insert(capability.to_string(), call_metrics.clone());
+                call_metrics
+            }
+        }
+    }
+
+    /// Reports on the calls, sorted by capability, operation, target, and the kind of error they
+    /// failed w/ (the successful ones first).
+    pub fn reports(&self) -> Vec<CallReport> {
+        let metrics = self.metrics.lock().unwrap();
+        let mut reports = Vec::new();
+        for call_metrics in metrics.values() {
+            let counts = call_metrics.counts.lock().unwrap();
+            for ((operation, target, error), calls) in &counts.calls {
+                reports.push(CallReport {
+                    capability: call_metrics.capability.clone(),
+                    operation: operation.clone(),
+                    target: target.clone(),
+                    error: *error,
+                    calls: *calls,
+                });
+            }
+        }
+        reports
+    }
+
+    /// Reports on the sizes of keys, and values, sorted by capability, the sizes they're of,
+    /// operation, and target — capabilities that carried none (e.g., only kv's do) have none.
+    pub fn size_reports(&self) -> Vec<SizeReport> {
+        let metrics = self.metrics.lock().unwrap();
+        let mut reports = Vec::new();
+        for call_metrics in metrics.values() {
+            let counts = call_metrics.counts.lock().unwrap();
+            let report =
+                |sizes, operation: &String, target: &Option<String>, histogram| SizeReport {
+                    capability: call_metrics.capability.clone(),
+                    operation: operation.clone(),
+                    target: target.clone(),
+                    sizes,
+                    histogram,
+                };
+            for (operation, histogram) in &counts.keys {
+                reports.push(report(Sizes::Keys, operation, &None, histogram.clone()));
+            }
+            for ((operation, target), histogram) in &counts.values {
+                reports.push(report(Sizes::Values, operation, target, histogram.clone()));
+            }
+        }
+        reports
+    }
+
+    /// How many calls of each capability were counted under `OTHER`, as their targets didn't
+    /// fit, sorted by capability.
+    pub fn bucketed(&self) -> Vec<(String, u64)> {
+        self.metrics
+            .lock()
+            .unwrap()
+            .values()
+            .map(|call_metrics| {
+                let bucketed = call_metrics.counts.lock().unwrap().bucketed;
+                (call_metrics.capability.clone(), bucketed)
+            })
+            .collect()
+    }
+
+    /// Tracks the traffic `split` of `capability`, so its effective split is exported — the
+    /// split it had already is kept (w/ what it counted so far) if it's the `same_split`, so
+    /// restarts don't reset the counts.
+    pub fn split(&self, capability: &str, split: TrafficSplit) -> TrafficSplit {
+        let mut splits = self.splits.lock().unwrap();
+        match splits.get(capability) {
+            Some(tracked) if tracked.same_split(&split) => tracked.clone(),
+            _ => {
+                splits.insert(capability.to_string(), split.clone());
+                split
+            }
+        }
+    }
+
+    /// The traffic splits of the app's capabilities, sorted by capability.
+    pub fn splits(&self) -> Vec<(String, TrafficSplit)> {
+        self.splits
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(capability, split)| (capability.clone(), split.clone()))
+            .collect()
+    }
+
+    /// Counts a restart of the app's guest after it crashed.
+    pub fn record_restart(&self) {
+        self.restarts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// How many times the app's guest was restarted after it crashed.
+    pub fn restarts(&self) -> u64 {
+        self.restarts.load(Ordering::Relaxed)
+    }
+
+    /// How many reads of each capability were served a last known good value, sorted by
+    /// capability.
+    pub fn stale_reads(&self) -> Vec<(String, u64)> {
+        self.metrics
+            .lock()
+            .unwrap()
+            .values()
+            .map(|call_metrics| {
+                let stale_reads = call_metrics.counts.lock().unwrap().stale_reads;
+                (call_metrics.capability.clone(), stale_reads)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use super::{LabelSettings, Metrics, SizeBuckets, Sizes, OTHER};
+    use crate::{
+        error_kind::ErrorKind,
+        split::{Operation, TrafficSplit},
+    };
+
+    #[test]
+    fn bucketing_test() {
+        let metrics = Metrics::default();
+        let kv = metrics.get(
+            "kv.filesystem",
+            LabelSettings {
+                target_label: true,
+                max_targets: 2,
+            },
+            SizeBuckets::default(),
+        );
+        for key in ["user:1", "user:2", "user:3", "user:1", "user:4"] {
+            kv.record("get", key, None);
+        }
+        kv.record("get", "user:2", Some(ErrorKind::Timeout));
+        kv.record("get", "user:2", Some(ErrorKind::Backend));
+        kv.record("get", "user:2", Some(ErrorKind::Timeout));
+
+        let counted = metrics
+            .reports()
+            .into_iter()
+            .map(|report| (report.target.unwrap(), report.error, report.calls))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            counted,
+            vec![
+                (OTHER.to_string(), None, 2),
+                ("user:1".to_string(), None, 2),
+                ("user:2".to_string(), None, 1),
+                ("user:2".to_string(), Some(ErrorKind::Timeout), 2),
+                ("user:2".to_string(), Some(ErrorKind::Backend), 1),
+            ]
+        );
+        assert_eq!(metrics.bucketed(), vec![("kv.filesystem".to_string(), 2)]);
+    }
+
+    #[test]
+    fn no_target_label_test() {
+        let metrics = Metrics::default();
+        let settings = LabelSettings {
+            target_label: false,
+            ..Default::default()
+        };
+        let mq = metrics.get("mq.filesystem", settings, SizeBuckets::default());
+        mq.record("send", "orders", None);
+        mq.record("send", "invoices", None);
+
+        let reports = metrics.reports();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].target, None);
+        assert_eq!(reports[0].calls, 2);
+        // the same settings get the same metrics, and other settings start over
+        assert_eq!(
+            metrics
+                .get("mq.filesystem", settings, SizeBuckets::default())
+                .settings,
+            settings
+        );
+        metrics.get(
+            "mq.filesystem",
+            LabelSettings::default(),
+            SizeBuckets::default(),
+        );
+        assert!(metrics.reports().is_empty());
+    }
+
+    #[test]
+    fn size_histograms_test() {
+        let metrics = Metrics::default();
+        let kv = metrics.get(
+            "kv.filesystem",
+            LabelSettings {
+                target_label: true,
+                max_targets: 1,
+            },
+            SizeBuckets::new(vec![16, 4], vec![100]),
+        );
+        // sizes are recorded while the call is made, so before it's counted
+        kv.record_sizes("get", "user:1", 6, Some(100));
+        kv.record("get", "user:1", None);
+        kv.record_sizes("get", "user:1", 6, Some(10_000));
+        // past `max_targets`, values are counted as `OTHER`'s
+        kv.record_sizes("get", "user:22", 7, Some(3));
+        kv.record("get", "user:22", None);
+        kv.record_sizes("delete", "user:1", 20, None);
+
+        let reports = metrics.size_reports();
+        let sizes = reports
+            .iter()
+            .map(|report| {
+                (
+                    report.sizes,
+                    report.operation.as_str(),
+                    report.target.as_deref(),
+                    report.histogram.buckets(),
+                    report.histogram.sum,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sizes,
+            vec![
+                (
+                    Sizes::Keys,
+                    "delete",
+                    None,
+                    vec![(Some(4), 0), (Some(16), 0), (None, 1)],
+                    20
+                ),
+                (
+                    Sizes::Keys,
+                    "get",
+                    None,
+                    vec![(Some(4), 0), (Some(16), 3), (None, 3)],
+                    19
+                ),
+                (
+                    Sizes::Values,
+                    "get",
+                    Some(OTHER),
+                    vec![(Some(100), 1), (None, 1)],
+                    3
+                ),
+                (
+                    Sizes::Values,
+                    "get",
+                    Some("user:1"),
+                    vec![(Some(100), 1), (None, 2)],
+                    10_100
+                ),
+            ]
+        );
+        assert_eq!(reports[1].histogram.count(), 3);
+        // the sizes of calls whose target was bucketed aren't counted as bucketed twice
+        assert_eq!(metrics.bucketed(), vec![("kv.filesystem".to_string(), 1)]);
+    }
+
+    #[test]
+    fn split_test() -> anyhow::Result<()> {
+        let metrics = Metrics::default();
+        let split = metrics.split(
+            "kv.filesystem",
+            TrafficSplit::new("kv.filesystem", "kv.azblob", 0, 100)?,
+        );
+        split.routes_to_canary(Operation::Write, Some(b"key"));
+
+        // the same split (e.g., after a restart) keeps counting where it was
+        let again = metrics.split(
+            "kv.filesystem",
+            TrafficSplit::new("kv.filesystem", "kv.azblob", 0, 100)?,
+        );
+        assert_eq!(again.stats().canary_writes, 1);
+        // while a different one starts over
+        let changed = metrics.split(
+            "kv.filesystem",
+            TrafficSplit::new("kv.filesystem", "kv.azblob", 50, 100)?,
+        );
+        assert_eq!(changed.stats().canary_writes, 0);
+        assert_eq!(metrics.splits().len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,325 @@
+use std::{
+    any::Any,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    call::{Call, Outcome, TimedOut},
+    error_kind::NotFound,
+    quota::RateLimited,
+    resource::ResourceMap,
+};
+
+/// The name `Mocks` are shared under in the `StateTable` (see `Mocks::install`).
+pub const MOCKS: &str = "slight.mocks";
+
+/// What a `Mock` responds to a call w/.
+#[derive(Clone)]
+enum Response {
+    /// the operation returns a value (made anew for every call)
+    Value(Arc<dyn Fn() -> Box<dyn Any> + Send + Sync>),
+    /// the operation fails
+    Error(Arc<dyn Fn() -> anyhow::Error + Send + Sync>),
+    /// the capability's backend is called, as if there was no mock
+    PassThrough,
+}
+
+/// A `Mock` scripts the responses of an operation of a capability (e.g., `kv`'s `get`), so a
+/// guest can be tested against specific backend behaviors (e.g., a timeout) w/o a real backend.
+///
+/// Calls get the scripted responses in order, and, once there are none left, the last one
+/// again — a mock w/o responses passes every call through to the backend (e.g., to add
+/// latency to it w/ `after`).
+#[derive(Clone)]
+pub struct Mock {
+    capability: String,
+    operation: String,
+    /// the target (e.g., a key, or a queue name) calls must be on, if any
+    target: Option<String>,
+    latency: Option<Duration>,
+    responses: Vec<Response>,
+    /// how many calls matched the mock
+    calls: usize,
+}
+
+impl fmt::Debug for Mock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Mock({}.{} on {})",
+            self.capability,
+            self.operation,
+            self.target.as_deref().unwrap_or("*")
+        )
+    }
+}
+
+impl Mock {
+    /// Mocks the `operation` of `capability` (i.e., its scheme, like `kv`, or `mq`).
+    pub fn new(capability: &str, operation: &str) -> Self {
+        Self {
+            capability: capability.to_string(),
+            operation: operation.to_string(),
+            target: None,
+            latency: None,
+            responses: Vec::new(),
+            calls: 0,
+        }
+    }
+
+    /// Only mocks calls on `target` (e.g., a key).
+    pub fn on(mut self, target: &str) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    /// Responds w/ `value`, which must be of the type the operation returns (e.g., a
+    /// `Vec<u8>` for `kv`'s `get`), or the call fails.
+    pub fn returns<T: Clone + Send + Sync + 'static>(mut self, value: T) -> Self {
+        self.responses.push(Response::Value(Arc::new(move || {
+            Box::new(value.clone()) as Box<dyn Any>
+        })));
+        self
+    }
+
+    /// Fails the call w/ `message` (i.e., an `error-with-description`).
+    pub fn fails(self, message: &str) -> Self {
+        let message = message.to_string();
+        self.fails_with(move || anyhow::anyhow!("{}", message))
+    }
+
+    /// Fails the call w/ the error made by `error`, which is turned into the capability's
+    /// error like any other (e.g., `quota::RateLimited` into a `rate-limited` error).
+    pub fn fails_with(mut self, error: impl Fn() -> anyhow::Error + Send + Sync + 'static) -> Self {
+        self.responses.push(Response::Error(Arc::new(error)));
+        self
+    }
+
+    /// Fails the call w/ the error a backend would for `fault`: `error` (i.e., an
+    /// `error-with-description`), `timeout`, `rate_limited`, or `not_found` — as in the
+    /// slightfile's mocks (see `slight run --mocks`).
+    pub fn fails_like(self, fault: &str) -> Result<Self> {
+        let (capability, operation) = (self.capability.clone(), self.operation.clone());
+        let error: Box<dyn Fn() -> anyhow::Error + Send + Sync> = match fault {
+            "error" => Box::new(move || {
+                anyhow::anyhow!("mocked: a backend error of {}.{}", capability, operation)
+            }),
+            "timeout" => Box::new(move || {
+                TimedOut(format!("mocked: a timeout of {}.{}", capability, operation)).into()
+            }),
+            "rate_limited" => Box::new(move || {
+                RateLimited {
+                    capability: capability.clone(),
+                    limit: "mocked".to_string(),
+                    retry_after: Duration::from_secs(1),
+                }
+                .into()
+            }),
+            "not_found" => Box::new(move || {
+                NotFound(format!("mocked: {}.{} found nothing", capability, operation)).into()
+            }),
+            _ => bail!(
+                "invalid mocked fault: '{}' (expected 'error', 'timeout', 'rate_limited', or 'not_found')",
+                fault
+            ),
+        };
+        Ok(self.fails_with(error))
+    }
+
+    /// Calls the capability's backend.
+    pub fn passes_through(mut self) -> Self {
+        self.responses.push(Response::PassThrough);
+        self
+    }
+
+    /// Responds only after `latency`.
+    pub fn after(mut self, latency: Duration) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    fn matches(&self, call: &Call<'_>) -> bool {
+        self.capability == call.capability
+            && self.operation == call.operation
+            && self.target.as_deref().map_or(true, |t| t == call.target)
+    }
+
+    /// The response to the next call.
+    fn next(&mut self) -> Response {
+        let response = self
+            .responses
+            .get(self.calls.min(self.responses.len().saturating_sub(1)))
+            .cloned()
+            .unwrap_or(Response::PassThrough);
+        self.calls += 1;
+        response
+    }
+}
+
+/// `Mocks` hold the mocks of an app's capabilities, and are shared w/ them through its
+/// `StateTable` (see `install`) — whether they're added by a test, or by the slightfile (i.e.,
+/// w/ `slight run --mocks`).
+///
+/// Calls are matched against mocks in the order they were added, so mocks on a specific
+/// target must be added before the ones on any target of the same operation.
+#[derive(Clone, Debug, Default)]
+pub struct Mocks(Arc<Mutex<Vec<Mock>>>);
+
+impl Mocks {
+    /// Installs the mocks in the `StateTable` of an app, so its capabilities respond w/ them.
+    ///
+    /// They must be installed before the capabilities are linked (i.e., before their
+    /// `BasicState` is created), but mocks can be added to them at any time.
+    pub fn install(&self, resource_map: &ResourceMap) -> Result<()> {
+        resource_map
+            .lock()
+            .unwrap()
+            .shared(MOCKS, || self.clone())?;
+        Ok(())
+    }
+
+    pub fn add(&self, mock: Mock) -> &Self {
+        self.0.lock().unwrap().push(mock);
+        self
+    }
+
+    /// How many calls to the `operation` of `capability` matched mocks.
+    pub fn calls(&self, capability: &str, operation: &str) -> usize {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|mock| mock.capability == capability && mock.operation == operation)
+            .map(|mock| mock.calls)
+            .sum()
+    }
+
+    /// The mocked outcome of a call, or `None` if it goes to the capability's backend.
+    pub(crate) fn respond<T: Outcome>(&self, call: &Call<'_>) -> Option<T> {
+        let (response, latency) = {
+            let mut mocks = self.0.lock().unwrap();
+            let mock = mocks.iter_mut().find(|mock| mock.matches(call))?;
+            (mock.next(), mock.latency)
+        };
+        if let Some(latency) = latency {
+            std::thread::sleep(latency);
+        }
+        match response {
+            Response::Value(value) => Some(T::from_value(value()).unwrap_or_else(|| {
+                T::from_error(anyhow::anyhow!(
+                    "the mocked response of {}.{} isn't of the type it returns",
+                    call.capability,
+                    call.operation
+                ))
+            })),
+            Response::Error(error) => Some(T::from_error(error())),
+            Response::PassThrough => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::{Duration, Instant};
+
+    use anyhow::{bail, Result};
+
+    use super::{Mock, Mocks};
+    use crate::{
+        error_kind::ErrorKind,
+        quota::RateLimited,
+        resource::{BasicState, ResourceMap},
+    };
+
+    fn get(state: &BasicState, key: &str) -> Result<Vec<u8>> {
+        state.instrument("kv", "get", key, || Ok(b"from the backend".to_vec()))
+    }
+
+    #[test]
+    fn scripted_responses_test() -> Result<()> {
+        let resource_map = ResourceMap::default();
+        let mocks = Mocks::default();
+        mocks.install(&resource_map)?;
+        mocks
+            .add(
+                Mock::new("kv", "get")
+                    .on("flaky")
+                    .fails("timeout")
+                    .passes_through(),
+            )
+            .add(Mock::new("kv", "get").returns(b"mocked".to_vec()));
+        let state = BasicState::new(resource_map.clone(), &[], "slightfile.toml");
+
+        assert_eq!(get(&state, "flaky").unwrap_err().to_string(), "timeout");
+        assert_eq!(get(&state, "flaky")?, b"from the backend");
+        assert_eq!(get(&state, "flaky")?, b"from the backend");
+        assert_eq!(get(&state, "any")?, b"mocked");
+        assert_eq!(mocks.calls("kv", "get"), 4);
+
+        // other operations go to the backend
+        let res: Result<()> = state.instrument("kv", "set", "any", || bail!("the backend"));
+        assert_eq!(res.unwrap_err().to_string(), "the backend");
+
+        // the same mocks are shared by every state of the app
+        let other = BasicState::new(resource_map, &[], "slightfile.toml");
+        assert_eq!(get(&other, "flaky")?, b"from the backend");
+        assert_eq!(mocks.calls("kv", "get"), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn failures_test() -> Result<()> {
+        let resource_map = ResourceMap::default();
+        let mocks = Mocks::default();
+        mocks.install(&resource_map)?;
+        mocks
+            .add(Mock::new("mq", "receive").fails_with(|| {
+                RateLimited {
+                    capability: "mq".to_string(),
+                    limit: "1 ops/sec".to_string(),
+                    retry_after: Duration::from_secs(1),
+                }
+                .into()
+            }))
+            .add(
+                Mock::new("kv", "get")
+                    .on("missing")
+                    .fails_like("not_found")?,
+            )
+            .add(Mock::new("kv", "get").returns("not a payload"));
+        let state = BasicState::new(resource_map, &[], "slightfile.toml");
+
+        let res: Result<Vec<u8>> = state.instrument("mq", "receive", "my-queue", || Ok(vec![]));
+        assert!(RateLimited::is(&res.unwrap_err()));
+        let e = get(&state, "missing").unwrap_err();
+        assert_eq!(ErrorKind::of(&e), ErrorKind::NotFound);
+        assert!(get(&state, "my-key").is_err());
+        assert!(Mock::new("kv", "get").fails_like("explode").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn latency_test() -> Result<()> {
+        let resource_map = ResourceMap::default();
+        let mocks = Mocks::default();
+        mocks.install(&resource_map)?;
+        mocks.add(Mock::new("kv", "get").after(Duration::from_millis(50)));
+        let state = BasicState::new(resource_map, &[], "slightfile.toml");
+
+        let start = Instant::now();
+        assert_eq!(get(&state, "my-key")?, b"from the backend");
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        Ok(())
+    }
+
+    #[test]
+    fn not_installed_test() -> Result<()> {
+        let state = BasicState::new(ResourceMap::default(), &[], "slightfile.toml");
+        assert_eq!(get(&state, "my-key")?, b"from the backend");
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+use std::{fmt, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use hmac::{Hmac, Mac};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::Sha256;
+
+use crate::signing::{hex, unhex};
+
+/// The version of the format of page tokens, which tokens of other versions fail w/.
+const VERSION: &str = "v1";
+
+/// How long a token can be, so resuming from one doesn't decrypt whatever the guest hands in.
+const MAX_TOKEN_LEN: usize = 8 * 1024;
+
+const NONCE_LEN: usize = 24;
+
+/// `PageTokens` seals where a paginated listing is at (i.e., the backend's cursor) into an
+/// opaque token that guests can keep (e.g., in a kv store, or in the response to a client),
+/// and resume the listing from later, in another invocation, or after a restart.
+///
+/// A token is `v1.<scope>.<sealed>`, where:
+///     - the `scope` is a keyed fingerprint of what it's for (e.g., a store, and the
+///     implementors that back it), so a token of another store, or of a configuration that
+///     changed since fails as stale, rather than resuming from a cursor of another backend, and
+///     - the `sealed` position is encrypted, and authenticated (w/ XChaCha20-Poly1305), so it
+///     doesn't leak the backend's cursor (which may have keys, or the backend's own
+///     identifiers), and a token that was tampered w/ fails as invalid.
+///
+/// Tokens are sealed w/ a key, which is the same across the guest instances, and restarts of
+/// an app — one derived from a secret (see `PageTokens::new`) is the same across hosts too,
+/// while the default one is random, and only lasts as long as the host's process.
+#[derive(Clone)]
+pub struct PageTokens {
+    seal_key: Arc<[u8; 32]>,
+    scope_key: Arc<[u8; 32]>,
+}
+
+impl fmt::Debug for PageTokens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PageTokens")
+    }
+}
+
+impl Default for PageTokens {
+    fn default() -> Self {
+        Self::new(&rand::random::<[u8; 32]>())
+    }
+}
+
+impl PageTokens {
+    /// Makes page tokens whose keys are derived from a `secret` (e.g., one read from the
+    /// secret stores), so every host w/ the secret resumes the tokens of the others.
+    pub fn new(secret: &[u8]) -> Self {
+        let derive = |purpose: &[u8]| -> [u8; 32] {
+            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret)
+                .expect("HMAC can take keys of any size");
+            mac.update(purpose);
+            mac.finalize().into_bytes().into()
+        };
+        Self {
+            seal_key: Arc::new(derive(b"slight.page-tokens.seal")),
+            scope_key: Arc::new(derive(b"slight.page-tokens.scope")),
+        }
+    }
+
+    /// Seals a `position` of a listing of `scope` into a token.
+    pub fn issue(&self, scope: &str, position: &impl Serialize) -> Result<String> {
+        let nonce = rand::random::<[u8; NONCE_LEN]>();
+        let sealed = self
+            .cipher()
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &serde_json::to_vec(position)?,
+                    aad: scope.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("failed to seal the page token"))?;
+        Ok(format!(
+            "{}.{}.{}{}",
+            VERSION,
+            self.fingerprint(scope),
+            hex(&nonce),
+            hex(&sealed)
+        ))
+    }
+
+    /// Opens a token issued for `scope` into the position it was sealed w/, failing if it's
+    /// of another version, or scope (i.e., it's stale), or if it isn't one this host issued.
+    pub fn resume<T: DeserializeOwned>(&self, scope: &str, token: &str) -> Result<T> {
+        if token.len() > MAX_TOKEN_LEN {
+            bail!(
+                "invalid page token: it's longer than {} characters",
+                MAX_TOKEN_LEN
+            );
+        }
+        let mut parts = token.splitn(3, '.');
+        let (version, fingerprint, sealed) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(version), Some(fingerprint), Some(sealed)) => (version, fingerprint, sealed),
+            _ => bail!("invalid page token: it isn't one"),
+        };
+        if version != VERSION {
+            bail!(
+                "invalid page token: its version is '{}' (expected '{}')",
+                version,
+                VERSION
+            );
+        }
+        if fingerprint != self.fingerprint(scope) {
+            bail!("stale page token: it's of another listing, or of a configuration that changed since");
+        }
+        let sealed = unhex(sealed)
+            .filter(|sealed| sealed.len() > NONCE_LEN)
+            .context("invalid page token: it's malformed")?;
+        let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+        let position = self
+            .cipher()
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: sealed,
+                    aad: scope.as_bytes(),
+                },
+            )
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "invalid page token: it was tampered w/, or it was issued w/ another key"
+                )
+            })?;
+        serde_json::from_slice(&position).context("invalid page token: its position is malformed")
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(Key::from_slice(&self.seal_key[..]))
+    }
+
+    /// A short keyed fingerprint of the scope, which tells stale tokens apart w/o telling
+    /// anything about the scope.
+    fn fingerprint(&self, scope: &str) -> String {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.scope_key[..])
+            .expect("HMAC can take keys of any size");
+        mac.update(scope.as_bytes());
+        hex(&mac.finalize().into_bytes()[..8])
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use super::PageTokens;
+
+    #[test]
+    fn page_token_test() -> anyhow::Result<()> {
+        let tokens = PageTokens::new(b"secret");
+        let token = tokens.issue("kv:orders:kv.filesystem", &(0_usize, Some("orders/42")))?;
+        assert!(token.starts_with("v1."));
+        // the cursor doesn't show through
+        assert!(!token.contains("orders"));
+
+        // another host w/ the same secret resumes it
+        let (backend, cursor): (usize, Option<String>) =
+            PageTokens::new(b"secret").resume("kv:orders:kv.filesystem", &token)?;
+        assert_eq!((backend, cursor.as_deref()), (0, Some("orders/42")));
+
+        // a token of another scope is stale, and one of another key, or tampered w/ is invalid
+        let stale = tokens
+            .resume::<(usize, Option<String>)>("kv:orders:kv.azblob", &token)
+            .unwrap_err();
+        assert!(stale.to_string().starts_with("stale"));
+        assert!(PageTokens::new(b"other")
+            .resume::<(usize, Option<String>)>("kv:orders:kv.filesystem", &token)
+            .is_err());
+        let mut tampered = token.clone();
+        let last = if tampered.ends_with('0') { "1" } else { "0" };
+        tampered.replace_range(tampered.len() - 1.., last);
+        let invalid = tokens
+            .resume::<(usize, Option<String>)>("kv:orders:kv.filesystem", &tampered)
+            .unwrap_err();
+        assert!(invalid.to_string().starts_with("invalid"));
+        assert!(tokens
+            .resume::<(usize, Option<String>)>("kv:orders:kv.filesystem", "v0.a.b")
+            .is_err());
+        assert!(tokens
+            .resume::<(usize, Option<String>)>("kv:orders:kv.filesystem", "")
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+
+use anyhow::Result;
+
+/// `PayloadLimit` is the max size of the payloads a guest sends through a capability (e.g., the
+/// values it sets in a kv store, or the messages it sends to a queue), so a guest can't exhaust
+/// the host's memory, or send a backend more than it takes in one go.
+///
+/// Payloads are checked before they reach the backend (see `BasicState::check_payload`), and
+/// the ones past the limit fail w/ `PayloadTooLarge`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayloadLimit {
+    capability: String,
+    max_bytes: usize,
+}
+
+impl PayloadLimit {
+    pub fn new(capability: &str, max_bytes: usize) -> Self {
+        Self {
+            capability: capability.to_string(),
+            max_bytes,
+        }
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    /// Checks the size of a payload `operation` sends, failing w/ `PayloadTooLarge` if it's
+    /// bigger than the limit.
+    pub fn check(&self, operation: &str, bytes: usize) -> Result<()> {
+        if bytes > self.max_bytes {
+            return Err(PayloadTooLarge {
+                capability: self.capability.clone(),
+                operation: operation.to_string(),
+                size: bytes,
+                limit: self.max_bytes,
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// `PayloadTooLarge` is the error calls sending a payload bigger than the `PayloadLimit` of
+/// their capability fail w/.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayloadTooLarge {
+    pub capability: String,
+    /// the operation, as its function is named (e.g., `send`)
+    pub operation: String,
+    /// the size of the payload, in bytes
+    pub size: usize,
+    /// the max size of payloads, in bytes (i.e., the capability's `max_payload_bytes`)
+    pub limit: usize,
+}
+
+impl PayloadTooLarge {
+    /// Whether an error was caused by a call sending too large a payload.
+    pub fn is(error: &anyhow::Error) -> bool {
+        error.downcast_ref::<Self>().is_some()
+    }
+}
+
+impl fmt::Display for PayloadTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the payload of '{}' is {} bytes, which is more than the {} bytes '{}' takes (see `max_payload_bytes`)",
+            self.operation, self.size, self.limit, self.capability
+        )
+    }
+}
+
+impl std::error::Error for PayloadTooLarge {}
+
+#[cfg(test)]
+mod unittests {
+    use super::{PayloadLimit, PayloadTooLarge};
+
+    #[test]
+    fn payload_limit_test() {
+        let limit = PayloadLimit::new("mq.filesystem", 1024);
+        assert!(limit.check("send", 0).is_ok());
+        assert!(limit.check("send", 1024).is_ok());
+
+        let e = limit.check("send", 1025).unwrap_err();
+        assert!(PayloadTooLarge::is(&e));
+        assert_eq!(
+            e.to_string(),
+            "the payload of 'send' is 1025 bytes, which is more than the 1024 bytes 'mq.filesystem' takes (see `max_payload_bytes`)"
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+/// How long a call waits for a connection to free up, if `PoolSettings` don't say.
+pub const DEFAULT_CONNECTION_WAIT: Duration = Duration::from_millis(1000);
+
+/// The limit on how many calls into a capability can be in flight at once (i.e., how many
+/// connections to its backend the guest can hold).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolSettings {
+    pub max_connections: u32,
+    /// how long a call waits for a connection to free up before it fails
+    pub wait: Duration,
+}
+
+impl PoolSettings {
+    pub fn new(max_connections: u32, wait_ms: Option<u64>) -> Self {
+        Self {
+            max_connections,
+            wait: wait_ms.map_or(DEFAULT_CONNECTION_WAIT, Duration::from_millis),
+        }
+    }
+}
+
+/// `PoolExhausted` is the error calls fail w/ if no connection freed up in time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoolExhausted {
+    pub capability: String,
+    pub max_connections: u32,
+    /// how long the call waited
+    pub waited: Duration,
+}
+
+impl PoolExhausted {
+    /// Whether an error was caused by a call that didn't get a connection.
+    pub fn is(error: &anyhow::Error) -> bool {
+        error.downcast_ref::<Self>().is_some()
+    }
+}
+
+impl fmt::Display for PoolExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "all {} connections of '{}' are in use, and none freed up in {:?}",
+            self.max_connections, self.capability, self.waited
+        )
+    }
+}
+
+impl std::error::Error for PoolExhausted {}
+
+/// A `Pool` bounds how many calls into a capability are in flight at once to its
+/// `max_connections`, like a semaphore — calls beyond them wait for one to be released,
+/// and fail w/ `PoolExhausted` if none is in time.
+///
+/// It counts the calls that waited, or were rejected, and the most connections in use at
+/// once (see `Pools::reports`).
+#[derive(Debug)]
+pub struct Pool {
+    capability: String,
+    settings: PoolSettings,
+    in_use: Mutex<u32>,
+    released: Condvar,
+    peak: AtomicU64,
+    waited: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl Pool {
+    fn new(capability: &str, settings: PoolSettings) -> Self {
+        Self {
+            capability: capability.to_string(),
+            settings,
+            in_use: Mutex::new(0),
+            released: Condvar::new(),
+            peak: AtomicU64::new(0),
+            waited: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Acquires a connection, which is released once the returned `Connection` is dropped.
+    ///
+    /// It waits no longer than the deadline of the call it's for, if it has one (see
+    /// `deadline::current`).
+    pub fn acquire(&self) -> Result<Connection<'_>> {
+        let start = Instant::now();
+        let deadline = start + self.settings.wait;
+        let deadline = crate::deadline::current().map_or(deadline, |call| call.min(deadline));
+        let mut in_use = self.in_use.lock().unwrap();
+        if *in_use >= self.settings.max_connections {
+            self.waited.fetch_add(1, Ordering::Relaxed);
+        }
+        while *in_use >= self.settings.max_connections {
+            let now = Instant::now();
+            if now >= deadline {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                tracing::debug!(
+                    "'{}' is out of connections ({} in use)",
+                    self.capability,
+                    *in_use
+                );
+                return Err(PoolExhausted {
+                    capability: self.capability.clone(),
+                    max_connections: self.settings.max_connections,
+                    waited: now - start,
+                }
+                .into());
+            }
+            in_use = self
+                .released
+                .wait_timeout(in_use, deadline - now)
+                .unwrap()
+                .0;
+        }
+        *in_use += 1;
+        self.peak.fetch_max(*in_use as u64, Ordering::Relaxed);
+        Ok(Connection(self))
+    }
+
+    pub fn in_use(&self) -> u32 {
+        *self.in_use.lock().unwrap()
+    }
+
+    pub fn max_connections(&self) -> u32 {
+        self.settings.max_connections
+    }
+}
+
+/// A connection of a `Pool`, held for as long as a call is in flight.
+#[derive(Debug)]
+pub struct Connection<'a>(&'a Pool);
+
+impl Drop for Connection<'_> {
+    fn drop(&mut self) {
+        *self.0.in_use.lock().unwrap() -= 1;
+        self.0.released.notify_one();
+    }
+}
+
+/// What `Pools` report about the pool of a capability.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoolReport {
+    pub capability: String,
+    pub max_connections: u32,
+    /// how many connections are in use right now
+    pub in_use: u32,
+    /// the most connections that were in use at once
+    pub peak: u64,
+    /// how many calls had to wait for a connection
+    pub waited: u64,
+    /// how many calls failed because no connection freed up in time
+    pub rejected: u64,
+}
+
+/// `Pools` hold the connection pools of an app's capabilities, which are shared by all of
+/// its guest instances (e.g., the ones handling http requests), as they are what can call
+/// into a capability concurrently.
+#[derive(Clone, Debug, Default)]
+pub struct Pools(Arc<Mutex<BTreeMap<String, Arc<Pool>>>>);
+
+impl Pools {
+    /// Gets the pool of `capability`, creating it w/ `settings` if there's none yet (or none
+    /// w/ these settings), or `None` if there's no limit.
+    pub fn get(&self, capability: &str, settings: Option<PoolSettings>) -> Option<Arc<Pool>> {
+        let settings = settings?;
+        let mut pools = self.0.lock().unwrap();
+        match pools.get(capability) {
+            Some(pool) if pool.settings == settings => Some(pool.clone()),
+            _ => {
+                let pool = Arc::new(Pool::new(capability, settings));
+                pools.insert(capability.to_string(), pool.clone());
+                Some(pool)
+            }
+        }
+    }
+
+    /// Reports on the pools, sorted by capability.
+    pub fn reports(&self) -> Vec<PoolReport> {
+        self.0
+            .lock()
+            .unwrap()
+            .values()
+            .map(|pool| PoolReport {
+                capability: pool.capability.clone(),
+                max_connections: pool.settings.max_connections,
+                in_use: pool.in_use(),
+                peak: pool.peak.load(Ordering::Relaxed),
+                waited: pool.waited.load(Ordering::Relaxed),
+                rejected: pool.rejected.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::{sync::Arc, thread, time::Duration};
+
+    use super::{PoolExhausted, PoolSettings, Pools};
+
+    #[test]
+    fn pool_test() {
+        let pools = Pools::default();
+        assert!(pools.get("kv.filesystem", None).is_none());
+        let pool = pools
+            .get("kv.filesystem", Some(PoolSettings::new(2, Some(20))))
+            .unwrap();
+
+        let first = pool.acquire().unwrap();
+        let _second = pool.acquire().unwrap();
+        let e = pool.acquire().unwrap_err();
+        assert!(PoolExhausted::is(&e));
+        assert!(e.downcast_ref::<PoolExhausted>().unwrap().waited >= Duration::from_millis(20));
+
+        // a released connection goes to the next call
+        drop(first);
+        let _third = pool.acquire().unwrap();
+
+        let reports = pools.reports();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].in_use, 2);
+        assert_eq!(reports[0].peak, 2);
+        assert_eq!(reports[0].waited, 1);
+        assert_eq!(reports[0].rejected, 1);
+    }
+
+    #[test]
+    fn waiting_test() {
+        let pools = Pools::default();
+        let pool = pools
+            .get("mq.azsbus", Some(PoolSettings::new(1, Some(5000))))
+            .unwrap();
+        let connection = pool.acquire().unwrap();
+
+        let waiting = {
+            let pool = Arc::clone(&pool);
+            thread::spawn(move || pool.acquire().map(|_| ()).is_ok())
+        };
+        thread::sleep(Duration::from_millis(20));
+        drop(connection);
+        assert!(waiting.join().unwrap());
+
+        // guest instances of the same app share the pool
+        let shared = pools
+            .get("mq.azsbus", Some(PoolSettings::new(1, Some(5000))))
+            .unwrap();
+        assert!(Arc::ptr_eq(&pool, &shared));
+        assert_eq!(pools.reports()[0].in_use, 0);
+    }
+}
@@ -0,0 +1,345 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+/// The limits a guest's calls into a capability are held to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QuotaSettings {
+    /// how many operations can be done per second
+    pub ops_per_sec: Option<u64>,
+    /// how many bytes (of payloads sent, or received) can go through per minute
+    pub bytes_per_min: Option<u64>,
+}
+
+impl QuotaSettings {
+    fn is_empty(&self) -> bool {
+        self.ops_per_sec.is_none() && self.bytes_per_min.is_none()
+    }
+}
+
+/// `RateLimited` is the error calls exceeding a quota fail w/.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RateLimited {
+    pub capability: String,
+    /// the limit that was exceeded (e.g., `100 ops/sec`)
+    pub limit: String,
+    /// how long it'll take for the call to be within the limit again
+    pub retry_after: Duration,
+}
+
+impl RateLimited {
+    /// Whether an error was caused by a call exceeding a quota.
+    pub fn is(error: &anyhow::Error) -> bool {
+        error.downcast_ref::<Self>().is_some()
+    }
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the quota of {} for '{}' was exceeded, retry in {:?}",
+            self.limit, self.capability, self.retry_after
+        )
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// A `TokenBucket` holds up to `capacity` tokens, and is refilled at a fixed rate.
+///
+/// It can go into debt (see `charge`), in which case nothing can be taken from it until it's
+/// refilled above zero.
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    per_sec: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: u64, per: Duration, now: Instant) -> Self {
+        Self {
+            capacity: capacity as f64,
+            per_sec: capacity as f64 / per.as_secs_f64(),
+            tokens: capacity as f64,
+            refilled_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.per_sec).min(self.capacity);
+        self.refilled_at = now;
+    }
+
+    /// Takes `n` tokens, or says how long until they can be — more tokens than the bucket
+    /// holds can be taken once it's full.
+    fn take(&mut self, n: u64, now: Instant) -> Result<(), Duration> {
+        self.refill(now);
+        let needed = (n as f64).min(self.capacity);
+        if self.tokens >= needed && self.tokens > 0.0 {
+            self.tokens -= n as f64;
+            Ok(())
+        } else if self.per_sec > 0.0 {
+            Err(Duration::from_secs_f64(
+                (needed.max(f64::MIN_POSITIVE) - self.tokens) / self.per_sec,
+            ))
+        } else {
+            // a quota of zero is never refilled
+            Err(Duration::MAX)
+        }
+    }
+
+    /// Takes `n` tokens whether there are enough of them or not (i.e., for something that
+    /// can't be refused anymore).
+    fn charge(&mut self, n: u64, now: Instant) {
+        self.refill(now);
+        self.tokens -= n as f64;
+    }
+}
+
+/// A `Quota` enforces the `QuotaSettings` of a guest's calls into a capability, and counts
+/// the calls it rejected, and the bytes that went through (see `Quotas::reports`).
+#[derive(Debug)]
+pub struct Quota {
+    capability: String,
+    settings: QuotaSettings,
+    ops: Option<Mutex<TokenBucket>>,
+    bytes: Option<Mutex<TokenBucket>>,
+    rejected_ops: AtomicU64,
+    rejected_bytes: AtomicU64,
+    bytes_total: AtomicU64,
+}
+
+impl Quota {
+    fn new(capability: &str, settings: QuotaSettings, now: Instant) -> Self {
+        Self {
+            capability: capability.to_string(),
+            settings,
+            ops: settings
+                .ops_per_sec
+                .map(|ops| Mutex::new(TokenBucket::new(ops, Duration::from_secs(1), now))),
+            bytes: settings
+                .bytes_per_min
+                .map(|bytes| Mutex::new(TokenBucket::new(bytes, Duration::from_secs(60), now))),
+            rejected_ops: AtomicU64::new(0),
+            rejected_bytes: AtomicU64::new(0),
+            bytes_total: AtomicU64::new(0),
+        }
+    }
+
+    /// Takes an operation from the quota — calls are also rejected while the bytes quota is
+    /// used up.
+    pub fn take_op(&self) -> Result<()> {
+        self.take_op_at(Instant::now())
+    }
+
+    /// Takes the bytes of a payload the guest sends from the quota, before it's sent.
+    pub fn take_bytes(&self, bytes: usize) -> Result<()> {
+        self.take_bytes_at(bytes as u64, Instant::now())
+    }
+
+    /// Charges the quota w/ the bytes of a payload the guest received, which can't be refused
+    /// anymore, so the quota may go into debt (i.e., later calls wait for it to be paid off).
+    pub fn charge_bytes(&self, bytes: usize) {
+        self.bytes_total.fetch_add(bytes as u64, Ordering::Relaxed);
+        if let Some(bucket) = &self.bytes {
+            bucket.lock().unwrap().charge(bytes as u64, Instant::now());
+        }
+    }
+
+    fn take_op_at(&self, now: Instant) -> Result<()> {
+        if let Some(bucket) = &self.ops {
+            if let Err(retry_after) = bucket.lock().unwrap().take(1, now) {
+                self.rejected_ops.fetch_add(1, Ordering::Relaxed);
+                return Err(self.rate_limited("ops", retry_after));
+            }
+        }
+        // nothing is taken, but the bytes quota mustn't be in debt
+        if let Some(bucket) = &self.bytes {
+            if let Err(retry_after) = bucket.lock().unwrap().take(0, now) {
+                self.rejected_bytes.fetch_add(1, Ordering::Relaxed);
+                return Err(self.rate_limited("bytes", retry_after));
+            }
+        }
+        Ok(())
+    }
+
+    fn take_bytes_at(&self, bytes: u64, now: Instant) -> Result<()> {
+        if let Some(bucket) = &self.bytes {
+            if let Err(retry_after) = bucket.lock().unwrap().take(bytes, now) {
+                self.rejected_bytes.fetch_add(1, Ordering::Relaxed);
+                return Err(self.rate_limited("bytes", retry_after));
+            }
+        }
+        self.bytes_total.fetch_add(bytes, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn rate_limited(&self, limit: &str, retry_after: Duration) -> anyhow::Error {
+        let limit = match limit {
+            "ops" => format!("{} ops/sec", self.settings.ops_per_sec.unwrap_or_default()),
+            _ => format!(
+                "{} bytes/min",
+                self.settings.bytes_per_min.unwrap_or_default()
+            ),
+        };
+        tracing::debug!("'{}' is rate limited ({})", self.capability, limit);
+        RateLimited {
+            capability: self.capability.clone(),
+            limit,
+            retry_after,
+        }
+        .into()
+    }
+}
+
+/// What `Quotas` report about the quota of a capability.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuotaReport {
+    pub capability: String,
+    /// how many calls were rejected for exceeding the ops quota
+    pub rejected_ops: u64,
+    /// how many calls were rejected for exceeding the bytes quota
+    pub rejected_bytes: u64,
+    /// how many bytes went through
+    pub bytes_total: u64,
+}
+
+/// `Quotas` hold the quotas of an app's capabilities, which are shared by all of its guest
+/// instances (e.g., the ones handling http requests), and kept across restarts.
+#[derive(Clone, Debug, Default)]
+pub struct Quotas(Arc<Mutex<BTreeMap<String, Arc<Quota>>>>);
+
+impl Quotas {
+    /// Gets the quota of `capability`, creating it w/ `settings` if there's none yet (or none
+    /// w/ these settings), or `None` if there are no limits.
+    pub fn get(&self, capability: &str, settings: QuotaSettings) -> Option<Arc<Quota>> {
+        if settings.is_empty() {
+            return None;
+        }
+        let mut quotas = self.0.lock().unwrap();
+        match quotas.get(capability) {
+            Some(quota) if quota.settings == settings => Some(quota.clone()),
+            _ => {
+                let quota = Arc::new(Quota::new(capability, settings, Instant::now()));
+                quotas.insert(capability.to_string(), quota.clone());
+                Some(quota)
+            }
+        }
+    }
+
+    /// Reports on the quotas, sorted by capability.
+    pub fn reports(&self) -> Vec<QuotaReport> {
+        self.0
+            .lock()
+            .unwrap()
+            .values()
+            .map(|quota| QuotaReport {
+                capability: quota.capability.clone(),
+                rejected_ops: quota.rejected_ops.load(Ordering::Relaxed),
+                rejected_bytes: quota.rejected_bytes.load(Ordering::Relaxed),
+                bytes_total: quota.bytes_total.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::{Duration, Instant};
+
+    use super::{Quota, QuotaSettings, Quotas, RateLimited};
+
+    fn settings(ops_per_sec: Option<u64>, bytes_per_min: Option<u64>) -> QuotaSettings {
+        QuotaSettings {
+            ops_per_sec,
+            bytes_per_min,
+        }
+    }
+
+    #[test]
+    fn ops_quota_test() {
+        let t = Instant::now();
+        let quota = Quota::new("kv.filesystem", settings(Some(2), None), t);
+        assert!(quota.take_op_at(t).is_ok());
+        assert!(quota.take_op_at(t).is_ok());
+
+        let e = quota.take_op_at(t).unwrap_err();
+        assert!(RateLimited::is(&e));
+        let rate_limited = e.downcast_ref::<RateLimited>().unwrap();
+        assert_eq!(rate_limited.limit, "2 ops/sec");
+        assert_eq!(rate_limited.retry_after, Duration::from_millis(500));
+
+        // half a second refills one op
+        assert!(quota.take_op_at(t + Duration::from_millis(500)).is_ok());
+        assert!(quota.take_op_at(t + Duration::from_millis(500)).is_err());
+        assert_eq!(
+            quota
+                .rejected_ops
+                .load(std::sync::atomic::Ordering::Relaxed),
+            2
+        );
+    }
+
+    #[test]
+    fn bytes_quota_test() {
+        let t = Instant::now();
+        let quota = Quota::new("mq.filesystem", settings(None, Some(60)), t);
+        assert!(quota.take_bytes_at(50, t).is_ok());
+        assert!(quota.take_bytes_at(20, t).is_err());
+        assert!(quota.take_bytes_at(10, t).is_ok());
+
+        // payloads larger than the quota go through once it's full
+        assert!(quota
+            .take_bytes_at(100, t + Duration::from_secs(59))
+            .is_err());
+        assert!(quota
+            .take_bytes_at(100, t + Duration::from_secs(60))
+            .is_ok());
+
+        // it's in debt now, so no call goes through until it's paid off
+        let t = t + Duration::from_secs(60);
+        assert!(quota.take_op_at(t + Duration::from_secs(40)).is_err());
+        assert!(quota.take_op_at(t + Duration::from_secs(41)).is_ok());
+    }
+
+    #[test]
+    fn quotas_test() {
+        let quotas = Quotas::default();
+        assert!(quotas.get("kv.filesystem", settings(None, None)).is_none());
+
+        let kv = quotas
+            .get("kv.filesystem", settings(Some(1), None))
+            .unwrap();
+        assert!(kv.take_op().is_ok());
+        // guest instances of the same app share the quota
+        let shared = quotas
+            .get("kv.filesystem", settings(Some(1), None))
+            .unwrap();
+        assert!(shared.take_op().is_err());
+
+        let mq = quotas
+            .get("mq.filesystem", settings(None, Some(100)))
+            .unwrap();
+        assert!(mq.take_bytes(10).is_ok());
+        mq.charge_bytes(5);
+
+        let reports = quotas.reports();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].capability, "kv.filesystem");
+        assert_eq!(reports[0].rejected_ops, 1);
+        assert_eq!(reports[1].capability, "mq.filesystem");
+        assert_eq!(reports[1].bytes_total, 15);
+    }
+}
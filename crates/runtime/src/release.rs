@@ -0,0 +1,361 @@
+use std::{
+    fmt,
+    ops::Deref,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::drain::{Drained, InFlight, Operation, DEFAULT_DRAIN_GRACE};
+
+/// A backend a resource holds (e.g., a client, and its connections), which the guest can
+/// release once it's done w/ it (see `Watch::releaser`), rather than holding it for as long as
+/// it runs (e.g., a store only read at startup).
+///
+/// A released backend is reopened w/ `open` on its next use, or, if `reopen` is off, using it
+/// fails — either way, the resource stays linked, and can be opened anew. If reopening it
+/// fails (e.g., as its backend is unreachable), so does the call, and the next one tries again.
+///
+/// The calls made w/ the backend are tracked (see `Lease`), so releasing it drains them first
+/// (for up to its `grace` period), rather than closing it mid-call.
+///
+/// It's a handle, so all of its clones (e.g., the one in the `ResourceMap`, and the guest's)
+/// share the backend.
+pub struct Releasable<T> {
+    backend: Arc<Mutex<Option<Opened<T>>>>,
+    open: Arc<dyn Fn() -> Result<T> + Send + Sync>,
+    reopen: bool,
+    /// what the backend is, for the logs of draining it
+    name: Arc<str>,
+    grace: Duration,
+}
+
+impl<T> Clone for Releasable<T> {
+    fn clone(&self) -> Self {
+        Self {
+            backend: self.backend.clone(),
+            open: self.open.clone(),
+            reopen: self.reopen,
+            name: self.name.clone(),
+            grace: self.grace,
+        }
+    }
+}
+
+/// A backend that's open, w/ the calls in flight on it — a backend reopened while the one it
+/// replaces is drained counts its own.
+#[derive(Debug)]
+struct Opened<T> {
+    backend: T,
+    in_flight: InFlight,
+}
+
+impl<T> Opened<T> {
+    fn new(backend: T) -> Self {
+        Self {
+            backend,
+            in_flight: InFlight::default(),
+        }
+    }
+}
+
+/// The backend a call got from a `Releasable` (see `get`), which counts as in flight until
+/// it's dropped.
+pub struct Lease<T> {
+    backend: T,
+    _operation: Operation,
+}
+
+impl<T> Deref for Lease<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.backend
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Releasable<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Releasable")
+            .field("backend", &self.backend)
+            .field("reopen", &self.reopen)
+            .field("name", &self.name)
+            .field("grace", &self.grace)
+            .finish()
+    }
+}
+
+impl<T: Clone> Releasable<T> {
+    /// Opens the backend w/ `open` right away, as resources are opened when they're used.
+    pub fn new(open: impl Fn() -> Result<T> + Send + Sync + 'static, reopen: bool) -> Result<Self> {
+        Ok(Self {
+            backend: Arc::new(Mutex::new(Some(Opened::new(open()?)))),
+            open: Arc::new(open),
+            reopen,
+            name: Arc::from("a resource"),
+            grace: DEFAULT_DRAIN_GRACE,
+        })
+    }
+
+    /// Names the backend (e.g., `kv 'orders'`) in the logs of draining it, and sets how long
+    /// releasing it waits for the calls in flight (see `release`).
+    pub fn with_drain(mut self, name: &str, grace: Duration) -> Self {
+        self.name = Arc::from(name);
+        self.grace = grace;
+        self
+    }
+
+    /// Gets the backend, reopening it if it was released (or failing, if `reopen` is off).
+    ///
+    /// The backend is cloned, so that calls don't hold the lock while they're made — a call in
+    /// flight when it's released finishes w/ the clone it got, which is waited for (see
+    /// `release`) until the `Lease` is dropped.
+    pub fn get(&self) -> Result<Lease<T>> {
+        self.opened(|opened| {
+            Ok(Lease {
+                backend: opened.backend.clone(),
+                _operation: opened.in_flight.start(),
+            })
+        })
+    }
+
+    /// Calls `f` w/ the backend, reopening it if it was released (see `get`), for calls that
+    /// change it (e.g., to watch a key).
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> Result<R>) -> Result<R> {
+        self.opened(|opened| f(&mut opened.backend))
+    }
+
+    fn opened<R>(&self, f: impl FnOnce(&mut Opened<T>) -> Result<R>) -> Result<R> {
+        let mut backend = self.backend.lock().unwrap();
+        if backend.is_none() {
+            if !self.reopen {
+                bail!("the resource was released, and released resources aren't reopened");
+            }
+            tracing::debug!("reopening a released resource");
+            let reopened = (self.open)()
+                .with_context(|| format!("failed to reopen {}, which was released", self.name))?;
+            *backend = Some(Opened::new(reopened));
+        }
+        f(backend.as_mut().unwrap())
+    }
+
+    /// Releases the backend (i.e., drops it, closing its connections), returning whether it
+    /// was open.
+    ///
+    /// The calls in flight on it are waited for first (for up to the `grace` period, see
+    /// `InFlight::drain`), while new ones reopen it (or fail), so they don't wait behind the
+    /// release.
+    pub fn release(&self) -> bool {
+        self.release_drained().is_some()
+    }
+
+    /// Releases the backend like `release`, returning how draining it went, if it was open.
+    pub fn release_drained(&self) -> Option<Drained> {
+        let opened = self.backend.lock().unwrap().take()?;
+        let drained = opened.in_flight.drain(&self.name, self.grace);
+        drop(opened);
+        Some(drained)
+    }
+
+    /// The calls in flight on the backend (none, if it's released).
+    pub fn in_flight(&self) -> usize {
+        self.backend
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(0, |opened| opened.in_flight.count())
+    }
+
+    pub fn is_released(&self) -> bool {
+        self.backend.lock().unwrap().is_none()
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use anyhow::{bail, Result};
+
+    use super::Releasable;
+    use crate::{
+        drain::Drained,
+        resource::{release_all, Releaser, ResourceMap, Watch},
+    };
+
+    /// A resource w/ a backend, as kept in the `ResourceMap`.
+    struct Held(Releasable<u32>);
+
+    impl Watch for Held {
+        fn releaser(&self) -> Option<Releaser> {
+            let backend = self.0.clone();
+            Some(Box::new(move || Ok(backend.release())))
+        }
+    }
+
+    struct Unreleasable;
+
+    impl Watch for Unreleasable {
+        fn releaser(&self) -> Option<Releaser> {
+            Some(Box::new(|| bail!("connection reset")))
+        }
+    }
+
+    #[test]
+    fn release_and_reopen_test() -> Result<()> {
+        let opened = Arc::new(AtomicUsize::new(0));
+        let releasable = {
+            let opened = opened.clone();
+            Releasable::new(move || Ok(opened.fetch_add(1, Ordering::SeqCst) + 1), true)?
+        };
+        let clone = releasable.clone();
+        assert_eq!(*releasable.get()?, 1);
+
+        // the clones share the backend
+        assert!(clone.release());
+        assert!(releasable.is_released());
+        assert!(!releasable.release());
+
+        // it's reopened once it's used again, and only then
+        assert_eq!(opened.load(Ordering::SeqCst), 1);
+        assert_eq!(*releasable.get()?, 2);
+        assert_eq!(*clone.get()?, 2);
+        assert!(!clone.is_released());
+        Ok(())
+    }
+
+    #[test]
+    fn released_wo_reopen_test() -> Result<()> {
+        let releasable = Releasable::new(|| Ok(vec!["key".to_string()]), false)?;
+        releasable.with(|keys| {
+            keys.push("other".to_string());
+            Ok(())
+        })?;
+        assert_eq!(releasable.get()?.len(), 2);
+
+        assert!(releasable.release());
+        assert!(releasable.get().is_err());
+        assert!(releasable.with(|_| Ok(())).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn release_drains_in_flight_test() -> Result<()> {
+        let releasable =
+            Releasable::new(|| Ok(1), true)?.with_drain("kv 'test'", Duration::from_secs(5));
+        let lease = releasable.get()?;
+        assert_eq!(releasable.in_flight(), 1);
+        let finishing = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(lease);
+        });
+        // the release waits for the call in flight, rather than closing the backend under it
+        assert_eq!(
+            releasable.release_drained(),
+            Some(Drained {
+                waited_for: 1,
+                outstanding: 0
+            })
+        );
+        finishing.join().unwrap();
+
+        // calls reopening it meanwhile aren't waited for, but the stuck ones are, for so long
+        let releasable = releasable.with_drain("kv 'test'", Duration::from_millis(10));
+        let _stuck = releasable.get()?;
+        let reopened = releasable.clone();
+        let other = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(5));
+            let _lease = reopened.get().unwrap();
+            thread::sleep(Duration::from_millis(50));
+        });
+        assert_eq!(
+            releasable.release_drained(),
+            Some(Drained {
+                waited_for: 1,
+                outstanding: 1
+            })
+        );
+        other.join().unwrap();
+        assert_eq!(releasable.in_flight(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn failed_reopen_test() -> Result<()> {
+        let reachable = Arc::new(AtomicBool::new(true));
+        let releasable = {
+            let reachable = reachable.clone();
+            Releasable::new(
+                move || match reachable.load(Ordering::SeqCst) {
+                    true => Ok(1),
+                    false => bail!("connection refused"),
+                },
+                true,
+            )?
+            .with_drain("kv 'test'", Duration::from_secs(5))
+        };
+        assert!(releasable.release());
+
+        // the call fails w/ why it couldn't be reopened, rather than panicking
+        reachable.store(false, Ordering::SeqCst);
+        let e = format!("{:#}", releasable.get().unwrap_err());
+        assert!(e.contains("failed to reopen kv 'test'"), "{}", e);
+        assert!(e.contains("connection refused"), "{}", e);
+        assert!(releasable.is_released());
+
+        // and the next one tries again
+        reachable.store(true, Ordering::SeqCst);
+        assert_eq!(*releasable.get()?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn release_all_test() -> Result<()> {
+        let resource_map = ResourceMap::default();
+        let slow =
+            Releasable::new(|| Ok(1), true)?.with_drain("kv 'slow'", Duration::from_millis(200));
+        let fast = Releasable::new(|| Ok(2), true)?;
+        let stuck = slow.get()?;
+        {
+            let mut map = resource_map.lock().unwrap();
+            map.set("slow".to_string(), Box::new(Held(slow.clone())));
+            map.set("fast".to_string(), Box::new(Held(fast.clone())));
+        }
+        let releasing = {
+            let resource_map = resource_map.clone();
+            thread::spawn(move || release_all(&resource_map))
+        };
+
+        // the map isn't locked while the slow one is drained, nor does it hold up the fast one
+        thread::sleep(Duration::from_millis(50));
+        let locking = Instant::now();
+        drop(resource_map.lock().unwrap());
+        assert!(locking.elapsed() < Duration::from_millis(100));
+        assert!(fast.is_released());
+        assert_eq!(releasing.join().unwrap()?, 2);
+        assert!(slow.is_released());
+        // and the call still in flight finishes w/ its backend
+        assert_eq!(*stuck, 1);
+        drop(stuck);
+
+        // one that fails doesn't stop the others, and all of the errors are told
+        resource_map
+            .lock()
+            .unwrap()
+            .set("unreleasable".to_string(), Box::new(Unreleasable));
+        assert_eq!(*fast.get()?, 2);
+        let e = release_all(&resource_map).unwrap_err().to_string();
+        assert!(e.contains("'unreleasable': connection reset"), "{}", e);
+        assert!(e.contains("released 1"), "{}", e);
+        assert!(fast.is_released());
+        Ok(())
+    }
+}
@@ -1,13 +1,25 @@
 use std::{
+    any::Any,
     collections::HashMap,
     sync::{Arc, Mutex},
 };
 
+use crate::call::{self, Call, CallSettings, Interceptor, Outcome};
+use crate::cassette::{Cassette, Recorded, Replayed, CASSETTE};
+use crate::chaos::{Chaos, CHAOS};
+use crate::connections::Connections;
+use crate::credentials::Credentials;
+use crate::headroom::Headroom;
+use crate::health::Health;
+use crate::last_known_good::LastKnownGood;
+use crate::mock::{Mocks, MOCKS};
+use crate::trace_context::Propagation;
 pub use crate::RuntimeContext;
-use anyhow::Result;
+use anyhow::{bail, Result};
 use as_any::{AsAny, Downcast};
 use crossbeam_channel::Sender;
-use slight_events_api::{Event, EventHandlerData};
+use slight_compression::Compression;
+use slight_events_api::{Event, EventBatchHandlerData, EventHandlerData};
 use slight_http_api::HttpHandlerData;
 pub use wasmtime::Linker;
 
@@ -31,6 +43,8 @@ pub type Ctx = RuntimeContext<HostState>;
 /// Guest data for event handler
 /// TODO (Joe): abstract this to a general guest data
 pub type GuestData = EventHandlerData;
+/// Guest data for the event handler that handles events in batches
+pub type BatchGuestData = EventBatchHandlerData;
 pub type HttpData = HttpHandlerData;
 
 /// `BasicState` provides an attempt at a "fit-all" for basic scenarios
@@ -38,28 +52,288 @@ pub type HttpData = HttpHandlerData;
 ///
 /// It contains:
 ///     - a `resource_map`,
-///     - a `secret_store`, and
-///     - the `config_toml_file_path`.
+///     - the `secret_stores` to look secrets up in, in order of precedence,
+///     - the `config_toml_file_path`,
+///     - the `call_settings` that apply to calls into the capability,
+///     - the `last_known_good` values to serve reads from if the backend is unavailable,
+///     - the `credentials` backends authenticate w/, shared by all capabilities of the app,
+///     - the `mocks` calls are responded to w/ instead of the backend (see `mock::Mocks`),
+///     if they were installed in the `resource_map`,
+///     - the `cassette` calls to the backend are recorded to, or replayed from (see
+///     `cassette::Cassette`), if it was installed in the `resource_map`,
+///     - the `chaos` injected into calls (see `chaos::Chaos`), if it was installed in the
+///     `resource_map`,
+///     - the `health` of the app's capabilities, which the outcome of each call is recorded in,
+///     - the `connections` to the backends, shared by all guest instances of the app (see
+///     `connections::Connections`),
+///     - the `compression` of the payloads the capability returns to the guest, if it has any,
+///     - the `headroom` of the guest instance's memory for them (see `headroom::Headroom`), and
+///     - the `trace_propagation` of the outbound requests the capability makes (see
+///     `trace_context::Propagation`).
 #[derive(Clone, Default)]
 pub struct BasicState {
     pub resource_map: ResourceMap,
-    pub secret_store: String,
+    pub secret_stores: Vec<String>,
     pub config_toml_file_path: String,
+    pub call_settings: CallSettings,
+    pub last_known_good: LastKnownGood,
+    pub credentials: Credentials,
+    pub mocks: Option<Mocks>,
+    pub cassette: Option<Cassette>,
+    pub chaos: Option<Chaos>,
+    pub health: Health,
+    pub connections: Connections,
+    pub compression: Option<Compression>,
+    pub headroom: Headroom,
+    pub trace_propagation: Propagation,
 }
 
 impl BasicState {
-    pub fn new(resource_map: ResourceMap, secret_store: &str, config_toml_file_path: &str) -> Self {
+    pub fn new(
+        resource_map: ResourceMap,
+        secret_stores: &[String],
+        config_toml_file_path: &str,
+    ) -> Self {
+        let (mocks, cassette, chaos, health, connections) = {
+            let mut state_table = resource_map.lock().unwrap();
+            (
+                state_table.find_shared::<Mocks>(MOCKS),
+                state_table.find_shared::<Cassette>(CASSETTE),
+                state_table.find_shared::<Chaos>(CHAOS),
+                Health::shared(&mut state_table),
+                Connections::shared(&mut state_table),
+            )
+        };
         Self {
             resource_map,
-            secret_store: secret_store.to_string(),
+            secret_stores: secret_stores.to_vec(),
             config_toml_file_path: config_toml_file_path.to_string(),
+            call_settings: CallSettings::default(),
+            last_known_good: LastKnownGood::default(),
+            credentials: Credentials::default(),
+            mocks,
+            cassette,
+            chaos,
+            health,
+            connections,
+            compression: None,
+            headroom: Headroom::default(),
+            trace_propagation: Propagation::default(),
+        }
+    }
+
+    pub fn with_call_settings(mut self, call_settings: CallSettings) -> Self {
+        self.call_settings = call_settings;
+        self
+    }
+
+    pub fn with_last_known_good(mut self, last_known_good: LastKnownGood) -> Self {
+        self.last_known_good = last_known_good;
+        self
+    }
+
+    pub fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_headroom(mut self, headroom: Headroom) -> Self {
+        self.headroom = headroom;
+        self
+    }
+
+    pub fn with_trace_propagation(mut self, trace_propagation: Propagation) -> Self {
+        self.trace_propagation = trace_propagation;
+        self
+    }
+
+    /// Runs every call of the capability through `interceptor` (see `call::Interceptor`), after
+    /// the ones added before it — it's to come after `with_call_settings`, which replaces them.
+    pub fn with_interceptor(mut self, interceptor: Arc<dyn Interceptor>) -> Self {
+        self.call_settings = self.call_settings.with_interceptor(interceptor);
+        self
+    }
+
+    /// Declares the operations of the capability that are idempotent (i.e., that guests can
+    /// safely retry if they time out, see `call::retryable`).
+    ///
+    /// Reads are, as are writes that leave the backend as it was if they're repeated (e.g.,
+    /// re-arming a timer), and operations w/o side effects (e.g., hashing) — the others aren't
+    /// (e.g., sending a message), as a timeout doesn't tell whether the backend did them, or
+    /// not.
+    pub fn with_idempotent_operations(mut self, operations: &'static [&'static str]) -> Self {
+        self.call_settings = self.call_settings.with_idempotent_operations(operations);
+        self
+    }
+
+    /// Declares the operations of the capability that only read (i.e., that don't change what
+    /// its backend holds), which audits of writes only leave out (see `audit::AuditSettings`).
+    ///
+    /// Reads are idempotent, but not every idempotent operation is a read (e.g., re-arming a
+    /// timer), so they're declared apart.
+    pub fn with_read_operations(mut self, operations: &'static [&'static str]) -> Self {
+        self.call_settings = self.call_settings.with_read_operations(operations);
+        self
+    }
+
+    /// Gets the connection of `implementor` to the backend `name` (e.g., a store), that the
+    /// guest instances of the app share, opening it w/ `open` if none of them holds it (see
+    /// `Connections::get_or_open`).
+    ///
+    /// Connections are told apart by the secret stores too, as they're what they're opened w/.
+    pub fn connection<T>(&self, implementor: &str, name: &str, open: impl FnOnce() -> T) -> Arc<T>
+    where
+        T: Send + Sync + 'static,
+    {
+        self.connections
+            .get_or_open(&self.connection_key(implementor, name), open)
+    }
+
+    /// Like `connection`, but for connections whose opening can fail.
+    pub fn try_connection<T>(
+        &self,
+        implementor: &str,
+        name: &str,
+        open: impl FnOnce() -> Result<T>,
+    ) -> Result<Arc<T>>
+    where
+        T: Send + Sync + 'static,
+    {
+        self.connections
+            .try_get_or_open(&self.connection_key(implementor, name), open)
+    }
+
+    fn connection_key(&self, implementor: &str, name: &str) -> String {
+        format!("{}:{}:{}", implementor, name, self.secret_stores.join(","))
+    }
+
+    /// Runs a capability operation w/ the `call_settings` of this state (see `call::instrument`).
+    pub fn instrument<T: Outcome>(
+        &self,
+        capability: &str,
+        operation: &str,
+        target: &str,
+        f: impl FnOnce() -> T,
+    ) -> T {
+        call::instrument(&self.call_settings, capability, operation, target, || {
+            let call = Call {
+                capability,
+                operation,
+                target,
+            };
+            // injected faults fail calls before they reach the mocks, or the backend
+            let injected = self.chaos.as_ref().and_then(|chaos| chaos.inject(&call));
+            let res = match (injected, &self.mocks) {
+                (Some(res), _) => res,
+                (None, Some(mocks)) => mocks.respond(&call).unwrap_or_else(f),
+                (None, None) => f(),
+            };
+            self.health.record(capability, res.error_kind());
+            res
+        })
+    }
+
+    /// Checks the guest is permitted to call `operation` of `capability` (i.e., it's granted,
+    /// and enabled, see `call::permitted`), for the operations that aren't instrumented, as
+    /// they don't call the backend (e.g., `open`, or `watch`, which only make a resource).
+    pub fn permit(&self, capability: &str, operation: &str) -> Result<()> {
+        call::permitted(&self.call_settings, capability, operation)
+    }
+
+    /// Calls the capability's backend w/ `f`, unless there's a `cassette` replaying its
+    /// outcome, which is then recorded to it if it's recording (see `Cassette::call`).
+    ///
+    /// The `arguments` tell calls of the same `operation` apart (e.g., the name of a store,
+    /// and a key).
+    pub fn recorded<T: Replayed>(
+        &self,
+        capability: &str,
+        operation: &str,
+        arguments: &[&dyn Recorded],
+        f: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        match &self.cassette {
+            Some(cassette) => cassette.call(capability, operation, arguments, f),
+            None => f(),
+        }
+    }
+
+    /// Checks the size of a payload `operation` sends through the capability against its
+    /// payload limit (if it has any), failing w/ `payload_limit::PayloadTooLarge` if it's
+    /// bigger — it's checked before the payload reaches the backend.
+    pub fn check_payload(&self, operation: &str, bytes: usize) -> Result<()> {
+        match &self.call_settings.payload_limit {
+            Some(payload_limit) => payload_limit.check(operation, bytes),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks the size of a result `operation` returns to the guest (as it's returned, i.e.,
+    /// compressed) against the headroom of its memory, failing w/ `headroom::OutOfMemory` if
+    /// the guest can't allocate it — rather than trapping while it's written into its memory.
+    pub fn check_headroom(&self, operation: &str, bytes: usize) -> Result<()> {
+        self.headroom.check(operation, bytes)
+    }
+
+    /// Takes the bytes of a payload sent through the capability from its quota (if it has
+    /// any), failing w/ `quota::RateLimited` if they exceed it.
+    pub fn take_bytes(&self, bytes: usize) -> Result<()> {
+        match &self.call_settings.quota {
+            Some(quota) => quota.take_bytes(bytes),
+            None => Ok(()),
+        }
+    }
+
+    /// Charges the quota of the capability (if it has any) w/ the bytes of a payload that
+    /// was received through it.
+    pub fn charge_bytes(&self, bytes: usize) {
+        if let Some(quota) = &self.call_settings.quota {
+            quota.charge_bytes(bytes);
+        }
+    }
+
+    /// Counts the length of the `key`, and the size of the `value` (if any) a call of
+    /// `operation` on `target` carried in the capability's metrics (if it has any, see
+    /// `metrics::CallMetrics::record_sizes`).
+    pub fn record_sizes(&self, operation: &str, target: &str, key: usize, value: Option<usize>) {
+        if let Some(metrics) = &self.call_settings.metrics {
+            metrics.record_sizes(operation, target, key, value);
+        }
+    }
+
+    /// How many calls into the capability can be in flight at once (i.e., the size of its
+    /// pool), if it's bounded.
+    pub fn pool_size(&self) -> Option<usize> {
+        self.call_settings
+            .pool
+            .as_ref()
+            .map(|pool| pool.max_connections() as usize)
+    }
+
+    /// Encodes a payload the capability returns to the guest w/ its `compression` (if it has
+    /// any, see `Compression::encode`) — it's done last, so quotas, and metrics count the
+    /// payload's uncompressed bytes.
+    pub fn compressed(&self, payload: Vec<u8>) -> Vec<u8> {
+        match &self.compression {
+            Some(compression) => compression.encode(payload),
+            None => payload,
         }
     }
 }
 /// A state table that is indexed by each resource unique identifier.
-/// The state table stores each resource inner of type WatchState.
+/// The state table stores each resource inner of type WatchState, and the
+/// state capabilities share across the guest instances of an app (e.g., an
+/// in-memory broker), indexed by name.
 #[derive(Default)]
-pub struct StateTable(HashMap<String, WatchState>);
+pub struct StateTable(
+    HashMap<String, WatchState>,
+    HashMap<String, Box<dyn Any + Send + Sync>>,
+);
 
 impl StateTable {
     /// A wrapper function for inserting a key, value pair in the map
@@ -67,6 +341,35 @@ impl StateTable {
         self.0.insert(key, value);
     }
 
+    /// Gets the shared state named `name`, creating it w/ `f` if there's none yet.
+    ///
+    /// The state is expected to be a handle (e.g., wrapping an `Arc`), so that
+    /// all of its clones share it.
+    pub fn shared<T>(&mut self, name: &str, f: impl FnOnce() -> T) -> Result<T>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        let state: &(dyn Any + Send + Sync) = &**self
+            .1
+            .entry(name.to_string())
+            .or_insert_with(|| Box::new(f()));
+        state
+            .downcast_ref::<T>()
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("shared state '{}' is of another type", name))
+    }
+
+    /// Gets the shared state named `name`, if there's any (see `shared`).
+    pub fn find_shared<T>(&self, name: &str) -> Option<T>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        self.1
+            .get(name)
+            .and_then(|state| state.downcast_ref::<T>())
+            .cloned()
+    }
+
     /// A wrapper function for getting a mutable value from the map
     pub fn get_mut(&mut self, key: &str) -> Result<&mut WatchState> {
         let value = self
@@ -84,6 +387,62 @@ impl StateTable {
             .ok_or_else(|| anyhow::anyhow!("failed because key '{}' was not found", &key))?;
         Ok(value)
     }
+
+    /// Gets how to release the backend of the resource `key` identifies (see
+    /// `Watch::releaser`), which stays in the map, so the guest can keep using it (i.e.,
+    /// reacquire the backend).
+    pub fn releaser(&self, key: &str) -> Result<Option<Releaser>> {
+        let value = self
+            .0
+            .get(key)
+            .ok_or_else(|| anyhow::anyhow!("failed because key '{}' was not found", &key))?;
+        Ok(value.releaser())
+    }
+}
+
+/// Releases the backends of all of the resources of `resource_map` (e.g., as the app shuts
+/// down), each once the calls in flight on it are drained, returning how many were open.
+///
+/// They're released at once, and w/o the map locked, so a backend whose calls take its whole
+/// grace period doesn't hold up the others, nor the calls that need the map meanwhile — and one
+/// that fails to be released doesn't stop the others: it fails w/ all of the errors, once
+/// they're all done.
+pub fn release_all(resource_map: &ResourceMap) -> Result<usize> {
+    let releasers = resource_map
+        .lock()
+        .unwrap()
+        .0
+        .iter()
+        .filter_map(|(key, resource)| Some((key.clone(), resource.releaser()?)))
+        .collect::<Vec<_>>();
+    let results = std::thread::scope(|scope| {
+        releasers
+            .into_iter()
+            .map(|(key, release)| (key, scope.spawn(release)))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|(key, releasing)| (key, releasing.join()))
+            .collect::<Vec<_>>()
+    });
+    let mut released = 0;
+    let mut errors = Vec::new();
+    for (key, result) in results {
+        match result {
+            Ok(Ok(true)) => released += 1,
+            Ok(Ok(false)) => {}
+            Ok(Err(e)) => errors.push(format!("'{}': {:#}", key, e)),
+            Err(_) => errors.push(format!("'{}': releasing it panicked", key)),
+        }
+    }
+    if !errors.is_empty() {
+        bail!(
+            "failed to release the backends of {} resources (and released {}): {}",
+            errors.len(),
+            released,
+            errors.join("; ")
+        );
+    }
+    Ok(released)
 }
 
 /// A trait for wit-bindgen resources
@@ -137,6 +496,65 @@ macro_rules! impl_resource {
 }
 pub use impl_resource;
 
+/// Implements `From<anyhow::Error>` for a capability's `Error` (i.e., the `error` variant of
+/// `types.wit`), so the errors guests should be able to tell apart from other failures (i.e.,
+/// calls exceeding a quota, timeouts, and rejected credentials) are reported as such — and
+/// `error_kind::Kind` for it, so the calls that fail w/ it are counted by its variant.
+///
+/// It expects the `TimeoutError` of the capability's bindings to be in scope.
+#[macro_export]
+macro_rules! impl_from_anyhow {
+    ($error:ty) => {
+        impl From<anyhow::Error> for $error {
+            fn from(e: anyhow::Error) -> Self {
+                use slight_runtime::{error_kind::ErrorKind, redact::redact};
+                // errors often quote the urls of backends (and so, their credentials)
+                let described = || redact(&format!("{:#}", e)).into_owned();
+                match ErrorKind::of(&e) {
+                    ErrorKind::RateLimited => Self::RateLimited(described()),
+                    ErrorKind::PayloadTooLarge => Self::PayloadTooLarge(described()),
+                    ErrorKind::OutOfMemory => Self::OutOfMemory(described()),
+                    ErrorKind::DeadlineExceeded => Self::DeadlineExceeded(described()),
+                    ErrorKind::PermissionDenied => Self::PermissionDenied(described()),
+                    ErrorKind::Disabled => Self::Disabled(described()),
+                    ErrorKind::Unsupported => Self::Unsupported(described()),
+                    ErrorKind::NotFound => Self::NotFound(described()),
+                    ErrorKind::Timeout => Self::Timeout(TimeoutError {
+                        description: described(),
+                        retryable: slight_runtime::call::retryable(&e),
+                    }),
+                    ErrorKind::CredentialsExpired => Self::CredentialsExpired(described()),
+                    ErrorKind::CredentialsInvalid => Self::CredentialsInvalid(described()),
+                    ErrorKind::Backend => {
+                        Self::ErrorWithDescription(redact(&e.to_string()).into_owned())
+                    }
+                }
+            }
+        }
+
+        impl slight_runtime::error_kind::Kind for $error {
+            fn kind(&self) -> slight_runtime::error_kind::ErrorKind {
+                use slight_runtime::error_kind::ErrorKind;
+                match self {
+                    Self::ErrorWithDescription(_) => ErrorKind::Backend,
+                    Self::CredentialsExpired(_) => ErrorKind::CredentialsExpired,
+                    Self::CredentialsInvalid(_) => ErrorKind::CredentialsInvalid,
+                    Self::PermissionDenied(_) => ErrorKind::PermissionDenied,
+                    Self::Disabled(_) => ErrorKind::Disabled,
+                    Self::RateLimited(_) => ErrorKind::RateLimited,
+                    Self::PayloadTooLarge(_) => ErrorKind::PayloadTooLarge,
+                    Self::OutOfMemory(_) => ErrorKind::OutOfMemory,
+                    Self::Timeout(_) => ErrorKind::Timeout,
+                    Self::DeadlineExceeded(_) => ErrorKind::DeadlineExceeded,
+                    Self::Unsupported(_) => ErrorKind::Unsupported,
+                    Self::NotFound(_) => ErrorKind::NotFound,
+                }
+            }
+        }
+    };
+}
+pub use impl_from_anyhow;
+
 /// A trait for inner representation of the resource
 pub trait Watch {
     fn watch(&mut self, key: &str, sender: Arc<Mutex<Sender<Event>>>) -> Result<()> {
@@ -146,14 +564,30 @@ pub trait Watch {
             sender
         );
     }
+
+    /// Gets how to release the backend the resource holds (e.g., closing its connections), as
+    /// the guest is done w/ it for now — resources w/o one (e.g., those w/ no connections to
+    /// close) have nothing to do.
+    ///
+    /// The `Releaser` holds its own handle of the backend, so it's released w/o the
+    /// `StateTable` locked (i.e., while the calls in flight on it are drained).
+    fn releaser(&self) -> Option<Releaser> {
+        None
+    }
 }
 
+/// Releases the backend of a resource (see `Watch::releaser`), returning whether there was
+/// anything to release.
+pub type Releaser = Box<dyn FnOnce() -> Result<bool> + Send>;
+
 /// Dynamically dispatch to respective host resource
 pub fn get_table<T, TTable>(cx: &mut Ctx, resource_key: String) -> (&mut T, &mut TTable)
 where
     T: 'static,
     TTable: 'static,
 {
+    // the call is held to the deadline of what the guest is handling (see `Ctx::deadline`)
+    crate::deadline::call(cx.deadline);
     let data = cx
         .data
         .get_mut(&resource_key)
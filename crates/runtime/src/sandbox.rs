@@ -0,0 +1,193 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+use wasi_cap_std_sync::{dir::Dir as WasiDir, WasiCtxBuilder};
+use wasi_common::{
+    dir::{DirCaps, DirEntry},
+    file::FileCaps,
+    WasiCtx,
+};
+use wasmtime_wasi::{ambient_authority, Dir};
+
+/// Where the app directory is mounted in the guest, read-only.
+pub const APP_MOUNT: &str = "/app";
+
+/// Where the scratch directory is mounted in the guest, read-write.
+pub const SCRATCH_MOUNT: &str = "/tmp";
+
+/// `FilesystemSandbox` is the filesystem a sandboxed guest sees — and all of it:
+///     - the `app_dir` at `APP_MOUNT`, read-only: its files, and directories can be opened,
+///     read, listed, and stat'ed, but creating, writing to, truncating, renaming, linking,
+///     removing them, or changing their times fails (w/ `notcapable`),
+///     - the `scratch_dir` at `SCRATCH_MOUNT`, read-write: anything goes within it, and
+///     - nothing else (i.e., not even the `cache` the guest gets otherwise), so writes outside
+///     of the scratch directory fail.
+///
+/// Neither can be escaped (e.g., w/ `..`, or symlinks pointing out of them), as they are
+/// opened as capabilities (see `cap_std::fs::Dir`).
+///
+/// A `temporary` scratch directory (i.e., one made for a run) is removed once the run is over
+/// (see `scratch_guard`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FilesystemSandbox {
+    pub app_dir: PathBuf,
+    pub scratch_dir: PathBuf,
+    pub temporary: bool,
+}
+
+impl FilesystemSandbox {
+    /// A guard that removes the scratch directory when it's dropped (i.e., once the run that
+    /// holds it is over), if it's temporary — scratch directories the slightfile names are
+    /// kept.
+    pub fn scratch_guard(&self) -> Option<ScratchGuard> {
+        self.temporary
+            .then(|| ScratchGuard(self.scratch_dir.clone()))
+    }
+
+    /// The WASI context of a guest that only sees this sandbox.
+    pub fn wasi(&self) -> Result<WasiCtx> {
+        let mut wasi = WasiCtxBuilder::new()
+            .inherit_stdio()
+            .inherit_args()?
+            .build();
+        let app_dir = Dir::open_ambient_dir(&self.app_dir, ambient_authority())
+            .with_context(|| format!("failed to open app dir {}", self.app_dir.display()))?;
+        // what isn't granted to a directory isn't granted to what's opened from it either
+        wasi.table().push(Box::new(DirEntry::new(
+            read_only_dir_caps(),
+            read_only_file_caps(),
+            Some(PathBuf::from(APP_MOUNT)),
+            Box::new(WasiDir::from_cap_std(app_dir)),
+        )))?;
+        let scratch_dir = Dir::open_ambient_dir(&self.scratch_dir, ambient_authority())
+            .with_context(|| {
+                format!("failed to open scratch dir {}", self.scratch_dir.display())
+            })?;
+        wasi.push_preopened_dir(Box::new(WasiDir::from_cap_std(scratch_dir)), SCRATCH_MOUNT)?;
+        Ok(wasi)
+    }
+}
+
+/// `ScratchGuard` removes a temporary scratch directory, and everything in it, when it's
+/// dropped (see `FilesystemSandbox::scratch_guard`).
+#[derive(Debug)]
+pub struct ScratchGuard(PathBuf);
+
+impl Drop for ScratchGuard {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.0) {
+            tracing::warn!("failed to remove scratch dir {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+/// What can be done w/ a read-only directory: looking around, and opening what's in it.
+fn read_only_dir_caps() -> DirCaps {
+    DirCaps::OPEN
+        | DirCaps::READDIR
+        | DirCaps::READLINK
+        | DirCaps::PATH_FILESTAT_GET
+        | DirCaps::FILESTAT_GET
+}
+
+/// What can be done w/ a file of a read-only directory: reading it — w/o `WRITE`, `ALLOCATE`,
+/// or `FILESTAT_SET_SIZE`, files are opened w/o write access at all.
+fn read_only_file_caps() -> FileCaps {
+    FileCaps::READ
+        | FileCaps::SEEK
+        | FileCaps::TELL
+        | FileCaps::ADVISE
+        | FileCaps::FILESTAT_GET
+        | FileCaps::POLL_READWRITE
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+    use tempdir::TempDir;
+    use wasmtime::Module;
+
+    use super::FilesystemSandbox;
+    use crate::Builder;
+
+    /// A guest that creates a file for writing at a path of one of its preopened dirs,
+    /// returning the WASI errno (i.e., 0 if it could).
+    const GUEST: &str = r#"
+        (module
+            (import "wasi_snapshot_preview1" "path_open"
+                (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "written.txt")
+            (data (i32.const 32) "../escaped.txt")
+            (func (export "create") (param $fd i32) (param $path i32) (param $length i32) (result i32)
+                ;; w/ `oflags::creat`, and `rights::fd_write`
+                (call $path_open (local.get $fd) (i32.const 0) (local.get $path) (local.get $length)
+                    (i32.const 1) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 64))))
+    "#;
+
+    /// The preopened dirs of a sandboxed guest, after stdio.
+    const APP_FD: i32 = 3;
+    const SCRATCH_FD: i32 = 4;
+
+    /// The errno of `notcapable`.
+    const NOTCAPABLE: i32 = 76;
+
+    #[test]
+    fn write_isolation_test() -> Result<()> {
+        let dir = TempDir::new("sandbox")?;
+        let app_dir = dir.path().join("app");
+        let scratch_dir = dir.path().join("scratch");
+        std::fs::create_dir_all(&app_dir)?;
+        std::fs::create_dir_all(&scratch_dir)?;
+        let sandbox = FilesystemSandbox {
+            app_dir: app_dir.clone(),
+            scratch_dir: scratch_dir.clone(),
+            temporary: false,
+        };
+
+        let mut builder = Builder::new_default()?;
+        builder.sandbox_filesystem(&sandbox)?;
+        builder.link_wasi()?;
+        let module = Module::new(builder.engine(), GUEST)?;
+        let instance_pre = builder.pre_build(&module)?;
+        let (mut store, instance) = builder.build_from_pre(&instance_pre)?;
+        let create = instance.get_typed_func::<(i32, i32, i32), i32, _>(&mut store, "create")?;
+
+        // the app dir is read-only
+        assert_eq!(create.call(&mut store, (APP_FD, 0, 11))?, NOTCAPABLE);
+        assert!(!app_dir.join("written.txt").exists());
+        // the scratch dir isn't
+        assert_eq!(create.call(&mut store, (SCRATCH_FD, 0, 11))?, 0);
+        assert!(scratch_dir.join("written.txt").exists());
+        // and neither can be escaped
+        assert_ne!(create.call(&mut store, (SCRATCH_FD, 32, 14))?, 0);
+        assert_ne!(create.call(&mut store, (APP_FD, 32, 14))?, 0);
+        assert!(!dir.path().join("escaped.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn scratch_guard_test() -> Result<()> {
+        let dir = TempDir::new("sandbox")?;
+        let sandbox = |scratch_dir: &str, temporary: bool| {
+            let scratch_dir = dir.path().join(scratch_dir);
+            std::fs::create_dir_all(&scratch_dir).unwrap();
+            std::fs::write(scratch_dir.join("file"), b"scratch").unwrap();
+            FilesystemSandbox {
+                app_dir: dir.path().to_path_buf(),
+                scratch_dir,
+                temporary,
+            }
+        };
+
+        let temporary = sandbox("temporary", true);
+        drop(temporary.scratch_guard());
+        assert!(!temporary.scratch_dir.exists());
+
+        // the ones the slightfile names are kept
+        let named = sandbox("named", false);
+        assert!(named.scratch_guard().is_none());
+        assert!(named.scratch_dir.join("file").exists());
+        Ok(())
+    }
+}
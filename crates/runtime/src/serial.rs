@@ -0,0 +1,112 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::resource::StateTable;
+
+/// `Serial` makes the operations on a backend that can't handle concurrent ones (e.g., one that
+/// rewrites a file for each) take turns, so the guest's parallel calls (i.e., from its http,
+/// and events instances, or the host's own, like batches) are queued, rather than racing.
+///
+/// Backends declare whether they require it (i.e., a `SERIALIZED` const on their implementor),
+/// and those that don't get one that serializes nothing, as locking would only slow them down.
+#[derive(Clone, Debug, Default)]
+pub struct Serial(Option<Arc<Mutex<()>>>);
+
+impl Serial {
+    /// The `Serial` of the backend `name` of `capability` (e.g., a queue), if it's `serialized`,
+    /// which is shared by all of the guest instances (see `StateTable::shared`), as their calls
+    /// have to take turns too.
+    pub fn of(
+        resource_map: &Mutex<StateTable>,
+        capability: &str,
+        name: &str,
+        serialized: bool,
+    ) -> Self {
+        if !serialized {
+            return Self::default();
+        }
+        resource_map
+            .lock()
+            .unwrap()
+            .shared(&format!("serial:{}:{}", capability, name), || {
+                Self(Some(Arc::default()))
+            })
+            .unwrap() // note: the name is namespaced, so no other type of state is under it
+    }
+
+    /// Runs `f` once it's the operation's turn, if the backend is serialized, or right away,
+    /// if it isn't.
+    ///
+    /// `f` mustn't run another operation of the same backend, as it'd wait for its own turn.
+    pub fn run<R>(&self, f: impl FnOnce() -> R) -> R {
+        match &self.0 {
+            Some(turn) => {
+                // a panicking operation can't leave the backend in more of a state than a
+                // failing one, so the next one goes ahead
+                let _turn = turn.lock().unwrap_or_else(PoisonError::into_inner);
+                f()
+            }
+            None => f(),
+        }
+    }
+
+    pub fn is_serialized(&self) -> bool {
+        self.0.is_some()
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use super::Serial;
+    use crate::resource::StateTable;
+
+    /// How many operations ran at once, at most, w/ `serial`.
+    fn most_at_once(serial: &Serial) -> usize {
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let operations = (0..4)
+            .map(|_| {
+                let (serial, running, most) = (serial.clone(), running.clone(), most.clone());
+                thread::spawn(move || {
+                    serial.run(|| {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        most.fetch_max(now, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(20));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        for operation in operations {
+            operation.join().unwrap();
+        }
+        most.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn serial_test() {
+        let resource_map = Mutex::new(StateTable::default());
+        let serial = Serial::of(&resource_map, "mq", "orders", true);
+        assert!(serial.is_serialized());
+        assert_eq!(most_at_once(&serial), 1);
+
+        // the instances of a backend share its turns, unlike those of other backends
+        let other_instance = Serial::of(&resource_map, "mq", "orders", true);
+        let (a, b) = (serial.0.unwrap(), other_instance.0.unwrap());
+        assert!(Arc::ptr_eq(&a, &b));
+        let other_queue = Serial::of(&resource_map, "mq", "invoices", true);
+        assert!(!Arc::ptr_eq(&a, &other_queue.0.unwrap()));
+
+        let concurrent = Serial::of(&resource_map, "mq", "orders", false);
+        assert!(!concurrent.is_serialized());
+        assert!(most_at_once(&concurrent) > 1);
+    }
+}
@@ -0,0 +1,238 @@
+use std::{fmt, sync::Arc};
+
+use anyhow::{bail, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// The header (or, w/o headers, the prefix) a message's signature travels in.
+pub const SIGNATURE_HEADER: &str = "slight-signature";
+
+/// The algorithms messages can be signed w/.
+///
+/// Only symmetric ones (i.e., where the same key signs, and verifies) are supported for now —
+/// an asymmetric one would be a variant whose key (see `SigningKey`) is a private key on the
+/// producer side, and a public key on the consumer side.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    HmacSha256,
+}
+
+impl Algorithm {
+    pub fn parse(algorithm: &str) -> Result<Self> {
+        match algorithm {
+            "hmac-sha256" => Ok(Self::HmacSha256),
+            a => bail!(
+                "invalid signing algorithm: '{}' (expected 'hmac-sha256')",
+                a
+            ),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::HmacSha256 => "hmac-sha256",
+        }
+    }
+}
+
+/// Why a message's signature couldn't be verified.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Unverified {
+    /// the message has no signature
+    Unsigned,
+    /// the message was signed w/ another algorithm than the key's
+    OtherAlgorithm(String),
+    /// the signature doesn't match the message (i.e., it, or the message were tampered w/, or
+    /// it was signed w/ another key)
+    Tampered,
+}
+
+impl fmt::Display for Unverified {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsigned => write!(f, "the message isn't signed"),
+            Self::OtherAlgorithm(algorithm) => {
+                write!(f, "the message is signed w/ '{}'", algorithm)
+            }
+            Self::Tampered => write!(f, "the message's signature doesn't match it"),
+        }
+    }
+}
+
+impl std::error::Error for Unverified {}
+
+/// A `SigningKey` signs messages, and verifies their signatures, which look like
+/// `<algorithm>=<hex digest>` (e.g., `hmac-sha256=6a3f…`).
+#[derive(Clone)]
+pub struct SigningKey {
+    algorithm: Algorithm,
+    key: Arc<Vec<u8>>,
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SigningKey({})", self.algorithm.name())
+    }
+}
+
+impl SigningKey {
+    pub fn new(algorithm: Algorithm, key: Vec<u8>) -> Self {
+        Self {
+            algorithm,
+            key: Arc::new(key),
+        }
+    }
+
+    /// Signs a message made of `parts` (e.g., a key, and a value).
+    pub fn sign(&self, parts: &[&[u8]]) -> String {
+        let digest = self.mac(parts).finalize().into_bytes();
+        format!("{}={}", self.algorithm.name(), hex(&digest))
+    }
+
+    /// Verifies the `signature` of a message made of `parts`, in constant time.
+    pub fn verify(&self, parts: &[&[u8]], signature: Option<&[u8]>) -> Result<(), Unverified> {
+        let signature = signature.ok_or(Unverified::Unsigned)?;
+        let (algorithm, digest) = std::str::from_utf8(signature)
+            .ok()
+            .and_then(|signature| signature.split_once('='))
+            .ok_or(Unverified::Tampered)?;
+        if algorithm != self.algorithm.name() {
+            return Err(Unverified::OtherAlgorithm(algorithm.to_string()));
+        }
+        let digest = unhex(digest).ok_or(Unverified::Tampered)?;
+        self.mac(parts)
+            .verify_slice(&digest)
+            .map_err(|_| Unverified::Tampered)
+    }
+
+    fn mac(&self, parts: &[&[u8]]) -> Hmac<Sha256> {
+        let mut mac = match self.algorithm {
+            Algorithm::HmacSha256 => {
+                Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any size")
+            }
+        };
+        for part in parts {
+            // prefixing w/ the length, so that, say, a key of "ab", and a value of "c"
+            // aren't signed the same as a key of "a", and a value of "bc".
+            mac.update(&(part.len() as u64).to_le_bytes());
+            mac.update(part);
+        }
+        mac
+    }
+}
+
+/// `Signing` signs the messages a capability sends, and verifies the ones it receives before
+/// they are handed to the guest.
+#[derive(Clone, Debug)]
+pub struct Signing {
+    key: SigningKey,
+    /// whether messages that fail verification are rejected, rather than only logged
+    enforce: bool,
+}
+
+impl Signing {
+    pub fn new(key: SigningKey, enforce: bool) -> Self {
+        Self { key, enforce }
+    }
+
+    pub fn sign(&self, parts: &[&[u8]]) -> String {
+        self.key.sign(parts)
+    }
+
+    /// Whether a message received through `capability` can be handed to the guest — ones that
+    /// fail verification only are if it isn't enforced.
+    pub fn accepts(&self, capability: &str, parts: &[&[u8]], signature: Option<&[u8]>) -> bool {
+        match self.key.verify(parts, signature) {
+            Ok(()) => true,
+            Err(e) if self.enforce => {
+                tracing::warn!(
+                    "rejecting a message received through '{}': {}",
+                    capability,
+                    e
+                );
+                false
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "accepting a message received through '{}', as signatures aren't enforced: {}",
+                    capability,
+                    e
+                );
+                true
+            }
+        }
+    }
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 == 1 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod unittests {
+    use super::{Algorithm, Signing, SigningKey, Unverified};
+
+    fn key(key: &[u8]) -> SigningKey {
+        SigningKey::new(Algorithm::parse("hmac-sha256").unwrap(), key.to_vec())
+    }
+
+    #[test]
+    fn sign_verify_test() {
+        let key = key(b"secret");
+        let signature = key.sign(&[b"key", b"value"]);
+        assert!(signature.starts_with("hmac-sha256="));
+        assert_eq!(
+            key.verify(&[b"key", b"value"], Some(signature.as_bytes())),
+            Ok(())
+        );
+
+        // the parts are signed separately
+        assert_eq!(
+            key.verify(&[b"ke", b"yvalue"], Some(signature.as_bytes())),
+            Err(Unverified::Tampered)
+        );
+        assert_eq!(
+            self::key(b"other").verify(&[b"key", b"value"], Some(signature.as_bytes())),
+            Err(Unverified::Tampered)
+        );
+        assert_eq!(
+            key.verify(&[b"key", b"value"], None),
+            Err(Unverified::Unsigned)
+        );
+        assert_eq!(
+            key.verify(&[b"key", b"value"], Some(b"ed25519=00")),
+            Err(Unverified::OtherAlgorithm("ed25519".to_string()))
+        );
+        assert_eq!(
+            key.verify(&[b"key", b"value"], Some(b"hmac-sha256=zz")),
+            Err(Unverified::Tampered)
+        );
+    }
+
+    #[test]
+    fn enforce_test() {
+        let signature = key(b"secret").sign(&[b"payload"]);
+        let enforced = Signing::new(key(b"secret"), true);
+        assert!(enforced.accepts("mq", &[b"payload"], Some(signature.as_bytes())));
+        assert!(!enforced.accepts("mq", &[b"tampered"], Some(signature.as_bytes())));
+        assert!(!enforced.accepts("mq", &[b"payload"], None));
+
+        let logged = Signing::new(key(b"secret"), false);
+        assert!(logged.accepts("mq", &[b"tampered"], Some(signature.as_bytes())));
+    }
+
+    #[test]
+    fn invalid_algorithm_test() {
+        assert!(Algorithm::parse("md5").is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use anyhow::{bail, Result};
+
+/// The effective split is logged every this many operations.
+const LOG_EVERY: u64 = 1000;
+
+/// Whether an operation reads, or writes — each is split on its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Read,
+    Write,
+}
+
+/// A `TrafficSplit` routes a percentage of a capability's operations to a `canary` backend,
+/// and the rest to the `primary` one, so a new backend can be validated under real traffic
+/// before fully switching to it.
+///
+/// Operations on a key are routed by a hash of the key, so a key always hits the same
+/// backend (i.e., if reads, and writes are split by the same percentage, a key is read
+/// from where it was written to). Operations w/o a key (e.g., listing keys) are routed
+/// round-robin.
+///
+/// The effective split is counted (see `stats`), and logged every `LOG_EVERY` operations — it's
+/// exported w/ the app's metrics too, if it's tracked by them (see `Metrics::split`).
+#[derive(Clone, Debug)]
+pub struct TrafficSplit {
+    primary: String,
+    canary: String,
+    read_percent: u8,
+    write_percent: u8,
+    counters: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    primary_reads: AtomicU64,
+    canary_reads: AtomicU64,
+    primary_writes: AtomicU64,
+    canary_writes: AtomicU64,
+    unkeyed: AtomicU64,
+}
+
+/// How many operations went to each backend so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SplitStats {
+    pub primary_reads: u64,
+    pub canary_reads: u64,
+    pub primary_writes: u64,
+    pub canary_writes: u64,
+}
+
+impl TrafficSplit {
+    pub fn new(primary: &str, canary: &str, read_percent: u8, write_percent: u8) -> Result<Self> {
+        if read_percent > 100 || write_percent > 100 {
+            bail!(
+                "the percentages of reads, and writes routed to '{}' must be between 0, and 100 (got {}, and {})",
+                canary,
+                read_percent,
+                write_percent
+            );
+        }
+        Ok(Self {
+            primary: primary.to_string(),
+            canary: canary.to_string(),
+            read_percent,
+            write_percent,
+            counters: Arc::new(Counters::default()),
+        })
+    }
+
+    /// Whether an operation (on `key`, if any) goes to the canary backend.
+    pub fn routes_to_canary(&self, operation: Operation, key: Option<&[u8]>) -> bool {
+        let percent = match operation {
+            Operation::Read => self.read_percent,
+            Operation::Write => self.write_percent,
+        };
+        let bucket = match key {
+            Some(key) => fnv1a(key) % 100,
+            None => self.counters.unkeyed.fetch_add(1, Ordering::Relaxed) % 100,
+        };
+        let to_canary = bucket < u64::from(percent);
+
+        let counter = match (operation, to_canary) {
+            (Operation::Read, false) => &self.counters.primary_reads,
+            (Operation::Read, true) => &self.counters.canary_reads,
+            (Operation::Write, false) => &self.counters.primary_writes,
+            (Operation::Write, true) => &self.counters.canary_writes,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        let stats = self.stats();
+        if (stats.reads() + stats.writes()).is_multiple_of(LOG_EVERY) {
+            tracing::info!(
+                "traffic split from '{}' to '{}': {:.1}% of {} reads (target: {}%), and {:.1}% of {} writes (target: {}%)",
+                self.primary,
+                self.canary,
+                percent_of(stats.canary_reads, stats.reads()),
+                stats.reads(),
+                self.read_percent,
+                percent_of(stats.canary_writes, stats.writes()),
+                stats.writes(),
+                self.write_percent
+            );
+        }
+        to_canary
+    }
+
+    pub fn primary(&self) -> &str {
+        &self.primary
+    }
+
+    pub fn canary(&self) -> &str {
+        &self.canary
+    }
+
+    /// Whether `other` splits the same backends by the same percentages (i.e., whatever they
+    /// counted so far).
+    pub fn same_split(&self, other: &Self) -> bool {
+        self.primary == other.primary
+            && self.canary == other.canary
+            && self.read_percent == other.read_percent
+            && self.write_percent == other.write_percent
+    }
+
+    pub fn stats(&self) -> SplitStats {
+        SplitStats {
+            primary_reads: self.counters.primary_reads.load(Ordering::Relaxed),
+            canary_reads: self.counters.canary_reads.load(Ordering::Relaxed),
+            primary_writes: self.counters.primary_writes.load(Ordering::Relaxed),
+            canary_writes: self.counters.canary_writes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl SplitStats {
+    pub fn reads(&self) -> u64 {
+        self.primary_reads + self.canary_reads
+    }
+
+    pub fn writes(&self) -> u64 {
+        self.primary_writes + self.canary_writes
+    }
+}
+
+fn percent_of(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
+
+/// A FNV-1a hash, which (unlike std's `DefaultHasher`) is stable across releases, so keys
+/// keep hitting the same backend across restarts.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+
+    use super::{Operation, TrafficSplit};
+
+    #[test]
+    fn keys_are_routed_consistently() -> Result<()> {
+        let split = TrafficSplit::new("kv.filesystem", "kv.azblob", 30, 30)?;
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            assert_eq!(
+                split.routes_to_canary(Operation::Read, Some(key.as_bytes())),
+                split.routes_to_canary(Operation::Write, Some(key.as_bytes()))
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reads_and_writes_split_independently() -> Result<()> {
+        let split = TrafficSplit::new("kv.filesystem", "kv.azblob", 0, 100)?;
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            assert!(!split.routes_to_canary(Operation::Read, Some(key.as_bytes())));
+            assert!(split.routes_to_canary(Operation::Write, Some(key.as_bytes())));
+        }
+        let stats = split.stats();
+        assert_eq!(stats.primary_reads, 100);
+        assert_eq!(stats.canary_reads, 0);
+        assert_eq!(stats.primary_writes, 0);
+        assert_eq!(stats.canary_writes, 100);
+        Ok(())
+    }
+
+    #[test]
+    fn effective_split_is_close_to_the_target() -> Result<()> {
+        let split = TrafficSplit::new("kv.filesystem", "kv.azblob", 10, 0)?;
+        for i in 0..10_000 {
+            let key = format!("key-{}", i);
+            split.routes_to_canary(Operation::Read, Some(key.as_bytes()));
+        }
+        let canary_reads = split.stats().canary_reads;
+        assert!((800..=1200).contains(&canary_reads), "{}", canary_reads);
+        Ok(())
+    }
+
+    #[test]
+    fn unkeyed_operations_are_round_robin() -> Result<()> {
+        let split = TrafficSplit::new("kv.filesystem", "kv.azblob", 25, 0)?;
+        let to_canary = (0..100)
+            .filter(|_| split.routes_to_canary(Operation::Read, None))
+            .count();
+        assert_eq!(to_canary, 25);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_percentages() {
+        assert!(TrafficSplit::new("kv.filesystem", "kv.azblob", 101, 0).is_err());
+        assert!(TrafficSplit::new("kv.filesystem", "kv.azblob", 0, 101).is_err());
+    }
+}
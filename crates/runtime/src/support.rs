@@ -0,0 +1,138 @@
+use std::fmt;
+
+/// `Unsupported` is the error of calling an operation the backend of a capability doesn't
+/// support (i.e., one of its `UNSUPPORTED_OPERATIONS`), so guests can tell it apart from the
+/// backend failing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unsupported {
+    /// the implementor of the capability (e.g., `kv.azblob`)
+    pub implementor: String,
+    /// the operation, as its function is named (e.g., `set-with-time-to-live`)
+    pub operation: String,
+    /// why the backend can't do it
+    pub reason: String,
+}
+
+impl Unsupported {
+    pub fn new(implementor: &str, operation: &str, reason: &str) -> Self {
+        Self {
+            implementor: implementor.to_string(),
+            operation: operation.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Whether an error was caused by calling an unsupported operation.
+    pub fn is(error: &anyhow::Error) -> bool {
+        error.downcast_ref::<Self>().is_some()
+    }
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} does not support '{}': {}",
+            self.implementor, self.operation, self.reason
+        )
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+/// What a linked capability supports, as advertised to the guest: the operations of its
+/// interface its backend does, w/ those it doesn't (i.e., that fail w/ `Unsupported`) left out.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Support {
+    /// the implementor of the capability (e.g., `kv.azblob`)
+    pub name: String,
+    pub operations: Vec<String>,
+}
+
+impl Support {
+    /// The support of the implementor `name` of the WIT `interface` (i.e., its source), where
+    /// `unsupported` are the operations its backend doesn't support.
+    pub fn of(name: &str, interface: &str, unsupported: &[&str]) -> Self {
+        Self {
+            name: name.to_string(),
+            operations: operations(interface)
+                .into_iter()
+                .filter(|operation| !unsupported.contains(&operation.as_str()))
+                .collect(),
+        }
+    }
+}
+
+/// The operations of a WIT `interface` (i.e., the names of its functions, static, or not, in
+/// the order they're declared in), as guests declare them in their `Manifest`.
+///
+/// Functions of different resources that share a name (e.g., their `open`s) are one operation.
+pub fn operations(interface: &str) -> Vec<String> {
+    let mut operations = Vec::<String>::new();
+    for line in interface.lines() {
+        let line = line.trim();
+        let line = line.strip_prefix("static ").unwrap_or(line);
+        let (name, rest) = match line.split_once(':') {
+            Some((name, rest)) => (name.trim(), rest.trim_start()),
+            None => continue,
+        };
+        let is_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if is_name && rest.starts_with("function") && !operations.iter().any(|o| o == name) {
+            operations.push(name.to_string());
+        }
+    }
+    operations
+}
+
+#[cfg(test)]
+mod unittests {
+    use super::{operations, Support, Unsupported};
+
+    const KV: &str = "
+use { error, payload } from types
+
+// a key-value store
+resource kv {
+	// open a key-value store: function(name: string)
+	static open: function(name: string) -> expected<kv, error>
+	get: function(key: string) -> expected<payload, error>
+	set-with-time-to-live: function(key: string, value: payload, ttl: u64) -> expected<unit, error>
+}
+
+record entry {
+	key: string,
+	function: string,
+}
+
+resource kv-batch {
+	static open: function(name: string) -> expected<kv-batch, error>
+}
+";
+
+    #[test]
+    fn support_test() {
+        assert_eq!(operations(KV), vec!["open", "get", "set-with-time-to-live"]);
+        assert_eq!(
+            Support::of("kv.azblob", KV, &["set-with-time-to-live"]),
+            Support {
+                name: "kv.azblob".to_string(),
+                operations: vec!["open".to_string(), "get".to_string()],
+            }
+        );
+
+        let e = anyhow::Error::new(Unsupported::new(
+            "kv.azblob",
+            "set-with-time-to-live",
+            "blobs don't expire",
+        ));
+        assert!(Unsupported::is(&e));
+        assert!(!Unsupported::is(&anyhow::anyhow!("kv.azblob failed")));
+        assert_eq!(
+            e.to_string(),
+            "kv.azblob does not support 'set-with-time-to-live': blobs don't expire"
+        );
+    }
+}
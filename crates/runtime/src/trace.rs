@@ -0,0 +1,507 @@
+use std::{
+    cell::Cell,
+    collections::HashSet,
+    fmt,
+    fs::File,
+    io::{Seek, SeekFrom, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context as _, Result};
+use serde_json::{json, Map, Value};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Metadata, Subscriber,
+};
+use tracing_subscriber::{
+    filter::{filter_fn, FilterFn, Filtered},
+    layer::{Context, Layer},
+    registry::LookupSpan,
+};
+
+use crate::{call::TRACE_TARGET, redact::redact};
+
+/// What closes the JSON array of events — it is written after every event, and
+/// overwritten by the next one, so the trace is valid JSON even if slight is killed.
+const CLOSING: &[u8] = b"\n]";
+
+/// The next id of a thread, as Chrome traces want numeric thread ids.
+static NEXT_TID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static TID: Cell<u64> = const { Cell::new(0) };
+}
+
+/// `ChromeTraceLayer` writes the spans of capability calls, and guest phases (see
+/// `call::instrument`, and `call::guest_phase`) to a file, as complete events of the
+/// Chrome Trace Event format (i.e., what `chrome://tracing`, and Perfetto load).
+///
+/// Each span is written once it closes, so nested spans come before their parents — as
+/// lasting from when it was created, until it was exited first, on the thread it was created
+/// on (i.e., w/o the time it was kept open as the cause of an event, see `cause`).
+///
+/// The spans of capability calls are only written if the `sampler` samples them, while those
+/// of guest phases always are.
+pub struct ChromeTraceLayer {
+    start: Instant,
+    writer: Mutex<TraceWriter>,
+    sampler: Sampler,
+}
+
+impl ChromeTraceLayer {
+    /// Creates a layer writing to `path`, which only records slight's trace spans, as sampled
+    /// by `sampler`.
+    pub fn create<S>(path: &Path, sampler: Sampler) -> Result<Filtered<Self, FilterFn, S>>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let file = File::create(path)
+            .with_context(|| format!("failed to create trace file '{}'", path.display()))?;
+        let layer = Self {
+            start: Instant::now(),
+            writer: Mutex::new(TraceWriter::new(file)?),
+            sampler,
+        };
+        Ok(layer.with_filter(filter_fn(is_trace as fn(&Metadata<'_>) -> bool)))
+    }
+
+    /// Whether the span of a capability call that started `started`, and just closed, is
+    /// written (see `Sampler`).
+    fn sampled(&self, started: &Started) -> bool {
+        let failed = started.fields.get("failed").and_then(Value::as_bool);
+        self.sampler
+            .sample(failed.unwrap_or_default(), started.duration())
+    }
+
+    /// The Chrome trace event of a span that started `started`, and just closed.
+    fn event(&self, name: &str, started: &Started) -> Value {
+        let mut args = started.fields.clone();
+        let (name, cat) = match name {
+            "call" => (
+                format!(
+                    "{}.{}",
+                    take_str(&mut args, "capability"),
+                    take_str(&mut args, "operation")
+                ),
+                "capability",
+            ),
+            "guest" => (take_str(&mut args, "phase"), "guest"),
+            name => (name.to_string(), "slight"),
+        };
+        json!({
+            "name": name,
+            "cat": cat,
+            "ph": "X",
+            "ts": micros(started.at.duration_since(self.start)),
+            "dur": micros(started.duration()),
+            "pid": std::process::id(),
+            "tid": started.tid,
+            "args": args,
+        })
+    }
+}
+
+impl<S> Layer<S> for ChromeTraceLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Started {
+                at: Instant::now(),
+                exited: None,
+                tid: tid(),
+                thread: thread_name(),
+                fields: fields.0,
+            });
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(started) = span.extensions_mut().get_mut::<Started>() {
+                let mut fields = Fields(std::mem::take(&mut started.fields));
+                values.record(&mut fields);
+                started.fields = fields.0;
+            }
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(started) = span.extensions_mut().get_mut::<Started>() {
+                started.exited.get_or_insert_with(Instant::now);
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(&id) {
+            Some(span) => span,
+            None => return,
+        };
+        let extensions = span.extensions();
+        let started = match extensions.get::<Started>() {
+            Some(started) => started,
+            None => return,
+        };
+        if span.name() == "call" && !self.sampled(started) {
+            return;
+        }
+        let event = self.event(span.name(), started);
+        let res = self
+            .writer
+            .lock()
+            .unwrap()
+            .write(started.tid, &started.thread, &event);
+        if let Err(e) = res {
+            tracing::error!("failed to write trace event: {:#}", e);
+        }
+    }
+}
+
+/// When a span started (and was exited first), the thread it started on, and its fields.
+struct Started {
+    at: Instant,
+    exited: Option<Instant>,
+    tid: u64,
+    thread: String,
+    fields: Map<String, Value>,
+}
+
+impl Started {
+    fn duration(&self) -> Duration {
+        self.exited
+            .unwrap_or_else(Instant::now)
+            .duration_since(self.at)
+    }
+}
+
+/// How the spans of capability calls are sampled (see `Sampler`), where each rate is the
+/// share of calls that are traced, from 0 (none) to 1 (all of them).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SamplingSettings {
+    /// the rate of calls in general
+    pub rate: f64,
+    /// the rate of the calls that fail
+    pub error_rate: f64,
+    /// the rate of the calls that take longer than the `slow_threshold`, if there's one
+    pub slow_rate: f64,
+    pub slow_threshold: Option<Duration>,
+}
+
+impl Default for SamplingSettings {
+    fn default() -> Self {
+        Self {
+            rate: 1.0,
+            error_rate: 1.0,
+            slow_rate: 1.0,
+            slow_threshold: None,
+        }
+    }
+}
+
+impl SamplingSettings {
+    /// Checks the rates are w/in 0, and 1.
+    pub fn validate(self) -> Result<Self> {
+        for (name, rate) in [
+            ("sample_rate", self.rate),
+            ("error_sample_rate", self.error_rate),
+            ("slow_sample_rate", self.slow_rate),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                bail!("invalid {}: {} (expected 0 to 1)", name, rate);
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// `Sampler` decides which spans of capability calls are traced, once they close (as whether
+/// they failed, or were slow, is only known then), so tracing under load is affordable, while
+/// still catching problems: calls that fail, or are slow are sampled at their own rate, which
+/// is never lower than the one of calls in general.
+///
+/// Sampling is deterministic, rather than random: of every `1 / rate` calls of a kind (i.e.,
+/// failed, slow, or neither), the first is traced, so rates hold even over few calls, and
+/// traces of the same run compare.
+///
+/// It's a handle, so it can be configured after the layer is installed (i.e., once the
+/// slightfile is read), and, until then, everything is sampled.
+#[derive(Clone, Debug, Default)]
+pub struct Sampler(Arc<Mutex<SamplerState>>);
+
+#[derive(Debug, Default)]
+struct SamplerState {
+    settings: SamplingSettings,
+    /// how many calls have been seen, by kind (i.e., in general, failed, and slow)
+    seen: [u64; 3],
+}
+
+impl Sampler {
+    pub fn configure(&self, settings: SamplingSettings) {
+        let mut state = self.0.lock().unwrap();
+        *state = SamplerState {
+            settings,
+            seen: [0; 3],
+        };
+    }
+
+    /// Whether a call that took `duration` (and `failed`, or not) is traced.
+    pub fn sample(&self, failed: bool, duration: Duration) -> bool {
+        let mut state = self.0.lock().unwrap();
+        let settings = state.settings;
+        let slow = settings
+            .slow_threshold
+            .is_some_and(|threshold| duration > threshold);
+        let (kind, rate) = if failed {
+            (1, settings.error_rate)
+        } else if slow {
+            (2, settings.slow_rate)
+        } else {
+            (0, settings.rate)
+        };
+        let rate = rate.max(settings.rate);
+        let seen = &mut state.seen[kind];
+        let sampled = (*seen as f64 * rate).ceil() < ((*seen + 1) as f64 * rate).ceil();
+        *seen += 1;
+        sampled
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Fields(pub(crate) Map<String, Value>);
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+}
+
+/// Writes events to a trace file, keeping it a valid JSON array after each of them.
+struct TraceWriter {
+    file: File,
+    empty: bool,
+    /// the threads whose name was written already (as a metadata event)
+    named_threads: HashSet<u64>,
+}
+
+impl TraceWriter {
+    fn new(mut file: File) -> Result<Self> {
+        file.write_all(b"[")?;
+        file.write_all(CLOSING)?;
+        file.seek(SeekFrom::Current(-(CLOSING.len() as i64)))?;
+        Ok(Self {
+            file,
+            empty: true,
+            named_threads: HashSet::new(),
+        })
+    }
+
+    fn write(&mut self, tid: u64, thread: &str, event: &Value) -> Result<()> {
+        if self.named_threads.insert(tid) {
+            self.append(&json!({
+                "name": "thread_name",
+                "ph": "M",
+                "pid": std::process::id(),
+                "tid": tid,
+                "args": {"name": thread},
+            }))?;
+        }
+        self.append(event)
+    }
+
+    fn append(&mut self, event: &Value) -> Result<()> {
+        let mut bytes = if self.empty {
+            b"\n".to_vec()
+        } else {
+            b",\n".to_vec()
+        };
+        bytes.extend_from_slice(redact(&serde_json::to_string(event)?).as_bytes());
+        bytes.extend_from_slice(CLOSING);
+        self.file.write_all(&bytes)?;
+        self.file.seek(SeekFrom::Current(-(CLOSING.len() as i64)))?;
+        self.empty = false;
+        Ok(())
+    }
+}
+
+/// Whether a span (or event) is one of slight's trace spans.
+fn is_trace(metadata: &Metadata<'_>) -> bool {
+    metadata.target() == TRACE_TARGET
+}
+
+/// The id of the current thread.
+fn tid() -> u64 {
+    TID.with(|tid| {
+        if tid.get() == 0 {
+            tid.set(NEXT_TID.fetch_add(1, Ordering::Relaxed));
+        }
+        tid.get()
+    })
+}
+
+/// The name of the current thread.
+fn thread_name() -> String {
+    std::thread::current()
+        .name()
+        .map_or_else(|| format!("thread {}", tid()), str::to_string)
+}
+
+fn micros(duration: std::time::Duration) -> f64 {
+    duration.as_nanos() as f64 / 1000.0
+}
+
+fn take_str(fields: &mut Map<String, Value>, name: &str) -> String {
+    match fields.remove(name) {
+        Some(Value::String(s)) => s,
+        Some(value) => value.to_string(),
+        None => "?".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::{fs, time::Duration};
+
+    use anyhow::Result;
+    use tempdir::TempDir;
+    use tracing_subscriber::prelude::*;
+
+    use super::{ChromeTraceLayer, Sampler, SamplingSettings};
+    use crate::call::{guest_phase, instrument, CallSettings};
+
+    #[test]
+    fn writes_valid_trace_test() -> Result<()> {
+        let dir = TempDir::new("trace")?;
+        let path = dir.path().join("trace.json");
+        let subscriber = tracing_subscriber::registry()
+            .with(ChromeTraceLayer::create(&path, Sampler::default())?);
+
+        tracing::subscriber::with_default(subscriber, || {
+            // nothing was recorded yet, but the trace is already valid
+            let trace = serde_json::from_slice::<serde_json::Value>(&fs::read(&path).unwrap());
+            assert_eq!(trace.unwrap(), serde_json::json!([]));
+
+            let _phase = guest_phase("start");
+            let res: Result<()> =
+                instrument(&CallSettings::default(), "kv", "get", "my-key", || {
+                    // spans of other targets aren't recorded
+                    let _span = tracing::info_span!("unrelated").entered();
+                    Ok(())
+                });
+            assert!(res.is_ok());
+        });
+
+        let trace = serde_json::from_slice::<serde_json::Value>(&fs::read(&path)?)?;
+        let events = trace.as_array().unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["ph"], "M");
+        assert_eq!(events[1]["name"], "kv.get");
+        assert_eq!(events[1]["cat"], "capability");
+        assert_eq!(events[1]["ph"], "X");
+        assert_eq!(events[1]["args"]["on"], "my-key");
+        assert_eq!(events[2]["name"], "start");
+        assert_eq!(events[2]["cat"], "guest");
+        // the call happened within the phase
+        assert!(events[2]["ts"].as_f64() <= events[1]["ts"].as_f64());
+        assert!(events[2]["dur"].as_f64() >= events[1]["dur"].as_f64());
+        Ok(())
+    }
+
+    #[test]
+    fn sampling_test() -> Result<()> {
+        let sampler = Sampler::default();
+        sampler.configure(SamplingSettings {
+            rate: 0.25,
+            error_rate: 1.0,
+            slow_rate: 0.5,
+            slow_threshold: Some(Duration::from_millis(100)),
+        });
+        let sampled = |failed, millis, calls| {
+            (0..calls)
+                .filter(|_| sampler.sample(failed, Duration::from_millis(millis)))
+                .count()
+        };
+        // the first of every 4 calls is traced, and each kind is counted on its own
+        assert_eq!(sampled(false, 1, 8), 2);
+        assert_eq!(sampled(true, 1, 8), 8);
+        assert_eq!(sampled(false, 500, 8), 4);
+
+        // failed calls are never sampled less than calls in general
+        sampler.configure(SamplingSettings {
+            rate: 0.5,
+            error_rate: 0.0,
+            ..Default::default()
+        });
+        assert_eq!(sampled(true, 1, 4), 2);
+        assert!(SamplingSettings {
+            rate: 1.5,
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+
+        // only the sampled calls are written, while guest phases always are
+        let dir = TempDir::new("trace")?;
+        let path = dir.path().join("trace.json");
+        let sampler = Sampler::default();
+        sampler.configure(SamplingSettings {
+            rate: 0.0,
+            ..Default::default()
+        });
+        let subscriber =
+            tracing_subscriber::registry().with(ChromeTraceLayer::create(&path, sampler)?);
+        tracing::subscriber::with_default(subscriber, || {
+            let _phase = guest_phase("start");
+            for key in ["a", "b"] {
+                let _: Result<()> =
+                    instrument(&CallSettings::default(), "kv", "get", key, || Ok(()));
+            }
+            let _: Result<()> = instrument(&CallSettings::default(), "kv", "get", "c", || {
+                anyhow::bail!("not found")
+            });
+        });
+        let trace = serde_json::from_slice::<serde_json::Value>(&fs::read(&path)?)?;
+        let names = trace
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|event| event["ph"] == "X")
+            .map(|event| (event["name"].clone(), event["args"]["on"].clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                ("kv.get".into(), "c".into()),
+                ("start".into(), serde_json::Value::Null)
+            ]
+        );
+        Ok(())
+    }
+}
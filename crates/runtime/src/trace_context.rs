@@ -0,0 +1,248 @@
+use std::cell::RefCell;
+
+use crate::signing::{hex, unhex};
+
+/// The header of the W3C trace context that identifies the trace, and the span of the caller.
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// The header of the W3C trace context that carries the vendors' own state of the trace.
+pub const TRACESTATE_HEADER: &str = "tracestate";
+
+/// The version of `traceparent` this host writes.
+const VERSION: &str = "00";
+
+/// How long a `tracestate` is propagated up to — the spec only requires 512 characters to
+/// be, so longer ones are dropped, rather than truncated in the middle of an entry.
+const MAX_TRACESTATE_LEN: usize = 512;
+
+/// The flag of `traceparent` telling the callee the caller may have recorded the trace.
+const SAMPLED: u8 = 0x01;
+
+/// `TraceContext` is the W3C trace context (see https://www.w3.org/TR/trace-context/) of what
+/// the guest is handling (e.g., an http request), which the outbound requests capabilities
+/// make while handling it carry (see `Propagation::headers`), so the traces of the services
+/// they call join the caller's trace.
+///
+/// slight doesn't report spans of its own to the trace, so it passes the context on as it
+/// received it (i.e., the outbound requests are children of the caller's span), and starts a
+/// new trace for requests w/o one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    /// the id of the caller's span
+    pub parent_id: [u8; 8],
+    pub flags: u8,
+    /// the `tracestate`, if there was one
+    pub state: Option<String>,
+}
+
+impl TraceContext {
+    /// Parses the context of a `traceparent`, and `tracestate` — a `traceparent` that's
+    /// malformed is `None` (i.e., as if there was none), as the spec asks.
+    pub fn parse(traceparent: &str, tracestate: Option<&str>) -> Option<Self> {
+        let traceparent = traceparent.trim();
+        let version = traceparent.get(..2)?;
+        // later versions are parsed as this one, as they may only append fields to it
+        let valid_len = match version {
+            VERSION => traceparent.len() == 55,
+            "ff" => false,
+            _ => traceparent.len() == 55 || traceparent.as_bytes().get(55) == Some(&b'-'),
+        };
+        if !valid_len || !is_lower_hex(version) {
+            return None;
+        }
+        let mut fields = traceparent.get(3..55)?.split('-');
+        let (trace_id, parent_id, flags) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(trace_id), Some(parent_id), Some(flags)) => (trace_id, parent_id, flags),
+            _ => return None,
+        };
+        if flags.len() != 2 || !is_lower_hex(flags) {
+            return None;
+        }
+        Some(Self {
+            trace_id: id(trace_id)?,
+            parent_id: id(parent_id)?,
+            flags: u8::from_str_radix(flags, 16).ok()?,
+            state: tracestate
+                .map(str::trim)
+                .filter(|state| !state.is_empty() && state.len() <= MAX_TRACESTATE_LEN)
+                .map(str::to_string),
+        })
+    }
+
+    /// Starts a new trace (e.g., for a request whose client didn't send a context), which is
+    /// sampled, so the services it calls record it.
+    pub fn start() -> Self {
+        Self {
+            trace_id: nonzero(),
+            parent_id: nonzero(),
+            flags: SAMPLED,
+            state: None,
+        }
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!(
+            "{}-{}-{}-{:02x}",
+            VERSION,
+            hex(&self.trace_id),
+            hex(&self.parent_id),
+            self.flags
+        )
+    }
+
+    /// Sets the context of what the guest is handling on this thread to `context` (or to none,
+    /// if `None`) until the returned guard is dropped, restoring the one it replaced.
+    pub fn enter(context: Option<Self>) -> Entered {
+        Entered(TRACE_CONTEXT.with(|current| current.replace(context)))
+    }
+}
+
+thread_local! {
+    /// The trace context of what the guest is handling on this thread (if any) — capability
+    /// calls are made on the thread the guest runs on, so they all inherit it.
+    static TRACE_CONTEXT: RefCell<Option<TraceContext>> = RefCell::new(None);
+}
+
+/// Restores the trace context it replaced when it's dropped (see `TraceContext::enter`).
+pub struct Entered(Option<TraceContext>);
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        TRACE_CONTEXT.with(|current| *current.borrow_mut() = self.0.take());
+    }
+}
+
+/// The trace context of what the guest is handling on this thread, if it has one.
+pub fn current() -> Option<TraceContext> {
+    TRACE_CONTEXT.with(|current| current.borrow().clone())
+}
+
+/// Whether the outbound requests of a capability carry the trace context (see `headers`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Propagation {
+    /// inject the context into the outbound requests (the default)
+    Inject,
+    /// leave them as they are (e.g., for services that reject unknown headers)
+    Off,
+}
+
+impl Default for Propagation {
+    fn default() -> Self {
+        Self::Inject
+    }
+}
+
+impl Propagation {
+    pub fn new(enabled: bool) -> Self {
+        if enabled {
+            Self::Inject
+        } else {
+            Self::Off
+        }
+    }
+
+    /// The headers an outbound request made on this thread is to carry: the `traceparent`,
+    /// and `tracestate` of the current trace context — none, if there's no context, or if
+    /// propagation is off.
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let context = match (self, current()) {
+            (Self::Inject, Some(context)) => context,
+            _ => return Vec::new(),
+        };
+        let mut headers = vec![(TRACEPARENT_HEADER, context.traceparent())];
+        if let Some(state) = context.state {
+            headers.push((TRACESTATE_HEADER, state));
+        }
+        headers
+    }
+}
+
+fn is_lower_hex(s: &str) -> bool {
+    s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Parses an id of `N` bytes, which can't be all zeros (i.e., the invalid id).
+fn id<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != 2 * N || !is_lower_hex(hex) {
+        return None;
+    }
+    let id: [u8; N] = unhex(hex)?.try_into().ok()?;
+    if id.iter().all(|b| *b == 0) {
+        return None;
+    }
+    Some(id)
+}
+
+/// A random id that isn't all zeros (i.e., the invalid id).
+fn nonzero<const N: usize>() -> [u8; N] {
+    loop {
+        let id = [(); N].map(|_| rand::random::<u8>());
+        if id.iter().any(|b| *b != 0) {
+            return id;
+        }
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use super::{current, Propagation, TraceContext, TRACEPARENT_HEADER, TRACESTATE_HEADER};
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn parse_test() {
+        let context = TraceContext::parse(TRACEPARENT, Some("congo=t61rcWkgMzE")).unwrap();
+        assert_eq!(context.flags, 0x01);
+        assert_eq!(context.traceparent(), TRACEPARENT);
+        assert_eq!(context.state.as_deref(), Some("congo=t61rcWkgMzE"));
+
+        // later versions may append fields
+        let later = format!("cc{}-what-the-future-holds", &TRACEPARENT[2..]);
+        assert_eq!(
+            TraceContext::parse(&later, None).unwrap().traceparent(),
+            TRACEPARENT
+        );
+
+        // malformed ones are as if there was none
+        for malformed in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        ] {
+            assert_eq!(TraceContext::parse(malformed, None), None, "{}", malformed);
+        }
+
+        let started = TraceContext::start();
+        assert_eq!(
+            TraceContext::parse(&started.traceparent(), None),
+            Some(started)
+        );
+    }
+
+    #[test]
+    fn propagation_test() {
+        assert!(Propagation::Inject.headers().is_empty());
+        {
+            let _entered = TraceContext::enter(TraceContext::parse(TRACEPARENT, Some("a=b")));
+            assert_eq!(
+                Propagation::Inject.headers(),
+                vec![
+                    (TRACEPARENT_HEADER, TRACEPARENT.to_string()),
+                    (TRACESTATE_HEADER, "a=b".to_string())
+                ]
+            );
+            assert!(Propagation::Off.headers().is_empty());
+            {
+                let _none = TraceContext::enter(None);
+                assert_eq!(current(), None);
+            }
+            assert!(current().is_some());
+        }
+        assert_eq!(current(), None);
+    }
+}
@@ -0,0 +1,268 @@
+mod store;
+
+/// The `SCHEME_NAME` defines the name under which a resource is
+/// identifiable by in a `ResourceMap`.
+const SCHEME_NAME: &str = "timers";
+/// The operations guests can retry if they time out (see `BasicState::with_idempotent_operations`)
+/// — `set` is, as setting a timer again only re-arms it.
+const IDEMPOTENT_OPERATIONS: &[&str] = &["set"];
+/// The operations that only read, which audits of writes only leave out (see
+/// `BasicState::with_read_operations`) — none, as even `next` fires a timer.
+const READ_OPERATIONS: &[&str] = &[];
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use uuid::Uuid;
+
+use slight_runtime::{
+    impl_resource,
+    resource::{BasicState, Releaser},
+};
+use store::{Fired, TimerStore};
+pub use store::{Misfire, MisfirePolicy, DEFAULT_MISFIRE_GRACE};
+
+/// It is mandatory to `use <interface>::*` due to `impl_resource!`.
+/// That is because `impl_resource!` accesses the `crate`'s
+/// `add_to_linker`, and not the `<interface>::add_to_linker` directly.
+use timers::*;
+wit_bindgen_wasmtime::export!("../../wit/timers.wit");
+wit_error_rs::impl_error!(timers::Error);
+slight_runtime::impl_from_anyhow!(timers::Error);
+
+/// How often `next` looks for a due timer while waiting for one.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The `Timers` structure is what will implement the `timers::Timers` trait
+/// coming from the generated code of off `timers.wit`.
+///
+/// It maintains a `host_state`.
+pub struct Timers {
+    host_state: TimersState,
+}
+
+impl_resource!(
+    Timers,
+    timers::TimersTables<Timers>,
+    TimersState,
+    SCHEME_NAME.to_string()
+);
+
+/// The settings of the timers capability, from a user's `slightfile`.
+///
+/// It holds:
+///     - a `store` `String` — the kv implementor timers are kept in (i.e., its `timers.store`),
+///     - a `lock` — the lockd implementor hosts lock timers in while they fire them (i.e., its
+///     `timers.lock`), if any,
+///     - an `election` — the election implementor hosts campaign in to fire leader-only timers
+///     (i.e., its `timers.election`), if any, and
+///     - a `misfire` — how timers that fire late are handled.
+#[derive(Clone, Debug)]
+pub struct TimersSettings {
+    pub store: String,
+    pub lock: Option<String>,
+    pub election: Option<String>,
+    pub misfire: Misfire,
+}
+
+/// This is the type of the `host_state` property from our `Timers` structure.
+///
+/// It holds:
+///     - the `settings` of the capability (see `TimersSettings`), and
+///     - the `slight_state` (of type `BasicState`) that contains common
+///     things received from the slight binary (i.e., the `resource_map`,
+///     the `config_type`, and the `config_toml_file_path`).
+pub struct TimersState {
+    settings: TimersSettings,
+    slight_state: BasicState,
+}
+
+impl TimersState {
+    pub fn new(settings: TimersSettings, slight_state: BasicState) -> Self {
+        Self {
+            settings,
+            slight_state: slight_state
+                .with_idempotent_operations(IDEMPOTENT_OPERATIONS)
+                .with_read_operations(READ_OPERATIONS),
+        }
+    }
+}
+
+impl From<Fired> for FiredTimer {
+    fn from(fired: Fired) -> Self {
+        Self {
+            name: fired.name,
+            payload: fired.payload,
+            due_at: fired.due_at,
+            late_by_secs: fired.late_by_secs,
+            missed: fired.missed,
+        }
+    }
+}
+
+impl timers::Timers for Timers {
+    type Timers = TimersInner;
+
+    fn timers_open(&mut self, name: &str) -> Result<Self::Timers, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
+        // populate our inner timers object w/ the state received from `slight`
+        // (i.e., what kv, and lockd implementors timers are kept in), and the name
+        // of the set of timers.
+        let inner = Self::Timers::new(
+            &self.host_state.settings,
+            &self.host_state.slight_state,
+            name,
+        )?;
+        inner.log_rearmed();
+
+        self.host_state
+            .slight_state
+            .resource_map
+            .lock()
+            .unwrap()
+            .set(inner.resource_descriptor.clone(), Box::new(inner.clone()));
+
+        Ok(inner)
+    }
+
+    fn timers_set(
+        &mut self,
+        self_: &Self::Timers,
+        timer_name: &str,
+        payload: PayloadParam<'_>,
+        options: TimerOptions,
+    ) -> Result<(), Error> {
+        Ok(self
+            .host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "set", timer_name, || {
+                self_.store.set(
+                    timer_name,
+                    payload,
+                    Duration::from_secs(options.delay_in_secs),
+                    Duration::from_secs(options.interval_in_secs),
+                    options.leader_only,
+                    now_secs(),
+                )
+            })?)
+    }
+
+    fn timers_cancel(&mut self, self_: &Self::Timers, timer_name: &str) -> Result<(), Error> {
+        Ok(self
+            .host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "cancel", timer_name, || {
+                self_.store.cancel(timer_name)
+            })?)
+    }
+
+    fn timers_next(
+        &mut self,
+        self_: &Self::Timers,
+        timeout_in_secs: u64,
+    ) -> Result<Option<FiredTimer>, Error> {
+        let misfire = self.host_state.settings.misfire;
+        Ok(self
+            .host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "next", &self_.name, || {
+                wait_for_next(&self_.store, &misfire, timeout_in_secs)
+            })?
+            .map(FiredTimer::from))
+    }
+}
+
+/// Waits (for up to `timeout_in_secs`) for a timer to fire, and fires it — the ones fired
+/// before were handled, as the guest asks for the next one.
+fn wait_for_next(
+    store: &TimerStore,
+    misfire: &Misfire,
+    timeout_in_secs: u64,
+) -> Result<Option<Fired>> {
+    let deadline = Instant::now() + Duration::from_secs(timeout_in_secs);
+    loop {
+        if let Some(fired) = store.fire_next(now_secs(), misfire)? {
+            return Ok(Some(fired));
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+        std::thread::sleep(remaining.min(POLL_INTERVAL));
+    }
+}
+
+/// The current time, in seconds since the unix epoch.
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// This is the type of the associated type coming from the `timers::Timers` trait
+/// implementation.
+///
+/// It holds:
+///     - the `store` timers are kept in,
+///     - the `name` of the set of timers, and
+///     - a `resource_descriptor` (i.e., an UUID that uniquely identifies
+///     resource's instance).
+///
+/// It must `derive`:
+///     - `Debug` due to a constraint on the associated type.
+///     - `Clone` because the `ResourceMap` it will be added onto,
+///     must own its data.
+///
+/// It must be public because the implementation of `timers::Timers` cannot leak
+/// a private type.
+#[derive(Debug, Clone)]
+pub struct TimersInner {
+    store: TimerStore,
+    name: String,
+    resource_descriptor: String,
+}
+
+impl TimersInner {
+    fn new(settings: &TimersSettings, slight_state: &BasicState, name: &str) -> Result<Self> {
+        Ok(Self {
+            store: TimerStore::open(
+                &settings.store,
+                settings.lock.as_deref(),
+                settings.election.as_deref(),
+                slight_state,
+                name,
+            )?,
+            name: name.to_string(),
+            resource_descriptor: Uuid::new_v4().to_string(),
+        })
+    }
+
+    /// Logs the timers that were armed before the host started (e.g., before a restart), which
+    /// fire as they would have (or, if they're overdue, as per the misfire policy).
+    fn log_rearmed(&self) {
+        match self.store.armed() {
+            Ok(armed) if !armed.is_empty() => {
+                let now = now_secs();
+                let overdue = armed.iter().filter(|timer| timer.due_at <= now).count();
+                tracing::info!(
+                    "re-armed {} timers of '{}' ({} of which are overdue)",
+                    armed.len(),
+                    self.name,
+                    overdue
+                );
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("failed to list the timers of '{}': {}", self.name, e),
+        }
+    }
+}
+
+impl slight_runtime::resource::Watch for TimersInner {
+    /// The timers handed to the guest are handled once it's done (i.e., its resources are
+    /// released as it exits) — a guest that crashed never gets here, so they're delivered again.
+    fn releaser(&self) -> Option<Releaser> {
+        let store = self.store.clone();
+        Some(Box::new(move || Ok(store.handled()? > 0)))
+    }
+}
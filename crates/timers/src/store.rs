@@ -0,0 +1,687 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use slight_election::HostElection;
+use slight_kv::HostKv;
+use slight_lockd::HostLockd;
+use slight_runtime::resource::BasicState;
+use uuid::Uuid;
+
+/// How late a timer can fire before it counts as misfired, unless the slightfile says otherwise.
+pub const DEFAULT_MISFIRE_GRACE: Duration = Duration::from_secs(60);
+
+/// How long a host holds the lock of a timer it's firing at most (i.e., if it dies meanwhile).
+const LOCK_TTL_SECS: i64 = 30;
+
+/// How long the guest has to handle a timer it got (i.e., until it calls `next` again, or its
+/// timers are released) before it's delivered again (e.g., as its host died meanwhile).
+const DELIVERY_TIMEOUT_SECS: u64 = 300;
+
+/// The key of the names of the timers that are armed.
+const ARMED: &[u8] = b"armed";
+
+/// What happens to a timer that fires later than the misfire grace allows (e.g., as no host
+/// was up when it was due).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MisfirePolicy {
+    /// fire it anyway, as soon as possible
+    Fire,
+    /// don't fire it (a recurring timer still fires at its next due time)
+    Skip,
+}
+
+impl MisfirePolicy {
+    pub fn parse(policy: &str) -> Result<Self> {
+        match policy {
+            "fire" => Ok(Self::Fire),
+            "skip" => Ok(Self::Skip),
+            _ => bail!(
+                "invalid misfire policy: '{}' (expected 'fire', or 'skip')",
+                policy
+            ),
+        }
+    }
+}
+
+/// How misfired timers are handled: a timer misfired if it fires more than `grace` after it
+/// was due, and then, it's handled as per `policy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Misfire {
+    pub policy: MisfirePolicy,
+    pub grace: Duration,
+}
+
+impl Default for Misfire {
+    fn default() -> Self {
+        Self {
+            policy: MisfirePolicy::Fire,
+            grace: DEFAULT_MISFIRE_GRACE,
+        }
+    }
+}
+
+/// The state of a timer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Armed,
+    /// handed to a guest, which hasn't handled it yet (see `TimerRecord::claim`)
+    Firing,
+    Fired,
+    Cancelled,
+}
+
+/// A timer, as kept in the kv store.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimerRecord {
+    pub name: String,
+    pub payload: Vec<u8>,
+    pub state: State,
+    /// when the timer is due, in secs since the unix epoch
+    pub due_at: u64,
+    /// how often a recurring timer fires (or 0, if it fires once)
+    pub interval_in_secs: u64,
+    /// whether only the host that leads the election of the timers fires it
+    #[serde(default)]
+    pub leader_only: bool,
+    /// which host handed a `Firing` timer to its guest, and until when
+    #[serde(default)]
+    pub claim: Option<Claim>,
+}
+
+/// The claim of a host on a timer it handed to its guest: no other host fires the timer until
+/// the claim expires, and, once the guest handled it, the host re-arms it (or marks it as fired).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claim {
+    pub host: String,
+    /// when the timer is delivered again, if the guest didn't handle it by then, in secs since
+    /// the unix epoch
+    pub until: u64,
+}
+
+/// A timer that fired, as handed to the guest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fired {
+    pub name: String,
+    pub payload: Vec<u8>,
+    pub due_at: u64,
+    pub late_by_secs: u64,
+    /// how many earlier firings of a recurring timer were missed
+    pub missed: u32,
+}
+
+/// `TimerStore` keeps timers in a kv store, so they survive restarts (i.e., a timer that's
+/// due while no host is up fires once one is), and can be fired by many hosts sharing the store.
+///
+/// Each timer is kept under its name, and the names of the armed ones are kept in an index.
+/// A timer is fired in two compare-and-swaps: one claims it for the host (i.e., so only one
+/// host ever hands each of its due times to a guest), and, once the guest handled it (i.e., it
+/// asks for the next timer, or its timers are released), the other re-arms it, if it's
+/// recurring, or marks it as fired, if it isn't — so a timer whose guest crashed before it
+/// handled it is delivered again once the claim expires, rather than being lost. As
+/// `kv.filesystem` only swaps atomically within one host, hosts can also take the timer's lock
+/// in a lockd implementor while they claim it.
+///
+/// A timer's name is only removed from the index once it's re-checked to be fired (or
+/// cancelled) after the removal, so a timer re-armed meanwhile (i.e., w/ `set`) is never lost.
+///
+/// Leader-only timers are only fired by the host that leads the election of the set of timers
+/// (i.e., `slight-timers/<name>`), which the store campaigns in if it has an election
+/// implementor — once the leadership changes, the new leader fires them from their next due
+/// time on (or, if it took over late, as per the misfire policy).
+#[derive(Debug, Clone)]
+pub struct TimerStore {
+    kv: HostKv,
+    lockd: Option<HostLockd>,
+    election: Option<HostElection>,
+    name: String,
+    /// the id claims of this store are made under
+    host: String,
+    /// the names of the timers handed to the guest that it hasn't handled yet
+    handling: Arc<Mutex<Vec<String>>>,
+}
+
+impl TimerStore {
+    pub fn open(
+        timers_store: &str,
+        timers_lock: Option<&str>,
+        timers_election: Option<&str>,
+        slight_state: &BasicState,
+        name: &str,
+    ) -> Result<Self> {
+        let election = timers_election
+            .map(|election| {
+                HostElection::open(
+                    election,
+                    slight_state,
+                    &format!("slight-timers/{}", name),
+                    Uuid::new_v4().to_string().as_bytes(),
+                )
+            })
+            .transpose()
+            .with_context(|| format!("failed to campaign for the timers of '{}'", name))?;
+        Ok(Self {
+            kv: HostKv::open(
+                timers_store,
+                slight_state,
+                &format!("slight-timers-{}", name),
+            )?,
+            lockd: timers_lock
+                .map(|lockd| HostLockd::open(lockd, slight_state))
+                .transpose()?,
+            election,
+            name: name.to_string(),
+            host: Uuid::new_v4().to_string(),
+            handling: Arc::default(),
+        })
+    }
+
+    /// Arms the timer `name`, replacing the one of that name, if there is — a `leader_only`
+    /// one needs the store to have an election implementor.
+    pub fn set(
+        &self,
+        name: &str,
+        payload: &[u8],
+        delay: Duration,
+        interval: Duration,
+        leader_only: bool,
+        now: u64,
+    ) -> Result<()> {
+        if leader_only && self.election.is_none() {
+            bail!(
+                "timer '{}' is leader-only, but the timers capability has no `timers.election`",
+                name
+            );
+        }
+        let timer = TimerRecord {
+            name: name.to_string(),
+            payload: payload.to_vec(),
+            state: State::Armed,
+            due_at: now + delay.as_secs(),
+            interval_in_secs: interval.as_secs(),
+            leader_only,
+            claim: None,
+        };
+        self.kv
+            .set(&timer_key(name), &serde_json::to_vec(&timer)?)?;
+        self.update_index(|names| {
+            if !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        })?;
+        tracing::info!("armed timer '{}', due at {}", name, timer.due_at);
+        Ok(())
+    }
+
+    pub fn cancel(&self, name: &str) -> Result<()> {
+        loop {
+            let (raw, timer) = match self.load(name)? {
+                Some((raw, timer)) if is_pending(&timer) => (raw, timer),
+                _ => bail!("timer '{}' isn't armed", name),
+            };
+            let cancelled = TimerRecord {
+                state: State::Cancelled,
+                claim: None,
+                ..timer
+            };
+            // it may have fired, or been re-armed in the meantime
+            if self.swap(&raw, &cancelled)? {
+                break;
+            }
+        }
+        self.unindex(name)?;
+        tracing::info!("cancelled timer '{}'", name);
+        Ok(())
+    }
+
+    /// The timers that are armed (or handed to a guest that hasn't handled them yet), e.g. to
+    /// tell what's re-armed once a host starts.
+    pub fn armed(&self) -> Result<Vec<TimerRecord>> {
+        let mut timers = Vec::new();
+        for name in self.index()? {
+            if let Some((_, timer)) = self.load(&name)? {
+                if is_pending(&timer) {
+                    timers.push(timer);
+                }
+            }
+        }
+        Ok(timers)
+    }
+
+    /// Fires the first due timer that has to be handed to the guest, if there's any, skipping
+    /// the leader-only ones unless the host leads — the timers handed to the guest before are
+    /// handled, as it asks for the next one.
+    ///
+    /// Misfired timers the `misfire` policy skips are re-armed (or marked as fired) along the way.
+    pub fn fire_next(&self, now: u64, misfire: &Misfire) -> Result<Option<Fired>> {
+        self.handled()?;
+        let leads = self
+            .election
+            .as_ref()
+            .map_or(false, HostElection::is_leader);
+        for name in self.index()? {
+            match self.load(&name)? {
+                Some((_, timer)) if is_due(&timer, now) && (!timer.leader_only || leads) => {}
+                Some((_, timer)) if is_pending(&timer) => continue,
+                // fired, or cancelled, but its host failed to remove it from the index
+                _ => {
+                    if let Err(e) = self.unindex(&name) {
+                        tracing::warn!("failed to remove timer '{}' from the index: {}", name, e);
+                    }
+                    continue;
+                }
+            }
+            if let Some(fired) = self.locked(&name, || self.fire(&name, now, misfire))? {
+                return Ok(Some(fired));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Marks the timers handed to the guest as handled (i.e., re-arms them, if they're
+    /// recurring, or marks them as fired, if they aren't), returning how many there were.
+    ///
+    /// A timer that was re-armed, or cancelled since it was handed to the guest is left as is.
+    pub fn handled(&self) -> Result<usize> {
+        let names = std::mem::take(&mut *self.handling.lock().unwrap());
+        let mut failures = Vec::new();
+        for name in &names {
+            if let Err(e) = self.finish(name) {
+                failures.push(format!("'{}': {:#}", name, e));
+            }
+        }
+        if !failures.is_empty() {
+            bail!(
+                "failed to mark {} of the {} timers handed to the guest as handled (they're delivered again once their claim expires): {}",
+                failures.len(),
+                names.len(),
+                failures.join("; ")
+            );
+        }
+        Ok(names.len())
+    }
+
+    /// Fires the timer `name`, if it's still due (i.e., no other host fired it meanwhile),
+    /// returning it if it has to be handed to the guest (which it's claimed for then).
+    fn fire(&self, name: &str, now: u64, misfire: &Misfire) -> Result<Option<Fired>> {
+        let (raw, timer) = match self.load(name)? {
+            Some((raw, timer)) if is_due(&timer, now) => (raw, timer),
+            _ => return Ok(None),
+        };
+        let (firing, next_due_at) = firing(&timer, now);
+
+        let misfired = firing.late_by_secs > misfire.grace.as_secs();
+        if misfired && misfire.policy == MisfirePolicy::Skip {
+            if self.swap(&raw, &next(timer, next_due_at))? && next_due_at.is_none() {
+                self.unindex(name)?;
+            }
+            tracing::warn!(
+                "skipped timer '{}', as it misfired by {} secs",
+                name,
+                firing.late_by_secs
+            );
+            return Ok(None);
+        }
+
+        let redelivered = timer.state == State::Firing;
+        let claimed = TimerRecord {
+            state: State::Firing,
+            due_at: firing.due_at,
+            claim: Some(Claim {
+                host: self.host.clone(),
+                until: now + DELIVERY_TIMEOUT_SECS,
+            }),
+            ..timer
+        };
+        if !self.swap(&raw, &claimed)? {
+            return Ok(None);
+        }
+        self.handling.lock().unwrap().push(name.to_string());
+
+        if redelivered {
+            tracing::warn!(
+                "delivering timer '{}' again, as the guest it was handed to didn't handle it in {} secs",
+                name,
+                DELIVERY_TIMEOUT_SECS
+            );
+        } else if misfired {
+            tracing::warn!("timer '{}' misfired by {} secs", name, firing.late_by_secs);
+        }
+        tracing::info!("fired timer '{}'", name);
+        Ok(Some(firing))
+    }
+
+    /// Re-arms the timer `name` (or marks it as fired), if it's still claimed by this host.
+    fn finish(&self, name: &str) -> Result<()> {
+        loop {
+            let (raw, timer) = match self.load(name)? {
+                Some((raw, timer))
+                    if timer.state == State::Firing
+                        && timer.claim.as_ref().map_or(false, |c| c.host == self.host) =>
+                {
+                    (raw, timer)
+                }
+                _ => return Ok(()),
+            };
+            let next_due_at = match timer.interval_in_secs {
+                0 => None,
+                interval => Some(timer.due_at + interval),
+            };
+            if self.swap(&raw, &next(timer, next_due_at))? {
+                if next_due_at.is_none() {
+                    self.unindex(name)?;
+                }
+                return Ok(());
+            }
+        }
+    }
+
+    /// Runs `f` holding the lock of the timer `name`, if timers are locked.
+    fn locked<T>(&self, name: &str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        let lockd = match &self.lockd {
+            Some(lockd) => lockd,
+            None => return f(),
+        };
+        let lock_name = format!("slight-timers/{}/{}", self.name, name);
+        let lock_key = lockd
+            .lock_with_time_to_live(lock_name.as_bytes(), LOCK_TTL_SECS)
+            .with_context(|| format!("failed to lock timer '{}'", name))?;
+        let res = f();
+        if let Err(e) = lockd.unlock(&lock_key) {
+            tracing::warn!("failed to unlock timer '{}': {}", name, e);
+        }
+        res
+    }
+
+    fn load(&self, name: &str) -> Result<Option<(Vec<u8>, TimerRecord)>> {
+        let raw = match self.kv.get(&timer_key(name))? {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+        let timer = serde_json::from_slice(&raw)
+            .with_context(|| format!("timer '{}' is corrupted", name))?;
+        Ok(Some((raw, timer)))
+    }
+
+    /// Replaces a timer, if it is still as it was when loaded (i.e., `raw`).
+    fn swap(&self, raw: &[u8], timer: &TimerRecord) -> Result<bool> {
+        self.kv.compare_and_swap(
+            &timer_key(&timer.name),
+            Some(raw),
+            &serde_json::to_vec(timer)?,
+        )
+    }
+
+    /// Removes the timer `name` from the index of armed timers, and re-checks it after, so if
+    /// it was re-armed meanwhile (i.e., its `set` found it in the index still), it's put back.
+    fn unindex(&self, name: &str) -> Result<()> {
+        self.update_index(|names| names.retain(|n| n != name))?;
+        if let Some((_, timer)) = self.load(name)? {
+            if is_pending(&timer) {
+                self.update_index(|names| {
+                    if !names.iter().any(|n| n == name) {
+                        names.push(name.to_string());
+                    }
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn index(&self) -> Result<Vec<String>> {
+        match self.kv.get(ARMED)? {
+            Some(raw) => Ok(serde_json::from_slice(&raw)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn update_index(&self, f: impl Fn(&mut Vec<String>)) -> Result<()> {
+        loop {
+            let current = self.kv.get(ARMED)?;
+            let mut names = match &current {
+                Some(raw) => serde_json::from_slice(raw)?,
+                None => Vec::new(),
+            };
+            f(&mut names);
+            if self
+                .kv
+                .compare_and_swap(ARMED, current.as_deref(), &serde_json::to_vec(&names)?)?
+            {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn timer_key(name: &str) -> Vec<u8> {
+    format!("timer/{}", name).into_bytes()
+}
+
+/// Whether a timer is still to be fired (i.e., it's armed, or handed to a guest that may not
+/// handle it).
+fn is_pending(timer: &TimerRecord) -> bool {
+    matches!(timer.state, State::Armed | State::Firing)
+}
+
+/// Whether a timer is due at `now`: it's armed, and its due time passed, or it was handed to a
+/// guest that didn't handle it before its claim expired.
+fn is_due(timer: &TimerRecord, now: u64) -> bool {
+    match timer.state {
+        State::Armed => timer.due_at <= now,
+        State::Firing => timer.claim.as_ref().map_or(true, |c| c.until <= now),
+        State::Fired | State::Cancelled => false,
+    }
+}
+
+/// A timer once it fired: re-armed for `next_due_at`, if it's recurring, or marked as fired.
+fn next(timer: TimerRecord, next_due_at: Option<u64>) -> TimerRecord {
+    TimerRecord {
+        state: match next_due_at {
+            Some(_) => State::Armed,
+            None => State::Fired,
+        },
+        due_at: next_due_at.unwrap_or(timer.due_at),
+        claim: None,
+        ..timer
+    }
+}
+
+/// What a due timer fires as at `now`, and when it's due next (if it's recurring).
+///
+/// A recurring timer that was due more than once since it last fired (e.g., as no host was up)
+/// fires once, for the latest of its due times — the earlier ones are counted as `missed`.
+fn firing(timer: &TimerRecord, now: u64) -> (Fired, Option<u64>) {
+    let (due_at, missed, next_due_at) = match timer.interval_in_secs {
+        0 => (timer.due_at, 0, None),
+        interval => {
+            let missed = (now - timer.due_at) / interval;
+            let due_at = timer.due_at + missed * interval;
+            (due_at, missed, Some(due_at + interval))
+        }
+    };
+    let fired = Fired {
+        name: timer.name.clone(),
+        payload: timer.payload.clone(),
+        due_at,
+        late_by_secs: now - due_at,
+        missed: u32::try_from(missed).unwrap_or(u32::MAX),
+    };
+    (fired, next_due_at)
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use slight_runtime::resource::BasicState;
+    use uuid::Uuid;
+
+    use super::{Misfire, MisfirePolicy, TimerRecord, TimerStore};
+
+    const NOW: u64 = 1_000_000;
+
+    fn store() -> TimerStore {
+        TimerStore::open(
+            "kv.filesystem",
+            None,
+            None,
+            &BasicState::default(),
+            &Uuid::new_v4().to_string(),
+        )
+        .unwrap()
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn timer_fires_once_test() -> Result<()> {
+        let store = store();
+        store.set("reminder", b"call ada", secs(3600), secs(0), false, NOW)?;
+        let misfire = Misfire::default();
+        assert!(store.fire_next(NOW + 3599, &misfire)?.is_none());
+
+        // a host that starts later (i.e., opens the store anew) still fires it
+        let restarted = TimerStore::open(
+            "kv.filesystem",
+            None,
+            None,
+            &BasicState::default(),
+            &store.name,
+        )?;
+        assert_eq!(restarted.armed()?.len(), 1);
+        let fired = restarted.fire_next(NOW + 3600, &misfire)?.unwrap();
+        assert_eq!(fired.name, "reminder");
+        assert_eq!(fired.payload, b"call ada");
+        assert_eq!(fired.late_by_secs, 0);
+        assert!(store.fire_next(NOW + 3600, &misfire)?.is_none());
+        // it's only fired once the guest handled it
+        assert_eq!(restarted.armed()?.len(), 1);
+        assert_eq!(restarted.handled()?, 1);
+        assert!(restarted.armed()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn redelivery_test() -> Result<()> {
+        let store = store();
+        store.set("reminder", b"call ada", secs(60), secs(0), false, NOW)?;
+        let misfire = Misfire::default();
+        assert!(store.fire_next(NOW + 60, &misfire)?.is_some());
+
+        // its guest crashed before it handled it, so another host delivers it again, once
+        // the claim expires
+        let other = TimerStore::open(
+            "kv.filesystem",
+            None,
+            None,
+            &BasicState::default(),
+            &store.name,
+        )?;
+        assert!(other.fire_next(NOW + 60 + 299, &misfire)?.is_none());
+        let fired = other.fire_next(NOW + 60 + 300, &misfire)?.unwrap();
+        assert_eq!(fired.name, "reminder");
+        assert_eq!(fired.due_at, NOW + 60);
+
+        // the first host's claim is gone, so it doesn't mark it as handled for the other one
+        assert_eq!(store.handled()?, 1);
+        assert_eq!(other.armed()?.len(), 1);
+        assert_eq!(other.handled()?, 1);
+        assert!(other.armed()?.is_empty());
+        assert!(store.fire_next(NOW + 3600, &misfire)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn unindex_rearmed_test() -> Result<()> {
+        let store = store();
+        store.set("reminder", b"", secs(60), secs(0), false, NOW)?;
+        // as if the removal of a fired timer raced its `set` (i.e., which found it in the
+        // index still), it's put back
+        store.unindex("reminder")?;
+        assert_eq!(store.index()?, vec!["reminder".to_string()]);
+
+        // and re-arming a timer the guest is handling isn't undone once it's handled
+        store.fire_next(NOW + 60, &Misfire::default())?.unwrap();
+        store.set("reminder", b"", secs(60), secs(0), false, NOW + 60)?;
+        assert_eq!(store.handled()?, 1);
+        assert_eq!(store.armed()?[0].due_at, NOW + 120);
+        assert_eq!(store.index()?, vec!["reminder".to_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn recurring_timer_test() -> Result<()> {
+        let store = store();
+        store.set("report", b"", secs(60), secs(60), false, NOW)?;
+        let misfire = Misfire::default();
+        assert_eq!(
+            store.fire_next(NOW + 60, &misfire)?.unwrap().due_at,
+            NOW + 60
+        );
+        assert!(store.fire_next(NOW + 119, &misfire)?.is_none());
+
+        // the firings missed while no host was up are folded into one
+        let fired = store.fire_next(NOW + 310, &misfire)?.unwrap();
+        assert_eq!(fired.due_at, NOW + 300);
+        assert_eq!(fired.missed, 3);
+        assert_eq!(fired.late_by_secs, 10);
+        store.handled()?;
+        assert_eq!(store.armed()?[0].due_at, NOW + 360);
+
+        store.cancel("report")?;
+        assert!(store.fire_next(NOW + 360, &misfire)?.is_none());
+        assert!(store.cancel("report").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn misfire_policy_test() -> Result<()> {
+        let skip = Misfire {
+            policy: MisfirePolicy::Skip,
+            grace: secs(30),
+        };
+        let store = store();
+        store.set("late", b"", secs(60), secs(0), false, NOW)?;
+        store.set("on-time", b"", secs(100), secs(0), false, NOW)?;
+        // `late` misfired, so it's skipped, while `on-time` is within the grace
+        let fired = store.fire_next(NOW + 120, &skip)?.unwrap();
+        assert_eq!(fired.name, "on-time");
+        store.handled()?;
+        assert!(store.armed()?.is_empty());
+
+        let fire = Misfire {
+            policy: MisfirePolicy::Fire,
+            ..skip
+        };
+        store.set("late", b"", secs(60), secs(0), false, NOW)?;
+        assert_eq!(store.fire_next(NOW + 120, &fire)?.unwrap().late_by_secs, 60);
+
+        assert_eq!(MisfirePolicy::parse("skip")?, MisfirePolicy::Skip);
+        assert!(MisfirePolicy::parse("catch-up").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn leader_only_test() -> Result<()> {
+        // w/o an election, no host could ever fire it
+        let store = store();
+        assert!(store
+            .set("cleanup", b"", secs(60), secs(60), true, NOW)
+            .is_err());
+        assert!(store.armed()?.is_empty());
+
+        // timers armed before leader-only ones were a thing fire on any host
+        let armed =
+            br#"{"name":"report","payload":[],"state":"armed","due_at":0,"interval_in_secs":0}"#;
+        let timer: TimerRecord = serde_json::from_slice(armed)?;
+        assert!(!timer.leader_only);
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+
+/// The most buckets a query can have, so a small step over a long range can't exhaust the
+/// host (i.e., it's Prometheus' limit on the points of a query).
+const MAX_BUCKETS: u64 = 11_000;
+
+/// A point of a series (i.e., its value at a time).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+    pub timestamp_ms: u64,
+    pub value: f64,
+}
+
+/// How the points of a bucket are aggregated into one value — the same for every implementor,
+/// as they're aggregated by the host, rather than by the backend (see `aggregate`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aggregation {
+    Sum,
+    Avg,
+    Min,
+    Max,
+    /// the increase per second from the first to the last point of the bucket, where a
+    /// decrease is a counter reset (i.e., like Prometheus' `rate`, w/o extrapolation)
+    Rate,
+}
+
+/// The range of time a query is over, split into buckets of `step_ms` (or into one bucket, if
+/// it's 0), where the last one may be narrower.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Range {
+    pub start_ms: u64,
+    /// exclusive
+    pub end_ms: u64,
+    pub step_ms: u64,
+}
+
+impl Range {
+    /// Checks the range isn't empty, and that it doesn't have more than `MAX_BUCKETS`.
+    pub fn new(start_ms: u64, end_ms: u64, step_ms: u64) -> Result<Self> {
+        if start_ms >= end_ms {
+            bail!(
+                "invalid range: it must start (i.e., at {}) before it ends (i.e., at {})",
+                start_ms,
+                end_ms
+            );
+        }
+        let range = Self {
+            start_ms,
+            end_ms,
+            step_ms,
+        };
+        if range.buckets() > MAX_BUCKETS {
+            bail!(
+                "invalid range: a step of {}ms makes {} buckets (expected up to {})",
+                step_ms,
+                range.buckets(),
+                MAX_BUCKETS
+            );
+        }
+        Ok(range)
+    }
+
+    pub fn contains(&self, timestamp_ms: u64) -> bool {
+        (self.start_ms..self.end_ms).contains(&timestamp_ms)
+    }
+
+    fn buckets(&self) -> u64 {
+        match self.step_ms {
+            0 => 1,
+            step => (self.end_ms - self.start_ms - 1) / step + 1,
+        }
+    }
+
+    /// The start of the bucket a point of the range falls in.
+    fn bucket_of(&self, timestamp_ms: u64) -> u64 {
+        match self.step_ms {
+            0 => self.start_ms,
+            step => timestamp_ms - (timestamp_ms - self.start_ms) % step,
+        }
+    }
+}
+
+/// The aggregated value of the points of a bucket.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bucket {
+    pub start_ms: u64,
+    pub value: f64,
+    /// how many points were aggregated
+    pub count: u64,
+}
+
+/// Aggregates the points of a series in the range into buckets, oldest first, where buckets
+/// w/o points are left out — so are the buckets a `Rate` can't be had of (i.e., w/o two
+/// points of the same tags).
+///
+/// The points are those of each of the tags (i.e., the tag sets) that matched, as a `Rate` is
+/// of each of them, and then summed (e.g., of the requests of each replica), while the rest
+/// aggregate the points of all of them.
+pub fn aggregate(series: Vec<Vec<Point>>, range: &Range, aggregation: Aggregation) -> Vec<Bucket> {
+    // the points of each bucket, of each tag set
+    let mut buckets: BTreeMap<u64, Vec<Vec<Point>>> = BTreeMap::new();
+    for mut points in series {
+        points.retain(|p| range.contains(p.timestamp_ms));
+        points.sort_by_key(|p| p.timestamp_ms);
+        let mut by_bucket: BTreeMap<u64, Vec<Point>> = BTreeMap::new();
+        for p in points {
+            by_bucket
+                .entry(range.bucket_of(p.timestamp_ms))
+                .or_default()
+                .push(p);
+        }
+        for (start_ms, points) in by_bucket {
+            buckets.entry(start_ms).or_default().push(points);
+        }
+    }
+    buckets
+        .into_iter()
+        .filter_map(|(start_ms, series)| {
+            let count = series.iter().map(|points| points.len() as u64).sum();
+            let values = series.iter().flatten().map(|p| p.value);
+            let value = match aggregation {
+                Aggregation::Sum => values.sum::<f64>(),
+                Aggregation::Avg => values.sum::<f64>() / count as f64,
+                Aggregation::Min => values.fold(f64::INFINITY, f64::min),
+                Aggregation::Max => values.fold(f64::NEG_INFINITY, f64::max),
+                Aggregation::Rate => {
+                    let rates = series.iter().filter_map(|points| rate(points));
+                    rates.fold(None, |sum, rate| Some(sum.unwrap_or(0.0) + rate))?
+                }
+            };
+            Some(Bucket {
+                start_ms,
+                value,
+                count,
+            })
+        })
+        .collect()
+}
+
+/// The increase per second of points of the same tags (oldest first), or `None` if they
+/// don't span any time.
+fn rate(points: &[Point]) -> Option<f64> {
+    let (first, last) = (points.first()?, points.last()?);
+    if last.timestamp_ms == first.timestamp_ms {
+        return None;
+    }
+    let increase: f64 = points
+        .windows(2)
+        .map(|w| match w[1].value - w[0].value {
+            // the counter was reset, so it increased by all it's at now
+            d if d < 0.0 => w[1].value,
+            d => d,
+        })
+        .sum();
+    Some(increase * 1000.0 / (last.timestamp_ms - first.timestamp_ms) as f64)
+}
+
+#[cfg(test)]
+mod unittests {
+    use super::{aggregate, Aggregation, Bucket, Point, Range};
+
+    fn points(points: &[(u64, f64)]) -> Vec<Point> {
+        points
+            .iter()
+            .map(|&(timestamp_ms, value)| Point {
+                timestamp_ms,
+                value,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn range_test() {
+        assert!(Range::new(10, 10, 0).is_err());
+        assert!(Range::new(0, 11_000, 1).is_ok());
+        assert!(Range::new(0, 11_001, 1).is_err());
+        let range = Range::new(1_000, 2_500, 1_000).unwrap();
+        assert_eq!(range.buckets(), 2);
+        assert_eq!(range.bucket_of(2_499), 2_000);
+        assert!(!range.contains(2_500));
+    }
+
+    #[test]
+    fn aggregate_test() {
+        let range = Range::new(0, 3_000, 1_000).unwrap();
+        let series = vec![
+            points(&[(0, 1.0), (500, 3.0), (2_100, 5.0), (3_000, 100.0)]),
+            points(&[(900, 2.0)]),
+        ];
+        let values = |aggregation| {
+            aggregate(series.clone(), &range, aggregation)
+                .iter()
+                .map(|b| (b.start_ms, b.value, b.count))
+                .collect::<Vec<_>>()
+        };
+        // the bucket at 1000 has no points, and the point at 3000 is out of the range
+        assert_eq!(values(Aggregation::Sum), vec![(0, 6.0, 3), (2_000, 5.0, 1)]);
+        assert_eq!(values(Aggregation::Avg), vec![(0, 2.0, 3), (2_000, 5.0, 1)]);
+        assert_eq!(values(Aggregation::Min), vec![(0, 1.0, 3), (2_000, 5.0, 1)]);
+        assert_eq!(values(Aggregation::Max), vec![(0, 3.0, 3), (2_000, 5.0, 1)]);
+        // only the first tag set of the first bucket has a rate (i.e., 2 over half a second)
+        assert_eq!(values(Aggregation::Rate), vec![(0, 4.0, 3)]);
+
+        // one bucket over the whole range, w/ a counter reset
+        let range = Range::new(0, 10_000, 0).unwrap();
+        let series = vec![
+            points(&[(0, 10.0), (4_000, 30.0), (5_000, 5.0)]),
+            points(&[(1_000, 0.0), (2_000, 1.0)]),
+        ];
+        assert_eq!(
+            aggregate(series, &range, Aggregation::Rate),
+            vec![Bucket {
+                start_ms: 0,
+                value: 6.0,
+                count: 5
+            }]
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use slight_runtime::describe::Description;
+
+use crate::aggregate::{Point, Range};
+
+/// The extension of the files series are kept in.
+const EXTENSION: &str = "jsonl";
+
+/// This is the underlying struct behind the `Filesystem` variant of the `TimeseriesImplementor`
+/// enum.
+///
+/// It provides a property that pertains solely to the filesystem implementation
+/// of this capability:
+///     - `base`.
+///
+/// Each series is a file of the `base`, w/ a JSON line per point written, named after it
+/// (e.g., `<base>/http_requests.jsonl`) — writes are appends, so a point written again is
+/// only replaced when it's read.
+///
+/// As per its usage in `TimeseriesImplementor`, it must `derive` `Debug`, and `Clone`.
+#[derive(Debug, Clone)]
+pub struct FilesystemImplementor {
+    /// The base path for where the time-series store can be found in your file-system
+    base: PathBuf,
+}
+
+/// A point, as it's kept in the file of its series.
+#[derive(Serialize, Deserialize)]
+struct Line {
+    #[serde(rename = "t")]
+    timestamp_ms: u64,
+    #[serde(rename = "v")]
+    value: f64,
+    tags: BTreeMap<String, String>,
+}
+
+impl FilesystemImplementor {
+    pub fn new(name: &str) -> Self {
+        Self {
+            base: env::temp_dir().join(format!("slight-timeseries-{}", name)),
+        }
+    }
+
+    /// Describes where stores are kept (see `slight_runtime::describe`) — each in a directory
+    /// of this one, named after it (i.e., `slight-timeseries-<name>`).
+    pub fn describe() -> Description {
+        Description::new("timeseries.filesystem", "the filesystem")
+            .with_setting("directory", Ok(env::temp_dir().display().to_string()))
+    }
+
+    fn path(&self, series: &str) -> PathBuf {
+        self.base.join(format!("{}.{}", series, EXTENSION))
+    }
+
+    /// Appends the point in one write, so concurrent writers don't interleave their lines.
+    pub fn write(
+        &self,
+        series: &str,
+        timestamp_ms: u64,
+        value: f64,
+        tags: &[(&str, &str)],
+    ) -> Result<()> {
+        fs::create_dir_all(&self.base).context("failed to create the time-series store")?;
+        let line = Line {
+            timestamp_ms,
+            value,
+            tags: tags
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        };
+        let mut line = serde_json::to_vec(&line)?;
+        line.push(b'\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(series))
+            .and_then(|mut file| file.write_all(&line))
+            .with_context(|| format!("failed to write a point to series '{}'", series))
+    }
+
+    /// Reads every point of the series, as there's no index to narrow them down, and returns
+    /// those in the range w/ all the tags, by tag set, where the last written of a timestamp
+    /// wins.
+    pub fn query(
+        &self,
+        series: &str,
+        tags: &[(&str, &str)],
+        range: &Range,
+    ) -> Result<Vec<Vec<Point>>> {
+        let contents = match fs::read_to_string(self.path(series)) {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            contents => contents.with_context(|| format!("failed to read series '{}'", series))?,
+        };
+        let mut by_tags: BTreeMap<BTreeMap<String, String>, BTreeMap<u64, f64>> = BTreeMap::new();
+        for line in contents.lines() {
+            let line = match serde_json::from_str::<Line>(line) {
+                Ok(line) => line,
+                // i.e., a write that was cut short
+                Err(e) => {
+                    tracing::warn!("skipping a malformed point of series '{}': {}", series, e);
+                    continue;
+                }
+            };
+            let matches = tags
+                .iter()
+                .all(|(k, v)| line.tags.get(*k).map_or(false, |tag| tag == *v));
+            if matches && range.contains(line.timestamp_ms) {
+                by_tags
+                    .entry(line.tags)
+                    .or_default()
+                    .insert(line.timestamp_ms, line.value);
+            }
+        }
+        Ok(by_tags
+            .into_values()
+            .map(|points| {
+                points
+                    .into_iter()
+                    .map(|(timestamp_ms, value)| Point {
+                        timestamp_ms,
+                        value,
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+use chrono::DateTime;
+use reqwest::{header, Response};
+use slight_runtime::deadline::block_on;
+use slight_runtime::{call::TimedOut, describe::Description, resource::BasicState};
+
+use crate::aggregate::{Point, Range};
+
+/// The field points are written to, as series have one value each.
+const FIELD: &str = "value";
+
+/// This is the underlying struct behind the `InfluxDb` variant of the `TimeseriesImplementor`
+/// enum.
+///
+/// It writes, and queries points w/ the InfluxDB v2 HTTP API, in the bucket named after the
+/// store, where each series is a measurement, w/ its tags, and one `value` field. Queries
+/// fetch the raw points (w/ Flux), which are aggregated by the host, like every implementor's.
+///
+/// The `INFLUXDB_URL`, `INFLUXDB_ORG`, and `INFLUXDB_TOKEN` are read from the secret store.
+///
+/// As per its usage in `TimeseriesImplementor`, it must `derive` `Debug`, and `Clone`.
+#[derive(Debug, Clone)]
+pub struct InfluxDbImplementor {
+    client: reqwest::Client,
+    url: String,
+    org: String,
+    token: String,
+    bucket: String,
+}
+
+impl InfluxDbImplementor {
+    pub fn new(slight_state: &BasicState, name: &str) -> Result<Self> {
+        let secret = |key: &str| -> Result<String> {
+            let value = slight_runtime_configs::resolve(
+                &slight_state.secret_stores,
+                key,
+                &slight_state.config_toml_file_path,
+            )
+            .with_context(|| {
+                format!(
+                    "failed to get '{}' secret using secret stores: {:?}",
+                    key, slight_state.secret_stores
+                )
+            })?;
+            Ok(String::from_utf8(value)?)
+        };
+        tracing::info!(
+            "Creating a new InfluxDB time-series store w/ bucket name: {}",
+            name
+        );
+        Ok(Self {
+            client: reqwest::Client::new(),
+            url: secret("INFLUXDB_URL")?.trim_end_matches('/').to_string(),
+            org: secret("INFLUXDB_ORG")?,
+            token: secret("INFLUXDB_TOKEN")?,
+            bucket: name.to_string(),
+        })
+    }
+
+    /// Describes the InfluxDB the store would write to (see `slight_runtime::describe`), w/o
+    /// connecting to it — the bucket is the store's name.
+    pub fn describe(slight_state: &BasicState) -> Description {
+        let setting = |key: &str| slight_runtime_configs::setting(slight_state, key);
+        Description::new("timeseries.influxdb", "InfluxDB")
+            .with_endpoint("INFLUXDB_URL", setting("INFLUXDB_URL"))
+            .with_setting("INFLUXDB_ORG", setting("INFLUXDB_ORG"))
+            .with_secret("INFLUXDB_TOKEN", setting("INFLUXDB_TOKEN"))
+    }
+
+    pub fn write(
+        &self,
+        series: &str,
+        timestamp_ms: u64,
+        value: f64,
+        tags: &[(&str, &str)],
+    ) -> Result<()> {
+        let req = self
+            .client
+            .post(format!("{}/api/v2/write", self.url))
+            .query(&[
+                ("org", self.org.as_str()),
+                ("bucket", self.bucket.as_str()),
+                ("precision", "ms"),
+            ])
+            .header(header::AUTHORIZATION, format!("Token {}", self.token))
+            .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(line_protocol(series, timestamp_ms, value, tags));
+        check(block_on(req.send())?.map_err(timed_out)?)
+            .with_context(|| format!("failed to write a point to series '{}'", series))?;
+        Ok(())
+    }
+
+    pub fn query(
+        &self,
+        series: &str,
+        tags: &[(&str, &str)],
+        range: &Range,
+    ) -> Result<Vec<Vec<Point>>> {
+        let req = self
+            .client
+            .post(format!("{}/api/v2/query", self.url))
+            .query(&[("org", self.org.as_str())])
+            .header(header::AUTHORIZATION, format!("Token {}", self.token))
+            .header(header::CONTENT_TYPE, "application/vnd.flux")
+            .header(header::ACCEPT, "application/csv")
+            .body(flux(&self.bucket, series, tags, range));
+        let res = check(block_on(req.send())?.map_err(timed_out)?)
+            .with_context(|| format!("failed to query series '{}'", series))?;
+        parse_csv(&block_on(res.text())?.map_err(timed_out)?)
+    }
+}
+
+/// Fails w/ the error InfluxDB responded w/, if it didn't succeed.
+fn check(res: Response) -> Result<Response> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+    let body = block_on(res.text())
+        .map(Result::unwrap_or_default)
+        .unwrap_or_default();
+    bail!("InfluxDB responded w/ {}: {}", status, body.trim())
+}
+
+/// Fails w/ `TimedOut` if InfluxDB didn't respond in time, so the guest can tell.
+fn timed_out(e: reqwest::Error) -> anyhow::Error {
+    if e.is_timeout() {
+        anyhow::Error::new(TimedOut(e.to_string()))
+    } else {
+        e.into()
+    }
+}
+
+/// A point in line protocol, where series names, and tag keys are made of characters that
+/// don't need escaping (see `check_series`, and `check_tags`), but tag values may.
+fn line_protocol(series: &str, timestamp_ms: u64, value: f64, tags: &[(&str, &str)]) -> String {
+    let mut line = series.to_string();
+    for (k, v) in tags {
+        line.push_str(&format!(",{}={}", k, escape_tag_value(v)));
+    }
+    // w/o a suffix (e.g., `i`), a field is a float
+    line.push_str(&format!(" {}={} {}", FIELD, value, timestamp_ms));
+    line
+}
+
+fn escape_tag_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ',' | '=' | ' ') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The Flux query of the raw points of the series in the range, w/ all the tags.
+fn flux(bucket: &str, series: &str, tags: &[(&str, &str)], range: &Range) -> String {
+    let mut filter = format!(
+        "r._measurement == {} and r._field == {}",
+        flux_string(series),
+        flux_string(FIELD)
+    );
+    for (k, v) in tags {
+        filter.push_str(&format!(" and r[{}] == {}", flux_string(k), flux_string(v)));
+    }
+    format!(
+        "from(bucket: {})\n  |> range(start: time(v: {}), stop: time(v: {}))\n  |> filter(fn: (r) => {})",
+        flux_string(bucket),
+        range.start_ms as u128 * 1_000_000,
+        range.end_ms as u128 * 1_000_000,
+        filter
+    )
+}
+
+/// A Flux string literal, where `$` is escaped too, as `${` interpolates.
+fn flux_string(s: &str) -> String {
+    let mut literal = String::with_capacity(s.len() + 2);
+    literal.push('"');
+    for c in s.chars() {
+        if matches!(c, '\\' | '"' | '$') {
+            literal.push('\\');
+        }
+        literal.push(c);
+    }
+    literal.push('"');
+    literal
+}
+
+/// Parses the points of an (annotation-less) CSV response, by table, as each table of a Flux
+/// result is a tag set of the series.
+fn parse_csv(csv: &str) -> Result<Vec<Vec<Point>>> {
+    let mut tables: BTreeMap<String, Vec<Point>> = BTreeMap::new();
+    // the index of the `table`, `_time`, and `_value` columns of the current table's header
+    let mut columns: Option<(usize, usize, usize)> = None;
+    for line in csv.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            // i.e., the end of a result, whose tables have a header of their own
+            columns = None;
+            continue;
+        }
+        let fields = split_csv(line);
+        let (table, time, value) = match columns {
+            Some(columns) => columns,
+            None => {
+                let index = |name: &str| {
+                    fields
+                        .iter()
+                        .position(|field| field == name)
+                        .with_context(|| format!("InfluxDB responded w/o a '{}' column", name))
+                };
+                columns = Some((index("table")?, index("_time")?, index("_value")?));
+                continue;
+            }
+        };
+        let field = |i: usize| {
+            fields
+                .get(i)
+                .map(String::as_str)
+                .context("InfluxDB responded w/ a short row")
+        };
+        let timestamp_ms = DateTime::parse_from_rfc3339(field(time)?)
+            .context("InfluxDB responded w/ an invalid time")?
+            .timestamp_millis();
+        let value = field(value)?
+            .parse::<f64>()
+            .context("InfluxDB responded w/ an invalid value")?;
+        tables
+            .entry(field(table)?.to_string())
+            .or_default()
+            .push(Point {
+                timestamp_ms: u64::try_from(timestamp_ms).unwrap_or_default(),
+                value,
+            });
+    }
+    Ok(tables.into_values().collect())
+}
+
+/// Splits a CSV line into its fields, where fields may be quoted (w/ `""` for a quote).
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+#[cfg(test)]
+mod unittests {
+    use super::{flux, line_protocol, parse_csv};
+    use crate::aggregate::{Point, Range};
+
+    #[test]
+    fn line_protocol_test() {
+        assert_eq!(
+            line_protocol(
+                "http_requests",
+                1_665_000_000_000,
+                1.0,
+                &[("host", "web 1"), ("path", "/a,b=c")]
+            ),
+            "http_requests,host=web\\ 1,path=/a\\,b\\=c value=1 1665000000000"
+        );
+    }
+
+    #[test]
+    fn flux_test() {
+        let range = Range::new(1_000, 2_000, 0).unwrap();
+        assert_eq!(
+            flux("metrics", "cpu", &[("host", "${secret}\"")], &range),
+            "from(bucket: \"metrics\")
+  |> range(start: time(v: 1000000000), stop: time(v: 2000000000))
+  |> filter(fn: (r) => r._measurement == \"cpu\" and r._field == \"value\" and r[\"host\"] == \"\\${secret}\\\"\")"
+        );
+    }
+
+    #[test]
+    fn parse_csv_test() {
+        let csv = ",result,table,_start,_stop,_time,_value,_field,_measurement,host\r
+,_result,0,2022-10-05T00:00:00Z,2022-10-06T00:00:00Z,2022-10-05T20:00:00.5Z,1.5,value,cpu,\"web,1\"\r
+,_result,0,2022-10-05T00:00:00Z,2022-10-06T00:00:00Z,2022-10-05T20:00:01Z,2,value,cpu,\"web,1\"\r
+,_result,1,2022-10-05T00:00:00Z,2022-10-06T00:00:00Z,2022-10-05T20:00:00Z,3,value,cpu,web-2\r
+\r
+";
+        assert_eq!(
+            parse_csv(csv).unwrap(),
+            vec![
+                vec![
+                    Point {
+                        timestamp_ms: 1_665_000_000_500,
+                        value: 1.5
+                    },
+                    Point {
+                        timestamp_ms: 1_665_000_001_000,
+                        value: 2.0
+                    }
+                ],
+                vec![Point {
+                    timestamp_ms: 1_665_000_000_000,
+                    value: 3.0
+                }]
+            ]
+        );
+        assert!(parse_csv("").unwrap().is_empty());
+        assert!(parse_csv(",result,table\r\n,_result,0\r\n").is_err());
+    }
+}
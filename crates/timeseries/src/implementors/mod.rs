@@ -0,0 +1,2 @@
+pub mod filesystem;
+pub mod influxdb;
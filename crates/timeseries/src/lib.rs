@@ -0,0 +1,338 @@
+mod aggregate;
+mod implementors;
+
+/// The `SCHEME_NAME` defines the name under which a resource is
+/// identifiable by in a `ResourceMap`.
+const SCHEME_NAME: &str = "timeseries";
+/// The operations guests can retry if they time out (see `BasicState::with_idempotent_operations`)
+/// — `write` is, as writing a point w/ the same timestamp, and tags again replaces it.
+const IDEMPOTENT_OPERATIONS: &[&str] = &["write", "query"];
+/// The operations that only read, which audits of writes only leave out (see
+/// `BasicState::with_read_operations`) — `write` is idempotent, but it isn't one.
+const READ_OPERATIONS: &[&str] = &["query"];
+
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use uuid::Uuid;
+
+use implementors::{filesystem::FilesystemImplementor, influxdb::InfluxDbImplementor};
+use slight_runtime::{describe::Description, impl_resource, resource::BasicState};
+
+/// It is mandatory to `use <interface>::*` due to `impl_resource!`.
+/// That is because `impl_resource!` accesses the `crate`'s
+/// `add_to_linker`, and not the `<interface>::add_to_linker` directly.
+use timeseries::*;
+wit_bindgen_wasmtime::export!("../../wit/timeseries.wit");
+wit_error_rs::impl_error!(timeseries::Error);
+slight_runtime::impl_from_anyhow!(timeseries::Error);
+
+/// The longest a series name, a tag key, or a tag value can be.
+const MAX_NAME_LEN: usize = 255;
+/// The latest a point can be at, in milliseconds since the unix epoch, as backends keep
+/// timestamps as signed nanoseconds (i.e., it's in 2262).
+const MAX_TIMESTAMP_MS: u64 = i64::MAX as u64 / 1_000_000;
+
+/// The `Timeseries` structure is what will implement the `timeseries::Timeseries` trait
+/// coming from the generated code of off `timeseries.wit`.
+///
+/// It maintains a `host_state`.
+pub struct Timeseries {
+    host_state: TimeseriesState,
+}
+
+impl_resource!(
+    Timeseries,
+    timeseries::TimeseriesTables<Timeseries>,
+    TimeseriesState,
+    SCHEME_NAME.to_string()
+);
+
+/// This is the type of the `host_state` property from our `Timeseries` structure.
+///
+/// It holds:
+///     - a `timeseries_implementor` `String` — this comes directly from a
+///     user's `slightfile` and it is what allows us to dynamically
+///     dispatch to a specific implementor's implentation, and
+///     - the `slight_state` (of type `BasicState`) that contains common
+///     things received from the slight binary (i.e., the `resource_map`,
+///     the `config_type`, and the `config_toml_file_path`).
+pub struct TimeseriesState {
+    timeseries_implementor: String,
+    slight_state: BasicState,
+}
+
+impl TimeseriesState {
+    pub fn new(timeseries_implementor: String, slight_state: BasicState) -> Self {
+        Self {
+            timeseries_implementor,
+            slight_state: slight_state
+                .with_idempotent_operations(IDEMPOTENT_OPERATIONS)
+                .with_read_operations(READ_OPERATIONS),
+        }
+    }
+
+    /// Describes the effective configuration of the backend (see `slight_runtime::describe`),
+    /// w/o connecting to it.
+    pub fn describe(&self) -> Result<Description> {
+        let description = match self.timeseries_implementor.as_str() {
+            "timeseries.filesystem" => FilesystemImplementor::describe(),
+            "timeseries.influxdb" => InfluxDbImplementor::describe(&self.slight_state),
+            p => bail!(
+                "failed to match provided name (i.e., '{}') to any known host implementations",
+                p
+            ),
+        };
+        Ok(description.with_pool_size(self.slight_state.pool_size()))
+    }
+}
+
+impl From<timeseries::Aggregation> for aggregate::Aggregation {
+    fn from(aggregation: timeseries::Aggregation) -> Self {
+        match aggregation {
+            timeseries::Aggregation::Sum => Self::Sum,
+            timeseries::Aggregation::Avg => Self::Avg,
+            timeseries::Aggregation::Min => Self::Min,
+            timeseries::Aggregation::Max => Self::Max,
+            timeseries::Aggregation::Rate => Self::Rate,
+        }
+    }
+}
+
+impl timeseries::Timeseries for Timeseries {
+    type Timeseries = TimeseriesInner;
+
+    fn timeseries_open(&mut self, name: &str) -> Result<Self::Timeseries, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
+        // populate our inner timeseries object w/ the state received from `slight`
+        // (i.e., what type of timeseries implementor we are using), and the assigned
+        // name of the object.
+        let inner = Self::Timeseries::new(
+            &self.host_state.timeseries_implementor,
+            &self.host_state.slight_state,
+            name,
+        )?;
+
+        self.host_state
+            .slight_state
+            .resource_map
+            .lock()
+            .unwrap()
+            .set(inner.resource_descriptor.clone(), Box::new(inner.clone()));
+
+        Ok(inner)
+    }
+
+    fn timeseries_write(
+        &mut self,
+        self_: &Self::Timeseries,
+        series: &str,
+        timestamp_ms: u64,
+        value: f64,
+        tags: Vec<(&str, &str)>,
+    ) -> Result<(), Error> {
+        check_series(series)?;
+        check_tags(&tags)?;
+        if timestamp_ms > MAX_TIMESTAMP_MS {
+            return Err(anyhow::anyhow!(
+                "invalid timestamp: {} (expected up to {})",
+                timestamp_ms,
+                MAX_TIMESTAMP_MS
+            )
+            .into());
+        }
+        if !value.is_finite() {
+            return Err(anyhow::anyhow!("invalid value: {} (expected a finite one)", value).into());
+        }
+        Ok(self.host_state.slight_state.instrument(
+            SCHEME_NAME,
+            "write",
+            series,
+            || match &self_.timeseries_implementor {
+                TimeseriesImplementor::Filesystem(fi) => {
+                    fi.write(series, timestamp_ms, value, &tags)
+                }
+                TimeseriesImplementor::InfluxDb(ii) => ii.write(series, timestamp_ms, value, &tags),
+            },
+        )?)
+    }
+
+    fn timeseries_query(
+        &mut self,
+        self_: &Self::Timeseries,
+        series: &str,
+        tags: Vec<(&str, &str)>,
+        range: TimeRange,
+        aggregation: timeseries::Aggregation,
+    ) -> Result<Vec<Bucket>, Error> {
+        check_series(series)?;
+        check_tags(&tags)?;
+        let range = aggregate::Range::new(
+            range.start_ms,
+            range.end_ms.min(MAX_TIMESTAMP_MS),
+            range.step_ms,
+        )?;
+        let points = self.host_state.slight_state.instrument(
+            SCHEME_NAME,
+            "query",
+            series,
+            || match &self_.timeseries_implementor {
+                TimeseriesImplementor::Filesystem(fi) => fi.query(series, &tags, &range),
+                TimeseriesImplementor::InfluxDb(ii) => ii.query(series, &tags, &range),
+            },
+        )?;
+        Ok(aggregate::aggregate(points, &range, aggregation.into())
+            .into_iter()
+            .map(|bucket| Bucket {
+                start_ms: bucket.start_ms,
+                value: bucket.value,
+                count: bucket.count,
+            })
+            .collect())
+    }
+}
+
+/// Makes sure a series name can be used as-is by every implementor (e.g., as a file name, or
+/// an InfluxDB measurement): it must be made of letters, digits, `-`, `_`, or `.`, and it
+/// can't start w/ a `.`.
+fn check_series(series: &str) -> Result<()> {
+    if series.is_empty()
+        || series.len() > MAX_NAME_LEN
+        || series.starts_with('.')
+        || !series
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        bail!(
+            "invalid series: '{}' (expected up to {} letters, digits, '-', '_', or '.', not starting w/ a '.')",
+            series,
+            MAX_NAME_LEN
+        );
+    }
+    Ok(())
+}
+
+/// Makes sure tags mean the same to every implementor: keys must be made of letters, digits,
+/// or `_`, w/o starting w/ a `_` (i.e., InfluxDB's reserved columns, like `_time`), and be
+/// unique, and values can't be empty, or have control characters, or `\`.
+fn check_tags(tags: &[(&str, &str)]) -> Result<()> {
+    for (i, (k, v)) in tags.iter().enumerate() {
+        if k.is_empty()
+            || k.len() > MAX_NAME_LEN
+            || k.starts_with('_')
+            || !k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            bail!(
+                "invalid tag key: '{}' (expected up to {} letters, digits, or '_', not starting w/ a '_')",
+                k,
+                MAX_NAME_LEN
+            );
+        }
+        if tags[..i].iter().any(|(other, _)| other == k) {
+            bail!("invalid tags: '{}' is given more than once", k);
+        }
+        if v.is_empty() || v.len() > MAX_NAME_LEN || v.chars().any(|c| c.is_control() || c == '\\')
+        {
+            bail!(
+                "invalid value of tag '{}': '{}' (expected up to {} characters, w/o control characters, or '\\')",
+                k,
+                v.escape_debug(),
+                MAX_NAME_LEN
+            );
+        }
+    }
+    Ok(())
+}
+
+/// This is the type of the associated type coming from the `timeseries::Timeseries` trait
+/// implementation.
+///
+/// It holds:
+///     - a `timeseries_implementor` (i.e., a variant `TimeseriesImplementor` `enum`), and
+///     - a `resource_descriptor` (i.e., an UUID that uniquely identifies
+///     resource's instance).
+///
+/// It must `derive`:
+///     - `Debug` due to a constraint on the associated type.
+///     - `Clone` because the `ResourceMap` it will be added onto,
+///     must own its data.
+///
+/// It must be public because the implementation of `timeseries::Timeseries` cannot leak
+/// a private type.
+#[derive(Debug, Clone)]
+pub struct TimeseriesInner {
+    timeseries_implementor: TimeseriesImplementor,
+    resource_descriptor: String,
+}
+
+impl TimeseriesInner {
+    fn new(timeseries_implementor: &str, slight_state: &BasicState, name: &str) -> Result<Self> {
+        Ok(Self {
+            timeseries_implementor: TimeseriesImplementor::new(
+                timeseries_implementor,
+                slight_state,
+                name,
+            )?,
+            resource_descriptor: Uuid::new_v4().to_string(),
+        })
+    }
+}
+
+impl slight_runtime::resource::Watch for TimeseriesInner {}
+
+/// This defines the available implementor implementations for the `Timeseries` interface.
+///
+/// As per its usage in `TimeseriesInner`, it must `derive` `Debug`, and `Clone`.
+///
+/// The implementors that hold clients share them w/ the other guest instances of the app
+/// (see `BasicState::connection`), rather than connecting once per instance.
+#[derive(Debug, Clone)]
+enum TimeseriesImplementor {
+    Filesystem(FilesystemImplementor),
+    InfluxDb(Arc<InfluxDbImplementor>),
+}
+
+impl TimeseriesImplementor {
+    fn new(timeseries_implementor: &str, slight_state: &BasicState, name: &str) -> Result<Self> {
+        Ok(match timeseries_implementor {
+            "timeseries.filesystem" => Self::Filesystem(FilesystemImplementor::new(name)),
+            "timeseries.influxdb" => Self::InfluxDb(slight_state.try_connection(
+                timeseries_implementor,
+                name,
+                || InfluxDbImplementor::new(slight_state, name),
+            )?),
+            p => bail!(
+                "failed to match provided name (i.e., '{}') to any known host implementations",
+                p
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use super::{check_series, check_tags};
+
+    #[test]
+    fn check_series_test() {
+        assert!(check_series("http_requests_total").is_ok());
+        assert!(check_series("node.cpu-seconds").is_ok());
+        assert!(check_series("node:cpu").is_err());
+        assert!(check_series("").is_err());
+        assert!(check_series("../secrets").is_err());
+        assert!(check_series("cpu,host=a").is_err());
+        assert!(check_series(&"a".repeat(256)).is_err());
+    }
+
+    #[test]
+    fn check_tags_test() {
+        assert!(check_tags(&[]).is_ok());
+        assert!(check_tags(&[("host", "web 1"), ("path", "/a,b=c")]).is_ok());
+        assert!(check_tags(&[("_measurement", "cpu")]).is_err());
+        assert!(check_tags(&[("host-name", "web")]).is_err());
+        assert!(check_tags(&[("host", "a"), ("host", "b")]).is_err());
+        assert!(check_tags(&[("host", "")]).is_err());
+        assert!(check_tags(&[("host", "web\n1")]).is_err());
+        assert!(check_tags(&[("host", "web\\1")]).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+mod rules;
+
+/// The `SCHEME_NAME` defines the name under which a resource is
+/// identifiable by in a `ResourceMap`.
+const SCHEME_NAME: &str = "validation";
+/// The operations guests can retry if they time out (see `BasicState::with_idempotent_operations`).
+const IDEMPOTENT_OPERATIONS: &[&str] = &["validate", "validate-with"];
+/// The operations that only read, which audits of writes only leave out (see
+/// `BasicState::with_read_operations`).
+const READ_OPERATIONS: &[&str] = &["validate", "validate-with"];
+
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use uuid::Uuid;
+
+use slight_runtime::{impl_resource, resource::BasicState};
+
+pub use rules::Rule;
+
+/// It is mandatory to `use <interface>::*` due to `impl_resource!`.
+/// That is because `impl_resource!` accesses the `crate`'s
+/// `add_to_linker`, and not the `<interface>::add_to_linker` directly.
+use validation::*;
+wit_bindgen_wasmtime::export!("../../wit/validation.wit");
+wit_error_rs::impl_error!(validation::Error);
+slight_runtime::impl_from_anyhow!(validation::Error);
+
+/// The `Validation` structure is what will implement the `validation::Validation` trait
+/// coming from the generated code of off `validation.wit`.
+///
+/// It maintains a `host_state`.
+pub struct Validation {
+    host_state: ValidationState,
+}
+
+impl_resource!(
+    Validation,
+    validation::ValidationTables<Validation>,
+    ValidationState,
+    SCHEME_NAME.to_string()
+);
+
+/// This is the type of the `host_state` property from our `Validation` structure.
+///
+/// It holds:
+///     - the `slight_state` (of type `BasicState`) that contains common
+///     things received from the slight binary (i.e., the `resource_map`,
+///     the `config_type`, and the `config_toml_file_path`), and
+///     - the `rule_sets` the slightfile declares, by their name.
+///
+/// Like `parsing`, values are validated by the host itself, so there is no
+/// implementor to choose from.
+pub struct ValidationState {
+    slight_state: BasicState,
+    rule_sets: Arc<HashMap<String, Arc<Rule>>>,
+}
+
+impl ValidationState {
+    pub fn new(slight_state: BasicState) -> Self {
+        Self {
+            slight_state: slight_state
+                .with_idempotent_operations(IDEMPOTENT_OPERATIONS)
+                .with_read_operations(READ_OPERATIONS),
+            rule_sets: Arc::default(),
+        }
+    }
+
+    /// The rule sets guests can validate against by their name (see `validate-with`), which
+    /// are parsed (and so, checked) when the capability is linked.
+    pub fn with_rule_sets(mut self, rule_sets: HashMap<String, Rule>) -> Self {
+        self.rule_sets = Arc::new(
+            rule_sets
+                .into_iter()
+                .map(|(name, rule)| (name, Arc::new(rule)))
+                .collect(),
+        );
+        self
+    }
+}
+
+impl From<rules::Violation> for Violation {
+    fn from(violation: rules::Violation) -> Self {
+        Self {
+            path: violation.path,
+            rule: violation.rule.to_string(),
+            message: violation.message,
+        }
+    }
+}
+
+impl Validation {
+    /// Validates `value` against `rule`, charging its bytes to the capability's quota.
+    fn validate(
+        &self,
+        operation: &str,
+        target: &str,
+        value: &[u8],
+        rule: impl FnOnce() -> Result<Arc<Rule>>,
+    ) -> Result<Vec<Violation>, Error> {
+        let slight_state = &self.host_state.slight_state;
+        slight_state.instrument(SCHEME_NAME, operation, target, || {
+            slight_state.take_bytes(value.len())?;
+            let violations = rules::validate(value, &rule()?);
+            tracing::debug!("{} found {} violations", operation, violations.len());
+            Ok(violations.into_iter().map(Violation::from).collect())
+        })
+    }
+}
+
+impl validation::Validation for Validation {
+    type Validation = ValidationInner;
+
+    fn validation_open(&mut self) -> Result<Self::Validation, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
+        let inner = Self::Validation::new();
+
+        self.host_state
+            .slight_state
+            .resource_map
+            .lock()
+            .unwrap()
+            .set(inner.resource_descriptor.clone(), Box::new(inner.clone()));
+
+        Ok(inner)
+    }
+
+    fn validation_validate(
+        &mut self,
+        _self_: &Self::Validation,
+        value: PayloadParam<'_>,
+        rules: &str,
+    ) -> Result<Vec<Violation>, Error> {
+        self.validate("validate", "", value, || Ok(Arc::new(Rule::parse(rules)?)))
+    }
+
+    fn validation_validate_with(
+        &mut self,
+        _self_: &Self::Validation,
+        value: PayloadParam<'_>,
+        rule_set: &str,
+    ) -> Result<Vec<Violation>, Error> {
+        self.validate("validate-with", rule_set, value, || {
+            self.host_state
+                .rule_sets
+                .get(rule_set)
+                .cloned()
+                .ok_or_else(|| anyhow!("the slightfile declares no rule set '{}'", rule_set))
+        })
+    }
+}
+
+/// This is the type of the associated type coming from the `validation::Validation` trait
+/// implementation.
+///
+/// It holds a `resource_descriptor` (i.e., an UUID that uniquely identifies
+/// resource's instance).
+///
+/// It must `derive`:
+///     - `Debug` due to a constraint on the associated type.
+///     - `Clone` because the `ResourceMap` it will be added onto,
+///     must own its data.
+///
+/// It must be public because the implementation of `validation::Validation` cannot leak
+/// a private type.
+#[derive(Debug, Clone)]
+pub struct ValidationInner {
+    resource_descriptor: String,
+}
+
+impl ValidationInner {
+    fn new() -> Self {
+        Self {
+            resource_descriptor: Uuid::new_v4().to_string(),
+        }
+    }
+}
+
+impl slight_runtime::resource::Watch for ValidationInner {}
@@ -0,0 +1,296 @@
+use std::{collections::BTreeMap, fmt};
+
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer};
+use serde_json::Value;
+
+/// The types a value can be declared to be — `integer` is a `number` w/o a fraction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Type {
+    Null,
+    Boolean,
+    Number,
+    Integer,
+    String,
+    Array,
+    Object,
+}
+
+impl Type {
+    fn of(value: &Value) -> Self {
+        match value {
+            Value::Null => Self::Null,
+            Value::Bool(_) => Self::Boolean,
+            Value::Number(n) if n.is_i64() || n.is_u64() => Self::Integer,
+            Value::Number(_) => Self::Number,
+            Value::String(_) => Self::String,
+            Value::Array(_) => Self::Array,
+            Value::Object(_) => Self::Object,
+        }
+    }
+
+    fn matches(&self, value: &Value) -> bool {
+        match (self, Self::of(value)) {
+            (Self::Number, Self::Integer) => true,
+            (expected, actual) => *expected == actual,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Null => "null",
+            Self::Boolean => "boolean",
+            Self::Number => "number",
+            Self::Integer => "integer",
+            Self::String => "string",
+            Self::Array => "array",
+            Self::Object => "object",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A regex a string must match (anywhere in it, unless it's anchored w/ `^`, and `$`).
+#[derive(Clone, Debug)]
+pub struct Pattern(Regex);
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Regex::new(&pattern).map(Self).map_err(de::Error::custom)
+    }
+}
+
+/// A `Rule` declares what a value must be — its constraints only apply to the values they're
+/// about (e.g., `pattern` to strings, and `min` to numbers), so a value of another type breaks
+/// only its `type`, if it has one.
+///
+/// It's parsed from JSON, e.g.:
+/// ```json
+/// {
+///   "type": "object",
+///   "fields": {
+///     "name": { "required": true, "type": "string", "max_length": 64 },
+///     "age": { "type": "integer", "min": 0 },
+///     "tags": { "type": "array", "items": { "pattern": "^[a-z]+$" } }
+///   }
+/// }
+/// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// (fields only) whether the object the field is in must have it
+    #[serde(default)]
+    required: bool,
+    #[serde(rename = "type")]
+    ty: Option<Type>,
+    /// the least a number can be
+    min: Option<f64>,
+    /// the most a number can be
+    max: Option<f64>,
+    /// the fewest characters a string, or items an array can have
+    min_length: Option<usize>,
+    /// the most characters a string, or items an array can have
+    max_length: Option<usize>,
+    pattern: Option<Pattern>,
+    /// the values that are allowed, if only some are
+    one_of: Option<Vec<Value>>,
+    /// the rules of the fields of an object, by their name — others aren't validated
+    fields: Option<BTreeMap<String, Rule>>,
+    /// the rule of each item of an array
+    items: Option<Box<Rule>>,
+}
+
+impl Rule {
+    pub fn parse(rules: &str) -> Result<Self> {
+        serde_json::from_str(rules).map_err(|e| anyhow!("invalid rules: {}", e))
+    }
+}
+
+/// A rule a value broke, w/ where it is in the one validated (e.g., `address.zip`, or
+/// `tags[2]`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub path: String,
+    pub rule: &'static str,
+    pub message: String,
+}
+
+/// Validates `value` (JSON) against `rule`, returning every rule it breaks, in the order its
+/// parts are in — or a `syntax` violation, if it isn't JSON.
+pub fn validate(value: &[u8], rule: &Rule) -> Vec<Violation> {
+    let value = match serde_json::from_slice::<Value>(value) {
+        Ok(value) => value,
+        Err(e) => {
+            return vec![Violation {
+                path: String::new(),
+                rule: "syntax",
+                message: e.to_string(),
+            }]
+        }
+    };
+    let mut violations = Vec::new();
+    check(&value, rule, "", &mut violations);
+    violations
+}
+
+fn check(value: &Value, rule: &Rule, path: &str, violations: &mut Vec<Violation>) {
+    let mut broke = |rule: &'static str, message: String| {
+        violations.push(Violation {
+            path: path.to_string(),
+            rule,
+            message,
+        })
+    };
+    if let Some(ty) = rule.ty {
+        if !ty.matches(value) {
+            broke(
+                "type",
+                format!("expected {}, found {}", ty, Type::of(value)),
+            );
+            return;
+        }
+    }
+    if let Some(allowed) = &rule.one_of {
+        if !allowed.contains(value) {
+            let allowed = allowed.iter().map(Value::to_string).collect::<Vec<_>>();
+            broke("one_of", format!("expected one of {}", allowed.join(", ")));
+        }
+    }
+    match value {
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or(f64::NAN);
+            if let Some(min) = rule.min.filter(|min| n < *min) {
+                broke("min", format!("expected at least {}, found {}", min, n));
+            }
+            if let Some(max) = rule.max.filter(|max| n > *max) {
+                broke("max", format!("expected at most {}, found {}", max, n));
+            }
+        }
+        Value::String(s) => {
+            check_length(rule, s.chars().count(), "characters", &mut broke);
+            if let Some(Pattern(pattern)) = &rule.pattern {
+                if !pattern.is_match(s) {
+                    broke("pattern", format!("expected to match '{}'", pattern));
+                }
+            }
+        }
+        Value::Array(items) => {
+            check_length(rule, items.len(), "items", &mut broke);
+            if let Some(item_rule) = &rule.items {
+                for (i, item) in items.iter().enumerate() {
+                    check(item, item_rule, &format!("{}[{}]", path, i), violations);
+                }
+            }
+        }
+        Value::Object(object) => {
+            for (name, field_rule) in rule.fields.iter().flatten() {
+                let field_path = match path {
+                    "" => name.clone(),
+                    path => format!("{}.{}", path, name),
+                };
+                match object.get(name) {
+                    Some(field) => check(field, field_rule, &field_path, violations),
+                    None if field_rule.required => violations.push(Violation {
+                        path: field_path,
+                        rule: "required",
+                        message: "expected it to be present".to_string(),
+                    }),
+                    None => {}
+                }
+            }
+        }
+        Value::Null | Value::Bool(_) => {}
+    }
+}
+
+fn check_length(
+    rule: &Rule,
+    length: usize,
+    of: &str,
+    broke: &mut impl FnMut(&'static str, String),
+) {
+    if let Some(min) = rule.min_length.filter(|min| length < *min) {
+        broke(
+            "min_length",
+            format!("expected at least {} {}, found {}", min, of, length),
+        );
+    }
+    if let Some(max) = rule.max_length.filter(|max| length > *max) {
+        broke(
+            "max_length",
+            format!("expected at most {} {}, found {}", max, of, length),
+        );
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use super::{validate, Rule};
+
+    fn violations(value: &str, rules: &str) -> Vec<(String, &'static str)> {
+        validate(value.as_bytes(), &Rule::parse(rules).unwrap())
+            .into_iter()
+            .map(|v| (v.path, v.rule))
+            .collect()
+    }
+
+    const USER: &str = r#"{
+        "type": "object",
+        "fields": {
+            "name": { "required": true, "type": "string", "min_length": 1, "max_length": 8 },
+            "age": { "type": "integer", "min": 0, "max": 150 },
+            "role": { "one_of": ["admin", "user"] },
+            "tags": { "type": "array", "max_length": 2, "items": { "pattern": "^[a-z]+$" } },
+            "address": {
+                "type": "object",
+                "fields": { "zip": { "required": true, "pattern": "^[0-9]{5}$" } }
+            }
+        }
+    }"#;
+
+    #[test]
+    fn validate_test() {
+        assert!(violations(r#"{"name": "ada", "age": 36, "tags": ["x"]}"#, USER).is_empty());
+
+        // every violation is reported, not only the first
+        let value = r#"{
+            "age": 36.5,
+            "role": "root",
+            "tags": ["ok", "Not", "ok"],
+            "address": {}
+        }"#;
+        assert_eq!(
+            violations(value, USER),
+            vec![
+                ("address.zip".to_string(), "required"),
+                ("age".to_string(), "type"),
+                ("name".to_string(), "required"),
+                ("role".to_string(), "one_of"),
+                ("tags".to_string(), "max_length"),
+                ("tags[1]".to_string(), "pattern"),
+            ]
+        );
+
+        // a value of the wrong type only breaks its type
+        assert_eq!(
+            violations(r#"{"name": 7, "age": -1}"#, USER),
+            vec![("age".to_string(), "min"), ("name".to_string(), "type")]
+        );
+        assert_eq!(violations("[1]", USER), vec![("".to_string(), "type")]);
+        assert_eq!(violations("{", USER), vec![("".to_string(), "syntax")]);
+    }
+
+    #[test]
+    fn invalid_rules_test() {
+        assert!(Rule::parse(r#"{"type": "text"}"#).is_err());
+        assert!(Rule::parse(r#"{"pattern": "("}"#).is_err());
+        // unknown keys are typos, rather than rules to ignore
+        assert!(Rule::parse(r#"{"max_len": 3}"#).is_err());
+        assert!(Rule::parse(r#"{"fields": {"a": {"required": true}}}"#).is_ok());
+    }
+}
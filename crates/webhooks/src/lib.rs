@@ -0,0 +1,399 @@
+mod signature;
+
+/// The `SCHEME_NAME` defines the name under which a resource is
+/// identifiable by in a `ResourceMap`.
+const SCHEME_NAME: &str = "webhooks";
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use crossbeam_channel::Sender;
+use uuid::Uuid;
+
+pub use signature::{Rejection, SignatureScheme, DEFAULT_SIGNATURE_HEADER, DEFAULT_TOLERANCE_SECS};
+use slight_events_api::{Event, EventBuilder, EventBuilderV10};
+use slight_runtime::{
+    impl_resource,
+    resource::{BasicState, StateTable},
+};
+
+/// It is mandatory to `use <interface>::*` due to `impl_resource!`.
+/// That is because `impl_resource!` accesses the `crate`'s
+/// `add_to_linker`, and not the `<interface>::add_to_linker` directly.
+use webhooks::*;
+wit_bindgen_wasmtime::export!("../../wit/webhooks.wit");
+wit_error_rs::impl_error!(webhooks::Error);
+slight_runtime::impl_from_anyhow!(webhooks::Error);
+
+/// The name the `Inbox` of an app is shared under in its `StateTable`.
+pub const INBOX: &str = "slight.webhooks";
+
+/// The type of the events of deliveries whose provider's type of event isn't known.
+pub const WEBHOOK_EVENT_TYPE: &str = "slight.webhook.v1";
+
+/// How big a delivery's body can be, as providers send small payloads (e.g., Stripe's are
+/// at most a few hundred KBs) — bigger ones are rejected w/ a 413 before they're read whole.
+pub const MAX_PAYLOAD_SIZE: usize = 1024 * 1024;
+
+/// A webhook of the slightfile, which the http server exposes at its `path`.
+///
+/// It holds:
+///     - the `name` the guest watches it by,
+///     - the `path` providers post its deliveries to,
+///     - the `scheme` they're signed w/, and
+///     - the `secret` they're signed w/ (read from the secret stores).
+#[derive(Clone)]
+pub struct Webhook {
+    pub name: String,
+    pub path: String,
+    pub scheme: SignatureScheme,
+    secret: Arc<Vec<u8>>,
+}
+
+impl fmt::Debug for Webhook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Webhook")
+            .field("name", &self.name)
+            .field("path", &self.path)
+            .field("scheme", &self.scheme)
+            .finish()
+    }
+}
+
+/// What became of a delivery, which decides the response to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Receipt {
+    /// it was verified, and handed to the guest's listeners
+    Delivered,
+    /// it failed verification, so the guest never saw it
+    Rejected(Rejection),
+    /// it was verified, but the guest isn't listening to the webhook (yet), so the provider
+    /// is asked to deliver it again later
+    Unwatched,
+}
+
+impl Receipt {
+    /// The status of the response to the delivery.
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::Delivered => 202,
+            Self::Rejected(rejection) => rejection.status(),
+            Self::Unwatched => 503,
+        }
+    }
+}
+
+impl Webhook {
+    pub fn new(name: &str, path: &str, scheme: SignatureScheme, secret: Vec<u8>) -> Result<Self> {
+        if !path.starts_with('/') {
+            bail!(
+                "invalid path of webhook '{}': '{}' must start w/ a '/'",
+                name,
+                path
+            );
+        }
+        if secret.is_empty() {
+            bail!("invalid secret of webhook '{}': it's empty", name);
+        }
+        Ok(Self {
+            name: name.to_string(),
+            path: path.to_string(),
+            scheme,
+            secret: Arc::new(secret),
+        })
+    }
+
+    /// Verifies a delivery (i.e., a request's `body`, and the `header`s it came w/), and hands
+    /// it to the guest's listeners of the webhook as an event if it passes — `now` is in secs
+    /// since the unix epoch.
+    pub fn receive<'a>(
+        &self,
+        inbox: &Inbox,
+        header: impl Fn(&str) -> Option<&'a str>,
+        body: &[u8],
+        now: u64,
+    ) -> Receipt {
+        if let Err(rejection) =
+            self.scheme
+                .verify(&self.secret, header(self.scheme.header()), body, now)
+        {
+            tracing::warn!(
+                "rejecting a delivery of webhook '{}': {}",
+                self.name,
+                rejection
+            );
+            return Receipt::Rejected(rejection);
+        }
+        let event = match self.event(&header, body) {
+            Ok(event) => event,
+            Err(e) => {
+                tracing::error!("failed to build webhook event: {:#}", e);
+                return Receipt::Unwatched;
+            }
+        };
+        if inbox.deliver(&self.name, event) {
+            Receipt::Delivered
+        } else {
+            tracing::warn!(
+                "no one is watching webhook '{}', asking for the delivery again later",
+                self.name
+            );
+            Receipt::Unwatched
+        }
+    }
+
+    /// The event of a delivery, w/ the provider's id, and type of event, if they're known.
+    fn event<'a>(&self, header: &impl Fn(&str) -> Option<&'a str>, body: &[u8]) -> Result<Event> {
+        let (id, ty) = match self.scheme {
+            SignatureScheme::GitHub => (
+                header("x-github-delivery").map(str::to_string),
+                header("x-github-event").map(str::to_string),
+            ),
+            // Stripe's events carry their id, and type in their body
+            SignatureScheme::Stripe { .. } => {
+                let event = serde_json::from_slice::<serde_json::Value>(body).unwrap_or_default();
+                let field =
+                    |name: &str| event.get(name).and_then(|v| v.as_str()).map(str::to_string);
+                (field("id"), field("type"))
+            }
+            SignatureScheme::HmacSha256 { .. } => (None, None),
+        };
+        EventBuilderV10::new()
+            .id(id.unwrap_or_else(|| Uuid::new_v4().to_string()))
+            .source(format!("webhooks/{}", self.name))
+            .ty(ty.unwrap_or_else(|| WEBHOOK_EVENT_TYPE.to_string()))
+            .subject(&self.name)
+            .time(Utc::now())
+            .data(
+                header("content-type").unwrap_or("application/octet-stream"),
+                body.to_vec(),
+            )
+            .build()
+            .with_context(|| "failed to build event")
+    }
+}
+
+/// The `Inbox` hands the verified deliveries of webhooks to the guest's listeners of them (see
+/// `watch`), and is shared by the http server, and the webhooks capability of an app.
+#[derive(Clone, Default)]
+pub struct Inbox(Arc<Mutex<HashMap<String, Vec<Arc<Mutex<Sender<Event>>>>>>>);
+
+impl fmt::Debug for Inbox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let watched = self.0.lock().unwrap().keys().cloned().collect::<Vec<_>>();
+        f.debug_tuple("Inbox").field(&watched).finish()
+    }
+}
+
+impl Inbox {
+    /// Gets the inbox of the app whose `StateTable` this is, creating it if there's none yet.
+    pub fn shared(state_table: &mut StateTable) -> Self {
+        state_table
+            .shared(INBOX, Self::default)
+            .expect("the name of the webhooks' inbox is reserved")
+    }
+
+    pub fn listen(&self, webhook: &str, sender: Arc<Mutex<Sender<Event>>>) {
+        self.0
+            .lock()
+            .unwrap()
+            .entry(webhook.to_string())
+            .or_default()
+            .push(sender);
+    }
+
+    /// Sends an event to the listeners of `webhook`, forgetting the ones that are gone, and
+    /// tells whether any got it.
+    fn deliver(&self, webhook: &str, event: Event) -> bool {
+        let mut listeners = self.0.lock().unwrap();
+        let listeners = match listeners.get_mut(webhook) {
+            Some(listeners) => listeners,
+            None => return false,
+        };
+        listeners.retain(|sender| sender.lock().unwrap().send(event.clone()).is_ok());
+        !listeners.is_empty()
+    }
+}
+
+/// The `Webhooks` structure is what will implement the `webhooks::Webhooks` trait
+/// coming from the generated code of off `webhooks.wit`.
+///
+/// It maintains a `host_state`.
+pub struct Webhooks {
+    host_state: WebhooksState,
+}
+
+impl_resource!(
+    Webhooks,
+    webhooks::WebhooksTables<Webhooks>,
+    WebhooksState,
+    SCHEME_NAME.to_string()
+);
+
+/// This is the type of the `host_state` property from our `Webhooks` structure.
+///
+/// It holds:
+///     - the `names` of the webhooks of the slightfile (which the http capability serves),
+///     and
+///     - the `slight_state` (of type `BasicState`) that contains common
+///     things received from the slight binary (i.e., the `resource_map`,
+///     the `config_type`, and the `config_toml_file_path`).
+pub struct WebhooksState {
+    names: Vec<String>,
+    slight_state: BasicState,
+}
+
+impl WebhooksState {
+    pub fn new(names: Vec<String>, slight_state: BasicState) -> Self {
+        Self {
+            names,
+            slight_state,
+        }
+    }
+}
+
+impl webhooks::Webhooks for Webhooks {
+    type Webhooks = WebhooksInner;
+
+    fn webhooks_open(&mut self) -> Result<Self::Webhooks, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
+        let mut resource_map = self.host_state.slight_state.resource_map.lock().unwrap();
+        let inner = Self::Webhooks::new(Inbox::shared(&mut resource_map));
+        resource_map.set(inner.resource_descriptor.clone(), Box::new(inner.clone()));
+        Ok(inner)
+    }
+
+    fn webhooks_watch(
+        &mut self,
+        self_: &Self::Webhooks,
+        webhook: &str,
+    ) -> Result<Observable, Error> {
+        let names = &self.host_state.names;
+        Ok(self
+            .host_state
+            .slight_state
+            .instrument(SCHEME_NAME, "watch", webhook, || {
+                if !names.iter().any(|name| name == webhook) {
+                    bail!(
+                        "failed to watch webhook '{}': the slightfile has no such webhook (i.e., one of {:?})",
+                        webhook,
+                        names
+                    );
+                }
+                Ok(Observable {
+                    rd: self_.resource_descriptor.clone(),
+                    key: webhook.to_string(),
+                })
+            })?)
+    }
+}
+
+/// This is the type of the associated type coming from the `webhooks::Webhooks` trait
+/// implementation.
+///
+/// It holds:
+///     - the `inbox` the deliveries of the webhooks are handed to the guest through, and
+///     - a `resource_descriptor` (i.e., an UUID that uniquely identifies
+///     resource's instance).
+///
+/// It must `derive`:
+///     - `Debug` due to a constraint on the associated type.
+///     - `Clone` because the `ResourceMap` it will be added onto,
+///     must own its data.
+///
+/// It must be public because the implementation of `webhooks::Webhooks` cannot leak
+/// a private type.
+#[derive(Debug, Clone)]
+pub struct WebhooksInner {
+    inbox: Inbox,
+    resource_descriptor: String,
+}
+
+impl WebhooksInner {
+    fn new(inbox: Inbox) -> Self {
+        Self {
+            inbox,
+            resource_descriptor: Uuid::new_v4().to_string(),
+        }
+    }
+}
+
+impl slight_runtime::resource::Watch for WebhooksInner {
+    fn watch(&mut self, key: &str, sender: Arc<Mutex<Sender<Event>>>) -> Result<()> {
+        self.inbox.listen(key, sender);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::sync::{Arc, Mutex};
+
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    use slight_events_api::AttributesReader;
+
+    use super::{Inbox, Receipt, Rejection, SignatureScheme, Webhook};
+
+    #[test]
+    fn receive_test() {
+        let github = Webhook::new(
+            "github",
+            "/webhooks/github",
+            SignatureScheme::GitHub,
+            b"secret".to_vec(),
+        )
+        .unwrap();
+        let body = br#"{"ref":"refs/heads/main"}"#;
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(body);
+        let signature = format!(
+            "sha256={}",
+            mac.finalize()
+                .into_bytes()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>()
+        );
+        let headers = |name: &str| match name {
+            "x-hub-signature-256" => Some(signature.as_str()),
+            "x-github-event" => Some("push"),
+            "x-github-delivery" => Some("72d3162e"),
+            "content-type" => Some("application/json"),
+            _ => None,
+        };
+
+        // verified deliveries no one is watching are asked for again
+        let inbox = Inbox::default();
+        assert_eq!(
+            github.receive(&inbox, &headers, body, 0),
+            Receipt::Unwatched
+        );
+
+        let (tx, rx) = crossbeam_channel::unbounded();
+        inbox.listen("github", Arc::new(Mutex::new(tx)));
+        let receipt = github.receive(&inbox, &headers, body, 0);
+        assert_eq!(receipt, Receipt::Delivered);
+        assert_eq!(receipt.status(), 202);
+        let event = rx.try_recv().unwrap();
+        assert_eq!(event.ty(), "push");
+        assert_eq!(event.id(), "72d3162e");
+        assert_eq!(event.subject(), Some("github"));
+
+        // the guest never sees the ones that fail verification
+        let tampered = github.receive(&inbox, &headers, b"{}", 0);
+        assert_eq!(tampered, Receipt::Rejected(Rejection::Mismatch));
+        assert_eq!(tampered.status(), 401);
+        assert!(rx.try_recv().is_err());
+
+        assert!(
+            Webhook::new("github", "webhooks", SignatureScheme::GitHub, b"s".to_vec()).is_err()
+        );
+        assert!(Webhook::new("github", "/webhooks", SignatureScheme::GitHub, Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use std::fmt;
+
+use anyhow::{bail, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// The header the signatures of the `hmac-sha256` scheme travel in, unless a webhook says
+/// otherwise.
+pub const DEFAULT_SIGNATURE_HEADER: &str = "x-signature-256";
+
+/// How old a Stripe delivery can be (by its signed timestamp) before it's rejected as a replay,
+/// unless a webhook says otherwise.
+pub const DEFAULT_TOLERANCE_SECS: u64 = 300;
+
+/// How a provider signs the deliveries of its webhooks, all w/ an HMAC-SHA256 of the secret the
+/// host shares w/ it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SignatureScheme {
+    /// `X-Hub-Signature-256: sha256=<hex digest of the body>`
+    GitHub,
+    /// `Stripe-Signature: t=<timestamp>,v1=<hex digest of "<timestamp>.<body>">`, where
+    /// deliveries older than `tolerance_secs` are rejected
+    Stripe { tolerance_secs: u64 },
+    /// `<header>: <hex digest of the body>`, optionally prefixed w/ `sha256=`
+    HmacSha256 { header: String },
+}
+
+impl SignatureScheme {
+    pub fn parse(scheme: &str, header: Option<&str>, tolerance_secs: Option<u64>) -> Result<Self> {
+        let scheme = match scheme {
+            "github" => Self::GitHub,
+            "stripe" => Self::Stripe {
+                tolerance_secs: tolerance_secs.unwrap_or(DEFAULT_TOLERANCE_SECS),
+            },
+            "hmac-sha256" => Self::HmacSha256 {
+                header: header
+                    .unwrap_or(DEFAULT_SIGNATURE_HEADER)
+                    .to_ascii_lowercase(),
+            },
+            s => bail!(
+                "invalid signature scheme: '{}' (expected 'github', 'stripe', or 'hmac-sha256')",
+                s
+            ),
+        };
+        if header.is_some() && !matches!(scheme, Self::HmacSha256 { .. }) {
+            bail!("invalid signature_header: only the 'hmac-sha256' scheme takes one");
+        }
+        if tolerance_secs.is_some() && !matches!(scheme, Self::Stripe { .. }) {
+            bail!("invalid tolerance_secs: only the 'stripe' scheme takes one");
+        }
+        Ok(scheme)
+    }
+
+    /// The header a delivery's signature travels in.
+    pub fn header(&self) -> &str {
+        match self {
+            Self::GitHub => "x-hub-signature-256",
+            Self::Stripe { .. } => "stripe-signature",
+            Self::HmacSha256 { header } => header,
+        }
+    }
+
+    /// Verifies the `signature` of a delivery's `body` w/ the `secret`, in constant time —
+    /// `now` is in secs since the unix epoch.
+    pub fn verify(
+        &self,
+        secret: &[u8],
+        signature: Option<&str>,
+        body: &[u8],
+        now: u64,
+    ) -> Result<(), Rejection> {
+        let signature = signature.ok_or(Rejection::Unsigned)?.trim();
+        match self {
+            Self::GitHub => {
+                let digest = signature
+                    .strip_prefix("sha256=")
+                    .and_then(unhex)
+                    .ok_or(Rejection::Malformed)?;
+                matches(secret, &[body], &digest)
+            }
+            Self::Stripe { tolerance_secs } => {
+                let mut timestamp = None;
+                let mut digests = Vec::new();
+                for part in signature.split(',') {
+                    match part.trim().split_once('=') {
+                        Some(("t", t)) => timestamp = t.parse::<u64>().ok(),
+                        Some(("v1", digest)) => digests.push(unhex(digest)),
+                        // other schemes (e.g., Stripe's test mode `v0`) are ignored
+                        _ => {}
+                    }
+                }
+                let timestamp = timestamp.ok_or(Rejection::Malformed)?;
+                if digests.is_empty() {
+                    return Err(Rejection::Malformed);
+                }
+                // the timestamp is part of what's signed, so it can't be forged
+                let signed_at = timestamp.to_string();
+                let payload: [&[u8]; 3] = [signed_at.as_bytes(), b".", body];
+                if !digests
+                    .into_iter()
+                    .flatten()
+                    .any(|digest| matches(secret, &payload, &digest).is_ok())
+                {
+                    return Err(Rejection::Mismatch);
+                }
+                if now.abs_diff(timestamp) > *tolerance_secs {
+                    return Err(Rejection::Stale);
+                }
+                Ok(())
+            }
+            Self::HmacSha256 { .. } => {
+                let digest = unhex(signature.strip_prefix("sha256=").unwrap_or(signature))
+                    .ok_or(Rejection::Malformed)?;
+                matches(secret, &[body], &digest)
+            }
+        }
+    }
+}
+
+/// Why a delivery was rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// the delivery has no signature
+    Unsigned,
+    /// the signature isn't in the format of the scheme
+    Malformed,
+    /// the signature doesn't match the delivery (i.e., it, or the delivery were tampered w/,
+    /// or it was signed w/ another secret)
+    Mismatch,
+    /// the delivery was signed longer ago than the scheme tolerates (i.e., it's a replay)
+    Stale,
+}
+
+impl Rejection {
+    /// The status of the response to a rejected delivery.
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::Malformed => 400,
+            Self::Unsigned | Self::Mismatch | Self::Stale => 401,
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsigned => write!(f, "the delivery isn't signed"),
+            Self::Malformed => write!(f, "the delivery's signature is malformed"),
+            Self::Mismatch => write!(f, "the delivery's signature doesn't match it"),
+            Self::Stale => write!(f, "the delivery was signed too long ago"),
+        }
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+/// Whether `digest` is the HMAC-SHA256 of the concatenated `parts` w/ the `secret`.
+fn matches(secret: &[u8], parts: &[&[u8]], digest: &[u8]) -> Result<(), Rejection> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.verify_slice(digest).map_err(|_| Rejection::Mismatch)
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 == 1 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod unittests {
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    use super::{Rejection, SignatureScheme};
+
+    fn digest(secret: &[u8], payload: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+        mac.update(payload);
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    #[test]
+    fn github_test() {
+        let github = SignatureScheme::parse("github", None, None).unwrap();
+        assert_eq!(github.header(), "x-hub-signature-256");
+        let body = br#"{"action":"opened"}"#;
+        let signature = format!("sha256={}", digest(b"secret", body));
+        assert!(github.verify(b"secret", Some(&signature), body, 0).is_ok());
+        assert_eq!(
+            github.verify(b"other", Some(&signature), body, 0),
+            Err(Rejection::Mismatch)
+        );
+        assert_eq!(
+            github.verify(b"secret", Some(&signature), b"{}", 0),
+            Err(Rejection::Mismatch)
+        );
+        assert_eq!(
+            github.verify(b"secret", None, body, 0),
+            Err(Rejection::Unsigned)
+        );
+        assert_eq!(
+            github.verify(b"secret", Some("sha1=abc"), body, 0),
+            Err(Rejection::Malformed)
+        );
+    }
+
+    #[test]
+    fn stripe_test() {
+        let stripe = SignatureScheme::parse("stripe", None, Some(60)).unwrap();
+        let body = br#"{"id":"evt_1","type":"invoice.paid"}"#;
+        let signed = [b"1700000000.".as_slice(), body.as_slice()].concat();
+        let signature = format!(
+            "t=1700000000,v1={},v1={},v0=ignored",
+            digest(b"rotated", &signed),
+            digest(b"secret", &signed)
+        );
+        // any of the `v1` signatures matching is enough (i.e., while a secret is rotated)
+        assert!(stripe
+            .verify(b"secret", Some(&signature), body, 1_700_000_030)
+            .is_ok());
+        assert_eq!(
+            stripe.verify(b"secret", Some(&signature), body, 1_700_000_061),
+            Err(Rejection::Stale)
+        );
+        // the timestamp is signed, so it can't be moved forward
+        let replayed = signature.replace("t=1700000000", "t=1700000060");
+        assert_eq!(
+            stripe.verify(b"secret", Some(&replayed), body, 1_700_000_061),
+            Err(Rejection::Mismatch)
+        );
+        assert_eq!(
+            stripe.verify(b"secret", Some("v1=abcd"), body, 0),
+            Err(Rejection::Malformed)
+        );
+    }
+
+    #[test]
+    fn hmac_sha256_test() {
+        let scheme = SignatureScheme::parse("hmac-sha256", Some("X-Acme-Signature"), None).unwrap();
+        assert_eq!(scheme.header(), "x-acme-signature");
+        let signature = digest(b"secret", b"payload");
+        assert!(scheme
+            .verify(b"secret", Some(&signature), b"payload", 0)
+            .is_ok());
+        assert!(scheme
+            .verify(
+                b"secret",
+                Some(&format!("sha256={}", signature)),
+                b"payload",
+                0
+            )
+            .is_ok());
+        assert_eq!(
+            scheme.verify(b"secret", Some("zz"), b"payload", 0),
+            Err(Rejection::Malformed)
+        );
+
+        assert!(SignatureScheme::parse("github", Some("x-signature"), None).is_err());
+        assert!(SignatureScheme::parse("stripe-v2", None, None).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::{HashSet, VecDeque};
+
+use anyhow::{bail, Result};
+
+/// How many transitions of an instance's history are kept, unless the workflow says otherwise.
+pub const DEFAULT_MAX_HISTORY: usize = 100;
+
+/// A transition a workflow allows: from a state, on an event, to another (or the same) state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transition {
+    pub from: String,
+    pub event: String,
+    pub to: String,
+}
+
+impl Transition {
+    pub fn new(from: &str, event: &str, to: &str) -> Self {
+        Self {
+            from: from.to_string(),
+            event: event.to_string(),
+            to: to.to_string(),
+        }
+    }
+}
+
+/// `Definition` is the state machine the instances of a workflow follow, as declared in the
+/// slightfile: its states, the one instances start in, the final ones (i.e., the ones no
+/// transitions leave), and the transitions between them.
+///
+/// It's validated when it's made (i.e., when slight starts), so a definition that's
+/// inconsistent (e.g., w/ a transition to a state it doesn't have) fails the run, rather
+/// than the first transition of an instance — and, as no state has two transitions on the
+/// same event, every transition of an instance is deterministic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Definition {
+    pub name: String,
+    pub initial: String,
+    pub states: Vec<String>,
+    pub final_states: Vec<String>,
+    pub transitions: Vec<Transition>,
+    /// how many transitions of an instance's history are kept, the oldest are dropped past it
+    pub max_history: usize,
+}
+
+impl Definition {
+    pub fn new(
+        name: &str,
+        initial: &str,
+        states: &[String],
+        final_states: &[String],
+        transitions: &[Transition],
+        max_history: Option<usize>,
+    ) -> Result<Self> {
+        if states.is_empty() {
+            bail!("invalid workflow '{}': it has no states", name);
+        }
+        let mut known = HashSet::new();
+        for state in states {
+            if state.is_empty() {
+                bail!("invalid workflow '{}': it has a state w/o a name", name);
+            }
+            if !known.insert(state.as_str()) {
+                bail!(
+                    "invalid workflow '{}': state '{}' is declared twice",
+                    name,
+                    state
+                );
+            }
+        }
+        let check_state = |state: &str, what: &str| -> Result<()> {
+            if !known.contains(state) {
+                bail!(
+                    "invalid workflow '{}': its {} '{}' isn't one of its states (i.e., one of {:?})",
+                    name,
+                    what,
+                    state,
+                    states
+                );
+            }
+            Ok(())
+        };
+        check_state(initial, "initial state")?;
+        for state in final_states {
+            check_state(state, "final state")?;
+        }
+
+        let mut events = HashSet::new();
+        for transition in transitions {
+            check_state(&transition.from, "transition's from state")?;
+            check_state(&transition.to, "transition's to state")?;
+            if transition.event.is_empty() {
+                bail!(
+                    "invalid workflow '{}': a transition from '{}' has no event",
+                    name,
+                    transition.from
+                );
+            }
+            if final_states.contains(&transition.from) {
+                bail!(
+                    "invalid workflow '{}': '{}' is a final state, but it has a transition on '{}'",
+                    name,
+                    transition.from,
+                    transition.event
+                );
+            }
+            if !events.insert((transition.from.as_str(), transition.event.as_str())) {
+                bail!(
+                    "invalid workflow '{}': '{}' has two transitions on '{}', so it'd be ambiguous which one an instance makes",
+                    name,
+                    transition.from,
+                    transition.event
+                );
+            }
+        }
+
+        let definition = Self {
+            name: name.to_string(),
+            initial: initial.to_string(),
+            states: states.to_vec(),
+            final_states: final_states.to_vec(),
+            transitions: transitions.to_vec(),
+            max_history: max_history.unwrap_or(DEFAULT_MAX_HISTORY),
+        };
+        // unreachable states are likely a mistake, but they're harmless
+        let reachable = definition.reachable();
+        for state in states.iter().filter(|s| !reachable.contains(s.as_str())) {
+            tracing::warn!(
+                "workflow '{}': state '{}' can't be reached from its initial state ('{}')",
+                name,
+                state,
+                initial
+            );
+        }
+        Ok(definition)
+    }
+
+    /// The state an instance in `state` transitions to on `event`, failing if there's no
+    /// transition on it from there.
+    pub fn next(&self, state: &str, event: &str) -> Result<&str> {
+        if !self.states.iter().any(|s| s == state) {
+            bail!(
+                "invalid transition: the instance is in state '{}', which workflow '{}' doesn't have (anymore?)",
+                state,
+                self.name
+            );
+        }
+        match self
+            .transitions
+            .iter()
+            .find(|t| t.from == state && t.event == event)
+        {
+            Some(transition) => Ok(transition.to.as_str()),
+            None if self.is_final(state) => bail!(
+                "invalid transition: the instance is in '{}', a final state of workflow '{}', which it can't transition from (i.e., on '{}')",
+                state,
+                self.name,
+                event
+            ),
+            None => bail!(
+                "invalid transition: workflow '{}' has no transition on '{}' from '{}' (i.e., it allows {:?} from there)",
+                self.name,
+                event,
+                state,
+                self.events(state)
+            ),
+        }
+    }
+
+    /// The events an instance in `state` can transition on.
+    pub fn events(&self, state: &str) -> Vec<&str> {
+        self.transitions
+            .iter()
+            .filter(|t| t.from == state)
+            .map(|t| t.event.as_str())
+            .collect()
+    }
+
+    pub fn is_final(&self, state: &str) -> bool {
+        self.final_states.iter().any(|s| s == state)
+    }
+
+    /// The states instances can reach from the initial one.
+    fn reachable(&self) -> HashSet<&str> {
+        let mut reachable = HashSet::from([self.initial.as_str()]);
+        let mut next = VecDeque::from([self.initial.as_str()]);
+        while let Some(state) = next.pop_front() {
+            for transition in self.transitions.iter().filter(|t| t.from == state) {
+                if reachable.insert(transition.to.as_str()) {
+                    next.push_back(transition.to.as_str());
+                }
+            }
+        }
+        reachable
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+
+    use super::{Definition, Transition, DEFAULT_MAX_HISTORY};
+
+    fn names(states: &[&str]) -> Vec<String> {
+        states.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn order(transitions: &[Transition]) -> Result<Definition> {
+        Definition::new(
+            "order",
+            "pending",
+            &names(&["pending", "paid", "shipped", "cancelled"]),
+            &names(&["shipped", "cancelled"]),
+            transitions,
+            None,
+        )
+    }
+
+    #[test]
+    fn next_test() -> Result<()> {
+        let order = order(&[
+            Transition::new("pending", "pay", "paid"),
+            Transition::new("pending", "cancel", "cancelled"),
+            Transition::new("paid", "ship", "shipped"),
+        ])?;
+        assert_eq!(order.max_history, DEFAULT_MAX_HISTORY);
+        assert_eq!(order.next("pending", "pay")?, "paid");
+        assert_eq!(order.events("pending"), vec!["pay", "cancel"]);
+
+        let e = order.next("pending", "ship").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "invalid transition: workflow 'order' has no transition on 'ship' from 'pending' (i.e., it allows [\"pay\", \"cancel\"] from there)"
+        );
+        assert!(order.is_final("shipped"));
+        assert!(order.next("shipped", "pay").is_err());
+        assert!(order.next("refunded", "pay").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_definition_test() {
+        for (transitions, expected) in [
+            (
+                vec![Transition::new("pending", "pay", "settled")],
+                "transition's to state 'settled'",
+            ),
+            (
+                vec![
+                    Transition::new("pending", "pay", "paid"),
+                    Transition::new("pending", "pay", "cancelled"),
+                ],
+                "'pending' has two transitions on 'pay'",
+            ),
+            (
+                vec![Transition::new("shipped", "return", "pending")],
+                "'shipped' is a final state",
+            ),
+            (vec![Transition::new("pending", "", "paid")], "has no event"),
+        ] {
+            let e = order(&transitions).unwrap_err();
+            assert!(e.to_string().contains(expected), "{}", e);
+        }
+
+        assert!(Definition::new("order", "pending", &[], &[], &[], None).is_err());
+        assert!(Definition::new(
+            "order",
+            "draft",
+            &names(&["pending", "paid"]),
+            &[],
+            &[],
+            None
+        )
+        .is_err());
+        assert!(Definition::new(
+            "order",
+            "pending",
+            &names(&["pending", "pending"]),
+            &[],
+            &[],
+            None
+        )
+        .is_err());
+    }
+}
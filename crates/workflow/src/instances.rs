@@ -0,0 +1,228 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use slight_kv::HostKv;
+use slight_runtime::resource::BasicState;
+use uuid::Uuid;
+
+use crate::definition::Definition;
+
+/// A transition an instance made, as kept in its history.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransitionRecord {
+    pub from: String,
+    pub event: String,
+    pub to: String,
+    /// when the transition was made, in secs since the unix epoch
+    pub at: u64,
+}
+
+/// An instance of a workflow, as kept in the kv store.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstanceRecord {
+    pub id: String,
+    pub workflow: String,
+    pub state: String,
+    /// the transitions the instance made, oldest first (up to the workflow's `max_history`)
+    pub history: Vec<TransitionRecord>,
+}
+
+/// `Instances` keeps the instances of a workflow in a kv store, so they survive restarts,
+/// and can be transitioned by many hosts sharing the store.
+///
+/// Each instance is kept under its id, and every transition is checked against the
+/// workflow's definition, and made w/ a compare-and-swap — a transition that races another
+/// one of the same instance is checked again from the state the other one left it in, so an
+/// instance never makes a transition its workflow doesn't allow.
+#[derive(Debug, Clone)]
+pub struct Instances {
+    kv: HostKv,
+    definition: Arc<Definition>,
+}
+
+impl Instances {
+    pub fn open(
+        workflow_store: &str,
+        slight_state: &BasicState,
+        definition: Definition,
+    ) -> Result<Self> {
+        Ok(Self {
+            kv: HostKv::open(
+                workflow_store,
+                slight_state,
+                &format!("slight-workflow-{}", definition.name),
+            )?,
+            definition: Arc::new(definition),
+        })
+    }
+
+    pub fn definition(&self) -> &Definition {
+        &self.definition
+    }
+
+    /// Starts an instance in the initial state, w/ an `id` (or a random one, if it's empty).
+    pub fn start(&self, id: &str) -> Result<InstanceRecord> {
+        let id = match id {
+            "" => Uuid::new_v4().to_string(),
+            id => id.to_string(),
+        };
+        let instance = InstanceRecord {
+            id,
+            workflow: self.definition.name.clone(),
+            state: self.definition.initial.clone(),
+            history: Vec::new(),
+        };
+        if !self.kv.compare_and_swap(
+            &instance_key(&instance.id),
+            None,
+            &serde_json::to_vec(&instance)?,
+        )? {
+            bail!(
+                "an instance of workflow '{}' w/ id '{}' exists already",
+                self.definition.name,
+                instance.id
+            );
+        }
+        tracing::info!(
+            "started instance '{}' of workflow '{}' in '{}'",
+            instance.id,
+            instance.workflow,
+            instance.state
+        );
+        Ok(instance)
+    }
+
+    pub fn get(&self, id: &str) -> Result<InstanceRecord> {
+        Ok(self.load(id)?.1)
+    }
+
+    /// Transitions an instance on `event`, failing if its workflow has no transition on it
+    /// from the instance's current state.
+    pub fn transition(&self, id: &str, event: &str, now: u64) -> Result<InstanceRecord> {
+        loop {
+            let (raw, mut instance) = self.load(id)?;
+            let from = instance.state.clone();
+            let to = self.definition.next(&from, event)?.to_string();
+            instance.state = to.clone();
+            instance.history.push(TransitionRecord {
+                from: from.clone(),
+                event: event.to_string(),
+                to,
+                at: now,
+            });
+            let dropped = instance
+                .history
+                .len()
+                .saturating_sub(self.definition.max_history);
+            instance.history.drain(..dropped);
+            // someone else may have transitioned it in the meantime
+            if self.kv.compare_and_swap(
+                &instance_key(id),
+                Some(&raw),
+                &serde_json::to_vec(&instance)?,
+            )? {
+                tracing::info!(
+                    "instance '{}' of workflow '{}' transitioned from '{}' to '{}' on '{}'",
+                    id,
+                    instance.workflow,
+                    from,
+                    instance.state,
+                    event
+                );
+                return Ok(instance);
+            }
+        }
+    }
+
+    fn load(&self, id: &str) -> Result<(Vec<u8>, InstanceRecord)> {
+        let raw = match self.kv.get(&instance_key(id))? {
+            Some(raw) => raw,
+            None => bail!(
+                "instance '{}' of workflow '{}' not found",
+                id,
+                self.definition.name
+            ),
+        };
+        let instance = serde_json::from_slice(&raw)
+            .with_context(|| format!("instance '{}' is corrupted", id))?;
+        Ok((raw, instance))
+    }
+}
+
+fn instance_key(id: &str) -> Vec<u8> {
+    format!("instance/{}", id).into_bytes()
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+    use slight_runtime::resource::BasicState;
+    use uuid::Uuid;
+
+    use super::Instances;
+    use crate::definition::{Definition, Transition};
+
+    const NOW: u64 = 1_000_000;
+
+    fn instances(max_history: Option<usize>) -> Result<Instances> {
+        let states = ["draft", "review", "published"].map(str::to_string);
+        let definition = Definition::new(
+            &Uuid::new_v4().to_string(),
+            "draft",
+            &states,
+            &["published".to_string()],
+            &[
+                Transition::new("draft", "submit", "review"),
+                Transition::new("review", "reject", "draft"),
+                Transition::new("review", "approve", "published"),
+            ],
+            max_history,
+        )?;
+        Instances::open("kv.filesystem", &BasicState::default(), definition)
+    }
+
+    #[test]
+    fn transition_test() -> Result<()> {
+        let instances = instances(None)?;
+        let started = instances.start("post-1")?;
+        assert_eq!(started.state, "draft");
+        assert!(instances.start("post-1").is_err());
+
+        instances.transition("post-1", "submit", NOW)?;
+        // an invalid transition leaves the instance as it was
+        assert!(instances.transition("post-1", "submit", NOW).is_err());
+        let published = instances.transition("post-1", "approve", NOW + 1)?;
+        assert_eq!(published.state, "published");
+        assert_eq!(published.history.len(), 2);
+        assert_eq!(published.history[1].from, "review");
+        assert_eq!(published.history[1].at, NOW + 1);
+        assert_eq!(instances.get("post-1")?, published);
+
+        assert!(instances.transition("post-1", "reject", NOW).is_err());
+        assert!(instances.get("post-2").is_err());
+        assert!(!instances.start("")?.id.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn history_test() -> Result<()> {
+        let instances = instances(Some(2))?;
+        instances.start("post-1")?;
+        for event in ["submit", "reject", "submit"] {
+            instances.transition("post-1", event, NOW)?;
+        }
+        let instance = instances.get("post-1")?;
+        assert_eq!(instance.state, "review");
+        // the oldest transitions are dropped
+        assert_eq!(
+            instance
+                .history
+                .iter()
+                .map(|t| t.event.as_str())
+                .collect::<Vec<_>>(),
+            vec!["reject", "submit"]
+        );
+        Ok(())
+    }
+}
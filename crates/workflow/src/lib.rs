@@ -0,0 +1,245 @@
+mod definition;
+mod instances;
+
+/// The `SCHEME_NAME` defines the name under which a resource is
+/// identifiable by in a `ResourceMap`.
+const SCHEME_NAME: &str = "workflow";
+/// The operations guests can retry if they time out (see `BasicState::with_idempotent_operations`).
+const IDEMPOTENT_OPERATIONS: &[&str] = &["get", "events"];
+/// The operations that only read, which audits of writes only leave out (see
+/// `BasicState::with_read_operations`).
+const READ_OPERATIONS: &[&str] = &["get", "events"];
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+use uuid::Uuid;
+
+pub use definition::{Definition, Transition};
+use instances::{InstanceRecord, Instances};
+use slight_runtime::{impl_resource, resource::BasicState};
+
+/// It is mandatory to `use <interface>::*` due to `impl_resource!`.
+/// That is because `impl_resource!` accesses the `crate`'s
+/// `add_to_linker`, and not the `<interface>::add_to_linker` directly.
+use workflow::*;
+wit_bindgen_wasmtime::export!("../../wit/workflow.wit");
+wit_error_rs::impl_error!(workflow::Error);
+slight_runtime::impl_from_anyhow!(workflow::Error);
+
+/// The `Workflow` structure is what will implement the `workflow::Workflow` trait
+/// coming from the generated code of off `workflow.wit`.
+///
+/// It maintains a `host_state`.
+pub struct Workflow {
+    host_state: WorkflowState,
+}
+
+impl_resource!(
+    Workflow,
+    workflow::WorkflowTables<Workflow>,
+    WorkflowState,
+    SCHEME_NAME.to_string()
+);
+
+/// This is the type of the `host_state` property from our `Workflow` structure.
+///
+/// It holds:
+///     - the `definitions` of the workflows of the slightfile (validated when slight
+///     starts),
+///     - a `workflow_store` `String` — this comes directly from a user's `slightfile`
+///     (i.e., its `workflow.store`), and it is the kv implementor instances are kept in, and
+///     - the `slight_state` (of type `BasicState`) that contains common
+///     things received from the slight binary (i.e., the `resource_map`,
+///     the `config_type`, and the `config_toml_file_path`).
+pub struct WorkflowState {
+    definitions: Vec<Definition>,
+    workflow_store: String,
+    slight_state: BasicState,
+}
+
+impl WorkflowState {
+    pub fn new(
+        definitions: Vec<Definition>,
+        workflow_store: String,
+        slight_state: BasicState,
+    ) -> Self {
+        Self {
+            definitions,
+            workflow_store,
+            slight_state: slight_state
+                .with_idempotent_operations(IDEMPOTENT_OPERATIONS)
+                .with_read_operations(READ_OPERATIONS),
+        }
+    }
+
+    fn definition(&self, name: &str) -> Result<Definition> {
+        match self.definitions.iter().find(|d| d.name == name) {
+            Some(definition) => Ok(definition.clone()),
+            None => bail!(
+                "failed to open workflow: the slightfile has no workflow '{}' (i.e., one of {:?})",
+                name,
+                self.definitions
+                    .iter()
+                    .map(|d| d.name.as_str())
+                    .collect::<Vec<_>>()
+            ),
+        }
+    }
+}
+
+impl workflow::Workflow for Workflow {
+    type Workflow = WorkflowInner;
+
+    fn workflow_open(&mut self, name: &str) -> Result<Self::Workflow, Error> {
+        self.host_state.slight_state.permit(SCHEME_NAME, "open")?;
+        // populate our inner workflow object w/ the state received from `slight`
+        // (i.e., the workflow's definition, and what kv implementor instances are kept in).
+        let definition = self.host_state.definition(name)?;
+        let inner = Self::Workflow::new(
+            &self.host_state.workflow_store,
+            &self.host_state.slight_state,
+            definition,
+        )?;
+
+        self.host_state
+            .slight_state
+            .resource_map
+            .lock()
+            .unwrap()
+            .set(inner.resource_descriptor.clone(), Box::new(inner.clone()));
+
+        Ok(inner)
+    }
+
+    fn workflow_start(
+        &mut self,
+        self_: &Self::Workflow,
+        instance_id: &str,
+    ) -> Result<WorkflowInstance, Error> {
+        let instance = self.host_state.slight_state.instrument(
+            SCHEME_NAME,
+            "start",
+            &self_.target(instance_id),
+            || self_.instances.start(instance_id),
+        )?;
+        Ok(self_.instance(instance))
+    }
+
+    fn workflow_get(
+        &mut self,
+        self_: &Self::Workflow,
+        instance_id: &str,
+    ) -> Result<WorkflowInstance, Error> {
+        let instance = self.host_state.slight_state.instrument(
+            SCHEME_NAME,
+            "get",
+            &self_.target(instance_id),
+            || self_.instances.get(instance_id),
+        )?;
+        Ok(self_.instance(instance))
+    }
+
+    fn workflow_transition(
+        &mut self,
+        self_: &Self::Workflow,
+        instance_id: &str,
+        event: &str,
+    ) -> Result<WorkflowInstance, Error> {
+        let instance = self.host_state.slight_state.instrument(
+            SCHEME_NAME,
+            "transition",
+            &self_.target(instance_id),
+            || self_.instances.transition(instance_id, event, now_secs()),
+        )?;
+        Ok(self_.instance(instance))
+    }
+
+    fn workflow_events(
+        &mut self,
+        self_: &Self::Workflow,
+        instance_id: &str,
+    ) -> Result<Vec<String>, Error> {
+        let instance = self.host_state.slight_state.instrument(
+            SCHEME_NAME,
+            "events",
+            &self_.target(instance_id),
+            || self_.instances.get(instance_id),
+        )?;
+        Ok(self_
+            .instances
+            .definition()
+            .events(&instance.state)
+            .into_iter()
+            .map(str::to_string)
+            .collect())
+    }
+}
+
+/// The current time, in seconds since the unix epoch.
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// This is the type of the associated type coming from the `workflow::Workflow` trait
+/// implementation.
+///
+/// It holds:
+///     - the `instances` of the workflow (which know its definition), and
+///     - a `resource_descriptor` (i.e., an UUID that uniquely identifies
+///     resource's instance).
+///
+/// It must `derive`:
+///     - `Debug` due to a constraint on the associated type.
+///     - `Clone` because the `ResourceMap` it will be added onto,
+///     must own its data.
+///
+/// It must be public because the implementation of `workflow::Workflow` cannot leak
+/// a private type.
+#[derive(Debug, Clone)]
+pub struct WorkflowInner {
+    instances: Instances,
+    resource_descriptor: String,
+}
+
+impl WorkflowInner {
+    fn new(
+        workflow_store: &str,
+        slight_state: &BasicState,
+        definition: Definition,
+    ) -> Result<Self> {
+        Ok(Self {
+            instances: Instances::open(workflow_store, slight_state, definition)?,
+            resource_descriptor: Uuid::new_v4().to_string(),
+        })
+    }
+
+    /// The target of a call on an instance (i.e., `<workflow>/<instance id>`).
+    fn target(&self, instance_id: &str) -> String {
+        format!("{}/{}", self.instances.definition().name, instance_id)
+    }
+
+    fn instance(&self, instance: InstanceRecord) -> WorkflowInstance {
+        WorkflowInstance {
+            finished: self.instances.definition().is_final(&instance.state),
+            id: instance.id,
+            workflow: instance.workflow,
+            state: instance.state,
+            history: instance
+                .history
+                .into_iter()
+                .map(|transition| WorkflowTransition {
+                    from_state: transition.from,
+                    event: transition.event,
+                    to_state: transition.to,
+                    at: transition.at,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl slight_runtime::resource::Watch for WorkflowInner {}
@@ -13,5 +13,10 @@ fn main() -> Result<()> {
     configs.set(&rand_key, "Hello, World!".as_bytes())?;
     dbg!(String::from_utf8(configs.get(&rand_key)?)?);
     // ^^^ if you look in your spiderlightning config file after this, you should have the configs show up!
+    dbg!(String::from_utf8(configs.get_or_default(
+        "THIS_CONFIG_IS_NEVER_SET",
+        "a default".as_bytes()
+    )?)?);
+    // ^^^ absent configs get their default from the slightfile's `defaults`, if it declares one, or else this one
     Ok(())
 }
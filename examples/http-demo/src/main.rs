@@ -11,6 +11,7 @@ fn main() -> Result<()> {
     let router_with_route = router
         .get("/hello", "handle_hello")?
         .get("/foo", "handle_foo")?
+        .get("/greet", "handle_greet")?
         .put("/bar", "handle_bar")?
         .post("/upload", "upload")?
         .delete("/delete-file", "delete_file_handler")?;
@@ -41,6 +42,19 @@ fn handle_foo(request: Request) -> Result<Response, Error> {
     })
 }
 
+#[register_handler]
+fn handle_greet(_req: Request) -> Result<Response, Error> {
+    // the host renders `templates/greet.hbs` w/ the body as its data
+    Ok(Response {
+        headers: Some(vec![(
+            "x-slight-template".to_string(),
+            "greet.hbs".to_string(),
+        )]),
+        body: Some(r#"{"name": "spiderlightning"}"#.as_bytes().to_vec()),
+        status: 200,
+    })
+}
+
 #[register_handler]
 fn handle_bar(request: Request) -> Result<Response, Error> {
     assert_eq!(request.method, Method::Put);
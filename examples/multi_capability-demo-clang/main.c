@@ -53,8 +53,10 @@ __attribute__((export_name("main"))) int main(int argc, char *argv[])
     // save msg to kv
     char buf[12];
     snprintf(buf, 12, "mykey_%d", i);
-    kv_string_t key;
-    kv_string_set(&key, buf);
+    kv_payload_t key = {
+      .ptr = (uint8_t *)buf,
+      .len = strlen(buf)
+    };
     kv_expected_unit_error_t ret;
     kv_payload_t payload = {
       .ptr = msg.ptr,
@@ -74,8 +76,10 @@ __attribute__((export_name("main"))) int main(int argc, char *argv[])
   {
     char buf[12];
     snprintf(buf, 12, "mykey_%d", i);
-    kv_string_t key;
-    kv_string_set(&key, buf);
+    kv_payload_t key = {
+      .ptr = (uint8_t *)buf,
+      .len = strlen(buf)
+    };
     // call kv.get
     kv_payload_t hostvalue;
     kv_expected_payload_error_t ret;
@@ -27,15 +27,15 @@ fn main() -> Result<()> {
     }
 
     let all_messages = messages_vec.join("\n");
-    kv.set("messages", all_messages.as_bytes())?;
+    kv.set("messages".as_bytes(), all_messages.as_bytes())?;
     println!("Adding all messages ever sent to the queue to the kv store...");
 
     println!(
         "Retrieving all messages ever sent to the queue:\n{}",
-        std::str::from_utf8(&kv.get("messages")?)?
+        std::str::from_utf8(&kv.get("messages".as_bytes())?)?
     );
 
-    kv.delete("messages")?;
+    kv.delete("messages".as_bytes())?;
     println!("Deleting all messages ever sent to a queue from the kv store...");
 
     Ok(())
@@ -0,0 +1,15 @@
+use anyhow::Result;
+
+use platform::*;
+wit_bindgen_rust::import!("../../wit/platform.wit");
+wit_error_rs::impl_error!(Error);
+
+fn main() -> Result<()> {
+    let platform = Platform::open()?;
+    for fact in platform.list_facts()? {
+        println!("{}: {}", &fact, platform.get(&fact)?);
+    }
+    // ^^^ facts outside of the allow-list are refused
+    assert!(platform.get("hostname").is_err());
+    Ok(())
+}
@@ -15,16 +15,16 @@ fn main() -> Result<()> {
     let kv1 = Kv::open("my-container")?;
     let kv2 = Kv::open("my-container2")?;
     let value = "spiderlightning".as_bytes();
-    kv1.set("key", value)?;
-    kv2.set("key", value)?;
+    kv1.set("key".as_bytes(), value)?;
+    kv2.set("key".as_bytes(), value)?;
     println!(
         "Hello, world! the value for kv1 is: {}, kv2 is {}",
-        std::str::from_utf8(&kv1.get("key")?)?,
-        std::str::from_utf8(&kv2.get("key")?)?,
+        std::str::from_utf8(&kv1.get("key".as_bytes())?)?,
+        std::str::from_utf8(&kv2.get("key".as_bytes())?)?,
     );
-    kv1.delete("key")?;
-    kv2.delete("key")?;
-    let value = kv1.get("key");
+    kv1.delete("key".as_bytes())?;
+    kv2.delete("key".as_bytes())?;
+    let value = kv1.get("key".as_bytes());
     assert!(value.is_err());
 
     let ob1 = kv1.watch("my-key")?;
@@ -58,7 +58,7 @@ impl event_handler::EventHandler for EventHandler {
         let value =
             serde_json::from_str::<serde_json::Value>(std::str::from_utf8(&data).unwrap()).unwrap();
         let key = value["key"].as_str().unwrap();
-        let value = kv.get(key).unwrap();
+        let value = kv.get(key.as_bytes()).unwrap();
         println!(
             "received event of type {}, key: {}, new value: {}",
             &ev.ty,
@@ -0,0 +1,161 @@
+use std::{collections::HashMap, fs};
+
+use anyhow::{bail, Context, Result};
+use slight_runtime::redact::{redact, MASK};
+use spiderlightning::core::slightfile::TomlFile;
+
+use crate::commands::run::{condition_holds, describe_capability, link_once, linked_implementor};
+
+/// Prints the configuration a slightfile runs w/ (as TOML), once it's resolved the way
+/// `slight run` resolves it:
+///     - only the capabilities whose `when` holds are kept (w/o it), in the order they're
+///     linked in,
+///     - each linked to the implementor its `SLIGHT_<SCHEME>_BACKEND` names, if it's set, and
+///     - the values of secret settings, and the credentials in the rest (e.g., the passwords of
+///     urls) are redacted.
+///
+/// It fails like a run would if the slightfile can't be resolved (e.g., if two capabilities of
+/// a scheme are linked, or an override names an implementor of another scheme).
+pub fn handle_config_show(slightfile: &str) -> Result<()> {
+    let contents = fs::read_to_string(slightfile)
+        .with_context(|| format!("failed to read slightfile {}", slightfile))?;
+    let toml = toml::from_str::<TomlFile>(&contents)
+        .with_context(|| format!("failed to parse slightfile {}", slightfile))?;
+    let effective =
+        effective(toml).with_context(|| format!("failed to resolve slightfile {}", slightfile))?;
+    // as a `Value`, so its tables come after its values, whatever the order of the fields
+    let effective = toml::Value::try_from(&effective)?;
+    print!("{}", redact(&toml::to_string(&effective)?));
+    Ok(())
+}
+
+/// Prints the effective configuration of the backends of the capabilities a slightfile links
+/// (see `slight_runtime::describe`), either as a block per capability (`text`), or as a JSON
+/// array of them (`json`) — w/ their settings resolved from the secret stores as `slight run`
+/// would (credentials only show whether they're set), but w/o connecting to them.
+///
+/// Settings that can't be resolved are reported along w/ the rest, rather than failing, as
+/// they're what's being looked for.
+pub fn handle_config_describe(slightfile: &str, format: &str) -> Result<()> {
+    if !matches!(format, "text" | "json") {
+        bail!("invalid format: '{}' (expected 'text', or 'json')", format);
+    }
+    let contents = fs::read_to_string(slightfile)
+        .with_context(|| format!("failed to read slightfile {}", slightfile))?;
+    let toml = toml::from_str::<TomlFile>(&contents)
+        .with_context(|| format!("failed to parse slightfile {}", slightfile))?;
+    let mut descriptions = Vec::new();
+    for c in toml.capabilities_in_link_order()? {
+        if !condition_holds(c)? {
+            continue;
+        }
+        let implementor = linked_implementor(c)?;
+        if let Some(description) = describe_capability(&toml, c, &implementor, slightfile)
+            .with_context(|| format!("failed to describe '{}'", c.name))?
+        {
+            descriptions.push(description);
+        }
+    }
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&descriptions)?);
+        return Ok(());
+    }
+    if descriptions.is_empty() {
+        tracing::info!("{} links no capabilities w/ a backend", slightfile);
+    }
+    for (i, description) in descriptions.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        print!("{}", description);
+    }
+    Ok(())
+}
+
+fn effective(mut toml: TomlFile) -> Result<TomlFile> {
+    if toml.specversion.as_deref() != Some("0.1") {
+        bail!("unsupported toml spec version");
+    }
+    let mut linked = HashMap::new();
+    let mut capabilities = Vec::new();
+    for c in toml.capabilities_in_link_order()? {
+        if !condition_holds(c)? {
+            continue;
+        }
+        link_once(&mut linked, c)?;
+        let mut c = c.clone();
+        c.name = linked_implementor(&c)?;
+        c.when = None;
+        capabilities.push(c);
+    }
+    // they're listed in the order they're linked in already
+    toml.link_order = None;
+    toml.capability = Some(capabilities);
+    for secret in toml.secret_settings.iter_mut().flatten() {
+        secret.value = MASK.to_string();
+    }
+    Ok(toml)
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+    use slight_runtime::redact::MASK;
+    use spiderlightning::core::slightfile::TomlFile;
+
+    use super::effective;
+
+    fn slightfile(contents: &str) -> Result<TomlFile> {
+        Ok(toml::from_str(&format!(
+            "specversion = \"0.1\"\nsecret_settings = [{{ name = \"TOKEN\", value = \"hunter2\" }}]\n{}",
+            contents
+        ))?)
+    }
+
+    #[test]
+    fn effective_test() -> Result<()> {
+        let toml = effective(slightfile(
+            r#"
+[[capability]]
+name = "kv.azblob"
+when = "env.SLIGHT_CONFIG_TEST_UNSET"
+
+[[capability]]
+name = "kv.filesystem"
+when = "!env.SLIGHT_CONFIG_TEST_UNSET"
+
+[[capability]]
+name = "mq.filesystem"
+"#,
+        )?)?;
+        // only the capabilities whose `when` holds are kept, w/o it
+        let capabilities = toml.capability.unwrap();
+        assert_eq!(
+            capabilities
+                .iter()
+                .map(|c| c.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["kv.filesystem", "mq.filesystem"]
+        );
+        assert!(capabilities.iter().all(|c| c.when.is_none()));
+        assert_eq!(toml.secret_settings.unwrap()[0].value, MASK);
+        Ok(())
+    }
+
+    #[test]
+    fn declared_multiple_times_test() -> Result<()> {
+        // it fails like a run would, w/ the same hint
+        let e = effective(slightfile(
+            "[[capability]]\nname = \"kv.azblob\"\n\n[[capability]]\nname = \"kv.filesystem\"\n",
+        )?)
+        .unwrap_err()
+        .to_string();
+        assert!(
+            e.contains("capability 'kv' declared multiple times (i.e., as 'kv.azblob', and 'kv.filesystem')"),
+            "{}",
+            e
+        );
+        assert!(e.contains("add `when` conditions"), "{}", e);
+        Ok(())
+    }
+}
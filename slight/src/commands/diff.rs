@@ -0,0 +1,26 @@
+use std::fs;
+
+use anyhow::{bail, Context, Result};
+use spiderlightning::core::diff::diff_slightfiles;
+
+/// Prints the semantic diff of two slightfiles, either as a line per change (`text`), or as
+/// a JSON array of changes (`json`).
+pub fn handle_diff(old: &str, new: &str, format: &str) -> Result<()> {
+    let read = |file: &str| {
+        fs::read_to_string(file).with_context(|| format!("failed to read slightfile {}", file))
+    };
+    let changes = diff_slightfiles(&read(old)?, &read(new)?)?;
+    match format {
+        "text" => {
+            if changes.is_empty() {
+                tracing::info!("{}, and {} are equivalent", old, new);
+            }
+            for change in &changes {
+                println!("{}", change);
+            }
+        }
+        "json" => println!("{}", serde_json::to_string_pretty(&changes)?),
+        _ => bail!("invalid format: '{}' (expected 'text', or 'json')", format),
+    }
+    Ok(())
+}
@@ -0,0 +1,32 @@
+use std::fs;
+
+use anyhow::{bail, Context, Result};
+use spiderlightning::core::format::{format_slightfile, has_comments};
+
+pub fn handle_fmt(file: &str, check: bool) -> Result<()> {
+    let contents =
+        fs::read_to_string(file).with_context(|| format!("failed to read slightfile {}", file))?;
+    let formatted =
+        format_slightfile(&contents).with_context(|| format!("failed to format {}", file))?;
+
+    if check {
+        if formatted != contents {
+            bail!(
+                "{} is not formatted (run `slight fmt {}` to format it)",
+                file,
+                file
+            );
+        }
+        return Ok(());
+    }
+    if formatted == contents {
+        tracing::info!("{} is already formatted", file);
+        return Ok(());
+    }
+    if has_comments(&contents) {
+        tracing::warn!("the comments in {} are not preserved by formatting", file);
+    }
+    fs::write(file, formatted).with_context(|| format!("failed to write {}", file))?;
+    tracing::info!("formatted {}", file);
+    Ok(())
+}
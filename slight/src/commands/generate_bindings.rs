@@ -0,0 +1,443 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+
+/// The WIT files of each capability, embedded from the same files the host
+/// links against, so the generated bindings can't drift from them.
+const WIT_FILES: [(&str, &str); 24] = [
+    ("types.wit", include_str!("../../../wit/types.wit")),
+    ("resources.wit", include_str!("../../../wit/resources.wit")),
+    ("kv.wit", include_str!("../../../wit/kv.wit")),
+    ("mq.wit", include_str!("../../../wit/mq.wit")),
+    ("lockd.wit", include_str!("../../../wit/lockd.wit")),
+    ("pubsub.wit", include_str!("../../../wit/pubsub.wit")),
+    ("configs.wit", include_str!("../../../wit/configs.wit")),
+    ("events.wit", include_str!("../../../wit/events.wit")),
+    (
+        "event-handler.wit",
+        include_str!("../../../wit/event-handler.wit"),
+    ),
+    (
+        "event-batch-handler.wit",
+        include_str!("../../../wit/event-batch-handler.wit"),
+    ),
+    ("http.wit", include_str!("../../../wit/http.wit")),
+    (
+        "http-types.wit",
+        include_str!("../../../wit/http-types.wit"),
+    ),
+    ("platform.wit", include_str!("../../../wit/platform.wit")),
+    (
+        "runtime-control.wit",
+        include_str!("../../../wit/runtime-control.wit"),
+    ),
+    (
+        "credentials.wit",
+        include_str!("../../../wit/credentials.wit"),
+    ),
+    ("jobs.wit", include_str!("../../../wit/jobs.wit")),
+    ("timers.wit", include_str!("../../../wit/timers.wit")),
+    ("webhooks.wit", include_str!("../../../wit/webhooks.wit")),
+    ("docstore.wit", include_str!("../../../wit/docstore.wit")),
+    (
+        "timeseries.wit",
+        include_str!("../../../wit/timeseries.wit"),
+    ),
+    (
+        "notifications.wit",
+        include_str!("../../../wit/notifications.wit"),
+    ),
+    ("election.wit", include_str!("../../../wit/election.wit")),
+    ("workflow.wit", include_str!("../../../wit/workflow.wit")),
+    (
+        "deployment.wit",
+        include_str!("../../../wit/deployment.wit"),
+    ),
+    ("parsing.wit", include_str!("../../../wit/parsing.wit")),
+    ("crypto.wit", include_str!("../../../wit/crypto.wit")),
+    (
+        "validation.wit",
+        include_str!("../../../wit/validation.wit"),
+    ),
+];
+
+/// A capability a guest can import, and what it takes to do so.
+struct Capability {
+    /// the name of the capability (i.e., what a user passes in `--capabilities`)
+    name: &'static str,
+    /// the name of the capability in a `slightfile` (i.e., its default implementor)
+    slightfile_name: &'static str,
+    /// the WIT files the guest imports
+    imports: &'static [&'static str],
+    /// the WIT files the guest exports
+    exports: &'static [&'static str],
+    /// the WIT files the imports and exports depend on
+    dependencies: &'static [&'static str],
+}
+
+const CAPABILITIES: [Capability; 19] = [
+    Capability {
+        name: "kv",
+        slightfile_name: "kv.filesystem",
+        imports: &["kv.wit"],
+        exports: &[],
+        dependencies: &["types.wit", "resources.wit"],
+    },
+    Capability {
+        name: "mq",
+        slightfile_name: "mq.filesystem",
+        imports: &["mq.wit"],
+        exports: &[],
+        dependencies: &["types.wit", "resources.wit"],
+    },
+    Capability {
+        name: "lockd",
+        slightfile_name: "lockd.etcd",
+        imports: &["lockd.wit"],
+        exports: &[],
+        dependencies: &["types.wit", "resources.wit"],
+    },
+    Capability {
+        name: "pubsub",
+        slightfile_name: "pubsub.confluent_apache_kafka",
+        imports: &["pubsub.wit"],
+        exports: &[],
+        dependencies: &["types.wit", "resources.wit"],
+    },
+    Capability {
+        name: "configs",
+        slightfile_name: "configs.envvars",
+        imports: &["configs.wit"],
+        exports: &[],
+        dependencies: &["types.wit", "resources.wit"],
+    },
+    Capability {
+        name: "events",
+        slightfile_name: "events",
+        imports: &["events.wit"],
+        // guests handle events one by one, or in batches (w/ `events.batch_window_ms` set)
+        exports: &["event-handler.wit", "event-batch-handler.wit"],
+        dependencies: &["types.wit", "resources.wit"],
+    },
+    Capability {
+        name: "http",
+        slightfile_name: "http",
+        imports: &["http.wit"],
+        exports: &[],
+        dependencies: &["types.wit", "http-types.wit"],
+    },
+    Capability {
+        name: "platform",
+        slightfile_name: "platform",
+        imports: &["platform.wit"],
+        exports: &[],
+        dependencies: &["types.wit"],
+    },
+    Capability {
+        name: "runtime_control",
+        slightfile_name: "runtime_control",
+        imports: &["runtime-control.wit"],
+        exports: &[],
+        dependencies: &["types.wit"],
+    },
+    Capability {
+        name: "credentials",
+        slightfile_name: "credentials.awssts",
+        imports: &["credentials.wit"],
+        exports: &[],
+        dependencies: &["types.wit", "resources.wit"],
+    },
+    Capability {
+        name: "jobs",
+        slightfile_name: "jobs",
+        imports: &["jobs.wit"],
+        exports: &[],
+        dependencies: &["types.wit", "resources.wit"],
+    },
+    Capability {
+        name: "timers",
+        slightfile_name: "timers",
+        imports: &["timers.wit"],
+        exports: &[],
+        dependencies: &["types.wit", "resources.wit"],
+    },
+    Capability {
+        name: "webhooks",
+        slightfile_name: "webhooks",
+        // deliveries are handled w/ the `events`, and served by the `http` capability
+        imports: &["webhooks.wit"],
+        exports: &[],
+        dependencies: &["types.wit", "resources.wit"],
+    },
+    Capability {
+        name: "docstore",
+        slightfile_name: "docstore.filesystem",
+        imports: &["docstore.wit"],
+        exports: &[],
+        dependencies: &["types.wit", "resources.wit"],
+    },
+    Capability {
+        name: "timeseries",
+        slightfile_name: "timeseries.filesystem",
+        imports: &["timeseries.wit"],
+        exports: &[],
+        dependencies: &["types.wit", "resources.wit"],
+    },
+    Capability {
+        name: "notifications",
+        slightfile_name: "notifications",
+        imports: &["notifications.wit"],
+        exports: &[],
+        dependencies: &["types.wit"],
+    },
+    Capability {
+        name: "workflow",
+        slightfile_name: "workflow",
+        imports: &["workflow.wit"],
+        exports: &[],
+        dependencies: &["types.wit", "resources.wit"],
+    },
+    Capability {
+        name: "election",
+        slightfile_name: "election.etcd",
+        imports: &["election.wit"],
+        exports: &[],
+        dependencies: &["types.wit", "resources.wit"],
+    },
+    Capability {
+        name: "deployment",
+        slightfile_name: "deployment",
+        imports: &["deployment.wit"],
+        exports: &[],
+        dependencies: &["types.wit"],
+    },
+    Capability {
+        name: "parsing",
+        slightfile_name: "parsing",
+        imports: &["parsing.wit"],
+        exports: &[],
+        dependencies: &["types.wit"],
+    },
+    Capability {
+        name: "crypto",
+        slightfile_name: "crypto",
+        imports: &["crypto.wit"],
+        exports: &[],
+        dependencies: &["types.wit"],
+    },
+    Capability {
+        name: "validation",
+        slightfile_name: "validation",
+        imports: &["validation.wit"],
+        exports: &[],
+        dependencies: &["types.wit"],
+    },
+];
+
+const WIT_BINDGEN_RUST_DEP: &str = r#"wit-bindgen-rust = { git = "https://github.com/bytecodealliance/wit-bindgen", rev = "a79a4be33d76ddf62839ba71602c26a96610ef7c" }"#;
+const WIT_ERROR_RS_DEP: &str = r#"wit-error-rs = { git = "https://github.com/danbugs/wit-error-rs", rev = "05362f1a4a3a9dc6a1de39195e06d2d5d6491a5e" }"#;
+const HTTP_HANDLER_MACRO_DEP: &str =
+    r#"slight-http-handler-macro = { git = "https://github.com/deislabs/spiderlightning" }"#;
+const COMPRESSION_DEP: &str =
+    r#"slight-compression = { git = "https://github.com/deislabs/spiderlightning" }"#;
+
+/// The imports whose results the host can compress (i.e., w/ `compress_results`), which the
+/// generated bindings decode (see `slight_compression::decoding!`).
+const DECODED_IMPORTS: [&str; 2] = ["kv.wit", "mq.wit"];
+
+/// Generates a starter guest project that imports exactly the given capabilities.
+pub fn handle_generate_bindings(capabilities: &[String], lang: &str, out: &str) -> Result<()> {
+    let capabilities = capabilities
+        .iter()
+        .map(|name| {
+            CAPABILITIES
+                .iter()
+                .find(|c| c.name == name.trim())
+                .with_context(|| {
+                    format!(
+                        "unknown capability '{}' (available capabilities are: {})",
+                        name,
+                        CAPABILITIES
+                            .iter()
+                            .map(|c| c.name)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if capabilities.is_empty() {
+        bail!("at least one capability is required to generate bindings");
+    }
+
+    let out = Path::new(out);
+    if out.exists() && out.read_dir()?.next().is_some() {
+        bail!(
+            "refusing to generate bindings into '{}' because it is not empty",
+            out.display()
+        );
+    }
+
+    let files = match lang {
+        "rust" => rust_project(&capabilities, out)?,
+        l => bail!(
+            "unsupported language '{}' (currently, slight can only generate bindings for 'rust')",
+            l
+        ),
+    };
+
+    for (path, contents) in files {
+        let path = out.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, contents)
+            .with_context(|| format!("failed to write '{}'", path.display()))?;
+        tracing::info!("Generated {}", path.display());
+    }
+    Ok(())
+}
+
+/// Gets the WIT files (and their contents) needed to bind to the given capabilities.
+fn wit_files(capabilities: &[&Capability]) -> Vec<(String, String)> {
+    WIT_FILES
+        .iter()
+        .filter(|(name, _)| {
+            capabilities.iter().any(|c| {
+                c.imports.contains(name)
+                    || c.exports.contains(name)
+                    || c.dependencies.contains(name)
+            })
+        })
+        .map(|(name, contents)| (format!("wit/{}", name), contents.to_string()))
+        .collect()
+}
+
+/// Gets the `slightfile` that links the given capabilities to their default implementors.
+fn slightfile(capabilities: &[&Capability]) -> String {
+    let mut slightfile = "specversion = \"0.1\"\nsecret_store = \"configs.envvars\"\n".to_string();
+    for c in capabilities {
+        slightfile.push_str(&format!(
+            "\n[[capability]]\nname = \"{}\"\n",
+            c.slightfile_name
+        ));
+    }
+    slightfile
+}
+
+/// Lays out a Rust guest project (i.e., its files, relative to the project root).
+fn rust_project(capabilities: &[&Capability], out: &Path) -> Result<Vec<(String, String)>> {
+    let package_name = out
+        .file_name()
+        .and_then(|n| n.to_str())
+        .with_context(|| format!("failed to infer a package name from '{}'", out.display()))?;
+
+    let mut dependencies = vec![
+        r#"anyhow = "1""#.to_string(),
+        WIT_BINDGEN_RUST_DEP.to_string(),
+        WIT_ERROR_RS_DEP.to_string(),
+    ];
+    if capabilities.iter().any(|c| c.name == "http") {
+        dependencies.push(HTTP_HANDLER_MACRO_DEP.to_string());
+    }
+    if capabilities
+        .iter()
+        .any(|c| c.imports.iter().any(|i| DECODED_IMPORTS.contains(i)))
+    {
+        dependencies.push(COMPRESSION_DEP.to_string());
+    }
+    let cargo_toml = format!(
+        "[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[[bin]]\nname = \"{name}\"\ntest = false\n\n[dependencies]\n{deps}\n\n[workspace]\n",
+        name = package_name,
+        deps = dependencies.join("\n")
+    );
+
+    let mut main_rs = "use anyhow::Result;\n".to_string();
+    for c in capabilities {
+        for import in c.imports {
+            let module = import.trim_end_matches(".wit").replace('-', "_");
+            main_rs.push_str(&format!(
+                "\nwit_bindgen_rust::import!(\"wit/{}\");\nwit_error_rs::impl_error!({}::Error);\n",
+                import, module
+            ));
+            if DECODED_IMPORTS.contains(import) {
+                // use `<module>_decoded` in place of `<module>`, so results compressed by
+                // the host are decoded
+                main_rs.push_str(&format!(
+                    "slight_compression::decoding!({m});\n#[allow(unused_imports)]\nuse {m}_decoded::*;\n",
+                    m = module
+                ));
+            }
+        }
+        for export in c.exports {
+            main_rs.push_str(&format!(
+                "\nwit_bindgen_rust::export!(\"wit/{}\");\n",
+                export
+            ));
+        }
+    }
+    main_rs.push_str(
+        "\nfn main() -> Result<()> {\n    // TODO: use your capabilities here\n    Ok(())\n}\n",
+    );
+    if capabilities.iter().any(|c| c.name == "events") {
+        main_rs.push_str(concat!(
+            "\npub struct EventHandler {}\n\n",
+            "impl event_handler::EventHandler for EventHandler {\n",
+            "    fn handle_event(_ev: event_handler::Event) -> Result<Option<event_handler::Event>, String> {\n",
+            "        // TODO: handle the events you are listening to here\n",
+            "        Ok(None)\n",
+            "    }\n",
+            "}\n",
+            "\npub struct EventBatchHandler {}\n\n",
+            "impl event_batch_handler::EventBatchHandler for EventBatchHandler {\n",
+            "    fn handle_events(_evs: Vec<event_batch_handler::Event>) -> Result<Vec<u32>, String> {\n",
+            "        // TODO: handle batches of events here, if `events.batch_window_ms` is set on the\n",
+            "        // capability, returning the indices of the events that failed\n",
+            "        Ok(Vec::new())\n",
+            "    }\n",
+            "}\n"
+        ));
+    }
+    if capabilities.iter().any(|c| c.name == "http") {
+        main_rs.push_str(concat!(
+            "\n// routes registered w/ the `http` capability are handled by functions like:\n",
+            "//\n",
+            "// #[slight_http_handler_macro::register_handler]\n",
+            "// fn handle_hello(req: http::Request) -> Result<http::Response, http::Error> { ... }\n"
+        ));
+    }
+
+    let mut files = vec![
+        ("Cargo.toml".to_string(), cargo_toml),
+        ("src/main.rs".to_string(), main_rs),
+        ("slightfile.toml".to_string(), slightfile(capabilities)),
+    ];
+    files.extend(wit_files(capabilities));
+    Ok(files)
+}
+
+#[cfg(test)]
+mod unittests {
+    use super::{wit_files, CAPABILITIES};
+
+    #[test]
+    fn dependencies_test() {
+        // every WIT file a capability binds to is generated w/ the ones it `use`s
+        for capability in &CAPABILITIES {
+            let files = wit_files(&[capability]);
+            for (name, contents) in &files {
+                for line in contents.lines().filter(|line| line.starts_with("use ")) {
+                    let used = line.rsplit(" from ").next().unwrap().trim();
+                    let used = format!("wit/{}.wit", used);
+                    assert!(
+                        files.iter().any(|(name, _)| name == &used),
+                        "{}: {} uses {}, which isn't one of its dependencies",
+                        capability.name,
+                        name,
+                        used
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use slight_mq::HostMq;
+use slight_pubsub::HostPub;
+use slight_runtime::{
+    credentials::Credentials,
+    log_sink::{LogSink, DEFAULT_LOG_SINK_BUFFER},
+    resource::{BasicState, StateTable},
+};
+use spiderlightning::core::slightfile::{self, TomlFile};
+
+/// Connects `log_sink` to the queue, or topic the slightfile ships the host's log records to
+/// (see `slightfile::LogSink`), w/ the slightfile's secret stores.
+///
+/// Records are sent one by one, as they are shipped from a thread of their own (pubsub records
+/// w/o a key).
+pub fn connect_log_sink(
+    log_sink: &LogSink,
+    settings: &slightfile::LogSink,
+    toml: &TomlFile,
+    toml_file_path: &str,
+) -> Result<()> {
+    let slight_state = BasicState::new(
+        Arc::new(Mutex::new(StateTable::default())),
+        &toml.secret_stores().unwrap_or_default(),
+        toml_file_path,
+    )
+    .with_credentials(Credentials::default());
+    let buffer = settings.buffer.unwrap_or(DEFAULT_LOG_SINK_BUFFER);
+    let exclusive = settings.exclusive.unwrap_or(false);
+    let name = settings.name.clone();
+    match settings.capability.as_str() {
+        implementor @ ("mq.filesystem" | "mq.azsbus") => {
+            let mq = HostMq::open(implementor, &slight_state, &name)?;
+            log_sink.connect(buffer, exclusive, move |record| mq.send(record))?;
+        }
+        implementor @ "pubsub.confluent_apache_kafka" => {
+            let publisher = HostPub::open(implementor, &slight_state)?;
+            log_sink.connect(buffer, exclusive, move |record| {
+                publisher.send_message_to_topic(b"", record, &name)
+            })?;
+        }
+        c => bail!(
+            "invalid log sink: '{}' (expected 'mq.filesystem', 'mq.azsbus', or 'pubsub.confluent_apache_kafka')",
+            c
+        ),
+    }
+    tracing::info!(
+        "shipping logs to '{}' w/ {}",
+        settings.name,
+        settings.capability
+    );
+    Ok(())
+}
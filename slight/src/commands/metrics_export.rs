@@ -0,0 +1,315 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use slight_runtime::{
+    export::{families, Exporter, Otlp, Prometheus},
+    metrics::Metrics,
+};
+use spiderlightning::core::slightfile;
+use tokio::task::JoinHandle;
+
+/// Where `prometheus` serves `/metrics`, unless the slightfile says otherwise.
+const DEFAULT_PROMETHEUS_ADDRESS: &str = "0.0.0.0:9464";
+/// Where `otlp` pushes to, and how often, unless the slightfile says otherwise.
+const DEFAULT_OTLP_ENDPOINT: &str = "http://localhost:4318/v1/metrics";
+const DEFAULT_OTLP_INTERVAL_SECS: u64 = 10;
+/// How long a push to `otlp` waits for the collector at most — less, if it pushes more often,
+/// so a push never outlasts the interval (i.e., pushes to a collector that hangs don't pile up).
+const MAX_OTLP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `MetricsExport` exports the capability call metrics of an app to the backend the slightfile
+/// says (see `slightfile::MetricsExport`), until it's stopped, or dropped.
+pub struct MetricsExport {
+    /// the task pushing the metrics, for exporters that push them
+    task: Option<JoinHandle<()>>,
+    /// what pushes the metrics, for exporters that push them
+    push: Option<Push>,
+    /// the server scraping the metrics, for exporters that are scraped, which the app is
+    /// removed from once it stops
+    served: Option<(MetricsServers, SocketAddr, String)>,
+}
+
+impl MetricsExport {
+    /// Starts exporting the `metrics` of `app` as per `settings`, failing if they're invalid,
+    /// or, for `prometheus`, if its address can't be bound (i.e., by one of `servers`, or
+    /// anew).
+    pub fn start(
+        settings: &slightfile::MetricsExport,
+        app: &str,
+        metrics: &Metrics,
+        servers: &MetricsServers,
+    ) -> Result<Self> {
+        match settings.exporter.as_str() {
+            "prometheus" => {
+                if settings.endpoint.is_some() || settings.interval_secs.is_some() {
+                    bail!("invalid metrics export: prometheus scrapes the metrics at the address, it isn't pushed them (i.e., endpoint, and interval_secs are otlp's)");
+                }
+                let address = settings
+                    .address
+                    .as_deref()
+                    .unwrap_or(DEFAULT_PROMETHEUS_ADDRESS);
+                let addr: SocketAddr = address
+                    .parse()
+                    .with_context(|| format!("invalid metrics export address: {}", address))?;
+                servers.serve(addr, app, metrics)?;
+                Ok(Self {
+                    task: None,
+                    push: None,
+                    served: Some((servers.clone(), addr, app.to_string())),
+                })
+            }
+            "otlp" => {
+                if settings.address.is_some() {
+                    bail!("invalid metrics export: otlp pushes the metrics to the endpoint, they aren't scraped (i.e., address is prometheus')");
+                }
+                let endpoint = settings
+                    .endpoint
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_OTLP_ENDPOINT.to_string());
+                let url = reqwest::Url::parse(&endpoint)
+                    .with_context(|| format!("invalid metrics export endpoint: {}", endpoint))?;
+                if !["http", "https"].contains(&url.scheme()) {
+                    bail!(
+                        "invalid metrics export endpoint: {} (expected an http, or https url)",
+                        endpoint
+                    );
+                }
+                let interval = match settings.interval_secs.unwrap_or(DEFAULT_OTLP_INTERVAL_SECS) {
+                    0 => bail!("invalid metrics export interval_secs: it must be greater than 0"),
+                    secs => Duration::from_secs(secs),
+                };
+                let client = reqwest::Client::builder()
+                    .timeout(interval.min(MAX_OTLP_TIMEOUT))
+                    .build()
+                    .context("failed to build the client the metrics are pushed w/")?;
+                let push = Push {
+                    client,
+                    endpoint,
+                    exporter: Otlp {
+                        since: metrics.since(),
+                    },
+                    app: app.to_string(),
+                    metrics: metrics.clone(),
+                };
+                tracing::info!(
+                    "pushing the metrics to {} every {:?}",
+                    push.endpoint,
+                    interval
+                );
+                let pusher = push.clone();
+                let task = tokio::spawn(async move {
+                    let mut ticks = tokio::time::interval(interval);
+                    // the first tick is right away, when there's nothing to push yet
+                    ticks.tick().await;
+                    loop {
+                        ticks.tick().await;
+                        pusher.push().await;
+                    }
+                });
+                Ok(Self {
+                    task: Some(task),
+                    push: Some(push),
+                    served: None,
+                })
+            }
+            e => bail!(
+                "invalid metrics exporter: '{}' (expected 'prometheus', or 'otlp')",
+                e
+            ),
+        }
+    }
+
+    /// Stops the export, pushing the metrics one last time, for exporters that push them, so
+    /// the calls since the last push aren't lost.
+    pub async fn stop(mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        if let Some(push) = self.push.take() {
+            push.push().await;
+        }
+    }
+}
+
+impl Drop for MetricsExport {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        if let Some((servers, addr, app)) = self.served.take() {
+            servers.unserve(addr, &app);
+        }
+    }
+}
+
+/// `MetricsServers` are the servers `prometheus` exports serve `/metrics` from, by address —
+/// the apps `slight serve` runs share them, so the ones exporting at the same address (e.g.,
+/// the default one) are scraped together (w/ the `app` label telling them apart), rather than
+/// all but the first failing to bind it.
+///
+/// A server stops once the last of its apps does.
+#[derive(Clone, Debug, Default)]
+pub struct MetricsServers(Arc<Mutex<HashMap<SocketAddr, Served>>>);
+
+#[derive(Debug)]
+struct Served {
+    apps: Arc<Mutex<BTreeMap<String, Metrics>>>,
+    task: JoinHandle<()>,
+}
+
+impl MetricsServers {
+    /// Serves the `metrics` of `app` at `addr`, binding it if none of the apps serves theirs
+    /// there yet.
+    fn serve(&self, addr: SocketAddr, app: &str, metrics: &Metrics) -> Result<()> {
+        let mut servers = self.0.lock().unwrap();
+        if let Some(served) = servers.get(&addr) {
+            let mut apps = served.apps.lock().unwrap();
+            if apps.contains_key(app) {
+                bail!(
+                    "failed to serve the metrics at {}: another app named '{}' serves its metrics there already",
+                    addr,
+                    app
+                );
+            }
+            apps.insert(app.to_string(), metrics.clone());
+            tracing::info!(
+                "serving the metrics at http://{}/metrics (w/ the ones of {} other apps)",
+                addr,
+                apps.len() - 1
+            );
+            return Ok(());
+        }
+
+        let apps = Arc::new(Mutex::new(BTreeMap::from([(
+            app.to_string(),
+            metrics.clone(),
+        )])));
+        let scraped = apps.clone();
+        let make_service = make_service_fn(move |_| {
+            let apps = scraped.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let res = scrape(&apps.lock().unwrap(), req);
+                    async move { Ok::<_, Infallible>(res) }
+                }))
+            }
+        });
+        let server = Server::try_bind(&addr)
+            .with_context(|| format!("failed to serve the metrics at {}", addr))?
+            .serve(make_service);
+        tracing::info!("serving the metrics at http://{}/metrics", addr);
+        let task = tokio::spawn(async move {
+            if let Err(e) = server.await {
+                tracing::error!("failed to serve the metrics: {}", e);
+            }
+        });
+        servers.insert(addr, Served { apps, task });
+        Ok(())
+    }
+
+    /// Stops serving the metrics of `app` at `addr`, and the server, if it was the last app.
+    fn unserve(&self, addr: SocketAddr, app: &str) {
+        let mut servers = self.0.lock().unwrap();
+        let last = match servers.get(&addr) {
+            Some(served) => {
+                let mut apps = served.apps.lock().unwrap();
+                apps.remove(app);
+                apps.is_empty()
+            }
+            None => return,
+        };
+        if last {
+            if let Some(served) = servers.remove(&addr) {
+                served.task.abort();
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Push {
+    client: reqwest::Client,
+    endpoint: String,
+    exporter: Otlp,
+    app: String,
+    metrics: Metrics,
+}
+
+impl Push {
+    /// Pushes the metrics, where failures are only logged, as the next push has them too (i.e.,
+    /// the counts are cumulative).
+    async fn push(&self) {
+        let body = self
+            .exporter
+            .encode(&families(&[(self.app.as_str(), &self.metrics)]));
+        let res = self
+            .client
+            .post(&self.endpoint)
+            .header(reqwest::header::CONTENT_TYPE, self.exporter.content_type())
+            .body(body)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+        if let Err(e) = res {
+            tracing::warn!("failed to push the metrics to {}: {}", self.endpoint, e);
+        }
+    }
+}
+
+/// Answers scrapes of `/metrics` w/ the metrics of `apps`.
+fn scrape(apps: &BTreeMap<String, Metrics>, req: Request<Body>) -> Response<Body> {
+    let (status, content_type, body) = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => (
+            StatusCode::OK,
+            Prometheus.content_type(),
+            Prometheus.encode(&families(
+                &apps
+                    .iter()
+                    .map(|(app, metrics)| (app.as_str(), metrics))
+                    .collect::<Vec<_>>(),
+            )),
+        ),
+        _ => (StatusCode::NOT_FOUND, "text/plain", b"not found".to_vec()),
+    };
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, content_type)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod unittests {
+    use slight_runtime::metrics::Metrics;
+
+    use super::MetricsServers;
+
+    #[tokio::test]
+    async fn metrics_servers_test() {
+        let servers = MetricsServers::default();
+        let addr = "127.0.0.1:0".parse().unwrap();
+        servers.serve(addr, "app", &Metrics::default()).unwrap();
+        // apps exporting at the same address share its server, rather than failing to bind it
+        servers
+            .serve(addr, "other-app", &Metrics::default())
+            .unwrap();
+        assert!(servers.serve(addr, "app", &Metrics::default()).is_err());
+        assert_eq!(servers.0.lock().unwrap().len(), 1);
+
+        servers.unserve(addr, "app");
+        assert_eq!(servers.0.lock().unwrap().len(), 1);
+        servers.unserve(addr, "other-app");
+        assert!(servers.0.lock().unwrap().is_empty());
+    }
+}
@@ -1,2 +1,10 @@
+pub mod config;
+pub mod diff;
+pub mod fmt;
+pub mod generate_bindings;
+pub mod log_sink;
+pub mod metrics_export;
 pub mod run;
 pub mod secret;
+pub mod serve;
+pub mod tail;
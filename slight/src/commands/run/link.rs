@@ -0,0 +1,1264 @@
+//! Linking the capabilities of the slightfile, w/ the settings each of them is linked w/.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use slight_compression::{Codec, Compression};
+use slight_credentials::CredentialsState;
+use slight_crypto::{Crypto, CryptoState};
+use slight_deployment::{Deployment, DeploymentContext, DeploymentState};
+use slight_docstore::{Docstore, DocstoreState};
+use slight_election::{Election, ElectionState};
+use slight_events::{batch::EventBatching, drivers::EVENTS_DRIVERS, Events, EventsState};
+use slight_http::{
+    AccessLogFormat, AccessLogSettings, CachePolicy, ClientAuth, EnrichmentSettings, Format, Http,
+    HttpSettings, HttpState, OpenApi, TlsSettings, DEFAULT_REDACTED_HEADERS,
+};
+use slight_jobs::{Jobs, JobsState};
+use slight_kv::{Kv, KvState};
+use slight_lockd::{Lockd, LockdState};
+use slight_mq::{Mq, MqState};
+use slight_notifications::{Channel, Notifications, NotificationsState, Provider};
+use slight_parsing::{Parsing, ParsingState};
+use slight_platform::{Platform, PlatformState};
+use slight_pubsub::{
+    DedupSettings, Delivery, Pubsub, PubsubState, SchemaFormat, SchemaRegistrySettings,
+};
+use slight_runtime::{
+    batch::BatchSettings,
+    call::CallSettings,
+    describe::Description,
+    drain::DEFAULT_DRAIN_GRACE,
+    encoding::Encoding,
+    flags::OperationFlags,
+    grants::Grants,
+    headroom::Headroom,
+    last_known_good::LastKnownGood,
+    metrics::{
+        LabelSettings, SizeBuckets, DEFAULT_KEY_BUCKETS, DEFAULT_MAX_TARGETS, DEFAULT_VALUE_BUCKETS,
+    },
+    page_token::PageTokens,
+    payload_limit::PayloadLimit,
+    pool::PoolSettings,
+    quota::QuotaSettings,
+    resource::{BasicState, StateTable},
+    signing::{Algorithm, Signing, SigningKey},
+    split::TrafficSplit,
+    support,
+    trace_context::Propagation,
+    Builder,
+};
+use slight_runtime_configs::{Configs, ConfigsState};
+use slight_runtime_control::{RuntimeControl, RuntimeControlState};
+use slight_timers::{Misfire, MisfirePolicy, Timers, TimersSettings, TimersState};
+use slight_timeseries::{Timeseries, TimeseriesState};
+use slight_validation::{Rule, Validation, ValidationState};
+use slight_webhooks::{SignatureScheme, Webhook, Webhooks, WebhooksState};
+use slight_workflow::{Definition, Transition, Workflow, WorkflowState};
+use spiderlightning::core::{
+    condition::Condition,
+    slightfile::{
+        Capability, DeploymentOptions, HttpOptions, JobsOptions, KvOptions, PubsubOptions,
+        TimersOptions, TomlFile, ValidationOptions, WorkflowOptions,
+    },
+};
+
+use super::{
+    capability_support, interface, Limits, CONFIGS_HOST_IMPLEMENTORS,
+    CREDENTIALS_HOST_IMPLEMENTORS, DOCSTORE_HOST_IMPLEMENTORS, ELECTION_HOST_IMPLEMENTORS,
+    KV_HOST_IMPLEMENTORS, LOCKD_HOST_IMPLEMENTORS, MQ_HOST_IMPLEMENTORS, PUBSUB_HOST_IMPLEMENTORS,
+    TIMESERIES_HOST_IMPLEMENTORS,
+};
+
+/// Links the capabilities of the slightfile to `builder`, in their link order (see
+/// `TomlFile::capabilities_in_link_order`), skipping those whose `when` condition doesn't hold.
+pub(super) fn link_capabilities(
+    builder: &mut Builder,
+    toml: &TomlFile,
+    toml_file_path: &str,
+    resource_map: &Arc<Mutex<StateTable>>,
+    limits: &Limits,
+    headroom: &Headroom,
+) -> Result<()> {
+    // the implementor each scheme is linked to, as a scheme can only be linked once
+    let mut linked = HashMap::new();
+    // every capability that fails to link is reported at once, rather than one per attempt
+    let mut failures = Vec::new();
+    let started = Instant::now();
+    let capabilities = toml.capabilities_in_link_order()?;
+    for (i, c) in capabilities.iter().copied().enumerate() {
+        if !condition_holds(c)? {
+            tracing::debug!(
+                "skipping capability '{}', because its condition doesn't hold: {}",
+                c.name,
+                c.when.as_deref().unwrap_or_default()
+            );
+            continue;
+        }
+        if let Err(e) = link_once(&mut linked, c) {
+            failures.push(e.to_string());
+            continue;
+        }
+        tracing::info!(
+            "linking capability {}/{}: '{}'",
+            i + 1,
+            capabilities.len(),
+            c.name
+        );
+        let started = Instant::now();
+        let linking = linked_implementor(c).and_then(|implementor| {
+            let operations = support::operations(interface(c.scheme()));
+            let grants = grants(c);
+            // the calls of events, and http aren't checked against them (i.e., they don't
+            // build their state w/ `basic_state`), so they'd grant, deny, enable, or
+            // disable nothing
+            if matches!(c.scheme(), "events" | "http") {
+                if !grants.is_unrestricted() {
+                    bail!("invalid `allow_operations`, or `deny_operations`: '{}' doesn't support them", c.scheme());
+                }
+                if c.enable_operations.is_some() || c.disable_operations.is_some() {
+                    bail!("invalid `enable_operations`, or `disable_operations`: '{}' doesn't support them", c.scheme());
+                }
+            }
+            grants
+                .validate(&operations)
+                .context("invalid `allow_operations`, or `deny_operations`")?;
+            operation_flags(c)
+                .validate(&operations)
+                .context("invalid `enable_operations`, or `disable_operations`")?;
+            compression(c).context("invalid `compress_results`")?;
+            payload_limit(c).context("invalid `max_payload_bytes`")?;
+            c.check_options()?;
+            link_capability(
+                builder,
+                c,
+                &implementor,
+                toml,
+                toml_file_path,
+                resource_map,
+                limits,
+                headroom,
+            )
+        });
+        match linking {
+            Ok(()) => tracing::debug!("linked '{}' in {:?}", c.name, started.elapsed()),
+            Err(e) => failures.push(format!("'{}': {:#}", c.name, e)),
+        }
+    }
+    if !failures.is_empty() {
+        bail!(
+            "{} capabilities failed to link:\n  - {}",
+            failures.len(),
+            failures.join("\n  - ")
+        );
+    }
+    tracing::info!(
+        "linked {} capabilities in {:?}",
+        linked.len(),
+        started.elapsed()
+    );
+    Ok(())
+}
+
+/// Records that capability `c` is linked in `linked` (i.e., the capability each scheme is
+/// linked w/), failing if another one of its scheme is already, as a scheme can only be
+/// linked once.
+pub fn link_once<'a>(linked: &mut HashMap<&'a str, &'a str>, c: &'a Capability) -> Result<()> {
+    if let Some(linked) = linked.insert(c.scheme(), c.name.as_str()) {
+        bail!(
+            "capability '{}' declared multiple times (i.e., as '{}', and '{}'); if they are meant for different environments, add `when` conditions so only one of them is linked",
+            c.scheme(),
+            linked,
+            c.name
+        );
+    }
+    Ok(())
+}
+
+/// Links the capability `c` to its `implementor` (see `linked_implementor`).
+#[allow(clippy::too_many_arguments)]
+fn link_capability(
+    builder: &mut Builder,
+    c: &Capability,
+    implementor: &str,
+    toml: &TomlFile,
+    toml_file_path: &str,
+    resource_map: &Arc<Mutex<StateTable>>,
+    limits: &Limits,
+    headroom: &Headroom,
+) -> Result<()> {
+    // the states of the capabilities only differ in the secret stores they read from
+    let basic = |secret_stores: &[String]| {
+        basic_state(
+            toml,
+            c,
+            resource_map.clone(),
+            secret_stores,
+            toml_file_path,
+            headroom,
+            limits,
+        )
+    };
+    let resource_type: &str = implementor;
+    match resource_type {
+        _ if EVENTS_DRIVERS.contains(&resource_type) => {
+            let events = c.events.clone().unwrap_or_default();
+            builder.link_capability::<Events>(
+                "events".to_string(),
+                EventsState::new(resource_type, resource_map.clone())?
+                    .with_invocations(limits.invocations.clone())
+                    .with_batching(
+                        batch_settings(events.batch_window_ms, events.batch_max_size)
+                            .map(|settings| EventBatching::new(settings, events.max_redeliveries)),
+                    ),
+            )?;
+        }
+        _ if KV_HOST_IMPLEMENTORS.contains(&resource_type) => {
+            if let Some(ss) = &toml.secret_stores() {
+                let kv = c.kv.clone().unwrap_or_default();
+                let mut kv_state = KvState::new(resource_type.to_string(), basic(ss))
+                    .with_allow_clear(kv.allow_clear.unwrap_or(false))
+                    .with_read_cache(
+                        kv.cache_ttl_secs.map(Duration::from_secs),
+                        kv.cache_max_entries,
+                    )
+                    .with_batching(limits.batches.get(
+                        &c.name,
+                        batch_settings(kv.batch_window_ms, kv.batch_max_size),
+                    ))
+                    .with_encoding(
+                        kv.encoding
+                            .as_deref()
+                            .map(Encoding::parse)
+                            .transpose()?
+                            .unwrap_or_default(),
+                    )
+                    .with_reopen_released(kv.reopen_released.unwrap_or(true))
+                    .with_page_tokens(page_tokens(&kv, ss, toml_file_path, limits)?)
+                    .with_drain_grace(
+                        kv.drain_grace_ms
+                            .map(Duration::from_millis)
+                            .unwrap_or(DEFAULT_DRAIN_GRACE),
+                    );
+                if let Some((canary, split)) = traffic_split(&c.name, &kv)? {
+                    let split = limits.metrics.split(&c.name, split);
+                    kv_state = kv_state.with_traffic_split(canary, split);
+                }
+                builder.link_capability::<Kv>("kv".to_string(), kv_state)?;
+            } else {
+                bail!("the kv capability requires a secret store of some type (i.e., envvars, or usersecrets) specified in your config file so it knows where to grab, say, the AZURE_STORAGE_ACCOUNT, and AZURE_STORAGE_KEY from.")
+            }
+        }
+        _ if MQ_HOST_IMPLEMENTORS.contains(&resource_type) => {
+            if let Some(ss) = &toml.secret_stores() {
+                builder.link_capability::<Mq>(
+                    "mq".to_string(),
+                    MqState::new(resource_type.to_string(), basic(ss))
+                        .with_signing(signing(c, ss, toml_file_path)?)
+                        .with_dead_letter_queue(
+                            c.mq.as_ref().and_then(|mq| mq.dead_letter_queue.clone()),
+                        ),
+                )?;
+            } else {
+                bail!("the mq capability requires a secret store of some type (i.e., envvars, or usersecrets) specified in your config file so it knows where to grab the AZURE_SERVICE_BUS_NAMESPACE, AZURE_POLICY_NAME, and AZURE_POLICY_KEY from.")
+            }
+        }
+        _ if LOCKD_HOST_IMPLEMENTORS.contains(&resource_type) => {
+            if let Some(ss) = &toml.secret_stores() {
+                let state = LockdState::new(resource_type.to_string(), basic(ss));
+                // an etcd server that's too old fails here, rather than on the guest's first lock
+                state.check_compatibility()?;
+                builder.link_capability::<Lockd>("lockd".to_string(), state)?;
+            } else {
+                bail!("the lockd capability requires a secret store of some type (i.e., envvars, or usersecrets) specified in your config file so it knows where to grab the ETCD_ENDPOINT.")
+            }
+        }
+        _ if ELECTION_HOST_IMPLEMENTORS.contains(&resource_type) => {
+            if let Some(ss) = &toml.secret_stores() {
+                let state = ElectionState::new(resource_type.to_string(), basic(ss))
+                    .with_lease_ttl(
+                        c.election
+                            .as_ref()
+                            .and_then(|election| election.lease_ttl_secs)
+                            .map(Duration::from_secs),
+                    );
+                state.check_compatibility()?;
+                builder.link_capability::<Election>("election".to_string(), state)?;
+            } else {
+                bail!("the election capability requires a secret store of some type (i.e., envvars, or usersecrets) specified in your config file so it knows where to grab the ETCD_ENDPOINT.")
+            }
+        }
+        _ if PUBSUB_HOST_IMPLEMENTORS.contains(&resource_type) => {
+            // pubsub.inmemory has no secrets to grab
+            let secret_stores = toml
+                .secret_stores()
+                .or_else(|| (resource_type == "pubsub.inmemory").then(Vec::new));
+            if let Some(ss) = &secret_stores {
+                let pubsub = c.pubsub.clone().unwrap_or_default();
+                builder.link_capability::<Pubsub>(
+                    "pubsub".to_string(),
+                    PubsubState::new(resource_type.to_string(), basic(ss))
+                        .with_dedup_settings(dedup_settings(&pubsub)?)
+                        .with_delivery(delivery(&c.name, &pubsub)?)
+                        .with_signing(signing(c, ss, toml_file_path)?)
+                        .with_schema_registry_settings(schema_registry_settings(
+                            &c.name,
+                            &pubsub,
+                            toml_file_path,
+                        )?),
+                )?;
+            } else {
+                bail!("the mq capability requires a secret store of some type (i.e., envvars, or usersecrets) specified in your config file so it knows where to grab the AZURE_SERVICE_BUS_NAMESPACE, AZURE_POLICY_NAME, and AZURE_POLICY_KEY from.")
+            }
+        }
+        _ if CONFIGS_HOST_IMPLEMENTORS.contains(&resource_type) => {
+            builder.link_capability::<Configs>(
+                "configs".to_string(),
+                ConfigsState::new(
+                    resource_type.to_string(),
+                    // configs.http reads the config server's url from the secret store
+                    basic(&toml.secret_stores().unwrap_or_default()),
+                )
+                .with_defaults(
+                    c.configs
+                        .as_ref()
+                        .and_then(|configs| configs.defaults.clone())
+                        .unwrap_or_default(),
+                ),
+            )?;
+        }
+        _ if CREDENTIALS_HOST_IMPLEMENTORS.contains(&resource_type) => {
+            builder.link_capability::<slight_credentials::Credentials>(
+                "credentials".to_string(),
+                CredentialsState::new(
+                    resource_type.to_string(),
+                    // credentials.azuread reads the app registration from the secret store
+                    basic(&toml.secret_stores().unwrap_or_default()),
+                    c.credentials
+                        .as_ref()
+                        .and_then(|credentials| credentials.scopes.clone())
+                        .unwrap_or_default(),
+                ),
+            )?;
+        }
+        _ if DOCSTORE_HOST_IMPLEMENTORS.contains(&resource_type) => {
+            builder.link_capability::<Docstore>(
+                "docstore".to_string(),
+                DocstoreState::new(
+                    resource_type.to_string(),
+                    // docstore.awsdynamodb reads the aws credentials from the secret store
+                    basic(&toml.secret_stores().unwrap_or_default()),
+                ),
+            )?;
+        }
+        _ if TIMESERIES_HOST_IMPLEMENTORS.contains(&resource_type) => {
+            builder.link_capability::<Timeseries>(
+                "timeseries".to_string(),
+                TimeseriesState::new(
+                    resource_type.to_string(),
+                    // timeseries.influxdb reads its url, org, and token from the secret store
+                    basic(&toml.secret_stores().unwrap_or_default()),
+                ),
+            )?;
+        }
+        "platform" => {
+            builder.link_capability::<Platform>(
+                resource_type.to_string(),
+                PlatformState::new(basic(&[])),
+            )?;
+        }
+        "deployment" => {
+            builder.link_capability::<Deployment>(
+                resource_type.to_string(),
+                DeploymentState::new(basic(&[])).with_context(deployment_context(
+                    c.deployment.clone().unwrap_or_default(),
+                )?),
+            )?;
+        }
+        "crypto" => {
+            builder.link_capability::<Crypto>(
+                resource_type.to_string(),
+                CryptoState::new(basic(&toml.secret_stores().unwrap_or_default())),
+            )?;
+        }
+        "validation" => {
+            builder.link_capability::<Validation>(
+                resource_type.to_string(),
+                ValidationState::new(basic(&[])).with_rule_sets(rule_sets(
+                    &c.validation.clone().unwrap_or_default(),
+                    toml_file_path,
+                )?),
+            )?;
+        }
+        "parsing" => {
+            builder.link_capability::<Parsing>(
+                resource_type.to_string(),
+                ParsingState::new(basic(&[])),
+            )?;
+        }
+        "runtime_control" => {
+            builder.link_capability::<RuntimeControl>(
+                resource_type.to_string(),
+                RuntimeControlState::new(basic(&[])).with_capabilities(capability_support(toml)?),
+            )?;
+        }
+        "jobs" => {
+            // jobs are kept in a kv implementor, which may read its credentials
+            // from the secret store
+            builder.link_capability::<Jobs>(
+                resource_type.to_string(),
+                JobsState::new(
+                    jobs_store(&c.jobs.clone().unwrap_or_default())?,
+                    basic(&toml.secret_stores().unwrap_or_default()),
+                ),
+            )?;
+        }
+        "timers" => {
+            // timers are kept in a kv implementor, and locked in a lockd implementor,
+            // which may read their credentials from the secret store
+            builder.link_capability::<Timers>(
+                resource_type.to_string(),
+                TimersState::new(
+                    timers_settings(&c.timers.clone().unwrap_or_default())?,
+                    basic(&toml.secret_stores().unwrap_or_default()),
+                ),
+            )?;
+        }
+        "webhooks" => {
+            // the webhooks are served by the http server (see `webhooks`), which hands
+            // their deliveries to the guest through the capability
+            if !toml
+                .capability
+                .iter()
+                .flatten()
+                .any(|c| c.scheme() == "http")
+            {
+                bail!("invalid webhooks: they're served by the http server, so the slightfile needs the http capability too");
+            }
+            let mut names = c
+                .webhooks
+                .iter()
+                .flatten()
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>();
+            names.sort();
+            builder.link_capability::<Webhooks>(
+                resource_type.to_string(),
+                WebhooksState::new(names, basic(&toml.secret_stores().unwrap_or_default())),
+            )?;
+        }
+        "notifications" => {
+            // the providers of the channels read their credentials from the secret store
+            builder.link_capability::<Notifications>(
+                resource_type.to_string(),
+                NotificationsState::new(
+                    notification_channels(c, toml_file_path, limits)?,
+                    basic(&toml.secret_stores().unwrap_or_default()),
+                ),
+            )?;
+        }
+        "workflow" => {
+            // the workflows are validated here, so an inconsistent one fails the run, and
+            // their instances are kept in a kv implementor, which may read its credentials
+            // from the secret store
+            let workflow = c.workflow.clone().unwrap_or_default();
+            builder.link_capability::<Workflow>(
+                resource_type.to_string(),
+                WorkflowState::new(
+                    workflow_definitions(&workflow)?,
+                    workflow_store(&workflow)?,
+                    basic(&toml.secret_stores().unwrap_or_default()),
+                ),
+            )?;
+        }
+        "http" => {
+            let slightfile_dir = Path::new(toml_file_path)
+                .parent()
+                .unwrap_or_else(|| Path::new(""));
+            let http = c.http.clone().unwrap_or_default();
+            let settings = HttpSettings {
+                templates_dir: http
+                    .templates_dir
+                    .as_ref()
+                    .map(|dir| slightfile_dir.join(dir)),
+                access_log: access_log_settings(&http)?,
+                formats: http
+                    .formats
+                    .iter()
+                    .flatten()
+                    .map(|format| Format::parse(format))
+                    .collect::<Result<_>>()?,
+                openapi: http
+                    .openapi
+                    .as_ref()
+                    .map(|spec| OpenApi::load(&slightfile_dir.join(spec)))
+                    .transpose()?,
+                enrichment: EnrichmentSettings::new(
+                    http.enrich.as_deref().unwrap_or_default(),
+                    http.trusted_proxies.as_deref().unwrap_or_default(),
+                    http.geoip_database
+                        .as_ref()
+                        .map(|db| slightfile_dir.join(db))
+                        .as_deref(),
+                )?,
+                request_timeout: http.request_timeout_ms.map(Duration::from_millis),
+                tls: tls_settings(&http, slightfile_dir)?,
+                cache_policies: http
+                    .cache_policies
+                    .iter()
+                    .flatten()
+                    .map(|(route, policy)| Ok((route.clone(), CachePolicy::parse(policy)?)))
+                    .collect::<Result<_>>()?,
+                webhooks: webhooks(toml, toml_file_path)?,
+                max_payload_bytes: c.max_payload_bytes,
+            };
+            builder.link_capability::<Http>(
+                resource_type.to_string(),
+                HttpState::new(resource_map.clone(), settings)
+                    .with_invocations(limits.invocations.clone()),
+            )?;
+        }
+        _ => {
+            bail!("invalid url: currently slight only supports 'configs.usersecrets', 'configs.envvars', 'configs.http', 'configs.configmap', 'credentials.awssts', 'credentials.azuread', 'docstore.filesystem', 'docstore.awsdynamodb', 'timeseries.filesystem', 'timeseries.influxdb', 'events', 'events.inmemory', 'kv.filesystem', 'kv.azblob', 'kv.awsdynamodb', 'mq.filesystem', 'mq.azsbus', 'lockd.etcd', 'election.etcd', 'pubsub.confluent_apache_kafka', 'pubsub.inmemory', 'jobs', 'timers', 'webhooks', 'notifications', 'workflow', 'platform', 'deployment', 'parsing', 'crypto', 'validation', 'runtime_control', and 'http' schemes")
+        }
+    }
+    Ok(())
+}
+
+/// Gets the context the `deployment` capability exposes to the guest.
+fn deployment_context(deployment: DeploymentOptions) -> Result<DeploymentContext> {
+    DeploymentContext::new(
+        deployment.environment,
+        deployment.region,
+        deployment.deployment_id,
+        deployment.metadata.unwrap_or_default(),
+    )
+}
+
+/// Whether a capability is linked as per its `when` condition (i.e., it has none, or it holds).
+pub fn condition_holds(c: &Capability) -> Result<bool> {
+    match &c.when {
+        Some(when) => Ok(Condition::parse(when)?.evaluate(|var| std::env::var(var).ok())),
+        None => Ok(true),
+    }
+}
+
+/// The env var that overrides the backend of the capabilities of `scheme` (e.g.,
+/// `SLIGHT_KV_BACKEND` for `kv`, and `SLIGHT_RUNTIME_CONTROL_BACKEND` for `runtime_control`).
+pub(super) fn backend_override_var(scheme: &str) -> String {
+    format!("SLIGHT_{}_BACKEND", scheme.to_uppercase())
+}
+
+/// The implementors a capability of `scheme` can be linked to.
+fn host_implementors(scheme: &str) -> &'static [&'static str] {
+    match scheme {
+        "events" => &EVENTS_DRIVERS,
+        "kv" => &KV_HOST_IMPLEMENTORS,
+        "mq" => &MQ_HOST_IMPLEMENTORS,
+        "lockd" => &LOCKD_HOST_IMPLEMENTORS,
+        "election" => &ELECTION_HOST_IMPLEMENTORS,
+        "pubsub" => &PUBSUB_HOST_IMPLEMENTORS,
+        "configs" => &CONFIGS_HOST_IMPLEMENTORS,
+        "credentials" => &CREDENTIALS_HOST_IMPLEMENTORS,
+        "docstore" => &DOCSTORE_HOST_IMPLEMENTORS,
+        "timeseries" => &TIMESERIES_HOST_IMPLEMENTORS,
+        "http" => &["http"],
+        "jobs" => &["jobs"],
+        "timers" => &["timers"],
+        "webhooks" => &["webhooks"],
+        "notifications" => &["notifications"],
+        "workflow" => &["workflow"],
+        "platform" => &["platform"],
+        "deployment" => &["deployment"],
+        "parsing" => &["parsing"],
+        "crypto" => &["crypto"],
+        "validation" => &["validation"],
+        "runtime_control" => &["runtime_control"],
+        _ => &[],
+    }
+}
+
+/// The implementor a capability is linked to: the one its `SLIGHT_<SCHEME>_BACKEND` env var
+/// names, if it's set (e.g., `SLIGHT_KV_BACKEND=kv.azblob`), or else the one it's declared as.
+///
+/// Overrides apply after `when` conditions (i.e., conditions pick which declaration of a scheme is
+/// linked, and the override swaps its backend, keeping its settings), and must name an
+/// implementor of the same scheme.
+pub fn linked_implementor(c: &Capability) -> Result<String> {
+    let var = backend_override_var(c.scheme());
+    let implementor = match std::env::var(&var) {
+        Ok(implementor) if !implementor.is_empty() => implementor,
+        _ => return Ok(c.name.clone()),
+    };
+    let implementors = host_implementors(c.scheme());
+    if !implementors.contains(&implementor.as_str()) {
+        bail!(
+            "invalid {}: '{}' is not a {} implementor (i.e., one of {:?})",
+            var,
+            implementor,
+            c.scheme(),
+            implementors
+        );
+    }
+    Ok(implementor)
+}
+
+/// Describes the effective configuration of the backend of capability `c`, linked to
+/// `implementor` (see `slight_runtime::describe`), w/ the settings it'd be linked w/ — or
+/// `None` if it has no backend of its own (e.g., `http`, or `timers`).
+pub fn describe_capability(
+    toml: &TomlFile,
+    c: &Capability,
+    implementor: &str,
+    toml_file_path: &str,
+) -> Result<Option<Description>> {
+    let state = || {
+        basic_state(
+            toml,
+            c,
+            Arc::new(Mutex::new(StateTable::default())),
+            &toml.secret_stores().unwrap_or_default(),
+            toml_file_path,
+            &Headroom::default(),
+            &Limits::default(),
+        )
+    };
+    let implementor_name = implementor.to_string();
+    let description = match implementor {
+        _ if KV_HOST_IMPLEMENTORS.contains(&implementor) => {
+            KvState::new(implementor_name, state()).describe()?
+        }
+        _ if MQ_HOST_IMPLEMENTORS.contains(&implementor) => {
+            MqState::new(implementor_name, state()).describe()?
+        }
+        _ if LOCKD_HOST_IMPLEMENTORS.contains(&implementor) => {
+            LockdState::new(implementor_name, state()).describe()?
+        }
+        _ if ELECTION_HOST_IMPLEMENTORS.contains(&implementor) => {
+            ElectionState::new(implementor_name, state()).describe()?
+        }
+        _ if PUBSUB_HOST_IMPLEMENTORS.contains(&implementor) => {
+            PubsubState::new(implementor_name, state()).describe()?
+        }
+        _ if DOCSTORE_HOST_IMPLEMENTORS.contains(&implementor) => {
+            DocstoreState::new(implementor_name, state()).describe()?
+        }
+        _ if TIMESERIES_HOST_IMPLEMENTORS.contains(&implementor) => {
+            TimeseriesState::new(implementor_name, state()).describe()?
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(description))
+}
+
+/// Gets the settings for deduplicating pubsub messages, if enabled (i.e., a window is set).
+fn dedup_settings(pubsub: &PubsubOptions) -> Result<Option<DedupSettings>> {
+    let window_secs = match pubsub.dedup_window_secs {
+        Some(window_secs) => window_secs,
+        None => return Ok(None),
+    };
+    let store = pubsub
+        .dedup_store
+        .clone()
+        .unwrap_or_else(|| "kv.filesystem".to_string());
+    if !KV_HOST_IMPLEMENTORS.contains(&store.as_str()) {
+        bail!(
+            "invalid pubsub.dedup_store: '{}' is not a kv implementor (i.e., one of {:?})",
+            store,
+            KV_HOST_IMPLEMENTORS
+        );
+    }
+    if store == "kv.azblob" {
+        bail!("invalid pubsub.dedup_store: kv.azblob does not support keys w/ a time to live");
+    }
+    Ok(Some(DedupSettings {
+        store,
+        window: Duration::from_secs(window_secs),
+        id_header: pubsub.dedup_id_header.clone(),
+    }))
+}
+
+/// Gets the kv implementor the jobs capability keeps jobs in.
+fn jobs_store(jobs: &JobsOptions) -> Result<String> {
+    let store = jobs
+        .store
+        .clone()
+        .unwrap_or_else(|| "kv.filesystem".to_string());
+    if !KV_HOST_IMPLEMENTORS.contains(&store.as_str()) {
+        bail!(
+            "invalid jobs.store: '{}' is not a kv implementor (i.e., one of {:?})",
+            store,
+            KV_HOST_IMPLEMENTORS
+        );
+    }
+    Ok(store)
+}
+
+/// Gets the kv implementor the workflow capability keeps instances in.
+fn workflow_store(workflow: &WorkflowOptions) -> Result<String> {
+    let store = workflow
+        .store
+        .clone()
+        .unwrap_or_else(|| "kv.filesystem".to_string());
+    if !KV_HOST_IMPLEMENTORS.contains(&store.as_str()) {
+        bail!(
+            "invalid workflow.store: '{}' is not a kv implementor (i.e., one of {:?})",
+            store,
+            KV_HOST_IMPLEMENTORS
+        );
+    }
+    Ok(store)
+}
+
+/// Gets the workflows of the workflow capability, failing if any of them is inconsistent
+/// (see `Definition::new`).
+fn workflow_definitions(workflow: &WorkflowOptions) -> Result<Vec<Definition>> {
+    let mut configured = workflow.workflows.iter().flatten().collect::<Vec<_>>();
+    configured.sort_by_key(|(name, _)| *name);
+    configured
+        .into_iter()
+        .map(|(name, workflow)| {
+            let transitions = workflow
+                .transitions
+                .iter()
+                .map(|t| Transition::new(&t.from, &t.event, &t.to))
+                .collect::<Vec<_>>();
+            Definition::new(
+                name,
+                &workflow.initial,
+                &workflow.states,
+                workflow.final_states.as_deref().unwrap_or_default(),
+                &transitions,
+                workflow.max_history,
+            )
+        })
+        .collect()
+}
+
+/// Gets the settings of the timers capability: the kv implementor timers are kept in, the lockd
+/// implementor they're locked in (if any), the election implementor hosts campaign in to fire
+/// leader-only timers (if any), and how misfired timers are handled.
+fn timers_settings(timers: &TimersOptions) -> Result<TimersSettings> {
+    let store = timers
+        .store
+        .clone()
+        .unwrap_or_else(|| "kv.filesystem".to_string());
+    if !KV_HOST_IMPLEMENTORS.contains(&store.as_str()) {
+        bail!(
+            "invalid timers.store: '{}' is not a kv implementor (i.e., one of {:?})",
+            store,
+            KV_HOST_IMPLEMENTORS
+        );
+    }
+    if let Some(lock) = &timers.lock {
+        if !LOCKD_HOST_IMPLEMENTORS.contains(&lock.as_str()) {
+            bail!(
+                "invalid timers.lock: '{}' is not a lockd implementor (i.e., one of {:?})",
+                lock,
+                LOCKD_HOST_IMPLEMENTORS
+            );
+        }
+    }
+    if let Some(election) = &timers.election {
+        if !ELECTION_HOST_IMPLEMENTORS.contains(&election.as_str()) {
+            bail!(
+                "invalid timers.election: '{}' is not an election implementor (i.e., one of {:?})",
+                election,
+                ELECTION_HOST_IMPLEMENTORS
+            );
+        }
+    }
+    let defaults = Misfire::default();
+    Ok(TimersSettings {
+        store,
+        lock: timers.lock.clone(),
+        election: timers.election.clone(),
+        misfire: Misfire {
+            policy: timers
+                .misfire_policy
+                .as_deref()
+                .map_or(Ok(defaults.policy), MisfirePolicy::parse)?,
+            grace: timers
+                .misfire_grace_secs
+                .map_or(defaults.grace, Duration::from_secs),
+        },
+    })
+}
+
+/// Gets the webhooks the http server exposes (i.e., those of the webhooks capability, if it's
+/// linked), w/ their secrets read from the secret stores.
+fn webhooks(toml: &TomlFile, toml_file_path: &str) -> Result<Vec<Webhook>> {
+    let secret_stores = toml.secret_stores().unwrap_or_default();
+    let mut webhooks: Vec<Webhook> = Vec::new();
+    for c in toml.capabilities_in_link_order()? {
+        if c.scheme() != "webhooks" || !condition_holds(c)? {
+            continue;
+        }
+        let mut configured = c.webhooks.iter().flatten().collect::<Vec<_>>();
+        configured.sort_by_key(|(name, _)| *name);
+        for (name, webhook) in configured {
+            let scheme = SignatureScheme::parse(
+                &webhook.scheme,
+                webhook.signature_header.as_deref(),
+                webhook.tolerance_secs,
+            )
+            .with_context(|| format!("invalid webhook '{}'", name))?;
+            let secret =
+                slight_runtime_configs::resolve(&secret_stores, &webhook.secret, toml_file_path)
+                    .with_context(|| {
+                        format!(
+                            "failed to get the secret '{}' of webhook '{}'",
+                            webhook.secret, name
+                        )
+                    })?;
+            if let Some(other) = webhooks.iter().find(|other| other.path == webhook.path) {
+                bail!(
+                    "invalid webhook '{}': webhook '{}' has the same path ('{}')",
+                    name,
+                    other.name,
+                    webhook.path
+                );
+            }
+            webhooks.push(Webhook::new(name, &webhook.path, scheme, secret)?);
+        }
+    }
+    Ok(webhooks)
+}
+
+/// Gets the channels of the notifications capability, w/ their templates read from files
+/// relative to the slightfile, and their rate limits taken from the app's `limits` (i.e., a
+/// quota per channel, shared by all of its guest instances).
+fn notification_channels(
+    capability: &Capability,
+    toml_file_path: &str,
+    limits: &Limits,
+) -> Result<Vec<Channel>> {
+    let slightfile_dir = Path::new(toml_file_path)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    let mut configured = capability
+        .notifications
+        .iter()
+        .flat_map(|notifications| notifications.channels.iter().flatten())
+        .collect::<Vec<_>>();
+    configured.sort_by_key(|(name, _)| *name);
+    configured
+        .into_iter()
+        .map(|(name, channel)| {
+            let provider = Provider::parse(&channel.provider)
+                .with_context(|| format!("invalid channel '{}'", name))?;
+            let mut templates = channel
+                .templates
+                .iter()
+                .map(|(template, path)| {
+                    let source =
+                        fs::read_to_string(slightfile_dir.join(path)).with_context(|| {
+                            format!(
+                                "failed to read template '{}' of channel '{}' (i.e., '{}')",
+                                template, name, path
+                            )
+                        })?;
+                    Ok((template.clone(), source))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            templates.sort();
+            let channel = Channel::new(
+                name,
+                provider,
+                channel.from.clone(),
+                channel.subject.as_deref(),
+                &templates,
+                channel.max_retries,
+            )?
+            .with_quota(limits.quotas.get(
+                &format!("{}.{}", capability.name, name),
+                QuotaSettings {
+                    ops_per_sec: channel.ops_per_sec,
+                    bytes_per_min: None,
+                },
+            ));
+            Ok(channel)
+        })
+        .collect()
+}
+
+/// Gets the settings of the http capability's access log, if enabled (i.e., a format is set).
+fn access_log_settings(http: &HttpOptions) -> Result<Option<AccessLogSettings>> {
+    let format = match &http.access_log {
+        Some(format) => AccessLogFormat::parse(format)?,
+        None => return Ok(None),
+    };
+    let redacted_headers = match &http.access_log_redacted_headers {
+        Some(headers) => headers.clone(),
+        None => DEFAULT_REDACTED_HEADERS.map(str::to_string).to_vec(),
+    };
+    Ok(Some(AccessLogSettings {
+        format,
+        redacted_headers,
+    }))
+}
+
+/// Gets how the http capability terminates TLS, if it's served over it (i.e., a `tls_cert` is
+/// set), w/ its files relative to the slightfile.
+fn tls_settings(http: &HttpOptions, slightfile_dir: &Path) -> Result<Option<TlsSettings>> {
+    let (cert, key) = match (&http.tls_cert, &http.tls_key) {
+        (Some(cert), Some(key)) => (cert, key),
+        (None, None) => {
+            if http.tls_client_ca.is_some() || http.tls_client_auth.is_some() {
+                bail!("invalid tls_client_ca: client certificates are only verified w/ a tls_cert");
+            }
+            return Ok(None);
+        }
+        _ => bail!("invalid tls_cert: it's set w/ a tls_key, or not at all"),
+    };
+    let client_ca = http
+        .tls_client_ca
+        .as_ref()
+        .map(|ca| slightfile_dir.join(ca));
+    let client_auth = http
+        .tls_client_auth
+        .as_deref()
+        .map(ClientAuth::parse)
+        .transpose()?;
+    Ok(Some(TlsSettings::new(
+        &slightfile_dir.join(cert),
+        &slightfile_dir.join(key),
+        client_ca.as_deref(),
+        client_auth,
+    )?))
+}
+
+fn delivery(name: &str, pubsub: &PubsubOptions) -> Result<Delivery> {
+    match &pubsub.delivery {
+        Some(delivery) if name != "pubsub.inmemory" => bail!(
+            "invalid pubsub.delivery: '{}' only applies to pubsub.inmemory",
+            delivery
+        ),
+        Some(delivery) => Delivery::parse(delivery),
+        None => Ok(Delivery::default()),
+    }
+}
+
+/// Gets the signing of the messages of the mq, or pubsub capability, if enabled (i.e., it has
+/// `signing`), w/ its key read from the secret stores.
+fn signing(
+    capability: &Capability,
+    secret_stores: &[String],
+    toml_file_path: &str,
+) -> Result<Option<Signing>> {
+    let signing = match &capability.signing {
+        Some(signing) => signing,
+        None if matches!(&capability.mq, Some(mq) if mq.dead_letter_queue.is_some()) => {
+            bail!("invalid mq.dead_letter_queue: it requires signing")
+        }
+        None => return Ok(None),
+    };
+    if capability.name == "pubsub.inmemory" {
+        bail!("invalid signing: the messages of pubsub.inmemory never leave the app's process, so they aren't signed");
+    }
+    let algorithm = Algorithm::parse(signing.algorithm.as_deref().unwrap_or("hmac-sha256"))?;
+    let key = slight_runtime_configs::resolve(secret_stores, &signing.key, toml_file_path)
+        .with_context(|| format!("failed to get the signing key '{}'", signing.key))?;
+    if key.is_empty() {
+        bail!("invalid signing key: '{}' is empty", signing.key);
+    }
+    Ok(Some(Signing::new(
+        SigningKey::new(algorithm, key),
+        signing.enforce.unwrap_or(true),
+    )))
+}
+
+/// Gets the page tokens of the kv capability: sealed w/ a key derived from the secret its
+/// `page_token_key` names, if it's set, or else w/ the app's (see `Limits`).
+fn page_tokens(
+    kv: &KvOptions,
+    secret_stores: &[String],
+    toml_file_path: &str,
+    limits: &Limits,
+) -> Result<PageTokens> {
+    let key_name = match &kv.page_token_key {
+        Some(key_name) => key_name,
+        None => return Ok(limits.page_tokens.clone()),
+    };
+    let key = slight_runtime_configs::resolve(secret_stores, key_name, toml_file_path)
+        .with_context(|| format!("failed to get the page token key '{}'", key_name))?;
+    if key.is_empty() {
+        bail!("invalid page token key: '{}' is empty", key_name);
+    }
+    Ok(PageTokens::new(&key))
+}
+
+/// Gets the settings for serializing the values of pubsub messages w/ schemas of a schema
+/// registry, if enabled (i.e., a `schema_format` is set), w/ the `schemas` read from their
+/// files.
+fn schema_registry_settings(
+    name: &str,
+    pubsub: &PubsubOptions,
+    toml_file_path: &str,
+) -> Result<Option<SchemaRegistrySettings>> {
+    let format = match &pubsub.schema_format {
+        Some(format) => SchemaFormat::parse(format)?,
+        None if pubsub.schemas.is_some() => {
+            bail!("invalid pubsub.schemas: they require a schema_format")
+        }
+        None => return Ok(None),
+    };
+    if name != "pubsub.confluent_apache_kafka" {
+        bail!("invalid pubsub.schema_format: it only applies to pubsub.confluent_apache_kafka");
+    }
+    let slightfile_dir = Path::new(toml_file_path)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    let schemas = pubsub
+        .schemas
+        .iter()
+        .flatten()
+        .map(|(topic, file)| {
+            let path = slightfile_dir.join(file);
+            let schema = fs::read_to_string(&path).with_context(|| {
+                format!(
+                    "failed to read the schema of '{}' from {}",
+                    topic,
+                    path.display()
+                )
+            })?;
+            Ok((topic.clone(), schema))
+        })
+        .collect::<Result<_>>()?;
+    Ok(Some(SchemaRegistrySettings { format, schemas }))
+}
+
+/// Gets the rule sets the validation capability declares, parsed from their files.
+fn rule_sets(
+    validation: &ValidationOptions,
+    toml_file_path: &str,
+) -> Result<HashMap<String, Rule>> {
+    let slightfile_dir = Path::new(toml_file_path)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    validation
+        .rule_sets
+        .iter()
+        .flatten()
+        .map(|(name, file)| {
+            let path = slightfile_dir.join(file);
+            let rules = fs::read_to_string(&path).with_context(|| {
+                format!(
+                    "failed to read the rule set '{}' from {}",
+                    name,
+                    path.display()
+                )
+            })?;
+            let rule = Rule::parse(&rules)
+                .with_context(|| format!("invalid rule set '{}' in {}", name, path.display()))?;
+            Ok((name.clone(), rule))
+        })
+        .collect()
+}
+
+fn traffic_split(name: &str, kv: &KvOptions) -> Result<Option<(String, TrafficSplit)>> {
+    let canary = match &kv.canary {
+        Some(canary) => canary,
+        None => return Ok(None),
+    };
+    if !KV_HOST_IMPLEMENTORS.contains(&canary.as_str()) {
+        bail!(
+            "invalid canary: '{}' is not a kv implementor (i.e., one of {:?})",
+            canary,
+            KV_HOST_IMPLEMENTORS
+        );
+    }
+    if canary == name {
+        bail!("invalid canary: '{}' is the kv implementor itself", canary);
+    }
+    let split = TrafficSplit::new(
+        name,
+        canary,
+        kv.canary_read_percent.unwrap_or(0),
+        kv.canary_write_percent.unwrap_or(0),
+    )?;
+    tracing::info!(
+        "routing {}% of reads, and {}% of writes to canary '{}'",
+        kv.canary_read_percent.unwrap_or(0),
+        kv.canary_write_percent.unwrap_or(0),
+        canary
+    );
+    Ok(Some((canary.clone(), split)))
+}
+
+/// Builds the `BasicState` of a capability, with per-capability settings taking
+/// precedence over global ones.
+///
+/// The capability's quota, and pool are taken from the app's `limits`, so all of its
+/// guest instances are held to the same ones.
+fn basic_state(
+    toml: &TomlFile,
+    capability: &Capability,
+    resource_map: Arc<Mutex<StateTable>>,
+    secret_stores: &[String],
+    toml_file_path: &str,
+    headroom: &Headroom,
+    limits: &Limits,
+) -> BasicState {
+    let slow_call_threshold_ms = capability
+        .slow_call_threshold_ms
+        .or(toml.slow_call_threshold_ms);
+    let metrics = limits.metrics.get(
+        &capability.name,
+        label_settings(toml, capability),
+        size_buckets(toml, capability),
+    );
+    BasicState::new(resource_map, secret_stores, toml_file_path)
+        .with_call_settings(
+            CallSettings::new(slow_call_threshold_ms)
+                .with_quota(limits.quotas.get(
+                    &capability.name,
+                    QuotaSettings {
+                        ops_per_sec: capability.quota_ops_per_sec,
+                        bytes_per_min: capability.quota_bytes_per_min,
+                    },
+                ))
+                .with_pool(
+                    limits.pools.get(
+                        &capability.name,
+                        capability
+                            .max_connections
+                            .map(|max| PoolSettings::new(max, capability.connection_wait_ms)),
+                    ),
+                )
+                .with_metrics(Some(metrics.clone()))
+                .with_grants(grants(capability))
+                .with_flags(operation_flags(capability))
+                .with_audit(limits.audit.get(&capability.name))
+                // validated before the capability is linked
+                .with_payload_limit(payload_limit(capability).unwrap_or_default()),
+        )
+        .with_last_known_good(
+            LastKnownGood::new(capability.last_known_good_max_age_ms).with_metrics(Some(metrics)),
+        )
+        .with_credentials(limits.credentials.clone())
+        // validated before the capability is linked
+        .with_compression(compression(capability).unwrap_or_default())
+        .with_headroom(headroom.clone())
+        .with_trace_propagation(Propagation::new(
+            capability
+                .notifications
+                .as_ref()
+                .and_then(|notifications| notifications.propagate_trace_context)
+                .unwrap_or(true),
+        ))
+}
+
+/// The operations of a capability the guest is granted, by its `allow_operations`, and
+/// `deny_operations` (all of them, if neither is set).
+fn grants(capability: &Capability) -> Grants {
+    Grants::new(
+        capability.allow_operations.clone(),
+        capability.deny_operations.clone().unwrap_or_default(),
+    )
+}
+
+/// The operations of a capability that are enabled, by its `enable_operations`, and
+/// `disable_operations` (all but the experimental ones, if neither is set).
+pub(super) fn operation_flags(capability: &Capability) -> OperationFlags {
+    OperationFlags::new(
+        experimental_operations(capability.scheme()),
+        capability.enable_operations.clone().unwrap_or_default(),
+        capability.disable_operations.clone().unwrap_or_default(),
+    )
+}
+
+/// The operations of the capabilities of `scheme` that are opt-in while they're new.
+fn experimental_operations(scheme: &str) -> &'static [&'static str] {
+    match scheme {
+        "kv" => slight_kv::EXPERIMENTAL_OPERATIONS,
+        "mq" => slight_mq::EXPERIMENTAL_OPERATIONS,
+        _ => &[],
+    }
+}
+
+/// The max size of the payloads the guest sends through a capability, which defaults to the
+/// capability's own (or `None` for capabilities that don't move data to a backend).
+fn payload_limit(capability: &Capability) -> Result<Option<PayloadLimit>> {
+    let default = match capability.scheme() {
+        "kv" => slight_kv::DEFAULT_MAX_PAYLOAD_BYTES,
+        "mq" => slight_mq::DEFAULT_MAX_PAYLOAD_BYTES,
+        "pubsub" => slight_pubsub::DEFAULT_MAX_PAYLOAD_BYTES,
+        // http checks the bodies of requests itself (see `HttpSettings::max_payload_bytes`)
+        "http" => return Ok(None),
+        _ if capability.max_payload_bytes.is_some() => bail!(
+            "only kv, mq, pubsub, and http capabilities limit their payloads, not '{}'",
+            capability.name
+        ),
+        _ => return Ok(None),
+    };
+    Ok(Some(PayloadLimit::new(
+        &capability.name,
+        capability.max_payload_bytes.unwrap_or(default),
+    )))
+}
+
+/// How the payloads a capability returns to the guest are compressed, or `None` if they aren't.
+fn compression(capability: &Capability) -> Result<Option<Compression>> {
+    let codec = match &capability.compress_results {
+        Some(codec) => Codec::parse(codec)?,
+        None if capability.compress_threshold_bytes.is_some() => {
+            bail!("`compress_threshold_bytes` is set, but `compress_results` isn't")
+        }
+        None => return Ok(None),
+    };
+    if !matches!(capability.scheme(), "kv" | "mq") {
+        bail!(
+            "only kv, and mq capabilities compress their results, not '{}'",
+            capability.name
+        );
+    }
+    Ok(Some(Compression::new(
+        codec,
+        capability.compress_threshold_bytes,
+    )))
+}
+
+/// How the calls of a capability (or, for events, the events the guest handles) are coalesced
+/// into batches, as per the `batch_window_ms`, and `batch_max_size` of its options, or `None`
+/// if they aren't.
+fn batch_settings(window_ms: Option<u64>, max_size: Option<usize>) -> Option<BatchSettings> {
+    window_ms.map(|window_ms| BatchSettings::new(window_ms, max_size))
+}
+
+/// The labels the calls of a capability are counted w/, w/ per-capability settings taking
+/// precedence over the slightfile's `metrics` ones.
+fn label_settings(toml: &TomlFile, capability: &Capability) -> LabelSettings {
+    let metrics = toml.metrics.as_ref();
+    LabelSettings {
+        target_label: capability
+            .metrics_target_label
+            .or_else(|| metrics.and_then(|metrics| metrics.target_label))
+            .unwrap_or(true),
+        max_targets: capability
+            .metrics_max_targets
+            .or_else(|| metrics.and_then(|metrics| metrics.max_targets))
+            .unwrap_or(DEFAULT_MAX_TARGETS),
+    }
+}
+
+/// The buckets the sizes of a capability's keys, and values are counted in, w/ per-capability
+/// settings taking precedence over the slightfile's `metrics` ones.
+fn size_buckets(toml: &TomlFile, capability: &Capability) -> SizeBuckets {
+    let metrics = toml.metrics.as_ref();
+    let kv = capability.kv.as_ref();
+    SizeBuckets::new(
+        kv.and_then(|kv| kv.metrics_key_size_buckets.clone())
+            .or_else(|| metrics.and_then(|metrics| metrics.key_size_buckets.clone()))
+            .unwrap_or_else(|| DEFAULT_KEY_BUCKETS.to_vec()),
+        kv.and_then(|kv| kv.metrics_value_size_buckets.clone())
+            .or_else(|| metrics.and_then(|metrics| metrics.value_size_buckets.clone()))
+            .unwrap_or_else(|| DEFAULT_VALUE_BUCKETS.to_vec()),
+    )
+}
@@ -0,0 +1,1146 @@
+use std::{
+    collections::HashMap,
+    fs,
+    future::Future,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+use as_any::Downcast;
+use slight_events::{drivers::EVENTS_DRIVERS, Events};
+use slight_events_api::{event_handler::EventHandler, EventBatchHandler};
+use slight_http::Http;
+use slight_kv::HostKv;
+use slight_lockd::HostLockd;
+use slight_runtime::{
+    audit::{Audit, AuditSettings},
+    batch::Batches,
+    call::guest_phase,
+    cassette::Cassette,
+    chaos::{self, Chaos, ChaosSettings, Fault, DEFAULT_CHAOS_LATENCY},
+    credentials::Credentials,
+    default_config,
+    invocations::Invocations,
+    manifest::Manifest,
+    memory::{GrowthAction, GrowthSettings, MemoryMonitor},
+    metrics::Metrics,
+    mock::{Mock, Mocks},
+    page_token::PageTokens,
+    pool::Pools,
+    quota::Quotas,
+    resource::{release_all, BasicState, Ctx, Resource, StateTable},
+    sandbox::{FilesystemSandbox, APP_MOUNT, SCRATCH_MOUNT},
+    support::{self, Support},
+    Builder,
+};
+use slight_runtime_control::Shutdown;
+use spiderlightning::core::slightfile::{self, Filesystem, Init, MemoryGrowth, TomlFile};
+use wit_bindgen_wasmtime::wasmtime::{Engine, Instance, Module, Store, Trap};
+
+use crate::commands::metrics_export::{MetricsExport, MetricsServers};
+
+mod link;
+
+use link::{backend_override_var, operation_flags};
+pub use link::{condition_holds, describe_capability, link_once, linked_implementor};
+
+const KV_HOST_IMPLEMENTORS: [&str; 3] = ["kv.filesystem", "kv.azblob", "kv.awsdynamodb"];
+const MQ_HOST_IMPLEMENTORS: [&str; 2] = ["mq.filesystem", "mq.azsbus"];
+const LOCKD_HOST_IMPLEMENTORS: [&str; 1] = ["lockd.etcd"];
+const ELECTION_HOST_IMPLEMENTORS: [&str; 1] = ["election.etcd"];
+const PUBSUB_HOST_IMPLEMENTORS: [&str; 2] = ["pubsub.confluent_apache_kafka", "pubsub.inmemory"];
+const CONFIGS_HOST_IMPLEMENTORS: [&str; 4] = [
+    "configs.usersecrets",
+    "configs.envvars",
+    "configs.http",
+    "configs.configmap",
+];
+const CREDENTIALS_HOST_IMPLEMENTORS: [&str; 2] = ["credentials.awssts", "credentials.azuread"];
+const DOCSTORE_HOST_IMPLEMENTORS: [&str; 2] = ["docstore.filesystem", "docstore.awsdynamodb"];
+const TIMESERIES_HOST_IMPLEMENTORS: [&str; 2] = ["timeseries.filesystem", "timeseries.influxdb"];
+
+/// The delay before the first restart of a guest that crashed, which doubles w/
+/// every restart after it, up to `MAX_RESTART_BACKOFF`.
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The exports of guests whose start is async (see `EntryPoint::Async`), and what their
+/// `_poll` returns while they're starting, and once they're done.
+const ASYNC_START_EXPORT: &str = "_start_async";
+const ASYNC_POLL_EXPORT: &str = "_poll";
+const ASYNC_POLL_PENDING: i32 = 0;
+const ASYNC_POLL_READY: i32 = 1;
+/// How long to wait between the polls of a guest whose start is pending.
+const ASYNC_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long the init lock outlives a replica that died holding it, unless the slightfile says
+/// otherwise.
+const DEFAULT_INIT_LOCK_TTL_SECS: i64 = 300;
+
+/// The limits the capability calls, linear memories, and guest invocations of an app are held
+/// to (and the metrics its capability calls are counted in, the batchers they're coalesced
+/// by, the audit they're recorded in, the key its page tokens are sealed w/, unless it
+/// has one of its own, and the credentials its capabilities authenticate w/), which are
+/// shared by all of its guest instances, and kept across restarts.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    pub quotas: Quotas,
+    pub pools: Pools,
+    pub memory: MemoryMonitor,
+    pub metrics: Metrics,
+    pub batches: Batches,
+    pub invocations: Invocations,
+    pub audit: Audit,
+    pub page_tokens: PageTokens,
+    /// fetched (and refreshed) once, for all of the app's capabilities, and guest instances
+    pub credentials: Credentials,
+    /// the servers the metrics are scraped from, which `slight serve` shares w/ all of its apps
+    pub metrics_servers: MetricsServers,
+}
+
+pub async fn handle_run(
+    module: &str,
+    toml: &TomlFile,
+    toml_file_path: &str,
+    max_restarts: u32,
+    cassette: Option<Cassette>,
+    chaos: Option<Chaos>,
+    mocks: Option<Mocks>,
+) -> Result<Option<i32>> {
+    tracing::info!("Starting slight");
+    let mut restarts = 0;
+    // limits are kept across restarts, so crashing doesn't reset them
+    let limits = Limits::default();
+    loop {
+        match run_app(
+            module,
+            toml,
+            toml_file_path,
+            None,
+            &limits,
+            cassette.as_ref(),
+            chaos.as_ref(),
+            mocks.as_ref(),
+            shutdown_signal(),
+        )
+        .await
+        {
+            // only crashes of the guest are worth restarting it for, as, say,
+            // an invalid slightfile won't fix itself.
+            Err(e) if e.is::<Trap>() && restarts < max_restarts => {
+                restarts += 1;
+                limits.metrics.record_restart();
+                let backoff = restart_backoff(restarts);
+                tracing::warn!(
+                    "{} crashed: {:#}, restarting it in {:?} (restart {} of {})",
+                    module,
+                    e,
+                    backoff,
+                    restarts,
+                    max_restarts
+                );
+                tokio::time::sleep(backoff).await;
+            }
+            Err(e) if e.is::<Trap>() && max_restarts > 0 => {
+                tracing::error!(
+                    "{} crashed again after being restarted {} times (its max restarts), giving up",
+                    module,
+                    restarts
+                );
+                return Err(e);
+            }
+            res => return res,
+        }
+    }
+}
+
+/// How long to wait before the `restart`th restart of a guest.
+pub fn restart_backoff(restart: u32) -> Duration {
+    RESTART_BACKOFF
+        .checked_mul(2u32.saturating_pow(restart.saturating_sub(1)))
+        .map_or(MAX_RESTART_BACKOFF, |backoff| {
+            backoff.min(MAX_RESTART_BACKOFF)
+        })
+}
+
+/// Runs an app, and, if it serves http, keeps it serving until `shutdown` completes, or its
+/// guest requests a shutdown (w/ the `runtime_control` capability), returning the exit code
+/// the guest requested, if it did.
+///
+/// Each app gets its own `StateTable`, so apps running in the same process
+/// (see `slight serve`) don't share resources, while the capability calls of
+/// all of its guest instances are held to the same `limits`, and, if there's a `cassette`,
+/// the calls of its capabilities to their backends are recorded to it, or replayed from it —
+/// if there's `chaos`, faults are injected into them too, and, if there are `mocks`, the calls
+/// they match are responded to w/ them.
+#[allow(clippy::too_many_arguments)]
+pub async fn run_app(
+    module: &str,
+    toml: &TomlFile,
+    toml_file_path: &str,
+    max_memory_bytes: Option<usize>,
+    limits: &Limits,
+    cassette: Option<&Cassette>,
+    chaos: Option<&Chaos>,
+    mocks: Option<&Mocks>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<Option<i32>> {
+    limits.memory.configure(
+        toml.memory_growth
+            .as_ref()
+            .map(growth_settings)
+            .transpose()?,
+    );
+    if toml.max_concurrent_invocations == Some(0) {
+        bail!("invalid max_concurrent_invocations: it must be greater than 0");
+    }
+    limits
+        .invocations
+        .configure(toml.max_concurrent_invocations);
+    limits.audit.configure(
+        toml.audit
+            .as_ref()
+            .map(|audit| audit_settings(audit, toml))
+            .transpose()?,
+    );
+    // the app is named after its module (e.g., in the metrics it exports)
+    let app = Path::new(module).file_stem().map_or_else(
+        || module.to_string(),
+        |stem| stem.to_string_lossy().into_owned(),
+    );
+    // warned about for `slight run`, and for each app `slight serve` runs
+    if toml.random_seed.is_some() {
+        tracing::warn!(
+            "the randomness of app '{}' is seeded, so its random numbers are predictable (i.e., only do this in tests)",
+            app
+        );
+    }
+    let metrics_export = toml
+        .metrics
+        .as_ref()
+        .and_then(|metrics| metrics.export.as_ref())
+        .map(|settings| {
+            MetricsExport::start(settings, &app, &limits.metrics, &limits.metrics_servers)
+        })
+        .transpose()?;
+    let resource_map = Arc::new(Mutex::new(StateTable::default()));
+    if let Some(cassette) = cassette {
+        cassette.install(&resource_map)?;
+    }
+    if let Some(chaos) = chaos {
+        chaos.install(&resource_map)?;
+    }
+    if let Some(mocks) = mocks {
+        mocks.install(&resource_map)?;
+    }
+    let requested_shutdown = Shutdown::default();
+    requested_shutdown.install(&resource_map)?;
+    log_backend_overrides(toml)?;
+    // all of the guest instances share the same sandbox (i.e., the same scratch directory)
+    let sandbox = toml
+        .filesystem
+        .as_ref()
+        .map(|filesystem| filesystem_sandbox(filesystem, toml_file_path))
+        .transpose()?;
+    // a temporary scratch directory is removed once the run is over, however it ends
+    let _scratch = sandbox.as_ref().and_then(FilesystemSandbox::scratch_guard);
+
+    // the module is compiled, and linked only once, and shared by the guest instances
+    // required by the events, and http capabilities.
+    let engine = Engine::new(&default_config()?)?;
+    let mut host_builder = build_store_instance(
+        toml,
+        toml_file_path,
+        resource_map.clone(),
+        &engine,
+        max_memory_bytes,
+        limits,
+        sandbox.as_ref(),
+    )?;
+    check_manifest(module, toml)?;
+    let compiled_module = {
+        let _phase = guest_phase("compile");
+        Module::from_file(&engine, module)?
+    };
+    let instance_pre = host_builder.pre_build(&compiled_module)?;
+    let (mut store, instance) = {
+        let _phase = guest_phase("instantiate");
+        host_builder.build_from_pre(&instance_pre)?
+    };
+
+    let caps = toml.capability.as_ref().unwrap();
+    // looking for events capability.
+    let events_enabled = caps
+        .iter()
+        .any(|cap| EVENTS_DRIVERS.contains(&cap.name.as_str()));
+
+    // looking for http capability.
+    let http_enabled = caps.iter().any(|cap| cap.name == "http");
+
+    if events_enabled {
+        log::debug!("Events capability enabled");
+        let guest_builder = build_store_instance(
+            toml,
+            toml_file_path,
+            resource_map.clone(),
+            &engine,
+            max_memory_bytes,
+            limits,
+            sandbox.as_ref(),
+        )?;
+        let (mut store2, instance2) = guest_builder.build_from_pre(&instance_pre)?;
+        let event_handler_resource: &mut Events = get_resource(&mut store, "events");
+        // guests that handle events in batches needn't export `event-handler.wit` too
+        let event_handler = if event_handler_resource.is_batching() {
+            let batch_handler =
+                EventBatchHandler::new(&mut store2, &instance2, |ctx| &mut ctx.batch_state)
+                    .with_context(|| {
+                        "events are delivered in batches (i.e., `batch_window_ms` is set on the events capability), but the guest doesn't export `event-batch-handler.wit`"
+                    })?;
+            event_handler_resource.update_batch_handler(Arc::new(Mutex::new(batch_handler)));
+            match EventHandler::new(&mut store2, &instance2, |ctx| &mut ctx.state) {
+                Ok(event_handler) => Some(event_handler),
+                Err(e) => {
+                    log::debug!(
+                        "the guest has no event handler, so events are only handed to its batch handler: {:#}",
+                        e
+                    );
+                    None
+                }
+            }
+        } else {
+            Some(EventHandler::new(&mut store2, &instance2, |ctx| {
+                &mut ctx.state
+            })?)
+        };
+        event_handler_resource.update_state(
+            Arc::new(Mutex::new(store2)),
+            event_handler.map(|event_handler| Arc::new(Mutex::new(event_handler))),
+        )?;
+    }
+
+    if http_enabled {
+        log::debug!("Http capability enabled");
+        let guest_builder = build_store_instance(
+            toml,
+            toml_file_path,
+            resource_map.clone(),
+            &engine,
+            max_memory_bytes,
+            limits,
+            sandbox.as_ref(),
+        )?;
+        let (store2, instance2) = guest_builder.build_from_pre(&instance_pre)?;
+        let http_api_resource: &mut Http = get_resource(&mut store, "http");
+        http_api_resource.update_state(
+            Arc::new(Mutex::new(store2)),
+            Arc::new(Mutex::new(instance2)),
+        )?;
+    }
+
+    if let Some(init) = &toml.init {
+        let guest_builder = build_store_instance(
+            toml,
+            toml_file_path,
+            resource_map.clone(),
+            &engine,
+            max_memory_bytes,
+            limits,
+            sandbox.as_ref(),
+        )?;
+        let (init_store, init_instance) = guest_builder.build_from_pre(&instance_pre)?;
+        initialize(
+            init,
+            toml,
+            toml_file_path,
+            resource_map.clone(),
+            init_store,
+            init_instance,
+        )?;
+    }
+
+    tracing::info!("Executing {}", module);
+    let res = match EntryPoint::detect(&mut store, &instance)? {
+        EntryPoint::Sync => {
+            let _phase = guest_phase("start");
+            instance
+                .get_typed_func::<(), _, _>(&mut store, "_start")?
+                .call(&mut store, ())
+        }
+        EntryPoint::Async => start_async(&mut store, &instance).await?,
+    };
+    if let Err(trap) = res {
+        if http_enabled {
+            // the guest may have started serving before it crashed, and, as it'll be
+            // restarted w/ capabilities linked anew, it mustn't keep serving.
+            let http_api_resource: &mut Http = get_resource(&mut store, "http");
+            http_api_resource.close();
+        }
+        return Err(trap.into());
+    }
+
+    if http_enabled {
+        log::info!("waiting for http to finish...");
+        tokio::select! {
+            _ = shutdown => {}
+            _ = requested_shutdown.requested() => {}
+        }
+        let http_api_resource: &mut Http = get_resource(&mut store, "http");
+        http_api_resource.close();
+    }
+    // the requests (or events) still being handled keep their calls in flight, so the backends
+    // are closed once those are drained (see `Releasable::release`), rather than under them
+    let released = tokio::task::spawn_blocking(move || release_all(&resource_map)).await??;
+    log::debug!("released the backends of {} resources", released);
+    if let Some(metrics_export) = metrics_export {
+        metrics_export.stop().await;
+    }
+    Ok(requested_shutdown.exit_code())
+}
+
+/// How a guest is started, as told by its exports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EntryPoint {
+    /// `_start`, which blocks until the guest is done starting (i.e., the default)
+    Sync,
+    /// `_start_async`, which begins starting the guest, and `_poll`, which drives it until it's
+    /// done, for guests whose start is a future (e.g., ones built w/ an async runtime of their
+    /// own)
+    Async,
+}
+
+impl EntryPoint {
+    /// Tells how the guest is started, where `_start` wins if it exports both, so guests
+    /// that already run keep running as they did.
+    fn detect(store: &mut Store<Ctx>, instance: &Instance) -> Result<Self> {
+        if instance.get_func(&mut *store, "_start").is_some() {
+            return Ok(Self::Sync);
+        }
+        match (
+            instance.get_func(&mut *store, ASYNC_START_EXPORT),
+            instance.get_func(&mut *store, ASYNC_POLL_EXPORT),
+        ) {
+            (Some(_), Some(_)) => Ok(Self::Async),
+            (Some(_), None) => bail!(
+                "the guest exports {}, but not {}, which drives it",
+                ASYNC_START_EXPORT,
+                ASYNC_POLL_EXPORT
+            ),
+            _ => bail!(
+                "the guest exports neither _start, nor {} (w/ {})",
+                ASYNC_START_EXPORT,
+                ASYNC_POLL_EXPORT
+            ),
+        }
+    }
+}
+
+/// Starts a guest w/ `_start_async`, and polls it w/ `_poll` until it says it's done (i.e.,
+/// returns `ASYNC_POLL_READY`), yielding to the host's runtime in between, so what the host
+/// serves (e.g., http) isn't held up by the guest's start.
+///
+/// The outer `Result` is whether the guest kept to the protocol, and the inner one whether it
+/// trapped, as w/ `_start`.
+async fn start_async(store: &mut Store<Ctx>, instance: &Instance) -> Result<Result<(), Trap>> {
+    let start = instance.get_typed_func::<(), (), _>(&mut *store, ASYNC_START_EXPORT)?;
+    let poll = instance.get_typed_func::<(), i32, _>(&mut *store, ASYNC_POLL_EXPORT)?;
+    // the phase is only entered around the guest's calls, not held across the waits
+    let res = {
+        let _phase = guest_phase("start");
+        start.call(&mut *store, ())
+    };
+    if let Err(trap) = res {
+        return Ok(Err(trap));
+    }
+    let mut polls = 0u64;
+    loop {
+        polls += 1;
+        let res = {
+            let _phase = guest_phase("start");
+            poll.call(&mut *store, ())
+        };
+        match res {
+            Ok(ASYNC_POLL_READY) => break,
+            Ok(ASYNC_POLL_PENDING) => tokio::time::sleep(ASYNC_POLL_INTERVAL).await,
+            Ok(status) => bail!(
+                "the guest's {} returned {}, which is neither pending (i.e., {}), nor ready (i.e., {})",
+                ASYNC_POLL_EXPORT,
+                status,
+                ASYNC_POLL_PENDING,
+                ASYNC_POLL_READY
+            ),
+            Err(trap) => return Ok(Err(trap)),
+        }
+    }
+    tracing::debug!("the guest started after {} polls", polls);
+    Ok(Ok(()))
+}
+
+/// Runs the guest's `_init` export (in an instance of its own), unless the marker of `init`
+/// says its `version` was initialized already, or the guest doesn't export it.
+///
+/// W/ a `lock`, replicas starting at once take turns, and the ones that get it after the first
+/// find the marker it set.
+fn initialize(
+    init: &Init,
+    toml: &TomlFile,
+    toml_file_path: &str,
+    resource_map: Arc<Mutex<StateTable>>,
+    mut store: Store<Ctx>,
+    instance: Instance,
+) -> Result<()> {
+    let func = match instance.get_func(&mut store, "_init") {
+        Some(func) => func.typed::<(), (), _>(&store)?,
+        None => {
+            tracing::debug!("the guest doesn't export _init, skipping its initialization");
+            return Ok(());
+        }
+    };
+    let marker_store = init
+        .marker_store
+        .clone()
+        .unwrap_or_else(|| "kv.filesystem".to_string());
+    if !KV_HOST_IMPLEMENTORS.contains(&marker_store.as_str()) {
+        bail!(
+            "invalid init marker_store: '{}' is not a kv implementor (i.e., one of {:?})",
+            marker_store,
+            KV_HOST_IMPLEMENTORS
+        );
+    }
+    let marker_key = init.marker_key.as_deref().unwrap_or("init");
+    let version = init.version.as_deref().unwrap_or("1");
+    let slight_state = BasicState::new(
+        resource_map,
+        &toml.secret_stores().unwrap_or_default(),
+        toml_file_path,
+    )
+    .with_credentials(Credentials::default());
+    let marker = HostKv::open(&marker_store, &slight_state, "slight-init")?;
+    let initialized = || -> Result<bool> {
+        Ok(marker.get(marker_key.as_bytes())?.as_deref() == Some(version.as_bytes()))
+    };
+    if initialized()? {
+        tracing::info!(
+            "skipping _init, as version '{}' was initialized already",
+            version
+        );
+        return Ok(());
+    }
+
+    let lock = match &init.lock {
+        Some(lockd) if LOCKD_HOST_IMPLEMENTORS.contains(&lockd.as_str()) => {
+            let lockd = HostLockd::open(lockd, &slight_state)?;
+            let lock_name = format!("slight-init-{}", marker_key);
+            tracing::info!("waiting for the init lock '{}'", lock_name);
+            let started = Instant::now();
+            // it's kept alive while _init runs, however long it takes
+            let (lock_key, kept_alive) = lockd.lock_kept_alive(
+                lock_name.as_bytes(),
+                init.lock_ttl_secs.unwrap_or(DEFAULT_INIT_LOCK_TTL_SECS),
+            )?;
+            tracing::info!(
+                "got the init lock '{}' after {:?}",
+                lock_name,
+                started.elapsed()
+            );
+            Some((lockd, lock_key, kept_alive))
+        }
+        Some(lockd) => bail!(
+            "invalid init lock: '{}' is not a lockd implementor (i.e., one of {:?})",
+            lockd,
+            LOCKD_HOST_IMPLEMENTORS
+        ),
+        None => None,
+    };
+
+    // another replica may have initialized while this one waited for the lock
+    let res = if lock.is_some() && initialized()? {
+        tracing::info!(
+            "skipping _init, as another replica initialized version '{}'",
+            version
+        );
+        Ok(())
+    } else {
+        tracing::info!("running _init for version '{}'", version);
+        let started = Instant::now();
+        let res = {
+            let _phase = guest_phase("init");
+            func.call(&mut store, ())
+        };
+        match res {
+            Ok(()) => {
+                tracing::info!("ran _init in {:?}", started.elapsed());
+                marker.set(marker_key.as_bytes(), version.as_bytes())
+            }
+            Err(trap) => Err(trap.into()),
+        }
+    };
+    if let Some((lockd, lock_key, _kept_alive)) = lock {
+        if let Err(e) = lockd.unlock(&lock_key) {
+            tracing::warn!("failed to release the init lock: {:#}", e);
+        }
+    }
+    res
+}
+
+fn get_resource<'a, T>(store: &'a mut Store<Ctx>, scheme_name: &'a str) -> &'a mut T
+where
+    T: Resource,
+{
+    let err_msg = format!(
+        "internal error: resource_map does not contain key: {}",
+        scheme_name
+    );
+    let err_msg2 = format!(
+        "internal error: resource map contains key {} but can't downcast",
+        scheme_name
+    );
+    store
+        .data_mut()
+        .data
+        .get_mut(scheme_name)
+        .expect(&err_msg)
+        .0
+        .as_mut()
+        .downcast_mut::<T>()
+        .expect(&err_msg2)
+}
+
+pub async fn shutdown_signal() {
+    // Wait for the CTRL+C signal
+    tokio::signal::ctrl_c()
+        .await
+        .expect("failed to install CTRL+C signal handler");
+}
+
+fn build_store_instance(
+    toml: &TomlFile,
+    toml_file_path: &str,
+    resource_map: Arc<Mutex<StateTable>>,
+    engine: &Engine,
+    max_memory_bytes: Option<usize>,
+    limits: &Limits,
+    sandbox: Option<&FilesystemSandbox>,
+) -> Result<Builder> {
+    let mut builder = Builder::new_with_engine(engine)?;
+    if let Some(sandbox) = sandbox {
+        builder.sandbox_filesystem(sandbox)?;
+    }
+    builder.link_wasi()?;
+    if let Some(max_memory_bytes) = max_memory_bytes {
+        builder.limit_memory(max_memory_bytes);
+    }
+    builder.monitor_memory(limits.memory.clone());
+    if let Some(seed) = toml.random_seed {
+        builder.seed_random(seed);
+    }
+    // the capabilities check what they return against the room the guest's memory has for it
+    let headroom = builder.headroom();
+    if toml.specversion.as_ref().unwrap() == "0.1" {
+        link::link_capabilities(
+            &mut builder,
+            toml,
+            toml_file_path,
+            &resource_map,
+            limits,
+            &headroom,
+        )?;
+    } else {
+        bail!("unsupported toml spec version");
+    }
+
+    Ok(builder)
+}
+
+/// The filesystem sandbox of an app (see `FilesystemSandbox`), w/ its directories relative to
+/// the slightfile — the scratch directory is created if it doesn't exist, and, if none is
+/// given, it's a new one under the OS' temp dir, which is removed once the run is over.
+fn filesystem_sandbox(filesystem: &Filesystem, toml_file_path: &str) -> Result<FilesystemSandbox> {
+    let slightfile_dir = Path::new(toml_file_path)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    let app_dir = slightfile_dir.join(filesystem.app_dir.as_deref().unwrap_or("."));
+    let temporary = filesystem.scratch_dir.is_none();
+    let scratch_dir = match &filesystem.scratch_dir {
+        Some(scratch_dir) => slightfile_dir.join(scratch_dir),
+        None => std::env::temp_dir().join(format!(
+            "slight-scratch-{}-{}",
+            std::process::id(),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos()
+        )),
+    };
+    fs::create_dir_all(&scratch_dir)
+        .with_context(|| format!("failed to create scratch dir {}", scratch_dir.display()))?;
+    tracing::info!(
+        "sandboxing the guest's filesystem: {} is mounted read-only at {}, and {} read-write at {}",
+        app_dir.display(),
+        APP_MOUNT,
+        scratch_dir.display(),
+        SCRATCH_MOUNT
+    );
+    Ok(FilesystemSandbox {
+        app_dir,
+        scratch_dir,
+        temporary,
+    })
+}
+
+/// Checks the capabilities of the slightfile against the manifest of the `module` (see
+/// `Manifest`), if it has one, so a guest that calls operations its backends don't support
+/// fails to start, rather than once it calls them.
+///
+/// Only the capabilities that are linked count (i.e., those whose `when` condition holds).
+fn check_manifest(module: &str, toml: &TomlFile) -> Result<()> {
+    let bytes = fs::read(module).with_context(|| format!("failed to read module {}", module))?;
+    let manifest = match Manifest::read(&bytes)? {
+        Some(manifest) => manifest,
+        None => return Ok(()),
+    };
+    let mut capabilities = Vec::new();
+    for c in toml.capability.iter().flatten() {
+        if condition_holds(c)? {
+            capabilities.push(linked_implementor(c)?);
+        }
+    }
+    let capabilities = capabilities.iter().map(String::as_str).collect::<Vec<_>>();
+    manifest.check(
+        &capabilities,
+        |name| support::operations(interface(&name.replace('-', "_"))),
+        unsupported_operations,
+    )
+}
+
+/// The operations an implementor doesn't support (i.e., that fail w/ `Unsupported`).
+fn unsupported_operations(implementor: &str) -> &'static [&'static str] {
+    slight_kv::UNSUPPORTED_OPERATIONS
+        .iter()
+        .chain(slight_runtime_configs::UNSUPPORTED_OPERATIONS)
+        .find(|(unsupporting, _)| *unsupporting == implementor)
+        .map_or(&[][..], |(_, operations)| *operations)
+}
+
+/// What the capabilities the slightfile links support (see `Support`), w/ the operations that
+/// aren't enabled left out (see `operation_flags`), as the `runtime_control` capability
+/// advertises it to the guest.
+fn capability_support(toml: &TomlFile) -> Result<Vec<Support>> {
+    let mut support = Vec::new();
+    for c in toml.capabilities_in_link_order()? {
+        if !condition_holds(c)? {
+            continue;
+        }
+        let implementor = linked_implementor(c)?;
+        let mut supported = Support::of(
+            &implementor,
+            interface(c.scheme()),
+            unsupported_operations(&implementor),
+        );
+        let flags = operation_flags(c);
+        supported
+            .operations
+            .retain(|operation| flags.enables(operation));
+        support.push(supported);
+    }
+    Ok(support)
+}
+
+/// The WIT interface of the capabilities of `scheme`, whose functions are their operations.
+fn interface(scheme: &str) -> &'static str {
+    match scheme {
+        "configs" => include_str!("../../../../wit/configs.wit"),
+        "credentials" => include_str!("../../../../wit/credentials.wit"),
+        "crypto" => include_str!("../../../../wit/crypto.wit"),
+        "deployment" => include_str!("../../../../wit/deployment.wit"),
+        "docstore" => include_str!("../../../../wit/docstore.wit"),
+        "election" => include_str!("../../../../wit/election.wit"),
+        "events" => include_str!("../../../../wit/events.wit"),
+        "http" => include_str!("../../../../wit/http.wit"),
+        "jobs" => include_str!("../../../../wit/jobs.wit"),
+        "timers" => include_str!("../../../../wit/timers.wit"),
+        "timeseries" => include_str!("../../../../wit/timeseries.wit"),
+        "webhooks" => include_str!("../../../../wit/webhooks.wit"),
+        "notifications" => include_str!("../../../../wit/notifications.wit"),
+        "workflow" => include_str!("../../../../wit/workflow.wit"),
+        "kv" => include_str!("../../../../wit/kv.wit"),
+        "lockd" => include_str!("../../../../wit/lockd.wit"),
+        "mq" => include_str!("../../../../wit/mq.wit"),
+        "parsing" => include_str!("../../../../wit/parsing.wit"),
+        "platform" => include_str!("../../../../wit/platform.wit"),
+        "pubsub" => include_str!("../../../../wit/pubsub.wit"),
+        "runtime_control" => include_str!("../../../../wit/runtime-control.wit"),
+        "validation" => include_str!("../../../../wit/validation.wit"),
+        _ => "",
+    }
+}
+
+/// Logs the capabilities whose backend is overridden (see `linked_implementor`) once per run,
+/// rather than once per guest instance, as what's linked isn't what the slightfile says.
+fn log_backend_overrides(toml: &TomlFile) -> Result<()> {
+    for c in toml.capability.iter().flatten() {
+        if !condition_holds(c)? {
+            continue;
+        }
+        let implementor = linked_implementor(c)?;
+        if implementor != c.name {
+            tracing::warn!(
+                "{} is set, so capability '{}' is linked to '{}' instead",
+                backend_override_var(c.scheme()),
+                c.name,
+                implementor
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Gets the budget of the growth of the guest's linear memories.
+fn growth_settings(memory_growth: &MemoryGrowth) -> Result<GrowthSettings> {
+    if memory_growth.window_secs == Some(0) {
+        bail!("invalid memory_growth: window_secs must be greater than 0");
+    }
+    Ok(GrowthSettings::new(
+        memory_growth.budget_bytes,
+        memory_growth.window_secs,
+        memory_growth
+            .action
+            .as_deref()
+            .map_or(Ok(GrowthAction::Warn), GrowthAction::parse)?,
+    ))
+}
+
+/// The chaos of an app, if it's `enabled` (i.e., w/ `slight run --chaos`, see `chaos::gate`),
+/// and the slightfile has any — failing if a capability it injects faults into isn't one of the
+/// slightfile's, or an operation it injects them into isn't one of the capability's (e.g., a
+/// typo, which would inject nothing w/o a word).
+pub fn app_chaos(toml: &TomlFile, enabled: bool) -> Result<Option<Chaos>> {
+    let enabled = chaos::gate(enabled, std::env::var(chaos::CHAOS_ENV_VAR).ok().as_deref())?;
+    let settings = match (&toml.chaos, enabled) {
+        (Some(settings), true) => settings,
+        (None, true) => {
+            bail!("--chaos needs the slightfile's chaos, which has the faults to inject")
+        }
+        (Some(_), false) => {
+            tracing::info!("the slightfile has chaos, which isn't injected w/o `--chaos`");
+            return Ok(None);
+        }
+        (None, false) => return Ok(None),
+    };
+    let capabilities = settings
+        .capabilities
+        .iter()
+        .map(|(scheme, capability)| {
+            if !toml
+                .capability
+                .iter()
+                .flatten()
+                .any(|c| c.scheme() == scheme)
+            {
+                bail!(
+                    "invalid chaos capabilities: '{}' isn't the scheme of a capability of the slightfile",
+                    scheme
+                );
+            }
+            for operation in capability.operations.iter().flatten() {
+                check_operation(toml, scheme, operation)
+                    .with_context(|| format!("invalid chaos operations of '{}'", scheme))?;
+            }
+            let settings = ChaosSettings {
+                rate: capability.rate,
+                faults: capability
+                    .faults
+                    .iter()
+                    .map(|fault| Fault::parse(fault))
+                    .collect::<Result<_>>()?,
+                latency: capability
+                    .latency_ms
+                    .map_or(DEFAULT_CHAOS_LATENCY, Duration::from_millis),
+                operations: capability.operations.clone(),
+            }
+            .validate()
+            .with_context(|| format!("invalid chaos of '{}'", scheme))?;
+            Ok((scheme.clone(), settings))
+        })
+        .collect::<Result<HashMap<_, _>>>()?;
+    let mut schemes = capabilities.keys().collect::<Vec<_>>();
+    schemes.sort();
+    tracing::warn!(
+        "chaos is enabled: faults are injected into the calls of {:?} (i.e., only do this in tests)",
+        schemes
+    );
+    Ok(Some(Chaos::new(capabilities, settings.seed)))
+}
+
+/// The mocks of an app, if they're `enabled` (i.e., w/ `slight run --mocks`), and the slightfile
+/// has any — failing if one mocks an operation that isn't one of a capability of the slightfile
+/// (e.g., a typo, which would mock nothing w/o a word).
+pub fn app_mocks(toml: &TomlFile, enabled: bool) -> Result<Option<Mocks>> {
+    let mocked = match (&toml.mock, enabled) {
+        (Some(mocked), true) => mocked,
+        (None, true) => bail!("--mocks needs the slightfile's mocks, which have the responses"),
+        (Some(_), false) => {
+            tracing::info!("the slightfile has mocks, which aren't used w/o `--mocks`");
+            return Ok(None);
+        }
+        (None, false) => return Ok(None),
+    };
+    let mocks = Mocks::default();
+    for m in mocked {
+        check_operation(toml, &m.capability, &m.operation)
+            .with_context(|| format!("invalid mock of {}.{}", m.capability, m.operation))?;
+        let mut mock = Mock::new(&m.capability, &m.operation);
+        if let Some(target) = &m.target {
+            mock = mock.on(target);
+        }
+        if let Some(latency_ms) = m.latency_ms {
+            mock = mock.after(Duration::from_millis(latency_ms));
+        }
+        for response in m.responses.iter().flatten() {
+            mock = match (&response.returns, &response.fails) {
+                (Some(value), None) => mock.returns(value.as_bytes().to_vec()),
+                (None, Some(fault)) => mock.fails_like(fault)?,
+                (None, None) => mock.passes_through(),
+                (Some(_), Some(_)) => bail!(
+                    "invalid mock of {}.{}: a response either `returns`, or `fails`, not both",
+                    m.capability,
+                    m.operation
+                ),
+            };
+        }
+        mocks.add(mock);
+    }
+    tracing::warn!(
+        "mocks are enabled: {} of the calls' responses are scripted (i.e., only do this in tests)",
+        mocked.len()
+    );
+    Ok(Some(mocks))
+}
+
+/// Checks `operation` is one of a capability of `scheme` that the slightfile has (e.g., to
+/// mock, or inject chaos into it).
+fn check_operation(toml: &TomlFile, scheme: &str, operation: &str) -> Result<()> {
+    if !toml
+        .capability
+        .iter()
+        .flatten()
+        .any(|c| c.scheme() == scheme)
+    {
+        bail!(
+            "'{}' isn't the scheme of a capability of the slightfile",
+            scheme
+        );
+    }
+    let operations = support::operations(interface(scheme));
+    if !operations.iter().any(|o| o == operation) {
+        bail!(
+            "'{}' isn't an operation of {} (i.e., one of {:?})",
+            operation,
+            scheme,
+            operations
+        );
+    }
+    Ok(())
+}
+
+/// Which capability calls are audited, failing if a capability it audits isn't one of the
+/// slightfile's (e.g., a typo, which would audit nothing w/o a word).
+fn audit_settings(audit: &slightfile::Audit, toml: &TomlFile) -> Result<AuditSettings> {
+    for audited in audit.capabilities.iter().flatten() {
+        if !toml
+            .capability
+            .iter()
+            .flatten()
+            .any(|c| &c.name == audited || c.scheme() == audited)
+        {
+            bail!(
+                "invalid audit capabilities: '{}' isn't a capability of the slightfile",
+                audited
+            );
+        }
+    }
+    AuditSettings {
+        writes_only: audit.writes_only.unwrap_or(false),
+        failures_only: audit.failures_only.unwrap_or(false),
+        capabilities: audit.capabilities.clone(),
+        read_sample_rate: audit.read_sample_rate.unwrap_or(1.0),
+    }
+    .validate()
+    .context("invalid audit")
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::{
+        sync::Arc,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use anyhow::{bail, Result};
+    use slight_runtime::{chaos, default_config, resource::Ctx, Builder};
+    use spiderlightning::core::slightfile::TomlFile;
+    use wit_bindgen_wasmtime::wasmtime::{Engine, Instance, Module, Store};
+
+    use super::{
+        app_chaos, app_mocks, build_store_instance, initialize, restart_backoff, Limits,
+        MAX_RESTART_BACKOFF, RESTART_BACKOFF,
+    };
+
+    fn slightfile(mock: &str) -> Result<TomlFile> {
+        Ok(toml::from_str(&format!(
+            "specversion = \"0.1\"\n\n{}\n\n[[capability]]\nname = \"kv.filesystem\"\n",
+            mock
+        ))?)
+    }
+
+    #[test]
+    fn restart_backoff_test() {
+        assert_eq!(restart_backoff(1), RESTART_BACKOFF);
+        assert_eq!(restart_backoff(2), RESTART_BACKOFF * 2);
+        assert_eq!(restart_backoff(4), RESTART_BACKOFF * 8);
+        // it's capped, however many times the guest crashed
+        assert_eq!(restart_backoff(6), MAX_RESTART_BACKOFF);
+        assert_eq!(restart_backoff(u32::MAX), MAX_RESTART_BACKOFF);
+        assert_eq!(restart_backoff(0), RESTART_BACKOFF);
+    }
+
+    /// A guest instance of the module `wat`.
+    fn guest(wat: &str) -> Result<(Store<Ctx>, Instance)> {
+        let mut builder = Builder::new_default()?;
+        let module = Module::new(builder.engine(), wat)?;
+        let instance_pre = builder.pre_build(&module)?;
+        builder.build_from_pre(&instance_pre)
+    }
+
+    #[test]
+    fn initialize_test() -> Result<()> {
+        let marker_key = format!(
+            "init-test-{}",
+            SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos()
+        );
+        let toml = slightfile(&format!(
+            "[init]\nversion = \"2\"\nmarker_key = \"{}\"",
+            marker_key
+        ))?;
+        let init = toml.init.clone().unwrap();
+        let initialize_with = |wat: &str| -> Result<()> {
+            let (store, instance) = guest(wat)?;
+            initialize(
+                &init,
+                &toml,
+                "slightfile.toml",
+                Arc::default(),
+                store,
+                instance,
+            )
+        };
+        let traps = r#"(module (func (export "_init") unreachable))"#;
+
+        // guests w/o _init skip it
+        initialize_with("(module)")?;
+        // a failed _init leaves the marker unset, so it's run again
+        assert!(initialize_with(traps).is_err());
+        assert!(initialize_with(traps).is_err());
+        initialize_with(r#"(module (func (export "_init")))"#)?;
+        // once the version was initialized, _init isn't run again
+        initialize_with(traps)?;
+        Ok(())
+    }
+
+    #[test]
+    fn app_mocks_test() -> Result<()> {
+        let toml = slightfile(
+            "[[mock]]\ncapability = \"kv\"\noperation = \"get\"\nresponses = [{ fails = \"timeout\" }, { returns = \"hello\" }]",
+        )?;
+        assert!(app_mocks(&toml, false)?.is_none());
+        assert!(app_mocks(&toml, true)?.is_some());
+        assert!(app_mocks(&slightfile("")?, true).is_err());
+
+        // mocks of what the slightfile doesn't have would mock nothing
+        for mock in [
+            "[[mock]]\ncapability = \"mq\"\noperation = \"receive\"",
+            "[[mock]]\ncapability = \"kv\"\noperation = \"fetch\"",
+            "[[mock]]\ncapability = \"kv\"\noperation = \"get\"\nresponses = [{ fails = \"explode\" }]",
+            "[[mock]]\ncapability = \"kv\"\noperation = \"get\"\nresponses = [{ returns = \"a\", fails = \"error\" }]",
+        ] {
+            assert!(app_mocks(&slightfile(mock)?, true).is_err(), "{}", mock);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn app_chaos_test() -> Result<()> {
+        std::env::set_var(chaos::CHAOS_ENV_VAR, chaos::CHAOS_ENV_VALUE);
+        let toml = slightfile(
+            "[chaos.capabilities.kv]\nrate = 0.5\nfaults = [\"timeout\"]\noperations = [\"get\", \"set\"]",
+        )?;
+        assert!(app_chaos(&toml, false)?.is_none());
+        assert!(app_chaos(&toml, true)?.is_some());
+
+        // chaos in what the slightfile doesn't have would inject nothing
+        for settings in [
+            "[chaos.capabilities.mq]\nrate = 0.5\nfaults = [\"timeout\"]",
+            "[chaos.capabilities.kv]\nrate = 0.5\nfaults = [\"timeout\"]\noperations = [\"fetch\"]",
+        ] {
+            assert!(
+                app_chaos(&slightfile(settings)?, true).is_err(),
+                "{}",
+                settings
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn link_failures_test() -> Result<()> {
+        // w/o a secret store, neither kv, nor mq link
+        let toml: TomlFile = toml::from_str(
+            r#"
+specversion = "0.1"
+
+[[capability]]
+name = "kv.filesystem"
+
+[[capability]]
+name = "mq.filesystem"
+
+[[capability]]
+name = "mq.azsbus"
+
+[[capability]]
+name = "parsing"
+max_payload_bytes = 1024
+
+[[capability]]
+name = "platform"
+"#,
+        )?;
+        let linked = build_store_instance(
+            &toml,
+            "slightfile.toml",
+            Arc::default(),
+            &Engine::new(&default_config()?)?,
+            None,
+            &Limits::default(),
+            None,
+        );
+        let e = match linked {
+            Ok(_) => bail!("expected the capabilities to fail to link"),
+            Err(e) => e.to_string(),
+        };
+        // every failure is reported at once, rather than only the first
+        assert!(e.starts_with("4 capabilities failed to link:"), "{}", e);
+        for failure in [
+            "'kv.filesystem': the kv capability requires a secret store",
+            "'mq.filesystem': the mq capability requires a secret store",
+            "capability 'mq' declared multiple times (i.e., as 'mq.filesystem', and 'mq.azsbus')",
+            "'parsing': invalid `max_payload_bytes`",
+        ] {
+            assert!(e.contains(failure), "{}", e);
+        }
+        assert!(!e.contains("'platform'"), "{}", e);
+        Ok(())
+    }
+}
@@ -1,13 +1,28 @@
 use anyhow::Result;
-use spiderlightning::core::{secret::create_secret, slightfile::TomlFile};
+use spiderlightning::core::{
+    secret::create_secret,
+    slightfile::{SecretStore, TomlFile},
+};
 use std::fs::File;
 
+const USERSECRETS: &str = "configs.usersecrets";
+
 pub fn handle_secret(
     key: &str,
     value: &str,
     toml: &mut TomlFile,
     toml_file: &mut File,
 ) -> Result<()> {
-    toml.secret_store = Some("configs.usersecrets".to_string());
+    // keep any other secret stores the user has listed, so that their
+    // order of precedence is preserved
+    toml.secret_store = match toml.secret_store.take() {
+        Some(SecretStore::Ordered(mut stores)) => {
+            if !stores.iter().any(|s| s == USERSECRETS) {
+                stores.push(USERSECRETS.to_string());
+            }
+            Some(SecretStore::Ordered(stores))
+        }
+        _ => Some(SecretStore::Single(USERSECRETS.to_string())),
+    };
     create_secret(key, value, toml, toml_file)
 }
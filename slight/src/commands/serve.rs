@@ -0,0 +1,709 @@
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt::Write,
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde::Serialize;
+use slight_runtime::export::{families, label_value, Prometheus};
+use spiderlightning::core::{
+    manifest::{App, Manifest},
+    slightfile::TomlFile,
+};
+use tokio::{sync::oneshot, task::JoinHandle};
+
+use crate::commands::{
+    metrics_export::MetricsServers,
+    run::{restart_backoff, run_app, shutdown_signal, Limits},
+};
+
+/// The state of a managed app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum AppState {
+    Running,
+    Exited,
+    Failed,
+    Stopped,
+}
+
+/// A `ManagedApp` is an app run, and restarted (as per its `RestartPolicy`) by `slight serve`.
+struct ManagedApp {
+    spec: App,
+    status: Mutex<AppStatus>,
+    /// the quotas, and connection pools of the app's capabilities, kept across restarts
+    limits: Limits,
+}
+
+struct AppStatus {
+    state: AppState,
+    restarts: u32,
+    last_error: Option<String>,
+    /// sends the app's shutdown signal, `None` if it was already sent
+    stop: Option<oneshot::Sender<()>>,
+    /// bumped each time the app is started, so that supervisors of previous runs
+    /// know to stop restarting it.
+    generation: u64,
+    /// the task supervising the latest run of the app, to wait for it to stop on shutdown
+    supervisor: Option<JoinHandle<()>>,
+}
+
+/// How long `slight serve` waits for its apps to stop when it shuts down (e.g., for http
+/// apps to answer the requests in flight), before it exits regardless.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What the admin endpoint reports about an app.
+#[derive(Debug, Serialize)]
+struct AppReport {
+    name: String,
+    state: AppState,
+    restarts: u32,
+    last_error: Option<String>,
+}
+
+type Apps = Arc<BTreeMap<String, Arc<ManagedApp>>>;
+
+impl ManagedApp {
+    fn new(spec: App, limits: Limits) -> Self {
+        Self {
+            spec,
+            status: Mutex::new(AppStatus {
+                state: AppState::Stopped,
+                restarts: 0,
+                last_error: None,
+                stop: None,
+                generation: 0,
+                supervisor: None,
+            }),
+            limits,
+        }
+    }
+
+    fn report(&self) -> AppReport {
+        let status = self.status.lock().unwrap();
+        AppReport {
+            name: self.spec.name.clone(),
+            state: status.state,
+            restarts: status.restarts,
+            last_error: status.last_error.clone(),
+        }
+    }
+}
+
+pub async fn handle_serve(apps: &str, admin_address: &str) -> Result<()> {
+    let manifest = Manifest::load(Path::new(apps))?;
+    // the apps that export their metrics at the same address share its server
+    let metrics_servers = MetricsServers::default();
+    let apps: Apps = Arc::new(
+        manifest
+            .app
+            .into_iter()
+            .map(|spec| {
+                let limits = Limits {
+                    metrics_servers: metrics_servers.clone(),
+                    ..Default::default()
+                };
+                (spec.name.clone(), Arc::new(ManagedApp::new(spec, limits)))
+            })
+            .collect(),
+    );
+    for app in apps.values() {
+        start(app.clone())?;
+    }
+
+    let addr: SocketAddr = admin_address
+        .parse()
+        .with_context(|| format!("could not parse admin address: {}", admin_address))?;
+    let admin_apps = apps.clone();
+    let make_service = make_service_fn(move |_| {
+        let apps = admin_apps.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| admin(apps.clone(), req))) }
+    });
+    tracing::info!(
+        "serving {} apps, w/ the admin endpoint at http://{}",
+        apps.len(),
+        addr
+    );
+    Server::bind(&addr)
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+
+    for app in apps.values() {
+        // apps that aren't running can't be stopped, and that's fine.
+        let _ = stop(app);
+    }
+    let supervisors = apps
+        .values()
+        .filter_map(|app| app.status.lock().unwrap().supervisor.take())
+        .collect::<Vec<_>>();
+    let stopped = async {
+        for supervisor in supervisors {
+            let _ = supervisor.await;
+        }
+    };
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, stopped)
+        .await
+        .is_err()
+    {
+        tracing::warn!(
+            "not all apps stopped w/in {:?}, exiting regardless",
+            SHUTDOWN_TIMEOUT
+        );
+    }
+    Ok(())
+}
+
+/// Starts an app, and supervises it in the background.
+fn start(app: Arc<ManagedApp>) -> Result<()> {
+    let mut status = app.status.lock().unwrap();
+    if status.state == AppState::Running {
+        bail!("app '{}' is already running", app.spec.name);
+    }
+    status.state = AppState::Running;
+    status.restarts = 0;
+    status.last_error = None;
+    status.generation += 1;
+    tracing::info!("starting app '{}'", app.spec.name);
+    status.supervisor = Some(tokio::spawn(supervise(app.clone(), status.generation)));
+    Ok(())
+}
+
+/// Stops an app.
+///
+/// Http apps are stopped right away, while other apps are stopped once their `_start` returns.
+fn stop(app: &ManagedApp) -> Result<()> {
+    let mut status = app.status.lock().unwrap();
+    if status.state != AppState::Running {
+        bail!("app '{}' is not running", app.spec.name);
+    }
+    tracing::info!("stopping app '{}'", app.spec.name);
+    status.state = AppState::Stopped;
+    if let Some(stop) = status.stop.take() {
+        let _ = stop.send(());
+    }
+    Ok(())
+}
+
+/// Runs an app, restarting it as per its `RestartPolicy`, until it is stopped,
+/// or started again (i.e., a newer `generation` of it is running).
+async fn supervise(app: Arc<ManagedApp>, generation: u64) {
+    loop {
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut status = app.status.lock().unwrap();
+            if status.generation != generation || status.state != AppState::Running {
+                return;
+            }
+            status.stop = Some(tx);
+        }
+
+        let res = run_once(&app.spec, app.limits.clone(), rx).await;
+        match restart_after(&app, generation, res) {
+            Some(backoff) => tokio::time::sleep(backoff).await,
+            None => return,
+        }
+    }
+}
+
+/// Records how an app exited, and decides whether to restart it, and after how long.
+fn restart_after(app: &ManagedApp, generation: u64, res: Result<()>) -> Option<Duration> {
+    let mut status = app.status.lock().unwrap();
+    if status.generation != generation {
+        return None;
+    }
+    status.stop = None;
+    if let Err(e) = &res {
+        tracing::error!("app '{}' failed: {:#}", app.spec.name, e);
+        status.last_error = Some(format!("{:#}", e));
+    }
+    if status.state == AppState::Stopped {
+        tracing::info!("app '{}' stopped", app.spec.name);
+        return None;
+    }
+    status.state = if res.is_ok() {
+        AppState::Exited
+    } else {
+        AppState::Failed
+    };
+
+    let out_of_restarts = app
+        .spec
+        .max_restarts
+        .map_or(false, |max_restarts| status.restarts >= max_restarts);
+    if !app.spec.restart.should_restart(res.is_err()) || out_of_restarts {
+        tracing::info!(
+            "app '{}' exited, and won't be restarted (restarted {} times)",
+            app.spec.name,
+            status.restarts
+        );
+        return None;
+    }
+    status.state = AppState::Running;
+    status.restarts += 1;
+    app.limits.metrics.record_restart();
+    let backoff = restart_backoff(status.restarts);
+    tracing::warn!(
+        "restarting app '{}' in {:?} (restart #{})",
+        app.spec.name,
+        backoff,
+        status.restarts
+    );
+    Some(backoff)
+}
+
+/// Runs an app once, from its slightfile, and module.
+///
+/// An app whose guest requested a shutdown w/ a non-zero exit code failed.
+async fn run_once(app: &App, limits: Limits, stop: oneshot::Receiver<()>) -> Result<()> {
+    let app = app.clone();
+    // guests block the thread they run on, so each app gets a thread of its own.
+    tokio::task::spawn_blocking(move || {
+        let toml_file_contents = std::fs::read_to_string(&app.config)
+            .with_context(|| format!("failed to read slightfile {}", app.config))?;
+        let toml = toml::from_str::<TomlFile>(&toml_file_contents)?;
+        let exit_code = tokio::runtime::Handle::current().block_on(run_app(
+            &app.module,
+            &toml,
+            &app.config,
+            app.max_memory_bytes,
+            &limits,
+            None,
+            None,
+            None,
+            async move {
+                let _ = stop.await;
+            },
+        ))?;
+        match exit_code {
+            Some(exit_code) if exit_code != 0 => {
+                bail!("the guest shut down w/ exit code {}", exit_code)
+            }
+            _ => Ok(()),
+        }
+    })
+    .await
+    .with_context(|| "app panicked")?
+}
+
+/// Handles requests to the admin endpoint:
+///     - `GET /apps` lists all apps, and their status,
+///     - `GET /apps/<name>` gets the status of an app,
+///     - `POST /apps/<name>/start` starts an app,
+///     - `POST /apps/<name>/stop` stops an app, and
+///     - `GET /metrics` reports the status, quotas, connection pools, memory, and capability calls (and the sizes of the
+///     keys, and values they carry) of all apps in the Prometheus text format.
+async fn admin(apps: Apps, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let path = req.uri().path().trim_matches('/').to_string();
+    let segments = path.split('/').collect::<Vec<_>>();
+    let res = match (req.method(), segments.as_slice()) {
+        (&Method::GET, ["apps"]) => json(
+            StatusCode::OK,
+            &apps.values().map(|app| app.report()).collect::<Vec<_>>(),
+        ),
+        (&Method::GET, ["apps", name]) => match apps.get(*name) {
+            Some(app) => json(StatusCode::OK, &app.report()),
+            None => not_found(name),
+        },
+        (&Method::POST, ["apps", name, action @ ("start" | "stop")]) => match apps.get(*name) {
+            Some(app) => {
+                let res = if *action == "start" {
+                    start(app.clone())
+                } else {
+                    stop(app)
+                };
+                match res {
+                    Ok(()) => json(StatusCode::OK, &app.report()),
+                    Err(e) => text(StatusCode::CONFLICT, e.to_string()),
+                }
+            }
+            None => not_found(name),
+        },
+        (&Method::GET, ["metrics"]) => text(StatusCode::OK, metrics(&apps)),
+        _ => text(StatusCode::NOT_FOUND, "not found".to_string()),
+    };
+    Ok(res)
+}
+
+fn metrics(apps: &Apps) -> String {
+    let reports = apps.values().map(|app| app.report()).collect::<Vec<_>>();
+    let mut out = String::new();
+    // note: writing to a `String` can't fail
+    writeln!(out, "# HELP slight_app_up Whether the app is running.").unwrap();
+    writeln!(out, "# TYPE slight_app_up gauge").unwrap();
+    for report in &reports {
+        writeln!(
+            out,
+            "slight_app_up{{app=\"{}\"}} {}",
+            label_value(&report.name),
+            (report.state == AppState::Running) as u8
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "# HELP slight_app_restarts_total How many times the app was restarted since it was started."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE slight_app_restarts_total counter").unwrap();
+    for report in &reports {
+        writeln!(
+            out,
+            "slight_app_restarts_total{{app=\"{}\"}} {}",
+            label_value(&report.name),
+            report.restarts
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "# HELP slight_quota_rejections_total How many capability calls were rejected for exceeding a quota."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE slight_quota_rejections_total counter").unwrap();
+    for (name, app) in apps.iter() {
+        for quota in app.limits.quotas.reports() {
+            for (limit, rejections) in
+                [("ops", quota.rejected_ops), ("bytes", quota.rejected_bytes)]
+            {
+                writeln!(
+                    out,
+                    "slight_quota_rejections_total{{app=\"{}\",capability=\"{}\",limit=\"{}\"}} {}",
+                    label_value(name),
+                    label_value(&quota.capability),
+                    limit,
+                    rejections
+                )
+                .unwrap();
+            }
+        }
+    }
+    writeln!(
+        out,
+        "# HELP slight_quota_bytes_total How many bytes went through capabilities w/ a quota."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE slight_quota_bytes_total counter").unwrap();
+    for (name, app) in apps.iter() {
+        for quota in app.limits.quotas.reports() {
+            writeln!(
+                out,
+                "slight_quota_bytes_total{{app=\"{}\",capability=\"{}\"}} {}",
+                label_value(name),
+                label_value(&quota.capability),
+                quota.bytes_total
+            )
+            .unwrap();
+        }
+    }
+    let pools = apps
+        .iter()
+        .flat_map(|(name, app)| {
+            app.limits
+                .pools
+                .reports()
+                .into_iter()
+                .map(move |pool| (name, pool))
+        })
+        .collect::<Vec<_>>();
+    writeln!(
+        out,
+        "# HELP slight_pool_connections How many connections of a capability's pool are in use, and its max."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE slight_pool_connections gauge").unwrap();
+    for (name, pool) in &pools {
+        for (state, connections) in [
+            ("in_use", pool.in_use as u64),
+            ("peak", pool.peak),
+            ("max", pool.max_connections as u64),
+        ] {
+            writeln!(
+                out,
+                "slight_pool_connections{{app=\"{}\",capability=\"{}\",state=\"{}\"}} {}",
+                label_value(name),
+                label_value(&pool.capability),
+                state,
+                connections
+            )
+            .unwrap();
+        }
+    }
+    writeln!(
+        out,
+        "# HELP slight_pool_waits_total How many capability calls waited for a connection, and how many of them got none in time."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE slight_pool_waits_total counter").unwrap();
+    for (name, pool) in &pools {
+        for (outcome, calls) in [
+            ("connected", pool.waited.saturating_sub(pool.rejected)),
+            ("rejected", pool.rejected),
+        ] {
+            writeln!(
+                out,
+                "slight_pool_waits_total{{app=\"{}\",capability=\"{}\",outcome=\"{}\"}} {}",
+                label_value(name),
+                label_value(&pool.capability),
+                outcome,
+                calls
+            )
+            .unwrap();
+        }
+    }
+    let memories = apps
+        .iter()
+        .map(|(name, app)| (name, app.limits.memory.report()))
+        .collect::<Vec<_>>();
+    writeln!(
+        out,
+        "# HELP slight_memory_bytes How many bytes the linear memories of the app's guest instances have, and the most they had at once."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE slight_memory_bytes gauge").unwrap();
+    for (name, memory) in &memories {
+        for (state, bytes) in [("current", memory.bytes), ("peak", memory.peak)] {
+            writeln!(
+                out,
+                "slight_memory_bytes{{app=\"{}\",state=\"{}\"}} {}",
+                label_value(name),
+                state,
+                bytes
+            )
+            .unwrap();
+        }
+    }
+    writeln!(
+        out,
+        "# HELP slight_memory_growth_bytes How many bytes the app's linear memories grew by within the window of its memory_growth budget."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE slight_memory_growth_bytes gauge").unwrap();
+    for (name, memory) in &memories {
+        writeln!(
+            out,
+            "slight_memory_growth_bytes{{app=\"{}\"}} {}",
+            label_value(name),
+            memory.growth_bytes
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "# HELP slight_memory_over_budget_total How many times the app's linear memories grew past their memory_growth budget, and how many of them were refused to."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE slight_memory_over_budget_total counter").unwrap();
+    for (name, memory) in &memories {
+        for (outcome, growths) in [
+            ("grown", memory.over_budget.saturating_sub(memory.refused)),
+            ("refused", memory.refused),
+        ] {
+            writeln!(
+                out,
+                "slight_memory_over_budget_total{{app=\"{}\",outcome=\"{}\"}} {}",
+                label_value(name),
+                outcome,
+                growths
+            )
+            .unwrap();
+        }
+    }
+    let invocations = apps
+        .iter()
+        .map(|(name, app)| (name, app.limits.invocations.report()))
+        .collect::<Vec<_>>();
+    writeln!(
+        out,
+        "# HELP slight_invocations How many times the guest is invoked at once across its triggers, the most it was at once, and its max_concurrent_invocations (if it has one)."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE slight_invocations gauge").unwrap();
+    for (name, report) in &invocations {
+        let max = report.max.map(|max| ("max", max));
+        for (state, invocations) in [("in_flight", report.in_flight), ("peak", report.peak)]
+            .into_iter()
+            .chain(max)
+        {
+            writeln!(
+                out,
+                "slight_invocations{{app=\"{}\",state=\"{}\"}} {}",
+                label_value(name),
+                state,
+                invocations
+            )
+            .unwrap();
+        }
+    }
+    writeln!(
+        out,
+        "# HELP slight_invocations_throttled_total How many invocations of the guest waited (i.e., events), or were shed (i.e., http requests), as max_concurrent_invocations were in flight."
+    )
+    .unwrap();
+    writeln!(out, "# TYPE slight_invocations_throttled_total counter").unwrap();
+    for (name, report) in &invocations {
+        for (outcome, throttled) in [("delayed", report.delayed), ("shed", report.shed)] {
+            writeln!(
+                out,
+                "slight_invocations_throttled_total{{app=\"{}\",outcome=\"{}\"}} {}",
+                label_value(name),
+                outcome,
+                throttled
+            )
+            .unwrap();
+        }
+    }
+    let metrics = apps
+        .iter()
+        .map(|(name, app)| (name.as_str(), &app.limits.metrics))
+        .collect::<Vec<_>>();
+    out.push_str(&Prometheus.render(&families(&metrics)));
+    out
+}
+
+fn json(status: StatusCode, body: &impl Serialize) -> Response<Body> {
+    match serde_json::to_vec(body) {
+        Ok(body) => Response::builder()
+            .status(status)
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap(),
+        Err(e) => text(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+fn text(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "text/plain")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn not_found(name: &str) -> Response<Body> {
+    text(StatusCode::NOT_FOUND, format!("app '{}' not found", name))
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::sync::Arc;
+
+    use anyhow::anyhow;
+    use hyper::{Body, Request, StatusCode};
+    use spiderlightning::core::manifest::{App, RestartPolicy};
+
+    use super::{admin, metrics, restart_after, AppState, Apps, ManagedApp};
+    use crate::commands::run::{restart_backoff, Limits};
+
+    fn app(name: &str, restart: RestartPolicy, max_restarts: Option<u32>) -> Arc<ManagedApp> {
+        Arc::new(ManagedApp::new(
+            App {
+                name: name.to_string(),
+                config: "slightfile.toml".to_string(),
+                module: "app.wasm".to_string(),
+                restart,
+                max_restarts,
+                max_memory_bytes: None,
+            },
+            Limits::default(),
+        ))
+    }
+
+    /// Marks `app` as running its first generation, as `start` would.
+    fn running(app: &ManagedApp) {
+        let mut status = app.status.lock().unwrap();
+        status.state = AppState::Running;
+        status.generation = 1;
+    }
+
+    #[test]
+    fn restart_after_test() {
+        let app = app("orders", RestartPolicy::OnFailure, Some(2));
+        running(&app);
+
+        assert_eq!(
+            restart_after(&app, 1, Err(anyhow!("trap"))),
+            Some(restart_backoff(1))
+        );
+        assert_eq!(
+            restart_after(&app, 1, Err(anyhow!("trap"))),
+            Some(restart_backoff(2))
+        );
+        // out of restarts
+        assert_eq!(restart_after(&app, 1, Err(anyhow!("trap"))), None);
+        let report = app.report();
+        assert_eq!(report.state, AppState::Failed);
+        assert_eq!(report.restarts, 2);
+        assert_eq!(report.last_error.as_deref(), Some("trap"));
+        assert_eq!(app.limits.metrics.restarts(), 2);
+
+        // an app that exited fine isn't restarted on failure only
+        let app = self::app("orders", RestartPolicy::OnFailure, None);
+        running(&app);
+        assert_eq!(restart_after(&app, 1, Ok(())), None);
+        assert_eq!(app.report().state, AppState::Exited);
+    }
+
+    #[test]
+    fn stale_generation_test() {
+        let app = app("orders", RestartPolicy::Always, None);
+        running(&app);
+        // a supervisor of a previous run doesn't restart the app, nor touch its status
+        assert_eq!(restart_after(&app, 0, Err(anyhow!("trap"))), None);
+        assert_eq!(app.report().state, AppState::Running);
+
+        app.status.lock().unwrap().state = AppState::Stopped;
+        assert_eq!(restart_after(&app, 1, Ok(())), None);
+        assert_eq!(app.report().state, AppState::Stopped);
+    }
+
+    #[test]
+    fn metrics_test() {
+        let apps: Apps = Arc::new(
+            [("a\"b".to_string(), app("a\"b", RestartPolicy::Never, None))]
+                .into_iter()
+                .collect(),
+        );
+        let text = metrics(&apps);
+        // label values are escaped, whatever an app is named
+        assert!(
+            text.contains("slight_app_up{app=\"a\\\"b\"} 0\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains("slight_app_restarts_total{app=\"a\\\"b\"} 0\n"));
+    }
+
+    #[tokio::test]
+    async fn admin_test() {
+        let apps: Apps = Arc::new(
+            [(
+                "orders".to_string(),
+                app("orders", RestartPolicy::Never, None),
+            )]
+            .into_iter()
+            .collect(),
+        );
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+        let res = admin(apps.clone(), get("/apps/orders")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = admin(apps.clone(), get("/apps/payments")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        // an app that isn't running can't be stopped
+        let stop = Request::post("/apps/orders/stop")
+            .body(Body::empty())
+            .unwrap();
+        let res = admin(apps, stop).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use slight_mq::HostMq;
+use slight_pubsub::HostSub;
+use slight_runtime::{
+    credentials::Credentials,
+    resource::{BasicState, StateTable},
+};
+use spiderlightning::core::{condition::Condition, slightfile::TomlFile};
+
+use crate::commands::run::linked_implementor;
+
+/// How long each peek, receive, or poll waits for messages, before waiting again.
+const TAIL_WAIT: Duration = Duration::from_secs(1);
+
+/// How many messages are consumed at once.
+const CONSUME_BATCH: u32 = 16;
+
+/// How messages are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    /// their bytes as they are, one message per line
+    Raw,
+    /// pretty-printed if they are JSON, as text if they are UTF-8, and in hex otherwise
+    Decoded,
+}
+
+impl Output {
+    fn parse(output: &str) -> Result<Self> {
+        match output {
+            "raw" => Ok(Output::Raw),
+            "decoded" => Ok(Output::Decoded),
+            _ => bail!(
+                "invalid output: '{}' (expected 'raw', or 'decoded')",
+                output
+            ),
+        }
+    }
+}
+
+/// Prints the messages sent to `queue` as they arrive, w/ the mq implementor, and secret
+/// stores of `slightfile` (but w/o running its guest).
+///
+/// By default, messages are only peeked at, so the app still receives them — `consume`
+/// takes them off the queue instead, as some implementors can't peek (i.e., `mq.azsbus`).
+pub fn handle_mq_tail(slightfile: &str, queue: &str, consume: bool, output: &str) -> Result<()> {
+    let output = Output::parse(output)?;
+    let (implementor, slight_state) = implementor_state(slightfile, "mq")?;
+    let mq = HostMq::open(&implementor, &slight_state, queue)?;
+    tracing::info!(
+        "tailing '{}' w/ {} ({})",
+        queue,
+        implementor,
+        if consume { "consuming" } else { "peeking" }
+    );
+
+    let wait_ms = TAIL_WAIT.as_millis() as u64;
+    let mut seen = HashSet::new();
+    loop {
+        let messages = if consume {
+            mq.consume(CONSUME_BATCH, wait_ms)?
+        } else {
+            mq.peek(wait_ms, &mut seen)?
+        };
+        for message in messages {
+            print_message(None, &message, output)?;
+        }
+    }
+}
+
+/// Prints the messages sent to `topic` as they arrive, w/ the pubsub implementor, and secret
+/// stores of `slightfile` (but w/o running its guest).
+///
+/// It subscribes in a consumer group of its own, so the app's subscribers still get every
+/// message, and only messages sent after it subscribed are printed.
+pub fn handle_pubsub_tail(slightfile: &str, topic: &str, output: &str) -> Result<()> {
+    let output = Output::parse(output)?;
+    let (implementor, slight_state) = implementor_state(slightfile, "pubsub")?;
+    let sub = HostSub::subscribe(&implementor, &slight_state, vec![topic])?;
+    tracing::info!("tailing '{}' w/ {}", topic, implementor);
+
+    loop {
+        match sub.poll(TAIL_WAIT)? {
+            (None, None) => {}
+            (key, value) => print_message(key.as_deref(), &value.unwrap_or_default(), output)?,
+        }
+    }
+}
+
+/// Finds the implementor of `scheme` that `slightfile` links (i.e., the capability whose
+/// condition holds, if it has one), w/ a `BasicState` to open it w/.
+fn implementor_state(slightfile: &str, scheme: &str) -> Result<(String, BasicState)> {
+    let contents = fs::read_to_string(slightfile)
+        .with_context(|| format!("failed to read slightfile {}", slightfile))?;
+    let toml = toml::from_str::<TomlFile>(&contents)?;
+    let mut implementor = None;
+    for c in toml.capabilities_in_link_order()? {
+        if c.scheme() != scheme {
+            continue;
+        }
+        if let Some(when) = &c.when {
+            if !Condition::parse(when)?.evaluate(|var| std::env::var(var).ok()) {
+                continue;
+            }
+        }
+        if implementor.is_some() {
+            bail!(
+                "capability '{}' declared multiple times; if they are meant for different environments, add `when` conditions so only one of them is linked",
+                scheme
+            );
+        }
+        implementor = Some(linked_implementor(c)?);
+    }
+    let implementor = implementor
+        .with_context(|| format!("{} doesn't link a {} capability", slightfile, scheme))?;
+    let slight_state = BasicState::new(
+        Arc::new(Mutex::new(StateTable::default())),
+        &toml.secret_stores().unwrap_or_default(),
+        slightfile,
+    )
+    .with_credentials(Credentials::default());
+    Ok((implementor, slight_state))
+}
+
+fn print_message(key: Option<&[u8]>, message: &[u8], output: Output) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    match output {
+        Output::Raw => {
+            if let Some(key) = key {
+                stdout.write_all(key)?;
+                stdout.write_all(b"\t")?;
+            }
+            stdout.write_all(message)?;
+            stdout.write_all(b"\n")?;
+        }
+        Output::Decoded => {
+            if let Some(key) = key {
+                write!(stdout, "{}: ", decode(key))?;
+            }
+            writeln!(stdout, "{}", decode(message))?;
+        }
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Decodes a message for humans to read (see `Output::Decoded`).
+fn decode(message: &[u8]) -> String {
+    if let Ok(json) = serde_json::from_slice::<serde_json::Value>(message) {
+        if let Ok(pretty) = serde_json::to_string_pretty(&json) {
+            return pretty;
+        }
+    }
+    match std::str::from_utf8(message) {
+        Ok(text) => text.to_string(),
+        Err(_) => format!(
+            "0x{}",
+            message
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>()
+        ),
+    }
+}
@@ -1,9 +1,28 @@
-use std::fs::OpenOptions;
+use std::{fs::OpenOptions, path::Path};
 
-use crate::commands::{run::handle_run, secret::handle_secret};
-use anyhow::Result;
+use crate::commands::{
+    config::{handle_config_describe, handle_config_show},
+    diff::handle_diff,
+    fmt::handle_fmt,
+    generate_bindings::handle_generate_bindings,
+    log_sink::connect_log_sink,
+    run::{app_chaos, app_mocks, handle_run},
+    secret::handle_secret,
+    serve::handle_serve,
+    tail::{handle_mq_tail, handle_pubsub_tail},
+};
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
-use spiderlightning::core::slightfile::TomlFile;
+use slight_runtime::{
+    cassette::{Cassette, CassetteMode},
+    log_sink::LogSink,
+    redact::{redact, Redacting},
+    trace::{ChromeTraceLayer, Sampler, SamplingSettings},
+};
+use spiderlightning::core::slightfile::{TomlFile, Tracing};
+use tracing_subscriber::{
+    filter::filter_fn, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer,
+};
 
 mod commands;
 
@@ -13,7 +32,7 @@ struct Args {
     #[clap(subcommand)]
     command: Commands,
     #[clap(short, long, value_parser)]
-    config: String,
+    config: Option<String>,
 }
 
 #[derive(Debug, Subcommand)]
@@ -22,6 +41,27 @@ enum Commands {
     Run {
         #[clap(short, long, value_parser)]
         module: String,
+        /// how many times to restart the guest if it crashes, w/ an exponential backoff
+        #[clap(long, value_parser, default_value_t = 0)]
+        max_restarts: u32,
+        /// write the capability calls (sampled as per the slightfile's `tracing`), and guest phases to this file, as a Chrome trace (see `chrome://tracing`)
+        #[clap(long, value_parser)]
+        trace_out: Option<String>,
+        /// record the calls of the kv, mq, lockd, and pubsub capabilities to their backends to this file, or replay them from it (see `--cassette-mode`)
+        #[clap(long, value_parser)]
+        cassette: Option<String>,
+        /// `record`, `replay` (w/o calling the backends), or `once` (i.e., replay the cassette if it exists, and record it otherwise)
+        #[clap(long, value_parser, default_value = "once")]
+        cassette_mode: String,
+        /// seed the guest's randomness, so runs are reproducible — for tests only (overrides the slightfile's `random_seed`)
+        #[clap(long, value_parser)]
+        random_seed: Option<u64>,
+        /// inject the faults of the slightfile's `chaos` into capability calls — for tests only, so it needs `SLIGHT_CHAOS=enabled` too
+        #[clap(long, value_parser)]
+        chaos: bool,
+        /// respond to the capability calls the slightfile's `mock`s match w/ their scripted responses, rather than the backends' — for tests only
+        #[clap(long, value_parser)]
+        mocks: bool,
     },
     /// Add a secret to the application
     Secret {
@@ -30,17 +70,205 @@ enum Commands {
         #[clap(short, long, value_parser)]
         value: String,
     },
+    /// Generate a starter guest project importing the given capabilities
+    GenerateBindings {
+        /// a comma-separated list of capabilities (e.g., kv,mq,http)
+        #[clap(short, long, value_parser, value_delimiter = ',')]
+        capabilities: Vec<String>,
+        /// the language of the guest project
+        #[clap(short, long, value_parser, default_value = "rust")]
+        lang: String,
+        /// the directory to generate the guest project into
+        #[clap(short, long, value_parser, default_value = "slight-guest")]
+        out: String,
+    },
+    /// Run, and manage many apps in one process, w/ an admin endpoint to start, and stop them
+    Serve {
+        /// a manifest of apps, or a directory of `<app>.toml` slightfiles, each next to its `<app>.wasm` module (but
+        /// for the ones its `.slightignore` matches, as gitignore-style patterns)
+        #[clap(short, long, value_parser)]
+        apps: String,
+        /// the address the admin endpoint listens on
+        #[clap(long, value_parser, default_value = "127.0.0.1:3001")]
+        admin_address: String,
+    },
+    /// Rewrite a slightfile in a canonical form, validating it in the process
+    Fmt {
+        /// the slightfile to format
+        #[clap(value_parser)]
+        file: String,
+        /// only check whether the slightfile is formatted, w/o rewriting it
+        #[clap(long, value_parser)]
+        check: bool,
+    },
+    /// Show what changed between two slightfiles (e.g., capabilities added, or settings changed), ignoring formatting, and order
+    Diff {
+        /// the slightfile before the change
+        #[clap(value_parser)]
+        old: String,
+        /// the slightfile after the change
+        #[clap(value_parser)]
+        new: String,
+        /// how to print the changes: `text`, or `json`
+        #[clap(long, value_parser, default_value = "text")]
+        format: String,
+    },
+    /// Look at the configuration an app runs w/
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommands,
+    },
+    /// Look at the messages of an app's queues w/o running it
+    Mq {
+        #[clap(subcommand)]
+        command: MqCommands,
+    },
+    /// Look at the messages of an app's topics w/o running it
+    Pubsub {
+        #[clap(subcommand)]
+        command: PubsubCommands,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommands {
+    /// Print the configuration a slightfile resolves to (i.e., the capabilities whose `when` holds, w/ their backend
+    /// overrides applied), w/ its secrets redacted — failing like `slight run` would if it can't be resolved
+    Show {
+        /// the slightfile to resolve
+        #[clap(value_parser)]
+        slightfile: String,
+    },
+    /// Print the effective configuration of the backends a slightfile links (i.e., their endpoints, settings, and pool
+    /// sizes, as resolved from the secret stores), w/ their credentials redacted — w/o connecting to them
+    Describe {
+        /// the slightfile to resolve
+        #[clap(value_parser)]
+        slightfile: String,
+        /// how to print the backends: `text`, or `json`
+        #[clap(long, value_parser, default_value = "text")]
+        format: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum MqCommands {
+    /// Print the messages sent to a queue as they arrive, w/ the mq capability of a slightfile
+    Tail {
+        /// the slightfile whose mq capability (and secret stores) to use
+        #[clap(value_parser)]
+        slightfile: String,
+        /// the queue to tail
+        #[clap(value_parser)]
+        queue: String,
+        /// receive the messages, taking them off the queue, rather than only peeking at them
+        #[clap(long, value_parser)]
+        consume: bool,
+        /// how to print the messages: `raw`, or `decoded` (i.e., JSON pretty-printed, text as is, and anything else in hex)
+        #[clap(long, value_parser, default_value = "decoded")]
+        output: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum PubsubCommands {
+    /// Print the messages sent to a topic as they arrive, w/ the pubsub capability of a slightfile
+    Tail {
+        /// the slightfile whose pubsub capability (and secret stores) to use
+        #[clap(value_parser)]
+        slightfile: String,
+        /// the topic to tail
+        #[clap(value_parser)]
+        topic: String,
+        /// how to print the messages: `raw`, or `decoded` (i.e., JSON pretty-printed, text as is, and anything else in hex)
+        #[clap(long, value_parser, default_value = "decoded")]
+        output: String,
+    },
 }
 
 /// The entry point for slight CLI
 #[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_writer(std::io::stderr)
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
+async fn main() {
+    if let Err(e) = run().await {
+        // errors often quote the urls of backends (and so, their credentials), so they're printed
+        // redacted, like logs are
+        eprintln!("Error: {}", redact(&format!("{:?}", e)));
+        std::process::exit(1);
+    }
+}
+
+async fn run() -> Result<()> {
     let args = Args::parse();
-    let toml_file_path = args.config;
+    // the host's log records are only shipped once the slightfile says where to
+    let log_sink = LogSink::default();
+    // and calls are only sampled once it says how
+    let sampler = Sampler::default();
+    init_tracing(&args, &log_sink, &sampler)?;
+    if let Commands::GenerateBindings {
+        capabilities,
+        lang,
+        out,
+    } = &args.command
+    {
+        // generating bindings doesn't require a slightfile
+        return handle_generate_bindings(capabilities, lang, out);
+    }
+    if let Commands::Serve {
+        apps,
+        admin_address,
+    } = &args.command
+    {
+        // each app brings its own slightfile
+        return handle_serve(apps, admin_address).await;
+    }
+    if let Commands::Fmt { file, check } = &args.command {
+        // the slightfile to format is given directly, rather than w/ `-c`
+        return handle_fmt(file, *check);
+    }
+    if let Commands::Diff { old, new, format } = &args.command {
+        // both slightfiles are given directly too
+        return handle_diff(old, new, format);
+    }
+    if let Commands::Config {
+        command: ConfigCommands::Show { slightfile },
+    } = &args.command
+    {
+        return handle_config_show(slightfile);
+    }
+    if let Commands::Config {
+        command: ConfigCommands::Describe { slightfile, format },
+    } = &args.command
+    {
+        return handle_config_describe(slightfile, format);
+    }
+    if let Commands::Mq {
+        command:
+            MqCommands::Tail {
+                slightfile,
+                queue,
+                consume,
+                output,
+            },
+    } = &args.command
+    {
+        // the slightfile is given directly, and its guest isn't run
+        return handle_mq_tail(slightfile, queue, *consume, output);
+    }
+    if let Commands::Pubsub {
+        command:
+            PubsubCommands::Tail {
+                slightfile,
+                topic,
+                output,
+            },
+    } = &args.command
+    {
+        return handle_pubsub_tail(slightfile, topic, output);
+    }
+
+    let toml_file_path = args
+        .config
+        .with_context(|| "a config file (i.e., `-c <slightfile>`) is required for this command")?;
     let mut toml_file = OpenOptions::new()
         .read(true)
         .write(true)
@@ -50,7 +278,100 @@ async fn main() -> Result<()> {
     let mut toml = toml::from_str::<TomlFile>(&toml_file_contents)?;
 
     match &args.command {
-        Commands::Run { module } => handle_run(module, &toml, &toml_file_path).await,
+        Commands::Run {
+            module,
+            max_restarts,
+            cassette,
+            cassette_mode,
+            random_seed,
+            chaos: chaos_enabled,
+            mocks: mocks_enabled,
+            ..
+        } => {
+            if let Some(random_seed) = random_seed {
+                toml.random_seed = Some(*random_seed);
+            }
+            if let Some(settings) = &toml.log_sink {
+                connect_log_sink(&log_sink, settings, &toml, &toml_file_path)?;
+            }
+            if let Some(settings) = &toml.tracing {
+                sampler.configure(sampling_settings(settings, &toml)?);
+            }
+            let cassette = cassette
+                .as_ref()
+                .map(|cassette| {
+                    Cassette::open(Path::new(cassette), CassetteMode::parse(cassette_mode)?)
+                })
+                .transpose()?;
+            let chaos = app_chaos(&toml, *chaos_enabled)?;
+            let mocks = app_mocks(&toml, *mocks_enabled)?;
+            let exit_code = handle_run(
+                module,
+                &toml,
+                &toml_file_path,
+                *max_restarts,
+                cassette,
+                chaos,
+                mocks,
+            )
+            .await?;
+            // the guest asked to exit w/ this code (see the `runtime_control` capability)
+            if let Some(exit_code) = exit_code.filter(|exit_code| *exit_code != 0) {
+                std::process::exit(exit_code);
+            }
+            Ok(())
+        }
         Commands::Secret { key, value } => handle_secret(key, value, &mut toml, &mut toml_file),
+        Commands::GenerateBindings { .. }
+        | Commands::Serve { .. }
+        | Commands::Fmt { .. }
+        | Commands::Diff { .. }
+        | Commands::Config { .. }
+        | Commands::Mq { .. }
+        | Commands::Pubsub { .. } => unreachable!(),
+    }
+}
+
+/// Logs to stderr (as per `RUST_LOG`), and, if `slight run` got a `--trace-out`, writes a trace
+/// there too — w/o it, there's no trace layer at all, so tracing costs nothing more.
+fn init_tracing(args: &Args, log_sink: &LogSink, sampler: &Sampler) -> Result<()> {
+    let trace_out = match &args.command {
+        Commands::Run {
+            trace_out: Some(trace_out),
+            ..
+        } => Some(ChromeTraceLayer::create(
+            Path::new(trace_out),
+            sampler.clone(),
+        )?),
+        _ => None,
+    };
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(Redacting(std::io::stderr))
+                .with_filter(EnvFilter::from_default_env())
+                .with_filter(filter_fn({
+                    let log_sink = log_sink.clone();
+                    move |_| log_sink.stderr_enabled()
+                })),
+        )
+        .with(log_sink.layer().with_filter(EnvFilter::from_default_env()))
+        .with(trace_out)
+        .init();
+    Ok(())
+}
+
+/// How capability calls are sampled for the trace, as per the slightfile's `tracing` section.
+fn sampling_settings(tracing: &Tracing, toml: &TomlFile) -> Result<SamplingSettings> {
+    let defaults = SamplingSettings::default();
+    SamplingSettings {
+        rate: tracing.sample_rate.unwrap_or(defaults.rate),
+        error_rate: tracing.error_sample_rate.unwrap_or(defaults.error_rate),
+        slow_rate: tracing.slow_sample_rate.unwrap_or(defaults.slow_rate),
+        slow_threshold: tracing
+            .slow_call_threshold_ms
+            .or(toml.slow_call_threshold_ms)
+            .map(std::time::Duration::from_millis),
     }
+    .validate()
 }
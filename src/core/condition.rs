@@ -0,0 +1,130 @@
+use anyhow::{bail, Context, Result};
+
+/// A `Condition` decides whether a capability is linked (i.e., its `when`), so that
+/// one slightfile can enable different backends in different environments.
+///
+/// On purpose, it only supports checking environment variables:
+///     - `env.PROFILE` is true if `PROFILE` is set,
+///     - `!env.PROFILE` is true if `PROFILE` isn't set,
+///     - `env.PROFILE == 'prod'` is true if `PROFILE` is set to `prod`, and
+///     - `env.PROFILE != 'prod'` is true if `PROFILE` isn't set to `prod` (or isn't set).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    Set(String),
+    NotSet(String),
+    Equals(String, String),
+    NotEquals(String, String),
+}
+
+impl Condition {
+    pub fn parse(condition: &str) -> Result<Self> {
+        let parse = || -> Result<Self> {
+            let condition = condition.trim();
+            if let Some((var, value)) = condition.split_once("==") {
+                Ok(Condition::Equals(var_name(var)?, quoted(value)?))
+            } else if let Some((var, value)) = condition.split_once("!=") {
+                Ok(Condition::NotEquals(var_name(var)?, quoted(value)?))
+            } else if let Some(var) = condition.strip_prefix('!') {
+                Ok(Condition::NotSet(var_name(var)?))
+            } else {
+                Ok(Condition::Set(var_name(condition)?))
+            }
+        };
+        parse().with_context(|| format!("invalid condition: \"{}\"", condition))
+    }
+
+    /// Evaluates the condition, w/ `env` looking environment variables up.
+    pub fn evaluate(&self, env: impl Fn(&str) -> Option<String>) -> bool {
+        match self {
+            Condition::Set(var) => env(var).is_some(),
+            Condition::NotSet(var) => env(var).is_none(),
+            Condition::Equals(var, value) => env(var).as_ref() == Some(value),
+            Condition::NotEquals(var, value) => env(var).as_ref() != Some(value),
+        }
+    }
+}
+
+/// Parses an `env.<NAME>` operand into `<NAME>`.
+fn var_name(operand: &str) -> Result<String> {
+    let name = match operand.trim().strip_prefix("env.") {
+        Some(name) => name,
+        None => bail!("expected an environment variable (i.e., env.<NAME>)"),
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        bail!("'{}' is not a valid environment variable name", name);
+    }
+    Ok(name.to_string())
+}
+
+/// Parses a `'<value>'` (or `"<value>"`) operand into `<value>`.
+fn quoted(operand: &str) -> Result<String> {
+    let operand = operand.trim();
+    for quote in ['\'', '"'] {
+        if let Some(value) = operand
+            .strip_prefix(quote)
+            .and_then(|o| o.strip_suffix(quote))
+        {
+            if !value.contains(quote) {
+                return Ok(value.to_string());
+            }
+        }
+    }
+    bail!("expected a quoted value (i.e., 'value')")
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+
+    use super::Condition;
+
+    fn env(var: &str) -> Option<String> {
+        match var {
+            "PROFILE" => Some("prod".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parse_test() -> Result<()> {
+        assert_eq!(
+            Condition::parse("env.PROFILE == 'prod'")?,
+            Condition::Equals("PROFILE".to_string(), "prod".to_string())
+        );
+        assert_eq!(
+            Condition::parse("env.PROFILE!=\"dev\"")?,
+            Condition::NotEquals("PROFILE".to_string(), "dev".to_string())
+        );
+        assert_eq!(
+            Condition::parse(" env.PROFILE ")?,
+            Condition::Set("PROFILE".to_string())
+        );
+        assert_eq!(
+            Condition::parse("!env.PROFILE")?,
+            Condition::NotSet("PROFILE".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_invalid_test() {
+        assert!(Condition::parse("PROFILE == 'prod'").is_err());
+        assert!(Condition::parse("env.PROFILE == prod").is_err());
+        assert!(Condition::parse("env.PROFILE == 'prod").is_err());
+        assert!(Condition::parse("env.PROFILE == 'a' == 'b'").is_err());
+        assert!(Condition::parse("env. == 'prod'").is_err());
+        assert!(Condition::parse("env.PROFILE || true").is_err());
+    }
+
+    #[test]
+    fn evaluate_test() -> Result<()> {
+        assert!(Condition::parse("env.PROFILE == 'prod'")?.evaluate(env));
+        assert!(!Condition::parse("env.PROFILE == 'dev'")?.evaluate(env));
+        assert!(Condition::parse("env.PROFILE != 'dev'")?.evaluate(env));
+        assert!(Condition::parse("env.REGION != 'eu'")?.evaluate(env));
+        assert!(Condition::parse("env.PROFILE")?.evaluate(env));
+        assert!(!Condition::parse("env.REGION")?.evaluate(env));
+        assert!(Condition::parse("!env.REGION")?.evaluate(env));
+        Ok(())
+    }
+}
@@ -0,0 +1,327 @@
+use std::{collections::BTreeMap, fmt};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use toml::{value::Table, Value};
+
+use crate::core::slightfile::{SecretStore, TomlFile};
+
+/// What the values of secret settings are shown as, as they are secrets.
+const REDACTED: &str = "***";
+
+/// A `Change` is a setting that differs between two slightfiles (see `diff_slightfiles`).
+///
+/// Its' `path` is the setting's key (e.g., `slow_call_threshold_ms`), w/ capabilities, and
+/// secret settings named by their `name` (e.g., `capability[kv.azblob].kv.allow_clear`).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "change", rename_all = "lowercase")]
+pub enum Change {
+    Added {
+        path: String,
+        value: Value,
+    },
+    Removed {
+        path: String,
+        value: Value,
+    },
+    Changed {
+        path: String,
+        old: Value,
+        new: Value,
+    },
+}
+
+impl Change {
+    pub fn path(&self) -> &str {
+        match self {
+            Change::Added { path, .. }
+            | Change::Removed { path, .. }
+            | Change::Changed { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { path, value } => write!(f, "+ {} = {}", path, inline(value)),
+            Change::Removed { path, value } => write!(f, "- {} = {}", path, inline(value)),
+            Change::Changed { path, old, new } => {
+                write!(f, "~ {}: {} -> {}", path, inline(old), inline(new))
+            }
+        }
+    }
+}
+
+/// Diffs two slightfiles semantically (i.e., what's linked, and how), rather than textually,
+/// so formatting, and the order capabilities, and secret settings are declared in don't show
+/// up — the secret stores' order does, as it's their precedence.
+///
+/// Changes are sorted by path, and the values of secret settings are redacted.
+pub fn diff_slightfiles(old: &str, new: &str) -> Result<Vec<Change>> {
+    let old = canonical(old).with_context(|| "failed to parse the old slightfile")?;
+    let new = canonical(new).with_context(|| "failed to parse the new slightfile")?;
+    let mut changes = Vec::new();
+    diff_tables(&old, &new, "", &mut changes);
+    changes.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(changes)
+}
+
+/// Parses a slightfile into a table where capabilities, and secret settings are keyed by
+/// name, rather than listed.
+fn canonical(contents: &str) -> Result<Table> {
+    let mut toml = toml::from_str::<TomlFile>(contents)?;
+    // a single secret store is the same as a list of one
+    toml.secret_store = toml
+        .secret_store
+        .map(|store| SecretStore::Ordered(store.stores()));
+    let mut table = match Value::try_from(&toml)? {
+        Value::Table(table) => table,
+        _ => unreachable!("a slightfile serializes to a table"),
+    };
+    for array in ["capability", "secret_settings"] {
+        if let Some(Value::Array(items)) = table.remove(array) {
+            table.insert(array.to_string(), Value::Table(keyed_by_name(items)));
+        }
+    }
+    Ok(table)
+}
+
+/// Keys tables by their `name` — if many tables have the same name (e.g., capabilities w/
+/// different conditions), they're told apart by their `when` too.
+fn keyed_by_name(items: Vec<Value>) -> Table {
+    let name = |item: &Value| {
+        item.get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string()
+    };
+    let mut counts = BTreeMap::new();
+    for item in &items {
+        *counts.entry(name(item)).or_insert(0) += 1;
+    }
+    let mut keyed = Table::new();
+    for item in items {
+        let mut key = name(&item);
+        if counts[&key] > 1 {
+            if let Some(when) = item.get("when").and_then(Value::as_str) {
+                key = format!("{} when {}", key, when);
+            }
+        }
+        // the same name, and condition twice is invalid, but both are still shown
+        let mut unique = key.clone();
+        let mut n = 1;
+        while keyed.contains_key(&unique) {
+            n += 1;
+            unique = format!("{} #{}", key, n);
+        }
+        keyed.insert(unique, item);
+    }
+    keyed
+}
+
+fn diff_tables(old: &Table, new: &Table, path: &str, changes: &mut Vec<Change>) {
+    for (key, old_value) in old {
+        let key_path = join(path, key);
+        match new.get(key) {
+            None => changes.push(redacted(Change::Removed {
+                path: key_path,
+                value: old_value.clone(),
+            })),
+            Some(new_value) => diff_values(old_value, new_value, &key_path, changes),
+        }
+    }
+    for (key, new_value) in new {
+        if !old.contains_key(key) {
+            changes.push(redacted(Change::Added {
+                path: join(path, key),
+                value: new_value.clone(),
+            }));
+        }
+    }
+}
+
+fn diff_values(old: &Value, new: &Value, path: &str, changes: &mut Vec<Change>) {
+    match (old, new) {
+        (Value::Table(old), Value::Table(new)) => diff_tables(old, new, path, changes),
+        (old, new) if old != new => changes.push(redacted(Change::Changed {
+            path: path.to_string(),
+            old: old.clone(),
+            new: new.clone(),
+        })),
+        _ => {}
+    }
+}
+
+/// The path of `key` within `path` — the items of capabilities, and secret settings are
+/// named in brackets (e.g., `capability[kv.azblob]`).
+fn join(path: &str, key: &str) -> String {
+    match path {
+        "" => key.to_string(),
+        "capability" | "secret_settings" => format!("{}[{}]", path, key),
+        path => format!("{}.{}", path, key),
+    }
+}
+
+/// Hides the values of secret settings.
+fn redacted(change: Change) -> Change {
+    let secret = |path: &str| path.starts_with("secret_settings");
+    let redact = |value: Value| match value {
+        Value::Table(mut table) => {
+            if table.contains_key("value") {
+                table.insert("value".to_string(), Value::String(REDACTED.to_string()));
+            }
+            Value::Table(table)
+        }
+        _ => Value::String(REDACTED.to_string()),
+    };
+    match change {
+        Change::Added { path, value } if secret(&path) => Change::Added {
+            value: redact(value),
+            path,
+        },
+        Change::Removed { path, value } if secret(&path) => Change::Removed {
+            value: redact(value),
+            path,
+        },
+        Change::Changed { path, old, new } if secret(&path) => Change::Changed {
+            old: redact(old),
+            new: redact(new),
+            path,
+        },
+        change => change,
+    }
+}
+
+/// Renders a value on a single line, as in an inline TOML table.
+fn inline(value: &Value) -> String {
+    match value {
+        Value::Table(table) => format!(
+            "{{ {} }}",
+            table
+                .iter()
+                .map(|(key, value)| format!("{} = {}", key, inline(value)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Value::Array(items) => format!(
+            "[{}]",
+            items.iter().map(inline).collect::<Vec<_>>().join(", ")
+        ),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+
+    use super::diff_slightfiles;
+
+    const SLIGHTFILE: &str = r#"
+        specversion = "0.1"
+        secret_store = "configs.envvars"
+
+        [[secret_settings]]
+        name = "TOKEN"
+        value = "encrypted"
+
+        [[capability]]
+        name = "kv.filesystem"
+        slow_call_threshold_ms = 100
+
+        [[capability]]
+        name = "mq.filesystem"
+    "#;
+
+    #[test]
+    fn equivalent_slightfiles_test() -> Result<()> {
+        // reordered, reformatted, and w/ the secret store as a list of one
+        let reordered = r#"
+            secret_store = ["configs.envvars"]
+            specversion = "0.1"
+            [[capability]]
+            name = "mq.filesystem"
+            [[capability]]
+            slow_call_threshold_ms = 100
+            name = "kv.filesystem"
+            [[secret_settings]]
+            value = "encrypted"
+            name = "TOKEN"
+        "#;
+        assert!(diff_slightfiles(SLIGHTFILE, reordered)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn changes_test() -> Result<()> {
+        let new = r#"
+            specversion = "0.1"
+            secret_store = ["configs.usersecrets", "configs.envvars"]
+
+            [[secret_settings]]
+            name = "TOKEN"
+            value = "re-encrypted"
+
+            [[capability]]
+            name = "kv.filesystem"
+            slow_call_threshold_ms = 200
+            kv = { allow_clear = true }
+
+            [[capability]]
+            name = "http"
+        "#;
+        let changes = diff_slightfiles(SLIGHTFILE, new)?
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec![
+                "+ capability[http] = { name = \"http\" }",
+                "+ capability[kv.filesystem].kv = { allow_clear = true }",
+                "~ capability[kv.filesystem].slow_call_threshold_ms: 100 -> 200",
+                "- capability[mq.filesystem] = { name = \"mq.filesystem\" }",
+                "~ secret_settings[TOKEN].value: \"***\" -> \"***\"",
+                "~ secret_store: [\"configs.envvars\"] -> [\"configs.usersecrets\", \"configs.envvars\"]",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn conditional_capabilities_test() -> Result<()> {
+        let old = r#"
+            specversion = "0.1"
+            [[capability]]
+            name = "kv.filesystem"
+            when = "env.PROFILE == 'dev'"
+            [[capability]]
+            name = "kv.filesystem"
+            when = "env.PROFILE == 'prod'"
+        "#;
+        let new = r#"
+            specversion = "0.1"
+            [[capability]]
+            name = "kv.filesystem"
+            when = "env.PROFILE == 'prod'"
+            [[capability]]
+            name = "kv.filesystem"
+            when = "env.PROFILE == 'dev'"
+            [capability.kv]
+            allow_clear = true
+        "#;
+        let changes = diff_slightfiles(old, new)?;
+        assert_eq!(changes.len(), 1);
+        assert_eq!(
+            changes[0].to_string(),
+            "+ capability[kv.filesystem when env.PROFILE == 'dev'].kv = { allow_clear = true }"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_slightfile_test() {
+        assert!(diff_slightfiles(SLIGHTFILE, "[[capability]]\nnam = 1\n").is_err());
+    }
+}
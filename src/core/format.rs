@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+use toml::Value;
+
+use crate::core::{condition::Condition, slightfile::TomlFile};
+
+/// The arrays of tables in a slightfile, which are sorted by their `name`.
+const SORTED_ARRAYS: [&str; 2] = ["secret_settings", "capability"];
+
+/// Formats a slightfile in a canonical form (i.e., settings in a fixed order, and
+/// capabilities, and secret settings sorted by name), validating it in the process.
+///
+/// It fails, rather than dropping them, if there are settings slight doesn't know about.
+///
+/// Note: comments are not preserved.
+pub fn format_slightfile(contents: &str) -> Result<String> {
+    let mut toml =
+        toml::from_str::<TomlFile>(contents).with_context(|| "failed to parse slightfile")?;
+    validate(&toml)?;
+    if let Some(secret_settings) = toml.secret_settings.as_mut() {
+        secret_settings.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+    if let Some(capabilities) = toml.capability.as_mut() {
+        // the sort is stable, so capabilities w/ the same name keep their order
+        capabilities.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+    let formatted = toml::to_string(&toml)?;
+
+    let mut original = toml::from_str::<Value>(contents)?;
+    sort_arrays(&mut original);
+    let mut lost = Vec::new();
+    lost_settings(
+        &original,
+        &toml::from_str::<Value>(&formatted)?,
+        "",
+        &mut lost,
+    );
+    if !lost.is_empty() {
+        bail!(
+            "found settings slight doesn't know about, which formatting would drop: {}",
+            lost.join(", ")
+        );
+    }
+    Ok(formatted)
+}
+
+/// Whether a slightfile has (full-line) comments, which formatting doesn't preserve.
+pub fn has_comments(contents: &str) -> bool {
+    contents
+        .lines()
+        .any(|line| line.trim_start().starts_with('#'))
+}
+
+fn validate(toml: &TomlFile) -> Result<()> {
+    match toml.specversion.as_deref() {
+        Some("0.1") => {}
+        Some(v) => bail!("unsupported specversion: '{}'", v),
+        None => bail!("a specversion (i.e., `specversion = \"0.1\"`) is required"),
+    }
+    // capabilities w/ conditions may be meant for different environments, but ones w/o
+    // conditions are always linked, so their schemes can't clash.
+    let mut unconditional = HashMap::new();
+    for c in toml.capability.iter().flatten() {
+        c.check_options()?;
+        match &c.when {
+            Some(when) => {
+                Condition::parse(when)
+                    .with_context(|| format!("invalid condition for capability '{}'", c.name))?;
+            }
+            None => {
+                if let Some(declared) = unconditional.insert(c.scheme(), &c.name) {
+                    bail!(
+                        "capability '{}' declared multiple times (i.e., as '{}', and '{}')",
+                        c.scheme(),
+                        declared,
+                        c.name
+                    );
+                }
+            }
+        }
+    }
+    toml.capabilities_in_link_order()?;
+    Ok(())
+}
+
+/// Sorts the `SORTED_ARRAYS` of a parsed slightfile the same way `format_slightfile` does.
+fn sort_arrays(slightfile: &mut Value) {
+    for array in SORTED_ARRAYS {
+        if let Some(Value::Array(tables)) = slightfile.get_mut(array) {
+            tables.sort_by(|a, b| {
+                let name = |t: &Value| t.get("name").and_then(Value::as_str).map(str::to_string);
+                name(a).cmp(&name(b))
+            });
+        }
+    }
+}
+
+/// Collects the paths of the settings in `original` that are missing from `formatted`.
+fn lost_settings(original: &Value, formatted: &Value, path: &str, lost: &mut Vec<String>) {
+    match (original, formatted) {
+        (Value::Table(original), Value::Table(formatted)) => {
+            for (key, value) in original {
+                let key_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                match formatted.get(key) {
+                    Some(formatted) => lost_settings(value, formatted, &key_path, lost),
+                    None => lost.push(key_path),
+                }
+            }
+        }
+        (Value::Array(original), Value::Array(formatted)) => {
+            for (i, value) in original.iter().enumerate() {
+                let item_path = format!("{}[{}]", path, i);
+                match formatted.get(i) {
+                    Some(formatted) => lost_settings(value, formatted, &item_path, lost),
+                    None => lost.push(item_path),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+
+    use super::{format_slightfile, has_comments};
+
+    const SLIGHTFILE: &str = r#"
+        # where secrets come from
+        secret_store = ["configs.envvars", "configs.usersecrets"]
+        specversion = "0.1"
+
+        [[capability]]
+        name = "mq.filesystem"
+
+        [[capability]]
+        slow_call_threshold_ms = 100
+        name = "kv.filesystem"
+        when = "env.PROFILE == 'dev'"
+
+        [[capability]]
+        name = "kv.azblob"
+        when = "env.PROFILE == 'prod'"
+    "#;
+
+    #[test]
+    fn format_slightfile_test() -> Result<()> {
+        let formatted = format_slightfile(SLIGHTFILE)?;
+        assert!(formatted.starts_with("specversion = \"0.1\"\n"));
+        let names = formatted
+            .lines()
+            .filter(|line| line.starts_with("name = "))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "name = \"kv.azblob\"",
+                "name = \"kv.filesystem\"",
+                "name = \"mq.filesystem\""
+            ]
+        );
+        assert!(formatted.contains("slow_call_threshold_ms = 100"));
+
+        // formatting is idempotent
+        assert_eq!(format_slightfile(&formatted)?, formatted);
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_settings_are_not_dropped_test() {
+        let slightfile = "specversion = \"0.1\"\n\n[[capability]]\nname = \"kv.filesystem\"\nallow_clearr = true\n";
+        let e = format_slightfile(slightfile).unwrap_err();
+        assert!(e.to_string().contains("capability[0].allow_clearr"));
+    }
+
+    #[test]
+    fn invalid_slightfile_test() {
+        assert!(format_slightfile("[[capability]]\nname = \"kv.filesystem\"\n").is_err());
+        assert!(format_slightfile("specversion = \"0.2\"\n").is_err());
+        assert!(format_slightfile(
+            "specversion = \"0.1\"\n\n[[capability]]\nname = \"kv.filesystem\"\nwhen = \"PROFILE\"\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn duplicate_capabilities_test() {
+        let e = format_slightfile(
+            "specversion = \"0.1\"\n\n[[capability]]\nname = \"kv.filesystem\"\n\n[[capability]]\nname = \"kv.azblob\"\n",
+        )
+        .unwrap_err();
+        assert!(e
+            .to_string()
+            .contains("capability 'kv' declared multiple times"));
+
+        // w/ conditions, they may be meant for different environments
+        assert!(format_slightfile(
+            "specversion = \"0.1\"\n\n[[capability]]\nname = \"kv.filesystem\"\n\n[[capability]]\nname = \"kv.azblob\"\nwhen = \"env.PROFILE == 'prod'\"\n",
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn has_comments_test() {
+        assert!(has_comments(SLIGHTFILE));
+        assert!(!has_comments("specversion = \"0.1\"\n"));
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Deserialize, Serialize};
+
+/// The file listing the slightfiles a directory's manifest leaves out (e.g., those of examples,
+/// or tests), as gitignore-style patterns (see `Manifest::load`).
+pub const IGNORE_FILE: &str = ".slightignore";
+
+/// A `Manifest` lists the apps `slight serve` runs, and manages, in one process.
+///
+/// ```toml
+/// [[app]]
+/// name = "kv-demo"
+/// config = "kv-demo/slightfile.toml"
+/// module = "kv-demo/kv-demo.wasm"
+/// restart = "on-failure"
+/// max_restarts = 3
+/// max_memory_bytes = 67108864
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub app: Vec<App>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct App {
+    pub name: String,
+    /// the app's slightfile, relative to the manifest
+    pub config: String,
+    /// the app's wasm module, relative to the manifest
+    pub module: String,
+    /// when to restart the app after it exits
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    /// how many times the app is restarted before giving up (unlimited if unset)
+    pub max_restarts: Option<u32>,
+    /// how large each of the app's linear memories can grow, in bytes (unlimited if unset)
+    pub max_memory_bytes: Option<usize>,
+}
+
+/// `RestartPolicy` decides whether an app that exited is started again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    OnFailure,
+    Always,
+}
+
+impl RestartPolicy {
+    pub fn should_restart(&self, failed: bool) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => failed,
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+impl Manifest {
+    /// Loads the manifest at `path`.
+    ///
+    /// If `path` is a directory, the manifest has an app for each `<name>.toml`
+    /// slightfile in it, whose module is the `<name>.wasm` next to it — but for the ones
+    /// its `.slightignore` (if any) matches, which aren't apps, w/ or w/o a module.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut manifest = if path.is_dir() {
+            Self::from_dir(path)?
+        } else {
+            let contents = fs::read_to_string(path)
+                .with_context(|| format!("failed to read manifest {}", path.display()))?;
+            let mut manifest = toml::from_str::<Manifest>(&contents)
+                .with_context(|| format!("failed to parse manifest {}", path.display()))?;
+            let base = path.parent().unwrap_or_else(|| Path::new(""));
+            for app in manifest.app.iter_mut() {
+                app.config = relative_to(base, &app.config);
+                app.module = relative_to(base, &app.module);
+            }
+            manifest
+        };
+        manifest.app.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut names = HashSet::new();
+        for app in &manifest.app {
+            if !names.insert(&app.name) {
+                bail!("found more than one app named '{}'", app.name);
+            }
+        }
+        Ok(manifest)
+    }
+
+    fn from_dir(dir: &Path) -> Result<Self> {
+        let ignored = ignored(dir)?;
+        let mut app = Vec::new();
+        for entry in
+            fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))?
+        {
+            let config = entry?.path();
+            if !matches!(config.extension(), Some(ext) if ext == "toml")
+                || ignored.matched(&config, config.is_dir()).is_ignore()
+            {
+                continue;
+            }
+            let module = config.with_extension("wasm");
+            if !module.is_file() {
+                bail!(
+                    "found slightfile {}, but no module for it (i.e., {})",
+                    config.display(),
+                    module.display()
+                );
+            }
+            app.push(App {
+                name: config
+                    .file_stem()
+                    .unwrap() // note: the file has an extension, so it has a stem too
+                    .to_string_lossy()
+                    .to_string(),
+                config: config.to_string_lossy().to_string(),
+                module: module.to_string_lossy().to_string(),
+                restart: RestartPolicy::default(),
+                max_restarts: None,
+                max_memory_bytes: None,
+            });
+        }
+        Ok(Self { app })
+    }
+}
+
+/// The patterns of the `.slightignore` in `dir`, which match nothing if there's none.
+fn ignored(dir: &Path) -> Result<Gitignore> {
+    let path = dir.join(IGNORE_FILE);
+    if !path.is_file() {
+        return Ok(Gitignore::empty());
+    }
+    let mut builder = GitignoreBuilder::new(dir);
+    if let Some(e) = builder.add(&path) {
+        return Err(e).with_context(|| format!("failed to read {}", path.display()));
+    }
+    builder
+        .build()
+        .with_context(|| format!("failed to parse {}", path.display()))
+}
+
+fn relative_to(base: &Path, path: &str) -> String {
+    let mut resolved = PathBuf::from(base);
+    resolved.push(path);
+    resolved.to_string_lossy().to_string()
+}
+
+#[cfg(test)]
+mod unittests {
+    use std::fs;
+
+    use anyhow::Result;
+    use tempdir::TempDir;
+
+    use super::{Manifest, RestartPolicy, IGNORE_FILE};
+
+    #[test]
+    fn load_manifest_test() -> Result<()> {
+        let dir = TempDir::new("tmp")?;
+        let manifest_path = dir.path().join("apps.toml");
+        fs::write(
+            &manifest_path,
+            r#"
+            [[app]]
+            name = "b"
+            config = "b/slightfile.toml"
+            module = "b/b.wasm"
+            restart = "on-failure"
+            max_restarts = 3
+
+            [[app]]
+            name = "a"
+            config = "a/slightfile.toml"
+            module = "/abs/a.wasm"
+            "#,
+        )?;
+
+        let manifest = Manifest::load(&manifest_path)?;
+        assert_eq!(manifest.app[0].name, "a");
+        assert_eq!(manifest.app[0].restart, RestartPolicy::Never);
+        assert_eq!(manifest.app[0].module, "/abs/a.wasm");
+        assert_eq!(manifest.app[1].restart, RestartPolicy::OnFailure);
+        assert_eq!(manifest.app[1].max_restarts, Some(3));
+        assert_eq!(
+            manifest.app[1].config,
+            dir.path().join("b/slightfile.toml").to_string_lossy()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn load_dir_test() -> Result<()> {
+        let dir = TempDir::new("tmp")?;
+        fs::write(dir.path().join("kv.toml"), "")?;
+        fs::write(dir.path().join("kv.wasm"), "")?;
+        fs::write(dir.path().join("README.md"), "")?;
+
+        let manifest = Manifest::load(dir.path())?;
+        assert_eq!(manifest.app.len(), 1);
+        assert_eq!(manifest.app[0].name, "kv");
+        assert!(manifest.app[0].module.ends_with("kv.wasm"));
+
+        fs::write(dir.path().join("orphan.toml"), "")?;
+        assert!(Manifest::load(dir.path()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn slightignore_test() -> Result<()> {
+        let dir = TempDir::new("tmp")?;
+        for app in ["kv", "example-kv", "example-mq", "kv-test"] {
+            fs::write(dir.path().join(format!("{}.toml", app)), "")?;
+            fs::write(dir.path().join(format!("{}.wasm", app)), "")?;
+        }
+        // an ignored slightfile isn't an app, so it needs no module
+        fs::write(dir.path().join("draft.toml"), "")?;
+        fs::write(
+            dir.path().join(IGNORE_FILE),
+            "# examples, and tests aren't apps\nexample-*.toml\n!example-kv.toml\n*-test.*\n/draft.toml\n",
+        )?;
+
+        let manifest = Manifest::load(dir.path())?;
+        let names = manifest
+            .app
+            .iter()
+            .map(|app| app.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["example-kv", "kv"]);
+
+        fs::write(dir.path().join(IGNORE_FILE), "")?;
+        assert!(Manifest::load(dir.path()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_app_names_test() -> Result<()> {
+        let dir = TempDir::new("tmp")?;
+        let manifest_path = dir.path().join("apps.toml");
+        fs::write(
+            &manifest_path,
+            r#"
+            [[app]]
+            name = "a"
+            config = "a.toml"
+            module = "a.wasm"
+
+            [[app]]
+            name = "a"
+            config = "b.toml"
+            module = "b.wasm"
+            "#,
+        )?;
+
+        assert!(Manifest::load(&manifest_path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn restart_policy_test() {
+        assert!(!RestartPolicy::Never.should_restart(true));
+        assert!(RestartPolicy::OnFailure.should_restart(true));
+        assert!(!RestartPolicy::OnFailure.should_restart(false));
+        assert!(RestartPolicy::Always.should_restart(false));
+    }
+}
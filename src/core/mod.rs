@@ -1,2 +1,6 @@
+pub mod condition;
+pub mod diff;
+pub mod format;
+pub mod manifest;
 pub mod secret;
 pub mod slightfile;
@@ -101,7 +101,7 @@ mod unittests {
     use tempdir::TempDir;
 
     use super::create_secret;
-    use crate::core::slightfile::TomlFile;
+    use crate::core::slightfile::{SecretStore, TomlFile};
 
     #[test]
     fn create_secret_test() -> Result<()> {
@@ -259,4 +259,32 @@ mod unittests {
 
         Ok(())
     }
+
+    #[test]
+    fn secret_store_list_roundtrip() -> Result<()> {
+        let toml_str = r#"
+        specversion = "0.1"
+        secret_store = ["configs.envvars", "configs.usersecrets"]
+        "#;
+
+        let tmp_toml = toml::from_str::<TomlFile>(toml_str)?;
+        assert_eq!(
+            tmp_toml.secret_stores(),
+            Some(vec![
+                "configs.envvars".to_string(),
+                "configs.usersecrets".to_string()
+            ])
+        );
+
+        let reparsed = toml::from_str::<TomlFile>(&toml::to_string(&tmp_toml)?)?;
+        assert_eq!(reparsed.secret_store, tmp_toml.secret_store);
+
+        let single = toml::from_str::<TomlFile>(r#"secret_store = "configs.envvars""#)?;
+        assert_eq!(
+            single.secret_store,
+            Some(SecretStore::Single("configs.envvars".to_string()))
+        );
+
+        Ok(())
+    }
 }
@@ -1,16 +1,658 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TomlFile {
     pub specversion: Option<String>,
-    pub secret_store: Option<String>,
+    /// either a single secret store, or a list of them in order of precedence
+    pub secret_store: Option<SecretStore>,
+    /// calls into any capability taking longer than this are logged as warnings
+    pub slow_call_threshold_ms: Option<u64>,
     pub secret_settings: Option<Vec<Config>>,
+    /// the order capabilities are linked in, either "file" (i.e., the order they are declared in,
+    /// the default), or "sorted" (i.e., by name, so startup logs, and errors don't depend on it)
+    pub link_order: Option<String>,
+    /// the one-time setup of the guest (i.e., its `_init` export), if it has any
+    pub init: Option<Init>,
+    /// the budget of how much the guest's linear memories can grow by over time, to catch slow leaks
+    pub memory_growth: Option<MemoryGrowth>,
+    /// seeds the guest's randomness (i.e., WASI `random_get`), so runs that depend on it are
+    /// reproducible (e.g., w/ a cassette) — for tests only, as the guest's random numbers are
+    /// then predictable; w/o it, they come from the OS' CSPRNG
+    pub random_seed: Option<u64>,
+    /// where the host's log records are shipped to (e.g., to an existing log pipeline), as JSON lines
+    pub log_sink: Option<LogSink>,
+    /// how the capability calls written to the trace (i.e., of `slight run --trace-out`) are sampled — w/o it, they all are
+    pub tracing: Option<Tracing>,
+    /// a sandboxed view of the filesystem for the guest: the app directory, read-only, and a single writable scratch
+    /// directory — w/o it, the guest gets the `cache` preopen (i.e., `./target`, read-write)
+    pub filesystem: Option<Filesystem>,
+    /// the labels capability calls are counted w/ in `slight serve`'s metrics (see `Capability` to override them), and
+    /// where they're exported to
+    pub metrics: Option<Metrics>,
+    /// which capability calls are recorded in the audit (i.e., under the `slight::audit` log target, which the `log_sink`
+    /// ships too) — w/o it, none are
+    pub audit: Option<Audit>,
+    /// how many times the guest can be invoked at once across its triggers (i.e., http requests, and events) — past
+    /// it, http requests are shed w/ a 503, and events wait for an invocation to finish; w/o it, there's no limit
+    pub max_concurrent_invocations: Option<u32>,
+    /// the faults injected into capability calls, to test how the guest handles backend failures — only injected w/
+    /// `slight run --chaos`, and `SLIGHT_CHAOS=enabled`, so it's never on by accident (e.g., in production)
+    pub chaos: Option<Chaos>,
+    /// the scripted responses of capability calls (i.e., `[[mock]]`s), to test how the guest handles specific backend
+    /// behaviors — only used w/ `slight run --mocks`, so they never stand in for the backends by accident
+    pub mock: Option<Vec<Mock>>,
     pub capability: Option<Vec<Capability>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl TomlFile {
+    /// Returns the secret stores to look secrets up in, in order of precedence,
+    /// or `None` if no secret store was specified.
+    pub fn secret_stores(&self) -> Option<Vec<String>> {
+        self.secret_store
+            .as_ref()
+            .map(SecretStore::stores)
+            .filter(|stores| !stores.is_empty())
+    }
+
+    /// Returns the capabilities in the order they are to be linked in (see `link_order`).
+    ///
+    /// Capabilities don't depend on each other to be linked, so any order works — sorting
+    /// is stable, so capabilities w/ the same name (e.g., w/ different conditions) keep
+    /// the order they are declared in.
+    pub fn capabilities_in_link_order(&self) -> Result<Vec<&Capability>> {
+        let mut capabilities = self.capability.iter().flatten().collect::<Vec<_>>();
+        match self.link_order.as_deref() {
+            None | Some("file") => {}
+            Some("sorted") => capabilities.sort_by(|a, b| a.name.cmp(&b.name)),
+            Some(order) => bail!(
+                "invalid link_order: '{}' (expected 'file', or 'sorted')",
+                order
+            ),
+        }
+        Ok(capabilities)
+    }
+}
+
+/// A `SecretStore` is either a single store (i.e., `secret_store = "configs.envvars"`),
+/// or an ordered list of them (i.e., `secret_store = ["configs.envvars", "configs.usersecrets"]`),
+/// where the first store that has a secret wins.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SecretStore {
+    Single(String),
+    Ordered(Vec<String>),
+}
+
+impl SecretStore {
+    pub fn stores(&self) -> Vec<String> {
+        match self {
+            SecretStore::Single(store) => vec![store.clone()],
+            SecretStore::Ordered(stores) => stores.clone(),
+        }
+    }
+}
+
+/// A capability the guest is linked to, w/ the settings of its calls, which every capability has, and the options of
+/// its scheme in a table of their own (e.g., `[capability.kv]` for `kv.azblob`), which the others can't have (see
+/// `check_options`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Capability {
     pub name: String,
+    /// only link this capability if the condition holds (e.g., `env.PROFILE == 'prod'`, see `Condition`)
+    pub when: Option<String>,
+    /// overrides the global `slow_call_threshold_ms` for this capability
+    pub slow_call_threshold_ms: Option<u64>,
+    /// (kv, and configs only) if a read fails as the backend did (i.e., w/ a backend error, or
+    /// a timeout), serve the last value read for the key instead, as long as it was read no
+    /// longer than this ago
+    pub last_known_good_max_age_ms: Option<u64>,
+    /// whether this capability's calls are counted w/ their target (e.g., a key, or a queue) as a metrics label —
+    /// overrides `metrics.target_label`
+    pub metrics_target_label: Option<bool>,
+    /// how many distinct targets this capability's calls get a metrics label of their own for, before the rest are
+    /// counted as `other` — overrides `metrics.max_targets`
+    pub metrics_max_targets: Option<usize>,
+    /// how many calls the guest can make into the capability per second (unlimited if not set)
+    pub quota_ops_per_sec: Option<u64>,
+    /// (kv, mq, and pubsub only) how many bytes of payloads the guest can send, or receive through the capability per minute (unlimited if not set)
+    pub quota_bytes_per_min: Option<u64>,
+    /// how many calls into the capability can be in flight at once, across all of the guest's instances (unlimited if not set)
+    pub max_connections: Option<u32>,
+    /// how long a call waits for one of the `max_connections` to free up before it fails (defaults to 1000)
+    pub connection_wait_ms: Option<u64>,
+    /// the only operations of the capability the guest is allowed to call, named as their function is (e.g., `["open", "get"]`
+    /// for a read-only kv store — all of them, if not set), which events, and http don't support
+    pub allow_operations: Option<Vec<String>>,
+    /// the operations of the capability the guest isn't allowed to call, even if they're in `allow_operations` (e.g., `["delete"]`)
+    pub deny_operations: Option<Vec<String>>,
+    /// the experimental operations of the capability the app opts into, which are disabled otherwise (i.e., calling them
+    /// fails w/ `disabled`)
+    pub enable_operations: Option<Vec<String>>,
+    /// the operations of the capability that are disabled for the app, to roll them out (or back) gradually (e.g., w/ a
+    /// `when` condition) — events, and http don't support it, nor `enable_operations`
+    pub disable_operations: Option<Vec<String>>,
+    /// (kv, and mq only) compress the values, keys, and messages returned to the guest w/ this codec: `deflate`, or `zstd`
+    /// (the guest's bindings decode them, see `slight_compression::decoding!`)
+    pub compress_results: Option<String>,
+    /// how big a value, or message is before it's compressed w/ `compress_results` (defaults to 4096)
+    pub compress_threshold_bytes: Option<usize>,
+    /// (kv, mq, pubsub, and http only) how big the payloads the guest sends through the capability (or, for http, the bodies of
+    /// the requests it handles) can be before they're rejected w/ a `payload-too-large` error (or a 413) — the defaults are 16 MiB
+    /// for kv, and http, and 1 MiB for mq, and pubsub
+    pub max_payload_bytes: Option<usize>,
+    /// (mq, and pubsub.confluent_apache_kafka only) how messages are signed, and verified — w/o it, they aren't
+    pub signing: Option<SigningOptions>,
+    pub http: Option<HttpOptions>,
+    pub configs: Option<ConfigsOptions>,
+    pub kv: Option<KvOptions>,
+    pub events: Option<EventsOptions>,
+    pub election: Option<ElectionOptions>,
+    pub mq: Option<MqOptions>,
+    pub pubsub: Option<PubsubOptions>,
+    pub validation: Option<ValidationOptions>,
+    pub jobs: Option<JobsOptions>,
+    pub timers: Option<TimersOptions>,
+    /// the webhooks the http server exposes, by the name the guest watches them by (e.g.,
+    /// `{ stripe = { path = "/webhooks/stripe", scheme = "stripe", secret = "STRIPE_WEBHOOK_SECRET" } }`)
+    pub webhooks: Option<HashMap<String, Webhook>>,
+    pub notifications: Option<NotificationsOptions>,
+    pub workflow: Option<WorkflowOptions>,
+    pub deployment: Option<DeploymentOptions>,
+    pub credentials: Option<CredentialsOptions>,
+}
+
+impl Capability {
+    /// The scheme a capability is linked under (e.g., `kv` for `kv.azblob`).
+    pub fn scheme(&self) -> &str {
+        self.name
+            .split_once('.')
+            .map_or(self.name.as_str(), |(scheme, _)| scheme)
+    }
+
+    /// Checks the capability only has the options of its own scheme (e.g., no `[capability.http]` on a kv capability),
+    /// as those of another one would be ignored.
+    pub fn check_options(&self) -> Result<()> {
+        let tables = [
+            ("http", self.http.is_some()),
+            ("configs", self.configs.is_some()),
+            ("kv", self.kv.is_some()),
+            ("events", self.events.is_some()),
+            ("election", self.election.is_some()),
+            ("mq", self.mq.is_some()),
+            ("pubsub", self.pubsub.is_some()),
+            ("validation", self.validation.is_some()),
+            ("jobs", self.jobs.is_some()),
+            ("timers", self.timers.is_some()),
+            ("webhooks", self.webhooks.is_some()),
+            ("notifications", self.notifications.is_some()),
+            ("workflow", self.workflow.is_some()),
+            ("deployment", self.deployment.is_some()),
+            ("credentials", self.credentials.is_some()),
+        ];
+        for (scheme, set) in tables {
+            if set && scheme != self.scheme() {
+                bail!(
+                    "invalid `{}` options: they only apply to {} capabilities, not '{}'",
+                    scheme,
+                    scheme,
+                    self.name
+                );
+            }
+        }
+        if self.signing.is_some() && !matches!(self.scheme(), "mq" | "pubsub") {
+            bail!(
+                "invalid `signing` options: they only apply to mq, and pubsub capabilities, not '{}'",
+                self.name
+            );
+        }
+        Ok(())
+    }
+}
+
+/// How the messages of an mq, or pubsub capability are signed, and verified.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigningOptions {
+    /// the secret the key messages are signed, and verified w/ is read from (e.g., `MQ_SIGNING_KEY`)
+    pub key: String,
+    /// what messages are signed w/: `hmac-sha256` (the default)
+    pub algorithm: Option<String>,
+    /// whether received messages that aren't signed, or whose signature doesn't match are rejected (the default), rather
+    /// than only logged
+    pub enforce: Option<bool>,
+}
+
+/// The options of the http capability (i.e., `[capability.http]`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HttpOptions {
+    /// the directory templates are rendered from, relative to the slightfile
+    pub templates_dir: Option<String>,
+    /// log each request on the host side, in this format: `common`, `combined`, or `json`
+    pub access_log: Option<String>,
+    /// the request headers redacted from the access log (defaults to `Authorization`, and `Cookie`)
+    pub access_log_redacted_headers: Option<Vec<String>>,
+    /// the formats JSON responses are negotiated in, as per the `Accept` header: `json`, `xml`, and/or `msgpack`
+    pub formats: Option<Vec<String>>,
+    /// the OpenAPI 3 spec (JSON, or YAML) requests, and responses are validated against, relative to the slightfile
+    pub openapi: Option<String>,
+    /// what's derived from each request on the host side, and handed to the guest w/ it: `cookies`, `client_ip`, and/or
+    /// `country`
+    pub enrich: Option<Vec<String>>,
+    /// the CIDRs of the proxies whose `X-Forwarded-For` is trusted when resolving the client's IP (e.g., `10.0.0.0/8`)
+    pub trusted_proxies: Option<Vec<String>>,
+    /// the GeoIP database (i.e., a MaxMind `.mmdb`) the `country` is looked up in, relative to the slightfile
+    pub geoip_database: Option<String>,
+    /// how long the guest has to handle a request, from when it's received — the capability calls it makes past it (or
+    /// past the client's `X-Request-Deadline`), or whose backends haven't answered by then fail w/ `deadline-exceeded`
+    pub request_timeout_ms: Option<u64>,
+    /// serve over TLS w/ this certificate chain (PEM), relative to the slightfile — requires `tls_key`
+    pub tls_cert: Option<String>,
+    /// the private key (PEM) of `tls_cert`, relative to the slightfile
+    pub tls_key: Option<String>,
+    /// verify client certificates against this CA bundle (PEM), relative to the slightfile (i.e., mutual TLS)
+    pub tls_client_ca: Option<String>,
+    /// whether clients must present a certificate the `tls_client_ca` verified: `required` (the default), or `optional`
+    pub tls_client_auth: Option<String>,
+    /// how the responses of routes are cached, by route (e.g., `{ "/users" = "max-age=60, etag" }`): w/ `max-age=<secs>`,
+    /// `s-maxage=<secs>`, `no-store`, `no-cache`, `private`, `public`, and/or `etag` (see `router.cache`)
+    pub cache_policies: Option<HashMap<String, String>>,
+}
+
+/// The options of the configs capability (i.e., `[capability.configs]`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigsOptions {
+    /// the values configs get when they're absent (e.g., `{ LOG_LEVEL = "info" }`), rather than failing — these win over
+    /// the guest's own defaults (i.e., those of `get-or-default`)
+    pub defaults: Option<HashMap<String, String>>,
+}
+
+/// The options of the kv capability (i.e., `[capability.kv]`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KvOptions {
+    /// enables the `clear` operation, which deletes keys in bulk
+    pub allow_clear: Option<bool>,
+    /// caches the values read for up to this many secs, unless a write through the guest (or its `invalidate`)
+    /// invalidates them first — w/o it, every read goes to the backend
+    pub cache_ttl_secs: Option<u64>,
+    /// how many values are cached at most (defaults to 10000), past which the least recently used one is evicted
+    pub cache_max_entries: Option<usize>,
+    /// coalesces the gets (that miss the cache) made by different guest instances w/in this many msecs into one batch
+    /// operation (DynamoDB's `BatchGetItem`, or concurrent gets on backends w/o one) — gets made while no other is
+    /// pending don't wait for it; w/o it, gets aren't batched
+    pub batch_window_ms: Option<u64>,
+    /// how many gets a batch holds at most (defaults to 25), before it's run w/o waiting for the window
+    pub batch_max_size: Option<usize>,
+    /// how the structured values the guest passes as payloads (i.e., patches) are encoded: `binary` (the default), or
+    /// `json`, which is bigger, but readable (e.g., in cassettes)
+    pub encoding: Option<String>,
+    /// whether a store whose backend the guest released (see `release`) is reopened once it's used again (the default),
+    /// rather than failing
+    pub reopen_released: Option<bool>,
+    /// the secret the page tokens of key streams are sealed w/ is read from (e.g., `KV_PAGE_TOKEN_KEY`), so they can be
+    /// resumed after the host restarts, and by other hosts — w/o it, they're sealed w/ a key that only lasts as long as
+    /// the host's process
+    pub page_token_key: Option<String>,
+    /// how long releasing a store's backend (or shutting down) waits for the calls in flight on it in millis (defaults
+    /// to 5000), before closing it regardless
+    pub drain_grace_ms: Option<u64>,
+    /// a second kv implementor (e.g., a new backend being canary-tested) a share of operations is routed to
+    pub canary: Option<String>,
+    /// the percentage of reads routed to the `canary`
+    pub canary_read_percent: Option<u8>,
+    /// the percentage of writes routed to the `canary`
+    pub canary_write_percent: Option<u8>,
+    /// the upper bounds (in bytes) of the buckets of the key length histograms — overrides `metrics.key_size_buckets`
+    pub metrics_key_size_buckets: Option<Vec<u64>>,
+    /// the upper bounds (in bytes) of the buckets of the value size histograms — overrides `metrics.value_size_buckets`
+    pub metrics_value_size_buckets: Option<Vec<u64>>,
+}
+
+/// The options of the events capability (i.e., `[capability.events]`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EventsOptions {
+    /// delivers the events that arrive w/in this many msecs to the guest in one call (i.e., its `event-batch-handler.wit`
+    /// export) — w/o it, they're delivered one by one
+    pub batch_window_ms: Option<u64>,
+    /// how many events a batch holds at most (defaults to 25), before it's delivered w/o waiting for the window
+    pub batch_max_size: Option<usize>,
+    /// how many times an event the guest failed to handle in a batch is delivered again (defaults to 3), before it's dropped
+    pub max_redeliveries: Option<u32>,
+}
+
+/// The options of the election capability (i.e., `[capability.election]`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ElectionOptions {
+    /// the time to live of a candidate's lease in secs (defaults to 10), which the host renews for as long as it leads —
+    /// a leader whose lease couldn't be renewed for this long loses its leadership
+    pub lease_ttl_secs: Option<u64>,
+}
+
+/// The options of the mq capability (i.e., `[capability.mq]`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MqOptions {
+    /// the queue messages rejected for their signature are sent to, rather than dropped (see `signing`)
+    pub dead_letter_queue: Option<String>,
+}
+
+/// The options of the pubsub capability (i.e., `[capability.pubsub]`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PubsubOptions {
+    /// skip messages seen in the last this many secs (i.e., duplicates)
+    pub dedup_window_secs: Option<u64>,
+    /// the kv implementor the ids of seen messages are kept in (defaults to `kv.filesystem`)
+    pub dedup_store: Option<String>,
+    /// the header holding the id of a message (defaults to a hash of its key, and value)
+    pub dedup_id_header: Option<String>,
+    /// (pubsub.inmemory only) what happens to messages published to a topic no one is subscribed to: `buffered` (the
+    /// default) keeps them for the first subscriber, and `drop` drops them
+    pub delivery: Option<String>,
+    /// (pubsub.confluent_apache_kafka only) the values of messages are serialized w/ schemas of a schema registry (whose
+    /// `CK_SCHEMA_REGISTRY_URL` is read from the secret store) in this format: `avro`, or `protobuf` — w/o it, they're raw bytes
+    pub schema_format: Option<String>,
+    /// (pubsub.confluent_apache_kafka only) the schema messages are produced w/, per topic, relative to the slightfile (e.g.,
+    /// `{ orders = "schemas/order.avsc" }`) — topics w/o one are produced w/ the latest schema registered for them
+    pub schemas: Option<HashMap<String, String>>,
+}
+
+/// The options of the validation capability (i.e., `[capability.validation]`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ValidationOptions {
+    /// the rule sets guests validate values against by their name, relative to the slightfile (e.g.,
+    /// `{ user = "rules/user.json" }`)
+    pub rule_sets: Option<HashMap<String, String>>,
+}
+
+/// The options of the jobs capability (i.e., `[capability.jobs]`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JobsOptions {
+    /// the kv implementor jobs are kept in (defaults to `kv.filesystem`)
+    pub store: Option<String>,
+}
+
+/// The options of the timers capability (i.e., `[capability.timers]`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TimersOptions {
+    /// the kv implementor timers are kept in (defaults to `kv.filesystem`)
+    pub store: Option<String>,
+    /// the lockd implementor hosts lock a timer in while they fire it (e.g., `lockd.etcd`) — w/o it, only the
+    /// compare-and-swap of the `store` keeps two hosts from firing it
+    pub lock: Option<String>,
+    /// the election implementor hosts campaign in to fire leader-only timers (e.g., `election.etcd`), so they fire on the
+    /// replica that leads — w/o it, timers can't be leader-only
+    pub election: Option<String>,
+    /// what happens to timers that fire more than `misfire_grace_secs` late: `fire` (the default), or `skip`
+    pub misfire_policy: Option<String>,
+    /// how late a timer can fire before it counts as misfired (defaults to 60)
+    pub misfire_grace_secs: Option<u64>,
+}
+
+/// The options of the notifications capability (i.e., `[capability.notifications]`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NotificationsOptions {
+    /// whether the requests the capability makes to external services (i.e., Twilio) while the guest handles an http
+    /// request carry its trace context (i.e., W3C `traceparent`, and `tracestate` headers), so the traces span them
+    /// (defaults to true) — turn it off for services that don't want the headers
+    pub propagate_trace_context: Option<bool>,
+    /// the channels notifications are sent through, by the name guests send through them by (e.g.,
+    /// `{ email = { provider = "smtp", from = "noreply@acme.com", templates = { welcome = "templates/welcome.hbs" } } }`)
+    pub channels: Option<HashMap<String, NotificationChannel>>,
+}
+
+/// The options of the workflow capability (i.e., `[capability.workflow]`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WorkflowOptions {
+    /// the kv implementor instances of workflows are kept in (defaults to `kv.filesystem`)
+    pub store: Option<String>,
+    /// the state machines the instances of workflows follow, by the name guests open them by (e.g.,
+    /// `{ order = { initial = "pending", states = ["pending", "paid"], transitions = [{ from = "pending", event = "pay", to = "paid" }] } }`)
+    pub workflows: Option<HashMap<String, WorkflowDefinition>>,
+}
+
+/// The options of the deployment capability (i.e., `[capability.deployment]`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeploymentOptions {
+    /// the name of the environment the guest is deployed to (e.g., `prod`)
+    pub environment: Option<String>,
+    /// the region the guest is deployed to (e.g., `eastus`)
+    pub region: Option<String>,
+    /// the id of the deployment (e.g., a release, or a commit)
+    pub deployment_id: Option<String>,
+    /// the operator's own key/values the guest can read (e.g., `{ team = "payments" }`) — unlike configs, they aren't
+    /// meant to be secret
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+/// The options of the credentials capability (i.e., `[capability.credentials]`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CredentialsOptions {
+    /// the scopes (i.e., aws role arns, or azure ad scopes) guests can get credentials for
+    pub scopes: Option<Vec<String>>,
+}
+
+/// The settings of the guest's `_init` export, which is run before `_start` until it succeeds
+/// once (e.g., to run migrations) — or, across replicas, once for all of them.
+///
+/// A marker, kept in kv, records the `version` last initialized, so `_init` only runs again
+/// when it changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Init {
+    /// what's initialized (e.g., the version of the schema) — changing it runs `_init` again (defaults to `1`)
+    pub version: Option<String>,
+    /// the kv implementor the marker is kept in (defaults to `kv.filesystem`, which replicas only share w/ a shared volume)
+    pub marker_store: Option<String>,
+    /// the key the marker is kept under (defaults to `init`)
+    pub marker_key: Option<String>,
+    /// the lockd implementor replicas take turns initializing w/ (e.g., `lockd.etcd`) — w/o it, replicas starting at
+    /// once may all run `_init`
+    pub lock: Option<String>,
+    /// how long the lock outlives the replica holding it, if it dies while initializing — it's kept alive while `_init`
+    /// runs, however long that takes (defaults to 300)
+    pub lock_ttl_secs: Option<i64>,
+}
+
+/// The budget of how much the guest's linear memories (i.e., those of all of its instances) can grow
+/// by within a window, past which it's likely leaking — unlike `max_memory_bytes`, this catches a
+/// slow leak long before it runs out of memory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryGrowth {
+    /// how many bytes the memories can grow by within the window, net (i.e., the growth of instances that are
+    /// dropped, e.g., once they've handled a request, doesn't count)
+    pub budget_bytes: u64,
+    /// the window growth is measured over (defaults to 3600)
+    pub window_secs: Option<u64>,
+    /// what happens when the growth goes over budget: `warn` (the default) logs a warning, and `trap`
+    /// refuses to grow the memories, which most guests trap on
+    pub action: Option<String>,
+}
+
+/// An mq queue, or pubsub topic the host's log records are shipped to, as JSON lines — they are
+/// buffered, so logging never waits on it, and dropped (and counted) if it can't keep up.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogSink {
+    /// the implementor records are sent w/: `mq.filesystem`, `mq.azsbus`, or `pubsub.confluent_apache_kafka`
+    pub capability: String,
+    /// the queue, or topic records are sent to
+    pub name: String,
+    /// how many records are buffered, before new ones are dropped (defaults to 1024)
+    pub buffer: Option<usize>,
+    /// whether records are only shipped, rather than written to stderr too (defaults to false)
+    pub exclusive: Option<bool>,
+}
+
+/// How capability calls are sampled for the trace, so tracing under load is affordable — calls that fail, or are slow are
+/// sampled at their own rate, which is never lower than `sample_rate`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tracing {
+    /// the share of calls traced, from 0 to 1 (defaults to 1, i.e., all of them)
+    pub sample_rate: Option<f64>,
+    /// the share of the calls that fail traced (defaults to 1)
+    pub error_sample_rate: Option<f64>,
+    /// the share of the slow calls traced (defaults to 1)
+    pub slow_sample_rate: Option<f64>,
+    /// how long a call takes to be slow (defaults to the `slow_call_threshold_ms`, or, w/o it, no call is)
+    pub slow_call_threshold_ms: Option<u64>,
+}
+
+/// The labels capability calls are counted w/, so high-cardinality targets (e.g., per-user keys) can't
+/// blow up the metrics backend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Metrics {
+    /// whether calls are counted w/ their target (e.g., a key, or a queue) as a label (defaults to true)
+    pub target_label: Option<bool>,
+    /// how many distinct targets get a label of their own, per capability, before the rest are counted as
+    /// `other` (defaults to 100)
+    pub max_targets: Option<usize>,
+    /// the upper bounds (in bytes) of the buckets of the key length histograms (defaults to 8 to 1024, by a factor of 2)
+    pub key_size_buckets: Option<Vec<u64>>,
+    /// the upper bounds (in bytes) of the buckets of the value size histograms (defaults to 64 to 4194304, by a factor of 4)
+    pub value_size_buckets: Option<Vec<u64>>,
+    /// where the metrics of capability calls are exported to, besides `slight serve`'s admin endpoint
+    pub export: Option<MetricsExport>,
+}
+
+/// Where the metrics of capability calls are exported to, in the format of the operator's metrics backend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsExport {
+    /// the exporter: `prometheus` (i.e., served to be scraped), or `otlp` (i.e., pushed to a collector over OTLP/HTTP)
+    pub exporter: String,
+    /// the address `prometheus` serves `/metrics` at (defaults to 0.0.0.0:9464, which the apps of a `slight serve` exporting at the same address share)
+    pub address: Option<String>,
+    /// the url `otlp` pushes to (defaults to http://localhost:4318/v1/metrics)
+    pub endpoint: Option<String>,
+    /// how often `otlp` pushes, in seconds (defaults to 10), which a push times out after (or after 10 secs, if it's longer)
+    pub interval_secs: Option<u64>,
+}
+
+/// Which capability calls are audited, so an audit captures what's required w/o recording every read — calls the grants
+/// deny, and `clear`s are always audited, whatever the filters, and sampling say.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Audit {
+    /// whether only writes (i.e., the operations of a capability that don't only read, whether they're idempotent, or not) are audited (defaults to false)
+    pub writes_only: Option<bool>,
+    /// whether only the calls that fail are audited (defaults to false)
+    pub failures_only: Option<bool>,
+    /// the capabilities audited, by name (e.g., `kv.azblob`), or scheme (e.g., `kv`) — defaults to all of them
+    pub capabilities: Option<Vec<String>>,
+    /// the share of reads audited, from 0 to 1 (defaults to 1, i.e., all of them)
+    pub read_sample_rate: Option<f64>,
+}
+
+/// The faults injected into the calls of capabilities, by scheme (e.g., `{ kv = { rate = 0.1, faults = ["timeout"] } }`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chaos {
+    /// seeds the picking of the calls faults are injected into, so runs are reproducible (defaults to a random seed)
+    pub seed: Option<u64>,
+    pub capabilities: HashMap<String, CapabilityChaos>,
+}
+
+/// The faults injected into the calls of a capability.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapabilityChaos {
+    /// the share of calls a fault is injected into, from 0 to 1
+    pub rate: f64,
+    /// the faults injected, one of which is picked at random per call: `latency`, `error`, `timeout`, or `rate_limited`
+    pub faults: Vec<String>,
+    /// how long `latency`, and `timeout` faults delay calls, in milliseconds (defaults to 500)
+    pub latency_ms: Option<u64>,
+    /// the operations faults are injected into (e.g., `["get", "set"]`) — defaults to all of them
+    pub operations: Option<Vec<String>>,
+}
+
+/// The scripted responses of the calls to an operation of a capability, which the backend isn't called for.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mock {
+    /// the scheme of the capability (e.g., `kv`)
+    pub capability: String,
+    /// the operation mocked (e.g., `get`)
+    pub operation: String,
+    /// the target calls must be on to be mocked (e.g., a key, or a queue name) — defaults to any; mocks on a target
+    /// must come before the ones on any target of the same operation
+    pub target: Option<String>,
+    /// how long calls are delayed before they're responded to, in milliseconds
+    pub latency_ms: Option<u64>,
+    /// the responses calls get, in order (the last one again, once there are none left) — w/o any, calls are passed
+    /// through to the backend (e.g., to only delay them)
+    pub responses: Option<Vec<MockResponse>>,
+}
+
+/// A response of a `Mock`: either the value it `returns`, or the fault it `fails` w/ — a response w/ neither passes
+/// the call through to the backend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MockResponse {
+    /// the value returned, as UTF-8 bytes (i.e., for operations that return a payload, like kv's `get`)
+    pub returns: Option<String>,
+    /// the fault the call fails w/: `error`, `timeout`, `rate_limited`, or `not_found`
+    pub fails: Option<String>,
+}
+
+/// The filesystem a guest sees, and all of it: the app directory, mounted read-only at `/app`, and a scratch directory,
+/// mounted read-write at `/tmp` — writes anywhere but the scratch directory fail.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Filesystem {
+    /// the app directory, relative to the slightfile (defaults to the slightfile's directory)
+    pub app_dir: Option<String>,
+    /// the scratch directory, relative to the slightfile (defaults to a new directory under the OS' temp dir, per run, which is
+    /// removed once the run is over)
+    pub scratch_dir: Option<String>,
+}
+
+/// A webhook external services (e.g., Stripe, or GitHub) post events to, whose deliveries are verified w/ a secret it
+/// shares w/ them, and handed to the guest as events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    /// the path of the http server deliveries are posted to (e.g., `/webhooks/stripe`)
+    pub path: String,
+    /// how deliveries are signed: `github`, `stripe`, or `hmac-sha256` (i.e., a hex HMAC-SHA256 of the body in a header)
+    pub scheme: String,
+    /// the secret deliveries are signed w/ is read from (e.g., `STRIPE_WEBHOOK_SECRET`)
+    pub secret: String,
+    /// (hmac-sha256 only) the header the signature travels in (defaults to `X-Signature-256`)
+    pub signature_header: Option<String>,
+    /// (stripe only) how old a delivery can be in secs before it's rejected as a replay (defaults to 300)
+    pub tolerance_secs: Option<u64>,
+}
+
+/// A channel notifications are sent through by one provider, w/ its own templates, and rate limit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationChannel {
+    /// who sends the notifications: `smtp` (emails), `twilio`, or `sns` (text messages), or `log` (i.e., they're only
+    /// logged, for local development) — the credentials of the provider are read from the secret store
+    pub provider: String,
+    /// the Handlebars templates notifications are rendered w/, by the name guests send them by, relative to the slightfile
+    /// (e.g., `{ welcome = "templates/welcome.hbs" }`)
+    pub templates: HashMap<String, String>,
+    /// (smtp, twilio, and sns only) who notifications are sent from: an email address (smtp), a phone number, or a messaging
+    /// service sid (twilio), or a sender id (sns, optional)
+    pub from: Option<String>,
+    /// (smtp, and sns only) the Handlebars template of the subject of notifications (defaults to the name of the template)
+    pub subject: Option<String>,
+    /// how many notifications can be sent through the channel per second (unlimited if not set)
+    pub ops_per_sec: Option<u64>,
+    /// how many times a send the provider failed transiently (e.g., it was unavailable) is retried (defaults to 3)
+    pub max_retries: Option<u32>,
+}
+
+/// The state machine the instances of a workflow follow, which every transition of an instance is checked against.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowDefinition {
+    /// the state instances start in
+    pub initial: String,
+    pub states: Vec<String>,
+    /// the states instances end in (i.e., no transitions leave them)
+    pub final_states: Option<Vec<String>>,
+    pub transitions: Vec<WorkflowTransition>,
+    /// how many transitions of an instance's history are kept, the oldest are dropped past it (defaults to 100)
+    pub max_history: Option<usize>,
+}
+
+/// A transition a workflow allows: from a state, on an event, to another (or the same) state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowTransition {
+    pub from: String,
+    pub event: String,
+    pub to: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,3 +666,87 @@ impl Config {
         Self { name, value }
     }
 }
+
+#[cfg(test)]
+mod unittests {
+    use anyhow::Result;
+
+    use super::{Capability, TomlFile};
+
+    fn names(toml: &TomlFile) -> Result<Vec<&str>> {
+        Ok(toml
+            .capabilities_in_link_order()?
+            .into_iter()
+            .map(|c| c.name.as_str())
+            .collect())
+    }
+
+    #[test]
+    fn capabilities_in_link_order_test() -> Result<()> {
+        let slightfile = |link_order: &str| {
+            toml::from_str::<TomlFile>(&format!(
+                r#"
+                specversion = "0.1"
+                {}
+
+                [[capability]]
+                name = "mq.filesystem"
+
+                [[capability]]
+                name = "kv.filesystem"
+                when = "env.SLIGHT_ENV == 'dev'"
+
+                [[capability]]
+                name = "kv.filesystem"
+                when = "env.SLIGHT_ENV == 'prod'"
+                "#,
+                link_order
+            ))
+        };
+
+        let toml = slightfile("")?;
+        assert_eq!(
+            names(&toml)?,
+            vec!["mq.filesystem", "kv.filesystem", "kv.filesystem"]
+        );
+
+        let toml = slightfile("link_order = \"sorted\"")?;
+        let capabilities = toml.capabilities_in_link_order()?;
+        assert_eq!(
+            names(&toml)?,
+            vec!["kv.filesystem", "kv.filesystem", "mq.filesystem"]
+        );
+        // capabilities w/ the same name keep their order
+        assert!(capabilities[0].when.as_ref().unwrap().contains("dev"));
+
+        assert!(slightfile("link_order = \"random\"")?
+            .capabilities_in_link_order()
+            .is_err());
+        Ok(())
+    }
+    #[test]
+    fn check_options_test() -> Result<()> {
+        let capability = |options: &str| -> Result<Capability> {
+            let toml = toml::from_str::<TomlFile>(&format!(
+                "specversion = \"0.1\"\n\n[[capability]]\n{}\n",
+                options
+            ))?;
+            Ok(toml.capability.unwrap().remove(0))
+        };
+
+        capability("name = \"kv.azblob\"\nkv = { allow_clear = true }")?.check_options()?;
+        capability("name = \"mq.azsbus\"\nsigning = { key = \"MQ_SIGNING_KEY\" }")?
+            .check_options()?;
+        // the options of another scheme would be ignored
+        let e = capability("name = \"kv.azblob\"\nhttp = { templates_dir = \"templates\" }")?
+            .check_options()
+            .unwrap_err();
+        assert!(e.to_string().contains("invalid `http` options"), "{}", e);
+        assert!(
+            capability("name = \"configs.envvars\"\nsigning = { key = \"KEY\" }")?
+                .check_options()
+                .is_err()
+        );
+        Ok(())
+    }
+}
@@ -9,46 +9,97 @@ fn main() -> Result<()> {
     // test get, set, delete
     let kv = Kv::open("rand")?;
     let value = "spiderlightning".as_bytes();
-    kv.set("key", value)?;
+    kv.set("key".as_bytes(), value)?;
     println!(
         "Hello, world! the value is: {}",
-        std::str::from_utf8(&kv.get("key")?)?
+        std::str::from_utf8(&kv.get("key".as_bytes())?)?
     );
-    kv.delete("key")?;
-    let value = kv.get("key");
+    kv.delete("key".as_bytes())?;
+    let value = kv.get("key".as_bytes());
     assert!(value.is_err());
 
     // test get_kv() will have a unique allocation in the resource table.
     // so two `get_kv()` with different names will return different allocations.
     let kv1 = Kv::open("random1")?;
     let kv2 = Kv::open("random2")?;
-    kv1.set("key1", "value1".as_bytes())?;
-    kv2.set("key2", "value2".as_bytes())?;
+    kv1.set("key1".as_bytes(), "value1".as_bytes())?;
+    kv2.set("key2".as_bytes(), "value2".as_bytes())?;
 
-    assert!(kv1.get("key2").is_err());
-    kv1.delete("key1")?;
-    kv2.delete("key2")?;
+    assert!(kv1.get("key2".as_bytes()).is_err());
+    kv1.delete("key1".as_bytes())?;
+    kv2.delete("key2".as_bytes())?;
 
     // test two get_kv() with the same name will return the same allocation.
     // but the resource descriptors are not the same.
     let kv1 = Kv::open("random1")?;
     let kv2 = Kv::open("random1")?;
-    kv1.set("key1", "value1".as_bytes())?;
-    kv2.set("key2", "value2".as_bytes())?;
-    assert!(kv1.get("key2")? == "value2".as_bytes());
-    kv1.delete("key1")?;
-    kv2.delete("key2")?;
+    kv1.set("key1".as_bytes(), "value1".as_bytes())?;
+    kv2.set("key2".as_bytes(), "value2".as_bytes())?;
+    assert!(kv1.get("key2".as_bytes())? == "value2".as_bytes());
+    kv1.delete("key1".as_bytes())?;
+    kv2.delete("key2".as_bytes())?;
 
     // test get empty key
     let kv3 = Kv::open("random3")?;
-    let value = kv3.get("");
+    let value = kv3.get("".as_bytes());
     assert!(value.is_err());
 
+    // test get_or_default() falls back to the default for a missing key
+    let value = kv3.get_or_default("missing".as_bytes(), "default".as_bytes())?;
+    assert!(value == "default".as_bytes());
+
+    // test incr_by() treats a missing key as zero and persists the counter
+    let kv5 = Kv::open("random5")?;
+    assert_eq!(kv5.incr_by("counter".as_bytes(), 1)?, 1);
+    assert_eq!(kv5.incr_by("counter".as_bytes(), 41)?, 42);
+    assert!(kv5.get("counter".as_bytes())? == "42".as_bytes());
+    kv5.set("not-a-counter".as_bytes(), "spiderlightning".as_bytes())?;
+    assert!(kv5.incr_by("not-a-counter".as_bytes(), 1).is_err());
+    kv5.delete("counter".as_bytes())?;
+    kv5.delete("not-a-counter".as_bytes())?;
+
+    // test binary keys round-trip, and are listed back as the original bytes
+    let kv6 = Kv::open("random6")?;
+    let binary_key: &[u8] = &[0x00, 0xde, 0xad, b'/', 0xff];
+    kv6.set(binary_key, "binary".as_bytes())?;
+    kv6.set("plain".as_bytes(), "plain".as_bytes())?;
+    assert!(kv6.get(binary_key)? == "binary".as_bytes());
+    let mut keys = kv6.list_keys()?;
+    keys.sort();
+    assert_eq!(keys, vec![binary_key.to_vec(), "plain".as_bytes().to_vec()]);
+    kv6.delete(binary_key)?;
+    kv6.delete("plain".as_bytes())?;
+
+    // test clear() only deletes keys under the given prefix
+    let kv7 = Kv::open("random7")?;
+    kv7.set("app/a".as_bytes(), "1".as_bytes())?;
+    kv7.set("app/b".as_bytes(), "2".as_bytes())?;
+    kv7.set("other".as_bytes(), "3".as_bytes())?;
+    assert_eq!(kv7.clear("app/".as_bytes())?, 2);
+    assert_eq!(kv7.list_keys()?, vec!["other".as_bytes().to_vec()]);
+    assert_eq!(kv7.clear(&[])?, 1);
+
+    // test get_range() returns just the requested bytes, and nothing past the end
+    let kv8 = Kv::open("random8")?;
+    kv8.set("key".as_bytes(), "spiderlightning".as_bytes())?;
+    assert!(kv8.get_range("key".as_bytes(), 6, 5)? == "light".as_bytes());
+    assert!(kv8.get_range("key".as_bytes(), 6, 100)? == "lightning".as_bytes());
+    assert!(kv8.get_range("key".as_bytes(), 100, 5)?.is_empty());
+    kv8.delete("key".as_bytes())?;
+
+    // test release() keeps the store usable, reopening its backend on the next call
+    let kv9 = Kv::open("random9")?;
+    kv9.set("key".as_bytes(), "value".as_bytes())?;
+    kv9.release()?;
+    kv9.release()?;
+    assert!(kv9.get("key".as_bytes())? == "value".as_bytes());
+    kv9.delete("key".as_bytes())?;
+
     // test get_kv() with empty name
     //
     // FIXME: not sure if this should be an error or success.
     let kv4 = Kv::open("random4")?;
-    let _ret = kv4.delete("key");
+    let _ret = kv4.delete("key".as_bytes());
 
     println!("finished running kv-test");
     Ok(())